@@ -0,0 +1,538 @@
+// Frame loop pieces on a headless device: encoder recording, buffer
+// uploads, GPU and CPU culling, instanced draw submission and generated
+// shapes. Anything that touches
+// the GPU waits for it, so the numbers include the GPU side.
+//
+//     cargo bench
+//
+// Without a GPU, WGPU_BACKEND=gl with a software rasterizer works too.
+
+use std::time::Duration;
+
+use cgmath::Deg;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use learn_wgpu::bounds::{Aabb, Frustum, Sphere};
+use learn_wgpu::camera::{self, Camera, CameraUniform, Projection};
+use learn_wgpu::compute::StorageBuffer;
+use learn_wgpu::culling::CpuCuller;
+use learn_wgpu::depth_prepass;
+use learn_wgpu::draw_list::TracedPass;
+use learn_wgpu::external_uniforms::ExternalBuffer;
+use learn_wgpu::fog::{Fog, FogUniform};
+use learn_wgpu::gpu_culling::GpuCuller;
+use learn_wgpu::hiz::DepthPyramid;
+use learn_wgpu::ibl::Ibl;
+use learn_wgpu::indirect::IndirectBatch;
+use learn_wgpu::instance::{self, InstanceRaw};
+use learn_wgpu::lighting::{self, Light, LightBuffers};
+use learn_wgpu::model::NodeTransform;
+use learn_wgpu::reflection_probes::ReflectionProbes;
+use learn_wgpu::scene::SceneModel;
+use learn_wgpu::shapes::Shape;
+use learn_wgpu::skinning::{ModelInstance, ModelRenderer};
+use learn_wgpu::upload::{self, DynamicUniforms, Uploader};
+use wgpu::util::DeviceExt;
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+const SIZE: u32 = 256;
+
+// Untextured quads, just enough to push instances through a pipeline
+const INSTANCED_SHADER: &str = "
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@vertex
+fn vs_main(
+    @location(0) position: vec3<f32>,
+    @location(5) m0: vec4<f32>,
+    @location(6) m1: vec4<f32>,
+    @location(7) m2: vec4<f32>,
+    @location(8) m3: vec4<f32>,
+) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * mat4x4<f32>(m0, m1, m2, m3) * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return vec4<f32>(1.0);
+}
+";
+
+const QUAD: &[[f32; 3]] = &[[-0.5, -0.5, 0.0], [0.5, -0.5, 0.0], [0.5, 0.5, 0.0], [-0.5, 0.5, 0.0]];
+const QUAD_INDICES: &[u16] = &[0, 1, 2, 0, 2, 3];
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    target: wgpu::TextureView,
+    camera_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    view_proj: cgmath::Matrix4<f32>,
+}
+
+impl Gpu {
+    // None without an adapter, the benches are skipped then
+    fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::util::backend_bits_from_env().unwrap_or_default(),
+            ..Default::default()
+        });
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+        let info = adapter.get_info();
+        println!("Benchmarking on {} ({:?})", info.name, info.backend);
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: learn_wgpu::indirect::optional_features(&adapter),
+                ..Default::default()
+            },
+            None,
+        ))
+        .ok()?;
+
+        let target = device
+            .create_texture(&wgpu::TextureDescriptor {
+                label: Some("Bench Target"),
+                size: wgpu::Extent3d {
+                    width: SIZE,
+                    height: SIZE,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                view_formats: &[],
+            })
+            .create_view(&wgpu::TextureViewDescriptor::default());
+
+        // the demo's default view, looking down the instance grid
+        let camera = Camera::new((0.0, 5.0, 10.0), Deg(-90.0), Deg(-20.0));
+        let projection = Projection::new(SIZE, SIZE, Deg(45.0), 0.1, 100.0);
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, &projection);
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        // fog off, as the demo starts
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[FogUniform::from(&Fog::default())]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let lights = LightBuffers::new(&device);
+        lights.upload(&queue, lighting::AMBIENT, &[Light::default()], None);
+        let camera_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Camera Bind Group Layout"),
+            entries: &camera::layout_entries(),
+        });
+        // lit by the default light's ambient from everywhere, without probes
+        let probes = ReflectionProbes::new(&device);
+        probes.upload(&queue);
+        let external = ExternalBuffer::new(&device);
+        let mut shared = vec![wgpu::BindGroupEntry {
+            binding: 1,
+            resource: fog_buffer.as_entire_binding(),
+        }];
+        shared.extend(lights.bind_group_entries());
+        shared.extend(probes.bind_group_entries());
+        shared.push(external.bind_group_entry());
+        let ibl = Ibl::new(&device, &queue, &camera_layout, &shared, lighting::AMBIENT);
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera_buffer.as_entire_binding(),
+        }];
+        entries.extend(shared);
+        entries.extend(ibl.bind_group_entries());
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_layout,
+            entries: &entries,
+        });
+
+        Some(Self {
+            device,
+            queue,
+            target,
+            camera_layout,
+            camera_bind_group,
+            view_proj: projection.calc_matrix() * camera.calc_matrix(),
+        })
+    }
+
+    fn encoder(&self) -> wgpu::CommandEncoder {
+        self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Bench Encoder"),
+        })
+    }
+
+    fn render_pass<'e>(&'e self, encoder: &'e mut wgpu::CommandEncoder) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Bench Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        })
+    }
+
+    fn submit_and_wait(&self, encoder: wgpu::CommandEncoder) {
+        self.queue.submit(std::iter::once(encoder.finish()));
+        self.device.poll(wgpu::Maintain::Wait);
+    }
+}
+
+// The instance grid with its mesh, as the demo draws it
+struct Instanced {
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    instances: StorageBuffer<InstanceRaw>,
+}
+
+impl Instanced {
+    fn new(gpu: &Gpu, per_row: u32) -> Self {
+        let device = &gpu.device;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Instanced Bench Shader"),
+            source: wgpu::ShaderSource::Wgsl(INSTANCED_SHADER.into()),
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Instanced Bench Pipeline Layout"),
+            bind_group_layouts: &[&gpu.camera_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Instanced Bench Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[
+                    wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Vertex,
+                        attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+                    },
+                    InstanceRaw::desc(),
+                ],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(FORMAT.into())],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Vertex Buffer"),
+            contents: bytemuck::cast_slice(QUAD),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Index Buffer"),
+            contents: bytemuck::cast_slice(QUAD_INDICES),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let raw: Vec<InstanceRaw> = instance::grid(per_row, 1.2, 1).iter().map(|instance| instance.to_raw()).collect();
+        let instances = StorageBuffer::from_slice_with_usage(device, "Instance Buffer", &raw, wgpu::BufferUsages::VERTEX);
+        Self {
+            pipeline,
+            vertex_buffer,
+            index_buffer,
+            instances,
+        }
+    }
+
+    fn bind<'p>(&'p self, gpu: &'p Gpu, pass: &mut TracedPass<'p>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &gpu.camera_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    }
+}
+
+// Frustum culling the grid, its depth pyramid's never built so it doesn't
+// occlusion cull
+fn gpu_culler(gpu: &Gpu, instanced: &Instanced) -> GpuCuller {
+    let usage = wgpu::TextureUsages::TEXTURE_BINDING;
+    let depth = depth_prepass::depth_target(&gpu.device, "Bench Depth", SIZE, SIZE, usage);
+    let pyramid = DepthPyramid::new(&gpu.device, &depth, SIZE, SIZE);
+    GpuCuller::new(&gpu.device, &instanced.instances, Sphere::new((0.0, 0.0, 0.0).into(), 0.71), 6, &pyramid)
+}
+
+// Recording a pass that draws the demo's glTF worm many times, without
+// submitting it
+fn encoder_recording(c: &mut Criterion, gpu: &Gpu) {
+    let mut renderer = ModelRenderer::new(&gpu.device, &gpu.queue, FORMAT, 1, &gpu.camera_layout);
+    let worm = concat!(env!("CARGO_MANIFEST_DIR"), "/res/worm.gltf");
+    let mut models: Vec<SceneModel> = (0..256)
+        .map(|_| SceneModel::load(&gpu.device, &gpu.queue, &renderer, worm, NodeTransform::default()).unwrap())
+        .collect();
+    let mut belt = wgpu::util::StagingBelt::new(upload::STAGING_CHUNK_SIZE);
+    let mut encoder = gpu.encoder();
+    let mut uploader = Uploader::new(&gpu.device, &mut encoder, &mut belt);
+    renderer.begin_frame();
+    for (object, model) in models.iter_mut().enumerate() {
+        model.upload(&mut uploader, &mut renderer, object as u32);
+    }
+    renderer.upload(&mut uploader);
+    for model in &models {
+        model.skin(&mut encoder, &renderer);
+    }
+    belt.finish();
+    gpu.submit_and_wait(encoder);
+    belt.recall();
+
+    let mut group = c.benchmark_group("encoder_recording");
+    for count in [16, 256] {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("model_draws", count), &count, |b, &count| {
+            b.iter(|| {
+                let mut encoder = gpu.encoder();
+                let mut pass = gpu.render_pass(&mut encoder);
+                for model in &models[..count] {
+                    model.draw(&mut pass, &renderer, &gpu.camera_bind_group);
+                }
+                drop(pass);
+                encoder.finish()
+            })
+        });
+    }
+    group.finish();
+}
+
+// Per-object data for a frame, one write per object through the queue,
+// through the staging belt, and packed into dynamic uniforms
+fn buffer_uploads(c: &mut Criterion, gpu: &Gpu) {
+    const OBJECTS: usize = 4096;
+    let data = [[1.0f32; 4]; 4];
+    let buffer = StorageBuffer::<[[f32; 4]; 4]>::zeroed(&gpu.device, "Upload Buffer", OBJECTS);
+    let mut belt = wgpu::util::StagingBelt::new(upload::STAGING_CHUNK_SIZE);
+    let mut uniforms = DynamicUniforms::<[[f32; 4]; 4]>::new(
+        &gpu.device,
+        "Bench Uniforms",
+        wgpu::ShaderStages::VERTEX,
+        OBJECTS,
+    );
+
+    let mut group = c.benchmark_group("buffer_uploads");
+    group.throughput(Throughput::Elements(OBJECTS as u64));
+    group.bench_function("queue_write_buffer", |b| {
+        b.iter(|| {
+            for object in 0..OBJECTS {
+                buffer.write(&gpu.queue, object, &[data]);
+            }
+            gpu.submit_and_wait(gpu.encoder());
+        })
+    });
+    group.bench_function("staging_belt", |b| {
+        b.iter(|| {
+            let mut encoder = gpu.encoder();
+            let mut uploader = Uploader::new(&gpu.device, &mut encoder, &mut belt);
+            for object in 0..OBJECTS {
+                buffer.write_staged(&mut uploader, object, &[data]);
+            }
+            belt.finish();
+            gpu.submit_and_wait(encoder);
+            belt.recall();
+        })
+    });
+    group.bench_function("dynamic_uniforms", |b| {
+        b.iter(|| {
+            let mut encoder = gpu.encoder();
+            let mut uploader = Uploader::new(&gpu.device, &mut encoder, &mut belt);
+            uniforms.clear();
+            for _ in 0..OBJECTS {
+                uniforms.push(&data);
+            }
+            uniforms.upload(&mut uploader);
+            belt.finish();
+            gpu.submit_and_wait(encoder);
+            belt.recall();
+        })
+    });
+    group.finish();
+}
+
+// Frustum culling compute pass over instance grids of growing size
+fn culling(c: &mut Criterion, gpu: &Gpu) {
+    let mut group = c.benchmark_group("gpu_culling");
+    for per_row in [31, 100, 300] {
+        let instanced = Instanced::new(gpu, per_row);
+        let culler = gpu_culler(gpu, &instanced);
+        group.throughput(Throughput::Elements((per_row * per_row) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(per_row * per_row), &culler, |b, culler| {
+            b.iter(|| {
+                culler.update(&gpu.queue, &gpu.view_proj, None);
+                let mut encoder = gpu.encoder();
+                culler.cull(&mut encoder);
+                gpu.submit_and_wait(encoder);
+            })
+        });
+    }
+    group.finish();
+}
+
+// The instance grid drawn and rendered four ways
+fn instanced_draws(c: &mut Criterion, gpu: &Gpu) {
+    const PER_ROW: u32 = 100;
+    let count = PER_ROW * PER_ROW;
+    let instanced = Instanced::new(gpu, PER_ROW);
+    let culler = gpu_culler(gpu, &instanced);
+    let quad_bounds = Aabb::from_points(QUAD.iter().map(|&corner| corner.into())).unwrap();
+    let mut cpu_culler = CpuCuller::new(&gpu.device, &instance::grid(PER_ROW, 1.2, 1), quad_bounds, 6);
+    let frustum = Frustum::from_view_proj(&gpu.view_proj);
+    let mut rows = IndirectBatch::new(&gpu.device, "Bench Row Batch", PER_ROW as usize);
+    for row in 0..PER_ROW {
+        rows.push(wgpu::util::DrawIndexedIndirectArgs {
+            index_count: 6,
+            instance_count: PER_ROW,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: row * PER_ROW,
+        });
+    }
+    rows.upload(&gpu.device, &gpu.queue);
+
+    let mut group = c.benchmark_group("instanced_draws");
+    group.throughput(Throughput::Elements(count as u64));
+    group.bench_function("one_draw", |b| {
+        b.iter(|| {
+            let mut encoder = gpu.encoder();
+            let mut pass = gpu.render_pass(&mut encoder);
+            instanced.bind(gpu, &mut pass);
+            pass.set_vertex_buffer(1, instanced.instances.buffer().slice(..));
+            pass.draw_indexed(0..6, 0, 0..count);
+            drop(pass);
+            gpu.submit_and_wait(encoder);
+        })
+    });
+    group.bench_function(format!("row_batch_{:?}", rows.mode()), |b| {
+        b.iter(|| {
+            let mut encoder = gpu.encoder();
+            let mut pass = gpu.render_pass(&mut encoder);
+            instanced.bind(gpu, &mut pass);
+            pass.set_vertex_buffer(1, instanced.instances.buffer().slice(..));
+            rows.draw(&mut pass);
+            drop(pass);
+            gpu.submit_and_wait(encoder);
+        })
+    });
+    group.bench_function("gpu_culled", |b| {
+        b.iter(|| {
+            culler.update(&gpu.queue, &gpu.view_proj, None);
+            let mut encoder = gpu.encoder();
+            culler.cull(&mut encoder);
+            let mut pass = gpu.render_pass(&mut encoder);
+            instanced.bind(gpu, &mut pass);
+            culler.draw(&mut pass, 1);
+            drop(pass);
+            gpu.submit_and_wait(encoder);
+        })
+    });
+    group.bench_function("cpu_culled", |b| {
+        b.iter(|| {
+            cpu_culler.cull(&gpu.queue, &frustum);
+            let mut encoder = gpu.encoder();
+            let mut pass = gpu.render_pass(&mut encoder);
+            instanced.bind(gpu, &mut pass);
+            cpu_culler.draw(&mut pass, 1);
+            drop(pass);
+            gpu.submit_and_wait(encoder);
+        })
+    });
+    group.finish();
+}
+
+// Generating each shape at about the detail a scene would use, and
+// recording draws of generated spheres instead of a loaded model
+fn shapes(c: &mut Criterion, gpu: &Gpu) {
+    let mut group = c.benchmark_group("shapes");
+    type Generate = fn() -> Shape;
+    let generators: [(&str, Generate); 7] = [
+        ("cube", || Shape::cube(8)),
+        ("uv_sphere", || Shape::uv_sphere(64, 32)),
+        ("icosphere", || Shape::icosphere(4)),
+        ("plane", || Shape::plane(64)),
+        ("cylinder", || Shape::cylinder(64, 8)),
+        ("cone", || Shape::cone(64, 8)),
+        ("torus", || Shape::torus(0.35, 0.15, 64, 32)),
+    ];
+    for (name, generate) in generators {
+        group.bench_function(BenchmarkId::new("generate", name), |b| b.iter(generate));
+    }
+
+    let mut renderer = ModelRenderer::new(&gpu.device, &gpu.queue, FORMAT, 1, &gpu.camera_layout);
+    let sphere = Shape::icosphere(3).to_model(&gpu.device, "icosphere");
+    let mut instances: Vec<ModelInstance> = instance::grid(16, 1.2, 1)
+        .iter()
+        .map(|placement| ModelInstance::new(&gpu.device, &renderer, "icosphere", &sphere, placement.model_matrix()))
+        .collect();
+    let mut belt = wgpu::util::StagingBelt::new(upload::STAGING_CHUNK_SIZE);
+    let mut encoder = gpu.encoder();
+    let mut uploader = Uploader::new(&gpu.device, &mut encoder, &mut belt);
+    renderer.begin_frame();
+    for (object, instance) in instances.iter_mut().enumerate() {
+        instance.upload(&mut uploader, &mut renderer, &sphere, object as u32);
+    }
+    renderer.upload(&mut uploader);
+    for instance in &instances {
+        instance.skin(&mut encoder, &renderer, &sphere);
+    }
+    belt.finish();
+    gpu.submit_and_wait(encoder);
+    belt.recall();
+
+    group.throughput(Throughput::Elements(instances.len() as u64));
+    group.bench_function("sphere_draws", |b| {
+        b.iter(|| {
+            let mut encoder = gpu.encoder();
+            let mut pass = gpu.render_pass(&mut encoder);
+            for instance in &instances {
+                instance.draw(&mut pass, &renderer, &gpu.camera_bind_group, &sphere);
+            }
+            drop(pass);
+            encoder.finish()
+        })
+    });
+    group.finish();
+}
+
+fn frame_loop(c: &mut Criterion) {
+    let Some(gpu) = Gpu::new() else {
+        eprintln!("No adapter, skipping the GPU benchmarks");
+        return;
+    };
+    encoder_recording(c, &gpu);
+    buffer_uploads(c, &gpu);
+    culling(c, &gpu);
+    instanced_draws(c, &gpu);
+    shapes(c, &gpu);
+}
+
+criterion_group! {
+    name = benches;
+    // GPU timings are noisy, more time per benchmark than the default helps
+    config = Criterion::default().measurement_time(Duration::from_secs(5));
+    targets = frame_loop
+}
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+// Runs a compute kernel on a headless device and reads the result back.
+//
+//     cargo run --example compute
+
+use learn_wgpu::compute::{self, ComputePipeline, StorageBuffer};
+use learn_wgpu::readback::Readback;
+
+const WORKGROUP_SIZE: u32 = 64;
+
+fn main() {
+    env_logger::init();
+    pollster::block_on(run());
+}
+
+async fn run() {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await
+        .expect("no suitable adapter");
+    let (device, queue) = adapter
+        .request_device(&wgpu::DeviceDescriptor::default(), None)
+        .await
+        .unwrap();
+
+    let input: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+    let values = StorageBuffer::from_slice(&device, "Values", &input);
+
+    let kernel = ComputePipeline::new(
+        &device,
+        "Square",
+        include_str!("square.wgsl"),
+        "cs_main",
+        &[&[compute::storage_entry(0, false)]],
+    );
+    let bind_group = kernel.create_bind_group(&device, 0, &[values.binding()]);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Compute Encoder"),
+    });
+    let workgroups = compute::workgroup_count(values.len() as u32, WORKGROUP_SIZE);
+    kernel.dispatch(&mut encoder, &[&bind_group], (workgroups, 1, 1));
+    // copy results into a mappable buffer in the same submission
+    let readback = Readback::buffer(&device, &mut encoder, values.buffer(), 0..values.size());
+    queue.submit(std::iter::once(encoder.finish()));
+
+    let bytes = readback.map().wait(&device).unwrap();
+    let output: Vec<f32> = bytemuck::cast_slice(&bytes).to_vec();
+
+    for (i, value) in output.iter().enumerate().take(8) {
+        println!("{}^2 = {}", input[i], value);
+    }
+    println!("...");
+    println!("{}^2 = {}", input[input.len() - 1], output[output.len() - 1]);
+}
@@ -0,0 +1,13 @@
+// Squares every element of the buffer in place
+
+@group(0) @binding(0)
+var<storage, read_write> values: array<f32>;
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= arrayLength(&values)) {
+        return;
+    }
+    values[i] = values[i] * values[i];
+}
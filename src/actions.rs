@@ -0,0 +1,460 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+use winit::event::{ElementState, KeyEvent, MouseButton, WindowEvent};
+use winit::keyboard::{KeyCode, PhysicalKey};
+
+/// A gamepad button, named as gilrs names them: the face buttons by
+/// where they sit, the bumpers as the triggers and the triggers as the
+/// second ones. Its own type so bindings can be saved and loaded without
+/// the gamepad feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum GamepadButton {
+    South,
+    East,
+    North,
+    West,
+    LeftTrigger,
+    LeftTrigger2,
+    RightTrigger,
+    RightTrigger2,
+    Select,
+    Start,
+    LeftThumb,
+    RightThumb,
+    DPadUp,
+    DPadDown,
+    DPadLeft,
+    DPadRight,
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadButton {
+    pub fn from_gilrs(button: gilrs::Button) -> Option<Self> {
+        use gilrs::Button;
+        Some(match button {
+            Button::South => Self::South,
+            Button::East => Self::East,
+            Button::North => Self::North,
+            Button::West => Self::West,
+            Button::LeftTrigger => Self::LeftTrigger,
+            Button::LeftTrigger2 => Self::LeftTrigger2,
+            Button::RightTrigger => Self::RightTrigger,
+            Button::RightTrigger2 => Self::RightTrigger2,
+            Button::Select => Self::Select,
+            Button::Start => Self::Start,
+            Button::LeftThumb => Self::LeftThumb,
+            Button::RightThumb => Self::RightThumb,
+            Button::DPadUp => Self::DPadUp,
+            Button::DPadDown => Self::DPadDown,
+            Button::DPadLeft => Self::DPadLeft,
+            Button::DPadRight => Self::DPadRight,
+            _ => return None,
+        })
+    }
+}
+
+/// An input an action can be bound to. Keys are physical, by where they
+/// are on a US layout, so WASD stays where it is on any other layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    Gamepad(GamepadButton),
+}
+
+impl Binding {
+    // The key or mouse button of a window event, and whether it went down.
+    // Key repeats count as neither.
+    fn from_event(event: &WindowEvent) -> Option<(Self, bool)> {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        physical_key: PhysicalKey::Code(key),
+                        state,
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => Some((Self::Key(*key), *state == ElementState::Pressed)),
+            WindowEvent::MouseInput { state, button, .. } => {
+                Some((Self::Mouse(*button), *state == ElementState::Pressed))
+            }
+            _ => None,
+        }
+    }
+}
+
+// The demo's actions, as `ActionMap::default` binds them
+const DEFAULT_BINDINGS: &[(&str, &[Binding])] = {
+    use Binding::*;
+    &[
+        ("move_forward", &[Key(KeyCode::KeyW), Key(KeyCode::ArrowUp)]),
+        ("move_backward", &[Key(KeyCode::KeyS), Key(KeyCode::ArrowDown)]),
+        ("move_left", &[Key(KeyCode::KeyA), Key(KeyCode::ArrowLeft)]),
+        ("move_right", &[Key(KeyCode::KeyD), Key(KeyCode::ArrowRight)]),
+        ("move_up", &[Key(KeyCode::Space), Gamepad(GamepadButton::South)]),
+        ("move_down", &[Key(KeyCode::ShiftLeft), Gamepad(GamepadButton::East)]),
+        ("grab_cursor", &[Mouse(MouseButton::Left)]),
+        // leaves mouse-look first, and quits the next time
+        ("quit", &[Key(KeyCode::Escape)]),
+        ("pick", &[Mouse(MouseButton::Right)]),
+        ("gpu_pick", &[Mouse(MouseButton::Middle)]),
+        ("cycle_present_mode", &[Key(KeyCode::KeyV)]),
+        ("cycle_window_mode", &[Key(KeyCode::F11)]),
+        ("screenshot", &[Key(KeyCode::F12)]),
+        ("toggle_particles", &[Key(KeyCode::KeyP)]),
+        ("cycle_culling", &[Key(KeyCode::KeyC)]),
+        ("cycle_lod", &[Key(KeyCode::KeyR)]),
+        ("toggle_hud", &[Key(KeyCode::KeyH), Gamepad(GamepadButton::Select)]),
+        ("toggle_debug", &[Key(KeyCode::KeyB)]),
+        ("toggle_debug_lights", &[Key(KeyCode::F6)]),
+        ("toggle_debug_shadows", &[Key(KeyCode::F7)]),
+        ("toggle_debug_decals", &[Key(KeyCode::F8)]),
+        ("cycle_wireframe", &[Key(KeyCode::KeyL)]),
+        ("toggle_tilemap", &[Key(KeyCode::KeyM)]),
+        ("cycle_transparency", &[Key(KeyCode::KeyO)]),
+        ("toggle_fxaa", &[Key(KeyCode::KeyX)]),
+        ("toggle_taa", &[Key(KeyCode::KeyZ)]),
+        ("toggle_ssr", &[Key(KeyCode::KeyY)]),
+        ("toggle_depth_of_field", &[Key(KeyCode::Digit0)]),
+        ("focus_nearer", &[Key(KeyCode::Comma)]),
+        ("focus_farther", &[Key(KeyCode::Period)]),
+        ("open_aperture", &[Key(KeyCode::Minus)]),
+        ("close_aperture", &[Key(KeyCode::Equal)]),
+        ("toggle_motion_blur", &[Key(KeyCode::Semicolon)]),
+        ("toggle_color_grading", &[Key(KeyCode::Quote)]),
+        ("toggle_auto_exposure", &[Key(KeyCode::KeyE)]),
+        ("toggle_depth_prepass", &[Key(KeyCode::KeyJ)]),
+        ("toggle_occlusion_culling", &[Key(KeyCode::KeyF)]),
+        ("cycle_fog", &[Key(KeyCode::KeyG)]),
+        ("remove_voxels", &[Key(KeyCode::Digit1)]),
+        ("add_voxels", &[Key(KeyCode::Digit2)]),
+        ("cycle_max_fps", &[Key(KeyCode::Digit3)]),
+        ("toggle_render_bundles", &[Key(KeyCode::Digit4)]),
+        ("toggle_grid", &[Key(KeyCode::Digit5)]),
+        ("toggle_axes", &[Key(KeyCode::Digit6)]),
+        ("dump_draws", &[Key(KeyCode::Digit7)]),
+        ("toggle_draw_dumps", &[Key(KeyCode::Digit8)]),
+        ("toggle_dynamic_resolution", &[Key(KeyCode::Digit9)]),
+        ("toggle_stats", &[Key(KeyCode::F3), Gamepad(GamepadButton::Start)]),
+        ("cycle_animation", &[Key(KeyCode::KeyN), Gamepad(GamepadButton::North)]),
+        ("pause_animation", &[Key(KeyCode::KeyK)]),
+        ("slower_animation", &[Key(KeyCode::BracketLeft)]),
+        ("faster_animation", &[Key(KeyCode::BracketRight)]),
+        ("save_scene", &[Key(KeyCode::F5)]),
+        ("load_scene", &[Key(KeyCode::F9)]),
+        ("cycle_filtering", &[Key(KeyCode::KeyT)]),
+        ("toggle_uv_debug", &[Key(KeyCode::KeyU)]),
+        ("toggle_split_screen", &[Key(KeyCode::KeyI)]),
+        ("cycle_projection", &[Key(KeyCode::KeyQ)]),
+    ]
+};
+
+/// What an input did to the actions bound to it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Triggered {
+    // the actions it pressed, by name
+    pub pressed: Vec<String>,
+    // whether it's bound to anything, pressed or let go, or was just bound
+    pub bound: bool,
+}
+
+/// Named actions and the keys, mouse buttons and gamepad buttons bound to
+/// them. Window events and gamepad polls go in, and game code asks for
+/// actions rather than inputs: whether one is held, or was pressed this
+/// frame. An input can be bound to several actions and an action to
+/// several inputs. Bindings can be changed at runtime, directly or by
+/// binding whatever's pressed next with `rebind_next`, and saved to and
+/// loaded from a RON file of action names and their bindings.
+#[derive(Debug, Clone)]
+pub struct ActionMap {
+    bindings: BTreeMap<String, Vec<Binding>>,
+    // inputs held down right now
+    held: HashSet<Binding>,
+    // actions pressed since the last `end_frame`
+    pressed: HashSet<String>,
+    // what the next input pressed is bound to, from `rebind_next`
+    listening: Option<String>,
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        let bindings = DEFAULT_BINDINGS
+            .iter()
+            .map(|(action, bindings)| (action.to_string(), bindings.to_vec()))
+            .collect();
+        Self::new(bindings)
+    }
+}
+
+impl ActionMap {
+    pub fn new(bindings: BTreeMap<String, Vec<Binding>>) -> Self {
+        Self {
+            bindings,
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            listening: None,
+        }
+    }
+
+    // Every action with a binding, by name
+    pub fn actions(&self) -> impl Iterator<Item = &str> {
+        self.bindings.keys().map(String::as_str)
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.bindings.get(action).map_or(&[], Vec::as_slice)
+    }
+
+    // The actions `binding` is bound to
+    pub fn actions_for(&self, binding: Binding) -> impl Iterator<Item = &str> {
+        self.bindings
+            .iter()
+            .filter(move |(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| action.as_str())
+    }
+
+    // Adds a binding to an action's others
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        let bindings = self.bindings.entry(action.to_owned()).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    // Takes `binding` off every action it's bound to
+    pub fn unbind(&mut self, binding: Binding) {
+        for bindings in self.bindings.values_mut() {
+            bindings.retain(|bound| *bound != binding);
+        }
+    }
+
+    // Replaces an action's bindings; none leaves it unbound
+    pub fn set_bindings(&mut self, action: &str, bindings: Vec<Binding>) {
+        self.bindings.insert(action.to_owned(), bindings);
+    }
+
+    // The actions whose bindings aren't the defaults any more, with theirs
+    pub fn overrides(&self) -> BTreeMap<String, Vec<Binding>> {
+        let defaults = Self::default();
+        self.bindings
+            .iter()
+            .filter(|(action, bindings)| defaults.bindings(action) != bindings.as_slice())
+            .map(|(action, bindings)| (action.clone(), bindings.clone()))
+            .collect()
+    }
+
+    // Back to the default bindings, with `overrides` in place of theirs
+    pub fn set_overrides(&mut self, overrides: BTreeMap<String, Vec<Binding>>) {
+        self.bindings = Self::default().bindings;
+        self.bindings.extend(overrides);
+    }
+
+    // Binds the next key, mouse button or gamepad button pressed to
+    // `action` in place of its bindings, taking it off any other action.
+    // That press doesn't trigger anything.
+    pub fn rebind_next(&mut self, action: &str) {
+        self.listening = Some(action.to_owned());
+    }
+
+    // The action `rebind_next` is waiting to bind, if it still is
+    pub fn listening(&self) -> Option<&str> {
+        self.listening.as_deref()
+    }
+
+    pub fn cancel_rebind(&mut self) {
+        self.listening = None;
+    }
+
+    pub fn is_down(&self, action: &str) -> bool {
+        self.bindings(action).iter().any(|binding| self.held.contains(binding))
+    }
+
+    // 1 while it's held, otherwise 0, for actions that move things
+    pub fn value(&self, action: &str) -> f32 {
+        if self.is_down(action) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+
+    // Since the last `end_frame`
+    pub fn was_pressed(&self, action: &str) -> bool {
+        self.pressed.contains(action)
+    }
+
+    // Once the frame's update has asked for what it needs
+    pub fn end_frame(&mut self) {
+        self.pressed.clear();
+    }
+
+    pub fn process_event(&mut self, event: &WindowEvent) -> Triggered {
+        // focus going elsewhere takes every key up with it
+        if let WindowEvent::Focused(false) = event {
+            self.held.clear();
+            return Triggered::default();
+        }
+        match Binding::from_event(event) {
+            Some((binding, pressed)) => self.process(binding, pressed),
+            None => Triggered::default(),
+        }
+    }
+
+    // Once per frame, with a fresh poll
+    #[cfg(feature = "gamepad")]
+    pub fn process_gamepad(&mut self, gamepad: &crate::gamepad::GamepadState) -> Triggered {
+        let down: HashSet<_> = gamepad
+            .buttons_down
+            .iter()
+            .filter_map(|button| GamepadButton::from_gilrs(*button).map(Binding::Gamepad))
+            .collect();
+        self.held.retain(|binding| !matches!(binding, Binding::Gamepad(_)));
+        self.held.extend(down);
+
+        let mut triggered = Triggered::default();
+        for button in gamepad.buttons_pressed.iter().filter_map(|button| GamepadButton::from_gilrs(*button)) {
+            let next = self.process(Binding::Gamepad(button), true);
+            triggered.pressed.extend(next.pressed);
+            triggered.bound |= next.bound;
+        }
+        triggered
+    }
+
+    fn process(&mut self, binding: Binding, pressed: bool) -> Triggered {
+        if !pressed {
+            self.held.remove(&binding);
+            return Triggered {
+                pressed: Vec::new(),
+                bound: self.actions_for(binding).next().is_some(),
+            };
+        }
+        if let Some(action) = self.listening.take() {
+            self.unbind(binding);
+            self.set_bindings(&action, vec![binding]);
+            log::info!("Bound {:?} to {}", binding, action);
+            return Triggered {
+                pressed: Vec::new(),
+                bound: true,
+            };
+        }
+        self.held.insert(binding);
+        let actions: Vec<String> = self.actions_for(binding).map(str::to_owned).collect();
+        self.pressed.extend(actions.iter().cloned());
+        Triggered {
+            bound: !actions.is_empty(),
+            pressed: actions,
+        }
+    }
+
+    // Every action and its bindings, including the unbound ones
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(&self.bindings, ron::ser::PrettyConfig::default())?;
+        std::fs::write(path, text).with_context(|| format!("Writing bindings to {}", path.display()))
+    }
+
+    // Replaces the bindings of the actions in the file, the others keep
+    // theirs
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Reading bindings from {}", path.display()))?;
+        let bindings: BTreeMap<String, Vec<Binding>> =
+            ron::from_str(&text).with_context(|| format!("Parsing bindings in {}", path.display()))?;
+        self.bindings.extend(bindings);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: Binding = Binding::Key(KeyCode::KeyW);
+    const UP: Binding = Binding::Key(KeyCode::ArrowUp);
+
+    fn pressed(actions: &[&str]) -> Triggered {
+        Triggered {
+            pressed: actions.iter().map(|action| action.to_string()).collect(),
+            bound: true,
+        }
+    }
+
+    #[test]
+    fn a_press_triggers_every_action_bound_to_it() {
+        let mut actions = ActionMap::new(BTreeMap::new());
+        actions.bind("walk", W);
+        actions.bind("climb", W);
+        assert_eq!(actions.process(W, true), pressed(&["climb", "walk"]));
+        assert!(actions.is_down("walk") && actions.is_down("climb"));
+        assert!(actions.was_pressed("walk"));
+        assert_eq!(actions.value("walk"), 1.0);
+
+        // letting go is still bound, but presses nothing
+        assert_eq!(actions.process(W, false), pressed(&[]));
+        assert!(!actions.is_down("walk"));
+        assert!(actions.was_pressed("walk"));
+        actions.end_frame();
+        assert!(!actions.was_pressed("walk"));
+
+        assert_eq!(actions.process(UP, true), Triggered::default());
+    }
+
+    #[test]
+    fn rebinding_takes_the_next_press() {
+        let mut actions = ActionMap::default();
+        actions.rebind_next("move_up");
+        assert_eq!(actions.listening(), Some("move_up"));
+        // swallowed, rather than moving forward
+        assert_eq!(actions.process(W, true), pressed(&[]));
+        assert!(!actions.was_pressed("move_forward") && !actions.is_down("move_up"));
+        assert_eq!(actions.listening(), None);
+        assert_eq!(actions.bindings("move_up"), [W]);
+        assert_eq!(actions.bindings("move_forward"), [UP]);
+
+        actions.process(W, false);
+        assert_eq!(actions.process(W, true), pressed(&["move_up"]));
+    }
+
+    #[test]
+    fn cancelling_a_rebind_leaves_the_bindings() {
+        let mut actions = ActionMap::default();
+        actions.rebind_next("move_up");
+        actions.cancel_rebind();
+        assert_eq!(actions.process(W, true), pressed(&["move_forward"]));
+        assert_eq!(actions.bindings("move_forward"), [W, UP]);
+    }
+
+    #[test]
+    fn overrides_round_trip() {
+        let mut actions = ActionMap::default();
+        assert!(actions.overrides().is_empty());
+        actions.set_bindings("move_forward", vec![UP]);
+        actions.set_bindings("quit", Vec::new());
+        actions.bind("jump", W);
+        let overrides = actions.overrides();
+        let changed: Vec<&str> = overrides.keys().map(String::as_str).collect();
+        assert_eq!(changed, ["jump", "move_forward", "quit"]);
+
+        let mut restored = ActionMap::default();
+        restored.set_overrides(overrides);
+        assert_eq!(restored.bindings, actions.bindings);
+        // and back to the defaults without them
+        restored.set_overrides(BTreeMap::new());
+        assert_eq!(restored.bindings, ActionMap::default().bindings);
+    }
+
+    #[test]
+    fn losing_focus_lets_go_of_everything() {
+        let mut actions = ActionMap::default();
+        actions.process(W, true);
+        actions.process(Binding::Mouse(MouseButton::Left), true);
+        assert!(actions.is_down("move_forward") && actions.is_down("grab_cursor"));
+        assert_eq!(actions.process_event(&WindowEvent::Focused(false)), Triggered::default());
+        assert!(!actions.is_down("move_forward") && !actions.is_down("grab_cursor"));
+        // what was pressed this frame still was
+        assert!(actions.was_pressed("move_forward"));
+    }
+}
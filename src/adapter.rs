@@ -0,0 +1,123 @@
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::*;
+
+/// One adapter as `enumerate_adapters` reports it
+#[derive(Debug, Clone)]
+pub struct AdapterSummary {
+    // what `AdapterSelector::Index` refers to
+    pub index: usize,
+    pub info: wgpu::AdapterInfo,
+    pub limits: wgpu::Limits,
+}
+
+impl fmt::Display for AdapterSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}: {} ({:?}, {:?}, max texture {}",
+            self.index, self.info.name, self.info.backend, self.info.device_type, self.limits.max_texture_dimension_2d
+        )?;
+        if !self.info.driver.is_empty() {
+            write!(f, ", {} {}", self.info.driver, self.info.driver_info)?;
+        }
+        write!(f, ")")
+    }
+}
+
+// Every adapter of the given backends, in the order wgpu lists them
+pub fn enumerate_adapters(instance: &wgpu::Instance, backends: wgpu::Backends) -> Vec<AdapterSummary> {
+    instance
+        .enumerate_adapters(backends)
+        .iter()
+        .enumerate()
+        .map(|(index, adapter)| AdapterSummary {
+            index,
+            info: adapter.get_info(),
+            limits: adapter.limits(),
+        })
+        .collect()
+}
+
+/// Which adapter to use instead of the one wgpu prefers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdapterSelector {
+    // position in `enumerate_adapters`
+    Index(usize),
+    // part of the name, ignoring case
+    Name(String),
+}
+
+impl AdapterSelector {
+    fn matches(&self, index: usize, info: &wgpu::AdapterInfo) -> bool {
+        match self {
+            AdapterSelector::Index(i) => *i == index,
+            AdapterSelector::Name(name) => info.name.to_lowercase().contains(&name.to_lowercase()),
+        }
+    }
+}
+
+// A number picks by index, anything else by name
+impl FromStr for AdapterSelector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ensure!(!s.is_empty(), "empty adapter name");
+        Ok(match s.parse() {
+            Result::Ok(index) => AdapterSelector::Index(index),
+            Err(_) => AdapterSelector::Name(s.to_owned()),
+        })
+    }
+}
+
+impl fmt::Display for AdapterSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AdapterSelector::Index(index) => write!(f, "adapter {}", index),
+            AdapterSelector::Name(name) => write!(f, "adapter {:?}", name),
+        }
+    }
+}
+
+// The selected adapter, or wgpu's choice for `options` without one. Of
+// several adapters matching a name, the first that can present to
+// `options.compatible_surface` wins.
+pub async fn select_adapter(
+    instance: &wgpu::Instance,
+    backends: wgpu::Backends,
+    selector: Option<&AdapterSelector>,
+    options: &wgpu::RequestAdapterOptions<'_, '_>,
+) -> Result<wgpu::Adapter> {
+    let Some(selector) = selector else {
+        return instance
+            .request_adapter(options)
+            .await
+            .context("No adapter supports this window, try --list-adapters");
+    };
+
+    let mut matching: Vec<wgpu::Adapter> = instance
+        .enumerate_adapters(backends)
+        .into_iter()
+        .enumerate()
+        .filter(|(index, adapter)| selector.matches(*index, &adapter.get_info()))
+        .map(|(_, adapter)| adapter)
+        .collect();
+    let presentable = matching.iter().position(|adapter| {
+        options
+            .compatible_surface
+            .is_none_or(|surface| adapter.is_surface_supported(surface))
+    });
+    match presentable {
+        Some(i) => Ok(matching.swap_remove(i)),
+        None if !matching.is_empty() => {
+            log::warn!("No {} can present to this window", selector);
+            Ok(matching.swap_remove(0))
+        }
+        None => {
+            let available: Vec<String> = enumerate_adapters(instance, backends).iter().map(ToString::to_string).collect();
+            ensure!(!available.is_empty(), "No adapters for {:?}", backends);
+            bail!("No {} among:\n  {}", selector, available.join("\n  "))
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use std::ffi::CString;
+use std::path::{Component, Path, PathBuf};
+use std::sync::OnceLock;
+
+use anyhow::*;
+use winit::event_loop::EventLoopBuilder;
+use winit::platform::android::activity::AndroidApp;
+use winit::platform::android::EventLoopBuilderExtAndroid;
+
+use crate::config::Config;
+
+// Set once by `android_main`, for the asset manager and data directory
+static APP: OnceLock<AndroidApp> = OnceLock::new();
+
+// Called by the NativeActivity glue on its own thread
+#[no_mangle]
+fn android_main(app: AndroidApp) {
+    android_logger::init_once(android_logger::Config::default().with_max_level(log::LevelFilter::Info));
+    let _ = APP.set(app.clone());
+
+    // there is no command line, only the environment
+    let config = Config::from_env().unwrap_or_else(|e| {
+        log::error!("{:#}", e);
+        Config::default()
+    });
+    let event_loop = EventLoopBuilder::new().with_android_app(app).build().unwrap();
+    crate::run_with(event_loop, config);
+}
+
+// Reads a file packaged from res/ into the APK's assets
+pub fn read_asset(path: &Path) -> Result<Vec<u8>> {
+    let app = APP.get().context("The Android app hasn't started")?;
+    let name = asset_name(path).with_context(|| format!("{}: not an asset path", path.display()))?;
+    let mut asset = app
+        .asset_manager()
+        .open(&CString::new(name)?)
+        .with_context(|| format!("{}: no such asset", path.display()))?;
+    Ok(asset.buffer().with_context(|| format!("Failed to read {}", path.display()))?.to_vec())
+}
+
+// The app's private storage, kept between runs
+pub fn data_dir() -> Option<PathBuf> {
+    APP.get()?.internal_data_path()
+}
+
+// The asset manager wants plain forward slash paths, so scene-relative
+// ones like "scenes/../worm.gltf" are resolved first
+fn asset_name(path: &Path) -> Option<String> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_str()?),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop()?;
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(parts.join("/"))
+}
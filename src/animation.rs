@@ -0,0 +1,576 @@
+use std::time::Duration;
+
+use anyhow::*;
+use cgmath::*;
+use gltf::animation::util::ReadOutputs;
+
+use crate::model::{Model, NodeTransform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Step,
+    Linear,
+    // Hermite spline, every keyframe carries an in- and out-tangent
+    CubicSpline,
+}
+
+impl From<gltf::animation::Interpolation> for Interpolation {
+    fn from(interpolation: gltf::animation::Interpolation) -> Self {
+        match interpolation {
+            gltf::animation::Interpolation::Step => Interpolation::Step,
+            gltf::animation::Interpolation::Linear => Interpolation::Linear,
+            gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+        }
+    }
+}
+
+// Which part of a node a channel drives
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    Rotation,
+    Scale,
+    // morph target weights of the node's mesh
+    Weights,
+}
+
+impl Property {
+    // floats per keyframe value, weights have one per morph target
+    fn width(self) -> Option<usize> {
+        match self {
+            Property::Rotation => Some(4),
+            Property::Translation | Property::Scale => Some(3),
+            Property::Weights => None,
+        }
+    }
+}
+
+/// Node transforms and morph weights, indexed like `Model::nodes`.
+#[derive(Debug, Clone, Default)]
+pub struct Pose {
+    pub transforms: Vec<NodeTransform>,
+    pub weights: Vec<Vec<f32>>,
+}
+
+impl Pose {
+    pub fn from_model(model: &Model) -> Self {
+        Self {
+            transforms: model.nodes.iter().map(|node| node.transform).collect(),
+            weights: model.nodes.iter().map(|node| node.weights.clone()).collect(),
+        }
+    }
+
+    // Blends `from` into `self`, `amount` 0 is all `from` and 1 all `self`
+    fn blend_from(&mut self, from: &Pose, amount: f32) {
+        for (to, from) in self.transforms.iter_mut().zip(&from.transforms) {
+            *to = from.lerp(to, amount);
+        }
+        for (to, from) in self.weights.iter_mut().zip(&from.weights) {
+            for (to, from) in to.iter_mut().zip(from) {
+                *to = from + (*to - from) * amount;
+            }
+        }
+    }
+
+    pub fn apply(&self, model: &mut Model) {
+        for ((node, transform), weights) in model.nodes.iter_mut().zip(&self.transforms).zip(&self.weights) {
+            node.transform = *transform;
+            node.weights.clone_from(weights);
+        }
+    }
+}
+
+/// Keyframes for one property of one node. `values` is flattened, with
+/// rotations stored x, y, z, w like glTF and weights one run per keyframe.
+/// Cubic spline channels store an in-tangent, the value and an out-tangent
+/// for every keyframe.
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub node: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+impl Channel {
+    // values stored per keyframe
+    fn parts(&self) -> usize {
+        match self.interpolation {
+            Interpolation::CubicSpline => 3,
+            Interpolation::Step | Interpolation::Linear => 1,
+        }
+    }
+
+    // floats per value
+    fn width(&self) -> usize {
+        self.property
+            .width()
+            .unwrap_or_else(|| self.values.len() / (self.times.len() * self.parts()).max(1))
+    }
+
+    // floats between the starts of two keyframes
+    fn stride(&self) -> usize {
+        self.parts() * self.width()
+    }
+
+    // `part` 0 is the in-tangent, 1 the value and 2 the out-tangent for
+    // cubic splines; other channels only have a value
+    fn element(&self, keyframe: usize, part: usize) -> &[f32] {
+        let width = self.width();
+        let start = keyframe * self.stride() + part * width;
+        &self.values[start..start + width]
+    }
+
+    fn value(&self, keyframe: usize) -> &[f32] {
+        match self.interpolation {
+            Interpolation::CubicSpline => self.element(keyframe, 1),
+            Interpolation::Step | Interpolation::Linear => self.element(keyframe, 0),
+        }
+    }
+
+    // Value at `time`, held at the first and last keyframes outside them
+    fn sample(&self, time: f32, out: &mut [f32]) {
+        let (Some(&first), Some(&last)) = (self.times.first(), self.times.last()) else {
+            return;
+        };
+        if time <= first {
+            out.copy_from_slice(self.value(0));
+            return;
+        }
+        if time >= last {
+            out.copy_from_slice(self.value(self.times.len() - 1));
+            return;
+        }
+
+        // times[k] <= time < times[k + 1]
+        let k = self.times.partition_point(|&t| t <= time) - 1;
+        let delta = self.times[k + 1] - self.times[k];
+        let t = (time - self.times[k]) / delta;
+
+        match self.interpolation {
+            Interpolation::Step => out.copy_from_slice(self.value(k)),
+            Interpolation::Linear if self.property == Property::Rotation => {
+                let q = quaternion(self.value(k)).slerp(quaternion(self.value(k + 1)), t);
+                out.copy_from_slice(&[q.v.x, q.v.y, q.v.z, q.s]);
+            }
+            Interpolation::Linear => {
+                let (a, b) = (self.value(k), self.value(k + 1));
+                for i in 0..out.len() {
+                    out[i] = a[i] + (b[i] - a[i]) * t;
+                }
+            }
+            Interpolation::CubicSpline => {
+                let (v0, b0) = (self.element(k, 1), self.element(k, 2));
+                let (a1, v1) = (self.element(k + 1, 0), self.element(k + 1, 1));
+                let (t2, t3) = (t * t, t * t * t);
+                for i in 0..out.len() {
+                    out[i] = (2.0 * t3 - 3.0 * t2 + 1.0) * v0[i]
+                        + (t3 - 2.0 * t2 + t) * delta * b0[i]
+                        + (-2.0 * t3 + 3.0 * t2) * v1[i]
+                        + (t3 - t2) * delta * a1[i];
+                }
+                if self.property == Property::Rotation {
+                    let q = quaternion(out).normalize();
+                    out.copy_from_slice(&[q.v.x, q.v.y, q.v.z, q.s]);
+                }
+            }
+        }
+    }
+
+    fn apply(&self, time: f32, pose: &mut Pose) {
+        if self.property == Property::Weights {
+            let weights = &mut pose.weights[self.node];
+            weights.resize(self.width(), 0.0);
+            self.sample(time, weights);
+            return;
+        }
+
+        let mut value = [0.0; 4];
+        let value = &mut value[..self.width()];
+        self.sample(time, value);
+        let transform = &mut pose.transforms[self.node];
+        match self.property {
+            Property::Translation => transform.translation = Vector3::new(value[0], value[1], value[2]),
+            Property::Rotation => transform.rotation = quaternion(value),
+            Property::Scale => transform.scale = Vector3::new(value[0], value[1], value[2]),
+            Property::Weights => unreachable!(),
+        }
+    }
+}
+
+// glTF x, y, z, w order to cgmath's
+fn quaternion(xyzw: &[f32]) -> Quaternion<f32> {
+    Quaternion::new(xyzw[3], xyzw[0], xyzw[1], xyzw[2])
+}
+
+// One glTF animation clip
+#[derive(Debug, Clone)]
+pub struct Animation {
+    pub name: Option<String>,
+    pub channels: Vec<Channel>,
+    // time of the last keyframe in seconds
+    pub duration: f32,
+}
+
+impl Animation {
+    pub fn from_gltf(animation: &gltf::Animation, buffers: &[gltf::buffer::Data]) -> Result<Self> {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+            let Some(inputs) = reader.read_inputs() else {
+                bail!("Animation {} has a channel without keyframe times", animation.index());
+            };
+            let (property, values): (Property, Vec<f32>) = match reader.read_outputs() {
+                Some(ReadOutputs::Translations(values)) => (Property::Translation, values.flatten().collect()),
+                Some(ReadOutputs::Rotations(values)) => (Property::Rotation, values.into_f32().flatten().collect()),
+                Some(ReadOutputs::Scales(values)) => (Property::Scale, values.flatten().collect()),
+                Some(ReadOutputs::MorphTargetWeights(values)) => (Property::Weights, values.into_f32().collect()),
+                None => bail!("Animation {} has a channel without keyframe values", animation.index()),
+            };
+
+            let channel = Channel {
+                node: channel.target().node().index(),
+                property,
+                interpolation: channel.sampler().interpolation().into(),
+                times: inputs.collect(),
+                values,
+            };
+            if channel.width() == 0 || channel.values.len() != channel.times.len() * channel.stride() {
+                bail!(
+                    "Animation {} has {} keyframe times but {} values",
+                    animation.index(),
+                    channel.times.len(),
+                    channel.values.len()
+                );
+            }
+            channels.push(channel);
+        }
+
+        let duration = channels.iter().filter_map(|channel| channel.times.last().copied()).fold(0.0, f32::max);
+        Ok(Self {
+            name: animation.name().map(str::to_owned),
+            channels,
+            duration,
+        })
+    }
+
+    // Writes the pose at `time` into `pose`. Properties no channel drives
+    // are left alone.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in &self.channels {
+            channel.apply(time, pose);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Playback {
+    animation: usize,
+    time: f32,
+    looping: bool,
+}
+
+impl Playback {
+    fn advance(&mut self, dt: f32, duration: f32) {
+        self.time += dt;
+        self.time = if self.looping && duration > 0.0 {
+            self.time.rem_euclid(duration)
+        } else {
+            self.time.clamp(0.0, duration)
+        };
+    }
+}
+
+// Plays a model's animations and writes the result into its nodes every
+// `update`. Starting a clip with `cross_fade` blends from whatever was
+// playing over the given time instead of cutting.
+pub struct AnimationPlayer {
+    // pose the model was loaded with, used for anything the playing clips
+    // don't drive
+    rest: Pose,
+    current: Option<Playback>,
+    // clip being faded out
+    previous: Option<Playback>,
+    fade_elapsed: f32,
+    fade_duration: f32,
+    // playback rate, negative plays backwards
+    pub speed: f32,
+    pub paused: bool,
+    // reused between updates
+    pose: Pose,
+    fade_pose: Pose,
+}
+
+impl AnimationPlayer {
+    pub fn new(model: &Model) -> Self {
+        Self {
+            rest: Pose::from_model(model),
+            current: None,
+            previous: None,
+            fade_elapsed: 0.0,
+            fade_duration: 0.0,
+            speed: 1.0,
+            paused: false,
+            pose: Pose::default(),
+            fade_pose: Pose::default(),
+        }
+    }
+
+    // Starts `animation` (an index into `Model::animations`) from the
+    // beginning, cutting off anything else
+    pub fn play(&mut self, animation: usize, looping: bool) {
+        self.current = Some(Playback {
+            animation,
+            time: 0.0,
+            looping,
+        });
+        self.previous = None;
+        self.paused = false;
+    }
+
+    pub fn cross_fade(&mut self, animation: usize, duration: Duration, looping: bool) {
+        let previous = self.current.take();
+        self.play(animation, looping);
+        if duration > Duration::ZERO {
+            self.previous = previous;
+            self.fade_elapsed = 0.0;
+            self.fade_duration = duration.as_secs_f32();
+        }
+    }
+
+    // Back to the rest pose on the next update
+    pub fn stop(&mut self) {
+        self.current = None;
+        self.previous = None;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn current(&self) -> Option<usize> {
+        self.current.map(|playback| playback.animation)
+    }
+
+    // Playback position of the current clip in seconds
+    pub fn time(&self) -> Option<f32> {
+        self.current.map(|playback| playback.time)
+    }
+
+    // Whether a one-shot clip has reached its end
+    pub fn is_finished(&self, model: &Model) -> bool {
+        self.current.is_some_and(|playback| {
+            let duration = model.animations[playback.animation].duration;
+            !playback.looping && (playback.time >= duration || playback.time <= 0.0 && self.speed < 0.0)
+        })
+    }
+
+    pub fn update(&mut self, dt: Duration, model: &mut Model) {
+        let dt = if self.paused { 0.0 } else { dt.as_secs_f32() * self.speed };
+
+        self.pose.clone_from(&self.rest);
+        if let Some(current) = &mut self.current {
+            let animation = &model.animations[current.animation];
+            current.advance(dt, animation.duration);
+            animation.sample(current.time, &mut self.pose);
+        }
+
+        if let Some(previous) = &mut self.previous {
+            self.fade_elapsed += dt.abs();
+            if self.fade_elapsed >= self.fade_duration {
+                self.previous = None;
+            } else {
+                let animation = &model.animations[previous.animation];
+                previous.advance(dt, animation.duration);
+                self.fade_pose.clone_from(&self.rest);
+                animation.sample(previous.time, &mut self.fade_pose);
+                self.pose.blend_from(&self.fade_pose, self.fade_elapsed / self.fade_duration);
+            }
+        }
+
+        self.pose.apply(model);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Node;
+
+    fn channel(property: Property, interpolation: Interpolation, times: &[f32], values: &[f32]) -> Channel {
+        Channel {
+            node: 0,
+            property,
+            interpolation,
+            times: times.to_vec(),
+            values: values.to_vec(),
+        }
+    }
+
+    fn sample<const N: usize>(channel: &Channel, time: f32) -> [f32; N] {
+        let mut out = [0.0; N];
+        channel.sample(time, &mut out);
+        out
+    }
+
+    fn assert_near<const N: usize>(actual: [f32; N], expected: [f32; N]) {
+        let close = actual.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-5);
+        assert!(close, "{:?} isn't {:?}", actual, expected);
+    }
+
+    // about y by `degrees`, x, y, z, w
+    fn about_y(degrees: f32) -> [f32; 4] {
+        let q = Quaternion::from_angle_y(Deg(degrees));
+        [q.v.x, q.v.y, q.v.z, q.s]
+    }
+
+    // One node, moved along x by a clip of one keyframe per animation
+    fn model(offsets: &[f32]) -> Model {
+        let node = Node {
+            name: None,
+            transform: NodeTransform::default(),
+            parent: None,
+            children: Vec::new(),
+            mesh: None,
+            skin: None,
+            weights: Vec::new(),
+        };
+        let animations = offsets
+            .iter()
+            .map(|&x| Animation {
+                name: None,
+                channels: vec![channel(Property::Translation, Interpolation::Linear, &[0.0], &[x, 0.0, 0.0])],
+                duration: 0.0,
+            })
+            .collect();
+        Model {
+            nodes: vec![node],
+            meshes: Vec::new(),
+            materials: Vec::new(),
+            skins: Vec::new(),
+            animations,
+            roots: vec![0],
+        }
+    }
+
+    #[test]
+    fn times_outside_the_keyframes_clamp() {
+        let translation = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        for interpolation in [Interpolation::Step, Interpolation::Linear] {
+            let channel = channel(Property::Translation, interpolation, &[1.0, 2.0], &translation);
+            assert_eq!(sample(&channel, -1.0), [1.0, 2.0, 3.0]);
+            assert_eq!(sample(&channel, 1.0), [1.0, 2.0, 3.0]);
+            assert_eq!(sample(&channel, 2.0), [4.0, 5.0, 6.0]);
+            assert_eq!(sample(&channel, 10.0), [4.0, 5.0, 6.0]);
+        }
+    }
+
+    #[test]
+    fn step_holds_each_keyframe() {
+        let channel = channel(Property::Weights, Interpolation::Step, &[0.0, 1.0, 2.0], &[0.0, 10.0, 20.0]);
+        assert_eq!(sample(&channel, 0.99), [0.0]);
+        assert_eq!(sample(&channel, 1.0), [10.0]);
+        assert_eq!(sample(&channel, 1.5), [10.0]);
+    }
+
+    #[test]
+    fn linear_goes_in_a_straight_line() {
+        let channel = channel(Property::Scale, Interpolation::Linear, &[1.0, 3.0], &[0.0, 0.0, 1.0, 2.0, 4.0, 1.0]);
+        assert_near(sample(&channel, 2.0), [1.0, 2.0, 1.0]);
+        assert_near(sample(&channel, 2.5), [1.5, 3.0, 1.0]);
+    }
+
+    #[test]
+    fn linear_rotations_slerp() {
+        let values: Vec<f32> = [about_y(0.0), about_y(90.0)].concat();
+        let channel = channel(Property::Rotation, Interpolation::Linear, &[0.0, 1.0], &values);
+        assert_near(sample(&channel, 0.5), about_y(45.0));
+        assert_near(sample(&channel, 0.25), about_y(22.5));
+    }
+
+    #[test]
+    fn cubic_splines_pass_through_their_keyframes() {
+        // in-tangent, value, out-tangent for each of three keyframes
+        let values = [5.0, 0.0, 1.0, -2.0, 3.0, 4.0, 0.0, -1.0, 9.0];
+        let channel = channel(Property::Weights, Interpolation::CubicSpline, &[0.0, 1.0, 3.0], &values);
+        assert_near(sample(&channel, 0.0), [0.0]);
+        assert_near(sample(&channel, 1.0), [3.0]);
+        assert_near(sample(&channel, 3.0), [-1.0]);
+        assert_near(sample(&channel, 7.0), [-1.0]);
+    }
+
+    #[test]
+    fn cubic_spline_tangents_scale_with_the_keyframe_gap() {
+        // flat at both ends but leaving the first at a slope of 1
+        let values = [0.0, 0.0, 1.0, 0.0, 0.0, 0.0];
+        let sloped = channel(Property::Weights, Interpolation::CubicSpline, &[0.0, 2.0], &values);
+        // (t³ - 2t² + t) * 2 at t = 0.5
+        assert_near(sample(&sloped, 1.0), [0.25]);
+        // and with no tangents it eases from one value to the other
+        let values = [0.0, 0.0, 0.0, 0.0, 4.0, 0.0];
+        let eased = channel(Property::Weights, Interpolation::CubicSpline, &[0.0, 2.0], &values);
+        assert_near(sample(&eased, 1.0), [2.0]);
+        assert_near(sample(&eased, 0.5), [4.0 * (3.0 * 0.0625 - 2.0 * 0.015625)]);
+    }
+
+    #[test]
+    fn cubic_spline_rotations_stay_unit_length() {
+        let zero = [0.0; 4];
+        let values: Vec<f32> = [zero, about_y(0.0), zero, zero, about_y(90.0), zero].concat();
+        let channel = channel(Property::Rotation, Interpolation::CubicSpline, &[0.0, 1.0], &values);
+        let q: [f32; 4] = sample(&channel, 0.5);
+        assert!((quaternion(&q).magnitude() - 1.0).abs() < 1e-5);
+        assert_near(q, about_y(45.0));
+    }
+
+    #[test]
+    fn looping_wraps_and_one_shots_stop() {
+        let mut playback = Playback {
+            animation: 0,
+            time: 0.9,
+            looping: true,
+        };
+        playback.advance(0.3, 1.0);
+        assert!((playback.time - 0.2).abs() < 1e-5);
+        // backwards past the start comes round to the end
+        playback.advance(-0.5, 1.0);
+        assert!((playback.time - 0.7).abs() < 1e-5);
+
+        playback.looping = false;
+        playback.advance(0.5, 1.0);
+        assert_eq!(playback.time, 1.0);
+        playback.advance(-3.0, 1.0);
+        assert_eq!(playback.time, 0.0);
+    }
+
+    #[test]
+    fn cross_fades_blend_over_their_duration() {
+        let mut model = model(&[0.0, 10.0]);
+        let mut player = AnimationPlayer::new(&model);
+        player.play(0, true);
+        player.update(Duration::ZERO, &mut model);
+        player.cross_fade(1, Duration::from_secs(1), true);
+        let mut x_after = |millis| {
+            player.update(Duration::from_millis(millis), &mut model);
+            model.nodes[0].transform.translation.x
+        };
+        assert_eq!(x_after(0), 0.0);
+        assert!((x_after(250) - 2.5).abs() < 1e-4);
+        assert!((x_after(500) - 7.5).abs() < 1e-4);
+        // and all of the new clip once it's over
+        assert_eq!(x_after(250), 10.0);
+        assert_eq!(x_after(250), 10.0);
+    }
+
+    #[test]
+    fn cutting_over_doesnt_fade() {
+        let mut model = model(&[0.0, 10.0]);
+        let mut player = AnimationPlayer::new(&model);
+        player.play(0, true);
+        player.cross_fade(1, Duration::ZERO, true);
+        player.update(Duration::from_millis(10), &mut model);
+        assert_eq!(model.nodes[0].transform.translation.x, 10.0);
+        assert_eq!(player.current(), Some(1));
+    }
+}
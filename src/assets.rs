@@ -0,0 +1,220 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::*;
+
+use crate::mesh_file;
+use crate::model::{self, Material, MeshData, Model, ModelVertex, Submesh};
+use crate::texture::{MipmapGenerator, Texture};
+
+/// File types `load_model` understands, picked by extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetKind {
+    Gltf,
+    Obj,
+    Image,
+    // what `mesh_file` writes
+    Mesh,
+}
+
+impl AssetKind {
+    pub fn of(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gltf" | "glb" => Some(AssetKind::Gltf),
+            "obj" => Some(AssetKind::Obj),
+            "png" | "jpg" | "jpeg" => Some(AssetKind::Image),
+            "mesh" => Some(AssetKind::Mesh),
+            _ => None,
+        }
+    }
+}
+
+// Whole file contents. On Android relative paths are looked up in the APK's
+// assets, which is where res/ gets packaged.
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    #[cfg(target_os = "android")]
+    if path.is_relative() {
+        return crate::android::read_asset(path);
+    }
+    std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+}
+
+pub fn read_image(path: &Path) -> Result<image::DynamicImage> {
+    let bytes = read(path)?;
+    image::load_from_memory(&bytes).with_context(|| format!("Failed to decode {}", path.display()))
+}
+
+// `gltf::import`, except that APK assets have to be .glb files or embed
+// their buffers and images, external ones are only read from the file system
+pub fn import_gltf(path: &Path) -> Result<(gltf::Document, Vec<gltf::buffer::Data>, Vec<gltf::image::Data>)> {
+    #[cfg(target_os = "android")]
+    if path.is_relative() {
+        return gltf::import_slice(read(path)?).with_context(|| format!("Failed to load {}", path.display()));
+    }
+    gltf::import(path).with_context(|| format!("Failed to load {}", path.display()))
+}
+
+// Loads anything that can be placed in the scene: glTF, OBJ and .mesh files
+// as they are, images as a textured quad
+pub fn load_model(device: &wgpu::Device, queue: &wgpu::Queue, path: impl AsRef<Path>) -> Result<Model> {
+    let path = path.as_ref();
+    match AssetKind::of(path) {
+        Some(AssetKind::Gltf) => Model::load(device, queue, path),
+        Some(AssetKind::Obj) => load_obj(device, queue, path),
+        Some(AssetKind::Image) => load_image_quad(device, queue, path),
+        Some(AssetKind::Mesh) => mesh_file::load(device, path),
+        None => bail!("{}: unsupported file type", path.display()),
+    }
+}
+
+// The meshes of a glTF or OBJ file on the CPU, with levels of detail, for
+// `mesh_file` to write. See `model::gltf_mesh_data` for what's kept of a
+// glTF file's nodes.
+pub fn load_mesh_data(path: &Path) -> Result<Vec<(MeshData, usize)>> {
+    match AssetKind::of(path) {
+        Some(AssetKind::Gltf) => {
+            let (document, buffers, _) = import_gltf(path)?;
+            model::gltf_mesh_data(&document, &buffers).with_context(|| format!("Failed to load {}", path.display()))
+        }
+        Some(AssetKind::Obj) => Ok(vec![(load_obj_mesh(path)?.0, 0)]),
+        _ => bail!("{}: only glTF and OBJ files have meshes to convert", path.display()),
+    }
+}
+
+fn vertex(position: [f32; 3], normal: [f32; 3], tex_coords: [f32; 2], color: [f32; 3]) -> ModelVertex {
+    ModelVertex {
+        position,
+        normal,
+        tex_coords,
+        color,
+        joints: [0; 4],
+        weights: [1.0, 0.0, 0.0, 0.0],
+        // neither format says, so plain rough dielectrics
+        metallic_roughness: [0.0, 1.0],
+    }
+}
+
+fn load_obj(device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<Model> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let (data, materials) = load_obj_mesh(path)?;
+    let mut mipmaps = MipmapGenerator::new(device);
+    let materials = materials
+        .iter()
+        .map(|material| Material {
+            name: Some(material.name.clone()),
+            base_color_texture: material.diffuse_texture.as_ref().and_then(|file| {
+                let texture_path = dir.join(file);
+                match read_image(&texture_path) {
+                    Result::Ok(image) => {
+                        let rgba = image.to_rgba8();
+                        let (width, height) = rgba.dimensions();
+                        Some(Texture::from_rgba_with_mipmaps(device, queue, &mut mipmaps, &rgba, width, height, Some(&material.name)))
+                    }
+                    Err(e) => {
+                        log::warn!("{:#}", e);
+                        None
+                    }
+                }
+            }),
+        })
+        .collect();
+    Ok(Model::from_meshes(vec![data.upload(device)], materials))
+}
+
+// Every object in the file goes into one mesh, with a submesh per object,
+// and the materials from its .mtl file
+fn load_obj_mesh(path: &Path) -> Result<(MeshData, Vec<tobj::Material>)> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let obj = read(path)?;
+    let (objects, materials) = tobj::load_obj_buf(&mut Cursor::new(obj), &tobj::GPU_LOAD_OPTIONS, |mtl_path| {
+        let mtl = read(&dir.join(mtl_path)).map_err(|_| tobj::LoadError::OpenFileFailed)?;
+        tobj::load_mtl_buf(&mut Cursor::new(mtl))
+    })
+    .with_context(|| format!("Failed to load {}", path.display()))?;
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("{}: no materials ({})", path.display(), e);
+        Vec::new()
+    });
+
+    let mut data = MeshData {
+        name: path.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+        ..Default::default()
+    };
+    for object in &objects {
+        let mesh = &object.mesh;
+        let material = mesh.material_id.filter(|&id| id < materials.len());
+        let color = material.and_then(|id| materials[id].diffuse).unwrap_or([1.0; 3]);
+
+        let base = data.vertices.len();
+        data.vertices.extend(mesh.positions.chunks_exact(3).enumerate().map(|(i, p)| {
+            let normal = mesh.normals.get(i * 3..i * 3 + 3).map_or([0.0; 3], |n| [n[0], n[1], n[2]]);
+            // OBJ's v axis points up
+            let tex_coords = mesh.texcoords.get(i * 2..i * 2 + 2).map_or([0.0; 2], |t| [t[0], 1.0 - t[1]]);
+            vertex([p[0], p[1], p[2]], normal, tex_coords, color)
+        }));
+        if mesh.normals.is_empty() {
+            smooth_normals(&mut data.vertices[base..], &mesh.indices);
+        }
+
+        let first_index = data.indices.len() as u32;
+        data.indices.extend(mesh.indices.iter().map(|&i| base as u32 + i));
+        data.submeshes.push(Submesh {
+            indices: first_index..data.indices.len() as u32,
+            material,
+        });
+    }
+
+    data.generate_lods();
+    Ok((data, materials))
+}
+
+// Area weighted vertex normals, for meshes that come without any
+fn smooth_normals(vertices: &mut [ModelVertex], indices: &[u32]) {
+    let mut normals = vec![Vector3::zero(); vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(vertices[triangle[i] as usize].position));
+        let normal = (b - a).cross(c - a);
+        for &i in triangle {
+            normals[i as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        if normal.magnitude2() > 0.0 {
+            vertex.normal = normal.normalize().into();
+        }
+    }
+}
+
+// A quad one unit tall with the image's aspect ratio, facing +z. Both sides
+// are drawn, the back shows the image mirrored.
+fn load_image_quad(device: &wgpu::Device, queue: &wgpu::Queue, path: &Path) -> Result<Model> {
+    let image = read_image(path)?.to_rgba8();
+    let (width, height) = image.dimensions();
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+    let texture = Texture::from_rgba(device, queue, &image, width, height, name.as_deref());
+
+    let half_width = width as f32 / height.max(1) as f32 * 0.5;
+    let corners = [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+    let side = |normal_z: f32| {
+        corners.map(|[x, y]| vertex([x * half_width, y * 0.5, 0.0], [0.0, 0.0, normal_z], [(x + 1.0) / 2.0, (1.0 - y) / 2.0], [1.0; 3]))
+    };
+
+    let data = MeshData {
+        name: name.clone(),
+        vertices: [side(1.0), side(-1.0)].concat(),
+        // counter-clockwise from the side each half faces
+        indices: vec![0, 1, 2, 0, 2, 3, 4, 6, 5, 4, 7, 6],
+        submeshes: vec![Submesh {
+            indices: 0..12,
+            material: Some(0),
+        }],
+        ..Default::default()
+    };
+    let material = Material {
+        name,
+        base_color_texture: Some(texture),
+    };
+    Ok(Model::from_meshes(vec![data.upload(device)], vec![material]))
+}
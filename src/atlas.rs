@@ -0,0 +1,493 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::time::Duration;
+
+use anyhow::*;
+use serde::Deserialize;
+
+use crate::texture::Texture;
+
+// Frame length for sprite sheets that don't specify one
+const DEFAULT_FRAME_DURATION: Duration = Duration::from_millis(100);
+
+/// Region of an atlas in pixels and in normalized texture coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtlasRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+}
+
+impl AtlasRect {
+    fn new(x: u32, y: u32, width: u32, height: u32, atlas_width: u32, atlas_height: u32) -> Self {
+        let (aw, ah) = (atlas_width as f32, atlas_height as f32);
+        Self {
+            x,
+            y,
+            width,
+            height,
+            uv_min: [x as f32 / aw, y as f32 / ah],
+            uv_max: [(x + width) as f32 / aw, (y + height) as f32 / ah],
+        }
+    }
+}
+
+/// Many images packed into one texture, so 2D content can share a single
+/// bind group and be drawn in one batch.
+pub struct TextureAtlas {
+    pub texture: Texture,
+    rects: Vec<AtlasRect>,
+    names: HashMap<String, usize>,
+}
+
+impl TextureAtlas {
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    pub fn rect(&self, index: usize) -> Option<AtlasRect> {
+        self.rects.get(index).copied()
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.get(name).copied()
+    }
+
+    pub fn get(&self, name: &str) -> Option<AtlasRect> {
+        self.index_of(name).and_then(|index| self.rect(index))
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.texture.texture.width(), self.texture.texture.height())
+    }
+}
+
+// Result of packing: atlas size and the top-left corner of every padded image
+#[derive(Debug)]
+struct Packing {
+    width: u32,
+    height: u32,
+    positions: Vec<(u32, u32)>,
+}
+
+/// Collects images and shelf-packs them into a `TextureAtlas`. Indices
+/// follow the order images were added in.
+pub struct AtlasBuilder {
+    max_size: u32,
+    // empty border around each image, filled by stretching its edge pixels
+    // so filtering and smaller mips don't pull in the neighbours
+    padding: u32,
+    images: Vec<(String, image::RgbaImage)>,
+}
+
+impl AtlasBuilder {
+    pub fn new(max_size: u32) -> Self {
+        Self {
+            max_size,
+            padding: 2,
+            images: Vec::new(),
+        }
+    }
+
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn add(&mut self, name: impl Into<String>, image: image::RgbaImage) -> usize {
+        self.images.push((name.into(), image));
+        self.images.len() - 1
+    }
+
+    // Tries power of two sizes from small to `max_size` until everything fits
+    fn pack(&self) -> Result<Packing> {
+        let padded: Vec<(u32, u32)> = self
+            .images
+            .iter()
+            .map(|(_, img)| (img.width() + 2 * self.padding, img.height() + 2 * self.padding))
+            .collect();
+        // tallest first keeps shelves tight
+        let mut order: Vec<usize> = (0..padded.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(padded[i].1));
+
+        // start around square, shelves waste some space so it may grow
+        let widest = padded.iter().map(|s| s.0).max().unwrap_or(1);
+        let area: u64 = padded.iter().map(|&(w, h)| w as u64 * h as u64).sum();
+        let mut width = widest.max((area as f64).sqrt().ceil() as u32).next_power_of_two();
+        while width <= self.max_size {
+            let mut positions = vec![(0, 0); padded.len()];
+            let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+            for &i in &order {
+                let (w, h) = padded[i];
+                if x + w > width {
+                    x = 0;
+                    y += shelf_height;
+                    shelf_height = 0;
+                }
+                positions[i] = (x, y);
+                x += w;
+                shelf_height = shelf_height.max(h);
+            }
+            let height = (y + shelf_height).max(1).next_power_of_two();
+            if height <= self.max_size {
+                return Ok(Packing {
+                    width,
+                    height,
+                    positions,
+                });
+            }
+            width *= 2;
+        }
+        bail!("{} images don't fit in a {size}x{size} atlas", self.images.len(), size = self.max_size)
+    }
+
+    pub fn build(self, device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> Result<TextureAtlas> {
+        ensure!(!self.images.is_empty(), "{}: atlas has no images", label);
+        // there'd be no edge to stretch into the padding
+        if let Some((name, img)) = self.images.iter().find(|(_, img)| img.width() == 0 || img.height() == 0) {
+            bail!("{}: {} is {}x{}, with nothing to pack", label, name, img.width(), img.height());
+        }
+        let Packing {
+            width,
+            height,
+            positions,
+        } = self.pack()?;
+
+        let mut pixels = image::RgbaImage::new(width, height);
+        let mut rects = Vec::with_capacity(self.images.len());
+        let mut names = HashMap::new();
+        let p = self.padding;
+        for (index, ((name, img), (x, y))) in self.images.iter().zip(positions).enumerate() {
+            let (w, h) = img.dimensions();
+            for py in 0..h + 2 * p {
+                for px in 0..w + 2 * p {
+                    // clamp into the image to extrude its border into the padding
+                    let sx = px.saturating_sub(p).min(w - 1);
+                    let sy = py.saturating_sub(p).min(h - 1);
+                    pixels.put_pixel(x + px, y + py, *img.get_pixel(sx, sy));
+                }
+            }
+            rects.push(AtlasRect::new(x + p, y + p, w, h, width, height));
+            names.insert(name.clone(), index);
+        }
+
+        let texture = Texture::from_rgba(device, queue, &pixels, width, height, Some(label));
+        Ok(TextureAtlas {
+            texture,
+            rects,
+            names,
+        })
+    }
+}
+
+/// One frame of a sprite sheet.
+#[derive(Debug, Clone)]
+pub struct SpriteFrame {
+    pub name: String,
+    pub rect: AtlasRect,
+    pub duration: Duration,
+}
+
+/// A pre-packed sprite sheet image plus its frame layout, either a
+/// uniform grid or TexturePacker-style JSON metadata.
+pub struct SpriteSheet {
+    pub texture: Texture,
+    frames: Vec<SpriteFrame>,
+    names: HashMap<String, usize>,
+}
+
+// TexturePacker writes frames either as a map keyed by name ("JSON Hash")
+// or as a list with a filename field ("JSON Array")
+#[derive(Deserialize)]
+struct SheetJson {
+    frames: SheetFramesJson,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SheetFramesJson {
+    Hash(HashMap<String, FrameJson>),
+    Array(Vec<FrameJson>),
+}
+
+#[derive(Deserialize)]
+struct FrameJson {
+    #[serde(default)]
+    filename: String,
+    frame: RectJson,
+    // milliseconds, as written by Aseprite
+    duration: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RectJson {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+impl SpriteSheet {
+    /// `columns` x `rows` equally sized frames, numbered row by row.
+    pub fn from_grid(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::RgbaImage,
+        columns: u32,
+        rows: u32,
+        label: &str,
+    ) -> Result<Self> {
+        ensure!(columns > 0 && rows > 0, "{}: grid needs at least one frame", label);
+        let (width, height) = image.dimensions();
+        let (frame_width, frame_height) = (width / columns, height / rows);
+        let frames = (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (row, column)))
+            .map(|(row, column)| SpriteFrame {
+                name: format!("{}", row * columns + column),
+                rect: AtlasRect::new(
+                    column * frame_width,
+                    row * frame_height,
+                    frame_width,
+                    frame_height,
+                    width,
+                    height,
+                ),
+                duration: DEFAULT_FRAME_DURATION,
+            })
+            .collect();
+        Ok(Self::new(device, queue, image, frames, label))
+    }
+
+    /// Frame metadata in the JSON format TexturePacker and Aseprite export.
+    pub fn from_json(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::RgbaImage,
+        json: &str,
+        label: &str,
+    ) -> Result<Self> {
+        let (width, height) = image.dimensions();
+        let frames = parse_frames(json, width, height, label)?;
+        Ok(Self::new(device, queue, image, frames, label))
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        image: &image::RgbaImage,
+        frames: Vec<SpriteFrame>,
+        label: &str,
+    ) -> Self {
+        let texture = Texture::from_rgba(device, queue, image, image.width(), image.height(), Some(label));
+        let names = frames
+            .iter()
+            .enumerate()
+            .map(|(index, frame)| (frame.name.clone(), index))
+            .collect();
+        Self {
+            texture,
+            frames,
+            names,
+        }
+    }
+
+    pub fn frames(&self) -> &[SpriteFrame] {
+        &self.frames
+    }
+
+    pub fn frame(&self, index: usize) -> Option<&SpriteFrame> {
+        self.frames.get(index)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SpriteFrame> {
+        self.names.get(name).map(|&index| &self.frames[index])
+    }
+
+    /// The frame showing `elapsed` into a looping playback of every frame.
+    pub fn frame_at(&self, elapsed: Duration) -> usize {
+        frame_at(&self.frames, elapsed)
+    }
+}
+
+// The frames of a sheet JSON describes, for a `width` x `height` image, in
+// the order they play: a JSON Array's own order, and a JSON Hash's by name,
+// as its map order isn't kept
+fn parse_frames(json: &str, width: u32, height: u32, label: &str) -> Result<Vec<SpriteFrame>> {
+    let sheet: SheetJson = serde_json::from_str(json).with_context(|| format!("{}: invalid sprite sheet JSON", label))?;
+    let frames: Vec<FrameJson> = match sheet.frames {
+        SheetFramesJson::Array(frames) => frames,
+        SheetFramesJson::Hash(frames) => {
+            let mut frames: Vec<FrameJson> = frames
+                .into_iter()
+                .map(|(filename, frame)| FrameJson { filename, ..frame })
+                .collect();
+            // names usually carry the frame number, "walk 2" before "walk 10"
+            frames.sort_by(|a, b| natural_cmp(&a.filename, &b.filename).then_with(|| a.filename.cmp(&b.filename)));
+            frames
+        }
+    };
+    frames
+        .into_iter()
+        .map(|frame| {
+            let RectJson { x, y, w, h } = frame.frame;
+            let inside = |start: u32, size: u32, end| start.checked_add(size).is_some_and(|far| far <= end);
+            ensure!(
+                inside(x, w, width) && inside(y, h, height),
+                "{}: frame {} lies outside the {}x{} image",
+                label,
+                frame.filename,
+                width,
+                height
+            );
+            Ok(SpriteFrame {
+                name: frame.filename,
+                rect: AtlasRect::new(x, y, w, h, width, height),
+                duration: frame.duration.map_or(DEFAULT_FRAME_DURATION, Duration::from_millis),
+            })
+        })
+        .collect()
+}
+
+// Names compared with runs of digits as the numbers they are, so "walk 9"
+// comes before "walk 10". Leading zeros don't count, "walk 01" and "walk 1"
+// are equal.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    fn split_number(s: &str) -> (&str, &str) {
+        s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()))
+    }
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let ((a_number, a_rest), (b_number, b_rest)) = (split_number(a), split_number(b));
+            let (a_number, b_number) = (a_number.trim_start_matches('0'), b_number.trim_start_matches('0'));
+            // more digits is a bigger number, as many compare digit by digit
+            let order = a_number.len().cmp(&b_number.len()).then_with(|| a_number.cmp(b_number));
+            if order != Ordering::Equal {
+                return order;
+            }
+            (a, b) = (a_rest, b_rest);
+        } else if x != y {
+            return x.cmp(&y);
+        } else {
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+// Which of `frames` shows `elapsed` into a looping playback of them all
+fn frame_at(frames: &[SpriteFrame], elapsed: Duration) -> usize {
+    let total: Duration = frames.iter().map(|frame| frame.duration).sum();
+    if total.is_zero() {
+        return 0;
+    }
+    let mut t = Duration::from_nanos((elapsed.as_nanos() % total.as_nanos()) as u64);
+    for (index, frame) in frames.iter().enumerate() {
+        if t < frame.duration {
+            return index;
+        }
+        t -= frame.duration;
+    }
+    frames.len() - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder(max_size: u32, padding: u32, sizes: &[(u32, u32)]) -> AtlasBuilder {
+        let mut builder = AtlasBuilder::new(max_size).with_padding(padding);
+        for (index, &(width, height)) in sizes.iter().enumerate() {
+            builder.add(format!("{}", index), image::RgbaImage::new(width, height));
+        }
+        builder
+    }
+
+    fn frame(name: &str, millis: u64) -> SpriteFrame {
+        SpriteFrame {
+            name: name.into(),
+            rect: AtlasRect::new(0, 0, 1, 1, 1, 1),
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    fn names(frames: &[SpriteFrame]) -> Vec<&str> {
+        frames.iter().map(|frame| frame.name.as_str()).collect()
+    }
+
+    #[test]
+    fn pack_fits_the_smallest_square() {
+        // 16x16 each once padded
+        let packing = builder(64, 2, &[(12, 12); 4]).pack().unwrap();
+        assert_eq!((packing.width, packing.height), (32, 32));
+        assert_eq!(packing.positions, [(0, 0), (16, 0), (0, 16), (16, 16)]);
+    }
+
+    #[test]
+    fn pack_grows_when_the_shelves_dont_fit() {
+        // side by side they're too wide for 32, stacked too tall for 64
+        let packing = builder(64, 0, &[(30, 17), (4, 60)]).pack().unwrap();
+        assert_eq!((packing.width, packing.height), (64, 64));
+        // tallest first
+        assert_eq!(packing.positions, [(4, 0), (0, 0)]);
+    }
+
+    #[test]
+    fn pack_stops_at_max_size() {
+        let error = builder(32, 0, &[(30, 17), (4, 60)]).pack().unwrap_err();
+        assert_eq!(error.to_string(), "2 images don't fit in a 32x32 atlas");
+        assert!(builder(32, 2, &[(32, 32)]).pack().is_err());
+    }
+
+    #[test]
+    fn frame_at_loops_over_the_durations() {
+        let frames = [frame("a", 100), frame("b", 200), frame("c", 100)];
+        let at = |millis| frame_at(&frames, Duration::from_millis(millis));
+        assert_eq!([at(0), at(99), at(100), at(299), at(300), at(399)], [0, 0, 1, 1, 2, 2]);
+        assert_eq!([at(400), at(550), at(4_000_300)], [0, 1, 2]);
+        assert_eq!(frame_at(&[frame("a", 0)], Duration::from_secs(1)), 0);
+    }
+
+    #[test]
+    fn array_sheets_keep_their_order() {
+        let json = r#"{"frames": [
+            {"filename": "walk 10", "frame": {"x": 0, "y": 0, "w": 4, "h": 4}},
+            {"filename": "walk 2", "frame": {"x": 4, "y": 0, "w": 4, "h": 4}, "duration": 50}
+        ]}"#;
+        let frames = parse_frames(json, 8, 4, "sheet").unwrap();
+        assert_eq!(names(&frames), ["walk 10", "walk 2"]);
+        assert_eq!(frames[0].duration, DEFAULT_FRAME_DURATION);
+        assert_eq!(frames[1].duration, Duration::from_millis(50));
+        assert_eq!(frames[1].rect.uv_min, [0.5, 0.0]);
+    }
+
+    #[test]
+    fn hash_sheets_go_in_number_order() {
+        let frame = r#"{"frame": {"x": 0, "y": 0, "w": 1, "h": 1}}"#;
+        let keys = ["walk 10.png", "walk 2.png", "walk 1.png", "run 3.png", "walk 02.png"];
+        let entries: Vec<String> = keys.iter().map(|key| format!(r#""{}": {}"#, key, frame)).collect();
+        let json = format!(r#"{{"frames": {{{}}}}}"#, entries.join(", "));
+        let frames = parse_frames(&json, 1, 1, "sheet").unwrap();
+        assert_eq!(names(&frames), ["run 3.png", "walk 1.png", "walk 02.png", "walk 2.png", "walk 10.png"]);
+    }
+
+    #[test]
+    fn frames_have_to_be_inside_the_image() {
+        let sheet = |x: u32, w: u32| {
+            format!(r#"{{"frames": [{{"frame": {{"x": {}, "y": 0, "w": {}, "h": 1}}}}]}}"#, x, w)
+        };
+        assert!(parse_frames(&sheet(4, 4), 8, 1, "sheet").is_ok());
+        assert!(parse_frames(&sheet(5, 4), 8, 1, "sheet").is_err());
+        // rather than wrapping around to inside it
+        assert!(parse_frames(&sheet(u32::MAX, 2), 8, 1, "sheet").is_err());
+    }
+}
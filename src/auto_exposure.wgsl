@@ -0,0 +1,80 @@
+// Auto exposure: a histogram of the HDR scene's log luminance, then its
+// average eased into the exposure the tonemap pass reads
+
+// Mirrors `ExposureParams` in exposure.rs
+struct ExposureParams {
+    min_luminance: f32,
+    luminance_range: f32,
+    brighten: f32,
+    darken: f32,
+    key: f32,
+    pixel_count: u32,
+    _padding: vec2<u32>,
+};
+
+// Matches `ExposureUniform` in exposure.rs
+struct ExposureUniform {
+    exposure: f32,
+    luminance: f32,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0) var<uniform> params: ExposureParams;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2) var scene: texture_2d<f32>;
+@group(0) @binding(3) var<storage, read_write> exposure: ExposureUniform;
+
+var<workgroup> tile_bins: array<atomic<u32>, 256>;
+var<workgroup> weighted: array<u32, 256>;
+
+// Bin 0 is black, 1 to 255 cover the range in stops
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if luminance < 1e-5 {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - params.min_luminance) / params.luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+// One 16x16 tile of the scene per workgroup, binned in workgroup memory
+// first so the histogram only sees one add per bin and tile
+@compute @workgroup_size(16, 16)
+fn cs_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    atomicStore(&tile_bins[index], 0u);
+    workgroupBarrier();
+    let size = textureDimensions(scene);
+    if id.x < size.x && id.y < size.y {
+        let bin = luminance_bin(textureLoad(scene, id.xy, 0).rgb);
+        atomicAdd(&tile_bins[bin], 1u);
+    }
+    workgroupBarrier();
+    atomicAdd(&histogram[index], atomicLoad(&tile_bins[index]));
+}
+
+// One workgroup, an invocation per bin. Empties the histogram for the next
+// frame on the way.
+@compute @workgroup_size(256)
+fn cs_average(@builtin(local_invocation_index) index: u32) {
+    let count = atomicExchange(&histogram[index], 0u);
+    weighted[index] = count * index;
+    workgroupBarrier();
+    for (var stride = 128u; stride > 0u; stride >>= 1u) {
+        if index < stride {
+            weighted[index] += weighted[index + stride];
+        }
+        workgroupBarrier();
+    }
+    // invocation 0's count is the black pixels'
+    if index == 0u {
+        let lit = max(f32(params.pixel_count) - f32(count), 1.0);
+        let bin = f32(weighted[0]) / lit - 1.0;
+        let average = bin / 254.0 * params.luminance_range + params.min_luminance;
+        let rate = select(params.darken, params.brighten, average > exposure.luminance);
+        exposure.luminance = mix(exposure.luminance, average, rate);
+        exposure.exposure = params.key / exp2(exposure.luminance);
+    }
+}
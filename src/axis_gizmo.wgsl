@@ -0,0 +1,41 @@
+// The world's x, y and z axes as the camera sees them, in red, green and
+// blue from the middle of the viewport they're drawn in. Those pointing
+// away from the camera are fainter.
+
+// Matches `GizmoUniform` in grid.rs
+struct GizmoUniform {
+    // each axis's end in the viewport's clip space, and in z how much it
+    // points towards the camera
+    axes: array<vec4<f32>, 3>,
+};
+
+@group(0) @binding(0)
+var<uniform> gizmo: GizmoUniform;
+
+const COLORS: array<vec3<f32>, 3> = array<vec3<f32>, 3>(
+    vec3<f32>(1.0, 0.2, 0.2),
+    vec3<f32>(0.2, 1.0, 0.2),
+    vec3<f32>(0.3, 0.5, 1.0),
+);
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+};
+
+// Two vertices a line, one line an axis
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    var colors = COLORS;
+    let axis = gizmo.axes[index / 2u];
+    let end = f32(index & 1u);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(axis.xy * end, 0.5, 1.0);
+    out.color = vec4<f32>(colors[index / 2u], 0.6 + 0.4 * axis.z);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return in.color;
+}
@@ -0,0 +1,143 @@
+use cgmath::*;
+
+/// Axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // Smallest box containing every point, None for an empty iterator
+    pub fn from_points<I: IntoIterator<Item = Point3<f32>>>(points: I) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(Self::new(first, first), |aabb, p| aabb.including(p)))
+    }
+
+    pub fn including(self, p: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(self.min.x.min(p.x), self.min.y.min(p.y), self.min.z.min(p.z)),
+            max: Point3::new(self.max.x.max(p.x), self.max.y.max(p.y), self.max.z.max(p.z)),
+        }
+    }
+
+    pub fn union(self, other: Aabb) -> Self {
+        self.including(other.min).including(other.max)
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // From `p` to the nearest point in the box, zero inside it
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        let nearest = Point3::new(
+            p.x.clamp(self.min.x, self.max.x),
+            p.y.clamp(self.min.y, self.max.y),
+            p.z.clamp(self.min.z, self.max.z),
+        );
+        (p - nearest).magnitude()
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Point3::new(a.x, a.y, a.z),
+            Point3::new(b.x, a.y, a.z),
+            Point3::new(a.x, b.y, a.z),
+            Point3::new(b.x, b.y, a.z),
+            Point3::new(a.x, a.y, b.z),
+            Point3::new(b.x, a.y, b.z),
+            Point3::new(a.x, b.y, b.z),
+            Point3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    // Bounds of this box after an arbitrary transform
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        Self::from_points(self.corners().iter().map(|c| transform.transform_point(*c)))
+            .unwrap_or(*self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn new(center: Point3<f32>, radius: f32) -> Self {
+        Self { center, radius }
+    }
+
+    // Bounds of this sphere after a transform, non-uniform scale
+    // is handled conservatively by taking the largest axis
+    pub fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        let scale = transform.x.truncate().magnitude()
+            .max(transform.y.truncate().magnitude())
+            .max(transform.z.truncate().magnitude());
+        Self {
+            center: transform.transform_point(self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+impl From<Aabb> for Sphere {
+    fn from(aabb: Aabb) -> Self {
+        Self::new(aabb.center(), aabb.half_extents().magnitude())
+    }
+}
+
+/// Six inward facing planes as (normal, distance), so a point is inside
+/// when `dot(normal, p) + distance >= 0` for every plane.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Gribb/Hartmann plane extraction, for wgpu's 0..1 clip space depth
+    pub fn from_view_proj(m: &Matrix4<f32>) -> Self {
+        let (r0, r1, r2, r3) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let planes = [
+            r3 + r0, // left
+            r3 - r0, // right
+            r3 + r1, // bottom
+            r3 - r1, // top
+            r2,      // near
+            r3 - r2, // far
+        ]
+        .map(|p| p / p.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        let center = sphere.center.to_vec();
+        self.planes
+            .iter()
+            .all(|p| p.truncate().dot(center) + p.w >= -sphere.radius)
+    }
+
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        // test the box corner furthest along each plane normal
+        self.planes.iter().all(|p| {
+            let positive = Vector3::new(
+                if p.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if p.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if p.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            p.truncate().dot(positive) + p.w >= 0.0
+        })
+    }
+}
@@ -0,0 +1,502 @@
+use std::f32::consts::FRAC_PI_2;
+use std::time::Duration;
+
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+use crate::actions::ActionMap;
+use crate::external_uniforms::ExternalUniform;
+use crate::fog::FogUniform;
+use crate::lighting::{GpuLight, LightUniform};
+use crate::reflection_probes::ProbeUniform;
+
+// cgmath is built for OpenGL's coordinate system, where the depth range
+// goes from -1.0 to 1.0. wgpu expects depth to go from 0.0 to 1.0
+#[rustfmt::skip]
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+// keeps the camera from flipping over when looking straight up or down
+const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+
+// what `ProjectionMode::next` switches to from a perspective view
+const ORTHOGRAPHIC_HEIGHT: f32 = 10.0;
+const PIXELS_PER_UNIT: f32 = 32.0;
+// How far `Projection::zoom` narrows and widens a perspective, in radians
+const MIN_FOVY: f32 = 0.1;
+const MAX_FOVY: f32 = 2.0;
+
+#[derive(Debug)]
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub yaw: Rad<f32>,
+    pub pitch: Rad<f32>,
+}
+
+impl Camera {
+    pub fn new<V: Into<Point3<f32>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(
+        position: V,
+        yaw: Y,
+        pitch: P,
+    ) -> Self {
+        Self {
+            position: position.into(),
+            yaw: yaw.into(),
+            pitch: pitch.into(),
+        }
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
+        Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize()
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_to_rh(self.position, self.forward(), Vector3::unit_y())
+    }
+}
+
+// How the view's projected onto the screen
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ProjectionMode {
+    // through `fovy`
+    #[default]
+    Perspective,
+    // parallel, `height` world units from the bottom of the view to the
+    // top, the width following the aspect, for CAD-style and map views
+    Orthographic { height: f32 },
+    // parallel, a world unit `pixels_per_unit` pixels across whatever the
+    // window's size, so sprites and tiles at that scale land on whole
+    // pixels while the camera's on a pixel's corner
+    PixelPerfect { pixels_per_unit: f32 },
+}
+
+impl ProjectionMode {
+    pub fn next(self) -> Self {
+        match self {
+            ProjectionMode::Perspective => ProjectionMode::Orthographic { height: ORTHOGRAPHIC_HEIGHT },
+            ProjectionMode::Orthographic { .. } => ProjectionMode::PixelPerfect { pixels_per_unit: PIXELS_PER_UNIT },
+            ProjectionMode::PixelPerfect { .. } => ProjectionMode::Perspective,
+        }
+    }
+}
+
+pub struct Projection {
+    // in pixels, for the pixel perfect mode and the aspect
+    width: u32,
+    height: u32,
+    mode: ProjectionMode,
+    fovy: Rad<f32>,
+    znear: f32,
+    zfar: f32,
+}
+
+impl Projection {
+    pub fn new<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32, zfar: f32) -> Self {
+        Self {
+            width: width.max(1),
+            height: height.max(1),
+            mode: ProjectionMode::Perspective,
+            fovy: fovy.into(),
+            znear,
+            zfar,
+        }
+    }
+
+    pub fn with_mode(self, mode: ProjectionMode) -> Self {
+        Self { mode, ..self }
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        self.width = width.max(1);
+        self.height = height.max(1);
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn mode(&self) -> ProjectionMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: ProjectionMode) {
+        self.mode = mode;
+    }
+
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    // Magnifies the view `factor` times, narrowing a perspective's field of
+    // view or shrinking what an orthographic one spans. A pixel perfect one
+    // keeps its scale, anything else would put texels across pixels.
+    pub fn zoom(&mut self, factor: f32) {
+        match &mut self.mode {
+            ProjectionMode::Perspective => {
+                let half_tan = (self.fovy / 2.0).tan() / factor;
+                self.fovy = Rad((2.0 * half_tan.atan()).clamp(MIN_FOVY, MAX_FOVY));
+            }
+            ProjectionMode::Orthographic { height } => {
+                *height = (*height / factor).clamp(ORTHOGRAPHIC_HEIGHT / 10.0, ORTHOGRAPHIC_HEIGHT * 10.0);
+            }
+            ProjectionMode::PixelPerfect { .. } => {}
+        }
+    }
+
+    pub fn znear(&self) -> f32 {
+        self.znear
+    }
+
+    pub fn zfar(&self) -> f32 {
+        self.zfar
+    }
+
+    // Half of what the view spans vertically `distance` in front of the
+    // camera, in world units
+    pub fn half_height_at(&self, distance: f32) -> f32 {
+        match self.mode {
+            ProjectionMode::Perspective => distance * (self.fovy / 2.0).tan(),
+            ProjectionMode::Orthographic { height } => height / 2.0,
+            ProjectionMode::PixelPerfect { pixels_per_unit } => self.height as f32 / pixels_per_unit / 2.0,
+        }
+    }
+
+    // In OpenGL's depth range, for whatever needs to change it before it's
+    // converted, like the water's oblique near plane
+    pub fn gl_matrix(&self) -> Matrix4<f32> {
+        match self.mode {
+            ProjectionMode::Perspective => perspective(self.fovy, self.aspect(), self.znear, self.zfar),
+            ProjectionMode::Orthographic { height } => {
+                let (half_width, half_height) = (height * self.aspect() / 2.0, height / 2.0);
+                ortho(-half_width, half_width, -half_height, half_height, self.znear, self.zfar)
+            }
+            ProjectionMode::PixelPerfect { pixels_per_unit } => {
+                // the camera on the corner of the pixel left of and below
+                // the middle, so an odd size doesn't leave every pixel
+                // straddling two texels
+                let left = -((self.width / 2) as f32) / pixels_per_unit;
+                let bottom = -((self.height / 2) as f32) / pixels_per_unit;
+                let (width, height) = (self.width as f32 / pixels_per_unit, self.height as f32 / pixels_per_unit);
+                ortho(left, left + width, bottom, bottom + height, self.znear, self.zfar)
+            }
+        }
+    }
+
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        OPENGL_TO_WGPU_MATRIX * self.gl_matrix()
+    }
+
+    // Through `fovy` whatever the mode, for what's infinitely far away like
+    // the sky, which a parallel projection never reaches
+    pub fn gl_sky_matrix(&self) -> Matrix4<f32> {
+        perspective(self.fovy, self.aspect(), self.znear, self.zfar)
+    }
+}
+
+// We need this for Rust to store our data correctly for the shaders
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    view_position: [f32; 4],
+    view_proj: [[f32; 4]; 4],
+    // view matrix on its own, for billboarding and view-space effects
+    view: [[f32; 4]; 4],
+    // without the TAA jitter, this frame's and the last frame's, for
+    // working out how far things moved on screen
+    unjittered_view_proj: [[f32; 4]; 4],
+    prev_view_proj: [[f32; 4]; 4],
+    // the view through `Projection::gl_sky_matrix`, jittered the same, for
+    // directions rather than points
+    sky_view_proj: [[f32; 4]; 4],
+}
+
+impl CameraUniform {
+    pub fn new() -> Self {
+        Self {
+            view_position: [0.0; 4],
+            view_proj: Matrix4::identity().into(),
+            view: Matrix4::identity().into(),
+            unjittered_view_proj: Matrix4::identity().into(),
+            prev_view_proj: Matrix4::identity().into(),
+            sky_view_proj: Matrix4::identity().into(),
+        }
+    }
+
+    // For a pass seen from somewhere other than the camera, like a shadow
+    // map from the light, with nothing moving between frames. The sky's
+    // seen through `projection` too, see `with_sky` when it's parallel.
+    pub fn from_view(position: Point3<f32>, view: Matrix4<f32>, projection: Matrix4<f32>) -> Self {
+        let view_proj = (projection * view).into();
+        Self {
+            view_position: position.to_homogeneous().into(),
+            view_proj,
+            view: view.into(),
+            unjittered_view_proj: view_proj,
+            prev_view_proj: view_proj,
+            sky_view_proj: view_proj,
+        }
+    }
+
+    // The sky through `projection` instead, a perspective one
+    pub fn with_sky(self, projection: Matrix4<f32>) -> Self {
+        Self {
+            sky_view_proj: (projection * Matrix4::from(self.view)).into(),
+            ..self
+        }
+    }
+
+    // As the shaders draw with it, jitter and all
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.view_proj.into()
+    }
+
+    // From this frame's clip space, without the jitter, to where the same
+    // point was in the last frame's
+    pub fn reprojection(&self) -> Matrix4<f32> {
+        let current = Matrix4::from(self.unjittered_view_proj);
+        Matrix4::from(self.prev_view_proj) * current.invert().unwrap_or(Matrix4::identity())
+    }
+
+    // Once a frame, the last frame's matrices become the previous ones
+    pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
+        self.update_jittered(camera, projection, Vector2::zero());
+    }
+
+    // `jitter` shifts the whole image by a fraction of a pixel, in NDC units
+    pub fn update_jittered(&mut self, camera: &Camera, projection: &Projection, jitter: Vector2<f32>) {
+        // w is only there to keep the uniform 16 byte aligned
+        self.view_position = camera.position.to_homogeneous().into();
+        let view = camera.calc_matrix();
+        let view_proj = projection.calc_matrix() * view;
+        // a clip space offset of jitter * w, so jitter after the divide
+        let jitter = Matrix4::from_translation(jitter.extend(0.0));
+        self.view_proj = (jitter * view_proj).into();
+        self.sky_view_proj = (jitter * OPENGL_TO_WGPU_MATRIX * projection.gl_sky_matrix() * view).into();
+        self.view = view.into();
+        self.prev_view_proj = self.unjittered_view_proj;
+        self.unjittered_view_proj = view_proj.into();
+    }
+}
+
+impl Default for CameraUniform {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The camera bind group every 3D pass shares, with the scene's fog at
+// binding 1, its lights at bindings 2 and 7, from
+// `LightBuffers::bind_group_entries`, and its image based lighting, from
+// `Ibl::bind_group_entries`, at 3 to 6, and its reflection probes, from
+// `ReflectionProbes::bind_group_entries`, at 8 and 9, and the host app's
+// values, from `ExternalBuffer::bind_group_entry`, at 10. Shaders that bind
+// it check it with `ShaderReflection::check_group` when their pipelines are
+// created.
+pub fn layout_entries() -> [wgpu::BindGroupLayoutEntry; 11] {
+    let texture = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    };
+    [
+        wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<FogUniform>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<LightUniform>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+        // irradiance, prefiltered radiance and the BRDF table
+        texture(3, wgpu::TextureViewDimension::Cube),
+        texture(4, wgpu::TextureViewDimension::Cube),
+        texture(5, wgpu::TextureViewDimension::D2),
+        wgpu::BindGroupLayoutEntry {
+            binding: 6,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 7,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<GpuLight>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+        texture(8, wgpu::TextureViewDimension::D2Array),
+        wgpu::BindGroupLayoutEntry {
+            binding: 9,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ProbeUniform>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+        wgpu::BindGroupLayoutEntry {
+            binding: 10,
+            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<ExternalUniform>() as wgpu::BufferAddress),
+            },
+            count: None,
+        },
+    ]
+}
+
+// Fly camera driven by digital (action) and analog (gamepad) input.
+// Digital amounts are 0 or 1, analog ones are in [-1, 1] and get added on top.
+#[derive(Debug)]
+pub struct CameraController {
+    amount_left: f32,
+    amount_right: f32,
+    amount_forward: f32,
+    amount_backward: f32,
+    amount_up: f32,
+    amount_down: f32,
+    analog_move: Vector3<f32>,
+    analog_look: Vector2<f32>,
+    // raw mouse motion accumulated since the last update
+    rotate_horizontal: f32,
+    rotate_vertical: f32,
+    speed: f32,
+    // radians per second at full stick deflection
+    look_speed: f32,
+    // radians per mouse count
+    sensitivity: f32,
+}
+
+impl CameraController {
+    pub fn new(speed: f32, look_speed: f32, sensitivity: f32) -> Self {
+        Self {
+            amount_left: 0.0,
+            amount_right: 0.0,
+            amount_forward: 0.0,
+            amount_backward: 0.0,
+            amount_up: 0.0,
+            amount_down: 0.0,
+            analog_move: Vector3::zero(),
+            analog_look: Vector2::zero(),
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            speed,
+            look_speed,
+            sensitivity,
+        }
+    }
+
+    // The move actions held this frame, in place of keys
+    pub fn process_actions(&mut self, actions: &ActionMap) {
+        self.amount_forward = actions.value("move_forward");
+        self.amount_backward = actions.value("move_backward");
+        self.amount_left = actions.value("move_left");
+        self.amount_right = actions.value("move_right");
+        self.amount_up = actions.value("move_up");
+        self.amount_down = actions.value("move_down");
+    }
+
+    // Analog input, sampled once per frame. `movement` is (right, up, forward)
+    // and `look` is (yaw, pitch), both as fractions of full deflection.
+    pub fn process_analog(&mut self, movement: Vector3<f32>, look: Vector2<f32>) {
+        self.analog_move = movement;
+        self.analog_look = look;
+    }
+
+    // Relative mouse motion (DeviceEvent::MouseMotion), not cursor positions
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        self.rotate_horizontal += mouse_dx as f32;
+        self.rotate_vertical += mouse_dy as f32;
+    }
+
+    pub fn update_camera(&mut self, camera: &mut Camera, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        // move forward/backward and left/right on the horizontal plane
+        let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
+        let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
+        let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
+
+        let forward_amount = self.amount_forward - self.amount_backward + self.analog_move.z;
+        let right_amount = self.amount_right - self.amount_left + self.analog_move.x;
+        let up_amount = self.amount_up - self.amount_down + self.analog_move.y;
+        camera.position += forward * forward_amount * self.speed * dt;
+        camera.position += right * right_amount * self.speed * dt;
+        camera.position.y += up_amount * self.speed * dt;
+
+        camera.yaw += Rad(self.analog_look.x * self.look_speed * dt);
+        camera.pitch += Rad(self.analog_look.y * self.look_speed * dt);
+
+        // mouse deltas are already per frame, so they don't get scaled by dt.
+        // Moving the mouse up gives a negative dy, which should pitch up
+        camera.yaw += Rad(self.rotate_horizontal * self.sensitivity);
+        camera.pitch += Rad(-self.rotate_vertical * self.sensitivity);
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        camera.pitch.0 = camera.pitch.0.clamp(-SAFE_FRAC_PI_2, SAFE_FRAC_PI_2);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sky_is_in_view_for_every_projection() {
+        let camera = Camera::new((0.0, 5.0, 0.0), Deg(-90.0), Deg(0.0));
+        let modes = [
+            ProjectionMode::Perspective,
+            ProjectionMode::Orthographic { height: 10.0 },
+            ProjectionMode::PixelPerfect { pixels_per_unit: 16.0 },
+        ];
+        for mode in modes {
+            let projection = Projection::new(640, 480, Deg(45.0), 0.1, 100.0).with_mode(mode);
+            let mut uniform = CameraUniform::new();
+            uniform.update_view_proj(&camera, &projection);
+            let clip = Matrix4::from(uniform.sky_view_proj) * camera.forward().extend(0.0);
+            assert!(clip.w > 0.0, "{:?}", mode);
+            // straight ahead is the middle of the screen
+            assert!((clip.x / clip.w).abs() < 1e-5 && (clip.y / clip.w).abs() < 1e-5, "{:?}", mode);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+// Matches `CameraUniform` in camera.rs. Each shader binds it at its own slot.
+struct CameraUniform {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    view: mat4x4<f32>,
+    unjittered_view_proj: mat4x4<f32>,
+    prev_view_proj: mat4x4<f32>,
+    // perspective even when the view's parallel, for the sky
+    sky_view_proj: mat4x4<f32>,
+};
+
+// How far `world_position`, if it stood still, moved on screen since the
+// last frame, in NDC units and leaving out the TAA jitter
+fn screen_motion(camera: CameraUniform, world_position: vec3<f32>) -> vec2<f32> {
+    return object_motion(camera, world_position, world_position);
+}
+
+// The same for something that was at `prev_world_position` last frame
+fn object_motion(camera: CameraUniform, world_position: vec3<f32>, prev_world_position: vec3<f32>) -> vec2<f32> {
+    let current = camera.unjittered_view_proj * vec4<f32>(world_position, 1.0);
+    let previous = camera.prev_view_proj * vec4<f32>(prev_world_position, 1.0);
+    return current.xy / current.w - previous.xy / previous.w;
+}
@@ -0,0 +1,282 @@
+use std::path::Path;
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::assets;
+
+// what a .cube file may say it is, anything bigger is unlikely to be a LUT
+const MAX_LUT_SIZE: u32 = 128;
+
+/// How the tonemapped frame is graded, as set per scene: lift, gamma and
+/// gain per channel, then the scene's 3D LUT if it has one, mixed in by
+/// `lut_strength`. Grading works on sRGB encoded colours, as they'll be
+/// seen.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorGrading {
+    pub enabled: bool,
+    // raises the blacks towards this, leaving white where it is
+    pub lift: [f32; 3],
+    // above 1 brightens the midtones, below darkens them
+    pub gamma: [f32; 3],
+    // scales everything, white included
+    pub gain: [f32; 3],
+    // 0 leaves the LUT out, 1 is all LUT
+    pub lut_strength: f32,
+}
+
+// off and neutral, until a scene or `'` turns it on
+impl Default for ColorGrading {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lift: [0.0; 3],
+            gamma: [1.0; 3],
+            gain: [1.0; 3],
+            lut_strength: 1.0,
+        }
+    }
+}
+
+// Matches `GradingUniform` in tonemap.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GradingUniform {
+    lift: [f32; 4],
+    gamma: [f32; 4],
+    gain: [f32; 4],
+    lut_strength: f32,
+    lut_size: f32,
+    enabled: u32,
+    _padding: u32,
+}
+
+impl GradingUniform {
+    // `lut_size` is that of the LUT bound with it, None when there's none
+    pub fn new(grading: &ColorGrading, lut_size: Option<u32>) -> Self {
+        Self {
+            lift: extend(grading.lift.map(|lift| lift.clamp(-1.0, 1.0))),
+            gamma: extend(grading.gamma.map(|gamma| gamma.max(0.01))),
+            gain: extend(grading.gain.map(|gain| gain.max(0.0))),
+            lut_strength: if lut_size.is_some() { grading.lut_strength.clamp(0.0, 1.0) } else { 0.0 },
+            lut_size: lut_size.unwrap_or(2) as f32,
+            enabled: grading.enabled as u32,
+            _padding: 0,
+        }
+    }
+}
+
+// vec3s in a uniform take up a vec4's room anyway
+fn extend([x, y, z]: [f32; 3]) -> [f32; 4] {
+    [x, y, z, 0.0]
+}
+
+/// A 3D colour lookup table, `size` entries along each of red, green and
+/// blue, from sRGB encoded colours to sRGB encoded colours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorLut {
+    pub size: u32,
+    // red changing fastest, then green, then blue
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl ColorLut {
+    // The LUT that changes nothing
+    pub fn identity(size: u32) -> Self {
+        let size = size.max(2);
+        let step = 1.0 / (size - 1) as f32;
+        let entries = (0..size * size * size)
+            .map(|i| [i % size, i / size % size, i / (size * size)].map(|c| c as f32 * step))
+            .collect();
+        Self { size, entries }
+    }
+
+    // An Adobe/Resolve .cube file, or a .png strip of `size` squares side by
+    // side, one for each blue, with red across each and green down it, as
+    // the neutral strips LUTs are often painted over come
+    pub fn load(path: &Path) -> Result<Self> {
+        let extension = path.extension().and_then(|ext| ext.to_str()).map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("cube") => {
+                let bytes = assets::read(path)?;
+                let text = String::from_utf8(bytes).with_context(|| format!("{}: not UTF-8 text", path.display()))?;
+                Self::parse_cube(&text).with_context(|| format!("{}: invalid .cube LUT", path.display()))
+            }
+            Some("png") => Self::from_strip(&assets::read_image(path)?)
+                .with_context(|| format!("{}: invalid LUT strip", path.display())),
+            _ => bail!("{}: LUTs need a .cube or .png extension", path.display()),
+        }
+    }
+
+    pub fn parse_cube(text: &str) -> Result<Self> {
+        let mut size = None;
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let line_number = number + 1;
+            let (keyword, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            // three numbers, for an entry or a domain
+            let rgb = |text: &str| -> Option<[f32; 3]> {
+                let values: Result<Vec<f32>, _> = text.split_whitespace().map(str::parse).collect();
+                values.ok()?.try_into().ok()
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_3D_SIZE" => match rest.trim().parse::<u32>().ok() {
+                    Some(value @ 2..=MAX_LUT_SIZE) => size = Some(value),
+                    _ => bail!("line {}: LUT_3D_SIZE has to be 2 to {}", line_number, MAX_LUT_SIZE),
+                },
+                "LUT_1D_SIZE" => bail!("line {}: only 3D LUTs are supported", line_number),
+                // the colours looked up are 0 to 1, so the domain has to be too
+                "DOMAIN_MIN" | "DOMAIN_MAX" => {
+                    let expected = if keyword == "DOMAIN_MIN" { 0.0 } else { 1.0 };
+                    if rgb(rest) != Some([expected; 3]) {
+                        bail!("line {}: only a {} of {} is supported", line_number, keyword, expected);
+                    }
+                }
+                _ => match rgb(line) {
+                    Some(entry) => entries.push(entry),
+                    None => bail!("line {}: expected three numbers, found {:?}", line_number, line),
+                },
+            }
+        }
+        let size = size.context("no LUT_3D_SIZE")?;
+        if entries.len() != (size * size * size) as usize {
+            bail!("{} entries for a size of {}, expected {}", entries.len(), size, size * size * size);
+        }
+        Ok(Self { size, entries })
+    }
+
+    pub fn from_strip(image: &image::DynamicImage) -> Result<Self> {
+        let (width, height) = (image.width(), image.height());
+        if !(2..=MAX_LUT_SIZE).contains(&height) || width != height * height {
+            bail!("a strip is as many squares across as each is tall, {}x{} isn't", width, height);
+        }
+        // 8 or 16 bits, as they are
+        let image = image.to_rgba32f();
+        let size = height;
+        let entries = (0..size * size * size)
+            .map(|i| {
+                let (r, g, b) = (i % size, i / size % size, i / (size * size));
+                let pixel = image.get_pixel(b * size + r, g);
+                [pixel[0], pixel[1], pixel[2]]
+            })
+            .collect();
+        Ok(Self { size, entries })
+    }
+
+    // An Rgba16Float 3D texture, red along x, green along y, blue along z
+    pub fn create_texture(&self, device: &wgpu::Device, queue: &wgpu::Queue, label: &str) -> wgpu::Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: self.size,
+                height: self.size,
+                depth_or_array_layers: self.size,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba16Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let texels: Vec<u16> = self
+            .entries
+            .iter()
+            .flat_map(|&[r, g, b]| [r, g, b, 1.0])
+            .map(|value| half::f16::from_f32(value).to_bits())
+            .collect();
+        queue.write_texture(
+            texture.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                // four half floats a texel
+                bytes_per_row: Some(self.size * 8),
+                rows_per_image: Some(self.size),
+            },
+            texture.size(),
+        );
+        texture
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the identity, with red inverted where blue is 1
+    const CUBE: &str = "\
+# made by hand
+TITLE \"Test LUT\"
+LUT_3D_SIZE 2
+DOMAIN_MIN 0.0 0.0 0.0
+DOMAIN_MAX 1.0 1.0 1.0
+
+# blue 0
+0.0 0.0 0.0
+1.0 0.0 0.0
+0.0 1.0 0.0
+1.0 1.0 0.0
+# blue 1
+1.0 0.0 1.0
+0.0 0.0 1.0
+1.0 1.0 1.0
+0.0 1.0 1.0
+";
+
+    #[test]
+    fn cube_entries_go_red_fastest() {
+        let lut = ColorLut::parse_cube(CUBE).unwrap();
+        assert_eq!(lut.size, 2);
+        let mut expected = ColorLut::identity(2).entries;
+        for entry in &mut expected[4..] {
+            entry[0] = 1.0 - entry[0];
+        }
+        assert_eq!(lut.entries, expected);
+        // red, then green, then blue
+        assert_eq!(lut.entries[1], [1.0, 0.0, 0.0]);
+        assert_eq!(lut.entries[2], [0.0, 1.0, 0.0]);
+        assert_eq!(lut.entries[5], [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn cube_needs_every_entry() {
+        let short = CUBE.trim_end().rsplit_once('\n').unwrap().0;
+        let error = ColorLut::parse_cube(short).unwrap_err();
+        assert_eq!(format!("{}", error), "7 entries for a size of 2, expected 8");
+        let long = format!("{}0.5 0.5 0.5\n", CUBE);
+        assert!(ColorLut::parse_cube(&long).is_err());
+    }
+
+    #[test]
+    fn cube_rejects_what_it_cant_look_up() {
+        let domain = CUBE.replace("DOMAIN_MAX 1.0 1.0 1.0", "DOMAIN_MAX 2.0 2.0 2.0");
+        assert_eq!(
+            format!("{}", ColorLut::parse_cube(&domain).unwrap_err()),
+            "line 5: only a DOMAIN_MAX of 1 is supported"
+        );
+        let one_d = CUBE.replace("LUT_3D_SIZE 2", "LUT_1D_SIZE 2");
+        assert_eq!(format!("{}", ColorLut::parse_cube(&one_d).unwrap_err()), "line 3: only 3D LUTs are supported");
+        let entry = CUBE.replace("1.0 1.0 0.0", "1.0 1.0");
+        let error = format!("{}", ColorLut::parse_cube(&entry).unwrap_err());
+        assert!(error.starts_with("line 11: expected three numbers"), "{}", error);
+        assert!(ColorLut::parse_cube(&CUBE.replace("LUT_3D_SIZE 2\n", "")).is_err());
+    }
+
+    #[test]
+    fn strip_matches_cube_order() {
+        // blue across the squares, red across each and green down it
+        let lut = ColorLut::parse_cube(CUBE).unwrap();
+        let strip = image::RgbImage::from_fn(4, 2, |x, y| {
+            let [r, g, b] = lut.entries[((x / 2) * 4 + y * 2 + x % 2) as usize];
+            image::Rgb([r, g, b].map(|c| (c * 255.0) as u8))
+        });
+        assert_eq!(ColorLut::from_strip(&strip.into()).unwrap(), lut);
+    }
+}
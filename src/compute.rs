@@ -0,0 +1,283 @@
+use std::marker::PhantomData;
+
+use wgpu::util::DeviceExt;
+
+use crate::resources::{ResourceKind, Tracked};
+use crate::upload::Uploader;
+
+// Number of workgroups needed to cover `count` invocations
+pub fn workgroup_count(count: u32, workgroup_size: u32) -> u32 {
+    count.div_ceil(workgroup_size)
+}
+
+/// Typed storage buffer, usable from compute and render shaders and as a
+/// copy source/destination.
+pub struct StorageBuffer<T: bytemuck::Pod> {
+    buffer: Tracked<wgpu::Buffer>,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> StorageBuffer<T> {
+    const USAGE: wgpu::BufferUsages = wgpu::BufferUsages::STORAGE
+        .union(wgpu::BufferUsages::COPY_SRC)
+        .union(wgpu::BufferUsages::COPY_DST);
+
+    pub fn from_slice(device: &wgpu::Device, label: &str, data: &[T]) -> Self {
+        Self::from_slice_with_usage(device, label, data, wgpu::BufferUsages::empty())
+    }
+
+    // `extra_usage` is added on top of STORAGE | COPY_SRC | COPY_DST,
+    // e.g. VERTEX when a compute pass writes geometry
+    pub fn from_slice_with_usage(
+        device: &wgpu::Device,
+        label: &str,
+        data: &[T],
+        extra_usage: wgpu::BufferUsages,
+    ) -> Self {
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(label),
+            contents: bytemuck::cast_slice(data),
+            usage: Self::USAGE | extra_usage,
+        });
+        Self {
+            buffer: Tracked::buffer(ResourceKind::Buffer, buffer),
+            len: data.len(),
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn zeroed(device: &wgpu::Device, label: &str, len: usize) -> Self {
+        Self::zeroed_with_usage(device, label, len, wgpu::BufferUsages::empty())
+    }
+
+    pub fn zeroed_with_usage(
+        device: &wgpu::Device,
+        label: &str,
+        len: usize,
+        extra_usage: wgpu::BufferUsages,
+    ) -> Self {
+        // wgpu zero-initializes buffers for us
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: (len * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+            usage: Self::USAGE | extra_usage,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer: Tracked::buffer(ResourceKind::Buffer, buffer),
+            len,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn write(&self, queue: &wgpu::Queue, offset: usize, data: &[T]) {
+        assert!(offset + data.len() <= self.len, "write past the end of storage buffer");
+        let byte_offset = (offset * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+        queue.write_buffer(&self.buffer, byte_offset, bytemuck::cast_slice(data));
+    }
+
+    // Like `write`, recorded into the uploader's encoder
+    pub fn write_staged(&self, uploader: &mut Uploader, offset: usize, data: &[T]) {
+        assert!(offset + data.len() <= self.len, "write past the end of storage buffer");
+        let byte_offset = (offset * std::mem::size_of::<T>()) as wgpu::BufferAddress;
+        uploader.write(&self.buffer, byte_offset, bytemuck::cast_slice(data));
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    // For `FramesInFlight::retire`, when it's replaced
+    pub fn into_buffer(self) -> Tracked<wgpu::Buffer> {
+        self.buffer
+    }
+
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn size(&self) -> wgpu::BufferAddress {
+        self.buffer.size()
+    }
+}
+
+// Layout entry for a storage buffer binding visible to compute shaders
+pub fn storage_entry(binding: u32, read_only: bool) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// Layout entry for a uniform buffer binding visible to compute shaders
+pub fn uniform_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// Layout entry for an unfilterable 2D float texture visible to compute
+// shaders, read with textureLoad. Depth textures bind as these too.
+pub fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+        },
+        count: None,
+    }
+}
+
+/// A compute pipeline plus the bind group layouts it was built with.
+pub struct ComputePipeline {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layouts: Vec<wgpu::BindGroupLayout>,
+    label: String,
+}
+
+impl ComputePipeline {
+    // `groups` holds the layout entries of each bind group, in group order
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        source: &str,
+        entry_point: &str,
+        groups: &[&[wgpu::BindGroupLayoutEntry]],
+    ) -> Self {
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(source.into()),
+        });
+
+        let bind_group_layouts: Vec<_> = groups
+            .iter()
+            .enumerate()
+            .map(|(i, entries)| {
+                device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some(&format!("{} Bind Group Layout {}", label, i)),
+                    entries,
+                })
+            })
+            .collect();
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some(&format!("{} Pipeline Layout", label)),
+            bind_group_layouts: &bind_group_layouts.iter().collect::<Vec<_>>(),
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(&layout),
+            module: &module,
+            entry_point,
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layouts,
+            label: label.to_string(),
+        }
+    }
+
+    pub fn pipeline(&self) -> &wgpu::ComputePipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layout(&self, group: usize) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layouts[group]
+    }
+
+    // Binds `resources` to consecutive binding slots 0, 1, 2... of `group`
+    pub fn create_bind_group(
+        &self,
+        device: &wgpu::Device,
+        group: usize,
+        resources: &[wgpu::BindingResource],
+    ) -> wgpu::BindGroup {
+        let entries: Vec<_> = resources
+            .iter()
+            .enumerate()
+            .map(|(i, resource)| wgpu::BindGroupEntry {
+                binding: i as u32,
+                resource: resource.clone(),
+            })
+            .collect();
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} Bind Group {}", self.label, group)),
+            layout: self.bind_group_layout(group),
+            entries: &entries,
+        })
+    }
+
+    // Records a dispatch into its own compute pass, so it can sit between
+    // render passes of the same encoder
+    pub fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroups: (u32, u32, u32),
+    ) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&self.label),
+            timestamp_writes: None,
+        });
+        self.dispatch_in_pass(&mut pass, bind_groups, workgroups);
+    }
+
+    // Records a dispatch into an existing compute pass
+    pub fn dispatch_in_pass(
+        &self,
+        pass: &mut wgpu::ComputePass,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroups: (u32, u32, u32),
+    ) {
+        pass.set_pipeline(&self.pipeline);
+        for (i, bind_group) in bind_groups.iter().enumerate() {
+            pass.set_bind_group(i as u32, bind_group, &[]);
+        }
+        pass.dispatch_workgroups(workgroups.0, workgroups.1, workgroups.2);
+    }
+
+    // Records the dispatch in a fresh encoder and submits it right away
+    pub fn run(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bind_groups: &[&wgpu::BindGroup],
+        workgroups: (u32, u32, u32),
+    ) -> wgpu::SubmissionIndex {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some(&format!("{} Encoder", self.label)),
+        });
+        self.dispatch(&mut encoder, bind_groups, workgroups);
+        queue.submit(std::iter::once(encoder.finish()))
+    }
+}
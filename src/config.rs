@@ -0,0 +1,328 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use winit::dpi::PhysicalSize;
+use winit::window::{Icon, WindowBuilder};
+
+use crate::adapter::AdapterSelector;
+use crate::assets;
+use crate::settings::{self, Settings, SettingsFile};
+
+pub const USAGE: &str = "\
+usage: learn_wgpu [options] [scene.ron]
+
+options:
+  --backend <list>       vulkan, dx12, metal, gl or webgpu, comma separated
+  --adapter <index|name> pick an adapter by its --list-adapters index or part of its name
+  --list-adapters        print the adapters of the chosen backends and exit
+  --convert-mesh <file>  write a glTF or OBJ file's meshes to a .mesh file next to it and exit
+  --power <low|high>     prefer the integrated or the discrete GPU
+  --fallback-adapter     use a software adapter
+  --present-mode <mode>  fifo, fifo-relaxed, mailbox, immediate, auto-vsync or auto-no-vsync
+  --max-fps <fps>        cap the frame rate, for present modes that don't wait for the display
+  --width <pixels>       initial window width
+  --height <pixels>      initial window height
+  --min-size <WxH>       smallest the window can be resized to, like 640x480
+  --fixed-size           don't let the window be resized
+  --no-decorations       leave out the title bar and borders
+  --title <text>         window title
+  --fps-in-title         show the frame rate after the window title
+  --icon <image>         window icon, from a PNG or another image file
+  --transparent          let the desktop show through where nothing is drawn
+  --alpha-mode <mode>    auto, opaque, premultiplied, postmultiplied or inherit
+  --bindings <file>      key, mouse and gamepad bindings to use over the defaults, from a RON file
+  -h, --help             print this and exit
+
+environment:
+  WGPU_BACKEND, WGPU_POWER_PREF, LEARN_WGPU_ADAPTER, LEARN_WGPU_PRESENT_MODE and
+  LEARN_WGPU_MAX_FPS take the same values as the options above, which override them
+  LEARN_WGPU_SETTINGS is the settings file to use in place of ./settings.ron, whose
+  values the environment and options override in turn
+";
+
+// Window size for whichever of --width and --height is left out
+const DEFAULT_SIZE: PhysicalSize<u32> = PhysicalSize::new(800, 600);
+
+/// How `run` builds the window. `State` can change most of it later, see
+/// `State::set_title`.
+#[derive(Debug, Clone)]
+pub struct WindowConfig {
+    pub title: String,
+    // the frame rate after the title, updated twice a second
+    pub fps_in_title: bool,
+    // None for both leaves the size to the platform
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub min_size: Option<PhysicalSize<u32>>,
+    pub resizable: bool,
+    // the title bar and borders
+    pub decorations: bool,
+    // clears to transparent black and picks a compositing alpha mode
+    pub transparent: bool,
+    // an image file, read like the scene's assets
+    pub icon: Option<PathBuf>,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            title: "learn_wgpu".to_owned(),
+            fps_in_title: false,
+            width: None,
+            height: None,
+            min_size: None,
+            resizable: true,
+            decorations: true,
+            transparent: false,
+            icon: None,
+        }
+    }
+}
+
+impl WindowConfig {
+    // None unless a width or height was given
+    pub fn size(&self) -> Option<PhysicalSize<u32>> {
+        if self.width.is_none() && self.height.is_none() {
+            return None;
+        }
+        Some(PhysicalSize::new(
+            self.width.unwrap_or(DEFAULT_SIZE.width),
+            self.height.unwrap_or(DEFAULT_SIZE.height),
+        ))
+    }
+
+    // An icon that fails to load is logged and left out
+    pub fn builder(&self) -> WindowBuilder {
+        let mut builder = WindowBuilder::new()
+            .with_title(&self.title)
+            .with_resizable(self.resizable)
+            .with_decorations(self.decorations)
+            .with_transparent(self.transparent);
+        if let Some(size) = self.size() {
+            builder = builder.with_inner_size(size);
+        }
+        if let Some(size) = self.min_size {
+            builder = builder.with_min_inner_size(size);
+        }
+        if let Some(path) = &self.icon {
+            match load_icon(path) {
+                Result::Ok(icon) => builder = builder.with_window_icon(Some(icon)),
+                Err(e) => log::warn!("{:#}", e),
+            }
+        }
+        builder
+    }
+}
+
+// Window icons are plain RGBA, whatever the file was
+pub fn load_icon(path: &Path) -> Result<Icon> {
+    let image = assets::read_image(path)?.to_rgba8();
+    let (width, height) = image.dimensions();
+    Icon::from_rgba(image.into_raw(), width, height).with_context(|| format!("{}: not a usable icon", path.display()))
+}
+
+/// How `run` sets up the window and GPU, from the command line,
+/// environment and settings file
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub backends: wgpu::Backends,
+    // None lets wgpu choose
+    pub adapter: Option<AdapterSelector>,
+    pub list_adapters: bool,
+    // a model to write the meshes of with `mesh_file::convert`
+    pub convert_mesh: Option<PathBuf>,
+    pub power_preference: wgpu::PowerPreference,
+    pub force_fallback_adapter: bool,
+    // None keeps Fifo
+    pub present_mode: Option<wgpu::PresentMode>,
+    // None leaves it uncapped
+    pub max_fps: Option<f64>,
+    pub window: WindowConfig,
+    // None picks one that suits `window.transparent`
+    pub alpha_mode: Option<wgpu::CompositeAlphaMode>,
+    pub scene: Option<PathBuf>,
+    // action bindings over the defaults, see `ActionMap::load`
+    pub bindings: Option<PathBuf>,
+    // what the settings file had, for `State::use_settings` to apply what
+    // isn't above
+    pub settings: Settings,
+    // None when there's no file to keep in step
+    pub settings_file: Option<SettingsFile>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backends: wgpu::Backends::PRIMARY,
+            adapter: None,
+            list_adapters: false,
+            convert_mesh: None,
+            power_preference: wgpu::PowerPreference::default(),
+            force_fallback_adapter: false,
+            present_mode: None,
+            max_fps: None,
+            window: WindowConfig::default(),
+            alpha_mode: None,
+            scene: None,
+            bindings: None,
+            settings: Settings::default(),
+            settings_file: None,
+        }
+    }
+}
+
+impl Config {
+    // The environment, overridden by this process's arguments. Prints the
+    // usage and exits on --help.
+    pub fn from_env_and_args() -> Result<Self> {
+        let mut config = Self::from_env()?;
+        config.parse_args(std::env::args_os().skip(1))?;
+        Ok(config)
+    }
+
+    // The settings file, overridden by the environment
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self::default();
+        let (file, settings) = SettingsFile::open(settings::default_path());
+        config.apply_settings(&settings).with_context(|| file.path().display().to_string())?;
+        config.settings = settings;
+        config.settings_file = Some(file);
+        if let Result::Ok(backends) = std::env::var("WGPU_BACKEND") {
+            config.backends = parse_backends(&backends).context("WGPU_BACKEND")?;
+        }
+        if let Some(power_preference) = wgpu::util::power_preference_from_env() {
+            config.power_preference = power_preference;
+        }
+        if let Result::Ok(adapter) = std::env::var("LEARN_WGPU_ADAPTER") {
+            config.adapter = Some(adapter.parse().context("LEARN_WGPU_ADAPTER")?);
+        }
+        if let Result::Ok(present_mode) = std::env::var("LEARN_WGPU_PRESENT_MODE") {
+            config.present_mode = Some(parse_present_mode(&present_mode).context("LEARN_WGPU_PRESENT_MODE")?);
+        }
+        if let Result::Ok(max_fps) = std::env::var("LEARN_WGPU_MAX_FPS") {
+            config.max_fps = Some(parse_max_fps(&max_fps).context("LEARN_WGPU_MAX_FPS")?);
+        }
+        Ok(config)
+    }
+
+    // The settings that are read before there's a window and device
+    fn apply_settings(&mut self, settings: &Settings) -> Result<()> {
+        if let Some(backends) = &settings.backend {
+            self.backends = parse_backends(backends).context("backend")?;
+        }
+        if let Some([width, height]) = settings.resolution {
+            ensure!(width > 0 && height > 0, "resolution: the window can't be empty");
+            self.window.width = Some(width);
+            self.window.height = Some(height);
+        }
+        Ok(())
+    }
+
+    // Options take their value as the next argument or after an `=`
+    pub fn parse_args(&mut self, args: impl IntoIterator<Item = OsString>) -> Result<()> {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let Some(option) = arg.to_str().filter(|arg| arg.starts_with('-')) else {
+                if self.scene.is_some() {
+                    bail!("only one scene file can be given");
+                }
+                self.scene = Some(arg.into());
+                continue;
+            };
+            let (name, mut value) = match option.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_owned())),
+                None => (option, None),
+            };
+            let mut value = || {
+                value
+                    .take()
+                    .or_else(|| args.next().and_then(|arg| arg.into_string().ok()))
+                    .with_context(|| format!("{} needs a value", name))
+            };
+            match name {
+                "--backend" => self.backends = parse_backends(&value()?).context("--backend")?,
+                "--adapter" => self.adapter = Some(value()?.parse().context("--adapter")?),
+                "--list-adapters" => self.list_adapters = true,
+                "--convert-mesh" => self.convert_mesh = Some(value()?.into()),
+                "--power" => self.power_preference = parse_power_preference(&value()?).context("--power")?,
+                "--fallback-adapter" => self.force_fallback_adapter = true,
+                "--present-mode" => self.present_mode = Some(parse_present_mode(&value()?).context("--present-mode")?),
+                "--max-fps" => self.max_fps = Some(parse_max_fps(&value()?).context("--max-fps")?),
+                "--width" => self.window.width = Some(parse_size(&value()?).context("--width")?),
+                "--height" => self.window.height = Some(parse_size(&value()?).context("--height")?),
+                "--min-size" => self.window.min_size = Some(parse_min_size(&value()?).context("--min-size")?),
+                "--fixed-size" => self.window.resizable = false,
+                "--no-decorations" => self.window.decorations = false,
+                "--title" => self.window.title = value()?,
+                "--fps-in-title" => self.window.fps_in_title = true,
+                "--icon" => self.window.icon = Some(value()?.into()),
+                "--transparent" => self.window.transparent = true,
+                "--alpha-mode" => self.alpha_mode = Some(parse_alpha_mode(&value()?).context("--alpha-mode")?),
+                "--bindings" => self.bindings = Some(value()?.into()),
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    std::process::exit(0);
+                }
+                _ => bail!("unknown option {}", name),
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_backends(value: &str) -> Result<wgpu::Backends> {
+    let backends = wgpu::util::parse_backends_from_comma_list(value);
+    ensure!(!backends.is_empty(), "no known backend in {:?}", value);
+    Ok(backends)
+}
+
+fn parse_size(value: &str) -> Result<u32> {
+    let size = value.parse().with_context(|| format!("{:?} is not a number", value))?;
+    ensure!(size > 0, "the window can't be empty");
+    Ok(size)
+}
+
+fn parse_min_size(value: &str) -> Result<PhysicalSize<u32>> {
+    let (width, height) = value.split_once('x').with_context(|| format!("expected WxH, not {:?}", value))?;
+    Ok(PhysicalSize::new(parse_size(width)?, parse_size(height)?))
+}
+
+fn parse_max_fps(value: &str) -> Result<f64> {
+    let fps: f64 = value.parse().with_context(|| format!("{:?} is not a number", value))?;
+    ensure!(fps.is_finite() && fps > 0.0, "the frame rate cap has to be above zero");
+    Ok(fps)
+}
+
+// Same words as WGPU_POWER_PREF
+fn parse_power_preference(value: &str) -> Result<wgpu::PowerPreference> {
+    match value.to_ascii_lowercase().as_str() {
+        "low" => Ok(wgpu::PowerPreference::LowPower),
+        "high" => Ok(wgpu::PowerPreference::HighPerformance),
+        "none" => Ok(wgpu::PowerPreference::None),
+        _ => bail!("expected low, high or none, not {:?}", value),
+    }
+}
+
+fn parse_present_mode(value: &str) -> Result<wgpu::PresentMode> {
+    match value.to_ascii_lowercase().as_str() {
+        "fifo" => Ok(wgpu::PresentMode::Fifo),
+        "fifo-relaxed" => Ok(wgpu::PresentMode::FifoRelaxed),
+        "mailbox" => Ok(wgpu::PresentMode::Mailbox),
+        "immediate" => Ok(wgpu::PresentMode::Immediate),
+        "auto-vsync" => Ok(wgpu::PresentMode::AutoVsync),
+        "auto-no-vsync" => Ok(wgpu::PresentMode::AutoNoVsync),
+        _ => bail!("unknown present mode {:?}", value),
+    }
+}
+
+fn parse_alpha_mode(value: &str) -> Result<wgpu::CompositeAlphaMode> {
+    match value.to_ascii_lowercase().as_str() {
+        "auto" => Ok(wgpu::CompositeAlphaMode::Auto),
+        "opaque" => Ok(wgpu::CompositeAlphaMode::Opaque),
+        "premultiplied" => Ok(wgpu::CompositeAlphaMode::PreMultiplied),
+        "postmultiplied" => Ok(wgpu::CompositeAlphaMode::PostMultiplied),
+        "inherit" => Ok(wgpu::CompositeAlphaMode::Inherit),
+        _ => bail!("unknown alpha mode {:?}", value),
+    }
+}
@@ -0,0 +1,19 @@
+// Passes that draw one fullscreen triangle into a cubemap face, or a mip of
+// one, and work out which direction each of its texels looks
+
+#include "fullscreen.wgsl"
+
+// Towards the texel at `uv` on `face`, +x, -x, +y, -y, +z then -z as wgpu
+// lays cubemaps out, and as `face_view_proj` in ibl.rs projects them
+fn cube_face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    switch face {
+        case 0u: { return normalize(vec3<f32>(1.0, -t, -s)); }
+        case 1u: { return normalize(vec3<f32>(-1.0, -t, s)); }
+        case 2u: { return normalize(vec3<f32>(s, 1.0, t)); }
+        case 3u: { return normalize(vec3<f32>(s, -1.0, -t)); }
+        case 4u: { return normalize(vec3<f32>(s, -t, 1.0)); }
+        default: { return normalize(vec3<f32>(-s, -t, -1.0)); }
+    }
+}
@@ -0,0 +1,179 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::{Aabb, Frustum};
+use crate::compute::StorageBuffer;
+use crate::draw_list::TracedPass;
+use crate::instance::{Instance, InstanceRaw};
+use crate::resources::ResourceUsage;
+
+// How the instanced meshes are frustum culled. Models are culled on the CPU
+// either way, unless it's off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CullingMode {
+    // a compute pass and an indirect draw, see `GpuCuller`
+    Gpu,
+    // each instance's bounds tested on the CPU, see `CpuCuller`
+    Cpu,
+    // everything drawn, for comparison
+    Off,
+}
+
+impl CullingMode {
+    pub fn next(self) -> Self {
+        match self {
+            CullingMode::Gpu => CullingMode::Cpu,
+            CullingMode::Cpu => CullingMode::Off,
+            CullingMode::Off => CullingMode::Gpu,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CullCounts {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+impl CullCounts {
+    // Nothing culled
+    pub fn all(count: usize) -> Self {
+        Self {
+            drawn: count as u32,
+            culled: 0,
+        }
+    }
+
+    pub(crate) fn count(&mut self, visible: bool) {
+        if visible {
+            self.drawn += 1;
+        } else {
+            self.culled += 1;
+        }
+    }
+}
+
+/// What frustum culling left to draw in the last frame, and what the GPU
+/// memory held then. Instances culled on the GPU are counted there and read
+/// back, so in that mode they're a frame or two old, and None until the
+/// first counts come back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub instances: Option<CullCounts>,
+    // of the culled instances, those occlusion culling hid
+    pub occluded: Option<u32>,
+    // instances uploaded again because their transforms were set
+    pub moved: usize,
+    pub models: CullCounts,
+    // the scene's light, which is directional, and the rest
+    pub lights: CullCounts,
+    // of the lights in view, those past `lighting::MAX_LIGHTS` left out
+    pub lights_dropped: u32,
+    pub resources: ResourceUsage,
+}
+
+impl FrameStats {
+    // Counts one model against the frustum, returning whether it's drawn.
+    // Models without bounds always are.
+    pub fn cull_model(&mut self, frustum: Option<&Frustum>, bounds: Option<Aabb>) -> bool {
+        let visible = match (frustum, bounds) {
+            (Some(frustum), Some(bounds)) => frustum.intersects_aabb(&bounds),
+            _ => true,
+        };
+        self.models.count(visible);
+        visible
+    }
+}
+
+// e.g. "812 of 1000 instances (96 occluded), 2 of 3 models, 5 of 40 lights",
+// with "(2 over the limit)" after the lights when some in view were left out
+impl fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(instances) = self.instances {
+            write!(f, "{} of {} instances", instances.drawn, instances.drawn + instances.culled)?;
+            if let Some(occluded) = self.occluded {
+                write!(f, " ({} occluded)", occluded)?;
+            }
+            write!(f, ", ")?;
+        }
+        write!(f, "{} of {} models, ", self.models.drawn, self.models.drawn + self.models.culled)?;
+        let lights = self.lights.drawn + self.lights.culled + self.lights_dropped;
+        write!(f, "{} of {} lights", self.lights.drawn, lights)?;
+        if self.lights_dropped > 0 {
+            write!(f, " ({} over the limit)", self.lights_dropped)?;
+        }
+        Ok(())
+    }
+}
+
+// Frustum culls an instance buffer on the CPU. Each instance's world space
+// bounds are worked out once when the instances are set, and every frame the
+// ones in view are copied to the front of a second instance buffer, to be
+// drawn in a single call.
+pub struct CpuCuller {
+    mesh_bounds: Aabb,
+    bounds: Vec<Aabb>,
+    instances: Vec<InstanceRaw>,
+    visible: StorageBuffer<InstanceRaw>,
+    // survivors of the last `cull`, reused from frame to frame
+    survivors: Vec<InstanceRaw>,
+    index_count: u32,
+}
+
+impl CpuCuller {
+    // `mesh_bounds` is in model space, `index_count` the mesh's index count
+    pub fn new(device: &wgpu::Device, instances: &[Instance], mesh_bounds: Aabb, index_count: u32) -> Self {
+        let visible = StorageBuffer::zeroed_with_usage(
+            device,
+            "Visible Instance Buffer",
+            instances.len().max(1),
+            wgpu::BufferUsages::VERTEX,
+        );
+        Self {
+            mesh_bounds,
+            bounds: instances.iter().map(|instance| mesh_bounds.transformed(&instance.model_matrix())).collect(),
+            instances: instances.iter().map(Instance::to_raw).collect(),
+            visible,
+            survivors: Vec::with_capacity(instances.len()),
+            index_count,
+        }
+    }
+
+    // Follows an instance that's been moved since it was set
+    pub fn update(&mut self, index: usize, instance: &Instance) {
+        self.bounds[index] = self.mesh_bounds.transformed(&instance.model_matrix());
+        self.instances[index] = instance.to_raw();
+    }
+
+    // World space bounds of every instance, in instance order
+    pub fn bounds(&self) -> &[Aabb] {
+        &self.bounds
+    }
+
+    // Uploads the instances inside `frustum` for the next `draw`
+    pub fn cull(&mut self, queue: &wgpu::Queue, frustum: &Frustum) -> CullCounts {
+        self.survivors.clear();
+        self.survivors.extend(
+            self.instances
+                .iter()
+                .zip(&self.bounds)
+                .filter(|(_, bounds)| frustum.intersects_aabb(bounds))
+                .map(|(instance, _)| *instance),
+        );
+        if !self.survivors.is_empty() {
+            self.visible.write(queue, 0, &self.survivors);
+        }
+        CullCounts {
+            drawn: self.survivors.len() as u32,
+            culled: (self.instances.len() - self.survivors.len()) as u32,
+        }
+    }
+
+    // Draws what the last `cull` kept, binding the visible instances to
+    // vertex buffer `slot`. Everything else must already be bound.
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>, slot: u32) {
+        render_pass.set_vertex_buffer(slot, self.visible.buffer().slice(..));
+        render_pass.draw_indexed(0..self.index_count, 0, 0..self.survivors.len() as u32);
+    }
+}
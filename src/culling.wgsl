@@ -0,0 +1,122 @@
+// Frustum culls instances, and occlusion culls them against last frame's
+// depth pyramid (see hiz.wgsl), compacting the survivors for an indirect draw
+
+struct InstanceRaw {
+    model: mat4x4<f32>,
+    material: u32,
+};
+
+// matches wgpu::util::DrawIndexedIndirectArgs
+struct DrawIndexedArgs {
+    index_count: u32,
+    instance_count: atomic<u32>,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+};
+
+// The draw's arguments, then counts read back for stats
+struct CullArgs {
+    draw: DrawIndexedArgs,
+    // what the frustum kept and the depth pyramid didn't
+    occluded: atomic<u32>,
+};
+
+struct CullParams {
+    planes: array<vec4<f32>, 6>,
+    // mesh bounding sphere in model space, radius in w
+    sphere: vec4<f32>,
+    // the view-projection the pyramid's depth was drawn with
+    occluder_view_proj: mat4x4<f32>,
+    instance_count: u32,
+    // the pyramid's level count, 0 when there's nothing to test against
+    occluder_levels: u32,
+    // the pyramid's first level's size
+    occluder_size: vec2<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> params: CullParams;
+@group(0) @binding(1)
+var<storage, read> instances: array<InstanceRaw>;
+@group(0) @binding(2)
+var<storage, read_write> visible: array<InstanceRaw>;
+@group(0) @binding(3)
+var<storage, read_write> args: CullArgs;
+
+// each level's rows one after the other, see hiz.wgsl
+@group(1) @binding(0)
+var<storage, read> depth_pyramid: array<f32>;
+
+// Whether a sphere was hidden behind what was drawn last frame: the box
+// around it on screen then, nearest depth first, against the pyramid's
+// farthest depth of the level where that box covers 2x2 texels at most
+fn occluded(center: vec3<f32>, radius: f32) -> bool {
+    var lo = vec3<f32>(2.0);
+    var hi = vec3<f32>(-2.0);
+    for (var i = 0u; i < 8u; i++) {
+        let corner = vec3<f32>(vec3<u32>(i, i >> 1u, i >> 2u) & vec3<u32>(1u)) * 2.0 - 1.0;
+        let clip = params.occluder_view_proj * vec4<f32>(center + corner * radius, 1.0);
+        // partly behind the camera, nothing to go by
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        lo = min(lo, ndc);
+        hi = max(hi, ndc);
+    }
+    // y goes down the texture
+    let uv_min = vec2<f32>(lo.x, -hi.y) * 0.5 + 0.5;
+    let uv_max = vec2<f32>(hi.x, -lo.y) * 0.5 + 0.5;
+    // partly off screen then, and what was there isn't known
+    if (any(uv_min < vec2<f32>(0.0)) || any(uv_max > vec2<f32>(1.0))) {
+        return false;
+    }
+
+    let size = vec2<f32>(params.occluder_size);
+    let extent = (uv_max - uv_min) * size;
+    let level = min(u32(max(ceil(log2(max(extent.x, extent.y))), 0.0)), params.occluder_levels - 1u);
+    var offset = 0u;
+    var level_size = params.occluder_size;
+    for (var l = 0u; l < level; l++) {
+        offset += level_size.x * level_size.y;
+        level_size = max(level_size / 2u, vec2<u32>(1u));
+    }
+    let last = level_size - 1u;
+    let a = min(vec2<u32>(uv_min * size) >> vec2<u32>(level), last);
+    let b = min(vec2<u32>(uv_max * size) >> vec2<u32>(level), last);
+    let row_a = offset + a.y * level_size.x;
+    let row_b = offset + b.y * level_size.x;
+    let farthest = max(
+        max(depth_pyramid[row_a + a.x], depth_pyramid[row_a + b.x]),
+        max(depth_pyramid[row_b + a.x], depth_pyramid[row_b + b.x]),
+    );
+    return lo.z > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.instance_count) {
+        return;
+    }
+
+    let model = instances[i].model;
+    let center = (model * vec4<f32>(params.sphere.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = params.sphere.w * scale;
+
+    for (var p = 0u; p < 6u; p++) {
+        let plane = params.planes[p];
+        if (dot(plane.xyz, center) + plane.w < -radius) {
+            return;
+        }
+    }
+    if (params.occluder_levels > 0u && occluded(center, radius)) {
+        atomicAdd(&args.occluded, 1u);
+        return;
+    }
+
+    let slot = atomicAdd(&args.draw.instance_count, 1u);
+    visible[slot] = instances[i];
+}
@@ -0,0 +1,307 @@
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::{Aabb, Sphere};
+use crate::draw_list::TracedPass;
+use crate::msaa::SampledPipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+// line vertices the buffer starts out with room for
+const INITIAL_CAPACITY: usize = 4096;
+// segments per circle when drawing spheres
+const CIRCLE_SEGMENTS: u32 = 24;
+
+pub const RED: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+pub const GREEN: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+pub const BLUE: [f32; 4] = [0.3, 0.5, 1.0, 1.0];
+pub const YELLOW: [f32; 4] = [1.0, 0.9, 0.2, 1.0];
+pub const WHITE: [f32; 4] = [1.0; 4];
+
+/// Bits for what the debug line overlay draws, toggled one at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct DebugCategories(pub u32);
+
+impl DebugCategories {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+    // the world axes, the bounds of the instances in view and the picked one
+    pub const INSTANCES: Self = Self(1 << 0);
+    // the rigid bodies' colliders, with the physics feature
+    pub const PHYSICS: Self = Self(1 << 1);
+    // point lights' ranges, spot lights' cones and directional lights' arrows
+    pub const LIGHTS: Self = Self(1 << 2);
+    // what the shadow map covers, as the light sees it
+    pub const SHADOWS: Self = Self(1 << 3);
+    // the decals' boxes
+    pub const DECALS: Self = Self(1 << 4);
+
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    // Every bit of `other` set in this
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Turns `other` on when it's off and off when it's on
+    pub const fn toggled(self, other: Self) -> Self {
+        Self(self.0 ^ other.0)
+    }
+}
+
+// what the overlay drew before it had categories
+impl Default for DebugCategories {
+    fn default() -> Self {
+        Self::INSTANCES.with(Self::PHYSICS)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Immediate-mode debug lines: call the shape functions any time during a
+// frame, `flush` uploads everything that was queued and `draw` renders it
+// as a line list over the scene. Nothing is kept between frames.
+pub struct DebugDraw {
+    vertices: Vec<LineVertex>,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    capacity: usize,
+    // vertices uploaded by the last `flush`
+    vertex_count: u32,
+    render_pipeline: SampledPipeline,
+}
+
+impl DebugDraw {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let shader =
+            shader_preprocessor::create_module(device, "Debug Draw Shader", "debug_draw.wgsl", &ShaderDefs::new());
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = SampledPipeline::new(samples, |multisample| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Debug Draw Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[LineVertex::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample,
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        Self {
+            vertices: Vec::new(),
+            vertex_buffer: Self::create_vertex_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            vertex_count: 0,
+            render_pipeline,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Vertex Buffer"),
+            size: (capacity * std::mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Tracked::buffer(ResourceKind::Buffer, buffer)
+    }
+
+    // lines queued since the last flush
+    pub fn line_count(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn line(&mut self, a: Point3<f32>, b: Point3<f32>, color: [f32; 4]) {
+        self.vertices.push(LineVertex { position: a.into(), color });
+        self.vertices.push(LineVertex { position: b.into(), color });
+    }
+
+    pub fn ray(&mut self, origin: Point3<f32>, direction: Vector3<f32>, length: f32, color: [f32; 4]) {
+        self.line(origin, origin + direction.normalize() * length, color);
+    }
+
+    // The 12 edges of an axis-aligned box
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) {
+        self.box_corners(aabb.corners(), color);
+    }
+
+    // A box under an arbitrary transform, e.g. an object's local bounds
+    // drawn with its model matrix so it stays tight when rotated
+    pub fn oriented_box(&mut self, aabb: &Aabb, transform: &Matrix4<f32>, color: [f32; 4]) {
+        self.box_corners(aabb.corners().map(|p| transform.transform_point(p)), color);
+    }
+
+    fn box_corners(&mut self, corners: [Point3<f32>; 8], color: [f32; 4]) {
+        // corners are ordered with x in bit 0, y in bit 1 and z in bit 2,
+        // so every edge joins two corners one bit apart
+        for i in 0..8 {
+            for bit in [1, 2, 4] {
+                if i & bit == 0 {
+                    self.line(corners[i], corners[i | bit], color);
+                }
+            }
+        }
+    }
+
+    // Three great circles, one around each axis
+    pub fn sphere(&mut self, center: Point3<f32>, radius: f32, color: [f32; 4]) {
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        for i in 0..3 {
+            self.circle(center, axes[(i + 1) % 3] * radius, axes[(i + 2) % 3] * radius, color);
+        }
+    }
+
+    pub fn bounding_sphere(&mut self, sphere: &Sphere, color: [f32; 4]) {
+        self.sphere(sphere.center, sphere.radius, color);
+    }
+
+    // Circle spanned by two perpendicular radius vectors
+    pub fn circle(&mut self, center: Point3<f32>, u: Vector3<f32>, v: Vector3<f32>, color: [f32; 4]) {
+        let point = |i: u32| {
+            let (sin, cos) = (i as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU).sin_cos();
+            center + u * cos + v * sin
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color);
+        }
+    }
+
+    // A line from `origin` with a four-pronged head at the end
+    pub fn arrow(&mut self, origin: Point3<f32>, direction: Vector3<f32>, length: f32, color: [f32; 4]) {
+        let axis = direction.normalize();
+        let tip = origin + axis * length;
+        self.line(origin, tip, color);
+        let (u, v) = perpendiculars(axis);
+        let base = tip - axis * length * 0.2;
+        for side in [u, -u, v, -v] {
+            self.line(tip, base + side * length * 0.1, color);
+        }
+    }
+
+    // A cone from `apex` along `direction`, `angle` out from its axis and
+    // closed off by a circle where its sides are `length` long, as far as a
+    // spot light reaches
+    pub fn cone(&mut self, apex: Point3<f32>, direction: Vector3<f32>, length: f32, angle: Rad<f32>, color: [f32; 4]) {
+        let axis = direction.normalize();
+        let (sin, cos) = angle.0.clamp(0.0, std::f32::consts::FRAC_PI_2).sin_cos();
+        let center = apex + axis * length * cos;
+        let (u, v) = perpendiculars(axis);
+        let (u, v) = (u * length * sin, v * length * sin);
+        self.circle(center, u, v, color);
+        for side in [u, -u, v, -v] {
+            self.line(apex, center + side, color);
+        }
+    }
+
+    // The transform's x, y and z axes in red, green and blue, `size` long
+    pub fn axes(&mut self, transform: &Matrix4<f32>, size: f32) {
+        let origin = transform.transform_point(Point3::origin());
+        let colors = [RED, GREEN, BLUE];
+        for (axis, color) in [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()].into_iter().zip(colors) {
+            let end = transform.transform_point(Point3::from_vec(axis * size));
+            self.line(origin, end, color);
+        }
+    }
+
+    // Outline of the volume a view-projection matrix sees, handy for
+    // checking culling against a frozen camera
+    pub fn frustum(&mut self, view_proj: &Matrix4<f32>, color: [f32; 4]) {
+        let Some(inverse) = view_proj.invert() else {
+            return;
+        };
+        // wgpu clip space: x and y in [-1, 1], depth in [0, 1]
+        let corners = std::array::from_fn(|i| {
+            let ndc = Vector4::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+                1.0,
+            );
+            let world = inverse * ndc;
+            Point3::from_homogeneous(world)
+        });
+        self.box_corners(corners, color);
+    }
+
+    // Uploads this frame's lines and clears the queue for the next one
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+        self.vertex_count = self.vertices.len() as u32;
+        self.vertices.clear();
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(self.render_pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+// Two unit vectors at right angles to `axis`, a unit vector, and each other
+fn perpendiculars(axis: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    // anything not along the axis makes a start
+    let other = if axis.y.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_y() };
+    let u = axis.cross(other).normalize();
+    (u, axis.cross(u))
+}
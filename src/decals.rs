@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::assets;
+use crate::camera::{self, CameraUniform};
+use crate::depth_prepass;
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::lighting::LightUniform;
+use crate::resources::{ResourceKind, Tracked};
+use crate::scene::DecalDesc;
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::shadow::ShadowMap;
+use crate::texture::{MipmapGenerator, SamplerDesc, Texture};
+
+// Matches `DecalUniform` in decals.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+}
+
+// One decal's box, matching `DecalInput` in decals.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DecalInstance {
+    // the unit box around the origin to the world, and back
+    model: [[f32; 4]; 4],
+    inverse: [[f32; 4]; 4],
+    tint: [f32; 4],
+}
+
+impl DecalInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 9] = wgpu::vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+            2 => Float32x4,
+            3 => Float32x4,
+            4 => Float32x4,
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<DecalInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// A run of decals next to each other in the scene with the same textures
+struct Batch {
+    bind_group: usize,
+    instances: Range<u32>,
+}
+
+// A scene's decals, drawn after the terrain: each box finds the terrain
+// inside it in the terrain's depth buffer and covers it with its texture,
+// lit by the scene's lights like the terrain is, its normal bent by the
+// decal's normal map. Everything drawn after them goes on top, as it does
+// over the terrain. Decals are drawn in order, and neighbours with the same
+// textures in one draw.
+pub struct Decals {
+    descs: Vec<DecalDesc>,
+    uniform_buffer: wgpu::Buffer,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    // the camera's uniform and the depth, which is the water's when there's
+    // water and otherwise `depth_view`, so it's bound as the frame is drawn
+    layout: wgpu::BindGroupLayout,
+    texture_bind_groups: Vec<wgpu::BindGroup>,
+    batches: Vec<Batch>,
+    pipeline: wgpu::RenderPipeline,
+    depth_view: Tracked<wgpu::TextureView>,
+}
+
+impl Decals {
+    // Paths in `descs` are used as they are. Draws into `color_format`
+    // targets of `width` x `height`, in the shadows of `shadow_map`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        shadow_map: &ShadowMap,
+        descs: &[DecalDesc],
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        let mut mipmaps = MipmapGenerator::new(device);
+        let mut textures = HashMap::new();
+        let mut load_texture = |path: &PathBuf, linear: bool| -> Result<()> {
+            if !textures.contains_key(path) {
+                let image = assets::read_image(path)?.to_rgba8();
+                let label = format!("Decal {}", path.display());
+                let load = if linear { Texture::from_linear_rgba_with_mipmaps } else { Texture::from_rgba_with_mipmaps };
+                let texture = load(device, queue, &mut mipmaps, &image, image.width(), image.height(), Some(&label));
+                textures.insert(path.clone(), texture);
+            }
+            Ok(())
+        };
+        for desc in descs {
+            load_texture(&desc.texture, false)?;
+            if let Some(normal_map) = &desc.normal_map {
+                load_texture(normal_map, true)?;
+            }
+        }
+        // facing straight out, for decals without a normal map
+        let flat = Texture::from_linear_rgba_with_mipmaps(
+            device,
+            queue,
+            &mut mipmaps,
+            &[128, 128, 255, 255],
+            1,
+            1,
+            Some("Decal Flat Normal"),
+        );
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let texture_entries = [
+            texture_entry(0),
+            texture_entry(1),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Decal Texture Bind Group Layout"),
+            entries: &texture_entries,
+        });
+        // nothing to repeat, the box ends at the texture's edges
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Decal Sampler"));
+
+        let mut texture_bind_groups = Vec::new();
+        let mut bind_group_indices = HashMap::new();
+        let mut batches: Vec<Batch> = Vec::new();
+        for (i, desc) in descs.iter().enumerate() {
+            let key = (&desc.texture, desc.normal_map.as_ref());
+            let bind_group = *bind_group_indices.entry(key).or_insert_with(|| {
+                let normal_map = desc.normal_map.as_ref().map_or(&flat, |path| &textures[path]);
+                texture_bind_groups.push(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Decal Texture Bind Group"),
+                    layout: &texture_layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::TextureView(&textures[&desc.texture].view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&normal_map.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(&sampler),
+                        },
+                    ],
+                }));
+                texture_bind_groups.len() - 1
+            });
+            let i = i as u32;
+            match batches.last_mut() {
+                Some(batch) if batch.bind_group == bind_group => batch.instances.end = i + 1,
+                _ => batches.push(Batch {
+                    bind_group,
+                    instances: i..i + 1,
+                }),
+            }
+        }
+
+        let instances: Vec<_> = descs
+            .iter()
+            .map(|desc| {
+                let model = desc.transform();
+                DecalInstance {
+                    model: model.into(),
+                    // a box squashed flat has nothing inside it
+                    inverse: model.invert().unwrap_or(Matrix4::zero()).into(),
+                    tint: desc.tint,
+                }
+            })
+            .collect();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Decal Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Decal Buffer"),
+            size: std::mem::size_of::<DecalUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // as floats, GL can't load from depth textures
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+        ];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Decal Bind Group Layout"),
+            entries: &entries,
+        });
+
+        let shader = shader_preprocessor::load("decals.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_group(1, &entries)?;
+                reflection.check_group(2, &texture_entries)?;
+                reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                reflection.check_struct::<FogUniform>("FogUniform")?;
+                reflection.check_struct::<LightUniform>("LightUniform")?;
+                reflection.check_struct::<DecalUniform>("DecalUniform")?;
+                reflection.check_vertex_buffers("vs_main", &[DecalInstance::desc()])?;
+                Ok(shader)
+            })?
+            .create_module(device, "Decal Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Decal Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout, &texture_layout, shadow_map.layout()],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Decal Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[DecalInstance::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            // the far side of each box, so every pixel is covered once and
+            // the camera can stand inside one
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Front),
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Ok(Self {
+            descs: descs.to_vec(),
+            uniform_buffer,
+            instance_buffer: Tracked::buffer(ResourceKind::Buffer, instance_buffer),
+            layout,
+            texture_bind_groups,
+            batches,
+            pipeline,
+            depth_view: Self::create_depth(device, width, height),
+        })
+    }
+
+    fn create_depth(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        depth_prepass::depth_target(device, "Decal Depth", width, height, wgpu::TextureUsages::TEXTURE_BINDING)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_view = Self::create_depth(device, width, height);
+    }
+
+    // As they were loaded
+    pub fn descs(&self) -> &[DecalDesc] {
+        &self.descs
+    }
+
+    // For the terrain to keep its depth in when there's no water's to use
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    // Once a frame, after the camera has moved
+    pub fn update(&self, queue: &wgpu::Queue, camera_uniform: &CameraUniform) {
+        let uniform = DecalUniform {
+            inverse_view_proj: camera_uniform.view_proj().invert().unwrap_or(Matrix4::identity()).into(),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Over `target`, right after the terrain has been drawn into it with
+    // `depth`
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        shadow_map: &ShadowMap,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Decal Bind Group"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+            ],
+        });
+        let mut render_pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Decal Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &bind_group, &[]);
+        render_pass.set_bind_group(3, shadow_map.bind_group(), &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        for batch in &self.batches {
+            render_pass.set_bind_group(2, &self.texture_bind_groups[batch.bind_group], &[]);
+            // a box as one strip of 14 vertices
+            render_pass.draw(0..14, batch.instances.clone());
+        }
+    }
+}
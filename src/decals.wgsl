@@ -0,0 +1,135 @@
+// Projected decals: boxes that find the terrain inside them in its depth
+// buffer and cover it with their texture, lit as the terrain is, with the
+// normal the depth has bent by their normal map
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "lighting.wgsl"
+#include "shadow.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+@group(0) @binding(2)
+var<uniform> lighting: LightUniform;
+@group(0) @binding(7)
+var<storage, read> lights: array<GpuLight>;
+
+// Matches `DecalUniform` in decals.rs
+struct DecalUniform {
+    // the camera's, from clip space back to the world
+    inverse_view_proj: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> decal: DecalUniform;
+// read as floats, GL can't load from depth textures
+@group(1) @binding(1)
+var t_depth: texture_2d<f32>;
+
+@group(2) @binding(0)
+var t_albedo: texture_2d<f32>;
+@group(2) @binding(1)
+var t_normal: texture_2d<f32>;
+@group(2) @binding(2)
+var s_decal: sampler;
+
+@group(3) @binding(0)
+var<uniform> shadow: ShadowUniform;
+@group(3) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(3) @binding(2)
+var s_shadow: sampler_comparison;
+
+// how far from the middle, along the box's y, the decal starts fading out
+const FADE_START: f32 = 0.3;
+// cosines between the surface and the box's y, faded out under the first
+// and whole over the second, so the sides of hills don't smear
+const FACING_MIN: f32 = 0.2;
+const FACING_FULL: f32 = 0.5;
+
+// Matches `DecalInstance` in decals.rs
+struct DecalInput {
+    @location(0) model_0: vec4<f32>,
+    @location(1) model_1: vec4<f32>,
+    @location(2) model_2: vec4<f32>,
+    @location(3) model_3: vec4<f32>,
+    @location(4) inverse_0: vec4<f32>,
+    @location(5) inverse_1: vec4<f32>,
+    @location(6) inverse_2: vec4<f32>,
+    @location(7) inverse_3: vec4<f32>,
+    @location(8) tint: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) tint: vec4<f32>,
+    // the box's inverse, to find where in it each pixel's surface is
+    @location(1) @interpolate(flat) inverse_0: vec4<f32>,
+    @location(2) @interpolate(flat) inverse_1: vec4<f32>,
+    @location(3) @interpolate(flat) inverse_2: vec4<f32>,
+    @location(4) @interpolate(flat) inverse_3: vec4<f32>,
+    // the box's x and y in the world
+    @location(5) @interpolate(flat) axis_x: vec3<f32>,
+    @location(6) @interpolate(flat) axis_y: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, in: DecalInput) -> VertexOutput {
+    // a cube as one strip of 14 vertices, as the sky's
+    let corner = vec3<u32>((0x287au >> index) & 1u, (0x02afu >> index) & 1u, (0x31e3u >> index) & 1u);
+    let model = mat4x4<f32>(in.model_0, in.model_1, in.model_2, in.model_3);
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model * vec4<f32>(vec3<f32>(corner) - 0.5, 1.0);
+    out.tint = in.tint;
+    out.inverse_0 = in.inverse_0;
+    out.inverse_1 = in.inverse_1;
+    out.inverse_2 = in.inverse_2;
+    out.inverse_3 = in.inverse_3;
+    out.axis_x = normalize(in.model_0.xyz);
+    out.axis_y = normalize(in.model_1.xyz);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = in.clip_position.xy / vec2<f32>(textureDimensions(t_depth));
+    let depth = textureLoad(t_depth, vec2<i32>(in.clip_position.xy), 0).r;
+    let clip = decal.inverse_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    let world_position = clip.xyz / clip.w;
+    let inverse = mat4x4<f32>(in.inverse_0, in.inverse_1, in.inverse_2, in.inverse_3);
+    let local = (inverse * vec4<f32>(world_position, 1.0)).xyz;
+    let tex_coords = local.xz + 0.5;
+
+    // derivatives while every pixel is still running; the surface's facing
+    // from how its position changes across the screen
+    let tex_dx = dpdx(tex_coords);
+    let tex_dy = dpdy(tex_coords);
+    let geometric = normalize(cross(dpdy(world_position), dpdx(world_position)));
+    if any(abs(local) > vec3<f32>(0.5)) {
+        discard;
+    }
+    let eye = camera.view_pos.xyz;
+    let n = select(geometric, -geometric, dot(geometric, eye - world_position) < 0.0);
+
+    let albedo = textureSampleGrad(t_albedo, s_decal, tex_coords, tex_dx, tex_dy) * in.tint;
+    let coverage = albedo.a
+        * (1.0 - smoothstep(FADE_START, 0.5, abs(local.y)))
+        * smoothstep(FACING_MIN, FACING_FULL, dot(n, in.axis_y));
+
+    // x along the box's x and y up the texture, towards the box's -z
+    let tangent = normalize(in.axis_x - n * dot(in.axis_x, n));
+    let mapped = textureSampleGrad(t_normal, s_decal, tex_coords, tex_dx, tex_dy).xyz * 2.0 - 1.0;
+    let normal = normalize(mat3x3<f32>(tangent, cross(n, tangent), n) * mapped);
+
+    // as the terrain's lit, with only `visibility` of the shadow caster
+    let visibility = shadow_visibility(shadow, t_shadow, s_shadow, world_position, n);
+    var light = lighting.ambient;
+    for (var i = 0u; i < lighting.count; i++) {
+        let sample = sample_light(lights[i], world_position);
+        light += diffuse_light(sample, normal) * select(1.0, visibility, i == lighting.shadow_caster);
+    }
+    let lit = albedo.rgb * light;
+    return vec4<f32>(apply_fog(fog, eye, world_position, lit), coverage);
+}
@@ -0,0 +1,355 @@
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::camera::Projection;
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::SamplerDesc;
+use crate::tonemap::HDR_FORMAT;
+
+// how tall the sensor behind the lens is taken to be, in world units as
+// metres: a full frame camera's, which the field of view is across
+const SENSOR_HEIGHT: f32 = 0.024;
+
+/// The camera's lens, as set per scene. What's `focus_distance` away is
+/// sharp, and what's nearer or further blurs by a thin lens's circle of
+/// confusion, more the wider the aperture is.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DepthOfField {
+    pub enabled: bool,
+    // in world units from the camera
+    pub focus_distance: f32,
+    // the focal length over the aperture's diameter, lower is wider
+    pub f_stop: f32,
+    // the widest the blur gets, as a radius in the scene's pixels
+    pub max_blur: f32,
+}
+
+// off, until a scene or `0` turns it on
+impl Default for DepthOfField {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            focus_distance: 4.0,
+            f_stop: 1.4,
+            max_blur: 16.0,
+        }
+    }
+}
+
+impl DepthOfField {
+    // The blur's radius in pixels of a frame `height` tall, per unit of
+    // (distance - focus_distance) / distance, for a lens with `projection`'s
+    // field of view across the sensor
+    fn coc_scale(&self, projection: &Projection, height: u32) -> f32 {
+        let focal_length = SENSOR_HEIGHT * 0.5 / (projection.fovy() * 0.5).tan();
+        let aperture = focal_length / self.f_stop.max(0.1);
+        // focused closer than the focal length, nothing would be sharp
+        let focus_distance = self.focus_distance.max(focal_length * 1.01);
+        aperture * focal_length / (focus_distance - focal_length) / SENSOR_HEIGHT * height as f32
+    }
+}
+
+// Matches `DofUniform` in depth_of_field.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DofUniform {
+    inverse_projection: [[f32; 4]; 4],
+    coc_scale: f32,
+    focus_distance: f32,
+    max_radius: f32,
+    _padding: f32,
+}
+
+// Depth of field over the HDR scene, before it's tonemapped, from the depth
+// of the G-buffer that screen-space reflections draw. The scene and each
+// pixel's circle of confusion go into a half size target, which is blurred
+// by gathering a disc around each pixel as wide as the widest blur: samples
+// count where their own blur reaches this far, and those behind a pixel
+// only as far as the pixel's own blur, so a blurry background doesn't
+// bleed over what's in focus in front of it. The result is blended over
+// the scene by how out of focus each pixel is, or how far the blur of
+// something in front of it spreads over it.
+pub struct DofPass {
+    layout: wgpu::BindGroupLayout,
+    input_layout: wgpu::BindGroupLayout,
+    downsample_pipeline: wgpu::RenderPipeline,
+    blur_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    // half size, the scene with its circles of confusion and then blurred
+    coc: Tracked<wgpu::TextureView>,
+    blurred: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    coc_bind_group: wgpu::BindGroup,
+    blurred_bind_group: wgpu::BindGroup,
+}
+
+impl DofPass {
+    // `depth` is the G-buffer's, the size of the scene
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, depth: &wgpu::TextureView) -> Self {
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1, false),
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let input_entries = [texture_entry(0, true)];
+        let shader = shader_preprocessor::load("depth_of_field.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &entries)?;
+                reflection.check_group(1, &input_entries)?;
+                reflection.check_struct::<DofUniform>("DofUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Depth of Field Shader");
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth of Field Bind Group Layout"),
+            entries: &entries,
+        });
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Depth of Field Input Bind Group Layout"),
+            entries: &input_entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Depth of Field Pipeline Layout"),
+            bind_group_layouts: &[&layout, &input_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point, blend: Option<wgpu::BlendState>| {
+            fullscreen_pipeline_with(
+                device,
+                label,
+                &pipeline_layout,
+                &shader,
+                entry_point,
+                &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend,
+                    write_mask: if blend.is_some() { wgpu::ColorWrites::COLOR } else { wgpu::ColorWrites::ALL },
+                })],
+            )
+        };
+        let downsample_pipeline = create_pipeline("Depth of Field Downsample Pipeline", "fs_downsample", None);
+        let blur_pipeline = create_pipeline("Depth of Field Blur Pipeline", "fs_blur", None);
+        // over the scene by how blurred it is there, the scene's alpha stays
+        // as it is
+        let composite_pipeline = create_pipeline(
+            "Depth of Field Composite Pipeline",
+            "fs_composite",
+            Some(wgpu::BlendState {
+                color: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::SrcAlpha,
+                    dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                    operation: wgpu::BlendOperation::Add,
+                },
+                alpha: wgpu::BlendComponent::REPLACE,
+            }),
+        );
+        // the blur's taps land between texels
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Depth of Field Sampler"));
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Depth of Field Buffer"),
+            contents: bytemuck::cast_slice(&[DofUniform {
+                inverse_projection: Matrix4::identity().into(),
+                coc_scale: 0.0,
+                focus_distance: 1.0,
+                max_radius: 0.0,
+                _padding: 0.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (coc, blurred, bind_group, coc_bind_group, blurred_bind_group) =
+            Self::create_targets(device, &layout, &input_layout, &sampler, &buffer, width, height, depth);
+        Self {
+            layout,
+            input_layout,
+            downsample_pipeline,
+            blur_pipeline,
+            composite_pipeline,
+            sampler,
+            buffer,
+            coc,
+            blurred,
+            bind_group,
+            coc_bind_group,
+            blurred_bind_group,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        input_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+        depth: &wgpu::TextureView,
+    ) -> (
+        Tracked<wgpu::TextureView>,
+        Tracked<wgpu::TextureView>,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+    ) {
+        let target = |label: &str| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.div_ceil(2).max(1),
+                    height: height.div_ceil(2).max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: HDR_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view_label = format!("{} View", label);
+            Tracked::view(
+                ResourceKind::Target,
+                &texture,
+                &wgpu::TextureViewDescriptor {
+                    label: Some(&view_label),
+                    ..Default::default()
+                },
+            )
+        };
+        let coc = target("Depth of Field CoC Target");
+        let blurred = target("Depth of Field Blur Target");
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of Field Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        let coc_bind_group = Self::input_bind_group(device, input_layout, &coc);
+        let blurred_bind_group = Self::input_bind_group(device, input_layout, &blurred);
+        (coc, blurred, bind_group, coc_bind_group, blurred_bind_group)
+    }
+
+    fn input_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        input: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Depth of Field Input Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            }],
+        })
+    }
+
+    // After the G-buffer's been resized, with its new depth
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, depth: &wgpu::TextureView) {
+        (self.coc, self.blurred, self.bind_group, self.coc_bind_group, self.blurred_bind_group) =
+            Self::create_targets(
+                device,
+                &self.layout,
+                &self.input_layout,
+                &self.sampler,
+                &self.buffer,
+                width,
+                height,
+                depth,
+            );
+    }
+
+    // Once a frame, for a scene `height` pixels tall seen through
+    // `projection`
+    pub fn update(&self, queue: &wgpu::Queue, settings: &DepthOfField, projection: &Projection, height: u32) {
+        let uniform = DofUniform {
+            inverse_projection: projection.calc_matrix().invert().unwrap_or(Matrix4::identity()).into(),
+            coc_scale: settings.coc_scale(projection, height),
+            focus_distance: settings.focus_distance,
+            max_radius: settings.max_blur.max(0.0),
+            _padding: 0.0,
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Blurs `scene`, which is HDR and the size of the G-buffer, where it's
+    // out of focus
+    pub fn encode(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, scene: &wgpu::TextureView) {
+        let scene_bind_group = Self::input_bind_group(device, &self.input_layout, scene);
+        // the half size targets are overwritten, the scene's blended over
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let load = wgpu::LoadOp::Load;
+        let passes = [
+            ("Depth of Field Downsample Pass", &*self.coc, clear, &self.downsample_pipeline, &scene_bind_group),
+            ("Depth of Field Blur Pass", &*self.blurred, clear, &self.blur_pipeline, &self.coc_bind_group),
+            ("Depth of Field Composite Pass", scene, load, &self.composite_pipeline, &self.blurred_bind_group),
+        ];
+        for (label, view, load, pipeline, input) in passes {
+            let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &self.bind_group, &[]);
+            pass.set_bind_group(1, input, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// Depth of field, see depth_of_field.rs. fs_downsample works out each half
+// size pixel's circle of confusion from the G-buffer's depth and keeps it
+// with the scene's colour there, fs_blur gathers a disc of those around
+// each pixel as wide as the blur gets, and fs_composite blends the result
+// over the scene wherever it's out of focus.
+
+#include "fullscreen.wgsl"
+
+// Matches `DofUniform` in depth_of_field.rs
+struct DofUniform {
+    // the camera's projection's, from clip space back to the view
+    inverse_projection: mat4x4<f32>,
+    // the blur's radius in full size pixels is this times
+    // (distance - focus_distance) / distance
+    coc_scale: f32,
+    focus_distance: f32,
+    // in full size pixels
+    max_radius: f32,
+    _padding: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> dof: DofUniform;
+// read as floats, GL can't load from depth textures
+@group(0) @binding(1)
+var t_depth: texture_2d<f32>;
+@group(0) @binding(2)
+var s_input: sampler;
+
+// the scene for fs_downsample, the half size colour and circles of
+// confusion for fs_blur, and the blurred one for fs_composite
+@group(1) @binding(0)
+var t_input: texture_2d<f32>;
+
+// spread over the disc as evenly as a sunflower's seeds
+const SAMPLES: u32 = 32u;
+const GOLDEN_ANGLE: f32 = 2.39996323;
+
+// In full size pixels, negative in front of the focus and positive behind
+// it, as far as the sky at the far plane
+fn circle_of_confusion(depth: f32) -> f32 {
+    let view = dof.inverse_projection * vec4<f32>(0.0, 0.0, depth, 1.0);
+    let distance = max(-view.z / view.w, 0.0001);
+    let coc = dof.coc_scale * (distance - dof.focus_distance) / distance;
+    return clamp(coc, -dof.max_radius, dof.max_radius);
+}
+
+@fragment
+fn fs_downsample(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<i32>(textureDimensions(t_input));
+    let corner = vec2<i32>(in.position.xy) * 2;
+    var color = vec3<f32>(0.0);
+    var depth = 1.0;
+    for (var i = 0; i < 4; i++) {
+        let pixel = min(corner + vec2<i32>(i & 1, i >> 1u), size - 1);
+        color += textureLoad(t_input, pixel, 0).rgb;
+        depth = min(depth, textureLoad(t_depth, pixel, 0).r);
+    }
+    // the nearest of the four, so what's in front keeps its blur at edges
+    return vec4<f32>(color * 0.25, circle_of_confusion(depth));
+}
+
+@fragment
+fn fs_blur(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_input));
+    let uv = in.position.xy * texel;
+    let center = textureSampleLevel(t_input, s_input, uv, 0.0);
+    // in half size pixels from here on
+    let center_radius = abs(center.a) * 0.5;
+    let max_radius = dof.max_radius * 0.5;
+    var color = center.rgb;
+    var radius = center_radius;
+    var count = 1.0;
+    for (var i = 0u; i < SAMPLES; i++) {
+        let distance = max_radius * sqrt((f32(i) + 0.5) / f32(SAMPLES));
+        let angle = f32(i) * GOLDEN_ANGLE;
+        let offset = vec2<f32>(cos(angle), sin(angle)) * distance * texel;
+        let sample = textureSampleLevel(t_input, s_input, uv + offset, 0.0);
+        var sample_radius = abs(sample.a) * 0.5;
+        // what's behind this pixel can't blur over it any further than
+        // this pixel's own blur reaches, or sharp edges would bleed
+        if sample.a > center.a {
+            sample_radius = min(sample_radius, center_radius * 2.0);
+        }
+        // samples whose blur doesn't reach this far count as the average
+        // so far
+        let reaches = smoothstep(distance - 0.5, distance + 0.5, sample_radius);
+        color += mix(color / count, sample.rgb, reaches);
+        radius += mix(radius / count, sample_radius, reaches);
+        count += 1.0;
+    }
+    return vec4<f32>(color / count, radius / count);
+}
+
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = textureDimensions(t_depth);
+    let coc = abs(circle_of_confusion(textureLoad(t_depth, vec2<i32>(in.position.xy), 0).r));
+    let blurred = textureSampleLevel(t_input, s_input, in.position.xy / vec2<f32>(size), 0.0);
+    // as blurry as this pixel is, or what's out of focus in front of it
+    // spreads over it
+    let radius = max(coc, blurred.a * 2.0);
+    return vec4<f32>(blurred.rgb, smoothstep(0.5, 2.0, radius));
+}
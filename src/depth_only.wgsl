@@ -0,0 +1,26 @@
+// Depth-only pass of instanced meshes, no fragment stage
+
+#include "camera.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+// the same sum as shader.wgsl, and invariant in both, so the depths match
+// exactly for the colour pass's `Equal` test
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @invariant @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    return camera.view_proj * world_position;
+}
@@ -0,0 +1,221 @@
+use crate::camera;
+use crate::draw_list::TracedPass;
+use crate::render_bundle::BundleTargets;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+
+pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// A DEPTH_FORMAT render target, with `usage` on top of RENDER_ATTACHMENT
+pub fn depth_target(
+    device: &wgpu::Device,
+    label: &str,
+    width: u32,
+    height: u32,
+    usage: wgpu::TextureUsages,
+) -> Tracked<wgpu::TextureView> {
+    let texture = depth_texture(device, label, width, height, 1, usage);
+    Tracked::view(ResourceKind::Target, &texture, &wgpu::TextureViewDescriptor::default())
+}
+
+// The same with `layers` layers, for targets that are arrays or cubemaps,
+// to make views of each layer from
+pub fn depth_texture(
+    device: &wgpu::Device,
+    label: &str,
+    width: u32,
+    height: u32,
+    layers: u32,
+    usage: wgpu::TextureUsages,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: layers.max(1),
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: DEPTH_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | usage,
+        view_formats: &[],
+    })
+}
+
+// A pipeline that only writes depth, from depth_only.wgsl, for meshes with
+// the `vertex_layouts` of the instanced meshes: a Float32x3 position at
+// location 0 and the instance matrix at locations 5 to 8. `layout` has the
+// camera at group 0, anything after it is unused. Shadow maps are drawn the
+// same way, from the light with a `bias` against acne.
+pub fn depth_only_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    vertex_layouts: &[wgpu::VertexBufferLayout],
+    bias: wgpu::DepthBiasState,
+) -> wgpu::RenderPipeline {
+    let shader = shader_preprocessor::load("depth_only.wgsl", &Default::default())
+        .and_then(|shader| {
+            let reflection = ShaderReflection::new(&shader)?;
+            reflection.check_group(0, &camera::layout_entries())?;
+            reflection.check_vertex_buffers("vs_main", vertex_layouts)?;
+            Ok(shader)
+        })
+        .unwrap_or_else(|e| panic!("{:#}", e))
+        .create_module(device, "Depth Only Shader");
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: vertex_layouts,
+            compilation_options: Default::default(),
+        },
+        fragment: None,
+        // as the scene pipelines cull
+        primitive: wgpu::PrimitiveState {
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias,
+        }),
+        multisample: Default::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+// Draws the opaque instanced meshes into a depth buffer before they're
+// shaded, so the colour pass after it only runs the fragment shader once
+// per pixel, for the nearest surface, instead of for every overlapping one.
+// The rest of the frame has no depth buffer and draws as before.
+pub struct DepthPrepass {
+    pipeline: wgpu::RenderPipeline,
+    depth_view: Tracked<wgpu::TextureView>,
+}
+
+impl DepthPrepass {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        layout: &wgpu::PipelineLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let pipeline = depth_only_pipeline(device, "Depth Prepass Pipeline", layout, vertex_layouts, Default::default());
+        Self {
+            pipeline,
+            depth_view: Self::create_depth(device, width, height),
+        }
+    }
+
+    // For the colour pipelines drawn in `begin_color`'s pass: the vertex
+    // shader has to work the position out exactly as depth_only.wgsl does
+    pub fn depth_equal() -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Equal,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }
+    }
+
+    fn create_depth(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        // bound for the occlusion culling's depth pyramid to be built from
+        depth_target(device, "Depth Prepass Target", width, height, wgpu::TextureUsages::TEXTURE_BINDING)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_view = Self::create_depth(device, width, height);
+    }
+
+    pub fn pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.pipeline
+    }
+
+    // What `begin`'s pass left, until it's resized
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth_view
+    }
+
+    // What `begin`'s pass draws to, for the render bundles drawn in it
+    pub const TARGETS: BundleTargets = BundleTargets {
+        color: None,
+        depth: Some(wgpu::RenderBundleDepthStencil {
+            format: DEPTH_FORMAT,
+            depth_read_only: false,
+            stencil_read_only: true,
+        }),
+        sample_count: 1,
+    };
+
+    // `begin_color`'s, for a `format` target
+    pub fn color_targets(format: wgpu::TextureFormat) -> BundleTargets {
+        BundleTargets {
+            color: Some(format),
+            depth: Some(wgpu::RenderBundleDepthStencil {
+                format: DEPTH_FORMAT,
+                depth_read_only: true,
+                stencil_read_only: true,
+            }),
+            sample_count: 1,
+        }
+    }
+
+    // The depth-only pass, cleared to the far plane, to draw with `pipeline`
+    pub fn begin<'e>(
+        &'e self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'e>>,
+    ) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Depth Prepass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes,
+        })
+    }
+
+    // A pass over what's drawn in `target` already, testing against the
+    // prepass's depth without writing it
+    pub fn begin_color<'e>(&'e self, encoder: &'e mut wgpu::CommandEncoder, target: &'e wgpu::TextureView) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Opaque Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                // read-only, it stays as the prepass left it
+                depth_ops: None,
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use crate::draw_list::TracedPass;
+use crate::shader_preprocessor::ShaderDefs;
+use crate::upload::{DynamicUniforms, Uploader};
+
+// Bytes of `DrawData`, all a push constant range has to cover
+const SIZE: u32 = std::mem::size_of::<DrawData>() as u32;
+
+const PUSH_CONSTANT_RANGES: &[wgpu::PushConstantRange] = &[wgpu::PushConstantRange {
+    stages: wgpu::ShaderStages::VERTEX_FRAGMENT,
+    range: 0..SIZE,
+}];
+
+/// The few values that change between draws, matching `DrawData` in
+/// draw_data.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct DrawData {
+    // linear RGBA, multiplied with the shaded colour
+    pub tint: [f32; 4],
+    pub object: u32,
+    // u32::MAX for none
+    pub material: u32,
+    // level of detail cross-fade, see `LodState::draws`: zero draws every
+    // pixel, a fade in (0, 1] that fraction of a dither pattern and its
+    // negation the rest
+    pub fade: f32,
+    pub _padding: u32,
+}
+
+impl DrawData {
+    pub fn new(object: u32, material: Option<u32>, tint: [f32; 4]) -> Self {
+        Self {
+            tint,
+            object,
+            material: material.unwrap_or(u32::MAX),
+            fade: 0.0,
+            _padding: 0,
+        }
+    }
+
+    pub fn with_fade(self, fade: f32) -> Self {
+        Self { fade, ..self }
+    }
+}
+
+// How per-draw data reaches the shaders, picked from the device features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrawDataMode {
+    // set_push_constants before each draw, no bind group changes
+    PushConstants,
+    // a dynamic offset into `DynamicUniforms`, rebinding one small group
+    Uniforms,
+}
+
+impl DrawDataMode {
+    pub fn from_device(device: &wgpu::Device) -> Self {
+        if device.features().contains(wgpu::Features::PUSH_CONSTANTS) && device.limits().max_push_constant_size >= SIZE {
+            DrawDataMode::PushConstants
+        } else {
+            DrawDataMode::Uniforms
+        }
+    }
+}
+
+// Push constants are native-only, and need a limit raised at device creation
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    if adapter.limits().max_push_constant_size >= SIZE {
+        adapter.features() & wgpu::Features::PUSH_CONSTANTS
+    } else {
+        wgpu::Features::empty()
+    }
+}
+
+// For `Limits::max_push_constant_size`, zero without the feature
+pub fn push_constant_size(adapter: &wgpu::Adapter) -> u32 {
+    if optional_features(adapter).is_empty() {
+        0
+    } else {
+        SIZE
+    }
+}
+
+/// Where a draw's `DrawData` is, returned by `PerDraw::push`.
+#[derive(Debug, Clone, Copy)]
+pub struct DrawSlot {
+    data: DrawData,
+    // into the fallback uniforms, unused with push constants
+    offset: u32,
+}
+
+/// Per-draw data for one pipeline, as push constants when the device has
+/// them and through dynamic uniform offsets otherwise. Each frame, `clear`
+/// and `push` every draw before `upload`, then `set` each slot before its
+/// draw call.
+pub struct PerDraw {
+    mode: DrawDataMode,
+    // only for DrawDataMode::Uniforms
+    uniforms: Option<DynamicUniforms<DrawData>>,
+}
+
+impl PerDraw {
+    pub fn new(device: &wgpu::Device, label: &str) -> Self {
+        let mode = DrawDataMode::from_device(device);
+        let uniforms = (mode == DrawDataMode::Uniforms)
+            .then(|| DynamicUniforms::per_frame(device, label, wgpu::ShaderStages::VERTEX_FRAGMENT, 64));
+        Self { mode, uniforms }
+    }
+
+    pub fn mode(&self) -> DrawDataMode {
+        self.mode
+    }
+
+    // Defines for shaders that `#include "draw_data.wgsl"` to read
+    // `draw: DrawData`. The fallback binds it at `group`, which has to be the
+    // pipeline layout slot after the caller's own groups.
+    pub fn shader_defs(&self, group: u32) -> ShaderDefs {
+        match self.mode {
+            DrawDataMode::PushConstants => ShaderDefs::new().with("DRAW_PUSH_CONSTANTS"),
+            DrawDataMode::Uniforms => ShaderDefs::new().with_value("DRAW_GROUP", group),
+        }
+    }
+
+    // To append to the pipeline's bind group layouts, None with push constants
+    pub fn bind_group_layout(&self) -> Option<&wgpu::BindGroupLayout> {
+        self.uniforms.as_ref().map(DynamicUniforms::layout)
+    }
+
+    // The entries of `bind_group_layout`, empty with push constants
+    pub fn layout_entries(&self) -> Vec<wgpu::BindGroupLayoutEntry> {
+        self.uniforms.iter().map(DynamicUniforms::layout_entry).collect()
+    }
+
+    pub fn push_constant_ranges(&self) -> &'static [wgpu::PushConstantRange] {
+        match self.mode {
+            DrawDataMode::PushConstants => PUSH_CONSTANT_RANGES,
+            DrawDataMode::Uniforms => &[],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if let Some(uniforms) = &mut self.uniforms {
+            uniforms.clear();
+        }
+    }
+
+    pub fn push(&mut self, data: DrawData) -> DrawSlot {
+        let offset = self.uniforms.as_mut().map_or(0, |uniforms| uniforms.push(&data));
+        DrawSlot { data, offset }
+    }
+
+    pub fn upload(&mut self, uploader: &mut Uploader) {
+        if let Some(uniforms) = &mut self.uniforms {
+            uniforms.upload(uploader);
+        }
+    }
+
+    // `group` is the same as the one passed to `wgsl`
+    pub fn set(&self, render_pass: &mut TracedPass, group: u32, slot: &DrawSlot) {
+        match &self.uniforms {
+            None => render_pass.set_push_constants(
+                wgpu::ShaderStages::VERTEX_FRAGMENT,
+                0,
+                bytemuck::bytes_of(&slot.data),
+            ),
+            Some(uniforms) => render_pass.set_bind_group(group, uniforms.bind_group(), &[slot.offset]),
+        }
+    }
+}
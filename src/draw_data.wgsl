@@ -0,0 +1,16 @@
+// Matches `DrawData` in draw_data.rs. `PerDraw::shader_defs` says where it
+// comes from: push constants with DRAW_PUSH_CONSTANTS, otherwise a uniform
+// at DRAW_GROUP.
+struct DrawData {
+    tint: vec4<f32>,
+    object: u32,
+    material: u32,
+    fade: f32,
+};
+
+#ifdef DRAW_PUSH_CONSTANTS
+var<push_constant> draw: DrawData;
+#else
+@group(#{DRAW_GROUP}) @binding(0)
+var<uniform> draw: DrawData;
+#endif
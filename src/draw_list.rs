@@ -0,0 +1,567 @@
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::collections::BTreeMap;
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::*;
+use serde::Serialize;
+
+// Set between `start` and `finish`, so passes only take the lock then
+static RECORDING: AtomicBool = AtomicBool::new(false);
+// what the passes recorded so far, in the order they ended
+static PASSES: Mutex<Vec<PassRecord>> = Mutex::new(Vec::new());
+// wgpu's global IDs by the numbers they're dumped as, counted separately
+// for pipelines, bind groups, buffers, bundles and texture views, whose IDs
+// overlap
+static NUMBERS: Mutex<BTreeMap<(Kind, u64), u64>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Kind {
+    Pipeline,
+    BindGroup,
+    Buffer,
+    Bundle,
+    View,
+}
+
+/// Every draw of a frame with the state it was made in, for diffing two
+/// frames to find out why something stopped drawing. Pipelines, bind
+/// groups, buffers, bundles and attachments' views are numbered in the order dumps first see
+/// them, so the same object has the same number in every frame for as long
+/// as it lives; what they're for is in the debug groups and markers, which
+/// are named after the models and meshes.
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawList {
+    pub frame: u64,
+    pub passes: Vec<PassRecord>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PassRecord {
+    pub label: String,
+    pub attachments: Vec<AttachmentRecord>,
+    pub draws: Vec<DrawRecord>,
+}
+
+// A view a pass renders into, and whether it keeps what was there before
+// and what it leaves behind
+#[derive(Debug, Clone, Serialize)]
+pub struct AttachmentRecord {
+    pub view: u64,
+    pub role: AttachmentRole,
+    // false where it's cleared first, or for a resolve target
+    pub load: bool,
+    // false where it's discarded at the end, or for read-only depth
+    pub store: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum AttachmentRole {
+    // by index in the pass's colour attachments
+    Color(usize),
+    Resolve(usize),
+    DepthStencil,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DrawRecord {
+    // the debug groups it's in, outermost first
+    pub groups: Vec<String>,
+    // the last marker in the innermost group, like a mesh's name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+    pub pipeline: Option<u64>,
+    // by group index, None where nothing's bound
+    pub bind_groups: Vec<Option<BoundGroup>>,
+    // the slots with a buffer bound; wgpu doesn't say which buffer a slice
+    // is of
+    pub vertex_buffers: Vec<u32>,
+    pub index_format: Option<wgpu::IndexFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub viewport: Option<[f32; 6]>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scissor: Option<[u32; 4]>,
+    pub call: DrawCall,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BoundGroup {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub offsets: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum DrawCall {
+    // ranges are start and end
+    Draw {
+        vertices: [u32; 2],
+        instances: [u32; 2],
+    },
+    DrawIndexed {
+        indices: [u32; 2],
+        base_vertex: i32,
+        instances: [u32; 2],
+    },
+    DrawIndirect {
+        buffer: u64,
+        offset: u64,
+    },
+    DrawIndexedIndirect {
+        buffer: u64,
+        offset: u64,
+    },
+    MultiDrawIndirect {
+        buffer: u64,
+        offset: u64,
+        count: u32,
+    },
+    MultiDrawIndexedIndirect {
+        buffer: u64,
+        offset: u64,
+        count: u32,
+    },
+    MultiDrawIndexedIndirectCount {
+        buffer: u64,
+        offset: u64,
+        count_buffer: u64,
+        count_offset: u64,
+        max_count: u32,
+    },
+    // bundles recorded earlier; what's inside them isn't traced
+    ExecuteBundles {
+        bundles: Vec<u64>,
+    },
+    // drawn by code that takes a plain wgpu pass, see `TracedPass::raw`
+    Untraced,
+}
+
+impl DrawList {
+    pub fn draw_count(&self) -> usize {
+        self.passes.iter().map(|pass| pass.draws.len()).sum()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Writing the draw list to {}", path.display()))
+    }
+}
+
+// Records what every `TracedPass` begun from now on draws, until `finish`
+pub fn start() {
+    PASSES.lock().unwrap_or_else(PoisonError::into_inner).clear();
+    RECORDING.store(true, Ordering::Release);
+}
+
+// The passes that ended since `start`, once they're all dropped
+pub fn finish(frame: u64) -> DrawList {
+    RECORDING.store(false, Ordering::Release);
+    let mut passes = std::mem::take(&mut *PASSES.lock().unwrap_or_else(PoisonError::into_inner));
+    let mut numbers = NUMBERS.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut number = |kind: Kind, id: &mut u64| {
+        if let Some(numbered) = numbers.get(&(kind, *id)) {
+            *id = *numbered;
+            return;
+        }
+        let next = numbers.keys().filter(|(numbered, _)| *numbered == kind).count() as u64 + 1;
+        numbers.insert((kind, *id), next);
+        *id = next;
+    };
+    for attachment in passes.iter_mut().flat_map(|pass| &mut pass.attachments) {
+        number(Kind::View, &mut attachment.view);
+    }
+    for draw in passes.iter_mut().flat_map(|pass| &mut pass.draws) {
+        draw.pipeline.iter_mut().for_each(|id| number(Kind::Pipeline, id));
+        draw.bind_groups.iter_mut().flatten().for_each(|group| number(Kind::BindGroup, &mut group.id));
+        match &mut draw.call {
+            DrawCall::DrawIndirect { buffer, .. }
+            | DrawCall::DrawIndexedIndirect { buffer, .. }
+            | DrawCall::MultiDrawIndirect { buffer, .. }
+            | DrawCall::MultiDrawIndexedIndirect { buffer, .. } => number(Kind::Buffer, buffer),
+            DrawCall::MultiDrawIndexedIndirectCount { buffer, count_buffer, .. } => {
+                number(Kind::Buffer, buffer);
+                number(Kind::Buffer, count_buffer);
+            }
+            DrawCall::ExecuteBundles { bundles } => bundles.iter_mut().for_each(|id| number(Kind::Bundle, id)),
+            DrawCall::Draw { .. } | DrawCall::DrawIndexed { .. } | DrawCall::Untraced => {}
+        }
+    }
+    DrawList { frame, passes }
+}
+
+pub fn is_recording() -> bool {
+    RECORDING.load(Ordering::Acquire)
+}
+
+// What's bound when a draw is made
+#[derive(Debug, Default)]
+struct Trace {
+    record: PassRecord,
+    groups: Vec<String>,
+    marker: Option<String>,
+    pipeline: Option<u64>,
+    bind_groups: Vec<Option<BoundGroup>>,
+    vertex_buffers: Vec<u32>,
+    index_format: Option<wgpu::IndexFormat>,
+    viewport: Option<[f32; 6]>,
+    scissor: Option<[u32; 4]>,
+}
+
+impl Trace {
+    fn push(&mut self, call: DrawCall) {
+        self.record.draws.push(DrawRecord {
+            groups: self.groups.clone(),
+            marker: self.marker.clone(),
+            pipeline: self.pipeline,
+            bind_groups: self.bind_groups.clone(),
+            vertex_buffers: self.vertex_buffers.clone(),
+            index_format: self.index_format,
+            viewport: self.viewport,
+            scissor: self.scissor,
+            call,
+        });
+    }
+}
+
+/// A render pass with the methods of `wgpu::RenderPass`, that while a
+/// `DrawList` is being recorded keeps track of what's bound and records
+/// each draw with it. Otherwise it's the plain pass.
+pub struct TracedPass<'e> {
+    pass: wgpu::RenderPass<'e>,
+    // None unless recording
+    trace: Option<Box<Trace>>,
+    // what its colour target has, for picking pipelines that match
+    sample_count: u32,
+}
+
+impl<'e> TracedPass<'e> {
+    pub fn begin(encoder: &'e mut wgpu::CommandEncoder, desc: &wgpu::RenderPassDescriptor<'_>) -> Self {
+        let mut pass = Self::new(encoder.begin_render_pass(desc), desc.label.unwrap_or_default());
+        if let Some(trace) = &mut pass.trace {
+            trace.record.attachments = attachments(desc);
+        }
+        pass
+    }
+
+    // For a pass begun somewhere else, `label` as it was begun with
+    pub fn new(pass: wgpu::RenderPass<'e>, label: &str) -> Self {
+        let trace = is_recording().then(|| {
+            Box::new(Trace {
+                record: PassRecord {
+                    label: label.to_owned(),
+                    ..Default::default()
+                },
+                ..Default::default()
+            })
+        });
+        Self {
+            pass,
+            trace,
+            sample_count: 1,
+        }
+    }
+
+    // For a pass with a multisampled colour target
+    pub fn with_sample_count(mut self, sample_count: u32) -> Self {
+        self.sample_count = sample_count;
+        self
+    }
+
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    // The pass for code that only takes wgpu's, like a library's. What it
+    // draws is recorded as one untraced draw, and what it binds isn't seen.
+    pub fn raw(&mut self) -> &mut wgpu::RenderPass<'e> {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::Untraced);
+        }
+        &mut self.pass
+    }
+
+    pub fn set_bind_group(&mut self, index: u32, bind_group: &wgpu::BindGroup, offsets: &[wgpu::DynamicOffset]) {
+        if let Some(trace) = &mut self.trace {
+            let index = index as usize;
+            if trace.bind_groups.len() <= index {
+                trace.bind_groups.resize(index + 1, None);
+            }
+            trace.bind_groups[index] = Some(BoundGroup {
+                id: bind_group.global_id().inner(),
+                offsets: offsets.to_vec(),
+            });
+        }
+        self.pass.set_bind_group(index, bind_group, offsets);
+    }
+
+    pub fn set_pipeline(&mut self, pipeline: &wgpu::RenderPipeline) {
+        if let Some(trace) = &mut self.trace {
+            trace.pipeline = Some(pipeline.global_id().inner());
+        }
+        self.pass.set_pipeline(pipeline);
+    }
+
+    pub fn set_blend_constant(&mut self, color: wgpu::Color) {
+        self.pass.set_blend_constant(color);
+    }
+
+    pub fn set_index_buffer(&mut self, buffer_slice: wgpu::BufferSlice<'_>, index_format: wgpu::IndexFormat) {
+        if let Some(trace) = &mut self.trace {
+            trace.index_format = Some(index_format);
+        }
+        self.pass.set_index_buffer(buffer_slice, index_format);
+    }
+
+    pub fn set_vertex_buffer(&mut self, slot: u32, buffer_slice: wgpu::BufferSlice<'_>) {
+        if let Some(trace) = &mut self.trace {
+            if let Err(index) = trace.vertex_buffers.binary_search(&slot) {
+                trace.vertex_buffers.insert(index, slot);
+            }
+        }
+        self.pass.set_vertex_buffer(slot, buffer_slice);
+    }
+
+    pub fn set_scissor_rect(&mut self, x: u32, y: u32, width: u32, height: u32) {
+        if let Some(trace) = &mut self.trace {
+            trace.scissor = Some([x, y, width, height]);
+        }
+        self.pass.set_scissor_rect(x, y, width, height);
+    }
+
+    pub fn set_viewport(&mut self, x: f32, y: f32, w: f32, h: f32, min_depth: f32, max_depth: f32) {
+        if let Some(trace) = &mut self.trace {
+            trace.viewport = Some([x, y, w, h, min_depth, max_depth]);
+        }
+        self.pass.set_viewport(x, y, w, h, min_depth, max_depth);
+    }
+
+    pub fn set_stencil_reference(&mut self, reference: u32) {
+        self.pass.set_stencil_reference(reference);
+    }
+
+    pub fn set_push_constants(&mut self, stages: wgpu::ShaderStages, offset: u32, data: &[u8]) {
+        self.pass.set_push_constants(stages, offset, data);
+    }
+
+    pub fn insert_debug_marker(&mut self, label: &str) {
+        if let Some(trace) = &mut self.trace {
+            trace.marker = Some(label.to_owned());
+        }
+        self.pass.insert_debug_marker(label);
+    }
+
+    pub fn push_debug_group(&mut self, label: &str) {
+        if let Some(trace) = &mut self.trace {
+            trace.groups.push(label.to_owned());
+            trace.marker = None;
+        }
+        self.pass.push_debug_group(label);
+    }
+
+    pub fn pop_debug_group(&mut self) {
+        if let Some(trace) = &mut self.trace {
+            trace.groups.pop();
+            trace.marker = None;
+        }
+        self.pass.pop_debug_group();
+    }
+
+    pub fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::Draw {
+                vertices: [vertices.start, vertices.end],
+                instances: [instances.start, instances.end],
+            });
+        }
+        self.pass.draw(vertices, instances);
+    }
+
+    pub fn draw_indexed(&mut self, indices: Range<u32>, base_vertex: i32, instances: Range<u32>) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::DrawIndexed {
+                indices: [indices.start, indices.end],
+                base_vertex,
+                instances: [instances.start, instances.end],
+            });
+        }
+        self.pass.draw_indexed(indices, base_vertex, instances);
+    }
+
+    pub fn draw_indirect(&mut self, indirect_buffer: &wgpu::Buffer, indirect_offset: wgpu::BufferAddress) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::DrawIndirect {
+                buffer: indirect_buffer.global_id().inner(),
+                offset: indirect_offset,
+            });
+        }
+        self.pass.draw_indirect(indirect_buffer, indirect_offset);
+    }
+
+    pub fn draw_indexed_indirect(&mut self, indirect_buffer: &wgpu::Buffer, indirect_offset: wgpu::BufferAddress) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::DrawIndexedIndirect {
+                buffer: indirect_buffer.global_id().inner(),
+                offset: indirect_offset,
+            });
+        }
+        self.pass.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
+    pub fn multi_draw_indirect(
+        &mut self,
+        indirect_buffer: &wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        count: u32,
+    ) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::MultiDrawIndirect {
+                buffer: indirect_buffer.global_id().inner(),
+                offset: indirect_offset,
+                count,
+            });
+        }
+        self.pass.multi_draw_indirect(indirect_buffer, indirect_offset, count);
+    }
+
+    pub fn multi_draw_indexed_indirect(
+        &mut self,
+        indirect_buffer: &wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        count: u32,
+    ) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::MultiDrawIndexedIndirect {
+                buffer: indirect_buffer.global_id().inner(),
+                offset: indirect_offset,
+                count,
+            });
+        }
+        self.pass.multi_draw_indexed_indirect(indirect_buffer, indirect_offset, count);
+    }
+
+    pub fn multi_draw_indexed_indirect_count(
+        &mut self,
+        indirect_buffer: &wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        count_buffer: &wgpu::Buffer,
+        count_offset: wgpu::BufferAddress,
+        max_count: u32,
+    ) {
+        if let Some(trace) = &mut self.trace {
+            trace.push(DrawCall::MultiDrawIndexedIndirectCount {
+                buffer: indirect_buffer.global_id().inner(),
+                offset: indirect_offset,
+                count_buffer: count_buffer.global_id().inner(),
+                count_offset,
+                max_count,
+            });
+        }
+        self.pass
+            .multi_draw_indexed_indirect_count(indirect_buffer, indirect_offset, count_buffer, count_offset, max_count);
+    }
+
+    pub fn execute_bundles<'a, I: IntoIterator<Item = &'a wgpu::RenderBundle>>(&mut self, render_bundles: I) {
+        let Some(trace) = &mut self.trace else {
+            self.pass.execute_bundles(render_bundles);
+            return;
+        };
+        let render_bundles = render_bundles.into_iter().collect::<Vec<_>>();
+        trace.push(DrawCall::ExecuteBundles {
+            bundles: render_bundles.iter().map(|bundle| bundle.global_id().inner()).collect(),
+        });
+        // executing bundles resets what's bound
+        trace.pipeline = None;
+        trace.bind_groups.clear();
+        trace.vertex_buffers.clear();
+        trace.index_format = None;
+        self.pass.execute_bundles(render_bundles);
+    }
+}
+
+// What `desc` renders into, colour attachments first
+fn attachments(desc: &wgpu::RenderPassDescriptor<'_>) -> Vec<AttachmentRecord> {
+    let mut attachments = Vec::new();
+    for (index, attachment) in desc.color_attachments.iter().enumerate() {
+        let Some(attachment) = attachment else {
+            continue;
+        };
+        attachments.push(AttachmentRecord {
+            view: attachment.view.global_id().inner(),
+            role: AttachmentRole::Color(index),
+            load: matches!(attachment.ops.load, wgpu::LoadOp::Load),
+            store: attachment.ops.store == wgpu::StoreOp::Store,
+        });
+        if let Some(resolve_target) = attachment.resolve_target {
+            attachments.push(AttachmentRecord {
+                view: resolve_target.global_id().inner(),
+                role: AttachmentRole::Resolve(index),
+                load: false,
+                store: true,
+            });
+        }
+    }
+    if let Some(attachment) = &desc.depth_stencil_attachment {
+        // aspects without ops are read only
+        let (depth_load, depth_store) = attachment
+            .depth_ops
+            .map_or((true, false), |ops| (matches!(ops.load, wgpu::LoadOp::Load), ops.store == wgpu::StoreOp::Store));
+        let (stencil_load, stencil_store) = attachment
+            .stencil_ops
+            .map_or((false, false), |ops| (matches!(ops.load, wgpu::LoadOp::Load), ops.store == wgpu::StoreOp::Store));
+        attachments.push(AttachmentRecord {
+            view: attachment.view.global_id().inner(),
+            role: AttachmentRole::DepthStencil,
+            load: depth_load || stencil_load,
+            store: depth_store || stencil_store,
+        });
+    }
+    attachments
+}
+
+impl Drop for TracedPass<'_> {
+    fn drop(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            PASSES.lock().unwrap_or_else(PoisonError::into_inner).push(trace.record);
+        }
+    }
+}
+
+// For draw code shared with render bundles
+impl<'e> wgpu::util::RenderEncoder<'e> for TracedPass<'e> {
+    fn set_bind_group(&mut self, index: u32, bind_group: &'e wgpu::BindGroup, offsets: &[wgpu::DynamicOffset]) {
+        TracedPass::set_bind_group(self, index, bind_group, offsets);
+    }
+
+    fn set_pipeline(&mut self, pipeline: &'e wgpu::RenderPipeline) {
+        TracedPass::set_pipeline(self, pipeline);
+    }
+
+    fn set_index_buffer(&mut self, buffer_slice: wgpu::BufferSlice<'e>, index_format: wgpu::IndexFormat) {
+        TracedPass::set_index_buffer(self, buffer_slice, index_format);
+    }
+
+    fn set_vertex_buffer(&mut self, slot: u32, buffer_slice: wgpu::BufferSlice<'e>) {
+        TracedPass::set_vertex_buffer(self, slot, buffer_slice);
+    }
+
+    fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        TracedPass::draw(self, vertices, instances);
+    }
+
+    fn draw_indexed(&mut self, indices: Range<u32>, base_vertex: i32, instances: Range<u32>) {
+        TracedPass::draw_indexed(self, indices, base_vertex, instances);
+    }
+
+    fn draw_indirect(&mut self, indirect_buffer: &'e wgpu::Buffer, indirect_offset: wgpu::BufferAddress) {
+        TracedPass::draw_indirect(self, indirect_buffer, indirect_offset);
+    }
+
+    fn draw_indexed_indirect(&mut self, indirect_buffer: &'e wgpu::Buffer, indirect_offset: wgpu::BufferAddress) {
+        TracedPass::draw_indexed_indirect(self, indirect_buffer, indirect_offset);
+    }
+
+    fn set_push_constants(&mut self, stages: wgpu::ShaderStages, offset: u32, data: &[u8]) {
+        TracedPass::set_push_constants(self, stages, offset, data);
+    }
+}
@@ -0,0 +1,244 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use winit::dpi::PhysicalSize;
+
+use crate::readback::{self, ReadbackRing};
+
+// Needed to time the frame on the GPU, which it's scaled by; without them
+// it goes by the CPU's frame time
+const TIMER_FEATURES: wgpu::Features =
+    wgpu::Features::TIMESTAMP_QUERY.union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS);
+
+// the scale moves in steps, so it settles instead of resizing every frame
+const SCALE_STEP: f32 = 0.05;
+const MIN_SCALE: f32 = 0.25;
+// how often the scale's adjusted, and how far it looks back
+const ADJUST_INTERVAL: Duration = Duration::from_millis(500);
+const SMOOTHING: f32 = 0.1;
+// fractions of the frame budget: over the first it scales down, under the
+// second up, each time aiming for the third
+const OVER_BUDGET: f32 = 0.95;
+const UNDER_BUDGET: f32 = 0.75;
+const AIM: f32 = 0.85;
+// vsync holds the CPU's frame time at the budget, so going by that it only
+// scales down on missed frames, and tries a step up after a while without
+const CPU_OVER_BUDGET: f32 = 1.15;
+const CPU_PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    if adapter.features().contains(TIMER_FEATURES) {
+        TIMER_FEATURES
+    } else {
+        wgpu::Features::empty()
+    }
+}
+
+/// What the 3D scene renders at, as a fraction of the window's size, before
+/// it's upscaled into the window. The 2D overlay and text always draw at the
+/// window's size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum RenderScale {
+    Fixed(f32),
+    // follows the GPU's frame time to stay within the frame budget
+    Dynamic { min: f32, max: f32 },
+}
+
+impl Default for RenderScale {
+    fn default() -> Self {
+        RenderScale::Fixed(1.0)
+    }
+}
+
+impl RenderScale {
+    // What `9` turns on
+    pub const DYNAMIC: Self = RenderScale::Dynamic { min: 0.5, max: 1.0 };
+
+    fn range(self) -> (f32, f32) {
+        let (min, max) = match self {
+            RenderScale::Fixed(scale) => (scale, scale),
+            RenderScale::Dynamic { min, max } => (min, max),
+        };
+        let max = max.clamp(MIN_SCALE, 1.0);
+        (min.clamp(MIN_SCALE, max), max)
+    }
+}
+
+/// How a scaled frame is brought up to the window's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Upscaling {
+    #[default]
+    Bilinear,
+    // bilinear, then FSR 1's contrast adaptive sharpening (RCAS) in the same
+    // pass, to get back some of the detail filtering blurs
+    Sharpened,
+}
+
+// `size` scaled, at least a pixel across
+pub fn scaled_size(size: PhysicalSize<u32>, scale: f32) -> PhysicalSize<u32> {
+    let scale = |pixels: u32| ((pixels as f32 * scale).round() as u32).clamp(1, pixels.max(1));
+    PhysicalSize::new(scale(size.width), scale(size.height))
+}
+
+/// A frame's time, from the GPU's timestamps if there are any.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameTime {
+    Gpu(f32),
+    Cpu(f32),
+}
+
+/// Picks the render scale from recent frame times: down when they're over
+/// the frame budget, up when there's room, in steps and no more than twice a
+/// second.
+#[derive(Debug, Clone)]
+pub struct ResolutionScaler {
+    scale: f32,
+    // smoothed, in seconds
+    frame_time: Option<f32>,
+    since_adjust: Duration,
+    since_over: Duration,
+}
+
+impl Default for ResolutionScaler {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            frame_time: None,
+            since_adjust: Duration::ZERO,
+            since_over: Duration::ZERO,
+        }
+    }
+}
+
+impl ResolutionScaler {
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Takes in the last frame's time, `dt` after the one before, against a
+    // `budget` in seconds. Returns the new scale when it changes.
+    pub fn update(&mut self, mode: RenderScale, frame_time: FrameTime, budget: f32, dt: Duration) -> Option<f32> {
+        let (min, max) = mode.range();
+        let scale = match mode {
+            RenderScale::Fixed(_) => min,
+            RenderScale::Dynamic { .. } => self.adjust(frame_time, budget, dt).clamp(min, max),
+        };
+        if scale == self.scale {
+            return None;
+        }
+        self.scale = scale;
+        // what was measured at the old scale doesn't say much about the new
+        self.frame_time = None;
+        self.since_adjust = Duration::ZERO;
+        Some(scale)
+    }
+
+    fn adjust(&mut self, frame_time: FrameTime, budget: f32, dt: Duration) -> f32 {
+        let (time, over_budget) = match frame_time {
+            FrameTime::Gpu(time) => (time, OVER_BUDGET),
+            FrameTime::Cpu(time) => (time, CPU_OVER_BUDGET),
+        };
+        let smoothed = self.frame_time.map_or(time, |smoothed| smoothed + (time - smoothed) * SMOOTHING);
+        self.frame_time = Some(smoothed);
+        self.since_adjust += dt;
+        self.since_over += dt;
+        if self.since_adjust < ADJUST_INTERVAL || budget <= 0.0 || smoothed <= 0.0 {
+            return self.scale;
+        }
+        self.since_adjust = Duration::ZERO;
+
+        let ratio = smoothed / budget;
+        let probe = matches!(frame_time, FrameTime::Cpu(_)) && self.since_over >= CPU_PROBE_INTERVAL;
+        if ratio > over_budget {
+            self.since_over = Duration::ZERO;
+            // the GPU's time goes with the pixel count, the square of the scale
+            snap(self.scale * (AIM / ratio).sqrt()).min(snap(self.scale - SCALE_STEP))
+        } else if ratio < UNDER_BUDGET || probe {
+            self.since_over = Duration::ZERO;
+            // one step at a time, overshooting would only bring it back down
+            snap(self.scale + SCALE_STEP)
+        } else {
+            self.scale
+        }
+    }
+}
+
+fn snap(scale: f32) -> f32 {
+    (scale / SCALE_STEP).round() * SCALE_STEP
+}
+
+/// Times each frame from its first command to its last with two GPU
+/// timestamps, read back a frame or two later.
+pub struct GpuFrameTimer {
+    queries: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback: ReadbackRing,
+    // nanoseconds a tick
+    period: f32,
+    frame_time: Option<f32>,
+}
+
+impl GpuFrameTimer {
+    // None without the features `optional_features` asks for
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Option<Self> {
+        if !device.features().contains(TIMER_FEATURES) {
+            return None;
+        }
+        let queries = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some("Frame Timer Queries"),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2,
+        });
+        let size = 2 * std::mem::size_of::<u64>() as wgpu::BufferAddress;
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Frame Timer Resolve Buffer"),
+            size,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        Some(Self {
+            queries,
+            resolve_buffer,
+            readback: ReadbackRing::buffer(device, "Frame Timer Readback Buffer", size, readback::FRAMES_IN_FLIGHT),
+            period: queue.get_timestamp_period(),
+            frame_time: None,
+        })
+    }
+
+    // At the start of the frame's first encoder
+    pub fn begin(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.queries, 0);
+    }
+
+    // At the end of its last, which is then submitted after the first
+    pub fn end(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.write_timestamp(&self.queries, 1);
+        encoder.resolve_query_set(&self.queries, 0..2, &self.resolve_buffer, 0);
+        self.readback.copy_buffer(encoder, &self.resolve_buffer, 0);
+    }
+
+    // Call once the encoder passed to `end` has been submitted
+    pub fn after_submit(&mut self) {
+        self.readback.map();
+    }
+
+    // Non-blocking, once a frame: takes in a frame time that's come back
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        match self.readback.try_read(device) {
+            Some(Ok(data)) => {
+                let [start, end]: [u64; 2] = bytemuck::pod_read_unaligned(&data);
+                // a timestamp can be reset between the two
+                if end > start {
+                    self.frame_time = Some((end - start) as f32 * self.period * 1e-9);
+                }
+            }
+            Some(Err(e)) => log::error!("Frame timer readback failed: {:#}", e),
+            None => {}
+        }
+    }
+
+    // The latest frame's time that came back, in seconds
+    pub fn frame_time(&self) -> Option<f32> {
+        self.frame_time
+    }
+}
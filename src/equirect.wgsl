@@ -0,0 +1,31 @@
+// Resamples an equirectangular panorama into a cubemap, one face per pass.
+// The panorama's middle is towards -z, in front of the default camera, and
+// its top row straight up.
+
+#include "cubemap.wgsl"
+
+// Matches `EquirectParams` in texture/equirect.rs
+struct EquirectParams {
+    face: u32,
+    // the panorama's mip whose texels are about as big as the face's
+    lod: f32,
+    _padding: vec2<f32>,
+};
+
+@group(0) @binding(0)
+var t_equirect: texture_2d<f32>;
+// wraps around horizontally, so the seam at +z filters across
+@group(0) @binding(1)
+var s_equirect: sampler;
+@group(0) @binding(2)
+var<uniform> params: EquirectParams;
+
+const PI: f32 = 3.14159265;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let direction = cube_face_direction(params.face, in.uv);
+    let uv = vec2<f32>(0.5 + atan2(direction.x, -direction.z) / (2.0 * PI), acos(clamp(direction.y, -1.0, 1.0)) / PI);
+    // an explicit level, the seam's jump in uv would pick the smallest one
+    return vec4<f32>(textureSampleLevel(t_equirect, s_equirect, uv, params.lod).rgb, 1.0);
+}
@@ -0,0 +1,62 @@
+use anyhow::*;
+
+// Left alone, wgpu panics on the first validation error with nothing but
+// its own message. Scopes turn errors into `Result`s that say what was being
+// done, while wgpu's message names the offending resource by its label.
+// Scopes belong to the device rather than the thread in this wgpu, so
+// anything the pipeline compiler's worker gets wrong while one is open is
+// reported under that scope.
+
+// Logs errors no scope caught, instead of panicking
+pub fn log_uncaptured(device: &wgpu::Device) {
+    device.on_uncaptured_error(Box::new(|error| {
+        log::error!("Uncaptured wgpu error: {}", error);
+    }));
+}
+
+/// Catches the validation and out-of-memory errors of everything between
+/// `push` and `pop`. Scopes nest, and each has to be popped.
+#[must_use = "an error scope has to be popped"]
+pub struct ErrorScope<'a> {
+    device: &'a wgpu::Device,
+    label: String,
+}
+
+impl<'a> ErrorScope<'a> {
+    // `label` says what the scope covers, e.g. "Loading worm.gltf"
+    pub fn push(device: &'a wgpu::Device, label: impl Into<String>) -> Self {
+        // the web can't wait for a scope, errors there go to the uncaptured
+        // error handler
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+            device.push_error_scope(wgpu::ErrorFilter::Validation);
+        }
+        Self {
+            device,
+            label: label.into(),
+        }
+    }
+
+    // The first error caught, with the scope's label as context
+    pub fn pop(self) -> Result<()> {
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // native backends have the result ready without polling
+            let validation = pollster::block_on(self.device.pop_error_scope());
+            let out_of_memory = pollster::block_on(self.device.pop_error_scope());
+            if let Some(error) = validation.or(out_of_memory) {
+                return Err(anyhow!("{}", error).context(self.label));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs `f` in an error scope
+pub fn check<T>(device: &wgpu::Device, label: impl Into<String>, f: impl FnOnce() -> T) -> Result<T> {
+    let scope = ErrorScope::push(device, label);
+    let value = f();
+    scope.pop()?;
+    Ok(value)
+}
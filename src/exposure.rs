@@ -0,0 +1,263 @@
+use serde::{Deserialize, Serialize};
+
+use crate::compute::{self, ComputePipeline, StorageBuffer};
+use crate::readback::{self, ReadbackRing};
+use crate::tonemap::Tonemap;
+
+// bins of the log luminance histogram, one per invocation of the averaging
+// workgroup; the first is for black, which the average leaves out
+const HISTOGRAM_BINS: usize = 256;
+// the histogram's workgroups are 16x16 pixels
+const TILE_SIZE: u32 = 16;
+// what the average luminance is exposed to, middle grey
+const KEY: f32 = 0.18;
+
+/// How bright the HDR scene is made before it's tonemapped, as set per
+/// scene. Luminances are in stops, log2 of the linear value.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Exposure {
+    // follows the scene's average luminance when on, otherwise the exposure
+    // is `compensation` alone
+    pub auto: bool,
+    // in stops, on top of the automatic exposure
+    pub compensation: f32,
+    // the histogram's range, luminances beyond it count as its ends
+    pub min_luminance: f32,
+    pub max_luminance: f32,
+    // how quickly the exposure catches up with a brighter or a darker
+    // scene, per second
+    pub speed_up: f32,
+    pub speed_down: f32,
+}
+
+// fixed and unchanged, until a scene or `E` turns it on
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            auto: false,
+            compensation: 0.0,
+            min_luminance: -8.0,
+            max_luminance: 4.0,
+            speed_up: 3.0,
+            speed_down: 1.0,
+        }
+    }
+}
+
+impl Exposure {
+    // What the scene is multiplied by while it's fixed
+    pub fn fixed(&self) -> f32 {
+        self.compensation.exp2()
+    }
+}
+
+// Matches `ExposureUniform` in tonemap.wgsl and auto_exposure.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ExposureUniform {
+    pub exposure: f32,
+    // the adapted average, in stops, that `exposure` exposes for
+    pub luminance: f32,
+    pub _padding: [f32; 2],
+}
+
+impl ExposureUniform {
+    pub fn fixed(exposure: &Exposure) -> Self {
+        Self {
+            exposure: exposure.fixed(),
+            // what auto exposure would expose the same for, so turning it
+            // on starts from where it is
+            luminance: KEY.log2(),
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+// Mirrors `ExposureParams` in auto_exposure.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ExposureParams {
+    min_luminance: f32,
+    luminance_range: f32,
+    // how much of the way to this frame's average to go, towards a brighter
+    // or a darker scene
+    brighten: f32,
+    darken: f32,
+    key: f32,
+    pixel_count: u32,
+    _padding: [u32; 2],
+}
+
+// Automatic exposure from the HDR scene, on the GPU: one pass bins every
+// pixel's log luminance into a histogram, a second averages it without the
+// black pixels, eases the exposure towards it and writes it where
+// `Tonemap` reads it. That's read back a frame or two later, for showing
+// what it adapted to.
+pub struct AutoExposure {
+    histogram_pipeline: ComputePipeline,
+    average_pipeline: ComputePipeline,
+    params_buffer: wgpu::Buffer,
+    histogram: StorageBuffer<u32>,
+    // reads the scene, so it's remade with the tonemap's targets
+    histogram_bind_group: wgpu::BindGroup,
+    average_bind_group: wgpu::BindGroup,
+    width: u32,
+    height: u32,
+    // the exposure on its way back, and the latest that came
+    readback: ReadbackRing,
+    adapted: Option<ExposureUniform>,
+}
+
+impl AutoExposure {
+    pub fn new(device: &wgpu::Device, tonemap: &Tonemap, width: u32, height: u32) -> Self {
+        let scene_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let source = include_str!("auto_exposure.wgsl");
+        let histogram_pipeline = ComputePipeline::new(
+            device,
+            "Luminance Histogram",
+            source,
+            "cs_histogram",
+            &[&[compute::uniform_entry(0), compute::storage_entry(1, false), scene_entry]],
+        );
+        let average_pipeline = ComputePipeline::new(
+            device,
+            "Exposure Average",
+            source,
+            "cs_average",
+            &[&[compute::uniform_entry(0), compute::storage_entry(1, false), compute::storage_entry(3, false)]],
+        );
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Params Buffer"),
+            size: std::mem::size_of::<ExposureParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let histogram = StorageBuffer::zeroed(device, "Luminance Histogram Buffer", HISTOGRAM_BINS);
+        let average_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Exposure Average Bind Group"),
+            layout: average_pipeline.bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: histogram.binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: tonemap.exposure_buffer().as_entire_binding(),
+                },
+            ],
+        });
+        let histogram_bind_group =
+            Self::create_histogram_bind_group(device, &histogram_pipeline, &params_buffer, &histogram, tonemap);
+        Self {
+            histogram_pipeline,
+            average_pipeline,
+            params_buffer,
+            histogram,
+            histogram_bind_group,
+            average_bind_group,
+            width,
+            height,
+            readback: ReadbackRing::buffer(
+                device,
+                "Exposure Readback Buffer",
+                std::mem::size_of::<ExposureUniform>() as wgpu::BufferAddress,
+                readback::FRAMES_IN_FLIGHT,
+            ),
+            adapted: None,
+        }
+    }
+
+    fn create_histogram_bind_group(
+        device: &wgpu::Device,
+        pipeline: &ComputePipeline,
+        params_buffer: &wgpu::Buffer,
+        histogram: &StorageBuffer<u32>,
+        tonemap: &Tonemap,
+    ) -> wgpu::BindGroup {
+        pipeline.create_bind_group(
+            device,
+            0,
+            &[
+                params_buffer.as_entire_binding(),
+                histogram.binding(),
+                wgpu::BindingResource::TextureView(tonemap.view()),
+            ],
+        )
+    }
+
+    // After `Tonemap::resize`
+    pub fn resize(&mut self, device: &wgpu::Device, tonemap: &Tonemap, width: u32, height: u32) {
+        self.histogram_bind_group = Self::create_histogram_bind_group(
+            device,
+            &self.histogram_pipeline,
+            &self.params_buffer,
+            &self.histogram,
+            tonemap,
+        );
+        self.width = width;
+        self.height = height;
+    }
+
+    // Once a frame while it's on, `dt` after the last
+    pub fn update(&self, queue: &wgpu::Queue, exposure: &Exposure, dt: std::time::Duration) {
+        let ease = |speed: f32| 1.0 - (-dt.as_secs_f32() * speed.max(0.0)).exp();
+        let params = ExposureParams {
+            min_luminance: exposure.min_luminance,
+            luminance_range: (exposure.max_luminance - exposure.min_luminance).max(1e-3),
+            brighten: ease(exposure.speed_up),
+            darken: ease(exposure.speed_down),
+            key: KEY * exposure.fixed(),
+            pixel_count: self.width * self.height,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+    }
+
+    // Between the scene being finished and `Tonemap::encode`, reading the
+    // result back unless every buffer for it is still on its way
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder, tonemap: &Tonemap) {
+        let workgroups = (
+            compute::workgroup_count(self.width, TILE_SIZE),
+            compute::workgroup_count(self.height, TILE_SIZE),
+            1,
+        );
+        self.histogram_pipeline.dispatch(encoder, &[&self.histogram_bind_group], workgroups);
+        self.average_pipeline.dispatch(encoder, &[&self.average_bind_group], (1, 1, 1));
+        self.readback.copy_buffer(encoder, tonemap.exposure_buffer(), 0);
+    }
+
+    // Call once the encoder passed to `encode` has been submitted
+    pub fn after_submit(&mut self) {
+        self.readback.map();
+    }
+
+    // Non-blocking, once a frame: takes in an exposure that's come back
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        match self.readback.try_read(device) {
+            Some(Ok(data)) => self.adapted = Some(bytemuck::pod_read_unaligned(&data)),
+            Some(Err(e)) => log::error!("Exposure readback failed: {:#}", e),
+            None => {}
+        }
+    }
+
+    // The latest exposure that came back, None until the first does
+    pub fn adapted(&self) -> Option<ExposureUniform> {
+        self.adapted
+    }
+}
@@ -0,0 +1,19 @@
+// What the host app pushes each frame, from `ExternalUniforms` in
+// external_uniforms.rs. Shaders that want it bind it next to the camera at
+// binding 10:
+//
+//   @group(0) @binding(10)
+//   var<uniform> app: ExternalUniforms;
+struct ExternalUniforms {
+    time: f32,
+    beat: f32,
+    // the app's own values, four to a vec4
+    values: array<vec4<f32>, 2>,
+};
+
+// The app's value `index`, 0 to 7
+fn external_value(app: ExternalUniforms, index: u32) -> f32 {
+    // a local copy, since a value's array can only be indexed by constants
+    var values = app.values;
+    return values[index / 4u][index % 4u];
+}
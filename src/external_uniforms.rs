@@ -0,0 +1,78 @@
+use wgpu::util::DeviceExt;
+
+// How many of the app's own values there are, in `ExternalUniforms::values`
+pub const EXTERNAL_VALUES: usize = 8;
+
+/// A few floats the host app pushes each frame for shaders to read, without
+/// any plumbing of their own: a clock, a beat and values that mean whatever
+/// the app wants, say an audio spectrum's bands. They're bound with the
+/// camera, see external.wgsl. Nothing in the renderer sets them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ExternalUniforms {
+    // in seconds, on whatever clock the app keeps, like a song's position
+    pub time: f32,
+    // say 0 to 1 between one beat and the next, or how loud the last one was
+    pub beat: f32,
+    pub values: [f32; EXTERNAL_VALUES],
+}
+
+// Matches `ExternalUniforms` in external.wgsl, where the values are packed
+// into vec4s since arrays in uniforms go in 16 byte steps
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ExternalUniform {
+    time: f32,
+    beat: f32,
+    _padding: [f32; 2],
+    values: [[f32; 4]; EXTERNAL_VALUES / 4],
+}
+
+impl From<&ExternalUniforms> for ExternalUniform {
+    fn from(uniforms: &ExternalUniforms) -> Self {
+        let mut values = [[0.0; 4]; EXTERNAL_VALUES / 4];
+        for (packed, value) in values.iter_mut().flatten().zip(uniforms.values) {
+            *packed = value;
+        }
+        Self {
+            time: uniforms.time,
+            beat: uniforms.beat,
+            _padding: [0.0; 2],
+            values,
+        }
+    }
+}
+
+// Binding 10 of the camera bind group, written when the values change
+pub struct ExternalBuffer {
+    buffer: wgpu::Buffer,
+    uploaded: ExternalUniforms,
+}
+
+impl ExternalBuffer {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let uploaded = ExternalUniforms::default();
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("External Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[ExternalUniform::from(&uploaded)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        Self { buffer, uploaded }
+    }
+
+    // Once a frame, before anything's drawn
+    pub fn upload(&mut self, queue: &wgpu::Queue, uniforms: &ExternalUniforms) {
+        if *uniforms == self.uploaded {
+            return;
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[ExternalUniform::from(uniforms)]));
+        self.uploaded = *uniforms;
+    }
+
+    // See `camera::layout_entries`
+    pub fn bind_group_entry(&self) -> wgpu::BindGroupEntry<'_> {
+        wgpu::BindGroupEntry {
+            binding: 10,
+            resource: self.buffer.as_entire_binding(),
+        }
+    }
+}
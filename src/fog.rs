@@ -0,0 +1,119 @@
+use serde::{Deserialize, Serialize};
+
+// How fog thickens with distance from the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FogMode {
+    Off,
+    // none before `start`, opaque from `end`
+    Linear,
+    // 1 - e^(-density * distance)
+    Exponential,
+}
+
+impl FogMode {
+    pub fn next(self) -> Self {
+        match self {
+            FogMode::Off => FogMode::Linear,
+            FogMode::Linear => FogMode::Exponential,
+            FogMode::Exponential => FogMode::Off,
+        }
+    }
+}
+
+/// Distance fog, optionally thinning out with height, as set per scene.
+/// Distances are in world units.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Fog {
+    pub mode: FogMode,
+    // linear RGB, also what the background fades to
+    pub color: [f32; 3],
+    // for exponential fog
+    pub density: f32,
+    // for linear fog
+    pub start: f32,
+    pub end: f32,
+    // fog is `e^(-height_falloff * y)` as thick `y` units above `height`, and
+    // the same everywhere when it's 0
+    pub height_falloff: f32,
+    pub height: f32,
+}
+
+impl Fog {
+    // How much of a colour seen `distance` away along a level ray from
+    // `eye_height` is fog, as fog.wgsl works it out per fragment
+    pub fn amount(&self, distance: f32, eye_height: f32) -> f32 {
+        let thickness = (-self.height_falloff * (eye_height - self.height)).exp();
+        match self.mode {
+            FogMode::Off => 0.0,
+            FogMode::Linear => {
+                let linear = ((distance - self.start) / (self.end - self.start).max(1e-4)).clamp(0.0, 1.0);
+                (linear * thickness).min(1.0)
+            }
+            FogMode::Exponential => 1.0 - (-self.density * distance * thickness).exp(),
+        }
+    }
+
+    // The clear colour seen through the fog at the far plane, so the
+    // background matches the geometry fading into it
+    pub fn background(&self, clear_color: wgpu::Color, zfar: f32, eye_height: f32) -> wgpu::Color {
+        let amount = self.amount(zfar, eye_height) as f64;
+        let [r, g, b] = self.color.map(|c| c as f64);
+        wgpu::Color {
+            r: clear_color.r + (r - clear_color.r) * amount,
+            g: clear_color.g + (g - clear_color.g) * amount,
+            b: clear_color.b + (b - clear_color.b) * amount,
+            a: clear_color.a,
+        }
+    }
+}
+
+// a light grey haze, off until a scene or `G` turns it on
+impl Default for Fog {
+    fn default() -> Self {
+        Self {
+            mode: FogMode::Off,
+            color: [0.5, 0.6, 0.7],
+            density: 0.08,
+            start: 5.0,
+            end: 40.0,
+            height_falloff: 0.0,
+            height: 0.0,
+        }
+    }
+}
+
+// Matches `FogUniform` in fog.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    color: [f32; 4],
+    // 0 off, 1 linear, 2 exponential
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+    height_falloff: f32,
+    height: f32,
+    _padding: [u32; 2],
+}
+
+impl From<&Fog> for FogUniform {
+    fn from(fog: &Fog) -> Self {
+        let [r, g, b] = fog.color;
+        Self {
+            color: [r, g, b, 1.0],
+            mode: match fog.mode {
+                FogMode::Off => 0,
+                FogMode::Linear => 1,
+                FogMode::Exponential => 2,
+            },
+            density: fog.density,
+            start: fog.start,
+            end: fog.end,
+            height_falloff: fog.height_falloff,
+            height: fog.height,
+            _padding: [0; 2],
+        }
+    }
+}
@@ -0,0 +1,46 @@
+// Distance and height fog. Matches `FogUniform` in fog.rs, which shaders
+// bind next to the camera.
+struct FogUniform {
+    color: vec4<f32>,
+    // 0 off, 1 linear, 2 exponential
+    mode: u32,
+    density: f32,
+    start: f32,
+    end: f32,
+    height_falloff: f32,
+    height: f32,
+};
+
+// How thick the fog is on average between heights `start` and `end`, as a
+// multiple of how thick it is at `fog.height`
+fn fog_thickness(fog: FogUniform, start: f32, end: f32) -> f32 {
+    let at_start = exp(-fog.height_falloff * (start - fog.height));
+    let rise = fog.height_falloff * (end - start);
+    if abs(rise) < 1e-4 {
+        return at_start;
+    }
+    // e^(-falloff * y) integrated along the ray, over its length
+    return at_start * (1.0 - exp(-rise)) / rise;
+}
+
+// How much of a surface at `position` is hidden in fog, seen from `eye`
+fn fog_amount(fog: FogUniform, eye: vec3<f32>, position: vec3<f32>) -> f32 {
+    let distance = length(position - eye);
+    let thickness = fog_thickness(fog, eye.y, position.y);
+    switch fog.mode {
+        case 1u: {
+            let linear = clamp((distance - fog.start) / max(fog.end - fog.start, 1e-4), 0.0, 1.0);
+            return min(linear * thickness, 1.0);
+        }
+        case 2u: {
+            return 1.0 - exp(-fog.density * distance * thickness);
+        }
+        default: {
+            return 0.0;
+        }
+    }
+}
+
+fn apply_fog(fog: FogUniform, eye: vec3<f32>, position: vec3<f32>, color: vec3<f32>) -> vec3<f32> {
+    return mix(color, fog.color.rgb, fog_amount(fog, eye, position));
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write};
+use std::path::Path;
+
+use anyhow::*;
+use serde::Serialize;
+
+use crate::draw_list::{DrawCall, DrawList};
+
+/// A frame's passes as a graph of what each reads and writes, worked out
+/// from its `DrawList`. The passes are put in order by hand in
+/// `State::render` rather than scheduled from this, and every target is a
+/// texture of its own, so there's no aliasing yet; what the graph shows is
+/// that order, and how long each resource is needed for within the frame.
+#[derive(Debug, Clone, Serialize)]
+pub struct FrameGraph {
+    pub frame: u64,
+    // in the order they were recorded, which is the order they run in but
+    // for the ones recorded on other threads at the same time
+    pub passes: Vec<GraphPass>,
+    pub resources: Vec<GraphResource>,
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphPass {
+    pub label: String,
+    pub draws: usize,
+}
+
+/// Something a pass reads or writes, numbered as in the draw list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum Resource {
+    // a texture view rendered into
+    View(u64),
+    BindGroup(u64),
+    // an indirect draw's arguments or count
+    Buffer(u64),
+    Bundle(u64),
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Resource::View(id) => write!(f, "view {}", id),
+            Resource::BindGroup(id) => write!(f, "bind group {}", id),
+            Resource::Buffer(id) => write!(f, "buffer {}", id),
+            Resource::Bundle(id) => write!(f, "bundle {}", id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphResource {
+    pub resource: Resource,
+    // the first and last passes to use it, by index in `passes`
+    pub first_pass: usize,
+    pub last_pass: usize,
+    // a view its first pass clears, so nothing in it from before the frame
+    // is needed and it only has to live from its first pass to its last
+    pub transient: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GraphEdge {
+    // indices in `passes` and `resources`
+    pub pass: usize,
+    pub resource: usize,
+    pub access: Access,
+}
+
+impl FrameGraph {
+    pub fn new(draws: &DrawList) -> Self {
+        let mut resources: Vec<GraphResource> = Vec::new();
+        let mut indices: BTreeMap<Resource, usize> = BTreeMap::new();
+        let mut edges = Vec::new();
+        for (pass_index, pass) in draws.passes.iter().enumerate() {
+            // what it does to each resource, and whether that starts with
+            // a clear
+            let mut uses: BTreeSet<(Resource, Access)> = BTreeSet::new();
+            let mut cleared = BTreeSet::new();
+            for attachment in &pass.attachments {
+                let view = Resource::View(attachment.view);
+                // a read-only depth attachment is neither cleared nor stored
+                if attachment.store {
+                    uses.insert((view, Access::Write));
+                }
+                if attachment.load {
+                    uses.insert((view, Access::Read));
+                } else {
+                    // cleared, or a resolve target written over
+                    cleared.insert(view);
+                }
+            }
+            for draw in &pass.draws {
+                let bind_groups = draw.bind_groups.iter().flatten();
+                uses.extend(bind_groups.map(|group| (Resource::BindGroup(group.id), Access::Read)));
+                uses.extend(indirect_buffers(&draw.call).map(|buffer| (buffer, Access::Read)));
+                if let DrawCall::ExecuteBundles { bundles } = &draw.call {
+                    uses.extend(bundles.iter().map(|&bundle| (Resource::Bundle(bundle), Access::Read)));
+                }
+            }
+
+            for (resource, access) in uses {
+                let index = *indices.entry(resource).or_insert_with(|| {
+                    resources.push(GraphResource {
+                        resource,
+                        first_pass: pass_index,
+                        last_pass: pass_index,
+                        transient: cleared.contains(&resource),
+                    });
+                    resources.len() - 1
+                });
+                resources[index].last_pass = pass_index;
+                edges.push(GraphEdge {
+                    pass: pass_index,
+                    resource: index,
+                    access,
+                });
+            }
+        }
+
+        Self {
+            frame: draws.frame,
+            passes: draws
+                .passes
+                .iter()
+                .map(|pass| GraphPass {
+                    label: pass.label.clone(),
+                    draws: pass.draws.len(),
+                })
+                .collect(),
+            resources,
+            edges,
+        }
+    }
+
+    // Graphviz, with the passes in a row in the order they ran and the views
+    // and buffers between them; bind groups are only counted, there are too
+    // many to draw, and are all in the JSON
+    pub fn to_dot(&self) -> String {
+        let mut dot = format!("digraph frame_{} {{\n    rankdir=LR;\n    node [fontname=\"Helvetica\"];\n", self.frame);
+        for (index, pass) in self.passes.iter().enumerate() {
+            let bind_groups = self
+                .edges
+                .iter()
+                .filter(|edge| edge.pass == index)
+                .filter(|edge| matches!(self.resources[edge.resource].resource, Resource::BindGroup(_)))
+                .count();
+            let _ = writeln!(
+                dot,
+                "    pass{} [shape=box, style=bold, label=\"{}: {}\\n{} draws, {} bind groups\"];",
+                index,
+                index,
+                escape(&pass.label),
+                pass.draws,
+                bind_groups
+            );
+            if index > 0 {
+                let _ = writeln!(dot, "    pass{} -> pass{} [style=dashed, color=gray, weight=10];", index - 1, index);
+            }
+        }
+        for (index, resource) in self.resources.iter().enumerate() {
+            if matches!(resource.resource, Resource::BindGroup(_)) {
+                continue;
+            }
+            let lifetime = if resource.first_pass == resource.last_pass {
+                format!("pass {}", resource.first_pass)
+            } else {
+                format!("passes {} to {}", resource.first_pass, resource.last_pass)
+            };
+            let _ = writeln!(
+                dot,
+                "    resource{} [shape={}, label=\"{}\\n{}{}\"];",
+                index,
+                if resource.transient { "ellipse" } else { "doubleoctagon" },
+                resource.resource,
+                lifetime,
+                if resource.transient { ", transient" } else { "" }
+            );
+        }
+        for edge in &self.edges {
+            if matches!(self.resources[edge.resource].resource, Resource::BindGroup(_)) {
+                continue;
+            }
+            let _ = match edge.access {
+                Access::Read => writeln!(dot, "    resource{} -> pass{};", edge.resource, edge.pass),
+                Access::Write => writeln!(dot, "    pass{} -> resource{};", edge.pass, edge.resource),
+            };
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn save_dot(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, self.to_dot()).with_context(|| format!("Writing the frame graph to {}", path.display()))
+    }
+
+    pub fn save_json(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        std::fs::write(path, json).with_context(|| format!("Writing the frame graph to {}", path.display()))
+    }
+}
+
+fn indirect_buffers(call: &DrawCall) -> impl Iterator<Item = Resource> {
+    let buffers = match *call {
+        DrawCall::DrawIndirect { buffer, .. }
+        | DrawCall::DrawIndexedIndirect { buffer, .. }
+        | DrawCall::MultiDrawIndirect { buffer, .. }
+        | DrawCall::MultiDrawIndexedIndirect { buffer, .. } => [Some(buffer), None],
+        DrawCall::MultiDrawIndexedIndirectCount { buffer, count_buffer, .. } => [Some(buffer), Some(count_buffer)],
+        DrawCall::Draw { .. } | DrawCall::DrawIndexed { .. } | DrawCall::ExecuteBundles { .. } | DrawCall::Untraced => {
+            [None, None]
+        }
+    };
+    buffers.into_iter().flatten().map(Resource::Buffer)
+}
+
+// For a DOT string in double quotes
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::resources::Tracked;
+
+/// How many frames the CPU records ahead of the GPU at most, and so how many
+/// copies there are of what's written every frame. A `ReadbackRing` keeps as
+/// many readbacks on their way at once.
+pub const FRAMES_IN_FLIGHT: usize = 3;
+
+/// A buffer or texture replaced while a frame in flight may still use it,
+/// kept by `FramesInFlight::retire` until none can.
+pub enum Retired {
+    Buffer(Tracked<wgpu::Buffer>),
+    Texture(Tracked<wgpu::Texture>),
+}
+
+impl From<Tracked<wgpu::Buffer>> for Retired {
+    fn from(buffer: Tracked<wgpu::Buffer>) -> Self {
+        Retired::Buffer(buffer)
+    }
+}
+
+impl From<Tracked<wgpu::Texture>> for Retired {
+    fn from(texture: Tracked<wgpu::Texture>) -> Self {
+        Retired::Texture(texture)
+    }
+}
+
+impl Retired {
+    // Frees the memory now, rather than whenever the last handle to it goes,
+    // and takes it out of the usage totals as it's dropped
+    fn destroy(self) {
+        match self {
+            Retired::Buffer(buffer) => buffer.destroy(),
+            Retired::Texture(texture) => texture.destroy(),
+        }
+    }
+}
+
+/// Keeps the CPU no more than `FRAMES_IN_FLIGHT` frames ahead of the GPU.
+/// Each frame gets a slot for its copy of what's written every frame, which
+/// the GPU is done with by the time the slot comes round again, and what's
+/// replaced along the way is destroyed once no frame in flight can use it.
+///
+/// `begin_frame` before recording a frame, `submitted` with each of its
+/// submissions and `end_frame` after the last.
+pub struct FramesInFlight {
+    // the frame being recorded, or the next one between frames
+    frame: u64,
+    // how many frames the GPU has finished, counted up by its callbacks
+    completed: Arc<AtomicU64>,
+    // the last submission of the frame that last had each slot
+    submissions: Vec<Option<wgpu::SubmissionIndex>>,
+    // with the frame they were retired in, oldest first
+    retired: VecDeque<(u64, Retired)>,
+}
+
+impl Default for FramesInFlight {
+    fn default() -> Self {
+        Self {
+            frame: 0,
+            completed: Arc::new(AtomicU64::new(0)),
+            submissions: vec![None; FRAMES_IN_FLIGHT],
+            retired: VecDeque::new(),
+        }
+    }
+}
+
+impl FramesInFlight {
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Which copy of what's written every frame this frame writes and draws
+    // with
+    pub fn slot(&self) -> usize {
+        (self.frame % FRAMES_IN_FLIGHT as u64) as usize
+    }
+
+    fn completed(&self) -> u64 {
+        self.completed.load(Ordering::Acquire)
+    }
+
+    // Frames submitted that the GPU hasn't finished yet
+    pub fn in_flight(&self) -> u64 {
+        self.frame.saturating_sub(self.completed())
+    }
+
+    // Resources waiting on frames in flight to be destroyed
+    pub fn retiring(&self) -> usize {
+        self.retired.len()
+    }
+
+    // Waits for the GPU to finish the frame that last had this one's slot,
+    // if it hasn't, then destroys what no frame in flight uses any more
+    pub fn begin_frame(&mut self, device: &wgpu::Device) {
+        // for the callbacks of frames that have finished since the last one
+        device.poll(wgpu::Maintain::Poll);
+        let slot = self.slot();
+        if let Some(submission) = self.submissions[slot].take() {
+            if self.completed() + FRAMES_IN_FLIGHT as u64 <= self.frame {
+                profiling::scope!("wait for frame");
+                // only blocks natively, browsers don't let frames pile up
+                device.poll(wgpu::Maintain::WaitForSubmissionIndex(submission));
+            }
+        }
+        let completed = self.completed();
+        let done = self.retired.iter().take_while(|(frame, _)| *frame < completed).count();
+        self.retired.drain(..done).for_each(|(_, resource)| resource.destroy());
+    }
+
+    // The index `Queue::submit` returned, for each of the frame's
+    // submissions
+    pub fn submitted(&mut self, submission: wgpu::SubmissionIndex) {
+        let slot = self.slot();
+        self.submissions[slot] = Some(submission);
+    }
+
+    // After the frame's last submission
+    pub fn end_frame(&mut self, queue: &wgpu::Queue) {
+        let completed = self.completed.clone();
+        let frames = self.frame + 1;
+        queue.on_submitted_work_done(move || {
+            completed.fetch_max(frames, Ordering::AcqRel);
+        });
+        self.frame = frames;
+    }
+
+    // Keeps `resource` until the GPU has finished this frame and every one
+    // before it, for what's replaced while they may still use it
+    pub fn retire(&mut self, resource: impl Into<Retired>) {
+        self.retired.push_back((self.frame, resource.into()));
+    }
+}
+
+// e.g. "2 of 3, 1 resource retiring"
+impl fmt::Display for FramesInFlight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {}", self.in_flight(), FRAMES_IN_FLIGHT)?;
+        match self.retiring() {
+            0 => Ok(()),
+            1 => write!(f, ", 1 resource retiring"),
+            count => write!(f, ", {} resources retiring", count),
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// The entry point of fullscreen.wgsl's vertex stage, for shaders that
+// `#include` it
+pub const VERTEX_ENTRY: &str = "vs_fullscreen";
+
+// A pass that shades every pixel of one `format` target once, with
+// `module`'s `fs_main`: no vertex buffers and no depth, drawn with
+// `draw(0..3, 0..1)`
+pub fn fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    fullscreen_pipeline_with(device, label, layout, module, "fs_main", &[Some(format.into())])
+}
+
+// The same with another fragment entry point, and targets that blend or
+// are more than one
+pub fn fullscreen_pipeline_with(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    fs_entry: &str,
+    targets: &[Option<wgpu::ColorTargetState>],
+) -> wgpu::RenderPipeline {
+    fullscreen_pipeline_sampled(device, label, layout, module, fs_entry, targets, Default::default())
+}
+
+// The same into multisampled targets
+pub fn fullscreen_pipeline_sampled(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    fs_entry: &str,
+    targets: &[Option<wgpu::ColorTargetState>],
+    multisample: wgpu::MultisampleState,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: VERTEX_ENTRY,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: fs_entry,
+            targets,
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample,
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,19 @@
+// The vertex stage of passes that shade each pixel of their target once,
+// see fullscreen.rs: one triangle from `draw(0..3, 0..1)` with no vertex
+// buffers. `uv` runs from 0 to 1 across the target, y down like texture
+// coordinates.
+
+struct FullscreenOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> FullscreenOutput {
+    // (0, 0), (2, 0), (0, 2): one triangle that covers the whole target
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: FullscreenOutput;
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
@@ -0,0 +1,143 @@
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::texture::SamplerDesc;
+
+// Fast approximate anti-aliasing as a post-process, see fxaa.wgsl. The 3D
+// scene is tonemapped into `view` instead of the frame, and `encode` filters
+// it into the frame. It smooths every edge the shaders produce, including
+// alpha cut-outs and shading, at the cost of a slightly softer image, and
+// needs no multisampled targets.
+pub struct Fxaa {
+    color_format: wgpu::TextureFormat,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    view: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Fxaa {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        let shader = shader_preprocessor::create_module(device, "FXAA Shader", "fxaa.wgsl", &ShaderDefs::new());
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("FXAA Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("FXAA Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(device, "FXAA Pipeline", &pipeline_layout, &shader, color_format);
+        // bilinear taps between texels, and nothing wraps around the edges
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("FXAA Sampler"));
+
+        let (view, bind_group) = Self::create_frame(device, &layout, &sampler, color_format, width, height);
+        Self {
+            color_format,
+            layout,
+            pipeline,
+            sampler,
+            view,
+            bind_group,
+        }
+    }
+
+    fn create_frame(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("FXAA Frame"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = Tracked::view(
+            ResourceKind::Target,
+            &texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("FXAA Frame View"),
+                ..Default::default()
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("FXAA Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        (view, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.view, self.bind_group) =
+            Self::create_frame(device, &self.layout, &self.sampler, self.color_format, width, height);
+    }
+
+    // Where the scene gets tonemapped instead of the frame
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // Filters the scene into `target`, which has the format `new` was given
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("FXAA Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,71 @@
+// Fast approximate anti-aliasing of the finished frame: finds edges from the
+// luma of each pixel's neighbours and blurs along them, after Timothy
+// Lottes' FXAA
+
+#include "fullscreen.wgsl"
+
+// same size as the target, sampled between texels along edges
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var frame_sampler: sampler;
+
+// how little of a contrast still counts as an edge, relative to the
+// brightest neighbour, and below that in absolute terms
+const EDGE_THRESHOLD: f32 = 0.125;
+const EDGE_THRESHOLD_MIN: f32 = 0.0312;
+// the blur reaches at most this many pixels along an edge
+const SPAN_MAX: f32 = 8.0;
+const REDUCE_MUL: f32 = 0.125;
+const REDUCE_MIN: f32 = 0.0078125;
+
+// Perceived brightness. The frame is linear, and edges look as strong as their
+// difference after gamma encoding, which the square root is close enough to.
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(sqrt(max(color, vec3<f32>(0.0))), vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(frame));
+    let uv = in.position.xy * texel;
+
+    let center = textureSampleLevel(frame, frame_sampler, uv, 0.0);
+    let luma_m = luma(center.rgb);
+    let luma_nw = luma(textureSampleLevel(frame, frame_sampler, uv + vec2<f32>(-1.0, -1.0) * texel, 0.0).rgb);
+    let luma_ne = luma(textureSampleLevel(frame, frame_sampler, uv + vec2<f32>(1.0, -1.0) * texel, 0.0).rgb);
+    let luma_sw = luma(textureSampleLevel(frame, frame_sampler, uv + vec2<f32>(-1.0, 1.0) * texel, 0.0).rgb);
+    let luma_se = luma(textureSampleLevel(frame, frame_sampler, uv + vec2<f32>(1.0, 1.0) * texel, 0.0).rgb);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    // flat areas are left alone, which is most of the frame
+    if luma_max - luma_min < max(EDGE_THRESHOLD_MIN, luma_max * EDGE_THRESHOLD) {
+        return center;
+    }
+
+    // across the luma gradient is along the edge
+    var dir = vec2<f32>(
+        (luma_sw + luma_se) - (luma_nw + luma_ne),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * REDUCE_MUL, REDUCE_MIN);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2<f32>(-SPAN_MAX), vec2<f32>(SPAN_MAX)) * texel;
+
+    // two taps close to the pixel, and two more farther out along the edge
+    let near = 0.5 * (
+        textureSampleLevel(frame, frame_sampler, uv + dir * (1.0 / 3.0 - 0.5), 0.0) +
+        textureSampleLevel(frame, frame_sampler, uv + dir * (2.0 / 3.0 - 0.5), 0.0)
+    );
+    let far = near * 0.5 + 0.25 * (
+        textureSampleLevel(frame, frame_sampler, uv + dir * -0.5, 0.0) +
+        textureSampleLevel(frame, frame_sampler, uv + dir * 0.5, 0.0)
+    );
+    // the wider blur overshot if it left the neighbourhood's luma range
+    let luma_far = luma(far.rgb);
+    if luma_far < luma_min || luma_far > luma_max {
+        return near;
+    }
+    return far;
+}
@@ -0,0 +1,162 @@
+use cgmath::{Vector2, Vector3, Zero};
+use gilrs::{Axis, Button, EventType, GamepadId, Gilrs};
+
+// sticks rarely rest at exactly zero
+const STICK_DEADZONE: f32 = 0.15;
+
+/// Snapshot of the active gamepad, taken once per frame.
+#[derive(Debug, Clone)]
+pub struct GamepadState {
+    pub connected: bool,
+    pub left_stick: Vector2<f32>,
+    pub right_stick: Vector2<f32>,
+    pub left_trigger: f32,
+    pub right_trigger: f32,
+    // buttons held down right now
+    pub buttons_down: Vec<Button>,
+    // buttons that went down since the last poll
+    pub buttons_pressed: Vec<Button>,
+}
+
+impl Default for GamepadState {
+    fn default() -> Self {
+        Self {
+            connected: false,
+            left_stick: Vector2::zero(),
+            right_stick: Vector2::zero(),
+            left_trigger: 0.0,
+            right_trigger: 0.0,
+            buttons_down: Vec::new(),
+            buttons_pressed: Vec::new(),
+        }
+    }
+}
+
+impl GamepadState {
+    pub fn is_down(&self, button: Button) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn was_pressed(&self, button: Button) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    // Camera controller mapping: left stick moves, right stick looks,
+    // triggers move up and down
+    pub fn camera_axes(&self) -> (Vector3<f32>, Vector2<f32>) {
+        let movement = Vector3::new(
+            self.left_stick.x,
+            self.right_trigger - self.left_trigger,
+            self.left_stick.y,
+        );
+        (movement, self.right_stick)
+    }
+}
+
+pub struct GamepadInput {
+    gilrs: Gilrs,
+    // the pad that sent the most recent event drives the input
+    active: Option<GamepadId>,
+}
+
+impl GamepadInput {
+    // Returns None (and logs) if the platform backend can't be initialised,
+    // so running without gamepad support is never fatal
+    pub fn new() -> Option<Self> {
+        match Gilrs::new() {
+            Ok(gilrs) => {
+                let active = gilrs.gamepads().next().map(|(id, gamepad)| {
+                    log::info!("Using gamepad {}", gamepad.name());
+                    id
+                });
+                Some(Self { gilrs, active })
+            }
+            Err(e) => {
+                log::warn!("Gamepad support unavailable: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn poll(&mut self) -> GamepadState {
+        let mut buttons_pressed = Vec::new();
+
+        // drain all events since last frame, gilrs updates its cached state as we go
+        while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+            match event {
+                EventType::Connected => {
+                    log::info!("Gamepad connected: {}", self.gilrs.gamepad(id).name());
+                    self.active.get_or_insert(id);
+                }
+                EventType::Disconnected => {
+                    log::info!("Gamepad disconnected: {}", self.gilrs.gamepad(id).name());
+                    if self.active == Some(id) {
+                        self.active = self.gilrs.gamepads().next().map(|(id, _)| id);
+                    }
+                }
+                EventType::ButtonPressed(button, _) => {
+                    self.active = Some(id);
+                    buttons_pressed.push(button);
+                }
+                EventType::AxisChanged(..) => self.active = Some(id),
+                _ => {}
+            }
+        }
+
+        let Some(gamepad) = self.active.map(|id| self.gilrs.gamepad(id)) else {
+            return GamepadState::default();
+        };
+
+        const BUTTONS: [Button; 16] = [
+            Button::South,
+            Button::East,
+            Button::North,
+            Button::West,
+            Button::LeftTrigger,
+            Button::LeftTrigger2,
+            Button::RightTrigger,
+            Button::RightTrigger2,
+            Button::Select,
+            Button::Start,
+            Button::LeftThumb,
+            Button::RightThumb,
+            Button::DPadUp,
+            Button::DPadDown,
+            Button::DPadLeft,
+            Button::DPadRight,
+        ];
+
+        GamepadState {
+            connected: gamepad.is_connected(),
+            left_stick: deadzone(Vector2::new(
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            )),
+            right_stick: deadzone(Vector2::new(
+                gamepad.value(Axis::RightStickX),
+                gamepad.value(Axis::RightStickY),
+            )),
+            left_trigger: trigger_value(&gamepad, Button::LeftTrigger2),
+            right_trigger: trigger_value(&gamepad, Button::RightTrigger2),
+            buttons_down: BUTTONS.into_iter().filter(|b| gamepad.is_pressed(*b)).collect(),
+            buttons_pressed,
+        }
+    }
+}
+
+// Radial deadzone, rescaled so output still covers the full [0, 1] range
+fn deadzone(stick: Vector2<f32>) -> Vector2<f32> {
+    let length = (stick.x * stick.x + stick.y * stick.y).sqrt();
+    if length < STICK_DEADZONE {
+        return Vector2::zero();
+    }
+    let scale = ((length - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)).min(1.0) / length;
+    stick * scale
+}
+
+// Analog triggers report through button data on most mappings
+fn trigger_value(gamepad: &gilrs::Gamepad, button: Button) -> f32 {
+    gamepad
+        .button_data(button)
+        .map_or(0.0, |data| data.value())
+}
@@ -0,0 +1,183 @@
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+// Every shader writes linear colour and relies on an sRGB target to encode
+// it. Surfaces without an sRGB format get one as a view of their linear
+// format if the backend allows that, otherwise frames are rendered offscreen
+// and `GammaBlit` encodes them on the way to the surface.
+
+/// How frames reach the surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SurfaceEncoding {
+    // the surface is sRGB, or floating point, where linear output is right
+    Direct,
+    // a linear surface rendered to through its sRGB view format
+    SrgbView,
+    // a linear surface that can't be viewed as sRGB, see `GammaBlit`
+    Blit,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceFormats {
+    // what the surface is configured with
+    pub surface: wgpu::TextureFormat,
+    // what every pipeline renders to
+    pub render: wgpu::TextureFormat,
+    pub encoding: SurfaceEncoding,
+}
+
+impl SurfaceFormats {
+    pub fn choose(caps: &wgpu::SurfaceCapabilities, downlevel: &wgpu::DownlevelCapabilities) -> Self {
+        if let Some(&format) = caps.formats.iter().find(|format| format.is_srgb()) {
+            return Self::direct(format);
+        }
+        let surface = caps.formats[0];
+        if matches!(surface, wgpu::TextureFormat::Rgba16Float | wgpu::TextureFormat::Rgba32Float) {
+            return Self::direct(surface);
+        }
+        let srgb = surface.add_srgb_suffix();
+        if srgb != surface && downlevel.flags.contains(wgpu::DownlevelFlags::SURFACE_VIEW_FORMATS) {
+            return Self {
+                surface,
+                render: srgb,
+                encoding: SurfaceEncoding::SrgbView,
+            };
+        }
+        Self {
+            surface,
+            render: wgpu::TextureFormat::Rgba8UnormSrgb,
+            encoding: SurfaceEncoding::Blit,
+        }
+    }
+
+    fn direct(format: wgpu::TextureFormat) -> Self {
+        Self {
+            surface: format,
+            render: format,
+            encoding: SurfaceEncoding::Direct,
+        }
+    }
+
+    // For `SurfaceConfiguration::view_formats`
+    pub fn view_formats(&self) -> Vec<wgpu::TextureFormat> {
+        match self.encoding {
+            SurfaceEncoding::SrgbView => vec![self.render],
+            _ => Vec::new(),
+        }
+    }
+}
+
+// Offscreen sRGB frame plus the pass that copies it to a linear surface with
+// the gamma curve applied in the shader
+pub struct GammaBlit {
+    render_format: wgpu::TextureFormat,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    view: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GammaBlit {
+    pub fn new(device: &wgpu::Device, formats: &SurfaceFormats, width: u32, height: u32) -> Self {
+        let shader = shader_preprocessor::create_module(device, "Gamma Blit Shader", "gamma.wgsl", &ShaderDefs::new());
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Gamma Blit Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Gamma Blit Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(device, "Gamma Blit Pipeline", &pipeline_layout, &shader, formats.surface);
+
+        let (view, bind_group) = Self::create_frame(device, &layout, formats.render, width, height);
+        Self {
+            render_format: formats.render,
+            layout,
+            pipeline,
+            view,
+            bind_group,
+        }
+    }
+
+    fn create_frame(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Gamma Blit Frame"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = Tracked::view(
+            ResourceKind::Target,
+            &texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("Gamma Blit Frame View"),
+                ..Default::default()
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Gamma Blit Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&view),
+            }],
+        });
+        (view, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.view, self.bind_group) = Self::create_frame(device, &self.layout, self.render_format, width, height);
+    }
+
+    // Where the frame gets rendered instead of the surface
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // Copies the frame to `target`, a view of the surface texture
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Gamma Blit Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,22 @@
+// Copies the sRGB render target to a linear surface, encoding the gamma
+// curve the surface format doesn't apply itself
+
+#include "fullscreen.wgsl"
+
+// same size as the surface, loaded texel for texel
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+
+// the exact piecewise sRGB curve, not a 2.2 power
+fn srgb_encode(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // reading the sRGB texture decodes it, so this is linear
+    let color = textureLoad(frame, vec2<i32>(in.position.xy), 0);
+    return vec4<f32>(srgb_encode(color.rgb), color.a);
+}
@@ -0,0 +1,17 @@
+// The G-buffer screen-space reflections trace against, see ssr.rs. Opaque
+// surfaces write `GBufferOutput` to the targets `ScreenSpaceReflections`
+// sets up, over its depth buffer.
+
+struct GBufferOutput {
+    // world space normal, and the roughness in w
+    @location(0) normal_roughness: vec4<f32>,
+    // f0, the reflectance head on
+    @location(1) specular: vec4<f32>,
+};
+
+fn gbuffer_output(n: vec3<f32>, roughness: f32, f0: vec3<f32>) -> GBufferOutput {
+    var out: GBufferOutput;
+    out.normal_roughness = vec4<f32>(n, roughness);
+    out.specular = vec4<f32>(f0, 1.0);
+    return out;
+}
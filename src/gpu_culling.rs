@@ -0,0 +1,267 @@
+use cgmath::{Matrix4, SquareMatrix};
+use wgpu::util::{DeviceExt, RenderEncoder};
+
+use crate::bounds::{Frustum, Sphere};
+use crate::compute::{self, ComputePipeline, StorageBuffer};
+use crate::culling::CullCounts;
+use crate::hiz::DepthPyramid;
+use crate::instance::InstanceRaw;
+use crate::readback::{self, ReadbackRing};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Mirrors `CullParams` in culling.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullParams {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    occluder_view_proj: [[f32; 4]; 4],
+    instance_count: u32,
+    occluder_levels: u32,
+    occluder_size: [u32; 2],
+}
+
+// `CullArgs` in culling.wgsl: the indirect draw's arguments, then the
+// occluded count
+const ARGS_SIZE: wgpu::BufferAddress = 24;
+
+/// What the GPU culled in a frame, read back a frame or two later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GpuCullStats {
+    pub instances: CullCounts,
+    // of the culled, those the frustum kept and the depth pyramid hid
+    pub occluded: u32,
+}
+
+// Frustum culls an instance buffer on the GPU, and occlusion culls it too
+// when it's given a depth pyramid. Survivors are compacted into a second
+// instance buffer and the instance count lands in an indirect draw argument
+// buffer, so the CPU never needs to know how many are visible.
+pub struct GpuCuller {
+    pipeline: ComputePipeline,
+    bind_group: wgpu::BindGroup,
+    pyramid_bind_group: wgpu::BindGroup,
+    params_buffer: wgpu::Buffer,
+    visible: StorageBuffer<InstanceRaw>,
+    args_buffer: wgpu::Buffer,
+    bounds: Sphere,
+    instance_count: u32,
+    index_count: u32,
+    // the arguments on their way back, and the latest that came
+    readback: ReadbackRing,
+    stats: Option<GpuCullStats>,
+}
+
+impl GpuCuller {
+    // `instances` needs STORAGE usage, `bounds` is the mesh bounding sphere
+    // in model space and `index_count` the mesh's index count. `pyramid` is
+    // what `update` can occlusion cull against.
+    pub fn new(
+        device: &wgpu::Device,
+        instances: &StorageBuffer<InstanceRaw>,
+        bounds: Sphere,
+        index_count: u32,
+        pyramid: &DepthPyramid,
+    ) -> Self {
+        let pipeline = ComputePipeline::new(
+            device,
+            "Frustum Culling",
+            include_str!("culling.wgsl"),
+            "cs_main",
+            &[
+                &[
+                    compute::uniform_entry(0),
+                    compute::storage_entry(1, true),
+                    compute::storage_entry(2, false),
+                    compute::storage_entry(3, false),
+                ],
+                &[compute::storage_entry(0, true)],
+            ],
+        );
+
+        let params_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Params Buffer"),
+            size: std::mem::size_of::<CullParams>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let visible = StorageBuffer::zeroed_with_usage(
+            device,
+            "Visible Instance Buffer",
+            instances.len(),
+            wgpu::BufferUsages::VERTEX,
+        );
+        let args_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Indirect Draw Buffer"),
+            contents: &Self::reset_args(index_count),
+            // COPY_SRC so the counts can be read back for stats
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let bind_group = pipeline.create_bind_group(
+            device,
+            0,
+            &[
+                params_buffer.as_entire_binding(),
+                instances.binding(),
+                visible.binding(),
+                args_buffer.as_entire_binding(),
+            ],
+        );
+        let pyramid_bind_group = Self::create_pyramid_bind_group(device, &pipeline, pyramid);
+
+        Self {
+            pipeline,
+            bind_group,
+            pyramid_bind_group,
+            params_buffer,
+            visible,
+            args_buffer,
+            bounds,
+            instance_count: instances.len() as u32,
+            index_count,
+            readback: ReadbackRing::buffer(device, "Cull Readback Buffer", ARGS_SIZE, readback::FRAMES_IN_FLIGHT),
+            stats: None,
+        }
+    }
+
+    fn create_pyramid_bind_group(
+        device: &wgpu::Device,
+        pipeline: &ComputePipeline,
+        pyramid: &DepthPyramid,
+    ) -> wgpu::BindGroup {
+        pipeline.create_bind_group(device, 1, &[pyramid.binding()])
+    }
+
+    // For a pyramid that's been resized
+    pub fn set_pyramid(&mut self, device: &wgpu::Device, pyramid: &DepthPyramid) {
+        self.pyramid_bind_group = Self::create_pyramid_bind_group(device, &self.pipeline, pyramid);
+    }
+
+    fn reset_args(index_count: u32) -> Vec<u8> {
+        let draw = wgpu::util::DrawIndexedIndirectArgs {
+            index_count,
+            instance_count: 0,
+            first_index: 0,
+            base_vertex: 0,
+            first_instance: 0,
+        };
+        [draw.as_bytes(), &0u32.to_ne_bytes()].concat()
+    }
+
+    // Uploads this frame's frustum and zeroes the counts. With
+    // `occluders`, the pyramid `new` or `set_pyramid` was given, it culls
+    // what was hidden in its depth too, unless that's out of date.
+    pub fn update(&self, queue: &wgpu::Queue, view_proj: &Matrix4<f32>, occluders: Option<&DepthPyramid>) {
+        let frustum = Frustum::from_view_proj(view_proj);
+        let c = self.bounds.center;
+        let occluders = occluders.and_then(|pyramid| Some((pyramid, pyramid.view_proj()?)));
+        let (occluder_view_proj, occluder_levels, occluder_size) = match occluders {
+            Some((pyramid, view_proj)) => (view_proj, pyramid.level_count(), pyramid.size()),
+            None => (Matrix4::identity(), 0, [0; 2]),
+        };
+        let params = CullParams {
+            planes: frustum.planes.map(Into::into),
+            sphere: [c.x, c.y, c.z, self.bounds.radius],
+            occluder_view_proj: occluder_view_proj.into(),
+            instance_count: self.instance_count,
+            occluder_levels,
+            occluder_size,
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[params]));
+        queue.write_buffer(&self.args_buffer, 0, &Self::reset_args(self.index_count));
+    }
+
+    // Records the culling dispatch, must come before the pass that draws
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let workgroups = compute::workgroup_count(self.instance_count, WORKGROUP_SIZE);
+        self.pipeline
+            .dispatch(encoder, &[&self.bind_group, &self.pyramid_bind_group], (workgroups, 1, 1));
+    }
+
+    // Records reading back the counts of the last `cull`, for `poll` to pick
+    // up a frame or two later; skipped while every buffer's on its way
+    pub fn copy_stats(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.readback.copy_buffer(encoder, &self.args_buffer, 0);
+    }
+
+    // Call once the encoder passed to `copy_stats` has been submitted
+    pub fn after_submit(&mut self) {
+        self.readback.map();
+    }
+
+    // Non-blocking, once a frame: takes in counts that have come back
+    pub fn poll(&mut self, device: &wgpu::Device) {
+        match self.readback.try_read(device) {
+            Some(Ok(data)) => {
+                let words: [u32; 6] = bytemuck::pod_read_unaligned(&data);
+                let drawn = words[1];
+                self.stats = Some(GpuCullStats {
+                    instances: CullCounts {
+                        drawn,
+                        culled: self.instance_count.saturating_sub(drawn),
+                    },
+                    occluded: words[5],
+                });
+            }
+            Some(Err(e)) => log::error!("Culling stats readback failed: {:#}", e),
+            None => {}
+        }
+    }
+
+    // The latest counts that came back, None until the first does
+    pub fn stats(&self) -> Option<GpuCullStats> {
+        self.stats
+    }
+
+    // Compacted instances, bind as the instance vertex buffer
+    pub fn visible_instances(&self) -> &wgpu::Buffer {
+        self.visible.buffer()
+    }
+
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.args_buffer
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut impl RenderEncoder<'a>, slot: u32) {
+        self.culled_draw().draw(render_pass, slot)
+    }
+
+    // The draw on its own, without the readback, for passes recorded on
+    // other threads
+    pub fn culled_draw(&self) -> CulledDraw<'_> {
+        CulledDraw {
+            visible: self.visible.buffer(),
+            args: &self.args_buffer,
+        }
+    }
+}
+
+/// The indirect draw of what a `GpuCuller` left, see `GpuCuller::culled_draw`
+#[derive(Clone, Copy)]
+pub struct CulledDraw<'a> {
+    visible: &'a wgpu::Buffer,
+    args: &'a wgpu::Buffer,
+}
+
+impl<'a> CulledDraw<'a> {
+    // Binds the compacted instances to `slot` and issues the indirect draw.
+    // The mesh vertex and index buffers must already be set. Records the
+    // same every frame, so it can go in a render bundle.
+    pub fn draw(self, render_pass: &mut impl RenderEncoder<'a>, slot: u32) {
+        render_pass.set_vertex_buffer(slot, self.visible.slice(..));
+        render_pass.draw_indexed_indirect(self.args, 0);
+    }
+
+    pub fn visible_instances(self) -> &'a wgpu::Buffer {
+        self.visible
+    }
+
+    pub fn indirect_buffer(self) -> &'a wgpu::Buffer {
+        self.args
+    }
+}
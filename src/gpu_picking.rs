@@ -0,0 +1,270 @@
+use crate::draw_list::TracedPass;
+use crate::readback::{self, ReadbackRing};
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::upload::DynamicUniforms;
+
+/// Cleared into the ID target, read back when the cursor is over nothing.
+pub const NO_OBJECT: u32 = u32::MAX;
+
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+
+// WebGL wants uniform bindings of at least 16 bytes
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ObjectUniform {
+    id: u32,
+    _padding: [u32; 3],
+}
+
+/// An object's slot in the picker's ID uniforms, bound with `offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct ObjectId {
+    pub id: u32,
+    pub offset: u32,
+}
+
+// Renders object IDs into an R32Uint target and reads back the pixel under
+// the cursor. The result shows up a frame (or more) later, never stalling,
+// and there can be a pick a frame on its way.
+pub struct GpuPicker {
+    pipeline: wgpu::RenderPipeline,
+    objects: DynamicUniforms<ObjectUniform>,
+    id_texture: Tracked<wgpu::Texture>,
+    id_view: wgpu::TextureView,
+    depth_view: Tracked<wgpu::TextureView>,
+    pending: Option<(u32, u32)>,
+    // the picked pixels on their way back
+    readback: ReadbackRing,
+}
+
+impl GpuPicker {
+    pub fn new(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let shader = shader_preprocessor::create_module(device, "ID Shader", "id.wgsl", &ShaderDefs::new());
+
+        // one slot per object, bound at the object's offset
+        let objects = DynamicUniforms::new(device, "Object ID", wgpu::ShaderStages::VERTEX, 16);
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ID Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, objects.layout()],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ID Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: vertex_layouts,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                // integer targets can't be blended
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: ID_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: Default::default(),
+                bias: Default::default(),
+            }),
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let (id_texture, id_view, depth_view) = Self::create_targets(device, width, height);
+        let pixel = wgpu::Extent3d {
+            width: 1,
+            height: 1,
+            depth_or_array_layers: 1,
+        };
+        // R32Uint is always copyable
+        let readback = ReadbackRing::texture(
+            device,
+            "Pick Readback Buffer",
+            ID_FORMAT,
+            wgpu::TextureAspect::All,
+            pixel,
+            readback::FRAMES_IN_FLIGHT,
+        )
+        .unwrap();
+
+        Self {
+            pipeline,
+            objects,
+            id_texture,
+            id_view,
+            depth_view,
+            pending: None,
+            readback,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::Texture>, wgpu::TextureView, Tracked<wgpu::TextureView>) {
+        let size = wgpu::Extent3d {
+            width: width.max(1),
+            height: height.max(1),
+            depth_or_array_layers: 1,
+        };
+        let id_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ID Target"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ID_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("ID Depth Target"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let id_view = id_texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("ID Target View"),
+            ..Default::default()
+        });
+        let depth_view = Tracked::view(
+            ResourceKind::Target,
+            &depth_texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("ID Depth Target View"),
+                ..Default::default()
+            },
+        );
+        (Tracked::texture(ResourceKind::Target, id_texture), id_view, depth_view)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let (id_texture, id_view, depth_view) = Self::create_targets(device, width, height);
+        self.id_texture = id_texture;
+        self.id_view = id_view;
+        self.depth_view = depth_view;
+    }
+
+    // IDs don't change, so each one is written once when it's created
+    pub fn create_object_id(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, id: u32) -> ObjectId {
+        let offset = self.objects.push(&ObjectUniform { id, _padding: [0; 3] });
+        self.objects.write(device, queue);
+        ObjectId { id, offset }
+    }
+
+    // Queues a pick at a pixel, replacing any request not yet recorded
+    pub fn request(&mut self, x: u32, y: u32) {
+        self.pending = Some((x, y));
+    }
+
+    // Records the ID pass and pixel copy if a pick is pending and there's a
+    // buffer free for the pixel. `draw` issues the draws with bind group 0 (camera) and
+    // 1 (the object ID bind group it's given, at an `ObjectId::offset`) set
+    // by the caller; the pipeline is already bound.
+    // The written ID is the object ID plus the instance index.
+    pub fn encode<F>(&mut self, encoder: &mut wgpu::CommandEncoder, draw: F)
+    where
+        F: FnOnce(&mut TracedPass, &wgpu::BindGroup),
+    {
+        if !self.readback.ready() {
+            return;
+        }
+        let Some((x, y)) = self.pending.take() else {
+            return;
+        };
+        let size = self.id_texture.size();
+        if x >= size.width || y >= size.height {
+            return;
+        }
+
+        let mut render_pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("ID Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.id_view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: NO_OBJECT as f64,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.0,
+                    }),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        draw(&mut render_pass, self.objects.bind_group());
+        drop(render_pass);
+
+        let pixel = wgpu::ImageCopyTexture {
+            texture: &self.id_texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d { x, y, z: 0 },
+            aspect: wgpu::TextureAspect::All,
+        };
+        self.readback.copy_texture(encoder, pixel);
+    }
+
+    // Call once the encoder passed to `encode` has been submitted
+    pub fn after_submit(&mut self) {
+        self.readback.map();
+    }
+
+    // Non-blocking: returns Some once a readback has completed, holding the
+    // picked ID or None if the pixel was empty. Of picks that come back
+    // together, the latest wins.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<Option<u32>> {
+        let result = self.readback.try_read(device)?;
+        let data = match result {
+            Ok(data) => data,
+            Err(e) => {
+                log::error!("ID readback failed: {:#}", e);
+                return None;
+            }
+        };
+        let id = u32::from_ne_bytes([data[0], data[1], data[2], data[3]]);
+        Some((id != NO_OBJECT).then_some(id))
+    }
+}
@@ -0,0 +1,338 @@
+use cgmath::*;
+
+use crate::camera::{self, CameraUniform};
+use crate::depth_prepass;
+use crate::draw_list::TracedPass;
+use crate::fullscreen;
+use crate::msaa::SampledPipeline;
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::shader_reflection::ShaderReflection;
+
+// across the corner the axis gizmo's drawn in, and from the window's
+// edges, in logical pixels
+const GIZMO_SIZE: f32 = 96.0;
+const GIZMO_MARGIN: f32 = 16.0;
+// of the gizmo's half size, how long each axis is
+const GIZMO_AXIS_LENGTH: f32 = 0.7;
+
+/// How the ground grid looks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GridStyle {
+    // world units between lines
+    pub cell_size: f32,
+    // cells between the brighter lines
+    pub major_every: f32,
+    // from the eye, where the lines have faded out
+    pub fade_distance: f32,
+    // of the plane, which is horizontal
+    pub height: f32,
+    // alpha scales how strong it all is
+    pub color: [f32; 4],
+}
+
+impl Default for GridStyle {
+    fn default() -> Self {
+        Self {
+            cell_size: 1.0,
+            major_every: 10.0,
+            fade_distance: 80.0,
+            height: 0.0,
+            color: [0.7, 0.7, 0.7, 0.8],
+        }
+    }
+}
+
+// Matches `GridUniform` in grid.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    inv_view_proj: [[f32; 4]; 4],
+    color: [f32; 4],
+    cell_size: f32,
+    major_every: f32,
+    fade_distance: f32,
+    height: f32,
+}
+
+fn uniform_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+// A uniform buffer of `size` bytes and a bind group of just it
+fn uniform_binding(
+    device: &wgpu::Device,
+    label: &str,
+    size: usize,
+    entries: &[wgpu::BindGroupLayoutEntry],
+) -> (wgpu::Buffer, wgpu::BindGroupLayout, wgpu::BindGroup) {
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(&format!("{} Buffer", label)),
+        size: size as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some(&format!("{} Bind Group Layout", label)),
+        entries,
+    });
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{} Bind Group", label)),
+        layout: &layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: buffer.as_entire_binding(),
+        }],
+    });
+    (buffer, layout, bind_group)
+}
+
+// An infinite ground grid for editors and model viewers, see grid.wgsl.
+// It's drawn over the whole screen in one triangle, blended over the
+// scene, into either a plain pass or the depth prepass's `begin_color`
+// one, where what's in front of the plane hides it.
+pub struct Grid {
+    pub style: GridStyle,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: SampledPipeline,
+    depth_pipeline: wgpu::RenderPipeline,
+}
+
+impl Grid {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let entries = [uniform_entry(0, wgpu::ShaderStages::FRAGMENT)];
+        let (uniform_buffer, layout, bind_group) =
+            uniform_binding(device, "Grid", std::mem::size_of::<GridUniform>(), &entries);
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Grid Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let shader = |defs: &ShaderDefs| {
+            shader_preprocessor::load("grid.wgsl", defs)
+                .and_then(|shader| {
+                    let reflection = ShaderReflection::new(&shader)?;
+                    reflection.check_group(0, &camera::layout_entries())?;
+                    reflection.check_group(1, &entries)?;
+                    reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                    reflection.check_struct::<GridUniform>("GridUniform")?;
+                    Ok(shader)
+                })
+                .unwrap_or_else(|e| panic!("{:#}", e))
+                .create_module(device, "Grid Shader")
+        };
+        // with a depth test, so not `fullscreen_pipeline`
+        let pipeline = |label, shader: &wgpu::ShaderModule, depth_stencil, multisample| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: fullscreen::VERTEX_ENTRY,
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: Default::default(),
+                depth_stencil,
+                multisample,
+                multiview: None,
+                cache: None,
+            })
+        };
+        // the prepass's depth is read-only in its colour pass
+        let depth_stencil = wgpu::DepthStencilState {
+            format: depth_prepass::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: Default::default(),
+            bias: Default::default(),
+        };
+        let (main_shader, depth_shader) = (shader(&ShaderDefs::new()), shader(&ShaderDefs::new().with("GRID_DEPTH")));
+
+        Self {
+            style: GridStyle::default(),
+            uniform_buffer,
+            bind_group,
+            pipeline: SampledPipeline::new(samples, |multisample| {
+                pipeline("Grid Pipeline", &main_shader, None, multisample)
+            }),
+            depth_pipeline: pipeline("Grid Depth Pipeline", &depth_shader, Some(depth_stencil), Default::default()),
+        }
+    }
+
+    // Once a frame, with the view-projection the camera's uniform has
+    pub fn update(&self, queue: &wgpu::Queue, view_proj: Matrix4<f32>) {
+        let style = self.style;
+        let uniform = GridUniform {
+            inv_view_proj: view_proj.invert().unwrap_or(Matrix4::identity()).into(),
+            color: style.color,
+            cell_size: style.cell_size.max(1e-3),
+            major_every: style.major_every.max(1.0),
+            fade_distance: style.fade_distance,
+            height: style.height,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // `depth_tested` for the prepass's colour pass, which has its depth;
+    // otherwise it's under whatever's drawn after it
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        depth_tested: bool,
+    ) {
+        let samples = render_pass.sample_count();
+        render_pass.set_pipeline(if depth_tested { &self.depth_pipeline } else { self.pipeline.get(samples) });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// Matches `GizmoUniform` in axis_gizmo.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GizmoUniform {
+    axes: [[f32; 4]; 3],
+}
+
+// The world's axes as the camera sees them, drawn in the window's bottom
+// right corner over the finished frame. `labels` says where to put their
+// names.
+pub struct AxisGizmo {
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    // each axis's end, in clip space of the corner, from the last update
+    axes: [Vector3<f32>; 3],
+}
+
+impl AxisGizmo {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat) -> Self {
+        let entries = [uniform_entry(0, wgpu::ShaderStages::VERTEX)];
+        let (uniform_buffer, layout, bind_group) =
+            uniform_binding(device, "Axis Gizmo", std::mem::size_of::<GizmoUniform>(), &entries);
+        let shader = shader_preprocessor::load("axis_gizmo.wgsl", &ShaderDefs::new())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &entries)?;
+                reflection.check_struct::<GizmoUniform>("GizmoUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Axis Gizmo Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Axis Gizmo Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Axis Gizmo Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            axes: [Vector3::zero(); 3],
+        }
+    }
+
+    // Once a frame, with the camera's view matrix
+    pub fn update(&mut self, queue: &wgpu::Queue, view: Matrix4<f32>) {
+        // x right, y up and z towards the camera
+        self.axes = [Vector4::unit_x(), Vector4::unit_y(), Vector4::unit_z()].map(|axis| {
+            let axis = (view * axis).truncate();
+            Vector3::new(axis.x * GIZMO_AXIS_LENGTH, axis.y * GIZMO_AXIS_LENGTH, axis.z)
+        });
+        let uniform = GizmoUniform {
+            axes: self.axes.map(|axis| axis.extend(0.0).into()),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // The corner it's drawn in, as x, y, width and height in physical pixels
+    fn corner(size: winit::dpi::PhysicalSize<u32>, scale_factor: f32) -> [f32; 4] {
+        let (side, margin) = (GIZMO_SIZE * scale_factor, GIZMO_MARGIN * scale_factor);
+        [size.width as f32 - side - margin, size.height as f32 - side - margin, side, side]
+    }
+
+    // Where the names of the x, y and z axes go, in logical pixels, just past
+    // the ends of their lines
+    pub fn labels(&self, size: winit::dpi::PhysicalSize<u32>, scale_factor: f32) -> [(&'static str, [f32; 2]); 3] {
+        let [x, y, side, _] = Self::corner(size, scale_factor).map(|v| v / scale_factor);
+        let center = [x + side / 2.0, y + side / 2.0];
+        let names = ["X", "Y", "Z"];
+        std::array::from_fn(|i| {
+            let end = self.axes[i].truncate() * 1.2 * side / 2.0;
+            (names[i], [center[0] + end.x, center[1] - end.y])
+        })
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f32,
+    ) {
+        let [x, y, width, height] = Self::corner(size, scale_factor);
+        if x < 0.0 || y < 0.0 {
+            return;
+        }
+        render_pass.set_viewport(x, y, width, height, 0.0, 1.0);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+        render_pass.set_viewport(0.0, 0.0, size.width as f32, size.height as f32, 0.0, 1.0);
+    }
+}
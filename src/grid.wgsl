@@ -0,0 +1,84 @@
+// An infinite grid on a horizontal plane, over the whole screen: each
+// pixel's view ray is crossed with the plane. Lines stay about a pixel wide
+// at any distance and fade out with it, every `major_every`th is brighter
+// and the lines through the origin are the x axis in red and the z axis in
+// blue. With GRID_DEPTH the plane's depth is written, so it's tested
+// against what's drawn already.
+
+#include "camera.wgsl"
+#include "fullscreen.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Matches `GridUniform` in grid.rs
+struct GridUniform {
+    // of the camera's view_proj, jitter and all
+    inv_view_proj: mat4x4<f32>,
+    color: vec4<f32>,
+    cell_size: f32,
+    major_every: f32,
+    // where the lines have faded out, from the eye
+    fade_distance: f32,
+    height: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> grid: GridUniform;
+
+const X_AXIS: vec3<f32> = vec3<f32>(1.0, 0.2, 0.2);
+const Z_AXIS: vec3<f32> = vec3<f32>(0.3, 0.5, 1.0);
+
+fn unproject(ndc: vec2<f32>, depth: f32) -> vec3<f32> {
+    let world = grid.inv_view_proj * vec4<f32>(ndc, depth, 1.0);
+    return world.xyz / world.w;
+}
+
+// How close to a line at every whole `coord` the pixel is, 1 on it and 0
+// a pixel or more away
+fn near_line(coord: vec2<f32>, width: vec2<f32>) -> vec2<f32> {
+    let distance = abs(fract(coord - 0.5) - 0.5) / max(width, vec2<f32>(1e-5));
+    return 1.0 - min(distance, vec2<f32>(1.0));
+}
+
+struct FragmentOutput {
+    @location(0) color: vec4<f32>,
+#ifdef GRID_DEPTH
+    @builtin(frag_depth) depth: f32,
+#endif
+};
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> FragmentOutput {
+    let ndc = vec2<f32>(in.uv.x * 2.0 - 1.0, 1.0 - in.uv.y * 2.0);
+    let near = unproject(ndc, 0.0);
+    let far = unproject(ndc, 1.0);
+    // how far from the near plane to the far one the ray crosses the plane
+    let t = (near.y - grid.height) / (near.y - far.y);
+    let position = mix(near, far, t);
+
+    // the derivatives before anything's discarded
+    let coord = position.xz / grid.cell_size;
+    let width = fwidth(coord);
+    let minor = near_line(coord, width);
+    let major = near_line(coord / grid.major_every, width / grid.major_every);
+    let axis = 1.0 - min(abs(coord) / max(width, vec2<f32>(1e-5)), vec2<f32>(1.0));
+
+    var color = vec4<f32>(grid.color.rgb, max(max(minor.x, minor.y) * 0.4, max(major.x, major.y)));
+    color = mix(color, vec4<f32>(X_AXIS, 1.0), axis.y);
+    color = mix(color, vec4<f32>(Z_AXIS, 1.0), axis.x);
+    let distance = length(position - camera.view_pos.xyz);
+    color.a *= grid.color.a * (1.0 - smoothstep(grid.fade_distance * 0.25, grid.fade_distance, distance));
+    // the plane's behind the eye or past the far plane
+    if !(t > 0.0 && t <= 1.0) || color.a <= 0.0 {
+        discard;
+    }
+
+    var out: FragmentOutput;
+    out.color = color;
+#ifdef GRID_DEPTH
+    let clip = camera.view_proj * vec4<f32>(position, 1.0);
+    out.depth = clip.z / clip.w;
+#endif
+    return out;
+}
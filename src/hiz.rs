@@ -0,0 +1,165 @@
+use cgmath::Matrix4;
+use wgpu::util::DeviceExt;
+
+use crate::compute::{self, ComputePipeline};
+use crate::resources::{ResourceKind, Tracked};
+use crate::texture;
+
+const WORKGROUP_SIZE: u32 = 8;
+
+// Mirrors `Level` in hiz.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LevelParams {
+    source_offset: u32,
+    target_offset: u32,
+    source_size: [u32; 2],
+    target_size: [u32; 2],
+    _padding: [u32; 2],
+}
+
+/// The depth prepass's depth and a mip chain of it down to 1x1, each level
+/// the farthest depth under its texels, see hiz.wgsl. Built after a frame's
+/// prepass, the next frame's GPU culling tests instances against it.
+pub struct DepthPyramid {
+    pipeline: ComputePipeline,
+    // every level, level 0 first, for the culling to read
+    buffer: Tracked<wgpu::Buffer>,
+    size: [u32; 2],
+    // each level's size and the bind group that writes it
+    levels: Vec<([u32; 2], wgpu::BindGroup)>,
+    // what the depth was drawn with, None until it's built or once it's out
+    // of date
+    view_proj: Option<Matrix4<f32>>,
+}
+
+impl DepthPyramid {
+    // `depth` is a view of the depth to build it from, `width` by `height`
+    pub fn new(device: &wgpu::Device, depth: &wgpu::TextureView, width: u32, height: u32) -> Self {
+        let pipeline = ComputePipeline::new(
+            device,
+            "Depth Pyramid",
+            include_str!("hiz.wgsl"),
+            "cs_main",
+            &[&[
+                compute::uniform_entry(0),
+                compute::storage_entry(1, false),
+                compute::texture_entry(2),
+            ]],
+        );
+        let (buffer, levels) = Self::create_levels(device, &pipeline, depth, width, height);
+        Self {
+            pipeline,
+            buffer,
+            size: [width.max(1), height.max(1)],
+            levels,
+            view_proj: None,
+        }
+    }
+
+    fn create_levels(
+        device: &wgpu::Device,
+        pipeline: &ComputePipeline,
+        depth: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::Buffer>, Vec<([u32; 2], wgpu::BindGroup)>) {
+        let (width, height) = (width.max(1), height.max(1));
+        let sizes: Vec<_> = (0..texture::mip_level_count(width, height))
+            .map(|level| [(width >> level).max(1), (height >> level).max(1)])
+            .collect();
+        let offsets: Vec<_> = sizes
+            .iter()
+            .scan(0, |offset, [width, height]| {
+                let start = *offset;
+                *offset += width * height;
+                Some(start)
+            })
+            .collect();
+        let texels: u32 = sizes.iter().map(|[width, height]| width * height).sum();
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Depth Pyramid Buffer"),
+            size: (texels as usize * std::mem::size_of::<f32>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let levels = (0..sizes.len())
+            .map(|level| {
+                // the first level has nothing above it, it's copied from the
+                // depth
+                let above = level.saturating_sub(1);
+                let params = LevelParams {
+                    source_offset: offsets[above],
+                    target_offset: offsets[level],
+                    source_size: sizes[above],
+                    target_size: sizes[level],
+                    _padding: [0; 2],
+                };
+                let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Depth Pyramid Level Buffer"),
+                    contents: bytemuck::cast_slice(&[params]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+                let bind_group = pipeline.create_bind_group(
+                    device,
+                    0,
+                    &[
+                        params_buffer.as_entire_binding(),
+                        buffer.as_entire_binding(),
+                        wgpu::BindingResource::TextureView(depth),
+                    ],
+                );
+                (sizes[level], bind_group)
+            })
+            .collect();
+        (Tracked::buffer(ResourceKind::Target, buffer), levels)
+    }
+
+    // For a new `depth`, the window's size. It's out of date until it's
+    // built again.
+    pub fn resize(&mut self, device: &wgpu::Device, depth: &wgpu::TextureView, width: u32, height: u32) {
+        (self.buffer, self.levels) = Self::create_levels(device, &self.pipeline, depth, width, height);
+        self.size = [width.max(1), height.max(1)];
+        self.view_proj = None;
+    }
+
+    // Every level's texels, row by row, level 0 first
+    pub fn binding(&self) -> wgpu::BindingResource<'_> {
+        self.buffer.as_entire_binding()
+    }
+
+    // Level 0's, the depth's size
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    pub fn level_count(&self) -> u32 {
+        self.levels.len() as u32
+    }
+
+    // The view-projection the depth it holds was drawn with, if it holds
+    // the last frame's
+    pub fn view_proj(&self) -> Option<Matrix4<f32>> {
+        self.view_proj
+    }
+
+    // Builds it from the depth, which was drawn with `view_proj`
+    pub fn encode(&mut self, encoder: &mut wgpu::CommandEncoder, view_proj: Matrix4<f32>) {
+        for ([width, height], bind_group) in &self.levels {
+            let workgroups = (
+                compute::workgroup_count(*width, WORKGROUP_SIZE),
+                compute::workgroup_count(*height, WORKGROUP_SIZE),
+                1,
+            );
+            self.pipeline.dispatch(encoder, &[bind_group], workgroups);
+        }
+        self.view_proj = Some(view_proj);
+    }
+
+    // For a frame that didn't build it, so the next one doesn't cull against
+    // depth from further back
+    pub fn invalidate(&mut self) {
+        self.view_proj = None;
+    }
+}
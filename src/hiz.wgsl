@@ -0,0 +1,46 @@
+// The depth pyramid occlusion culling tests against: level 0 is the depth
+// buffer as it was drawn, every level after it the farthest depth of the
+// 2x2 texels above it, one dispatch a level. The levels are rows of texels
+// one after the other in a buffer, not a texture's mips, as GL can't write
+// one mip of a texture while another's bound to read.
+
+struct Level {
+    // where the level above and this one start in `pyramid`
+    source_offset: u32,
+    target_offset: u32,
+    source_size: vec2<u32>,
+    target_size: vec2<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> level: Level;
+@group(0) @binding(1)
+var<storage, read_write> pyramid: array<f32>;
+// for the first level, read as floats since GL can't load from depth
+// textures
+@group(0) @binding(2)
+var depth: texture_2d<f32>;
+
+@compute @workgroup_size(8, 8)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (any(id.xy >= level.target_size)) {
+        return;
+    }
+    var farthest = 0.0;
+    if (level.target_offset == 0u) {
+        farthest = textureLoad(depth, vec2<i32>(id.xy), 0).r;
+    } else {
+        let size = level.source_size;
+        let texel = id.xy * 2u;
+        // the last texel of an odd sized level's row or column takes in a
+        // third, which would otherwise fall between the texels below it
+        let extent = vec2<u32>(select(2u, 3u, texel.x + 3u == size.x), select(2u, 3u, texel.y + 3u == size.y));
+        for (var y = 0u; y < extent.y; y++) {
+            for (var x = 0u; x < extent.x; x++) {
+                let at = min(texel + vec2<u32>(x, y), size - 1u);
+                farthest = max(farthest, pyramid[level.source_offset + at.y * size.x + at.x]);
+            }
+        }
+    }
+    pyramid[level.target_offset + id.y * level.target_size.x + id.x] = farthest;
+}
@@ -0,0 +1,408 @@
+use cgmath::*;
+
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::reflection_probes::ReflectionProbes;
+use crate::render_target::{self, CubeCameras, RenderTarget};
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::Texture;
+use crate::tonemap;
+use crate::upload::DynamicUniforms;
+
+// What the environment is captured into, and the maps baked from it; the
+// frame's own, so reflection probes capture the scene with its pipelines
+pub const ENVIRONMENT_FORMAT: wgpu::TextureFormat = tonemap::HDR_FORMAT;
+// texels across each face
+const CAPTURE_SIZE: u32 = 64;
+const IRRADIANCE_SIZE: u32 = 32;
+pub(crate) const PREFILTERED_SIZE: u32 = 64;
+// roughness 0 to 1 a quarter at a time, as MAX_REFLECTION_LOD in model.wgsl
+// expects
+pub(crate) const PREFILTERED_MIPS: u32 = 5;
+// the capture's near plane, its far one is the camera's
+const CAPTURE_ZNEAR: f32 = 0.1;
+const BRDF_LUT_SIZE: u32 = 128;
+const BRDF_LUT_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+
+// Matches `BakeParams` in ibl_bake.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BakeParams {
+    face: u32,
+    roughness: f32,
+    source_size: f32,
+    source_mips: f32,
+}
+
+fn cube_texture(
+    device: &wgpu::Device,
+    label: &str,
+    kind: ResourceKind,
+    size: u32,
+    mip_level_count: u32,
+) -> (Tracked<wgpu::Texture>, wgpu::TextureView) {
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: ENVIRONMENT_FORMAT,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some(&format!("{} View", label)),
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    });
+    (Tracked::texture(kind, texture), view)
+}
+
+fn clear_pass<'e>(encoder: &'e mut wgpu::CommandEncoder, label: &str, view: &wgpu::TextureView, color: wgpu::Color) -> TracedPass<'e> {
+    TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(color),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    })
+}
+
+// The camera bind group's bindings 3 to 6, see `camera::layout_entries`
+fn map_entries<'a>(
+    irradiance: &'a wgpu::TextureView,
+    prefiltered: &'a wgpu::TextureView,
+    brdf_lut: &'a wgpu::TextureView,
+    sampler: &'a wgpu::Sampler,
+) -> [wgpu::BindGroupEntry<'a>; 4] {
+    [
+        wgpu::BindGroupEntry {
+            binding: 3,
+            resource: wgpu::BindingResource::TextureView(irradiance),
+        },
+        wgpu::BindGroupEntry {
+            binding: 4,
+            resource: wgpu::BindingResource::TextureView(prefiltered),
+        },
+        wgpu::BindGroupEntry {
+            binding: 5,
+            resource: wgpu::BindingResource::TextureView(brdf_lut),
+        },
+        wgpu::BindGroupEntry {
+            binding: 6,
+            resource: wgpu::BindingResource::Sampler(sampler),
+        },
+    ]
+}
+
+// Image based lighting: the diffuse irradiance and the prefiltered specular
+// radiance of an environment cubemap, and the split sum's BRDF table, bound
+// with the camera at bindings 3 to 6. The maps are baked at load time from a
+// cubemap file, from whatever is drawn into `begin_capture`'s passes (the
+// sky, when the scene has one), or from a plain colour; the textures are
+// kept, so the bind groups they're in stay valid across bakes. Captures can
+// be prefiltered into a reflection probe instead, see `bake_probe`.
+pub struct Ibl {
+    // with a depth face for each, for the terrain, which is drawn with depth
+    capture: RenderTarget,
+    // looking out from the capture's eye
+    capture_cameras: CubeCameras,
+    irradiance: Tracked<wgpu::Texture>,
+    irradiance_view: wgpu::TextureView,
+    prefiltered: Tracked<wgpu::Texture>,
+    prefiltered_view: wgpu::TextureView,
+    brdf_lut_view: Tracked<wgpu::TextureView>,
+    sampler: wgpu::Sampler,
+    source_layout: wgpu::BindGroupLayout,
+    params: DynamicUniforms<BakeParams>,
+    irradiance_pipeline: wgpu::RenderPipeline,
+    prefilter_pipeline: wgpu::RenderPipeline,
+}
+
+impl Ibl {
+    // Starts out baked from a constant `ambient`, as if it came from every
+    // direction. The capture's camera bind groups share `shared`, the
+    // camera bind group's entries but for the camera's own and these maps',
+    // with the camera's.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_layout: &wgpu::BindGroupLayout,
+        shared: &[wgpu::BindGroupEntry],
+        ambient: [f32; 3],
+    ) -> Self {
+        let capture = RenderTarget::cube(device, "Environment Capture", ENVIRONMENT_FORMAT, CAPTURE_SIZE);
+        let (irradiance, irradiance_view) =
+            cube_texture(device, "Irradiance Map", ResourceKind::Texture, IRRADIANCE_SIZE, 1);
+        let (prefiltered, prefiltered_view) =
+            cube_texture(device, "Prefiltered Environment", ResourceKind::Texture, PREFILTERED_SIZE, PREFILTERED_MIPS);
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: BRDF_LUT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let brdf_lut_view = Tracked::view(ResourceKind::Texture, &brdf_lut, &Default::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("IBL Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let params = DynamicUniforms::new(device, "IBL Bake Params", wgpu::ShaderStages::FRAGMENT, 6 * (PREFILTERED_MIPS as usize + 1));
+        let source_entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("IBL Source Bind Group Layout"),
+            entries: &source_entries,
+        });
+        let shader = shader_preprocessor::load("ibl_bake.wgsl", &ShaderDefs::new())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &[params.layout_entry()])?;
+                reflection.check_group(1, &source_entries)?;
+                reflection.check_struct::<BakeParams>("BakeParams")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "IBL Bake Shader");
+        let bake_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("IBL Bake Pipeline Layout"),
+            bind_group_layouts: &[params.layout(), &source_layout],
+            push_constant_ranges: &[],
+        });
+        let brdf_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("BRDF LUT Pipeline Layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |label, layout, entry_point, format: wgpu::TextureFormat| {
+            fullscreen_pipeline_with(device, label, layout, &shader, entry_point, &[Some(format.into())])
+        };
+        let irradiance_pipeline = pipeline("Irradiance Pipeline", &bake_layout, "fs_irradiance", ENVIRONMENT_FORMAT);
+        let prefilter_pipeline = pipeline("Prefilter Pipeline", &bake_layout, "fs_prefilter", ENVIRONMENT_FORMAT);
+        let brdf_pipeline = pipeline("BRDF LUT Pipeline", &brdf_layout, "fs_brdf", BRDF_LUT_FORMAT);
+
+        // it doesn't depend on the environment, so once is enough
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        let mut pass = clear_pass(&mut encoder, "BRDF LUT Pass", &brdf_lut_view, wgpu::Color::TRANSPARENT);
+        pass.set_pipeline(&brdf_pipeline);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        queue.submit([encoder.finish()]);
+
+        let capture_cameras = {
+            let mut entries = shared.to_vec();
+            entries.extend(map_entries(&irradiance_view, &prefiltered_view, &brdf_lut_view, &sampler));
+            CubeCameras::new(device, "Environment Capture", camera_layout, &entries)
+        };
+        let mut ibl = Self {
+            capture,
+            capture_cameras,
+            irradiance,
+            irradiance_view,
+            prefiltered,
+            prefiltered_view,
+            brdf_lut_view,
+            sampler,
+            source_layout,
+            params,
+            irradiance_pipeline,
+            prefilter_pipeline,
+        };
+        ibl.fill(device, queue, ambient);
+        ibl
+    }
+
+    // The camera bind group's bindings 3 to 6, see `camera::layout_entries`
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 4] {
+        map_entries(&self.irradiance_view, &self.prefiltered_view, &self.brdf_lut_view, &self.sampler)
+    }
+
+    // Bakes from `color` coming equally from everywhere
+    pub fn fill(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, [r, g, b]: [f32; 3]) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Fill Encoder"),
+        });
+        let color = wgpu::Color {
+            r: r as f64,
+            g: g as f64,
+            b: b as f64,
+            a: 1.0,
+        };
+        for face in 0..6 {
+            self.capture.begin_layer_color(&mut encoder, "Environment Fill Pass", face, color);
+        }
+        queue.submit([encoder.finish()]);
+        self.bake_capture(device, queue);
+    }
+
+    // Bakes from a cubemap loaded from a file, which has to be filterable
+    pub fn bake(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, environment: &Texture) {
+        let texture = &environment.texture;
+        self.bake_from(device, queue, &environment.view, texture.width(), texture.mip_level_count(), None);
+    }
+
+    // Where the capture's passes see the scene from, before `begin_capture`,
+    // as far as `zfar`
+    pub fn set_capture_eye(&self, queue: &wgpu::Queue, eye: Point3<f32>, zfar: f32) {
+        self.capture_cameras.set_eye(queue, eye, CAPTURE_ZNEAR, zfar);
+    }
+
+    // Draws into face `face` of the capture, cleared to `clear`, with
+    // `capture_bind_group(face)` as the camera. Pipelines have to target
+    // ENVIRONMENT_FORMAT without depth.
+    pub fn begin_capture<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        face: u32,
+        clear: wgpu::Color,
+    ) -> TracedPass<'e> {
+        self.capture.begin_layer_color(encoder, "Environment Capture Pass", face, clear)
+    }
+
+    pub fn capture_bind_group(&self, face: u32) -> &wgpu::BindGroup {
+        self.capture_cameras.bind_group(face)
+    }
+
+    // Face `face` of the capture, for passes of their own after
+    // `begin_capture`'s, like the terrain's with `capture_depth_view`
+    pub fn capture_view(&self, face: u32) -> &wgpu::TextureView {
+        self.capture.layer_view(face)
+    }
+
+    // DEPTH_FORMAT, the size of a face
+    pub fn capture_depth_view(&self, face: u32) -> &wgpu::TextureView {
+        self.capture.depth_layer_view(face)
+    }
+
+    // Once the capture's passes have been submitted
+    pub fn bake_capture(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let view = self.capture.texture().create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        self.bake_from(device, queue, &view, CAPTURE_SIZE, 1, None);
+    }
+
+    // Once the capture's passes have been submitted, prefilters it into
+    // probe `index` of `probes` instead, leaving the environment's maps be
+    pub fn bake_probe(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, probes: &ReflectionProbes, index: usize) {
+        let view = self.capture.texture().create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        self.bake_from(device, queue, &view, CAPTURE_SIZE, 1, Some((probes, index)));
+    }
+
+    // Into the irradiance and prefiltered maps, or with `probe` only into
+    // that probe's prefiltered faces
+    fn bake_from(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: &wgpu::TextureView,
+        size: u32,
+        mips: u32,
+        probe: Option<(&ReflectionProbes, usize)>,
+    ) {
+        self.params.clear();
+        let params = |face, roughness| BakeParams {
+            face,
+            roughness,
+            source_size: size as f32,
+            source_mips: mips as f32,
+        };
+        let irradiance_faces = if probe.is_some() { 0..0 } else { 0..6 };
+        let irradiance_offsets: Vec<u32> = irradiance_faces.map(|face| self.params.push(&params(face, 0.0))).collect();
+        let prefilter_offsets: Vec<Vec<u32>> = (0..PREFILTERED_MIPS)
+            .map(|mip| {
+                let roughness = mip as f32 / (PREFILTERED_MIPS - 1) as f32;
+                (0..6).map(|face| self.params.push(&params(face, roughness))).collect()
+            })
+            .collect();
+        self.params.write(device, queue);
+
+        let source_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("IBL Source Bind Group"),
+            layout: &self.source_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+            ],
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("IBL Bake Encoder"),
+        });
+        let mut draw = |target: &wgpu::Texture, mip, face, pipeline, offset| {
+            let view = render_target::mip_layer_view(target, face, mip);
+            let mut pass = clear_pass(&mut encoder, "IBL Bake Pass", &view, wgpu::Color::BLACK);
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, self.params.bind_group(), &[offset]);
+            pass.set_bind_group(1, &source_bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        };
+        for (face, &offset) in irradiance_offsets.iter().enumerate() {
+            draw(&self.irradiance, 0, face as u32, &self.irradiance_pipeline, offset);
+        }
+        // a probe's faces follow each other in its array
+        let (prefiltered, first_layer) = match probe {
+            Some((probes, index)) => (probes.texture(), 6 * index as u32),
+            None => (&*self.prefiltered, 0),
+        };
+        for (mip, offsets) in prefilter_offsets.iter().enumerate() {
+            for face in 0..6 {
+                draw(prefiltered, mip as u32, first_layer + face, &self.prefilter_pipeline, offsets[face as usize]);
+            }
+        }
+        queue.submit([encoder.finish()]);
+    }
+}
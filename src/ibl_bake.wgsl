@@ -0,0 +1,106 @@
+// Bakes image based lighting out of an environment cubemap: the diffuse
+// irradiance, the specular radiance prefiltered for each roughness, and the
+// split sum's BRDF table, a face or a mip of a face at a time.
+
+#include "cubemap.wgsl"
+#include "pbr.wgsl"
+
+// Matches `BakeParams` in ibl.rs
+struct BakeParams {
+    // +x, -x, +y, -y, +z, -z, as wgpu lays cubemaps out
+    face: u32,
+    // of the prefiltered mip being drawn
+    roughness: f32,
+    // the source's faces, in texels across mip 0, and how many mips it has
+    source_size: f32,
+    source_mips: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: BakeParams;
+@group(1) @binding(0)
+var t_source: texture_cube<f32>;
+@group(1) @binding(1)
+var s_source: sampler;
+
+// samples per pixel of the prefiltered map and the BRDF table
+const SAMPLE_COUNT: u32 = 256u;
+// between the irradiance's samples, in radians
+const IRRADIANCE_STEP: f32 = 0.05;
+
+// The source's mip whose texels are about as big as `solid_angle`
+fn source_lod(solid_angle: f32) -> f32 {
+    let texel = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+    return clamp(0.5 * log2(solid_angle / texel), 0.0, params.source_mips - 1.0);
+}
+
+// Cosine weighted over the hemisphere around each direction, divided by pi,
+// so a constant environment has the irradiance of its own colour
+@fragment
+fn fs_irradiance(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let n = cube_face_direction(params.face, in.uv);
+    let frame = tangent_frame(n);
+    let lod = source_lod(IRRADIANCE_STEP * IRRADIANCE_STEP);
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += IRRADIANCE_STEP) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += IRRADIANCE_STEP) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let radiance = textureSampleLevel(t_source, s_source, frame * local, lod).rgb;
+            sum += radiance * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * sum / count, 1.0);
+}
+
+// The environment as a surface of `roughness` reflects it, taking the view
+// to be along the normal. Far-apart samples read smaller mips of the
+// source, so a few hundred of them don't alias.
+@fragment
+fn fs_prefilter(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let n = cube_face_direction(params.face, in.uv);
+    if params.roughness == 0.0 {
+        return vec4<f32>(textureSampleLevel(t_source, s_source, n, 0.0).rgb, 1.0);
+    }
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let h = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), n, params.roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if n_dot_l > 0.0 {
+            // with n = v, the pdf is d(h) / 4
+            let pdf = distribution_ggx(max(dot(n, h), 0.0), params.roughness) / 4.0;
+            // a mip further out blurs between the samples
+            let lod = source_lod(4.0 / (f32(SAMPLE_COUNT) * pdf + 1e-4));
+            sum += textureSampleLevel(t_source, s_source, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+// The split sum's scale and bias on f0, for n·v across and roughness down
+@fragment
+fn fs_brdf(in: FullscreenOutput) -> @location(0) vec2<f32> {
+    let n_dot_v = max(in.uv.x, 1e-3);
+    let roughness = in.uv.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let n = vec3<f32>(0.0, 0.0, 1.0);
+    let k = roughness * roughness / 2.0;
+    var scale_bias = vec2<f32>(0.0);
+    for (var i = 0u; i < SAMPLE_COUNT; i++) {
+        let h = importance_sample_ggx(hammersley(i, SAMPLE_COUNT), n, roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        if n_dot_l > 0.0 {
+            let n_dot_h = max(h.z, 0.0);
+            let v_dot_h = max(dot(v, h), 0.0);
+            let visibility = geometry_smith(n_dot_v, n_dot_l, k) * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale_bias += vec2<f32>(1.0 - fresnel, fresnel) * visibility;
+        }
+    }
+    return scale_bias / f32(SAMPLE_COUNT);
+}
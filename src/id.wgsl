@@ -0,0 +1,46 @@
+// Object ID pass for GPU picking
+
+#include "camera.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct ObjectUniform {
+    id: u32,
+};
+@group(1) @binding(0)
+var<uniform> object: ObjectUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) @interpolate(flat) id: u32,
+};
+
+@vertex
+fn vs_main(
+    @builtin(instance_index) instance_index: u32,
+    @location(0) position: vec3<f32>,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+    out.id = object.id + instance_index;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) u32 {
+    return in.id;
+}
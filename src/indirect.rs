@@ -0,0 +1,176 @@
+use wgpu::util::DrawIndexedIndirectArgs;
+
+use crate::draw_list::TracedPass;
+use crate::resources::{ResourceKind, Tracked};
+
+const ARGS_SIZE: wgpu::BufferAddress = std::mem::size_of::<DrawIndexedIndirectArgs>() as wgpu::BufferAddress;
+
+// How a batch ends up being submitted, picked from the device features
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MultiDrawMode {
+    // one multi_draw_indexed_indirect_count call, count read from a GPU buffer
+    IndirectCount,
+    // one multi_draw_indexed_indirect call
+    Indirect,
+    // a direct draw_indexed per command from the CPU copy, works everywhere
+    Loop,
+}
+
+impl MultiDrawMode {
+    pub fn from_features(features: wgpu::Features) -> Self {
+        // without it the first_instance of every command must be 0
+        if !features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE) {
+            MultiDrawMode::Loop
+        } else if features.contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT) {
+            MultiDrawMode::IndirectCount
+        } else if features.contains(wgpu::Features::MULTI_DRAW_INDIRECT) {
+            MultiDrawMode::Indirect
+        } else {
+            MultiDrawMode::Loop
+        }
+    }
+}
+
+// Features to request at device creation for the best supported mode
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features()
+        & (wgpu::Features::MULTI_DRAW_INDIRECT
+            | wgpu::Features::MULTI_DRAW_INDIRECT_COUNT
+            | wgpu::Features::INDIRECT_FIRST_INSTANCE)
+}
+
+// Many indexed draws packed into one indirect buffer. All commands must share
+// the pipeline, bind groups and vertex/index buffers bound by the caller, so
+// meshes are expected to live in one big vertex and index buffer.
+pub struct IndirectBatch {
+    label: String,
+    commands: Vec<DrawIndexedIndirectArgs>,
+    buffer: Tracked<wgpu::Buffer>,
+    // draw count for the IndirectCount mode, as a single u32
+    count_buffer: wgpu::Buffer,
+    capacity: usize,
+    mode: MultiDrawMode,
+}
+
+impl IndirectBatch {
+    pub fn new(device: &wgpu::Device, label: &str, capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (buffer, count_buffer) = Self::create_buffers(device, label, capacity);
+        Self {
+            label: label.to_string(),
+            commands: Vec::with_capacity(capacity),
+            buffer,
+            count_buffer,
+            capacity,
+            mode: MultiDrawMode::from_features(device.features()),
+        }
+    }
+
+    fn create_buffers(device: &wgpu::Device, label: &str, capacity: usize) -> (Tracked<wgpu::Buffer>, wgpu::Buffer) {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity as wgpu::BufferAddress * ARGS_SIZE,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Count", label)),
+            size: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        (Tracked::buffer(ResourceKind::Buffer, buffer), count_buffer)
+    }
+
+    pub fn mode(&self) -> MultiDrawMode {
+        self.mode
+    }
+
+    // Forces a mode, e.g. to compare against the fallback. Modes the device
+    // lacks the feature for are ignored.
+    pub fn set_mode(&mut self, device: &wgpu::Device, mode: MultiDrawMode) {
+        let supported = MultiDrawMode::from_features(device.features());
+        let allowed = match mode {
+            MultiDrawMode::IndirectCount => supported == MultiDrawMode::IndirectCount,
+            MultiDrawMode::Indirect => supported != MultiDrawMode::Loop,
+            MultiDrawMode::Loop => true,
+        };
+        if allowed {
+            self.mode = mode;
+        } else {
+            log::warn!("{:?} multi-draw is not supported, staying on {:?}", mode, self.mode);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    pub fn push(&mut self, command: DrawIndexedIndirectArgs) {
+        self.commands.push(command);
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    // The argument buffer, e.g. for a compute pass that writes commands itself
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn count_buffer(&self) -> &wgpu::Buffer {
+        &self.count_buffer
+    }
+
+    // Uploads the queued commands, growing the buffers when needed
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.commands.len() > self.capacity {
+            self.capacity = self.commands.len().next_power_of_two();
+            let (buffer, count_buffer) = Self::create_buffers(device, &self.label, self.capacity);
+            self.buffer = buffer;
+            self.count_buffer = count_buffer;
+        }
+        let bytes: Vec<u8> = self.commands
+            .iter()
+            .flat_map(|command| command.as_bytes().iter().copied())
+            .collect();
+        queue.write_buffer(&self.buffer, 0, &bytes);
+        queue.write_buffer(&self.count_buffer, 0, bytemuck::cast_slice(&[self.commands.len() as u32]));
+    }
+
+    // Issues every uploaded command
+    pub fn draw(&self, render_pass: &mut TracedPass) {
+        let count = self.commands.len() as u32;
+        if count == 0 {
+            return;
+        }
+        match self.mode {
+            MultiDrawMode::IndirectCount => render_pass.multi_draw_indexed_indirect_count(
+                &self.buffer,
+                0,
+                &self.count_buffer,
+                0,
+                count,
+            ),
+            MultiDrawMode::Indirect => render_pass.multi_draw_indexed_indirect(&self.buffer, 0, count),
+            MultiDrawMode::Loop => {
+                for c in &self.commands {
+                    render_pass.draw_indexed(
+                        c.first_index..c.first_index + c.index_count,
+                        c.base_vertex,
+                        c.first_instance..c.first_instance + c.instance_count,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use cgmath::*;
+
+/// Placement of one copy of a mesh.
+#[derive(Debug, Clone, Copy)]
+pub struct Instance {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    // index into the material textures
+    pub material: u32,
+}
+
+impl Instance {
+    pub fn model_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    pub fn to_raw(&self) -> InstanceRaw {
+        InstanceRaw {
+            model: self.model_matrix().into(),
+            material: self.material,
+            _padding: [0; 3],
+        }
+    }
+}
+
+// What actually goes into the instance buffer, shaders can't use quaternions
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    pub model: [[f32; 4]; 4],
+    pub material: u32,
+    // keep the size a multiple of 16 so it matches the WGSL struct in storage buffers
+    pub _padding: [u32; 3],
+}
+
+impl InstanceRaw {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
+                // for each vec4. We'll have to reassemble the mat4 in the shader.
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // leave room for more per-vertex attributes
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Uint32,
+                },
+            ],
+        }
+    }
+
+    // Just the model matrix, at locations 10 to 13, for a second buffer of
+    // the instances as they were last frame
+    pub fn previous_desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] =
+            wgpu::vertex_attr_array![10 => Float32x4, 11 => Float32x4, 12 => Float32x4, 13 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Grid of `per_row` x `per_row` instances on the XZ plane, stepping away
+// from the camera along -Z, each turned a little around Y and cycling
+// through `materials` material indices
+pub fn grid(per_row: u32, spacing: f32, materials: u32) -> Vec<Instance> {
+    let half = (per_row as f32 - 1.0) / 2.0;
+    (0..per_row)
+        .flat_map(|row| {
+            (0..per_row).map(move |col| {
+                let position = Vector3::new((col as f32 - half) * spacing, 0.0, -(row as f32) * spacing);
+                let angle = ((row * 7 + col * 3) % 11) as f32 / 10.0 - 0.5;
+                Instance {
+                    position,
+                    rotation: Quaternion::from_angle_y(Rad(angle)),
+                    scale: Vector3::new(1.0, 1.0, 1.0),
+                    material: (row + col) % materials.max(1),
+                }
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,49 @@
+use serde::{Deserialize, Serialize};
+
+/// Bits for what something's drawn on, or a camera's mask of what it
+/// draws. A camera draws whatever has a layer in common with its mask.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RenderLayers(pub u32);
+
+impl RenderLayers {
+    pub const NONE: Self = Self(0);
+    pub const ALL: Self = Self(u32::MAX);
+    // what models are on unless they say otherwise, and everything else in
+    // the scene always is: the sky, terrain, water, tilemap, instanced mesh
+    // and particles
+    pub const WORLD: Self = Self::layer(0);
+    // the debug line overlay
+    pub const DEBUG: Self = Self::layer(31);
+
+    // Just layer `index`, 0 to 31
+    pub const fn layer(index: u32) -> Self {
+        Self(1 << index)
+    }
+
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn intersects(self, other: Self) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    pub fn is_world(&self) -> bool {
+        *self == Self::WORLD
+    }
+
+    pub fn is_all(&self) -> bool {
+        *self == Self::ALL
+    }
+}
+
+impl Default for RenderLayers {
+    fn default() -> Self {
+        Self::WORLD
+    }
+}
@@ -1,18 +1,196 @@
-use std::process::Termination;
+use std::f32::consts::{FRAC_1_SQRT_2, SQRT_2};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use wgpu::util::DeviceExt;
+use wgpu::util::{DeviceExt, RenderEncoder};
 use winit::{
     event::*,
-    event_loop::EventLoop,
-    keyboard::{KeyCode, PhysicalKey},
-    window::{Window, WindowBuilder},
+    event_loop::{ControlFlow, EventLoop},
+    monitor::VideoMode,
+    window::{CursorGrabMode, Fullscreen, Window},
 };
 
+pub mod actions;
+pub mod adapter;
+#[cfg(target_os = "android")]
+pub mod android;
+pub mod animation;
+pub mod assets;
+pub mod atlas;
+pub mod bounds;
+pub mod camera;
+pub mod color_grading;
+pub mod compute;
+pub mod config;
+pub mod culling;
+pub mod debug_draw;
+pub mod decals;
+pub mod depth_of_field;
+pub mod depth_prepass;
+pub mod draw_list;
+pub mod dynamic_resolution;
+pub mod draw_data;
+pub mod error_scope;
+pub mod exposure;
+pub mod external_uniforms;
+pub mod fog;
+pub mod frame_graph;
+pub mod frames;
+pub mod fullscreen;
+pub mod fxaa;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
+pub mod gamma;
+pub mod gpu_culling;
+pub mod gpu_picking;
+pub mod grid;
+pub mod hiz;
+pub mod ibl;
+pub mod indirect;
+pub mod instance;
+pub mod layers;
+pub mod lighting;
+pub mod lod;
+pub mod material;
+pub mod mesh_file;
+pub mod model;
+pub mod motion_blur;
+pub mod msaa;
+pub mod oit;
+pub mod outline;
+pub mod parallel_encoding;
+pub mod particles;
+#[cfg(feature = "physics")]
+pub mod physics;
+pub mod picking;
+pub mod pipeline_cache;
+pub mod pipeline_compiler;
+pub mod plugin;
+pub mod pointer;
+pub mod profiler;
+pub mod readback;
+pub mod reflection_probes;
+pub mod render_bundle;
+pub mod render_queue;
+pub mod render_target;
+pub mod resources;
+pub mod scene;
+pub mod settings;
+pub mod shader_preprocessor;
+pub mod shader_reflection;
+pub mod shader_reload;
+pub mod shader_validation;
+pub mod shadow;
+pub mod shapes;
+pub mod simplify;
+pub mod skinning;
+pub mod sky;
+pub mod sky_cube;
+pub mod skybox;
+pub mod sprite_batch;
+pub mod ssr;
+pub mod taa;
+pub mod terrain;
+pub mod text;
+pub mod texture;
+pub mod tilemap;
+pub mod time;
+pub mod tonemap;
+pub mod touch;
+pub mod transforms;
+pub mod upload;
+pub mod viewport;
+pub mod voxels;
+pub mod water;
+pub mod wireframe;
+
+use actions::ActionMap;
+use bounds::{Aabb, Sphere};
+use camera::{Camera, CameraController, CameraUniform, Projection, ProjectionMode};
+use color_grading::{ColorGrading, ColorLut};
+use compute::StorageBuffer;
+use config::Config;
+use culling::{CpuCuller, CullCounts, CullingMode, FrameStats};
+use debug_draw::{DebugCategories, DebugDraw};
+use decals::Decals;
+use depth_of_field::{DepthOfField, DofPass};
+use depth_prepass::DepthPrepass;
+use draw_list::TracedPass;
+use dynamic_resolution::{FrameTime, GpuFrameTimer, RenderScale, ResolutionScaler, Upscaling};
+use error_scope::ErrorScope;
+use exposure::{AutoExposure, Exposure};
+use external_uniforms::{ExternalBuffer, ExternalUniforms};
+use fog::{Fog, FogUniform};
+use frame_graph::FrameGraph;
+use frames::FramesInFlight;
+use fxaa::Fxaa;
+use gamma::{GammaBlit, SurfaceFormats};
+use gpu_culling::{CulledDraw, GpuCuller};
+use gpu_picking::{GpuPicker, ObjectId};
+use grid::{AxisGizmo, Grid};
+use hiz::DepthPyramid;
+use ibl::Ibl;
+use indirect::IndirectBatch;
+use instance::{Instance, InstanceRaw};
+use layers::RenderLayers;
+use lighting::{Light, LightBuffers, LightCullStats};
+use lod::LodMode;
+use material::MaterialTextures;
+use model::NodeTransform;
+use motion_blur::{MotionBlur, MotionBlurPass};
+use msaa::{MsaaTarget, SampledPipeline};
+use oit::WeightedBlendedOit;
+use outline::SelectionOutline;
+use atlas::{AtlasBuilder, TextureAtlas};
+use parallel_encoding::EncodeJob;
+use particles::{EmitterParams, ParticleSystem};
+use picking::{Hit, Pickable, Ray};
+use pipeline_cache::PipelineCache;
+use pipeline_compiler::PipelineCompiler;
+use plugin::{Plugin, RenderContext};
+use pointer::{PointerPhase, Pointers};
+use profiler::GpuProfiler;
+use readback::{PendingReadback, Readback};
+use reflection_probes::{ReflectionProbe, ReflectionProbes};
+use render_bundle::{BundleCache, BundleTargets};
+use render_queue::{RenderQueues, TransparencyMode};
+use resources::{ResourceKind, ResourceUsage, Tracked};
+use scene::{
+    CameraDesc, DecalDesc, InstanceDesc, ModelDesc, RenderSettings, SceneDesc, SceneModel, SkyDesc, TerrainDesc, VoxelDesc,
+    WaterDesc,
+};
+use shader_preprocessor::{PreprocessedShader, ShaderDefs};
+use shader_reflection::ShaderReflection;
+use settings::{AntiAliasing, Settings, SettingsFile};
+use shader_reload::ShaderWatcher;
+use shadow::{ShadowMap, ShadowQuality};
+use skinning::ModelRenderer;
+use sky::Sky;
+use skybox::Skybox;
+use sprite_batch::{Sprite, SpriteBatch};
+use ssr::ScreenSpaceReflections;
+use taa::TemporalAa;
+use terrain::Terrain;
+use text::{FontFamily, TextRenderer, TextStyle};
+use tilemap::{TileLayer, Tilemap};
+use texture::{SamplerCache, SamplerDesc, Texture};
+use time::{FixedTimestep, FramePacer};
+use tonemap::Tonemap;
+use touch::{TouchGesture, TouchInput};
+use transforms::Transforms;
+use upload::Uploader;
+use viewport::{Viewport, ViewportCompositor, ViewportRect};
+use voxels::VoxelTerrain;
+use water::Water;
+use wireframe::WireframeMode;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 struct Vertex {
     position: [f32; 3],
     color: [f32; 3],
+    tex_coords: [f32; 2],
 }
 
 impl Vertex {
@@ -31,17 +209,22 @@ impl Vertex {
                     format: wgpu::VertexFormat::Float32x3,
                     shader_location: 1,
                 },
+                wgpu::VertexAttribute {
+                    offset: std::mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    format: wgpu::VertexFormat::Float32x2,
+                    shader_location: 2,
+                },
             ],
         }
     }
 }
 
 const VERTICES: &[Vertex] = &[
-    Vertex { position: [-0.0868241, 0.49240386, 0.0], color: [0.5, 0.0, 0.5] }, // A
-    Vertex { position: [-0.49513406, 0.06958647, 0.0], color: [0.5, 0.0, 0.5] }, // B
-    Vertex { position: [-0.21918549, -0.44939706, 0.0], color: [0.5, 0.0, 0.5] }, // C
-    Vertex { position: [0.35966998, -0.3473291, 0.0], color: [0.5, 0.0, 0.5] }, // D
-    Vertex { position: [0.44147372, 0.2347359, 0.0], color: [0.5, 0.0, 0.5] }, // E
+    Vertex { position: [-0.0868241, 0.49240386, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.4131759, 0.00759614] }, // A
+    Vertex { position: [-0.49513406, 0.06958647, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.0048659444, 0.43041354] }, // B
+    Vertex { position: [-0.21918549, -0.44939706, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.28081453, 0.949397] }, // C
+    Vertex { position: [0.35966998, -0.3473291, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.85967, 0.84732914] }, // D
+    Vertex { position: [0.44147372, 0.2347359, 0.0], color: [0.5, 0.0, 0.5], tex_coords: [0.9414737, 0.2652641] }, // E
 ];
 
 const INDICES: &[u16] = &[
@@ -50,126 +233,833 @@ const INDICES: &[u16] = &[
     2, 3, 4,
 ];
 
+// Background behind the scene, in linear colour
+const CLEAR_COLOR: wgpu::Color = wgpu::Color {
+    r: 0.1,
+    g: 0.2,
+    b: 0.3,
+    a: 1.0,
+};
+
+// Alpha modes a transparent window can use, in order of preference.
+// Everything drawn writes straight alpha, which doesn't matter where it's
+// opaque and is close enough over transparent black.
+const TRANSPARENT_ALPHA_MODES: [wgpu::CompositeAlphaMode; 3] = [
+    wgpu::CompositeAlphaMode::PreMultiplied,
+    wgpu::CompositeAlphaMode::PostMultiplied,
+    wgpu::CompositeAlphaMode::Inherit,
+];
+
+// Material texture filtering, cycled with T
+const FILTERING_PRESETS: [(&str, SamplerDesc); 4] = [
+    ("trilinear", SamplerDesc::TRILINEAR),
+    ("anisotropic x4", SamplerDesc::TRILINEAR.with_anisotropy(4)),
+    ("anisotropic x16", SamplerDesc::TRILINEAR.with_anisotropy(16)),
+    ("nearest", SamplerDesc::NEAREST),
+];
+
+// Demo tilemap size in tiles, and the on-screen size of a tileset texel
+// in logical pixels
+const TILEMAP_WIDTH: u32 = 256;
+const TILEMAP_HEIGHT: u32 = 48;
+const TILEMAP_SCALE: f32 = 2.0;
+
+// Skinned glTF demo model, loaded at startup
+#[cfg(not(target_os = "android"))]
+const WORM_MODEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/res/worm.gltf");
+// from the APK's assets, res/ is packaged there
+#[cfg(target_os = "android")]
+const WORM_MODEL: &str = "worm.gltf";
+
+// How long switching the demo model's animation blends for
+const ANIMATION_FADE: Duration = Duration::from_millis(400);
+
+// How far in front of the camera dropped files appear
+const SPAWN_DISTANCE: f32 = 2.0;
+// how far above the terrain the camera stays
+const CAMERA_CLEARANCE: f32 = 0.5;
+// how far the split-screen's overhead camera is above the main one
+const SPLIT_SCREEN_HEIGHT: f32 = 20.0;
+// the sky's environment is captured again once the sun has moved this far
+// from where it was, about 2 degrees, as a cosine
+const ENVIRONMENT_RECAPTURE_COS: f32 = 0.9994;
+// the radius of what 1 and 2 dig out of or add to the voxels, in cells
+const VOXEL_EDIT_CELLS: f32 = 3.0;
+// how long the debug overlay's arrow for a directional light is
+const LIGHT_ARROW_LENGTH: f32 = 3.0;
+// seconds between updates of the frame rate in the window title
+const TITLE_FPS_INTERVAL: f32 = 0.5;
+
+// F5 / F9 save and load here unless a scene was loaded from elsewhere
+const DEFAULT_SCENE: &str = "scene.ron";
+
+// F12 writes screenshot-<unix time>.png to the working directory
+const SCREENSHOT_PREFIX: &str = "screenshot";
+// and 7 draws-<unix time>-<frame>.json, with the frame graph worked out
+// from it in graph-<unix time>-<frame>.dot and .json
+const DRAW_LIST_PREFIX: &str = "draws";
+const FRAME_GRAPH_PREFIX: &str = "graph";
 
-struct State<'a> {
-    surface: wgpu::Surface<'a>,
-    device: wgpu::Device,
+const NUM_INSTANCES_PER_ROW: u32 = 31;
+const INSTANCE_SPACING: f32 = 1.2;
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+impl WindowMode {
+    fn next(self) -> Self {
+        match self {
+            WindowMode::Windowed => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive => WindowMode::Windowed,
+        }
+    }
+}
+
+// What a render bundle of the instances was recorded from, see
+// `State::prepare_bundles`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct InstanceBundleKey {
+    pipeline: wgpu::Id<wgpu::RenderPipeline>,
+    targets: BundleTargets,
+    // the camera's and the materials'
+    bind_groups: [wgpu::Id<wgpu::BindGroup>; 2],
+    // the mesh's vertices and indices, and what the GPU culling left
+    buffers: [wgpu::Id<wgpu::Buffer>; 4],
+}
+
+pub struct State<'a> {
+    // kept to recreate the surface on resume
+    instance: wgpu::Instance,
+    adapter: wgpu::Adapter,
+    // None while the app is suspended
+    surface: Option<wgpu::Surface<'a>>,
+    device: Arc<wgpu::Device>,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
+    // what the 3D scene renders to, HDR until it's tonemapped into the
+    // surface format or its sRGB view
+    color_format: wgpu::TextureFormat,
+    // what the passes drawn straight into the scene draw to, when
+    // settings.ron asks for MSAA and the adapter has it
+    msaa: Option<MsaaTarget>,
+    tonemap: Tonemap,
+    // fixed unless it's auto, which follows the scene when on, toggled with E
+    exposure: Exposure,
+    auto_exposure: AutoExposure,
+    // after the tonemapping, toggled with '
+    color_grading: ColorGrading,
+    // where the tonemap's LUT came from, for saving the scene
+    color_lut: Option<PathBuf>,
+    // Some for linear surfaces that can't be viewed as sRGB, frames go
+    // through it to get gamma encoded
+    gamma_blit: Option<GammaBlit>,
+    // anti-aliasing of the 3D scene before the 2D overlay, toggled with X
+    fxaa: bool,
+    fxaa_pass: Fxaa,
+    // temporal anti-aliasing of the 3D scene, before FXAA when both are on,
+    // toggled with Z
+    taa: bool,
+    taa_pass: TemporalAa,
+    // screen-space reflections on the opaque models, toggled with Y
+    ssr: bool,
+    ssr_pass: ScreenSpaceReflections,
+    // blurs what's out of focus before tonemapping, from the G-buffer's
+    // depth, toggled with 0
+    depth_of_field: DepthOfField,
+    dof_pass: DofPass,
+    // smears what moves along TAA's velocity before tonemapping, toggled
+    // with ;
+    motion_blur: MotionBlur,
+    motion_blur_pass: MotionBlurPass,
+    // present modes the surface supports, used for the vsync toggle
+    present_modes: Vec<wgpu::PresentMode>,
+    alpha_modes: Vec<wgpu::CompositeAlphaMode>,
+    clear_color: wgpu::Color,
+    adapter_info: wgpu::AdapterInfo,
     size: winit::dpi::PhysicalSize<u32>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    index_buffer: wgpu::Buffer,
+    // physical pixels per logical one, 2D overlays are laid out in logical
+    // pixels
+    scale_factor: f64,
+    render_pipeline: ScenePipelines,
+    pipeline_cache: PipelineCache,
+    // what the background compiler needs to build scene permutations
+    render_pipeline_layout: Arc<wgpu::PipelineLayout>,
+    // scene pipeline permutations, keyed by the defines they add to the
+    // material ones
+    scene_variants: PipelineCompiler<ShaderDefs, ScenePipelines>,
+    scene_shading: ShaderDefs,
+    wireframe: WireframeMode,
+    // None when the device lacks POLYGON_MODE_LINE
+    wireframe_pipeline: Option<SampledPipeline<Arc<wgpu::RenderPipeline>>>,
+    // the instanced meshes' depth ahead of shading them, toggled with J
+    depth_prepass: bool,
+    prepass: DepthPrepass,
+    // the instances' GPU culled draws, recorded once for every pass they're
+    // in until something they draw with changes, toggled with 4
+    render_bundles: bool,
+    instance_bundles: BundleCache<InstanceBundleKey>,
+    vertex_buffer: Tracked<wgpu::Buffer>,
+    index_buffer: Tracked<wgpu::Buffer>,
     num_indices: u32,
+    // moved by handle, with only what's moved uploaded each frame
+    instances: Transforms,
+    // STORAGE as well as VERTEX so the culling pass can read it
+    instance_buffer: StorageBuffer<InstanceRaw>,
+    // the instances as they were last frame, for the velocity pass
+    prev_instance_buffer: StorageBuffer<InstanceRaw>,
+    gpu_culler: GpuCuller,
+    cpu_culler: CpuCuller,
+    // off draws every instance through `row_batch`, for comparison
+    culling: CullingMode,
+    // GPU culling against last frame's depth pyramid as well, toggled with F.
+    // It's built from the depth prepass, so it needs that on too.
+    occlusion_culling: bool,
+    depth_pyramid: DepthPyramid,
+    // which of `models` passed this frame's culling, in the same order
+    visible_models: Vec<bool>,
+    frame_stats: FrameStats,
+    lod_mode: LodMode,
+    // one multi-draw command per instance row
+    row_batch: IndirectBatch,
+    // CPU copy of the mesh for picking
+    mesh_bounds: Aabb,
+    mesh_triangles: Vec<[cgmath::Point3<f32>; 3]>,
+    mesh_object_id: ObjectId,
+    gpu_picker: GpuPicker,
+    camera: Camera,
+    projection: Projection,
+    camera_controller: CameraController,
+    camera_uniform: CameraUniform,
+    camera_buffer: wgpu::Buffer,
+    // what the camera draws, see `State::set_camera_layers`
+    camera_layers: RenderLayers,
+    // the main camera's part of the window, and the other cameras' drawn
+    // after it, each scaled into its rectangle by the compositor
+    main_viewport: ViewportRect,
+    viewports: Vec<Viewport>,
+    compositor: ViewportCompositor,
+    // the 3D scene's size as a fraction of the window's, picked from the
+    // GPU's frame times when it's dynamic
+    render_scale: RenderScale,
+    resolution_scaler: ResolutionScaler,
+    frame_timer: Option<GpuFrameTimer>,
+    // shared with the camera bind group, see `set_fog`
+    fog: Fog,
+    fog_buffer: wgpu::Buffer,
+    // the same, see `set_light`: the sun or the default light, the ambient,
+    // and the scene's other lights after them
+    light: Light,
+    ambient: [f32; 3],
+    lights: Vec<Light>,
+    light_buffers: LightBuffers,
+    // and what the host app pushes for shaders, see `set_external_uniforms`
+    external_uniforms: ExternalUniforms,
+    external_buffer: ExternalBuffer,
+    // and the environment's light, baked from the cubemap at `environment`
+    // when the scene has one, otherwise from the sky once the sun has moved
+    // far enough since `captured_sun`, or from the light's ambient. The
+    // cubemap is also drawn behind the scene instead of the sky.
+    ibl: Ibl,
+    environment: Option<(PathBuf, Skybox)>,
+    environment_stale: bool,
+    captured_sun: cgmath::Vector3<f32>,
+    // captured with the scene's capture pipelines and shared like the lights, and
+    // baked again after whatever they see changes
+    reflection_probes: ReflectionProbes,
+    probes_stale: bool,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    camera_bind_group: wgpu::BindGroup,
+    materials: MaterialTextures,
+    samplers: SamplerCache,
+    // index into FILTERING_PRESETS
+    filtering: usize,
+    particles: ParticleSystem,
+    model_renderer: ModelRenderer,
+    // how models with a translucent tint are drawn, cycled with O
+    transparency: TransparencyMode,
+    oit: WeightedBlendedOit,
+    // glTF models in the scene, the demo worm unless a scene replaced it
+    models: Vec<SceneModel>,
+    // only scenes have one
+    terrain: Option<Terrain>,
+    // only scenes have them, projected onto the terrain
+    decals: Option<Decals>,
+    // drawn whenever there's a terrain to receive the shadows
+    shadow_map: ShadowMap,
+    // only scenes have one, with its reflection and what's under it drawn
+    // off screen first
+    water: Option<Water>,
+    // only scenes have one, dug into with 1 and built up with 2
+    voxels: Option<VoxelTerrain>,
+    // only scenes have one, instead of the clear colour, and it moves the
+    // light
+    sky: Option<Sky>,
+    // per-frame uploads of model joint palettes and morph weights
+    staging_belt: wgpu::util::StagingBelt,
+    // how far ahead of the GPU the frame being recorded is, and what's
+    // waiting on frames in flight to be destroyed
+    frames: FramesInFlight,
+    // timestamps around the frame's passes, only with a profiling feature
+    gpu_profiler: GpuProfiler,
+    // where F5 saves the scene and F9 reloads it from
+    scene_path: PathBuf,
+    // None where shaders can't be reloaded from disk
+    shader_watcher: Option<ShaderWatcher>,
+    debug_draw: DebugDraw,
+    // bounds and axes overlay, toggled with B
+    show_debug: bool,
+    // what it draws, lights, shadows and decals toggled with F6 to F8
+    debug_categories: DebugCategories,
+    // the ground grid and the corner axes, for looking at models
+    grid: Grid,
+    show_grid: bool,
+    axis_gizmo: AxisGizmo,
+    show_axes: bool,
+    // screen-space HUD drawn over the scene
+    hud_atlas: TextureAtlas,
+    sprite_batch: SpriteBatch,
+    show_hud: bool,
+    // scrolling 2D backdrop drawn behind the scene
+    tilemap: Tilemap,
+    show_tilemap: bool,
+    // total time since startup, drives the HUD animation
+    elapsed: Duration,
+    text: TextRenderer,
+    show_stats: bool,
+    // smoothed frame time in seconds, for the stats readout
+    frame_time: f32,
+    // the selection: last instance picked with the right or middle mouse
+    // button, labelled and outlined
+    picked: Option<u32>,
+    selection_outline: SelectionOutline,
+    // F12 pressed, the next frame gets copied
+    screenshot_requested: bool,
+    // the next frame's draw list is written out, or every frame's
+    draw_dump_requested: bool,
+    dump_every_frame: bool,
+    // frames rendered so far
+    frame: u64,
+    // the copied frame with its size, on its way back
+    pending_screenshot: Option<(PendingReadback, wgpu::Extent3d)>,
+    // last frame's wgpu error, so a repeating one is only logged once
+    frame_error: Option<String>,
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<gamepad::GamepadInput>,
+    touch: TouchInput,
+    // rebuilt with every scene, stepped in `fixed_update`
+    #[cfg(feature = "physics")]
+    physics: physics::Physics,
+    // None runs gameplay logic once per frame with the variable dt
+    fixed_timestep: Option<FixedTimestep>,
+    frame_pacer: FramePacer,
+    // taken out while they're called, see `call_plugins`
+    plugins: Vec<Box<dyn Plugin>>,
+    // what the scene is finished in and the 2D overlay drawn to
+    frame_format: wgpu::TextureFormat,
+    // covered by other windows or otherwise hidden, as the compositor tells
+    occluded: bool,
+    window_mode: WindowMode,
+    // without the frame rate `fps_in_title` adds
+    title: String,
+    fps_in_title: bool,
+    // until the frame rate in the title is next updated
+    title_countdown: f32,
+    // mouse-look mode: cursor hidden and locked to the window
+    cursor_grabbed: bool,
+    pointers: Pointers,
+    actions: ActionMap,
+    // set by the quit action, the event loop exits once it's handled
+    // the events it has
+    exit_requested: bool,
+    settings_file: Option<SettingsFile>,
+    // what the settings file was last in step with
+    saved_settings: Settings,
+    // takes the app's settings as saved at the next sync without writing
+    // them, after changes the file shouldn't get, like a scene's
+    resync_settings: bool,
     // window must be declared after the surface
     // to control order of release
     window: &'a Window,
 }
 
+// Directory relative model paths in a scene file are resolved against
+fn scene_dir(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_owned).unwrap_or_default()
+}
+
+// One multi-draw command per NUM_INSTANCES_PER_ROW instances, the last
+// row may be short
+fn instance_rows(device: &wgpu::Device, queue: &wgpu::Queue, index_count: u32, instance_count: u32) -> IndirectBatch {
+    let rows = instance_count.div_ceil(NUM_INSTANCES_PER_ROW);
+    let mut row_batch = IndirectBatch::new(device, "Instance Row Batch", rows as usize);
+    for row in 0..rows {
+        let first_instance = row * NUM_INSTANCES_PER_ROW;
+        row_batch.push(wgpu::util::DrawIndexedIndirectArgs {
+            index_count,
+            instance_count: NUM_INSTANCES_PER_ROW.min(instance_count - first_instance),
+            first_index: 0,
+            base_vertex: 0,
+            first_instance,
+        });
+    }
+    row_batch.upload(device, queue);
+    row_batch
+}
+
+// Four 16x16 tiles side by side: two sky shades, dirt and grass
+fn demo_tileset() -> image::RgbaImage {
+    let tiles = [
+        texture::checkerboard(16, 2, [70, 110, 170, 255], [60, 100, 160, 255]),
+        texture::checkerboard(16, 4, [120, 150, 200, 255], [100, 130, 190, 255]),
+        texture::checkerboard(16, 4, [120, 80, 50, 255], [100, 65, 40, 255]),
+        texture::checkerboard(16, 8, [90, 170, 70, 255], [70, 140, 50, 255]),
+    ];
+    let mut tileset = image::RgbaImage::new(64, 16);
+    for (i, tile) in tiles.iter().enumerate() {
+        image::imageops::replace(&mut tileset, tile, i as i64 * 16, 0);
+    }
+    tileset
+}
+
+// A far layer of sky tiles with stripes of clouds and a rolling ground layer
+// in front of it, scrolling at different speeds
+fn demo_tile_layers() -> [TileLayer; 2] {
+    let mut sky = TileLayer::new(TILEMAP_WIDTH, TILEMAP_HEIGHT)
+        .with_parallax(cgmath::Vector2::new(0.3, 1.0))
+        .with_tint([0.8, 0.8, 0.9, 1.0]);
+    let mut ground = TileLayer::new(TILEMAP_WIDTH, TILEMAP_HEIGHT);
+    for x in 0..TILEMAP_WIDTH {
+        for y in 0..TILEMAP_HEIGHT {
+            let cloud = (x / 6 + y / 3) % 5 == 0 && y < TILEMAP_HEIGHT / 2;
+            sky.set(x, y, if cloud { 2 } else { 1 });
+        }
+        let hill = 6.0 + 4.0 * (x as f32 * 0.12).sin() + 2.0 * (x as f32 * 0.31).sin();
+        let surface = TILEMAP_HEIGHT - hill as u32;
+        ground.set(x, surface, 4);
+        for y in surface + 1..TILEMAP_HEIGHT {
+            ground.set(x, y, 3);
+        }
+    }
+    [sky, ground]
+}
+
+// Records `draw` in a debug group, so captures show what each run of draw
+// calls is for
+fn debug_group<'p>(render_pass: &mut TracedPass<'p>, label: &str, draw: impl FnOnce(&mut TracedPass<'p>)) {
+    render_pass.push_debug_group(label);
+    draw(render_pass);
+    render_pass.pop_debug_group();
+}
+
+// A light's colour brought up to full brightness, so dim lights show up in
+// the debug overlay as well as bright ones
+fn gizmo_color(color: [f32; 3], alpha: f32) -> [f32; 4] {
+    let brightest = color.into_iter().fold(f32::EPSILON, f32::max);
+    let [r, g, b] = color.map(|channel| channel / brightest);
+    [r, g, b, alpha]
+}
+
+// Everything in the camera bind group after the camera itself, for bind
+// groups that see the scene from somewhere else
+fn shared_camera_entries<'a>(
+    fog_buffer: &'a wgpu::Buffer,
+    lights: &'a LightBuffers,
+    ibl: &'a Ibl,
+    probes: &'a ReflectionProbes,
+    external: &'a ExternalBuffer,
+) -> Vec<wgpu::BindGroupEntry<'a>> {
+    let mut entries = scene_camera_entries(fog_buffer, lights, probes, external);
+    entries.extend(ibl.bind_group_entries());
+    entries
+}
+
+// The same but for the image based lighting's maps, which its environment
+// captures bind their own way
+fn scene_camera_entries<'a>(
+    fog_buffer: &'a wgpu::Buffer,
+    lights: &'a LightBuffers,
+    probes: &'a ReflectionProbes,
+    external: &'a ExternalBuffer,
+) -> Vec<wgpu::BindGroupEntry<'a>> {
+    let mut entries = vec![wgpu::BindGroupEntry {
+        binding: 1,
+        resource: fog_buffer.as_entire_binding(),
+    }];
+    entries.extend(lights.bind_group_entries());
+    entries.extend(probes.bind_group_entries());
+    entries.push(external.bind_group_entry());
+    entries
+}
+
+// Picks the frame up where an earlier pass on `view` left it, after one
+// that needed other attachments
+fn continue_pass<'e>(encoder: &'e mut wgpu::CommandEncoder, view: &'e wgpu::TextureView) -> TracedPass<'e> {
+    TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+        label: Some("Overlay Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        ..Default::default()
+    })
+}
+
+// The scene shading that shows texture coordinates as colours
+fn uv_debug_shading(enabled: bool) -> ShaderDefs {
+    if enabled {
+        ShaderDefs::new().with("UV_DEBUG")
+    } else {
+        ShaderDefs::new()
+    }
+}
+
+// The scene shader with `shading` on top of the material defines, checked
+// against the layouts, uniforms and vertex buffers it's drawn with
+fn load_scene_shader(materials: &MaterialTextures, shading: &ShaderDefs) -> anyhow::Result<PreprocessedShader> {
+    use anyhow::Context;
+
+    let defs = materials.shader_defs().union(shading);
+    let shader = shader_preprocessor::load("shader.wgsl", &defs)?;
+    let reflection = ShaderReflection::new(&shader)?;
+    let check = || -> anyhow::Result<()> {
+        reflection.check_group(0, &camera::layout_entries())?;
+        reflection.check_group(1, materials.layout_entries())?;
+        reflection.check_struct::<CameraUniform>("CameraUniform")?;
+        reflection.check_struct::<FogUniform>("FogUniform")?;
+        reflection.check_vertex_buffers("vs_main", &[Vertex::desc(), InstanceRaw::desc()])
+    };
+    check().with_context(|| format!("shader.wgsl with {} doesn't match the scene pipelines", defs))?;
+    Ok(shader)
+}
+
+// A scene shading drawn on its own, over the depth prepass, and into a
+// face of a cubemap
+struct ScenePipelines {
+    fill: SampledPipeline<Arc<wgpu::RenderPipeline>>,
+    depth_equal: Arc<wgpu::RenderPipeline>,
+    capture: Arc<wgpu::RenderPipeline>,
+}
+
+impl ScenePipelines {
+    fn new(
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        format: wgpu::TextureFormat,
+        samples: u32,
+        mut create: impl FnMut(&wgpu::RenderPipelineDescriptor) -> Arc<wgpu::RenderPipeline>,
+    ) -> Self {
+        let fill = wgpu::PolygonMode::Fill;
+        let depth_equal = Some(DepthPrepass::depth_equal());
+        Self {
+            fill: SampledPipeline::new(samples, |multisample| {
+                with_scene_pipeline(layout, shader, "fs_main", format, fill, None, |desc| {
+                    create(&wgpu::RenderPipelineDescriptor { multisample, ..desc.clone() })
+                })
+            }),
+            depth_equal: with_scene_pipeline(layout, shader, "fs_main", format, fill, depth_equal, &mut create),
+            capture: with_scene_pipeline(layout, shader, "fs_main", format, fill, None, |desc| {
+                create(&wgpu::RenderPipelineDescriptor {
+                    primitive: wgpu::PrimitiveState {
+                        front_face: render_target::CUBE_FACE_FRONT_FACE,
+                        ..desc.primitive
+                    },
+                    ..desc.clone()
+                })
+            }),
+        }
+    }
+}
+
+// The main scene pipelines, and the wireframe one where the device has line
+// polygon mode; without it wireframes fall back to debug lines
+fn scene_pipelines(
+    device: &wgpu::Device,
+    pipeline_cache: &mut PipelineCache,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    samples: u32,
+) -> (ScenePipelines, Option<SampledPipeline<Arc<wgpu::RenderPipeline>>>) {
+    let render_pipeline =
+        ScenePipelines::new(layout, shader, format, samples, |desc| pipeline_cache.render_pipeline(device, desc));
+    let wireframe_pipeline = wireframe::supported(device).then(|| {
+        SampledPipeline::new(samples, |multisample| {
+            with_scene_pipeline(layout, shader, "fs_wireframe", format, wgpu::PolygonMode::Line, None, |desc| {
+                pipeline_cache.render_pipeline(device, &wgpu::RenderPipelineDescriptor { multisample, ..desc.clone() })
+            })
+        })
+    });
+    (render_pipeline, wireframe_pipeline)
+}
+
+// The scene pipeline's descriptor, shared by the main pipeline and the
+// debug permutations compiled in the background. Only the fragment entry
+// point differs between them.
+fn with_scene_pipeline<R>(
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fs_entry: &str,
+    format: wgpu::TextureFormat,
+    polygon_mode: wgpu::PolygonMode,
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    f: impl FnOnce(&wgpu::RenderPipelineDescriptor) -> R,
+) -> R {
+    f(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[Vertex::desc(), InstanceRaw::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fs_entry,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend: Some(wgpu::BlendState {
+                    color: wgpu::BlendComponent::REPLACE,
+                    alpha: wgpu::BlendComponent::REPLACE,
+                }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: Some(wgpu::Face::Back),
+            polygon_mode,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil, // only over the depth prepass
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false, // used when supporting anti-aliasing
+        },
+        multiview: None, // no render to texture arrays
+        cache: None, // filled in by the pipeline cache
+    })
+}
+
 impl<'a> State<'a> {
     // Creating some wgpu types requires async code
-    async fn new(window: &'a Window) -> State<'a> {
+    async fn new(window: &'a Window, app_config: &Config) -> State<'a> {
         let size = window.inner_size();
 
         // Get a handle to our GPU
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor { 
-            backends: wgpu::Backends::PRIMARY,
+            backends: app_config.backends,
             ..Default::default()
         });
 
         let surface = instance.create_surface(window).unwrap();
 
-        let adapter = instance.request_adapter(
+        let adapter = adapter::select_adapter(
+            &instance,
+            app_config.backends,
+            app_config.adapter.as_ref(),
             &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                force_fallback_adapter: false, 
+                power_preference: app_config.power_preference,
+                force_fallback_adapter: app_config.force_fallback_adapter,
                 compatible_surface: Some(&surface)
             },
-        ).await.unwrap();
+        ).await.unwrap_or_else(|e| panic!("{:#}", e));
+        let adapter_info = adapter.get_info();
+        log::info!("Using {} ({:?}, {:?})", adapter_info.name, adapter_info.backend, adapter_info.device_type);
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                label: None,
+                required_features: indirect::optional_features(&adapter)
+                    | draw_data::optional_features(&adapter)
+                    | dynamic_resolution::optional_features(&adapter)
+                    | material::optional_features(&adapter)
+                    | msaa::optional_features(&adapter)
+                    | pipeline_cache::optional_features(&adapter)
+                    | profiler::optional_features(&adapter)
+                    | texture::optional_features(&adapter)
+                    | wireframe::optional_features(&adapter),
+                required_limits: wgpu::Limits {
+                    max_push_constant_size: draw_data::push_constant_size(&adapter),
+                    ..Default::default()
+                },
+                label: Some("Device"),
                 memory_hints: Default::default(),
             },
             None, // Trace path
         ).await.unwrap();
+        // shared with the background pipeline compiler
+        let device = Arc::new(device);
+        error_scope::log_uncaptured(&device);
+        let creation_scope = ErrorScope::push(&device, "Creating the renderer");
 
         let surface_caps = surface.get_capabilities(&adapter);
-        // Shader code in this tutorial assumes an sRGB surface texture. Using a different
-        // one will result in all the colors coming out darker. If you want to support non
-        // sRGB surfaces, you'll need to account for that when drawing to the frame.
-        let surface_format = surface_caps.formats.iter()
-            .find(|f| f.is_srgb())
-            .copied()
-            .unwrap_or(surface_caps.formats[0]);
+        // Shaders output linear colour and count on an sRGB target to encode it, so
+        // linear surfaces get an sRGB view or a gamma encoding pass
+        let surface_formats = SurfaceFormats::choose(&surface_caps, &adapter.get_downlevel_capabilities());
+        log::info!(
+            "Surface format {:?}, rendering to {:?} ({:?})",
+            surface_formats.surface, surface_formats.render, surface_formats.encoding
+        );
+        // what the 3D scene renders to, and what it's tonemapped to for the
+        // 2D overlay to go on top
+        let color_format = tonemap::HDR_FORMAT;
+        let frame_format = surface_formats.render;
+        let msaa_samples = msaa::supported_samples(&adapter, &device, color_format, app_config.settings.msaa_samples);
+        log::info!("MSAA: {} samples", msaa_samples);
+        let msaa = (msaa_samples > 1)
+            .then(|| MsaaTarget::new(&device, color_format, msaa_samples, size.width, size.height));
+        // Fifo (vsync) is the only mode guaranteed to be supported everywhere
+        let present_mode = match app_config.present_mode {
+            Some(mode) if surface_caps.present_modes.contains(&mode) => mode,
+            _ if surface_caps.present_modes.contains(&wgpu::PresentMode::Fifo) => wgpu::PresentMode::Fifo,
+            _ => surface_caps.present_modes[0],
+        };
+        if app_config.present_mode.is_some_and(|mode| mode != present_mode) {
+            log::warn!("Present mode {:?} is not supported by this surface", app_config.present_mode.unwrap());
+        }
+        // compositing needs the alpha channel to mean something
+        let alpha_mode = match app_config.alpha_mode {
+            // resolved by wgpu, never listed in the capabilities
+            Some(wgpu::CompositeAlphaMode::Auto) => wgpu::CompositeAlphaMode::Auto,
+            Some(mode) if surface_caps.alpha_modes.contains(&mode) => mode,
+            None if app_config.window.transparent => TRANSPARENT_ALPHA_MODES
+                .into_iter()
+                .find(|mode| surface_caps.alpha_modes.contains(mode))
+                .unwrap_or_else(|| {
+                    log::warn!("This surface can't be composited, the window stays opaque");
+                    surface_caps.alpha_modes[0]
+                }),
+            Some(mode) => {
+                log::warn!("Alpha mode {:?} is not supported by this surface", mode);
+                surface_caps.alpha_modes[0]
+            }
+            None => surface_caps.alpha_modes[0],
+        };
         let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
+            // copyable where possible, for screenshots
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | (surface_caps.usages & wgpu::TextureUsages::COPY_SRC),
+            format: surface_formats.surface,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![],
+            present_mode,
+            alpha_mode,
+            view_formats: surface_formats.view_formats(),
             desired_maximum_frame_latency: 2,
         };
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("Shader"),
-            source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+        let material_images = [
+            texture::checkerboard(256, 8, [230, 230, 230, 255], [40, 40, 40, 255]),
+            texture::checkerboard(128, 4, [240, 180, 60, 255], [120, 60, 20, 255]),
+            texture::checkerboard(64, 2, [90, 200, 120, 255], [20, 80, 40, 255]),
+            texture::checkerboard(32, 16, [100, 140, 240, 255], [30, 40, 90, 255]),
+        ];
+        let mut samplers = SamplerCache::new();
+        let materials = MaterialTextures::new(
+            &device,
+            &queue,
+            &material_images,
+            &mut samplers,
+            FILTERING_PRESETS[0].1,
+        );
+        log::info!("Material textures: {:?}", materials.mode());
+
+        let shader = load_scene_shader(&materials, &ShaderDefs::new())
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(&device, "Shader");
+
+        let camera = Camera::new((0.0, 0.0, 2.0), cgmath::Deg(-90.0), cgmath::Deg(0.0));
+        let projection = Projection::new(size.width, size.height, cgmath::Deg(45.0), 0.1, 100.0);
+        let camera_controller = CameraController::new(2.0, 2.0, 0.002);
+
+        let mut camera_uniform = CameraUniform::new();
+        camera_uniform.update_view_proj(&camera, &projection);
+
+        let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Camera Buffer"),
+            contents: bytemuck::cast_slice(&[camera_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-            label: Some("Render Pipeline Layout"),
-            bind_group_layouts: &[],
-            push_constant_ranges: &[] 
+        let fog = Fog::default();
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Fog Buffer"),
+            contents: bytemuck::cast_slice(&[FogUniform::from(&fog)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
+        let light = Light::default();
+        let light_buffers = LightBuffers::new(&device);
+        light_buffers.upload(&queue, lighting::AMBIENT, &[light], None);
 
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            label: Some("Render Pipeline"),
-            layout: Some(&render_pipeline_layout),
-            vertex: wgpu::VertexState { 
-                module: &shader, 
-                entry_point: "vs_main", 
-                buffers: &[
-                    Vertex::desc(),
-                ],
-                compilation_options: Default::default(), 
-            },
-            fragment: Some(wgpu::FragmentState { 
-                module: &shader, 
-                entry_point: "fs_main", 
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: config.format,
-                    blend: Some(wgpu::BlendState {
-                        color: wgpu::BlendComponent::REPLACE,
-                        alpha: wgpu::BlendComponent::REPLACE,
-                    }),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
-                compilation_options: Default::default(),
-             }),
-             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::TriangleList,
-                strip_index_format: None,
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: Some(wgpu::Face::Back),
-                polygon_mode: wgpu::PolygonMode::Fill,
-                unclipped_depth: false,
-                conservative: false,
-             },
-             depth_stencil: None,                   // no depth/stencil buffers yet
-             multisample: wgpu::MultisampleState { 
-                count: 1, 
-                mask: !0, 
-                alpha_to_coverage_enabled: false    // used when supporting anti-aliasing
-            },
-            multiview: None,                        // no render to texture arrays
-            cache: None                             // only for Android build targets
+        let camera_bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Camera Bind Group Layout"),
+            entries: &camera::layout_entries(),
+        });
+
+        let reflection_probes = ReflectionProbes::new(&device);
+        reflection_probes.upload(&queue);
+        let external_buffer = ExternalBuffer::new(&device);
+        let ibl = Ibl::new(
+            &device,
+            &queue,
+            &camera_bind_group_layout,
+            &scene_camera_entries(&fog_buffer, &light_buffers, &reflection_probes, &external_buffer),
+            lighting::AMBIENT,
+        );
+        let mut camera_entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera_buffer.as_entire_binding(),
+        }];
+        camera_entries.extend(shared_camera_entries(
+            &fog_buffer,
+            &light_buffers,
+            &ibl,
+            &reflection_probes,
+            &external_buffer,
+        ));
+        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Camera Bind Group"),
+            layout: &camera_bind_group_layout,
+            entries: &camera_entries,
         });
 
+        let render_pipeline_layout = Arc::new(device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Render Pipeline Layout"),
+            bind_group_layouts: &[&camera_bind_group_layout, materials.layout()],
+            push_constant_ranges: &[] 
+        }));
+
+        let mut pipeline_cache = PipelineCache::new(
+            &device,
+            &adapter_info,
+            &pipeline_cache::default_cache_dir(),
+        );
+
+        let (render_pipeline, wireframe_pipeline) =
+            scene_pipelines(&device, &mut pipeline_cache, &render_pipeline_layout, &shader, color_format, msaa_samples);
+        let scene_variants = PipelineCompiler::new(device.clone(), pipeline_cache.shared_cache());
+        let prepass = DepthPrepass::new(
+            &device,
+            size.width,
+            size.height,
+            &render_pipeline_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+        );
+
         let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Vertex Buffer"),
             contents: bytemuck::cast_slice(VERTICES),
@@ -184,22 +1074,475 @@ impl<'a> State<'a> {
 
         let num_indices = INDICES.len() as u32;
 
-        Self {
-            surface,
+        let instances = instance::grid(NUM_INSTANCES_PER_ROW, INSTANCE_SPACING, materials.count());
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_buffer = StorageBuffer::from_slice_with_usage(
+            &device,
+            "Instance Buffer",
+            &instance_data,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let prev_instance_buffer = StorageBuffer::from_slice_with_usage(
+            &device,
+            "Previous Instance Buffer",
+            &instance_data,
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        let positions: Vec<cgmath::Point3<f32>> = VERTICES.iter()
+            .map(|v| v.position.into())
+            .collect();
+        let mesh_bounds = Aabb::from_points(positions.iter().copied()).unwrap();
+        let mesh_triangles = INDICES.chunks_exact(3)
+            .map(|tri| [0, 1, 2].map(|i| positions[tri[i] as usize]))
+            .collect();
+
+        let mut gpu_picker = GpuPicker::new(
+            &device,
+            size.width,
+            size.height,
+            &camera_bind_group_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+        );
+        let mesh_object_id = gpu_picker.create_object_id(&device, &queue, 0);
+        let selection_outline = SelectionOutline::new(
+            &device,
+            color_format,
+            size.width,
+            size.height,
+            &camera_bind_group_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+        );
+        let taa_pass = TemporalAa::new(
+            &device,
+            color_format,
+            size.width,
+            size.height,
+            &camera_bind_group_layout,
+            &[Vertex::desc(), InstanceRaw::desc(), InstanceRaw::previous_desc()],
+        );
+        let motion_blur_pass = MotionBlurPass::new(&device, size.width, size.height, taa_pass.velocity_view());
+        let ssr_pass = ScreenSpaceReflections::new(
+            &device,
+            color_format,
+            size.width,
+            size.height,
+            &camera_bind_group_layout,
+            &[Vertex::desc(), InstanceRaw::desc()],
+        );
+        let dof_pass = DofPass::new(&device, size.width, size.height, ssr_pass.depth_view());
+
+        let depth_pyramid = DepthPyramid::new(&device, prepass.depth_view(), size.width, size.height);
+        let bounds = Sphere::from(mesh_bounds);
+        let gpu_culler = GpuCuller::new(&device, &instance_buffer, bounds, num_indices, &depth_pyramid);
+        let cpu_culler = CpuCuller::new(&device, &instances, mesh_bounds, num_indices);
+
+        let row_batch = instance_rows(&device, &queue, num_indices, instances.len() as u32);
+        log::info!("Multi-draw mode: {:?}", row_batch.mode());
+
+        let mut particles = ParticleSystem::new(
+            &device,
+            color_format,
+            msaa_samples,
+            &camera_bind_group_layout,
+            10_000,
+            EmitterParams::default(),
+        );
+        // off until toggled with P
+        particles.emitting = false;
+
+        let model_renderer = ModelRenderer::new(&device, &queue, color_format, msaa_samples, &camera_bind_group_layout);
+        let oit = WeightedBlendedOit::new(&device, color_format, msaa_samples, size.width, size.height);
+        let placement = NodeTransform {
+            translation: cgmath::Vector3::new(0.6, -0.6, 0.8),
+            ..Default::default()
+        };
+        let models = match SceneModel::load(&device, &queue, &model_renderer, WORM_MODEL, placement) {
+            Ok(mut worm) => {
+                worm.play(Some("Wiggle"));
+                vec![worm]
+            }
+            Err(e) => {
+                log::warn!("{:#}", e);
+                Vec::new()
+            }
+        };
+
+        let shadow_map = ShadowMap::new(&device, &[Vertex::desc(), InstanceRaw::desc()], ShadowQuality::default());
+
+        let debug_draw = DebugDraw::new(&device, color_format, msaa_samples, &camera_bind_group_layout);
+        let grid = Grid::new(&device, color_format, msaa_samples, &camera_bind_group_layout);
+        let axis_gizmo = AxisGizmo::new(&device, frame_format);
+
+        let mut hud_builder = AtlasBuilder::new(device.limits().max_texture_dimension_2d);
+        hud_builder.add("solid", image::RgbaImage::from_pixel(8, 8, image::Rgba([255; 4])));
+        hud_builder.add("icon", texture::checkerboard(32, 4, [250, 210, 80, 255], [200, 90, 30, 255]));
+        let hud_atlas = hud_builder.build(&device, &queue, "HUD Atlas").unwrap();
+        let sprite_batch = SpriteBatch::new(&device, frame_format, &hud_atlas.texture);
+
+        let text = TextRenderer::new(&device, &queue, frame_format);
+
+        let gamma_blit = (surface_formats.encoding == gamma::SurfaceEncoding::Blit)
+            .then(|| GammaBlit::new(&device, &surface_formats, size.width, size.height));
+        let fxaa_pass = Fxaa::new(&device, frame_format, size.width, size.height);
+        let compositor = ViewportCompositor::new(&device, frame_format, size.width, size.height);
+        let tonemap = Tonemap::new(&device, &queue, frame_format, size.width, size.height);
+        let exposure = Exposure::default();
+        tonemap.set_exposure(&queue, &exposure);
+        let auto_exposure = AutoExposure::new(&device, &tonemap, size.width, size.height);
+
+        let tileset = texture::Texture::from_rgba(&device, &queue, &demo_tileset(), 64, 16, Some("Tileset"));
+        let mut tilemap = Tilemap::new(&device, color_format, msaa_samples, &tileset, 16, 16).unwrap();
+        tilemap.scale = TILEMAP_SCALE;
+        for layer in demo_tile_layers() {
+            tilemap.add_layer(&device, &layer);
+        }
+
+        // everything is compiled by now, persist it for the next launch
+        if let Err(e) = pipeline_cache.save() {
+            log::warn!("Failed to save pipeline cache: {}", e);
+        }
+
+        let gpu_profiler = GpuProfiler::new(adapter_info.backend, &device, &queue);
+        let frame_timer = GpuFrameTimer::new(&device, &queue);
+        creation_scope.pop().unwrap_or_else(|e| panic!("{:#}", e));
+
+        let mut state = Self {
+            instance,
+            adapter,
+            surface: Some(surface),
             device,
             queue,
             size,
+            scale_factor: 1.0,
             config,
+            color_format,
+            msaa,
+            tonemap,
+            exposure,
+            auto_exposure,
+            color_grading: ColorGrading::default(),
+            color_lut: None,
+            gamma_blit,
+            fxaa: false,
+            fxaa_pass,
+            taa: false,
+            taa_pass,
+            ssr: false,
+            ssr_pass,
+            depth_of_field: DepthOfField::default(),
+            dof_pass,
+            motion_blur: MotionBlur::default(),
+            motion_blur_pass,
+            present_modes: surface_caps.present_modes,
+            alpha_modes: surface_caps.alpha_modes,
+            clear_color: if app_config.window.transparent { wgpu::Color::TRANSPARENT } else { CLEAR_COLOR },
+            adapter_info,
             render_pipeline,
-            vertex_buffer,
-            index_buffer,
+            pipeline_cache,
+            render_pipeline_layout,
+            scene_variants,
+            scene_shading: ShaderDefs::new(),
+            wireframe: WireframeMode::Off,
+            wireframe_pipeline,
+            depth_prepass: false,
+            prepass,
+            render_bundles: true,
+            instance_bundles: BundleCache::new(),
+            vertex_buffer: Tracked::buffer(ResourceKind::Mesh, vertex_buffer),
+            index_buffer: Tracked::buffer(ResourceKind::Mesh, index_buffer),
             num_indices,
+            instances: Transforms::new(instances),
+            instance_buffer,
+            prev_instance_buffer,
+            gpu_culler,
+            cpu_culler,
+            culling: CullingMode::Gpu,
+            occlusion_culling: false,
+            depth_pyramid,
+            visible_models: Vec::new(),
+            frame_stats: FrameStats::default(),
+            lod_mode: LodMode::CrossFade,
+            row_batch,
+            mesh_bounds,
+            mesh_triangles,
+            mesh_object_id,
+            gpu_picker,
+            camera,
+            projection,
+            camera_controller,
+            camera_uniform,
+            camera_buffer,
+            camera_layers: RenderLayers::ALL,
+            main_viewport: ViewportRect::FULL,
+            viewports: Vec::new(),
+            compositor,
+            render_scale: RenderScale::default(),
+            resolution_scaler: ResolutionScaler::default(),
+            frame_timer,
+            fog,
+            fog_buffer,
+            light,
+            ambient: lighting::AMBIENT,
+            lights: Vec::new(),
+            light_buffers,
+            external_uniforms: ExternalUniforms::default(),
+            external_buffer,
+            ibl,
+            environment: None,
+            environment_stale: false,
+            captured_sun: cgmath::Vector3::unit_y(),
+            reflection_probes,
+            probes_stale: false,
+            camera_bind_group_layout,
+            camera_bind_group,
+            materials,
+            samplers,
+            filtering: 0,
+            particles,
+            model_renderer,
+            transparency: TransparencyMode::Sorted,
+            oit,
+            models,
+            terrain: None,
+            decals: None,
+            shadow_map,
+            water: None,
+            voxels: None,
+            sky: None,
+            staging_belt: wgpu::util::StagingBelt::new(upload::STAGING_CHUNK_SIZE),
+            frames: FramesInFlight::default(),
+            gpu_profiler,
+            scene_path: PathBuf::from(DEFAULT_SCENE),
+            shader_watcher: ShaderWatcher::new(),
+            debug_draw,
+            show_debug: false,
+            debug_categories: DebugCategories::default(),
+            grid,
+            show_grid: false,
+            axis_gizmo,
+            show_axes: false,
+            hud_atlas,
+            sprite_batch,
+            show_hud: true,
+            tilemap,
+            show_tilemap: false,
+            elapsed: Duration::ZERO,
+            text,
+            show_stats: true,
+            frame_time: 0.0,
+            picked: None,
+            selection_outline,
+            screenshot_requested: false,
+            draw_dump_requested: false,
+            dump_every_frame: false,
+            frame: 0,
+            pending_screenshot: None,
+            frame_error: None,
+            #[cfg(feature = "gamepad")]
+            gamepad: gamepad::GamepadInput::new(),
+            touch: TouchInput::new(),
+            #[cfg(feature = "physics")]
+            physics: physics::Physics::default(),
+            fixed_timestep: Some(FixedTimestep::default()),
+            frame_pacer: FramePacer::new(app_config.max_fps),
+            plugins: Vec::new(),
+            frame_format,
+            occluded: false,
+            window_mode: WindowMode::Windowed,
+            title: app_config.window.title.clone(),
+            fps_in_title: app_config.window.fps_in_title,
+            title_countdown: 0.0,
+            cursor_grabbed: false,
+            pointers: Pointers::new(),
+            actions: ActionMap::default(),
+            exit_requested: false,
+            settings_file: None,
+            saved_settings: Settings::default(),
+            resync_settings: true,
             window,
-        }
+        };
+        state.set_scale_factor(window.scale_factor());
+        state
     }
 
     pub fn window(&self) -> &Window {
-        &self.window
+        self.window
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        &self.device
+    }
+
+    pub fn queue(&self) -> &wgpu::Queue {
+        &self.queue
+    }
+
+    // The format of the view plugins draw to, see `RenderContext`
+    pub fn frame_format(&self) -> wgpu::TextureFormat {
+        self.frame_format
+    }
+
+    // Attaches it, after the plugins added before it
+    pub fn add_plugin(&mut self, mut plugin: Box<dyn Plugin>) {
+        plugin.on_attach(self);
+        self.plugins.push(plugin);
+    }
+
+    // The plugins are moved out while `call` runs, so each can be handed
+    // the state. Any added meanwhile go after them.
+    fn call_plugins<R>(&mut self, call: impl FnOnce(&mut [Box<dyn Plugin>], &mut Self) -> R) -> R {
+        let mut plugins = std::mem::take(&mut self.plugins);
+        let result = call(&mut plugins, self);
+        plugins.append(&mut self.plugins);
+        self.plugins = plugins;
+        result
+    }
+
+    // True if a plugin used the event, which stops it there
+    fn plugin_event(&mut self, event: &Event<()>) -> bool {
+        self.call_plugins(|plugins, state| plugins.iter_mut().any(|plugin| plugin.on_event(state, event)))
+    }
+
+    // Lines queued here are drawn over the scene this frame only
+    pub fn debug_draw(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn debug_categories(&self) -> DebugCategories {
+        self.debug_categories
+    }
+
+    // What the overlay draws from the next frame on, while it's shown
+    pub fn set_debug_categories(&mut self, categories: DebugCategories) {
+        self.debug_categories = categories;
+    }
+
+    // Turning a category on shows the overlay too, if it was hidden
+    fn toggle_debug_category(&mut self, category: DebugCategories) {
+        self.debug_categories = self.debug_categories.toggled(category);
+        if self.debug_categories.contains(category) {
+            self.show_debug = true;
+        }
+        log::info!("Debug overlay: {:?}", self.debug_categories);
+    }
+
+    // The ground grid `show_grid` draws, to restyle
+    pub fn grid(&mut self) -> &mut Grid {
+        &mut self.grid
+    }
+
+    pub fn show_grid(&self) -> bool {
+        self.show_grid
+    }
+
+    pub fn set_show_grid(&mut self, show: bool) {
+        self.show_grid = show;
+    }
+
+    pub fn show_axes(&self) -> bool {
+        self.show_axes
+    }
+
+    pub fn set_show_axes(&mut self, show: bool) {
+        self.show_axes = show;
+    }
+
+    // Text queued here before the update is drawn over the frame, this
+    // frame only
+    pub fn text(&mut self) -> &mut TextRenderer {
+        &mut self.text
+    }
+
+    // Pipelines built here share the on-disk driver cache
+    pub fn pipeline_cache(&mut self) -> &mut PipelineCache {
+        &mut self.pipeline_cache
+    }
+
+    // Drivers may keep compiling variants lazily, so this is worth
+    // calling again on shutdown and not just after startup
+    pub fn save_pipeline_cache(&self) {
+        if let Err(e) = self.pipeline_cache.save() {
+            log::warn!("Failed to save pipeline cache: {}", e);
+        }
+    }
+
+    pub fn set_fixed_timestep(&mut self, timestep: Option<FixedTimestep>) {
+        self.fixed_timestep = timestep;
+    }
+
+    pub fn max_fps(&self) -> Option<f64> {
+        self.frame_pacer.max_fps()
+    }
+
+    // None leaves the frame rate to the present mode
+    pub fn set_max_fps(&mut self, max_fps: Option<f64>) {
+        self.frame_pacer.set_max_fps(max_fps);
+        match self.frame_pacer.max_fps() {
+            Some(fps) => log::info!("Frame rate capped at {} fps", fps),
+            None => log::info!("Frame rate uncapped"),
+        }
+    }
+
+    // Steps through no cap -> 30 -> 60 -> 144 fps
+    pub fn cycle_max_fps(&mut self) {
+        const CYCLE: [Option<f64>; 4] = [None, Some(30.0), Some(60.0), Some(144.0)];
+        let current = CYCLE.iter().position(|fps| *fps == self.max_fps()).unwrap_or(0);
+        self.set_max_fps(CYCLE[(current + 1) % CYCLE.len()]);
+    }
+
+    // Nothing would be seen of a frame: suspended, minimized or occluded
+    pub fn is_hidden(&self) -> bool {
+        let size = self.window.inner_size();
+        self.surface.is_none() || self.occluded || size.width == 0 || size.height == 0
+    }
+
+    fn configure_surface(&self) {
+        if let Some(surface) = &self.surface {
+            let label = format!("Configuring the surface for {:?}", self.config.format);
+            if let Err(e) = error_scope::check(&self.device, label, || surface.configure(&self.device, &self.config)) {
+                log::error!("{:#}", e);
+            }
+        }
+    }
+
+    // Android destroys the native window when the app goes to the
+    // background, and some compositors do the same when minimizing, so the
+    // surface has to go with it
+    pub fn suspend(&mut self) {
+        if self.surface.take().is_some() {
+            log::info!("Suspended, surface dropped");
+            // the process may be killed without another event
+            self.save_pipeline_cache();
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if self.surface.is_some() {
+            return;
+        }
+        let surface = match self.instance.create_surface(self.window) {
+            Ok(surface) => surface,
+            Err(e) => {
+                log::error!("Failed to recreate the surface: {}", e);
+                return;
+            }
+        };
+        // every pipeline was built for this format
+        let formats = surface.get_capabilities(&self.adapter).formats;
+        if !formats.contains(&self.config.format) {
+            log::error!("The new surface doesn't support {:?}", self.config.format);
+        }
+        self.surface = Some(surface);
+        log::info!("Resumed, surface recreated");
+        // the window may have changed size while suspended
+        let size = self.window.inner_size();
+        self.resize(size);
+        // resize skips empty sizes, keep the old one then
+        if self.size != size {
+            self.configure_surface();
+        }
+        self.window.request_redraw();
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -207,114 +1550,3331 @@ impl<'a> State<'a> {
             self.size = new_size;
             self.config.width = new_size.width;
             self.config.height = new_size.height;
-            self.surface.configure(&self.device, &self.config);
+            self.configure_surface();
+            self.fit_projections();
+            let label = format!("Resizing to {}x{}", new_size.width, new_size.height);
+            let scope = ErrorScope::push(&self.device, label);
+            if let Some(gamma_blit) = &mut self.gamma_blit {
+                gamma_blit.resize(&self.device, new_size.width, new_size.height);
+            }
+            // picks are in the window's pixels, whatever the scene's drawn at
+            self.gpu_picker.resize(&self.device, new_size.width, new_size.height);
+            if let Err(e) = scope.pop() {
+                log::error!("{:#}", e);
+            }
+            self.resize_targets();
         }
     }
 
-    fn input(&mut self, _event: &WindowEvent) -> bool {
-        false
+    // The 3D scene's targets, at the render size
+    fn resize_targets(&mut self) {
+        let size = self.render_size();
+        let (width, height) = (size.width, size.height);
+        let scope = ErrorScope::push(&self.device, format!("Resizing the scene's targets to {}x{}", width, height));
+        self.prepass.resize(&self.device, width, height);
+        self.depth_pyramid.resize(&self.device, self.prepass.depth_view(), width, height);
+        self.gpu_culler.set_pyramid(&self.device, &self.depth_pyramid);
+        if let Some(msaa) = &mut self.msaa {
+            msaa.resize(&self.device, width, height);
+        }
+        self.tonemap.resize(&self.device, width, height);
+        self.auto_exposure.resize(&self.device, &self.tonemap, width, height);
+        self.fxaa_pass.resize(&self.device, width, height);
+        self.compositor.resize(&self.device, width, height);
+        self.taa_pass.resize(&self.device, width, height);
+        self.motion_blur_pass.resize(&self.device, width, height, self.taa_pass.velocity_view());
+        self.ssr_pass.resize(&self.device, width, height);
+        self.dof_pass.resize(&self.device, width, height, self.ssr_pass.depth_view());
+        self.oit.resize(&self.device, width, height);
+        self.selection_outline.resize(&self.device, &self.queue, width, height);
+        if let Some(terrain) = &mut self.terrain {
+            terrain.resize(&self.device, width, height);
+        }
+        if let Some(decals) = &mut self.decals {
+            decals.resize(&self.device, width, height);
+        }
+        if let Some(water) = &mut self.water {
+            water.resize(&self.device, width, height);
+        }
+        if let Some(voxels) = &mut self.voxels {
+            voxels.resize(&self.device, width, height);
+        }
+        if let Err(e) = scope.pop() {
+            log::error!("{:#}", e);
+        }
     }
 
-    fn update(&mut self) {
-        
+    // Per pixel of the passes drawn straight into the scene, 1 without MSAA
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa.as_ref().map_or(1, MsaaTarget::samples)
     }
 
-    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.surface.get_current_texture()?;
-        let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Commands Encoder"),
-        });
+    // What the 3D scene's drawn at before it's scaled into the window
+    pub fn render_size(&self) -> winit::dpi::PhysicalSize<u32> {
+        dynamic_resolution::scaled_size(self.size, self.resolution_scaler.scale())
+    }
 
-        // create our render pass
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Render Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment { 
-                view: &view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color { 
-                        r: 0.1,
-                        g: 0.2,
-                        b: 0.3, 
-                        a: 1.0
-                    }),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            occlusion_query_set: None,
-            timestamp_writes: None,
-        });
+    pub fn render_scale(&self) -> RenderScale {
+        self.render_scale
+    }
 
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
-        render_pass.draw_indexed(0..self.num_indices, 0, 0..1);
+    // A fixed scale applies from the next frame, a dynamic one adjusts
+    // from there to the frame times
+    pub fn set_render_scale(&mut self, render_scale: RenderScale) {
+        self.render_scale = render_scale;
+        log::info!("Render scale: {:?}", render_scale);
+    }
 
-        // encoder borrows render_pass via (&mut self)
-        // drop it manually to call encoder.finish()
-        drop(render_pass);
+    pub fn upscaling(&self) -> Upscaling {
+        self.compositor.upscaling()
+    }
 
-        // submit command queue
-        self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+    pub fn set_upscaling(&mut self, upscaling: Upscaling) {
+        self.compositor.set_upscaling(upscaling);
+    }
 
-        Ok(())
+    // What a frame has to fit in: the frame cap's interval, otherwise the
+    // monitor's refresh interval, or 60 Hz's if that's unknown
+    fn frame_budget(&self) -> f32 {
+        let refresh_rate = || {
+            let millihertz = self.window.current_monitor()?.refresh_rate_millihertz()?;
+            Some(millihertz as f64 / 1000.0)
+        };
+        let fps = self.max_fps().or_else(refresh_rate).unwrap_or(60.0);
+        (1.0 / fps.max(1.0)) as f32
     }
-}
 
-pub async fn run() {
-    env_logger::init();
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new().build(&event_loop).unwrap();
-    let mut state = State::new(&window).await;
-
-    let res = event_loop.run(move |event, control_flow| match event {
-        Event::WindowEvent { 
-            window_id, 
-            ref event 
-        } if window_id == state.window().id() => if !state.input(event) {
-            match event {
-                WindowEvent::CloseRequested 
-                | WindowEvent::KeyboardInput { 
-                    event:
-                        KeyEvent {
-                            state: ElementState::Pressed,
-                            physical_key: PhysicalKey::Code(KeyCode::Escape),
-                            ..
-                        },
-                    ..
-                } => control_flow.exit(),
-                WindowEvent::Resized(physical_size) => {
-                    state.resize(*physical_size);
-                },
-                WindowEvent::RedrawRequested => {
-                    // request another frame after this one
-                    state.window().request_redraw();
+    // Rescales the scene's targets when the frame times call for it
+    fn update_render_scale(&mut self, dt: Duration) {
+        if let Some(timer) = &mut self.frame_timer {
+            timer.poll(&self.device);
+        }
+        let frame_time = match self.frame_timer.as_ref().and_then(GpuFrameTimer::frame_time) {
+            Some(time) => FrameTime::Gpu(time),
+            None => FrameTime::Cpu(dt.as_secs_f32()),
+        };
+        let budget = self.frame_budget();
+        if let Some(scale) = self.resolution_scaler.update(self.render_scale, frame_time, budget, dt) {
+            log::debug!("Rendering at {:.0}%", scale * 100.0);
+            self.resize_targets();
+        }
+    }
 
-                    state.update();
-                    match state.render() {
-                        Ok(_) => {}
-                        // Reconfigure the surface if it's lost or out of date
-                        Err(
-                            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
-                        ) => state.resize(state.size),
-                        // Quit on OOM error
-                        Err(wgpu::SurfaceError::OutOfMemory) => {
-                            log::error!("Out of memory");
-                            control_flow.exit();
-                        },
-                        Err(wgpu::SurfaceError::Timeout) => {
-                            log::warn!("Device timeout!")
-                        }
-                    }
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn logical_size(&self) -> winit::dpi::LogicalSize<f64> {
+        self.size.to_logical(self.scale_factor)
+    }
+
+    // Moving to a monitor with another DPI changes this, the new physical
+    // size arrives separately as a Resized event
+    fn set_scale_factor(&mut self, scale_factor: f64) {
+        self.scale_factor = scale_factor;
+        self.text.set_scale_factor(scale_factor as f32);
+        self.sprite_batch.set_scale_factor(scale_factor as f32);
+        self.tilemap.scale = TILEMAP_SCALE * scale_factor as f32;
+        log::info!("Scale factor {}", scale_factor);
+    }
+
+    pub fn adapter_info(&self) -> &wgpu::AdapterInfo {
+        &self.adapter_info
+    }
+
+    pub fn present_mode(&self) -> wgpu::PresentMode {
+        self.config.present_mode
+    }
+
+    // Switches the presentation mode (vsync) at runtime. Returns false
+    // and leaves the surface untouched if the mode isn't supported.
+    pub fn set_present_mode(&mut self, mode: wgpu::PresentMode) -> bool {
+        if !self.present_modes.contains(&mode) {
+            log::warn!("Present mode {:?} is not supported by this surface", mode);
+            return false;
+        }
+        if self.config.present_mode != mode {
+            self.config.present_mode = mode;
+            self.configure_surface();
+            log::info!("Present mode set to {:?}", mode);
+        }
+        true
+    }
+
+    pub fn alpha_mode(&self) -> wgpu::CompositeAlphaMode {
+        self.config.alpha_mode
+    }
+
+    // How the compositor blends the window with what's behind it. Returns
+    // false and leaves the surface untouched if the mode isn't supported.
+    pub fn set_alpha_mode(&mut self, mode: wgpu::CompositeAlphaMode) -> bool {
+        if mode != wgpu::CompositeAlphaMode::Auto && !self.alpha_modes.contains(&mode) {
+            log::warn!("Alpha mode {:?} is not supported by this surface", mode);
+            return false;
+        }
+        if self.config.alpha_mode != mode {
+            self.config.alpha_mode = mode;
+            self.configure_surface();
+            log::info!("Alpha mode set to {:?}", mode);
+        }
+        true
+    }
+
+    pub fn clear_color(&self) -> wgpu::Color {
+        self.clear_color
+    }
+
+    // Linear colour; with a compositing alpha mode an alpha below one lets
+    // the desktop through, premultiply it for PreMultiplied
+    pub fn set_clear_color(&mut self, color: wgpu::Color) {
+        self.clear_color = color;
+    }
+
+    pub fn vsync(&self) -> bool {
+        matches!(
+            self.config.present_mode,
+            wgpu::PresentMode::Fifo | wgpu::PresentMode::FifoRelaxed | wgpu::PresentMode::AutoVsync
+        )
+    }
+
+    // Fifo, or without vsync the first of Mailbox and Immediate the surface
+    // supports
+    pub fn set_vsync(&mut self, vsync: bool) -> bool {
+        if vsync == self.vsync() {
+            return true;
+        }
+        let mode = match vsync {
+            true => wgpu::PresentMode::Fifo,
+            false => [wgpu::PresentMode::Mailbox, wgpu::PresentMode::Immediate]
+                .into_iter()
+                .find(|mode| self.present_modes.contains(mode))
+                .unwrap_or(wgpu::PresentMode::Immediate),
+        };
+        self.set_present_mode(mode)
+    }
+
+    // Steps to the next supported mode in the Fifo -> Mailbox -> Immediate cycle
+    pub fn cycle_present_mode(&mut self) {
+        const CYCLE: [wgpu::PresentMode; 3] = [
+            wgpu::PresentMode::Fifo,
+            wgpu::PresentMode::Mailbox,
+            wgpu::PresentMode::Immediate,
+        ];
+        let current = CYCLE.iter()
+            .position(|m| *m == self.config.present_mode)
+            .unwrap_or(0);
+        let next = (1..=CYCLE.len())
+            .map(|i| CYCLE[(current + i) % CYCLE.len()])
+            .find(|m| self.present_modes.contains(m));
+        if let Some(mode) = next {
+            self.set_present_mode(mode);
+        }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        self.window_mode
+    }
+
+    pub fn set_window_mode(&mut self, mode: WindowMode) {
+        let fullscreen = match mode {
+            WindowMode::Windowed => None,
+            WindowMode::Borderless => Some(Fullscreen::Borderless(None)),
+            WindowMode::Exclusive => match self.best_video_mode() {
+                Some(video_mode) => Some(Fullscreen::Exclusive(video_mode)),
+                None => {
+                    // some platforms (e.g. Wayland) expose no video modes
+                    log::warn!("No exclusive video mode available, using borderless");
+                    Some(Fullscreen::Borderless(None))
+                }
+            },
+        };
+        self.window.set_fullscreen(fullscreen);
+        self.window_mode = mode;
+        log::info!("Window mode set to {:?}", mode);
+
+        // Resized usually follows, but not on every platform, so
+        // reconfigure right away with whatever size we got
+        self.resize(self.window.inner_size());
+    }
+
+    // F11 cycles windowed -> borderless -> exclusive -> windowed
+    pub fn cycle_window_mode(&mut self) {
+        self.set_window_mode(self.window_mode.next());
+    }
+
+    // Picks the current monitor's video mode with the highest
+    // resolution, and of those the highest refresh rate
+    fn best_video_mode(&self) -> Option<VideoMode> {
+        let monitor = self.window.current_monitor()?;
+        monitor.video_modes().max_by_key(|mode| {
+            let size = mode.size();
+            (size.width * size.height, mode.refresh_rate_millihertz())
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    // The frame rate still follows it if `fps_in_title` is on
+    pub fn set_title(&mut self, title: impl Into<String>) {
+        self.title = title.into();
+        self.update_title();
+    }
+
+    pub fn set_fps_in_title(&mut self, fps_in_title: bool) {
+        self.fps_in_title = fps_in_title;
+        self.update_title();
+    }
+
+    fn update_title(&mut self) {
+        self.title_countdown = TITLE_FPS_INTERVAL;
+        if self.fps_in_title {
+            let fps = 1.0 / self.frame_time.max(1e-6);
+            self.window.set_title(&format!("{} - {:.0} fps", self.title, fps));
+        } else {
+            self.window.set_title(&self.title);
+        }
+    }
+
+    pub fn set_resizable(&mut self, resizable: bool) {
+        self.window.set_resizable(resizable);
+    }
+
+    // The title bar and borders
+    pub fn set_decorations(&mut self, decorations: bool) {
+        self.window.set_decorations(decorations);
+    }
+
+    pub fn set_min_size(&mut self, size: Option<winit::dpi::PhysicalSize<u32>>) {
+        self.window.set_min_inner_size(size);
+    }
+
+    // From an image file, or None for the platform's default
+    pub fn set_window_icon(&mut self, path: Option<&Path>) -> anyhow::Result<()> {
+        let icon = path.map(config::load_icon).transpose()?;
+        self.window.set_window_icon(icon);
+        Ok(())
+    }
+
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    pub fn set_cursor_grab(&mut self, grab: bool) {
+        if grab == self.cursor_grabbed {
+            return;
+        }
+        if grab {
+            // Locked isn't available on Windows and X11, Confined isn't on macOS
+            let result = self.window.set_cursor_grab(CursorGrabMode::Locked)
+                .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined));
+            if let Err(e) = result {
+                log::warn!("Could not grab the cursor: {}", e);
+                return;
+            }
+        } else if let Err(e) = self.window.set_cursor_grab(CursorGrabMode::None) {
+            log::warn!("Could not release the cursor: {}", e);
+        }
+        self.window.set_cursor_visible(!grab);
+        self.cursor_grabbed = grab;
+    }
+
+    // Where the mouse and fingers are
+    pub fn pointers(&self) -> &Pointers {
+        &self.pointers
+    }
+
+    // Magnifies the main camera's view, see `Projection::zoom`
+    pub fn zoom(&mut self, factor: f32) {
+        self.projection.zoom(factor);
+    }
+
+    // What the inputs are bound to, see `ActionMap`
+    pub fn actions(&self) -> &ActionMap {
+        &self.actions
+    }
+
+    pub fn actions_mut(&mut self) -> &mut ActionMap {
+        &mut self.actions
+    }
+
+    // Exits once the events already queued are handled, like closing the
+    // window does
+    pub fn request_exit(&mut self) {
+        self.exit_requested = true;
+    }
+
+    // What the app is using of what settings.ron holds. The resolution is
+    // the window's size while it's windowed, MSAA and the backend what it
+    // started with.
+    pub fn settings(&self) -> Settings {
+        Settings {
+            resolution: match self.window_mode {
+                WindowMode::Windowed => Some([self.size.width, self.size.height]),
+                _ => self.saved_settings.resolution,
+            },
+            vsync: self.vsync(),
+            anti_aliasing: self.anti_aliasing(),
+            msaa_samples: self.saved_settings.msaa_samples,
+            shadow_quality: self.shadow_quality(),
+            render_scale: self.render_scale,
+            upscaling: self.upscaling(),
+            backend: self.saved_settings.backend.clone(),
+            bindings: self.actions.overrides(),
+        }
+    }
+
+    // Applies what the config didn't take from `settings`, and from then on
+    // keeps `file` in step with the app: edits to it are applied and
+    // changes made by the app written to it
+    pub fn use_settings(&mut self, file: SettingsFile, settings: &Settings) {
+        // Fifo is where it starts anyway, and a scene may want another
+        if !settings.vsync {
+            self.set_vsync(false);
+        }
+        self.set_anti_aliasing(settings.anti_aliasing);
+        self.set_shadow_quality(settings.shadow_quality);
+        self.set_render_scale(settings.render_scale);
+        self.set_upscaling(settings.upscaling);
+        self.actions.set_overrides(settings.bindings.clone());
+        self.settings_file = Some(file);
+        self.saved_settings = settings.clone();
+        self.resync_settings = true;
+    }
+
+    // Everything but MSAA and the backend can change while running
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        if let (Some([width, height]), WindowMode::Windowed) = (settings.resolution, self.window_mode) {
+            if let Some(size) = self.window.request_inner_size(winit::dpi::PhysicalSize::new(width, height)) {
+                self.resize(size);
+            }
+        }
+        self.set_vsync(settings.vsync);
+        self.set_anti_aliasing(settings.anti_aliasing);
+        self.set_shadow_quality(settings.shadow_quality);
+        self.set_render_scale(settings.render_scale);
+        self.set_upscaling(settings.upscaling);
+        self.actions.set_overrides(settings.bindings.clone());
+        if settings.msaa_samples != self.saved_settings.msaa_samples {
+            log::info!("The MSAA setting takes effect from the next start");
+        }
+        if settings.backend != self.saved_settings.backend {
+            log::info!("The backend setting takes effect from the next start");
+        }
+    }
+
+    // Writes the settings file if the app's settings changed since it was
+    // last in step
+    pub fn save_settings(&mut self) {
+        let current = self.settings();
+        if std::mem::take(&mut self.resync_settings) {
+            self.saved_settings = current;
+            return;
+        }
+        if current == self.saved_settings {
+            return;
+        }
+        if let Some(file) = &mut self.settings_file {
+            file.save(&current);
+        }
+        self.saved_settings = current;
+    }
+
+    fn sync_settings(&mut self, dt: Duration) {
+        let Some(file) = &mut self.settings_file else {
+            return;
+        };
+        if !file.tick(dt) {
+            return;
+        }
+        match file.reload() {
+            // whatever the app makes of it isn't written straight back over
+            // the user's edit
+            Some(settings) => {
+                self.apply_settings(&settings);
+                self.saved_settings = settings;
+                self.resync_settings = true;
+            }
+            None => self.save_settings(),
+        }
+    }
+
+    pub fn projection_mode(&self) -> ProjectionMode {
+        self.projection.mode()
+    }
+
+    // The main camera's, perspective or orthographic
+    pub fn set_projection_mode(&mut self, mode: ProjectionMode) {
+        self.projection.set_mode(mode);
+        // the last frame's view-projection doesn't line up with this one's
+        self.taa_pass.reset();
+    }
+
+    pub fn camera_layers(&self) -> RenderLayers {
+        self.camera_layers
+    }
+
+    // The main camera only draws what's on these layers, every one of them
+    // to begin with; viewports have masks of their own
+    pub fn set_camera_layers(&mut self, layers: RenderLayers) {
+        self.camera_layers = layers;
+    }
+
+    pub fn main_viewport(&self) -> ViewportRect {
+        self.main_viewport
+    }
+
+    // The main camera's part of the window, what no viewport covers of the
+    // rest is black
+    pub fn set_main_viewport(&mut self, rect: ViewportRect) {
+        self.main_viewport = rect;
+        self.fit_projections();
+    }
+
+    pub fn viewports(&self) -> &[Viewport] {
+        &self.viewports
+    }
+
+    // Another camera drawn every frame after the main one, and after the
+    // viewports added before it where they overlap. Returns its index.
+    pub fn add_viewport(&mut self, viewport: Viewport) -> usize {
+        self.viewports.push(viewport);
+        self.fit_projections();
+        self.viewports.len() - 1
+    }
+
+    // A projection's aspect is put back to its rectangle's every update
+    pub fn viewport_mut(&mut self, index: usize) -> Option<&mut Viewport> {
+        self.viewports.get_mut(index)
+    }
+
+    pub fn remove_viewport(&mut self, index: usize) -> Viewport {
+        self.viewports.remove(index)
+    }
+
+    pub fn clear_viewports(&mut self) {
+        self.viewports.clear();
+    }
+
+    // Every camera's frame is drawn at the window's size and scaled into its
+    // rectangle, so its aspect is the rectangle's
+    fn fit_projections(&mut self) {
+        let [_, _, width, height] = self.main_viewport.pixels(self.size);
+        self.projection.resize(width, height);
+        for viewport in &mut self.viewports {
+            let [_, _, width, height] = viewport.rect.pixels(self.size);
+            viewport.projection.resize(width, height);
+        }
+    }
+
+    // The main camera on the left half of the window and the right half
+    // looking down from above where it is, or the main camera alone again
+    fn toggle_split_screen(&mut self) {
+        if !self.viewports.is_empty() {
+            self.clear_viewports();
+            self.set_main_viewport(ViewportRect::FULL);
+            return;
+        }
+        let position = self.camera.position + cgmath::Vector3::new(0.0, SPLIT_SCREEN_HEIGHT, 0.0);
+        let camera = Camera::new(position, self.camera.yaw, cgmath::Deg(-89.0));
+        let projection = Projection::new(1, 1, self.projection.fovy(), self.projection.znear(), self.projection.zfar());
+        self.set_main_viewport(ViewportRect::LEFT_HALF);
+        let mut overhead = Viewport::new(ViewportRect::RIGHT_HALF, camera, projection);
+        // a map of the scene, without the overlay's lines
+        overhead.layers = RenderLayers::ALL.without(RenderLayers::DEBUG);
+        self.add_viewport(overhead);
+    }
+
+    pub fn view_proj(&self) -> cgmath::Matrix4<f32> {
+        self.projection.calc_matrix() * self.camera.calc_matrix()
+    }
+
+    // Projects a world position to window coordinates in physical pixels,
+    // in the main camera's viewport, None if it's behind the camera
+    pub fn world_to_screen(&self, point: cgmath::Point3<f32>) -> Option<[f32; 2]> {
+        use cgmath::EuclideanSpace;
+        let clip = self.view_proj() * point.to_vec().extend(1.0);
+        if clip.w <= 0.0 {
+            return None;
+        }
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        let frame = [
+            (x * 0.5 + 0.5) * self.size.width as f32,
+            (0.5 - y * 0.5) * self.size.height as f32,
+        ];
+        Some(self.main_viewport.from_frame(frame, self.size))
+    }
+
+    // Casts a ray through a window position (in physical pixels) and
+    // returns the closest mesh it hits, None outside the main camera's
+    // viewport
+    pub fn pick(&self, position: winit::dpi::PhysicalPosition<f64>) -> Option<Hit> {
+        let position = self.main_viewport.to_frame(position, self.size)?;
+        let ray = Ray::from_cursor(position, self.size, self.view_proj())?;
+        let targets: Vec<_> = self.instances.iter()
+            .enumerate()
+            .map(|(i, instance)| Pickable {
+                id: i as u32,
+                model: instance.model_matrix(),
+                bounds: self.mesh_bounds,
+                triangles: Some(&self.mesh_triangles),
+            })
+            .collect();
+        picking::pick(&ray, &targets)
+    }
+
+    // Digs a ball out of the voxels where the cursor points at them, or
+    // adds one on top with `build`
+    fn edit_voxels(&mut self, build: bool) {
+        let position = self.main_viewport.to_frame(self.cursor_or_center(), self.size);
+        let Some(ray) = position.and_then(|position| Ray::from_cursor(position, self.size, self.view_proj())) else {
+            return;
+        };
+        let far = self.projection.zfar();
+        let Some(voxels) = &mut self.voxels else {
+            return;
+        };
+        if let Some(hit) = voxels.field().raycast(&ray, far) {
+            let radius = VOXEL_EDIT_CELLS * voxels.desc().cell_size;
+            let field = voxels.field_mut();
+            if build {
+                field.add_sphere(hit, radius);
+            } else {
+                field.carve_sphere(hit, radius);
+            }
+        }
+    }
+
+    // Picks at `position` and outlines what it hit, if anything
+    fn select_at(&mut self, position: winit::dpi::PhysicalPosition<f64>) {
+        let hit = self.pick(position);
+        match hit {
+            Some(hit) => log::info!(
+                "Picked instance {} at distance {:.3}, point {:?}",
+                hit.id, hit.distance, hit.point
+            ),
+            None => log::info!("Picked nothing"),
+        }
+        self.picked = hit.map(|hit| hit.id);
+    }
+
+    // Where the cursor is, or the middle of the main camera's viewport
+    // during mouse-look
+    fn cursor_or_center(&self) -> winit::dpi::PhysicalPosition<f64> {
+        let [x, y, width, height] = self.main_viewport.pixels(self.size).map(f64::from);
+        match self.pointers.mouse() {
+            Some(position) if !self.cursor_grabbed => position,
+            _ => winit::dpi::PhysicalPosition::new(x + width / 2.0, y + height / 2.0),
+        }
+    }
+
+    // Queues a pick through the ID buffer, the result is logged when the
+    // readback completes a frame or so later
+    pub fn request_gpu_pick(&mut self, position: winit::dpi::PhysicalPosition<f64>) {
+        // the ID buffer's drawn from the main camera, at the window's size
+        if let Some(position) = self.main_viewport.to_frame(position, self.size) {
+            self.gpu_picker.request(position.x as u32, position.y as u32);
+        }
+    }
+
+    // Raw device motion, only used for mouse-look while the cursor is grabbed
+    fn mouse_motion(&mut self, delta: (f64, f64)) {
+        if self.cursor_grabbed {
+            self.camera_controller.process_mouse(delta.0, delta.1);
+        }
+    }
+
+    fn input(&mut self, event: &WindowEvent) -> bool {
+        profiling::scope!("input");
+        // pointer events a plugin used, like a tap on its UI, stop here
+        let pointer_events = self.pointers.process(event, self.scale_factor);
+        let used = self.call_plugins(|plugins, state| {
+            let mut used = false;
+            for pointer in &pointer_events {
+                used |= plugins.iter_mut().any(|plugin| plugin.on_pointer(state, pointer));
+            }
+            used
+        });
+        if used {
+            return true;
+        }
+        // a tap picks on touch screens, where there's no right click
+        for pointer in &pointer_events {
+            if pointer.phase == PointerPhase::Tap && pointer.is_touch() {
+                self.select_at(pointer.position);
+            }
+        }
+        match event {
+            WindowEvent::KeyboardInput { .. } | WindowEvent::MouseInput { .. } => {
+                let triggered = self.actions.process_event(event);
+                for action in &triggered.pressed {
+                    self.run_action(action);
+                }
+                triggered.bound
+            }
+            WindowEvent::Focused(false) => {
+                self.actions.process_event(event);
+                self.set_cursor_grab(false);
+                false
+            }
+            WindowEvent::Touch(touch) => {
+                match self.touch.process(touch) {
+                    // logical pixels, so a swipe turns as far on any screen density
+                    Some(TouchGesture::Look(delta)) => {
+                        let delta = delta.cast::<f64>().unwrap() / self.scale_factor;
+                        self.camera_controller.process_mouse(delta.x, delta.y);
+                    }
+                    Some(TouchGesture::Pinch(factor)) => self.zoom(factor),
+                    None => {}
+                }
+                true
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                self.set_scale_factor(*scale_factor);
+                true
+            }
+            WindowEvent::DroppedFile(path) => {
+                if let Err(e) = self.spawn_file(path) {
+                    log::warn!("{:#}", e);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // What the demo's actions do when pressed, see `ActionMap::default`.
+    // Actions held rather than pressed, like moving, are read in `update`.
+    // Returns false for actions it doesn't know.
+    pub fn run_action(&mut self, action: &str) -> bool {
+        match action {
+            "grab_cursor" => self.set_cursor_grab(true),
+            // releases the cursor first, the next one quits
+            "quit" if self.cursor_grabbed => self.set_cursor_grab(false),
+            "quit" => self.exit_requested = true,
+            "pick" => self.select_at(self.cursor_or_center()),
+            "gpu_pick" => {
+                if let Some(position) = self.pointers.mouse() {
+                    self.request_gpu_pick(position);
+                }
+            }
+            "cycle_present_mode" => self.cycle_present_mode(),
+            "cycle_window_mode" => self.cycle_window_mode(),
+            "screenshot" => self.screenshot_requested = true,
+            "toggle_particles" => self.particles.emitting = !self.particles.emitting,
+            "cycle_culling" => {
+                self.culling = self.culling.next();
+                log::info!("Culling: {:?}", self.culling);
+            }
+            "cycle_lod" => {
+                self.lod_mode = self.lod_mode.next();
+                log::info!("Levels of detail: {:?}", self.lod_mode);
+            }
+            "toggle_hud" => self.show_hud = !self.show_hud,
+            "toggle_debug" => self.show_debug = !self.show_debug,
+            "toggle_debug_lights" => self.toggle_debug_category(DebugCategories::LIGHTS),
+            "toggle_debug_shadows" => self.toggle_debug_category(DebugCategories::SHADOWS),
+            "toggle_debug_decals" => self.toggle_debug_category(DebugCategories::DECALS),
+            "toggle_grid" => self.show_grid = !self.show_grid,
+            "toggle_axes" => self.show_axes = !self.show_axes,
+            "dump_draws" => self.dump_draw_list(),
+            "toggle_draw_dumps" => self.set_dump_every_frame(!self.dump_every_frame),
+            "toggle_dynamic_resolution" => self.set_render_scale(match self.render_scale {
+                RenderScale::Dynamic { .. } => RenderScale::default(),
+                RenderScale::Fixed(_) => RenderScale::DYNAMIC,
+            }),
+            "cycle_wireframe" => self.set_wireframe(self.wireframe.next()),
+            "toggle_tilemap" => self.show_tilemap = !self.show_tilemap,
+            "cycle_transparency" => self.transparency = self.transparency.next(),
+            "toggle_fxaa" => self.fxaa = !self.fxaa,
+            "toggle_taa" => self.set_taa(!self.taa),
+            "toggle_ssr" => self.ssr = !self.ssr,
+            "toggle_depth_of_field" => self.set_depth_of_field(DepthOfField {
+                enabled: !self.depth_of_field.enabled,
+                ..self.depth_of_field
+            }),
+            "focus_nearer" | "focus_farther" => {
+                let factor = if action == "focus_farther" { 1.25 } else { 0.8 };
+                self.set_depth_of_field(DepthOfField {
+                    focus_distance: (self.depth_of_field.focus_distance * factor).clamp(0.1, self.projection.zfar()),
+                    ..self.depth_of_field
+                });
+            }
+            // a stop at a time
+            "open_aperture" | "close_aperture" => {
+                let factor = if action == "close_aperture" { SQRT_2 } else { FRAC_1_SQRT_2 };
+                self.set_depth_of_field(DepthOfField {
+                    f_stop: (self.depth_of_field.f_stop * factor).clamp(0.7, 32.0),
+                    ..self.depth_of_field
+                });
+            }
+            "toggle_color_grading" => self.set_color_grading(ColorGrading {
+                enabled: !self.color_grading.enabled,
+                ..self.color_grading
+            }),
+            "toggle_motion_blur" => self.set_motion_blur(MotionBlur {
+                enabled: !self.motion_blur.enabled,
+                ..self.motion_blur
+            }),
+            "toggle_auto_exposure" => {
+                self.set_exposure(Exposure {
+                    auto: !self.exposure.auto,
+                    ..self.exposure
+                });
+                log::info!("Auto exposure: {}", self.exposure.auto);
+            }
+            "toggle_depth_prepass" => self.depth_prepass = !self.depth_prepass,
+            "toggle_occlusion_culling" => {
+                self.occlusion_culling = !self.occlusion_culling;
+                log::info!("Occlusion culling: {}", self.occlusion_culling);
+            }
+            "cycle_fog" => self.set_fog(Fog {
+                mode: self.fog.mode.next(),
+                ..self.fog
+            }),
+            "remove_voxels" | "add_voxels" => self.edit_voxels(action == "add_voxels"),
+            "cycle_max_fps" => self.cycle_max_fps(),
+            "toggle_render_bundles" => {
+                self.render_bundles = !self.render_bundles;
+                log::info!("Render bundles: {}", self.render_bundles);
+            }
+            "toggle_stats" => self.show_stats = !self.show_stats,
+            "cycle_animation" => self.cycle_animation(),
+            "pause_animation" => self.models.iter_mut().for_each(|model| model.player.toggle_pause()),
+            "slower_animation" | "faster_animation" => {
+                let factor = if action == "faster_animation" { 2.0 } else { 0.5 };
+                for model in &mut self.models {
+                    model.player.speed = (model.player.speed * factor).clamp(0.125, 8.0);
+                }
+                if let Some(model) = self.models.first() {
+                    log::info!("Animation speed {}x", model.player.speed);
+                }
+            }
+            "save_scene" => {
+                let path = self.scene_path.clone();
+                match self.save_scene(&path) {
+                    Ok(()) => log::info!("Saved scene to {}", path.display()),
+                    Err(e) => log::warn!("{:#}", e),
+                }
+            }
+            "load_scene" => {
+                let path = self.scene_path.clone();
+                if let Err(e) = self.load_scene(&path) {
+                    log::warn!("{:#}", e);
+                }
+            }
+            "cycle_filtering" => self.cycle_filtering(),
+            "toggle_uv_debug" => self.set_scene_shading(uv_debug_shading(!self.scene_shading.is_defined("UV_DEBUG"))),
+            "toggle_split_screen" => self.toggle_split_screen(),
+            "cycle_projection" => {
+                self.set_projection_mode(self.projection.mode().next());
+                log::info!("Projection: {:?}", self.projection.mode());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn cycle_filtering(&mut self) {
+        self.set_filtering((self.filtering + 1) % FILTERING_PRESETS.len());
+    }
+
+    fn set_filtering(&mut self, preset: usize) {
+        self.filtering = preset;
+        let (name, desc) = FILTERING_PRESETS[self.filtering];
+        self.materials.set_sampler(&self.device, &mut self.samplers, desc);
+        log::info!("Texture filtering: {} ({} samplers cached)", name, self.samplers.len());
+    }
+
+    // Cross-fades every model to its next animation clip
+    fn cycle_animation(&mut self) {
+        for scene_model in &mut self.models {
+            let (model, player) = (&scene_model.model, &mut scene_model.player);
+            if model.animations.is_empty() {
+                continue;
+            }
+            let next = player.current().map_or(0, |current| (current + 1) % model.animations.len());
+            player.cross_fade(next, ANIMATION_FADE, true);
+            log::info!("Animation: {}", model.animations[next].name.as_deref().unwrap_or("unnamed"));
+        }
+    }
+
+    pub fn culling(&self) -> CullingMode {
+        self.culling
+    }
+
+    pub fn set_culling(&mut self, mode: CullingMode) {
+        self.culling = mode;
+    }
+
+    pub fn occlusion_culling(&self) -> bool {
+        self.occlusion_culling
+    }
+
+    // Only with GPU culling and the depth prepass, whose depth it culls
+    // against a frame later
+    pub fn set_occlusion_culling(&mut self, enabled: bool) {
+        self.occlusion_culling = enabled;
+    }
+
+    // Drawn and culled counts from the last update
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats
+    }
+
+    pub fn wireframe(&self) -> WireframeMode {
+        self.wireframe
+    }
+
+    pub fn set_wireframe(&mut self, mode: WireframeMode) {
+        self.wireframe = mode;
+        let path = if self.wireframe_pipeline.is_some() { "line polygon mode" } else { "debug line fallback" };
+        log::info!("Wireframe: {:?} ({})", mode, path);
+    }
+
+    pub fn fog(&self) -> Fog {
+        self.fog
+    }
+
+    // Applies to everything lit from the next frame on, and to the clear
+    // colour at the far plane
+    pub fn set_fog(&mut self, fog: Fog) {
+        self.fog = fog;
+        self.queue.write_buffer(&self.fog_buffer, 0, bytemuck::cast_slice(&[FogUniform::from(&fog)]));
+    }
+
+    pub fn external_uniforms(&self) -> ExternalUniforms {
+        self.external_uniforms
+    }
+
+    // What shaders see at binding 10 next to the camera from the next frame
+    // on, however often it's called before then
+    pub fn set_external_uniforms(&mut self, uniforms: ExternalUniforms) {
+        self.external_uniforms = uniforms;
+    }
+
+    pub fn exposure(&self) -> Exposure {
+        self.exposure
+    }
+
+    // What the scene's multiplied by before it's tonemapped. Auto exposure's
+    // is read back from the GPU a frame or two behind, None until it is.
+    pub fn current_exposure(&self) -> Option<f32> {
+        if !self.exposure.auto {
+            return Some(self.exposure.fixed());
+        }
+        self.auto_exposure.adapted().map(|adapted| adapted.exposure)
+    }
+
+    // Auto exposure carries on from wherever it has adapted to, anything
+    // else starts from the fixed exposure
+    pub fn set_exposure(&mut self, exposure: Exposure) {
+        if !(self.exposure.auto && exposure.auto) {
+            self.tonemap.set_exposure(&self.queue, &exposure);
+        }
+        self.exposure = exposure;
+    }
+
+    pub fn color_grading(&self) -> ColorGrading {
+        self.color_grading
+    }
+
+    pub fn set_color_grading(&mut self, color_grading: ColorGrading) {
+        self.tonemap.set_grading(&self.queue, &color_grading);
+        self.color_grading = color_grading;
+    }
+
+    // The LUT `ColorLut::load` loaded from its path, or None for lift, gamma
+    // and gain alone
+    pub fn set_color_lut(&mut self, color_lut: Option<(PathBuf, ColorLut)>) {
+        self.tonemap.set_lut(&self.device, &self.queue, color_lut.as_ref().map(|(_, lut)| lut));
+        self.color_lut = color_lut.map(|(path, _)| path);
+    }
+
+    pub fn depth_of_field(&self) -> DepthOfField {
+        self.depth_of_field
+    }
+
+    pub fn set_depth_of_field(&mut self, depth_of_field: DepthOfField) {
+        self.depth_of_field = depth_of_field;
+    }
+
+    pub fn motion_blur(&self) -> MotionBlur {
+        self.motion_blur
+    }
+
+    pub fn set_motion_blur(&mut self, motion_blur: MotionBlur) {
+        self.motion_blur = motion_blur;
+    }
+
+    pub fn anti_aliasing(&self) -> AntiAliasing {
+        AntiAliasing::new(self.taa, self.fxaa)
+    }
+
+    pub fn set_anti_aliasing(&mut self, anti_aliasing: AntiAliasing) {
+        self.fxaa = anti_aliasing.fxaa();
+        self.set_taa(anti_aliasing.taa());
+    }
+
+    pub fn set_taa(&mut self, enabled: bool) {
+        // whatever is in the history is from before it was turned off
+        if enabled && !self.taa {
+            self.taa_pass.reset();
+        }
+        self.taa = enabled;
+    }
+
+    // The instanced mesh copies, moved through `transforms_mut`
+    pub fn transforms(&self) -> &Transforms {
+        &self.instances
+    }
+
+    // Set transforms are uploaded with the next frame
+    pub fn transforms_mut(&mut self) -> &mut Transforms {
+        &mut self.instances
+    }
+
+    // Replaces the instanced mesh copies, rebuilding everything sized by
+    // the instance count. Handles to the old ones stop working.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_buffer = StorageBuffer::from_slice_with_usage(
+            &self.device,
+            "Instance Buffer",
+            &instance_data,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let prev_instance_buffer = StorageBuffer::from_slice_with_usage(
+            &self.device,
+            "Previous Instance Buffer",
+            &instance_data,
+            wgpu::BufferUsages::VERTEX,
+        );
+        // the frames in flight still draw the old instances
+        let replaced = std::mem::replace(&mut self.instance_buffer, instance_buffer);
+        self.frames.retire(replaced.into_buffer());
+        let replaced = std::mem::replace(&mut self.prev_instance_buffer, prev_instance_buffer);
+        self.frames.retire(replaced.into_buffer());
+        self.gpu_culler = GpuCuller::new(
+            &self.device,
+            &self.instance_buffer,
+            Sphere::from(self.mesh_bounds),
+            self.num_indices,
+            &self.depth_pyramid,
+        );
+        self.cpu_culler = CpuCuller::new(&self.device, &instances, self.mesh_bounds, self.num_indices);
+        let mode = self.row_batch.mode();
+        self.row_batch = instance_rows(&self.device, &self.queue, self.num_indices, instances.len() as u32);
+        self.row_batch.set_mode(&self.device, mode);
+        self.instances.replace(instances);
+        self.picked = None;
+    }
+
+    pub fn terrain(&self) -> Option<&Terrain> {
+        self.terrain.as_ref()
+    }
+
+    // Replaces the terrain, and fits the shadow map around the new one
+    pub fn set_terrain(&mut self, terrain: Option<Terrain>) {
+        self.terrain = terrain;
+        self.fit_shadow_map();
+        self.probes_stale = true;
+    }
+
+    pub fn decals(&self) -> Option<&Decals> {
+        self.decals.as_ref()
+    }
+
+    // From the next frame on, onto whatever terrain there is
+    pub fn set_decals(&mut self, decals: Option<Decals>) {
+        self.decals = decals;
+    }
+
+    pub fn light(&self) -> Light {
+        self.light
+    }
+
+    // Applies to everything lit from the next frame on, and turns the
+    // terrain's shadows to match if it's the first directional light. A sky
+    // sets it every frame.
+    pub fn set_light(&mut self, light: Light) {
+        self.light = light;
+        self.fit_shadow_map();
+    }
+
+    pub fn ambient(&self) -> [f32; 3] {
+        self.ambient
+    }
+
+    // On everything, whichever way it faces. A sky sets it every frame too.
+    pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+        self.ambient = ambient;
+    }
+
+    // The scene's lights besides `light`
+    pub fn lights(&self) -> &[Light] {
+        &self.lights
+    }
+
+    // From the next frame on, as many in view as the light buffer holds. The
+    // shadows are refitted in case one of them is the first directional light.
+    pub fn set_lights(&mut self, lights: Vec<Light>) {
+        if lights.len() >= lighting::MAX_LIGHTS {
+            log::warn!("{} lights, only the first {} in view are drawn", lights.len(), lighting::MAX_LIGHTS - 1);
+        }
+        self.lights = lights;
+        self.fit_shadow_map();
+    }
+
+    // `light` and then the rest, in the order the light buffer has them
+    fn scene_lights(&self) -> Vec<Light> {
+        std::iter::once(self.light).chain(self.lights.iter().copied()).collect()
+    }
+
+    // Once a frame, the lights the camera can see the light of, unless
+    // culling is off. The reflection in the water only gets those too.
+    fn upload_lights(&self) -> LightCullStats {
+        profiling::scope!("light culling");
+        let frustum = (self.culling != CullingMode::Off).then(|| bounds::Frustum::from_view_proj(&self.view_proj()));
+        self.light_buffers.upload(&self.queue, self.ambient, &self.scene_lights(), frustum.as_ref())
+    }
+
+    pub fn shadow_quality(&self) -> ShadowQuality {
+        self.shadow_map.quality()
+    }
+
+    // The new map is empty until the next frame's shadow pass
+    pub fn set_shadow_quality(&mut self, quality: ShadowQuality) {
+        self.shadow_map.set_quality(&self.device, quality);
+        self.fit_shadow_map();
+    }
+
+    // From the light `lighting::shadow_caster` picks, as the light buffer's
+    // shadow caster is
+    fn fit_shadow_map(&mut self) {
+        let lights = self.scene_lights();
+        let direction = lighting::shadow_caster(&lights).and_then(|caster| lights[caster].direction());
+        if let (Some(terrain), Some(direction)) = (&self.terrain, direction) {
+            // a set sun still casts shadows, just long ones from the horizon
+            use cgmath::InnerSpace;
+            let direction = cgmath::Vector3::new(direction.x, direction.y.max(0.1), direction.z).normalize();
+            self.shadow_map.update(&self.queue, direction, terrain.heightmap().bounds());
+        }
+    }
+
+    pub fn water(&self) -> Option<&Water> {
+        self.water.as_ref()
+    }
+
+    pub fn set_water(&mut self, desc: Option<&WaterDesc>) {
+        self.water = desc.map(|desc| {
+            Water::new(
+                &self.device,
+                self.color_format,
+                &self.camera_bind_group_layout,
+                &shared_camera_entries(
+                    &self.fog_buffer,
+                    &self.light_buffers,
+                    &self.ibl,
+                    &self.reflection_probes,
+                    &self.external_buffer,
+                ),
+                desc,
+                self.render_size().width,
+                self.render_size().height,
+            )
+        });
+    }
+
+    pub fn voxels(&self) -> Option<&VoxelTerrain> {
+        self.voxels.as_ref()
+    }
+
+    // Edit through `VoxelTerrain::field_mut`, it's meshed again next frame
+    pub fn voxels_mut(&mut self) -> Option<&mut VoxelTerrain> {
+        self.voxels.as_mut()
+    }
+
+    pub fn set_voxels(&mut self, desc: Option<&VoxelDesc>) {
+        let size = self.render_size();
+        self.voxels = desc.map(|desc| VoxelTerrain::new(&self.device, desc, size.width, size.height));
+    }
+
+    pub fn sky(&self) -> Option<&Sky> {
+        self.sky.as_ref()
+    }
+
+    pub fn sky_mut(&mut self) -> Option<&mut Sky> {
+        self.sky.as_mut()
+    }
+
+    // Without a sky the light goes back to the default one
+    pub fn set_sky(&mut self, desc: Option<&SkyDesc>) {
+        let (samples, layout) = (self.msaa_samples(), &self.camera_bind_group_layout);
+        self.sky = desc.map(|desc| Sky::new(&self.device, self.color_format, samples, layout, desc));
+        match &self.sky {
+            Some(sky) => {
+                self.ambient = sky.ambient();
+                self.set_light(sky.light());
+            }
+            None => {
+                self.ambient = lighting::AMBIENT;
+                self.set_light(Light::default());
+            }
+        }
+        self.environment_stale = true;
+    }
+
+    // Cubemaps are drawn in the DDS or KTX2 files' own formats, which have to
+    // be filterable. HDR and EXR panoramas are made into cubemaps.
+    pub fn load_environment(&self, path: &Path) -> anyhow::Result<Texture> {
+        let bytes = assets::read(path)?;
+        let label = path.display().to_string();
+        let texture = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dds") => Texture::from_dds(&self.device, &self.queue, &bytes, &label)?,
+            Some("ktx2") => Texture::from_ktx2(&self.device, &self.queue, &bytes, &label)?,
+            Some("hdr" | "exr") => Texture::from_equirect(&self.device, &self.queue, &bytes, &label)?,
+            _ => anyhow::bail!("{}: environments need a .dds, .ktx2, .hdr or .exr extension", label),
+        };
+        if texture.texture.depth_or_array_layers() != 6 {
+            anyhow::bail!("{}: not a cubemap", label);
+        }
+        Ok(texture)
+    }
+
+    // Bakes image based lighting from a cubemap `load_environment` loaded
+    // from its path, or goes back to the sky's (or the light's ambient)
+    pub fn set_environment(&mut self, environment: Option<(PathBuf, Texture)>) {
+        match environment {
+            Some((path, texture)) => {
+                let layout = &self.camera_bind_group_layout;
+                let skybox = Skybox::new(&self.device, self.color_format, self.msaa_samples(), layout, texture);
+                self.ibl.bake(&self.device, &self.queue, skybox.environment());
+                self.environment = Some((path, skybox));
+                self.probes_stale = true;
+            }
+            None => {
+                self.environment = None;
+                self.environment_stale = true;
+            }
+        }
+    }
+
+    // Bakes the sky's environment, or a plain one from the light's ambient,
+    // unless the scene brought its own
+    fn refresh_environment(&mut self) {
+        self.environment_stale = false;
+        // which is behind everything they see
+        self.probes_stale = true;
+        if self.environment.is_some() {
+            return;
+        }
+        let Some(sky) = &self.sky else {
+            self.ibl.fill(&self.device, &self.queue, self.ambient);
+            return;
+        };
+        // from the camera, for the fog's height
+        self.ibl.set_capture_eye(&self.queue, self.camera.position, self.projection.zfar());
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Capture Encoder"),
+        });
+        for face in 0..6 {
+            let mut pass = self.ibl.begin_capture(&mut encoder, face, wgpu::Color::BLACK);
+            sky.draw_capture(&mut pass, self.ibl.capture_bind_group(face));
+        }
+        self.queue.submit([encoder.finish()]);
+        self.captured_sun = sky.sun_direction();
+        self.ibl.bake_capture(&self.device, &self.queue);
+    }
+
+    pub fn reflection_probes(&self) -> &[ReflectionProbe] {
+        self.reflection_probes.probes()
+    }
+
+    // Baked on the next update, up to reflection_probes::MAX_PROBES
+    // of them. Until then models reflect the environment.
+    pub fn set_reflection_probes(&mut self, probes: Vec<ReflectionProbe>) {
+        self.reflection_probes.set(&self.queue, probes);
+        self.probes_stale = true;
+    }
+
+    // Captures every probe again on the next update, after something they
+    // see has changed that they can't tell, like the models or instances.
+    // The terrain, the sky and the environment bake them when they change.
+    pub fn rebake_reflection_probes(&mut self) {
+        self.probes_stale = true;
+    }
+
+    // The scene around each probe, as the water reflects it, and prefiltered
+    // into the probe's faces
+    fn bake_reflection_probes(&mut self) {
+        profiling::scope!("reflection probes");
+        self.probes_stale = false;
+        if self.reflection_probes.probes().is_empty() {
+            return;
+        }
+        // without a sky or environment, what's behind it is the ambient
+        let [r, g, b] = self.ambient.map(f64::from);
+        let clear = wgpu::Color { r, g, b, a: 1.0 };
+        for (index, probe) in self.reflection_probes.probes().iter().enumerate() {
+            self.ibl.set_capture_eye(&self.queue, probe.position.into(), self.projection.zfar());
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Reflection Probe Capture Encoder"),
+            });
+            for face in 0..6 {
+                let camera = self.ibl.capture_bind_group(face);
+                let view = self.ibl.capture_view(face);
+                let mut pass = self.ibl.begin_capture(&mut encoder, face, clear);
+                if let Some((_, skybox)) = &self.environment {
+                    skybox.draw(&mut pass, camera);
+                } else if let Some(sky) = &self.sky {
+                    // leaving the sun to the direct light, as the environment does
+                    sky.draw_capture(&mut pass, camera);
+                }
+                if let Some(terrain) = &self.terrain {
+                    drop(pass);
+                    let depth = self.ibl.capture_depth_view(face);
+                    terrain.encode_capture(&mut encoder, view, depth, camera, &self.shadow_map);
+                    pass = continue_pass(&mut encoder, view);
+                }
+                self.scene_passes().draw_surroundings(&mut pass, camera, true);
+            }
+            self.queue.submit([encoder.finish()]);
+            self.ibl.bake_probe(&self.device, &self.queue, &self.reflection_probes, index);
+        }
+        self.reflection_probes.upload(&self.queue);
+    }
+
+    // Loads the terrain `desc` describes, paths relative to `base_dir`
+    pub fn load_terrain(&self, desc: &TerrainDesc, base_dir: &Path) -> anyhow::Result<Terrain> {
+        Terrain::load(
+            &self.device,
+            &self.queue,
+            self.color_format,
+            &self.camera_bind_group_layout,
+            &self.shadow_map,
+            &desc.map_paths(|path| base_dir.join(path)),
+            self.render_size().width,
+            self.render_size().height,
+        )
+    }
+
+    // Loads the decals `descs` describe, paths relative to `base_dir`, or
+    // None without any
+    pub fn load_decals(&self, descs: &[DecalDesc], base_dir: &Path) -> anyhow::Result<Option<Decals>> {
+        if descs.is_empty() {
+            return Ok(None);
+        }
+        let descs: Vec<_> = descs.iter().map(|desc| desc.map_paths(|path| base_dir.join(path))).collect();
+        Decals::load(
+            &self.device,
+            &self.queue,
+            self.color_format,
+            &self.camera_bind_group_layout,
+            &self.shadow_map,
+            &descs,
+            self.render_size().width,
+            self.render_size().height,
+        )
+        .map(Some)
+    }
+
+    // The terrain's height under `x`, `z`, None without one there
+    pub fn ground_height(&self, x: f32, z: f32) -> Option<f32> {
+        self.terrain.as_ref()?.heightmap().height_at(x, z)
+    }
+
+    // Loads a glTF, OBJ or image file and places it in front of the camera,
+    // scaled to about a unit across and turned to face it, standing on the
+    // terrain if there's one under it
+    pub fn spawn_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        use cgmath::{EuclideanSpace, InnerSpace, Rotation};
+
+        let mut scene_model =
+            SceneModel::load(&self.device, &self.queue, &self.model_renderer, path, NodeTransform::default())?;
+        let (scale, center) = match scene_model.bounds {
+            Some(bounds) => {
+                let size = bounds.half_extents().magnitude() * 2.0;
+                (if size > 0.0 { 1.0 / size } else { 1.0 }, bounds.center())
+            }
+            None => (1.0, cgmath::Point3::origin()),
+        };
+        let forward = self.camera.forward();
+        let rotation = cgmath::Quaternion::from(cgmath::Matrix3::from_angle_y(cgmath::Rad((-forward.x).atan2(-forward.z))));
+        let mut target = self.camera.position + forward * SPAWN_DISTANCE;
+        // only turned around y, so its lowest point is as far below its
+        // centre as before
+        if let (Some(bounds), Some(ground)) = (scene_model.bounds, self.ground_height(target.x, target.z)) {
+            target.y = ground + (center.y - bounds.min.y) * scale;
+        }
+        scene_model.placement = NodeTransform {
+            translation: target.to_vec() - rotation.rotate_vector(center.to_vec() * scale),
+            rotation,
+            scale: cgmath::Vector3::new(scale, scale, scale),
+        };
+        scene_model.play(None);
+        log::info!("Spawned {}", path.display());
+        self.models.push(scene_model);
+        Ok(())
+    }
+
+    // Everything a scene file can describe, as it is right now. Model paths
+    // under `base_dir` are written relative to it.
+    pub fn scene_desc(&self, base_dir: &Path) -> SceneDesc {
+        SceneDesc {
+            camera: CameraDesc {
+                layers: self.camera_layers,
+                ..CameraDesc::from_camera(&self.camera, &self.projection)
+            },
+            instances: Some(self.instances.iter().map(InstanceDesc::from).collect()),
+            models: self
+                .models
+                .iter()
+                .map(|model| {
+                    let path = model.path.strip_prefix(base_dir).unwrap_or(&model.path);
+                    ModelDesc {
+                        tint: model.instance.tint,
+                        body: model.body,
+                        layers: model.layers,
+                        ..ModelDesc::new(path, &model.placement, model.current_animation())
+                    }
+                })
+                .collect(),
+            terrain: self
+                .terrain
+                .as_ref()
+                .map(|terrain| terrain.desc().map_paths(|path| path.strip_prefix(base_dir).unwrap_or(path).to_owned())),
+            decals: self.decals.as_ref().map_or(Vec::new(), |decals| {
+                decals
+                    .descs()
+                    .iter()
+                    .map(|desc| desc.map_paths(|path| path.strip_prefix(base_dir).unwrap_or(path).to_owned()))
+                    .collect()
+            }),
+            water: self.water.as_ref().map(|water| water.desc().clone()),
+            sky: self.sky.as_ref().map(|sky| sky.desc().clone()),
+            voxels: self.voxels.as_ref().map(|voxels| voxels.desc().clone()),
+            environment: self
+                .environment
+                .as_ref()
+                .map(|(path, _)| path.strip_prefix(base_dir).unwrap_or(path).to_owned()),
+            color_lut: self.color_lut.as_ref().map(|path| path.strip_prefix(base_dir).unwrap_or(path).to_owned()),
+            lights: self.lights.clone(),
+            reflection_probes: self.reflection_probes.probes().to_vec(),
+            settings: RenderSettings {
+                present_mode: self.config.present_mode,
+                max_fps: self.max_fps(),
+                filtering: FILTERING_PRESETS[self.filtering].0.to_owned(),
+                wireframe: self.wireframe,
+                fog: self.fog,
+                exposure: self.exposure,
+                color_grading: self.color_grading,
+                transparency: self.transparency,
+                fxaa: self.fxaa,
+                taa: self.taa,
+                ssr: self.ssr,
+                depth_of_field: self.depth_of_field,
+                motion_blur: self.motion_blur,
+                uv_debug: self.scene_shading.is_defined("UV_DEBUG"),
+                culling: self.culling,
+                occlusion_culling: self.occlusion_culling,
+                lod: self.lod_mode,
+                depth_prepass: self.depth_prepass,
+                render_bundles: self.render_bundles,
+                particles: self.particles.emitting,
+                show_hud: self.show_hud,
+                show_tilemap: self.show_tilemap,
+                show_stats: self.show_stats,
+                show_debug: self.show_debug,
+                debug_categories: self.debug_categories,
+                show_grid: self.show_grid,
+                show_axes: self.show_axes,
+            },
+        }
+    }
+
+    // Writes the scene as .ron or .json, by extension
+    pub fn save_scene(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.scene_desc(&scene_dir(path)).save(path)?;
+        self.scene_path = path.to_owned();
+        Ok(())
+    }
+
+    // Replaces the scene with the one in `path`. Models and the terrain load
+    // first, so a scene that fails to load leaves the current one untouched.
+    pub fn load_scene(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let desc = SceneDesc::load(path)?;
+        self.apply_scene(&desc, &scene_dir(path))?;
+        self.scene_path = path.to_owned();
+        log::info!("Loaded scene {}", path.display());
+        Ok(())
+    }
+
+    // Model and terrain paths in `desc` are relative to `base_dir`
+    pub fn apply_scene(&mut self, desc: &SceneDesc, base_dir: &Path) -> anyhow::Result<()> {
+        use anyhow::Context;
+
+        let filtering = FILTERING_PRESETS
+            .iter()
+            .position(|(name, _)| *name == desc.settings.filtering)
+            .with_context(|| format!("Unknown texture filtering preset {:?}", desc.settings.filtering))?;
+        if desc.instances.as_ref().is_some_and(Vec::is_empty) {
+            anyhow::bail!("A scene's instance list can't be empty, leave it out to keep the default grid");
+        }
+        let models = desc
+            .models
+            .iter()
+            .map(|model| {
+                let mut scene_model =
+                    SceneModel::load(&self.device, &self.queue, &self.model_renderer, base_dir.join(&model.path), model.placement())?;
+                scene_model.play(model.animation.as_deref());
+                scene_model.instance.tint = model.tint;
+                scene_model.body = model.body;
+                scene_model.layers = model.layers;
+                Ok(scene_model)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let terrain = desc.terrain.as_ref().map(|terrain| self.load_terrain(terrain, base_dir)).transpose()?;
+        let decals = self.load_decals(&desc.decals, base_dir)?;
+        let environment = desc
+            .environment
+            .as_ref()
+            .map(|path| {
+                let path = base_dir.join(path);
+                self.load_environment(&path).map(|texture| (path, texture))
+            })
+            .transpose()?;
+        let color_lut = desc
+            .color_lut
+            .as_ref()
+            .map(|path| {
+                let path = base_dir.join(path);
+                ColorLut::load(&path).map(|lut| (path, lut))
+            })
+            .transpose()?;
+
+        self.models = models;
+        self.set_terrain(terrain);
+        self.reset_physics();
+        self.set_decals(decals);
+        self.set_water(desc.water.as_ref());
+        self.set_sky(desc.sky.as_ref());
+        self.set_voxels(desc.voxels.as_ref());
+        self.set_lights(desc.lights.clone());
+        self.set_environment(environment);
+        self.set_color_lut(color_lut);
+        self.set_reflection_probes(desc.reflection_probes.clone());
+        if let Some(instances) = &desc.instances {
+            self.set_instances(instances.iter().map(Instance::from).collect());
+        }
+        self.camera = desc.camera.camera();
+        self.projection = desc.camera.projection(self.size.width, self.size.height);
+        self.camera_layers = desc.camera.layers;
+
+        let settings = &desc.settings;
+        self.set_present_mode(settings.present_mode);
+        self.set_max_fps(settings.max_fps);
+        if filtering != self.filtering {
+            self.set_filtering(filtering);
+        }
+        self.wireframe = settings.wireframe;
+        self.set_fog(settings.fog);
+        self.set_exposure(settings.exposure);
+        self.set_color_grading(settings.color_grading);
+        self.transparency = settings.transparency;
+        self.fxaa = settings.fxaa;
+        self.set_taa(settings.taa);
+        self.ssr = settings.ssr;
+        self.set_depth_of_field(settings.depth_of_field);
+        self.set_motion_blur(settings.motion_blur);
+        self.set_scene_shading(uv_debug_shading(settings.uv_debug));
+        self.culling = settings.culling;
+        self.occlusion_culling = settings.occlusion_culling;
+        self.lod_mode = settings.lod;
+        self.depth_prepass = settings.depth_prepass;
+        self.render_bundles = settings.render_bundles;
+        self.particles.emitting = settings.particles;
+        self.show_hud = settings.show_hud;
+        self.show_tilemap = settings.show_tilemap;
+        self.show_stats = settings.show_stats;
+        self.show_debug = settings.show_debug;
+        self.debug_categories = settings.debug_categories;
+        self.show_grid = settings.show_grid;
+        self.show_axes = settings.show_axes;
+        // the scene's anti-aliasing and present mode stay out of the
+        // settings file
+        self.resync_settings = true;
+        Ok(())
+    }
+
+    // Switches the scene shader to the permutation with `shading` defined
+    // on top of the material defines, e.g. UV_DEBUG, or back to the main
+    // pipeline when it's empty. New permutations compile in the background
+    // and the current pipeline keeps drawing until they're ready.
+    pub fn set_scene_shading(&mut self, shading: ShaderDefs) {
+        self.scene_shading = shading.clone();
+        if shading.is_empty() {
+            return;
+        }
+        // checked here, so a broken permutation is reported rather than
+        // taking the worker down
+        let shader = match load_scene_shader(&self.materials, &shading) {
+            Ok(shader) => shader,
+            Err(e) => {
+                log::error!("{:#}", e);
+                return;
+            }
+        };
+        let layout = self.render_pipeline_layout.clone();
+        let (format, samples) = (self.color_format, self.msaa_samples());
+        self.scene_variants.request(shading, move |device, cache| {
+            let shader = shader.create_module(device, "Shader");
+            ScenePipelines::new(&layout, &shader, format, samples, |desc| {
+                Arc::new(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                    cache,
+                    ..desc.clone()
+                }))
+            })
+        });
+    }
+
+    // Queues this frame's HUD: a pulsing bar and a spinning icon in the
+    // top-left corner. Everything is in pixels, so it stays put on resize.
+    fn queue_hud(&mut self) {
+        let t = self.elapsed.as_secs_f32();
+        let solid = self.hud_atlas.get("solid").unwrap();
+        let icon = self.hud_atlas.get("icon").unwrap();
+        let origin = cgmath::Vector2::new(16.0, 16.0);
+        let bar = cgmath::Vector2::new(200.0, 16.0);
+
+        self.sprite_batch.push(
+            Sprite::from_rect(origin + cgmath::Vector2::new(48.0, 8.0), solid)
+                .with_pivot(cgmath::Vector2::new(0.0, 0.0))
+                .with_scale(cgmath::Vector2::new(bar.x / solid.width as f32, bar.y / solid.height as f32))
+                .with_color([0.0, 0.0, 0.0, 0.5]),
+        );
+        let fill = 0.5 + 0.5 * (t * 0.8).sin();
+        self.sprite_batch.push(
+            Sprite::from_rect(origin + cgmath::Vector2::new(50.0, 10.0), solid)
+                .with_pivot(cgmath::Vector2::new(0.0, 0.0))
+                .with_scale(cgmath::Vector2::new(
+                    (bar.x - 4.0) * fill / solid.width as f32,
+                    (bar.y - 4.0) / solid.height as f32,
+                ))
+                .with_color([0.9 - 0.6 * fill, 0.3 + 0.6 * fill, 0.2, 0.9]),
+        );
+        self.sprite_batch.push(
+            Sprite::from_rect(origin + cgmath::Vector2::new(16.0, 16.0), icon)
+                .with_rotation(cgmath::Rad(t)),
+        );
+    }
+
+    // Whichever of `debug_categories` are on: world axes, the bounds of
+    // every instance in view, a sphere around the picked one, the rigid
+    // bodies' colliders, the lights, the shadow map and the decals
+    fn queue_debug_lines(&mut self) {
+        use cgmath::SquareMatrix;
+
+        let categories = self.debug_categories;
+        if categories.contains(DebugCategories::LIGHTS) {
+            self.queue_light_gizmos();
+        }
+        if categories.contains(DebugCategories::SHADOWS) {
+            if let Some(view_proj) = self.shadow_map.view_proj() {
+                self.debug_draw.frustum(&view_proj, debug_draw::BLUE);
+            }
+        }
+        if let Some(decals) = self.decals.as_ref().filter(|_| categories.contains(DebugCategories::DECALS)) {
+            let unit_box = Aabb::new(cgmath::Point3::new(-0.5, -0.5, -0.5), cgmath::Point3::new(0.5, 0.5, 0.5));
+            for desc in decals.descs() {
+                let [r, g, b, _] = desc.tint;
+                self.debug_draw.oriented_box(&unit_box, &desc.transform(), [r, g, b, 1.0]);
+            }
+        }
+        #[cfg(feature = "physics")]
+        if categories.contains(DebugCategories::PHYSICS) {
+            self.physics.queue_debug_lines(&mut self.debug_draw);
+        }
+        if !categories.contains(DebugCategories::INSTANCES) {
+            return;
+        }
+
+        self.debug_draw.axes(&cgmath::Matrix4::identity(), 1.0);
+
+        let frustum = bounds::Frustum::from_view_proj(&self.view_proj());
+        for (instance, bounds) in self.instances.iter().zip(self.cpu_culler.bounds()) {
+            if frustum.intersects_aabb(bounds) {
+                self.debug_draw.oriented_box(&self.mesh_bounds, &instance.model_matrix(), debug_draw::GREEN);
+            }
+        }
+
+        if let Some(instance) = self.picked.and_then(|id| self.instances.get(id as usize)) {
+            let model = instance.model_matrix();
+            let sphere = Sphere::from(self.mesh_bounds).transformed(&model);
+            self.debug_draw.bounding_sphere(&sphere, debug_draw::YELLOW);
+            self.debug_draw.axes(&model, 0.3);
+        }
+    }
+
+    // Each light in its own colour: a sphere out to a point light's range,
+    // a spot light's outer cone and a fainter inner one, and an arrow into
+    // the origin the way a directional light shines
+    fn queue_light_gizmos(&mut self) {
+        use cgmath::EuclideanSpace;
+
+        for light in std::iter::once(self.light).chain(self.lights.iter().copied()) {
+            match light {
+                Light::Directional { direction, color, .. } => {
+                    let direction = cgmath::Vector3::from(direction);
+                    let start = cgmath::Point3::origin() + direction * LIGHT_ARROW_LENGTH;
+                    self.debug_draw.arrow(start, -direction, LIGHT_ARROW_LENGTH, gizmo_color(color, 1.0));
+                }
+                Light::Point { position, color, range, .. } => {
+                    self.debug_draw.sphere(position.into(), range, gizmo_color(color, 1.0));
+                }
+                Light::Spot {
+                    position,
+                    direction,
+                    color,
+                    range,
+                    inner_angle,
+                    outer_angle,
+                    ..
+                } => {
+                    let (apex, axis) = (position.into(), direction.into());
+                    let outer = cgmath::Rad::from(cgmath::Deg(outer_angle));
+                    let inner = cgmath::Rad::from(cgmath::Deg(inner_angle.min(outer_angle)));
+                    self.debug_draw.cone(apex, axis, range, outer, gizmo_color(color, 1.0));
+                    self.debug_draw.cone(apex, axis, range, inner, gizmo_color(color, 0.4));
+                }
+            }
+        }
+    }
+
+    // Triangle edges of every instance in view, for devices that can't
+    // rasterize lines themselves
+    fn queue_wireframe_fallback(&mut self) {
+        let frustum = bounds::Frustum::from_view_proj(&self.view_proj());
+        for (instance, bounds) in self.instances.iter().zip(self.cpu_culler.bounds()) {
+            if frustum.intersects_aabb(bounds) {
+                wireframe::outline_triangles(&mut self.debug_draw, &self.mesh_triangles, &instance.model_matrix(), debug_draw::GREEN);
+            }
+        }
+    }
+
+    // Queues this frame's text: the stats readout under the HUD and a label
+    // over the picked instance
+    // The level each model is drawn at
+    fn lod_levels(&self) -> String {
+        if self.models.is_empty() {
+            return "no models".to_owned();
+        }
+        let levels: Vec<String> = self.models.iter().map(|model| model.instance.lod.level().to_string()).collect();
+        levels.join(" ")
+    }
+
+    // Status of the first model's animation
+    fn animation_status(&self) -> String {
+        let Some(model) = self.models.first() else {
+            return "no model".to_owned();
+        };
+        match model.player.current() {
+            Some(_) => format!(
+                "{} {:.2}x{}",
+                model.current_animation().unwrap_or("unnamed"),
+                model.player.speed,
+                if model.player.paused { " (paused)" } else { "" },
+            ),
+            None => "stopped".to_owned(),
+        }
+    }
+
+    // e.g. "1280x720 of 1920x1080, sharpened", or the window's size alone
+    fn resolution_status(&self) -> String {
+        let size = self.render_size();
+        if size == self.size {
+            return format!("{}x{}", size.width, size.height);
+        }
+        let upscaling = match self.upscaling() {
+            Upscaling::Bilinear => "bilinear",
+            Upscaling::Sharpened => "sharpened",
+        };
+        format!("{}x{} of {}x{}, {}", size.width, size.height, self.size.width, self.size.height, upscaling)
+    }
+
+    fn queue_text(&mut self) {
+        use cgmath::EuclideanSpace;
+
+        if self.show_hud {
+            let style = TextStyle::new(14.0).with_color([230, 230, 230, 255]);
+            self.text.queue("HP", [272.0, 23.0], &style);
+        }
+
+        if self.show_stats {
+            let mut stats = format!(
+                "{:.0} fps ({:.2} ms)\n\
+                 adapter: {} ({:?})\n\
+                 present mode: {:?}, {}\n\
+                 frames in flight: {}\n\
+                 resolution: {} ({})\n\
+                 instances: {}, {} moved (depth prepass {}, render bundles {})\n\
+                 culling: {:?}{}, drawn {}\n\
+                 levels of detail: {:?} ({})\n\
+                 terrain: {}\n\
+                 filtering: {}\n\
+                 wireframe: {:?}\n\
+                 fog: {:?}\n\
+                 transparency: {:?}\n\
+                 anti-aliasing: {}\n\
+                 depth of field: {}\n\
+                 motion blur: {}\n\
+                 exposure: {}\n\
+                 colour grading: {}\n\
+                 particles: {}\n\
+                 animation: {}\n\
+                 memory: {}",
+                1.0 / self.frame_time.max(1e-6),
+                self.frame_time * 1000.0,
+                self.adapter_info.name,
+                self.adapter_info.backend,
+                self.config.present_mode,
+                match self.max_fps() {
+                    Some(fps) => format!("capped at {} fps", fps),
+                    None => "uncapped".to_owned(),
+                },
+                self.frames,
+                self.resolution_status(),
+                match self.render_scale {
+                    RenderScale::Dynamic { .. } => "dynamic",
+                    RenderScale::Fixed(_) => "fixed",
+                },
+                self.instances.len(),
+                self.frame_stats.moved,
+                if self.depth_prepass { "on" } else { "off" },
+                if self.render_bundles {
+                    format!("on, {} recorded", self.instance_bundles.recorded())
+                } else {
+                    "off".to_owned()
+                },
+                self.culling,
+                if self.occlusion_culling { " + occlusion" } else { "" },
+                self.frame_stats,
+                self.lod_mode,
+                self.lod_levels(),
+                self.terrain.as_ref().map_or("none".to_owned(), |terrain| {
+                    format!("{} triangles in {} chunks", terrain.triangle_count(), terrain.visible_chunks())
+                }),
+                FILTERING_PRESETS[self.filtering].0,
+                self.wireframe,
+                self.fog.mode,
+                self.transparency,
+                match (self.taa, self.fxaa) {
+                    (false, false) => "off",
+                    (false, true) => "FXAA",
+                    (true, false) => "TAA",
+                    (true, true) => "TAA + FXAA",
+                },
+                if self.depth_of_field.enabled {
+                    format!("focused at {:.1}, f/{:.1}", self.depth_of_field.focus_distance, self.depth_of_field.f_stop)
+                } else {
+                    "off".to_owned()
+                },
+                if self.motion_blur.enabled {
+                    format!("{} samples, shutter {:.2}", self.motion_blur.samples, self.motion_blur.shutter)
+                } else {
+                    "off".to_owned()
+                },
+                match (self.exposure.auto, self.current_exposure()) {
+                    (true, Some(exposure)) => format!("auto, {:.2}x", exposure),
+                    (false, Some(exposure)) => format!("{:.2}x", exposure),
+                    (_, None) => "auto".to_owned(),
+                },
+                match (self.color_grading.enabled, &self.color_lut) {
+                    (false, _) => "off".to_owned(),
+                    (true, None) => "lift, gamma and gain".to_owned(),
+                    (true, Some(path)) => path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                },
+                if self.particles.emitting { "emitting" } else { "off" },
+                self.animation_status(),
+                self.frame_stats.resources,
+            );
+            // only when profiling on a device with timestamps
+            if let Some(gpu_time) = self.gpu_profiler.frame_time() {
+                stats += &format!("\ngpu: {:.2} ms", gpu_time * 1000.0);
+            }
+            let style = TextStyle::new(14.0).with_family(FontFamily::Monospace);
+            self.text.queue(&stats, [16.0, 56.0], &style);
+        }
+
+        if self.show_axes {
+            let style = TextStyle::new(12.0);
+            for (name, [x, y]) in self.axis_gizmo.labels(self.size, self.scale_factor as f32) {
+                let (width, height) = self.text.measure(name, &style);
+                self.text.queue(name, [x - width / 2.0, y - height / 2.0], &style);
+            }
+        }
+
+        let label = self.picked.and_then(|id| {
+            let position = self.instances.get(id as usize)?.position;
+            Some((id, self.world_to_screen(cgmath::Point3::from_vec(position))?))
+        });
+        if let Some((id, [x, y])) = label {
+            let [x, y] = [x, y].map(|v| v / self.scale_factor as f32);
+            let style = TextStyle::new(16.0).with_color([255, 230, 120, 255]);
+            let text = format!("instance {}", id);
+            let (width, height) = self.text.measure(&text, &style);
+            // centred just above the instance
+            self.text.queue(&text, [x - width / 2.0, y - height - 8.0], &style);
+        }
+    }
+
+    // Rebuilds the scene and model pipelines after a shader source changed.
+    // Whichever doesn't compile is logged and keeps its old pipeline, so a
+    // broken edit never takes the app down.
+    fn reload_shaders(&mut self) {
+        profiling::scope!("reload shaders");
+
+        match self.rebuild_scene_pipelines() {
+            Ok(()) => log::info!("Scene shader reloaded"),
+            Err(e) => log::error!("{:#}", e),
+        }
+        match self.model_renderer.reload_shader(&self.device) {
+            Ok(()) => log::info!("Model shader reloaded"),
+            Err(e) => log::error!("{:#}", e),
+        }
+    }
+
+    fn rebuild_scene_pipelines(&mut self) -> anyhow::Result<()> {
+        let device = self.device.clone();
+        let shader = load_scene_shader(&self.materials, &ShaderDefs::new())?.create_module(&device, "Shader");
+        let samples = self.msaa_samples();
+        let (render_pipeline, wireframe_pipeline) = error_scope::check(&device, "Rebuilding the scene pipelines", || {
+            scene_pipelines(
+                &device,
+                &mut self.pipeline_cache,
+                &self.render_pipeline_layout,
+                &shader,
+                self.color_format,
+                samples,
+            )
+        })?;
+        self.render_pipeline = render_pipeline;
+        self.wireframe_pipeline = wireframe_pipeline;
+        // permutations of the old source go, the current one is rebuilt
+        self.scene_variants = PipelineCompiler::new(device, self.pipeline_cache.shared_cache());
+        self.set_scene_shading(self.scene_shading.clone());
+        Ok(())
+    }
+
+    // The pipeline for the current shading, or the main one as a
+    // placeholder while a permutation is still compiling
+    fn scene_pipeline(&self) -> &ScenePipelines {
+        self.scene_variants.get(&self.scene_shading).map_or(&self.render_pipeline, |pipelines| pipelines)
+    }
+
+    fn update(&mut self, dt: Duration) {
+        use cgmath::{Vector2, Vector3, Zero};
+
+        profiling::scope!("update");
+        self.call_plugins(|plugins, state| plugins.iter_mut().for_each(|plugin| plugin.on_update(state, dt)));
+
+        if self.shader_watcher.as_mut().is_some_and(ShaderWatcher::poll) {
+            self.reload_shaders();
+        }
+        for shading in self.scene_variants.poll() {
+            log::info!("Scene pipeline {} ready", shading);
+        }
+
+        self.poll_screenshot();
+        if let Some(picked) = self.gpu_picker.poll(&self.device) {
+            match picked {
+                Some(id) => log::info!("GPU picked instance {}", id),
+                None => log::info!("GPU picked nothing"),
+            }
+            self.picked = picked;
+        }
+        self.gpu_culler.poll(&self.device);
+        self.auto_exposure.poll(&self.device);
+        self.update_render_scale(dt);
+
+        // gamepads are polled, not evented, so sample them once per frame
+        #[cfg(feature = "gamepad")]
+        let (movement, look) = match self.gamepad.as_mut().map(gamepad::GamepadInput::poll) {
+            Some(gamepad) => {
+                for action in self.actions.process_gamepad(&gamepad).pressed {
+                    self.run_action(&action);
+                }
+                gamepad.camera_axes()
+            }
+            None => (Vector3::zero(), Vector2::zero()),
+        };
+        #[cfg(not(feature = "gamepad"))]
+        let (movement, look) = (Vector3::zero(), Vector2::zero());
+        self.camera_controller.process_actions(&self.actions);
+        // two-finger drags add to the left stick
+        self.camera_controller.process_analog(movement + self.touch.movement(self.size), look);
+
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        if let Some(ground) = self.ground_height(self.camera.position.x, self.camera.position.z) {
+            self.camera.position.y = self.camera.position.y.max(ground + CAMERA_CLEARANCE);
+        }
+        self.fit_projections();
+        let jitter = if self.taa {
+            let size = self.render_size();
+            self.taa_pass.next_jitter(size.width, size.height)
+        } else {
+            Vector2::zero()
+        };
+        self.camera_uniform.update_jittered(&self.camera, &self.projection, jitter);
+        if self.show_grid {
+            self.grid.update(&self.queue, self.camera_uniform.view_proj());
+        }
+        if self.show_axes {
+            self.axis_gizmo.update(&self.queue, self.camera.calc_matrix());
+        }
+        self.particles.update(&self.queue, dt);
+        if self.exposure.auto {
+            self.auto_exposure.update(&self.queue, &self.exposure, dt);
+        }
+
+        self.elapsed += dt;
+        if let Some(sky) = &mut self.sky {
+            sky.advance(dt);
+            sky.update(&self.queue, self.projection.zfar());
+            use cgmath::InnerSpace;
+            let light = sky.light();
+            if sky.sun_direction().dot(self.captured_sun) < ENVIRONMENT_RECAPTURE_COS {
+                self.environment_stale = true;
+            }
+            self.ambient = sky.ambient();
+            self.set_light(light);
+        }
+        // before the sky is captured, which is lit by the sun
+        let light_counts = self.upload_lights();
+        if self.environment_stale {
+            self.refresh_environment();
+        }
+        if self.probes_stale {
+            self.bake_reflection_probes();
+        }
+        if let Some((_, skybox)) = &self.environment {
+            skybox.update(&self.queue, self.projection.zfar());
+        }
+        for model in &mut self.models {
+            model.update(dt);
+        }
+        let moved = self.upload_transforms();
+        self.external_buffer.upload(&self.queue, &self.external_uniforms);
+        self.prepare_view(true);
+        self.update_lods(dt);
+        self.frame_stats.lights = light_counts.lights;
+        self.frame_stats.lights_dropped = light_counts.dropped;
+        self.frame_stats.moved = moved;
+        if self.show_hud {
+            self.queue_hud();
+        }
+        self.sprite_batch.flush(&self.device, &self.queue, self.size.width, self.size.height);
+
+        // exponential moving average, so the readout doesn't flicker
+        self.frame_time += (dt.as_secs_f32() - self.frame_time) * 0.05;
+        self.title_countdown -= dt.as_secs_f32();
+        if self.fps_in_title && self.title_countdown <= 0.0 {
+            self.update_title();
+        }
+        self.sync_settings(dt);
+        self.queue_text();
+        if let Err(e) = self.text.prepare(&self.device, &self.queue, self.size.width, self.size.height) {
+            log::warn!("{:#}", e);
+        }
+
+        if self.show_tilemap {
+            // pan back and forth across the whole map
+            let map_width = (TILEMAP_WIDTH * 16) as f32;
+            let view_width = self.size.width as f32 / self.tilemap.scale;
+            let pan = 0.5 - 0.5 * (self.elapsed.as_secs_f32() * 0.05).cos();
+            self.tilemap.camera.x = pan * (map_width - view_width).max(0.0);
+            // keep the ground at the bottom of the window
+            self.tilemap.camera.y = (TILEMAP_HEIGHT * 16) as f32 - self.size.height as f32 / self.tilemap.scale;
+            self.tilemap.prepare(&self.queue, self.size.width, self.size.height);
+        }
+        if self.show_debug {
+            self.queue_debug_lines();
+        }
+        if self.wireframe != WireframeMode::Off && self.wireframe_pipeline.is_none() {
+            self.queue_wireframe_fallback();
+        }
+        self.debug_draw.flush(&self.device, &self.queue);
+
+        // run as many fixed simulation steps as the elapsed time allows
+        if let Some(timestep) = self.fixed_timestep.as_mut() {
+            let step = timestep.step();
+            for _ in 0..timestep.advance(dt) {
+                self.fixed_update(step);
+            }
+        } else {
+            self.fixed_update(dt);
+        }
+        // what was pressed is this frame's only
+        self.actions.end_frame();
+    }
+
+    // Writes the camera's uniforms, and those of what's drawn differently
+    // from wherever it is, then culls for it. Once for each viewport, with
+    // its camera in the main one's place, `main` for the main camera itself.
+    fn prepare_view(&mut self, main: bool) {
+        self.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera_uniform]));
+        if let Some(water) = &self.water {
+            water.update(&self.queue, &self.camera, &self.projection, &self.camera_uniform, self.elapsed);
+        }
+        self.ssr_pass.update(&self.queue, &self.camera_uniform);
+        // only the main camera's frame is blurred
+        if main && self.depth_of_field.enabled {
+            let height = self.render_size().height;
+            self.dof_pass.update(&self.queue, &self.depth_of_field, &self.projection, height);
+        }
+        if main && self.motion_blur.enabled {
+            self.motion_blur_pass.update(&self.queue, &self.motion_blur, &self.camera_uniform);
+        }
+        if let Some(decals) = &self.decals {
+            decals.update(&self.queue, &self.camera_uniform);
+        }
+        self.cull(main);
+    }
+
+    // Frustum culls the frame's instances and models, for the GPU to
+    // finish in `encode_scene` when it culls the instances itself, and
+    // picks the terrain's levels of detail. Only the main camera occlusion
+    // culls, the depth pyramid is from its view.
+    fn cull(&mut self, main: bool) {
+        profiling::scope!("culling");
+        let view_proj = self.view_proj();
+        let frustum = bounds::Frustum::from_view_proj(&view_proj);
+        let mut stats = FrameStats {
+            resources: ResourceUsage::current(),
+            ..Default::default()
+        };
+        match self.culling {
+            CullingMode::Gpu => {
+                let occluders = (main && self.occlusion_culling).then_some(&self.depth_pyramid);
+                self.gpu_culler.update(&self.queue, &view_proj, occluders);
+                if let Some(gpu_stats) = self.gpu_culler.stats() {
+                    stats.instances = Some(gpu_stats.instances);
+                    stats.occluded = self.occlusion_culling.then_some(gpu_stats.occluded);
+                }
+            }
+            CullingMode::Cpu => stats.instances = Some(self.cpu_culler.cull(&self.queue, &frustum)),
+            CullingMode::Off => stats.instances = Some(CullCounts::all(self.instances.len())),
+        }
+        let frustum = (self.culling != CullingMode::Off).then_some(&frustum);
+        // models the camera doesn't draw aren't counted as culled
+        self.visible_models = self
+            .models
+            .iter()
+            .map(|model| self.camera_layers.intersects(model.layers) && stats.cull_model(frustum, model.world_bounds()))
+            .collect();
+        self.frame_stats = stats;
+        if let Some(terrain) = &mut self.terrain {
+            terrain.select(&self.queue, self.camera.position, frustum);
+        }
+    }
+
+    // The models' levels of detail, from the main camera's view; the other
+    // viewports' draw them the same
+    fn update_lods(&mut self, dt: Duration) {
+        for (model, _) in self.models.iter_mut().zip(&self.visible_models).filter(|(_, &visible)| visible) {
+            let size = model
+                .world_bounds()
+                .map_or(f32::MAX, |bounds| lod::screen_size(&Sphere::from(bounds), self.camera.position, &self.projection));
+            let levels = model.lod_levels();
+            model.instance.lod.update(self.lod_mode, size, levels, dt);
+        }
+    }
+
+    // Records a copy of the frame about to be presented
+    fn copy_screenshot(&self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::Texture) -> Option<Readback> {
+        if !self.config.usage.contains(wgpu::TextureUsages::COPY_SRC) {
+            log::warn!("This surface can't be copied, no screenshot");
+            return None;
+        }
+        encoder.insert_debug_marker("Screenshot Copy");
+        Readback::texture(&self.device, encoder, frame.as_image_copy(), frame.size())
+            .map_err(|e| log::warn!("{:#}", e))
+            .ok()
+    }
+
+    fn poll_screenshot(&mut self) {
+        let Some((readback, size)) = &mut self.pending_screenshot else {
+            return;
+        };
+        let Some(result) = readback.try_read(&self.device) else {
+            return;
+        };
+        let size = *size;
+        self.pending_screenshot = None;
+
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = PathBuf::from(format!("{}-{}.png", SCREENSHOT_PREFIX, secs));
+        let saved = result
+            .and_then(|data| readback::rgba_image(data, size.width, size.height, self.config.format))
+            .and_then(|mut image| {
+                // an opaque surface's alpha channel is whatever the shaders wrote
+                if !TRANSPARENT_ALPHA_MODES.contains(&self.config.alpha_mode) {
+                    image.pixels_mut().for_each(|pixel| pixel[3] = 255);
+                }
+                image.save(&path).map_err(anyhow::Error::from)
+            });
+        match saved {
+            Ok(()) => log::info!("Saved screenshot to {}", path.display()),
+            Err(e) => log::warn!("Screenshot failed: {:#}", e),
+        }
+    }
+
+    // Writes every draw of the next frame to draws-<unix time>-<frame>.json
+    // in the working directory, see `DrawList`
+    pub fn dump_draw_list(&mut self) {
+        self.draw_dump_requested = true;
+    }
+
+    // The same for every frame from now on, for catching the one where
+    // something goes wrong
+    pub fn set_dump_every_frame(&mut self, enabled: bool) {
+        self.dump_every_frame = enabled;
+        log::info!("Draw list dumps every frame: {}", if enabled { "on" } else { "off" });
+    }
+
+    fn save_draw_list(&self, draws: draw_list::DrawList) {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let path = PathBuf::from(format!("{}-{}-{}.json", DRAW_LIST_PREFIX, secs, draws.frame));
+        match draws.save(&path) {
+            Ok(()) => log::info!("Saved {} draws to {}", draws.draw_count(), path.display()),
+            Err(e) => log::warn!("{:#}", e),
+        }
+
+        let graph = FrameGraph::new(&draws);
+        let path = PathBuf::from(format!("{}-{}-{}", FRAME_GRAPH_PREFIX, secs, draws.frame));
+        let saved = graph
+            .save_dot(&path.with_extension("dot"))
+            .and_then(|()| graph.save_json(&path.with_extension("json")));
+        match saved {
+            Ok(()) => {
+                log::info!("Saved the graph of {} passes to {}.dot and .json", graph.passes.len(), path.display())
+            }
+            Err(e) => log::warn!("{:#}", e),
+        }
+    }
+
+    // Rigid bodies for the models that have one, at rest where they are,
+    // on the terrain
+    #[cfg(feature = "physics")]
+    fn reset_physics(&mut self) {
+        let ground = self.terrain.as_ref().map(Terrain::heightmap);
+        self.physics = physics::Physics::new(&self.models, ground);
+        if self.physics.body_count() > 0 {
+            log::info!("{} rigid bodies", self.physics.body_count());
+        }
+    }
+
+    #[cfg(not(feature = "physics"))]
+    fn reset_physics(&mut self) {
+        if self.models.iter().any(|model| model.body.is_some()) {
+            log::warn!("Built without the physics feature, the scene's rigid bodies stay put");
+        }
+    }
+
+    // deterministic gameplay logic goes here, `dt` is constant
+    // whenever a fixed timestep is set
+    // Writes the instances moved since the last frame over their old
+    // transforms, returning how many
+    fn upload_transforms(&mut self) -> usize {
+        let cpu_culler = &mut self.cpu_culler;
+        self.instances.upload(&self.queue, &self.instance_buffer, |index, instance| {
+            cpu_culler.update(index, instance);
+        })
+    }
+
+    fn fixed_update(&mut self, dt: Duration) {
+        #[cfg(feature = "physics")]
+        self.physics.step(dt, &mut self.models);
+        #[cfg(not(feature = "physics"))]
+        let _ = dt;
+    }
+
+    // What the scene passes record with this frame, see `ScenePasses`
+    fn scene_passes(&self) -> ScenePasses<'_> {
+        ScenePasses {
+            device: &self.device,
+            gpu_profiler: &self.gpu_profiler,
+            camera_bind_group: &self.camera_bind_group,
+            camera_layers: self.camera_layers,
+            background: self.fog.background(self.clear_color, self.projection.zfar(), self.camera.position.y),
+            view_matrix: self.camera.calc_matrix(),
+            color_format: self.color_format,
+            msaa: self.msaa.as_ref(),
+            pipelines: self.scene_pipeline(),
+            wireframe_pipeline: self.wireframe_pipeline.as_ref(),
+            wireframe: self.wireframe,
+            depth_prepass: self.depth_prepass,
+            prepass: &self.prepass,
+            shadow_map: &self.shadow_map,
+            materials: &self.materials,
+            vertex_buffer: &self.vertex_buffer,
+            index_buffer: &self.index_buffer,
+            num_indices: self.num_indices,
+            instance_buffer: &self.instance_buffer,
+            instance_count: self.instances.len() as u32,
+            culling: self.culling,
+            gpu_culled: self.gpu_culler.culled_draw(),
+            cpu_culler: &self.cpu_culler,
+            row_batch: &self.row_batch,
+            instance_bundles: &self.instance_bundles,
+            environment: self.environment.as_ref().map(|(_, skybox)| skybox),
+            sky: self.sky.as_ref(),
+            terrain: self.terrain.as_ref(),
+            water: self.water.as_ref(),
+            voxels: self.voxels.as_ref(),
+            decals: self.decals.as_ref(),
+            tilemap: &self.tilemap,
+            show_tilemap: self.show_tilemap,
+            grid: &self.grid,
+            show_grid: self.show_grid,
+            models: &self.models,
+            visible_models: &self.visible_models,
+            model_renderer: &self.model_renderer,
+            transparency: self.transparency,
+            oit: &self.oit,
+            ssr: self.ssr,
+            ssr_pass: &self.ssr_pass,
+            depth_of_field: self.depth_of_field.enabled,
+            particles: &self.particles,
+            debug_draw: &self.debug_draw,
+            picked: self.picked,
+            selection_outline: &self.selection_outline,
+        }
+    }
+
+    // The GPU culling's dispatch, before the passes that draw what it left
+    fn encode_culling(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.culling == CullingMode::Gpu {
+            profiling::scope!("culling");
+            let scope = self.gpu_profiler.begin("Culling", encoder, &self.device);
+            self.gpu_culler.cull(encoder);
+            self.gpu_profiler.end(encoder, scope);
+        }
+    }
+
+    // Records render bundles of the instances for the passes this frame
+    // draws them in, unless they're recorded already. Only the GPU culled
+    // draw is the same from frame to frame, the culling writes the counts.
+    fn prepare_bundles(&mut self) {
+        profiling::scope!("render bundles");
+        let mut bundles = std::mem::take(&mut self.instance_bundles);
+        if self.render_bundles && self.culling == CullingMode::Gpu {
+            let scene = self.scene_passes();
+            let passes = if scene.draws_prepass() {
+                vec![
+                    (self.prepass.pipeline(), DepthPrepass::TARGETS),
+                    (&*scene.pipelines.depth_equal, DepthPrepass::color_targets(self.color_format)),
+                ]
+            } else {
+                vec![(&**scene.pipelines.fill.get(scene.samples()), scene.scene_targets())]
+            };
+            let wanted = passes
+                .into_iter()
+                .map(|(pipeline, targets)| (scene.instance_bundle_key(pipeline, targets), pipeline));
+            bundles.update(wanted, |key, pipeline| {
+                render_bundle::record(&self.device, "Instances", key.targets, |encoder| {
+                    scene.bind_scene(encoder, pipeline);
+                    scene.gpu_culled.draw(encoder, 1);
+                })
+            });
+        } else {
+            bundles.clear();
+        }
+        self.instance_bundles = bundles;
+    }
+
+    fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        // nothing to draw to until resumed
+        let Some(surface) = &self.surface else {
+            return Ok(());
+        };
+        let output = {
+            // where a blocking present mode waits for the display
+            profiling::scope!("acquire");
+            surface.get_current_texture()?
+        };
+        let device = self.device.clone();
+        let frame_scope = ErrorScope::push(&device, "Rendering a frame");
+        let dump_draws = std::mem::take(&mut self.draw_dump_requested) || self.dump_every_frame;
+        if dump_draws {
+            draw_list::start();
+        }
+        let screenshot = self.render_frame(&output.texture);
+        if dump_draws {
+            self.save_draw_list(draw_list::finish(self.frame));
+        }
+        self.frame += 1;
+        self.frames.end_frame(&self.queue);
+
+        self.gpu_profiler.end_frame(&self.device, &self.queue);
+        self.gpu_picker.after_submit();
+        self.gpu_culler.after_submit();
+        self.auto_exposure.after_submit();
+        if let Some(timer) = &mut self.frame_timer {
+            timer.after_submit();
+        }
+        self.pending_screenshot = screenshot.map(|readback| (readback.map(), output.texture.size()));
+        {
+            profiling::scope!("present");
+            output.present();
+        }
+        self.report_frame_error(frame_scope.pop());
+
+        Ok(())
+    }
+
+    // In order, in one submission
+    fn submit(&mut self, command_buffers: Vec<wgpu::CommandBuffer>) {
+        profiling::scope!("submit");
+        self.staging_belt.finish();
+        let submission = self.queue.submit(command_buffers);
+        self.frames.submitted(submission);
+        self.staging_belt.recall();
+    }
+
+    // A broken frame tends to break the same way every frame after it, so
+    // each error is only logged once in a row
+    fn report_frame_error(&mut self, result: anyhow::Result<()>) {
+        let error = result.err().map(|e| format!("{:#}", e));
+        if let Some(message) = error.as_ref().filter(|&message| self.frame_error.as_ref() != Some(message)) {
+            log::error!("{}", message);
+        }
+        self.frame_error = error;
+    }
+
+    // Records and submits the whole frame, to be drawn into `frame` from
+    // the surface: the main camera's view, then each viewport's, then the
+    // 2D overlay over all of them
+    fn render_frame(&mut self, frame: &wgpu::Texture) -> Option<Readback> {
+        profiling::scope!("encode");
+        // before anything's written into this frame's slot
+        self.frames.begin_frame(&self.device);
+
+        let surface_view = frame.create_view(&wgpu::TextureViewDescriptor {
+            // the sRGB view of a linear surface format
+            format: self.config.view_formats.first().copied(),
+            ..Default::default()
+        });
+        // the cameras' frames are scaled into their rectangles, unless the
+        // main camera's is the only one, fills the window and is drawn at
+        // its size
+        let composited = !self.main_viewport.is_full() || !self.viewports.is_empty() || self.render_size() != self.size;
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Commands Encoder"),
+        });
+        if let Some(timer) = &self.frame_timer {
+            timer.begin(&mut encoder);
+        }
+
+        encoder.push_debug_group("Uploads");
+        let mut uploader = Uploader::new(&self.device, &mut encoder, &mut self.staging_belt).in_frame(&mut self.frames);
+        self.model_renderer.begin_frame();
+        for (object, model) in self.models.iter_mut().enumerate() {
+            model.upload(&mut uploader, &mut self.model_renderer, object as u32);
+        }
+        if let Some(voxels) = &mut self.voxels {
+            voxels.upload(&mut self.model_renderer, self.models.len() as u32);
+        }
+        self.model_renderer.upload(&mut uploader);
+        encoder.pop_debug_group();
+
+        // once for every pass the models are drawn in
+        let scope = self.gpu_profiler.begin("Skinning", &mut encoder, &self.device);
+        for model in &self.models {
+            model.skin(&mut encoder, &self.model_renderer);
+        }
+        self.gpu_profiler.end(&mut encoder, scope);
+        if let Some(voxels) = &mut self.voxels {
+            let scope = self.gpu_profiler.begin("Marching Cubes", &mut encoder, &self.device);
+            voxels.encode_mesh(&self.queue, &mut encoder);
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+
+        let scope = self.gpu_profiler.begin("Particles", &mut encoder, &self.device);
+        self.particles.simulate(&mut encoder);
+        self.gpu_profiler.end(&mut encoder, scope);
+
+        // where the 3D scene goes: the HDR target, unless TAA resolves into
+        // it or motion blur blurs into it later. It's tonemapped into FXAA's
+        // input if that's on, which FXAA filters into the frame, otherwise
+        // into the frame itself.
+        self.prepare_bundles();
+        let scene_view = if self.taa {
+            self.taa_pass.view()
+        } else if self.motion_blur.enabled {
+            self.motion_blur_pass.view()
+        } else {
+            self.tonemap.view()
+        };
+        self.encode_culling(&mut encoder);
+        // these only read what's uploaded and simulated above, so they're
+        // recorded at once, on encoders of their own
+        let scene = &self.scene_passes();
+        let mut jobs = Vec::new();
+        // the terrain's the only receiver so far
+        if let Some(terrain) = scene.terrain {
+            jobs.push(EncodeJob::new("Shadow Commands Encoder", |encoder| scene.encode_shadows(encoder, terrain)));
+        }
+        jobs.push(EncodeJob::new("Scene Commands Encoder", |encoder| scene.encode_scene(encoder, scene_view)));
+        jobs.push(EncodeJob::new("Model Commands Encoder", |encoder| scene.encode_models(encoder, scene_view)));
+        let mut command_buffers = vec![encoder.finish()];
+        command_buffers.extend(parallel_encoding::encode(&self.device, jobs));
+        let draws_prepass = scene.draws_prepass();
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Post Commands Encoder"),
+        });
+        // what the main camera's culling counted, before the viewports cull
+        if self.culling == CullingMode::Gpu {
+            self.gpu_culler.copy_stats(&mut encoder);
+        }
+        // the prepass's depth is what the next frame occlusion culls against
+        if self.occlusion_culling && self.culling == CullingMode::Gpu && draws_prepass {
+            let scope = self.gpu_profiler.begin("Depth Pyramid", &mut encoder, &self.device);
+            self.depth_pyramid.encode(&mut encoder, self.camera_uniform.view_proj());
+            self.gpu_profiler.end(&mut encoder, scope);
+        } else {
+            self.depth_pyramid.invalidate();
+        }
+
+        // anti-aliases, blurs and tonemaps everything so far, the 2D overlay
+        // stays crisp and goes on the finished frame
+        if self.taa || self.motion_blur.enabled {
+            self.encode_velocity(&mut encoder);
+        }
+        // the scene as it's resolved, before motion blur
+        let resolved_view = if self.motion_blur.enabled { self.motion_blur_pass.view() } else { self.tonemap.view() };
+        if self.taa {
+            let scope = self.gpu_profiler.begin("TAA", &mut encoder, &self.device);
+            self.taa_pass.resolve(&self.queue, &mut encoder, resolved_view);
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+        // on the resolved scene, so TAA doesn't smear the blur
+        if self.depth_of_field.enabled {
+            let scope = self.gpu_profiler.begin("Depth of Field", &mut encoder, &self.device);
+            self.dof_pass.encode(&self.device, &mut encoder, resolved_view);
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+        if self.motion_blur.enabled {
+            let scope = self.gpu_profiler.begin("Motion Blur", &mut encoder, &self.device);
+            self.motion_blur_pass.encode(&mut encoder, self.tonemap.view());
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+        // what moved this frame is where it was for the next one's velocity
+        if self.frame_stats.moved > 0 {
+            let instances = self.instance_buffer.buffer();
+            encoder.copy_buffer_to_buffer(instances, 0, self.prev_instance_buffer.buffer(), 0, instances.size());
+        }
+        // measured from the main camera's frame, the viewports' are exposed
+        // the same
+        if self.exposure.auto {
+            self.auto_exposure.encode(&mut encoder, &self.tonemap);
+        }
+        let view = self.gamma_blit.as_ref().map_or(&surface_view, GammaBlit::view);
+        if composited {
+            self.encode_post(&mut encoder, self.compositor.view());
+            self.compositor.encode(&mut encoder, view, self.size, self.main_viewport, Some(wgpu::Color::BLACK));
+        } else {
+            self.encode_post(&mut encoder, view);
+        }
+
+        // only records anything when a GPU pick is pending
+        self.gpu_picker.encode(&mut encoder, |pass, objects| {
+            pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            pass.set_bind_group(1, objects, &[self.mesh_object_id.offset]);
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            // every instance, so the picked ID is the instance index
+            pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+            pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+        });
+
+        // the viewports share the main camera's uniforms, which are written
+        // for each of them, so each is a submission of its own
+        if !self.viewports.is_empty() {
+            command_buffers.push(encoder.finish());
+            self.submit(std::mem::take(&mut command_buffers));
+            self.render_viewports(&surface_view);
+            encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Overlay Commands Encoder"),
+            });
+        }
+
+        // 2D goes last, on top of everything
+        let view = self.gamma_blit.as_ref().map_or(&surface_view, GammaBlit::view);
+        let mut render_pass = continue_pass(&mut encoder, view);
+        if self.show_axes {
+            let scale_factor = self.scale_factor as f32;
+            debug_group(&mut render_pass, "Axis Gizmo", |pass| self.axis_gizmo.draw(pass, self.size, scale_factor));
+        }
+        debug_group(&mut render_pass, "Sprites", |pass| self.sprite_batch.draw(pass));
+        debug_group(&mut render_pass, "Text", |pass| self.text.draw(pass));
+        drop(render_pass);
+        if !self.plugins.is_empty() {
+            let scope = self.gpu_profiler.begin("Plugins", &mut encoder, &self.device);
+            let mut context = RenderContext {
+                device: &self.device,
+                queue: &self.queue,
+                encoder: &mut encoder,
+                view,
+                format: self.frame_format,
+                size: self.size,
+            };
+            self.plugins.iter_mut().for_each(|plugin| plugin.on_render(&mut context));
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+
+        if let Some(gamma_blit) = &self.gamma_blit {
+            let scope = self.gpu_profiler.begin("Gamma Blit", &mut encoder, &self.device);
+            gamma_blit.encode(&mut encoder, &surface_view);
+            self.gpu_profiler.end(&mut encoder, scope);
+        }
+        let screenshot = std::mem::take(&mut self.screenshot_requested)
+            .then(|| self.copy_screenshot(&mut encoder, frame))
+            .flatten();
+
+        self.gpu_profiler.resolve(&mut encoder);
+        if let Some(timer) = &mut self.frame_timer {
+            timer.end(&mut encoder);
+        }
+        command_buffers.push(encoder.finish());
+        self.submit(command_buffers);
+        screenshot
+    }
+
+    // Each viewport's camera in the main one's place, drawn over the frame
+    // so far, then the main camera's put back for picking and the next
+    // frame. TAA keeps the main camera's history, so it's off for them.
+    fn render_viewports(&mut self, surface_view: &wgpu::TextureView) {
+        let stats = self.frame_stats;
+        for index in 0..self.viewports.len() {
+            self.swap_viewport(index);
+            self.camera_uniform.update_view_proj(&self.camera, &self.projection);
+            self.prepare_view(false);
+
+            let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Viewport Commands Encoder"),
+            });
+            self.encode_culling(&mut encoder);
+            self.scene_passes().encode_scene(&mut encoder, self.tonemap.view());
+            self.encode_post(&mut encoder, self.compositor.view());
+            let view = self.gamma_blit.as_ref().map_or(surface_view, GammaBlit::view);
+            self.compositor.encode(&mut encoder, view, self.size, self.viewports[index].rect, None);
+            self.swap_viewport(index);
+            self.submit(vec![encoder.finish()]);
+        }
+        self.prepare_view(true);
+        self.frame_stats = stats;
+    }
+
+    fn swap_viewport(&mut self, index: usize) {
+        let viewport = &mut self.viewports[index];
+        std::mem::swap(&mut self.camera, &mut viewport.camera);
+        std::mem::swap(&mut self.projection, &mut viewport.projection);
+        std::mem::swap(&mut self.camera_uniform, &mut viewport.uniform);
+        std::mem::swap(&mut self.camera_layers, &mut viewport.layers);
+    }
+
+    // Resolves the scene from TAA's target into the HDR one
+    // How far everything moved on screen since the last frame, for TAA and
+    // motion blur
+    fn encode_velocity(&self, encoder: &mut wgpu::CommandEncoder) {
+        let scene = self.scene_passes();
+        let scope = self.gpu_profiler.begin("Velocity", encoder, &self.device);
+        let mut velocity_pass = self.taa_pass.begin_velocity(encoder);
+        if let Some(terrain) = scene.drawn_terrain() {
+            terrain.draw_velocity(&mut velocity_pass, &self.camera_bind_group, &self.shadow_map);
+            velocity_pass.set_pipeline(self.taa_pass.velocity_pipeline());
+        }
+        if let Some(water) = scene.drawn_water() {
+            water.draw_velocity(&mut velocity_pass, &self.camera_bind_group);
+            velocity_pass.set_pipeline(self.taa_pass.velocity_pipeline());
+        }
+        if scene.draws_world() {
+            velocity_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+            velocity_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            velocity_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+            velocity_pass.set_vertex_buffer(2, self.prev_instance_buffer.buffer().slice(..));
+            velocity_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            velocity_pass.draw_indexed(0..self.num_indices, 0, 0..self.instances.len() as u32);
+        }
+        if let Some(voxels) = scene.drawn_voxels() {
+            voxels.draw_velocity(&mut velocity_pass, &self.model_renderer, &self.camera_bind_group);
+        }
+        for model in scene.drawn_models() {
+            model.draw_velocity(&mut velocity_pass, &self.model_renderer, &self.camera_bind_group);
+        }
+        drop(velocity_pass);
+        self.gpu_profiler.end(encoder, scope);
+    }
+
+    // Tonemaps the HDR target into `view`, through FXAA if that's on
+    fn encode_post(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let fxaa_view = if self.fxaa { self.fxaa_pass.view() } else { view };
+        let scope = self.gpu_profiler.begin("Tonemap", encoder, &self.device);
+        self.tonemap.encode(encoder, fxaa_view);
+        self.gpu_profiler.end(encoder, scope);
+        if self.fxaa {
+            let scope = self.gpu_profiler.begin("FXAA", encoder, &self.device);
+            self.fxaa_pass.encode(encoder, view);
+            self.gpu_profiler.end(encoder, scope);
+        }
+    }
+}
+
+// What the scene passes record with, borrowed from the state for the frame.
+// The passes are recorded on rayon's threads, so this is all they get: none
+// of the state only the main thread touches, like the plugins and whatever's
+// waiting on a channel.
+struct ScenePasses<'a> {
+    device: &'a wgpu::Device,
+    gpu_profiler: &'a GpuProfiler,
+    camera_bind_group: &'a wgpu::BindGroup,
+    camera_layers: RenderLayers,
+    // what the camera sees beyond everything, and where it looks from
+    background: wgpu::Color,
+    view_matrix: cgmath::Matrix4<f32>,
+    color_format: wgpu::TextureFormat,
+    msaa: Option<&'a MsaaTarget>,
+    pipelines: &'a ScenePipelines,
+    wireframe_pipeline: Option<&'a SampledPipeline<Arc<wgpu::RenderPipeline>>>,
+    wireframe: WireframeMode,
+    depth_prepass: bool,
+    prepass: &'a DepthPrepass,
+    shadow_map: &'a ShadowMap,
+    materials: &'a MaterialTextures,
+    // the instanced mesh
+    vertex_buffer: &'a Tracked<wgpu::Buffer>,
+    index_buffer: &'a Tracked<wgpu::Buffer>,
+    num_indices: u32,
+    instance_buffer: &'a StorageBuffer<InstanceRaw>,
+    instance_count: u32,
+    culling: CullingMode,
+    gpu_culled: CulledDraw<'a>,
+    cpu_culler: &'a CpuCuller,
+    row_batch: &'a IndirectBatch,
+    instance_bundles: &'a BundleCache<InstanceBundleKey>,
+    environment: Option<&'a Skybox>,
+    sky: Option<&'a Sky>,
+    terrain: Option<&'a Terrain>,
+    water: Option<&'a Water>,
+    voxels: Option<&'a VoxelTerrain>,
+    decals: Option<&'a Decals>,
+    tilemap: &'a Tilemap,
+    show_tilemap: bool,
+    grid: &'a Grid,
+    show_grid: bool,
+    models: &'a [SceneModel],
+    visible_models: &'a [bool],
+    model_renderer: &'a ModelRenderer,
+    transparency: TransparencyMode,
+    oit: &'a WeightedBlendedOit,
+    ssr: bool,
+    ssr_pass: &'a ScreenSpaceReflections,
+    // only its depth is needed from the G-buffer
+    depth_of_field: bool,
+    particles: &'a ParticleSystem,
+    debug_draw: &'a DebugDraw,
+    picked: Option<u32>,
+    selection_outline: &'a SelectionOutline,
+}
+
+impl<'a> ScenePasses<'a> {
+    // The terrain and the instances into the shadow map
+    fn encode_shadows(&self, encoder: &mut wgpu::CommandEncoder, terrain: &Terrain) {
+        let scope = self.gpu_profiler.begin_pass("Shadows", encoder, self.device);
+        let mut shadow_pass = self.shadow_map.begin(encoder, scope.render_pass_timestamp_writes());
+        debug_group(&mut shadow_pass, "Terrain", |pass| terrain.draw_shadow(pass));
+        debug_group(&mut shadow_pass, "Instances", |pass| {
+            pass.set_pipeline(self.shadow_map.instance_pipeline());
+            pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            // off screen instances cast shadows too
+            pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+            pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+            pass.draw_indexed(0..self.num_indices, 0, 0..self.instance_count);
+        });
+        drop(shadow_pass);
+        self.gpu_profiler.end(encoder, scope);
+    }
+
+    // Records the 3D scene from the camera into `scene_view`, which is HDR,
+    // with whatever's on the camera's layers, up to the models, which
+    // `encode_models` draws over it
+    fn encode_scene(&self, encoder: &mut wgpu::CommandEncoder, scene_view: &wgpu::TextureView) {
+        let world = self.draws_world();
+        let depth_prepass = self.draws_prepass();
+        if depth_prepass {
+            let scope = self.gpu_profiler.begin_pass("Depth Prepass", encoder, self.device);
+            let mut prepass = self.prepass.begin(encoder, scope.render_pass_timestamp_writes());
+            debug_group(&mut prepass, "Instances", |pass| {
+                self.draw_scene(pass, self.prepass.pipeline(), DepthPrepass::TARGETS)
+            });
+            drop(prepass);
+            self.gpu_profiler.end(encoder, scope);
+        }
+
+        let background = self.background;
+        if let Some(water) = self.drawn_water() {
+            let scope = self.gpu_profiler.begin("Water Reflection", encoder, self.device);
+            let reflection = water.reflection();
+            // the tilemap's a backdrop on the screen, nothing to reflect
+            let mut reflection_pass = water.begin_reflection(encoder, background);
+            self.draw_sky(&mut reflection_pass, water.reflection_bind_group());
+            if let Some(terrain) = self.drawn_terrain() {
+                drop(reflection_pass);
+                encoder.push_debug_group("Terrain");
+                let camera = water.reflection_bind_group();
+                terrain.encode_with_depth(encoder, reflection.view(), reflection.depth_view(), camera, self.shadow_map);
+                encoder.pop_debug_group();
+                reflection_pass = continue_pass(encoder, reflection.view());
+            }
+            // culled for the camera, not its reflection
+            self.draw_surroundings(&mut reflection_pass, water.reflection_bind_group(), false);
+            drop(reflection_pass);
+            self.gpu_profiler.end(encoder, scope);
+        }
+
+        // create our render pass, off screen first when there's water to
+        // go over what's been drawn so far
+        let opaque_view = self.drawn_water().map_or(scene_view, |water| water.scene().view());
+        let pass_scope = self.gpu_profiler.begin_pass("Render Pass", encoder, self.device);
+        let load = wgpu::LoadOp::Clear(background);
+        let timestamp_writes = pass_scope.render_pass_timestamp_writes();
+        let mut render_pass = match self.msaa {
+            Some(msaa) => msaa.begin(self.device, encoder, "Render Pass", opaque_view, load, timestamp_writes),
+            None => TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+                label: Some("Render Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: opaque_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes,
+            }),
+        };
+
+        // no depth buffer, so the backdrops just go down first
+        if world {
+            self.draw_sky(&mut render_pass, self.camera_bind_group);
+        }
+        if self.show_tilemap && world {
+            debug_group(&mut render_pass, "Tilemap", |pass| self.tilemap.draw(pass));
+        }
+        // with a depth buffer of its own, everything else goes on top
+        if let Some(terrain) = self.drawn_terrain() {
+            drop(render_pass);
+            encoder.push_debug_group("Terrain");
+            // the water's hidden by the terrain in front of it and decals
+            // are projected onto it, either keeps its depth
+            let depth = self.drawn_water().map(|water| water.scene().depth_view());
+            let depth = depth.or(self.decals.map(Decals::depth_view));
+            match depth {
+                Some(depth) => {
+                    terrain.encode_with_depth(encoder, opaque_view, depth, self.camera_bind_group, self.shadow_map)
+                }
+                None => terrain.encode(encoder, scene_view, self.camera_bind_group, self.shadow_map),
+            }
+            encoder.pop_debug_group();
+            if let (Some(decals), Some(depth)) = (self.decals, depth) {
+                encoder.push_debug_group("Decals");
+                decals.encode(self.device, encoder, opaque_view, depth, self.camera_bind_group, self.shadow_map);
+                encoder.pop_debug_group();
+            }
+            render_pass = self.continue_scene_pass(encoder, opaque_view);
+        }
+        if let Some(voxels) = self.drawn_voxels() {
+            drop(render_pass);
+            encoder.push_debug_group("Voxels");
+            voxels.encode(encoder, opaque_view, self.model_renderer, self.camera_bind_group);
+            encoder.pop_debug_group();
+            render_pass = self.continue_scene_pass(encoder, opaque_view);
+        }
+        // refracts the backdrop and the terrain, like the terrain everything
+        // after it goes on top
+        if let Some(water) = self.drawn_water() {
+            drop(render_pass);
+            encoder.push_debug_group("Water");
+            if self.drawn_terrain().is_none() {
+                water.clear_depth(encoder);
+            }
+            water.encode(encoder, scene_view, self.camera_bind_group);
+            encoder.pop_debug_group();
+            render_pass = self.continue_scene_pass(encoder, scene_view);
+        }
+
+        if depth_prepass {
+            // the depth buffer needs a pass of its own, the rest of the
+            // frame goes on without it
+            drop(render_pass);
+            let mut opaque_pass = self.prepass.begin_color(encoder, scene_view);
+            let targets = DepthPrepass::color_targets(self.color_format);
+            debug_group(&mut opaque_pass, "Instances", |pass| {
+                self.draw_scene(pass, &self.pipelines.depth_equal, targets)
+            });
+            if self.show_grid && world {
+                debug_group(&mut opaque_pass, "Grid", |pass| self.grid.draw(pass, self.camera_bind_group, true));
+            }
+            drop(opaque_pass);
+            render_pass = self.continue_scene_pass(encoder, scene_view);
+        } else {
+            // nothing to test its depth against, so it goes under them
+            if self.show_grid && world {
+                debug_group(&mut render_pass, "Grid", |pass| self.grid.draw(pass, self.camera_bind_group, false));
+            }
+            if self.wireframe != WireframeMode::Only && world {
+                let pipeline = self.pipelines.fill.get(self.samples());
+                let targets = self.scene_targets();
+                debug_group(&mut render_pass, "Instances", |pass| self.draw_scene(pass, pipeline, targets));
+            }
+        }
+        if let Some(pipeline) = self.wireframe_pipeline {
+            // no depth buffer, drawing second is enough to stay on top
+            if self.wireframe != WireframeMode::Off && world {
+                let (pipeline, targets) = (pipeline.get(self.samples()), self.scene_targets());
+                debug_group(&mut render_pass, "Wireframe", |pass| self.draw_scene(pass, pipeline, targets));
+            }
+        }
+        drop(render_pass);
+        self.gpu_profiler.end(encoder, pass_scope);
+    }
+
+    // The models over what `encode_scene` drew, then what goes over them:
+    // their reflections, whatever's translucent, the particles, debug lines
+    // and the selection outline
+    fn encode_models(&self, encoder: &mut wgpu::CommandEncoder, scene_view: &wgpu::TextureView) {
+        let world = self.draws_world();
+        let pass_scope = self.gpu_profiler.begin("Models", encoder, self.device);
+        let mut render_pass = self.continue_scene_pass(encoder, scene_view);
+
+        // each model is a group of its own, named after its file
+        let view_matrix = self.view_matrix;
+        let mut queues = RenderQueues::new();
+        for model in self.drawn_models() {
+            if self.transparency != TransparencyMode::CutOut && model.instance.is_transparent() {
+                queues.push_transparent(model, &view_matrix, model.center());
+            } else {
+                queues.push_opaque(model);
+            }
+        }
+        queues.sort();
+        for model in queues.opaque() {
+            model.draw(&mut render_pass, self.model_renderer, self.camera_bind_group);
+        }
+        // over the opaque scene, which the trace reads, and under what's
+        // translucent, which isn't in the G-buffer. Depth of field only
+        // needs its depth.
+        if self.ssr || self.depth_of_field {
+            drop(render_pass);
+            let scope = self.gpu_profiler.begin("SSR", encoder, self.device);
+            let mut gbuffer_pass = self.ssr_pass.begin_gbuffer(encoder);
+            if let Some(terrain) = self.drawn_terrain() {
+                terrain.draw_gbuffer(&mut gbuffer_pass, self.camera_bind_group, self.shadow_map);
+                gbuffer_pass.set_pipeline(self.ssr_pass.instance_pipeline());
+            }
+            if self.wireframe != WireframeMode::Only && world {
+                gbuffer_pass.set_bind_group(0, self.camera_bind_group, &[]);
+                gbuffer_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                gbuffer_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                gbuffer_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                gbuffer_pass.draw_indexed(0..self.num_indices, 0, 0..self.instance_count);
+            }
+            if let Some(voxels) = self.drawn_voxels() {
+                voxels.draw_gbuffer(&mut gbuffer_pass, self.model_renderer, self.camera_bind_group);
+            }
+            for model in queues.opaque() {
+                model.draw_gbuffer(&mut gbuffer_pass, self.model_renderer, self.camera_bind_group);
+            }
+            drop(gbuffer_pass);
+            if self.ssr {
+                self.ssr_pass.encode(self.device, encoder, scene_view, self.camera_bind_group);
+            }
+            self.gpu_profiler.end(encoder, scope);
+            render_pass = self.continue_scene_pass(encoder, scene_view);
+        }
+        if self.transparency == TransparencyMode::Sorted {
+            for model in queues.transparent() {
+                model.draw_blended(&mut render_pass, self.model_renderer, self.camera_bind_group);
+            }
+        } else if queues.has_transparent() {
+            // the accumulation targets need a pass of their own, the rest
+            // of the frame goes on in another one after the resolve
+            drop(render_pass);
+            let scope = self.gpu_profiler.begin_pass("Transparency", encoder, self.device);
+            let mut oit_pass = self.oit.begin_accumulate(encoder, scope.render_pass_timestamp_writes());
+            for model in queues.transparent() {
+                model.draw_oit(&mut oit_pass, self.model_renderer, self.camera_bind_group);
+            }
+            drop(oit_pass);
+            self.gpu_profiler.end(encoder, scope);
+
+            render_pass = self.continue_scene_pass(encoder, scene_view);
+            debug_group(&mut render_pass, "OIT Resolve", |pass| self.oit.resolve(pass));
+        }
+        if world {
+            debug_group(&mut render_pass, "Particles", |pass| self.particles.draw(pass, self.camera_bind_group));
+        }
+        if self.camera_layers.intersects(RenderLayers::DEBUG) {
+            debug_group(&mut render_pass, "Debug Lines", |pass| self.debug_draw.draw(pass, self.camera_bind_group));
+        }
+
+        // over the 3D scene and under the 2D overlay, in a pass with a
+        // stencil buffer
+        if let Some(selected) = self.picked.filter(|&id| world && id < self.instance_count) {
+            drop(render_pass);
+            encoder.push_debug_group("Selection Outline");
+            self.selection_outline.encode(encoder, scene_view, |pass| {
+                pass.set_bind_group(0, self.camera_bind_group, &[]);
+                pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                pass.draw_indexed(0..self.num_indices, 0, selected..selected + 1);
+            });
+            encoder.pop_debug_group();
+            render_pass = self.continue_scene_pass(encoder, scene_view);
+        }
+        drop(render_pass);
+        self.gpu_profiler.end(encoder, pass_scope);
+    }
+
+    // `continue_pass` for the passes drawn straight into the scene, through
+    // the multisampled target with MSAA on
+    fn continue_scene_pass<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        view: &'e wgpu::TextureView,
+    ) -> TracedPass<'e>
+    where
+        'a: 'e,
+    {
+        match self.msaa {
+            Some(msaa) => msaa.begin(self.device, encoder, "Overlay Pass", view, wgpu::LoadOp::Load, None),
+            None => continue_pass(encoder, view),
+        }
+    }
+
+    fn samples(&self) -> u32 {
+        self.msaa.map_or(1, MsaaTarget::samples)
+    }
+
+    fn draws_world(&self) -> bool {
+        self.camera_layers.intersects(RenderLayers::WORLD)
+    }
+
+    fn draws_prepass(&self) -> bool {
+        // nothing to shade without the instances
+        self.depth_prepass && self.wireframe != WireframeMode::Only && self.draws_world()
+    }
+
+    fn drawn_terrain(&self) -> Option<&'a Terrain> {
+        self.terrain.filter(|_| self.draws_world())
+    }
+
+    fn drawn_water(&self) -> Option<&'a Water> {
+        self.water.filter(|_| self.draws_world())
+    }
+
+    fn drawn_voxels(&self) -> Option<&'a VoxelTerrain> {
+        self.voxels.filter(|_| self.draws_world())
+    }
+
+    // The models that passed culling, on the camera's layers
+    fn drawn_models(&self) -> impl Iterator<Item = &'a SceneModel> + '_ {
+        // a model spawned since the last update hasn't been culled yet
+        self.models
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| self.visible_models.get(*index).copied().unwrap_or(true))
+            .map(|(_, model)| model)
+    }
+
+    // The instances with `pipeline`, in a pass with `targets`, from the
+    // render bundle for them if there is one
+    fn draw_scene<'p>(
+        &self,
+        render_pass: &mut TracedPass<'p>,
+        pipeline: &'p wgpu::RenderPipeline,
+        targets: BundleTargets,
+    ) where
+        'a: 'p,
+    {
+        if let Some(bundle) = self.instance_bundles.get(&self.instance_bundle_key(pipeline, targets)) {
+            render_pass.execute_bundles(std::iter::once(bundle));
+            return;
+        }
+        self.bind_scene(render_pass, pipeline);
+        match self.culling {
+            CullingMode::Gpu => self.gpu_culled.draw(render_pass, 1),
+            CullingMode::Cpu => self.cpu_culler.draw(render_pass, 1),
+            CullingMode::Off => {
+                render_pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                self.row_batch.draw(render_pass);
+            }
+        }
+    }
+
+    fn bind_scene<'p>(&self, encoder: &mut impl RenderEncoder<'p>, pipeline: &'p wgpu::RenderPipeline)
+    where
+        'a: 'p,
+    {
+        encoder.set_pipeline(pipeline);
+        encoder.set_bind_group(0, self.camera_bind_group, &[]);
+        // one bind group for every material, instances pick by index
+        encoder.set_bind_group(1, self.materials.bind_group(), &[]);
+        encoder.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        encoder.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+    }
+
+    fn instance_bundle_key(&self, pipeline: &wgpu::RenderPipeline, targets: BundleTargets) -> InstanceBundleKey {
+        InstanceBundleKey {
+            pipeline: pipeline.global_id(),
+            targets,
+            bind_groups: [self.camera_bind_group.global_id(), self.materials.bind_group().global_id()],
+            buffers: [
+                self.vertex_buffer.global_id(),
+                self.index_buffer.global_id(),
+                self.gpu_culled.visible_instances().global_id(),
+                self.gpu_culled.indirect_buffer().global_id(),
+            ],
+        }
+    }
+
+    // What the main pass draws to, as render bundles see it
+    fn scene_targets(&self) -> BundleTargets {
+        BundleTargets {
+            color: Some(self.color_format),
+            depth: None,
+            sample_count: self.samples(),
+        }
+    }
+
+    // The opaque instances and models, unculled, for views other than the
+    // camera's. `capture` is for a face of a cubemap, which winds the other
+    // way, see `render_target::CUBE_FACE_FRONT_FACE`.
+    fn draw_surroundings<'p>(
+        &self,
+        render_pass: &mut TracedPass<'p>,
+        camera_bind_group: &'p wgpu::BindGroup,
+        capture: bool,
+    ) where
+        'a: 'p,
+    {
+        if self.wireframe != WireframeMode::Only {
+            debug_group(render_pass, "Instances", |pass| {
+                let pipelines = self.pipelines;
+                let samples = pass.sample_count();
+                pass.set_pipeline(if capture { &pipelines.capture } else { pipelines.fill.get(samples) });
+                pass.set_bind_group(0, camera_bind_group, &[]);
+                pass.set_bind_group(1, self.materials.bind_group(), &[]);
+                pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+                pass.set_vertex_buffer(1, self.instance_buffer.buffer().slice(..));
+                pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                self.row_batch.draw(pass);
+            });
+        }
+        for model in self.models.iter().filter(|model| !model.instance.is_transparent()) {
+            if capture {
+                model.draw_capture(render_pass, self.model_renderer, camera_bind_group);
+            } else {
+                model.draw(render_pass, self.model_renderer, camera_bind_group);
+            }
+        }
+    }
+
+    // The environment cubemap when the scene has one, otherwise the sky
+    fn draw_sky<'p>(&self, render_pass: &mut TracedPass<'p>, camera_bind_group: &'p wgpu::BindGroup)
+    where
+        'a: 'p,
+    {
+        if let Some(skybox) = self.environment {
+            debug_group(render_pass, "Skybox", |pass| skybox.draw(pass, camera_bind_group));
+        } else if let Some(sky) = self.sky {
+            debug_group(render_pass, "Sky", |pass| sky.draw(pass, camera_bind_group));
+        }
+    }
+}
+
+pub async fn run() {
+    env_logger::init();
+    let config = match Config::from_env_and_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}\n\n{}", e, config::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = &config.convert_mesh {
+        match mesh_file::convert(path) {
+            Ok(out) => println!("Wrote {}", out.display()),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if config.list_adapters {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: config.backends,
+            ..Default::default()
+        });
+        for adapter in adapter::enumerate_adapters(&instance, config.backends) {
+            println!("{}", adapter);
+        }
+        return;
+    }
+    run_with(EventLoop::new().unwrap(), config);
+}
+
+// The event loop behind `run` and the Android entry point
+pub fn run_with(event_loop: EventLoop<()>, config: Config) {
+    run_with_plugins(event_loop, config, Vec::new());
+}
+
+// `run_with`, with plugins added to the state once it's created
+pub fn run_with_plugins(event_loop: EventLoop<()>, config: Config, mut plugins: Vec<Box<dyn Plugin>>) {
+    // lives until the event loop is done
+    let _profiler_session = profiler::start();
+    let window = config.window.builder().build(&event_loop).unwrap();
+    let window = &window;
+    // Android only has a native window to draw to after the first Resumed,
+    // so the state is created there on every platform
+    let mut state: Option<State> = None;
+    let mut last_render_time = Instant::now();
+
+    let res = event_loop.run(move |event, control_flow| {
+        if let Event::Resumed = event {
+            match state.as_mut() {
+                Some(state) => state.resume(),
+                None => {
+                    let mut new_state = pollster::block_on(State::new(window, &config));
+                    // `cargo run -- level.ron` starts with a scene file
+                    if let Some(path) = &config.scene {
+                        if let Err(e) = new_state.load_scene(path) {
+                            log::error!("{:#}", e);
+                        }
+                    }
+                    if let Some(file) = &config.settings_file {
+                        new_state.use_settings(file.clone(), &config.settings);
+                    }
+                    // the command line wins over the settings file and the
+                    // scene's settings
+                    if let Some(mode) = config.present_mode {
+                        new_state.set_present_mode(mode);
+                    }
+                    if config.max_fps.is_some() {
+                        new_state.set_max_fps(config.max_fps);
+                    }
+                    if let Some(path) = &config.bindings {
+                        if let Err(e) = new_state.actions_mut().load(path) {
+                            log::error!("{:#}", e);
+                        }
+                    }
+                    for plugin in std::mem::take(&mut plugins) {
+                        new_state.add_plugin(plugin);
+                    }
+                    state = Some(new_state);
+                    last_render_time = Instant::now();
+                }
+            }
+            if let Some(state) = state.as_mut() {
+                state.plugin_event(&event);
+            }
+            return;
+        }
+        let Some(state) = state.as_mut() else {
+            return;
+        };
+        let used = state.plugin_event(&event);
+
+        match event {
+            Event::WindowEvent { 
+                window_id, 
+                ref event 
+            } if window_id == state.window().id() && !used && !state.input(event) => {
+                match event {
+                    WindowEvent::CloseRequested => state.request_exit(),
+                    WindowEvent::Resized(physical_size) => {
+                        state.resize(*physical_size);
+                    },
+                    WindowEvent::Occluded(occluded) => state.occluded = *occluded,
+                    WindowEvent::RedrawRequested => {
+                        state.frame_pacer.begin_frame();
+                        let now = Instant::now();
+                        let dt = now - last_render_time;
+                        last_render_time = now;
+                        state.update(dt);
+                        let result = state.render();
+                        profiling::finish_frame!();
+                        match result {
+                            Ok(_) => {}
+                            // Reconfigure the surface if it's lost or out of date
+                            Err(
+                                wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated,
+                            ) => state.resize(state.size),
+                            // Quit on OOM error
+                            Err(wgpu::SurfaceError::OutOfMemory) => {
+                                log::error!("Out of memory");
+                                control_flow.exit();
+                            },
+                            Err(wgpu::SurfaceError::Timeout) => {
+                                log::warn!("Device timeout!")
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } => state.mouse_motion(delta),
+            Event::Suspended => state.suspend(),
+            // Frames are asked for here, once the events are handled, and
+            // no sooner than the frame pacer allows
+            Event::AboutToWait => {
+                if state.exit_requested {
+                    state.save_pipeline_cache();
+                    state.save_settings();
+                    control_flow.exit();
+                } else if state.is_hidden() {
+                    // sleep until something changes, and don't count the
+                    // time hidden as a frame's
+                    control_flow.set_control_flow(ControlFlow::Wait);
+                    last_render_time = Instant::now();
+                } else if let Some(deadline) = state.frame_pacer.wait() {
+                    control_flow.set_control_flow(ControlFlow::WaitUntil(deadline));
+                } else {
+                    control_flow.set_control_flow(ControlFlow::Wait);
+                    state.window().request_redraw();
                 }
-                _ => {}
             }
+            _ => {}
         }
-        _ => {}
     });
 
-    println!("result is: {:?}", res.unwrap().report());
+    println!("result is: {:?}", res);
 }
\ No newline at end of file
@@ -0,0 +1,283 @@
+use bytemuck::Zeroable;
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::{Frustum, Sphere};
+use crate::compute::StorageBuffer;
+use crate::culling::CullCounts;
+
+// Where the light comes from without a sky, with its own up above and a
+// little behind the default camera
+pub const LIGHT_DIR: Vector3<f32> = Vector3::new(0.4, 0.8, 0.45);
+// the ambient without a sky, enough that faces turned away from the light
+// don't go black
+pub const AMBIENT: [f32; 3] = [0.25; 3];
+// how many lights `LightBuffers` holds once they're culled, the scene's
+// light and the rest
+pub const MAX_LIGHTS: usize = 1024;
+
+/// A light in the scene. Intensities are physical-ish: a directional
+/// light's is the illuminance on a surface facing it, a point or spot
+/// light's its luminous intensity, which makes that illuminance one unit
+/// away and falls off with the square of the distance, smoothly down to
+/// none at `range`. Colours are linear RGB, times the intensity.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Light {
+    Directional {
+        // towards the light
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+    },
+    Point {
+        position: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+    },
+    // a point light in a cone along `direction`, full inside
+    // `inner_angle` and fading out to `outer_angle`, in degrees from its axis
+    Spot {
+        position: [f32; 3],
+        direction: [f32; 3],
+        color: [f32; 3],
+        intensity: f32,
+        range: f32,
+        inner_angle: f32,
+        outer_angle: f32,
+    },
+}
+
+// the scene's light without a sky
+impl Default for Light {
+    fn default() -> Self {
+        Self::Directional {
+            direction: LIGHT_DIR.normalize().into(),
+            color: [1.0; 3],
+            intensity: 0.75,
+        }
+    }
+}
+
+impl Light {
+    // Towards a directional light, which is the only kind shadows are cast
+    // from
+    pub fn direction(&self) -> Option<Vector3<f32>> {
+        match *self {
+            Light::Directional { direction, .. } => Some(Vector3::from(direction).normalize()),
+            Light::Point { .. } | Light::Spot { .. } => None,
+        }
+    }
+
+    // Everything a point or spot light reaches, None for a directional one,
+    // which reaches everything. A spot light's cone is bounded as in Bart
+    // Wronski's "Cull that cone!".
+    pub fn bounds(&self) -> Option<Sphere> {
+        match *self {
+            Light::Directional { .. } => None,
+            Light::Point { position, range, .. } => Some(Sphere::new(position.into(), range)),
+            Light::Spot {
+                position,
+                direction,
+                range,
+                outer_angle,
+                ..
+            } => {
+                let angle = Rad::from(Deg(outer_angle.clamp(0.0, 90.0)));
+                let axis = Vector3::from(direction).normalize();
+                let (center, radius) = if angle.0 > std::f32::consts::FRAC_PI_4 {
+                    (range * angle.cos(), range * angle.sin())
+                } else {
+                    let radius = range / (2.0 * angle.cos());
+                    (radius, radius)
+                };
+                Some(Sphere::new(Point3::from(position) + axis * center, radius))
+            }
+        }
+    }
+}
+
+// One light as lighting.wgsl sees it, whatever kind it is
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuLight {
+    position: [f32; 3],
+    // LIGHT_DIRECTIONAL, LIGHT_POINT or LIGHT_SPOT in lighting.wgsl
+    kind: u32,
+    // towards a directional light, along a spot light
+    direction: [f32; 3],
+    range: f32,
+    // the colour times the intensity
+    color: [f32; 3],
+    // take the cosine from the spot's axis into 0 to 1 across the cone's
+    // edge, see `cone_falloff`
+    cone_scale: f32,
+    cone_offset: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&Light> for GpuLight {
+    fn from(light: &Light) -> Self {
+        let radiance = |color: [f32; 3], intensity: f32| color.map(|channel| channel * intensity);
+        match *light {
+            Light::Directional {
+                direction,
+                color,
+                intensity,
+            } => GpuLight {
+                kind: 0,
+                direction: Vector3::from(direction).normalize().into(),
+                color: radiance(color, intensity),
+                ..Zeroable::zeroed()
+            },
+            Light::Point {
+                position,
+                color,
+                intensity,
+                range,
+            } => GpuLight {
+                position,
+                kind: 1,
+                range: range.max(1e-3),
+                color: radiance(color, intensity),
+                ..Zeroable::zeroed()
+            },
+            Light::Spot {
+                position,
+                direction,
+                color,
+                intensity,
+                range,
+                inner_angle,
+                outer_angle,
+            } => {
+                let outer = Rad::from(Deg(outer_angle.clamp(0.0, 90.0))).cos();
+                let inner = Rad::from(Deg(inner_angle.clamp(0.0, outer_angle))).cos();
+                let cone_scale = 1.0 / (inner - outer).max(1e-4);
+                GpuLight {
+                    position,
+                    kind: 2,
+                    direction: Vector3::from(direction).normalize().into(),
+                    range: range.max(1e-3),
+                    color: radiance(color, intensity),
+                    cone_scale,
+                    cone_offset: -outer * cone_scale,
+                    _padding: [0.0; 3],
+                }
+            }
+        }
+    }
+}
+
+// Matches `LightUniform` in lighting.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LightUniform {
+    // linear RGB, on every surface whichever way it faces
+    ambient: [f32; 3],
+    // of `LightBuffers::lights` this frame
+    count: u32,
+    // the index of the one shadows are cast from, NO_SHADOW_CASTER for none
+    shadow_caster: u32,
+    _padding: [u32; 3],
+}
+
+// `LightUniform::shadow_caster` when none of the lights casts shadows
+const NO_SHADOW_CASTER: u32 = u32::MAX;
+
+// Which light the terrain's shadows are cast from: the first directional
+// one, the only kind the shadow map can be fitted for
+pub fn shadow_caster(lights: &[Light]) -> Option<usize> {
+    lights.iter().position(|light| light.direction().is_some())
+}
+
+/// How many lights `LightBuffers::upload` got through to the shaders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LightCullStats {
+    pub lights: CullCounts,
+    // of those in view, the ones past `MAX_LIGHTS` that were left out
+    pub dropped: u32,
+}
+
+// The scene's lights as shaders bind them next to the camera: the ambient
+// and how many lights there are at binding 2, the lights themselves at
+// binding 7. `upload` leaves out the ones the camera can't see the light
+// of, so there can be hundreds as long as not all of them are in view.
+pub struct LightBuffers {
+    uniform: wgpu::Buffer,
+    lights: StorageBuffer<GpuLight>,
+}
+
+impl LightBuffers {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            uniform: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Light Buffer"),
+                size: std::mem::size_of::<LightUniform>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            }),
+            lights: StorageBuffer::zeroed(device, "Light Storage Buffer", MAX_LIGHTS),
+        }
+    }
+
+    // Once a frame, with the lights that reach into `frustum`, or all of
+    // them without one. The terrain's shadows are cast from `shadow_caster`'s
+    // pick of them, which is never culled. Any past `MAX_LIGHTS` are left out.
+    pub fn upload(
+        &self,
+        queue: &wgpu::Queue,
+        ambient: [f32; 3],
+        lights: &[Light],
+        frustum: Option<&Frustum>,
+    ) -> LightCullStats {
+        let caster = shadow_caster(lights);
+        let mut stats = LightCullStats::default();
+        let mut shadow_caster = NO_SHADOW_CASTER;
+        let mut visible = Vec::new();
+        for (index, light) in lights.iter().enumerate() {
+            let in_view = match (frustum, light.bounds()) {
+                (Some(frustum), Some(bounds)) => frustum.intersects_sphere(&bounds),
+                _ => true,
+            };
+            stats.lights.count(in_view);
+            if in_view {
+                if caster == Some(index) {
+                    shadow_caster = visible.len() as u32;
+                }
+                visible.push(GpuLight::from(light));
+            }
+        }
+        if visible.len() > MAX_LIGHTS {
+            stats.dropped = (visible.len() - MAX_LIGHTS) as u32;
+            stats.lights.drawn -= stats.dropped;
+            visible.truncate(MAX_LIGHTS);
+        }
+        if shadow_caster as usize >= visible.len() {
+            shadow_caster = NO_SHADOW_CASTER;
+        }
+        self.lights.write(queue, 0, &visible);
+        let uniform = LightUniform {
+            ambient,
+            count: visible.len() as u32,
+            shadow_caster,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::cast_slice(&[uniform]));
+        stats
+    }
+
+    // Bindings 2 and 7 of the camera bind group, see `camera::layout_entries`
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 2] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: self.uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 7,
+                resource: self.lights.binding(),
+            },
+        ]
+    }
+}
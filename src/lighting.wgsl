@@ -0,0 +1,79 @@
+// The scene's lights, directional, point and spot, as `LightBuffers` in
+// lighting.rs binds them next to the camera
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+// `LightUniform::shadow_caster` when no light casts shadows
+const NO_SHADOW_CASTER: u32 = 0xffffffffu;
+// Matches `GpuLight` in lighting.rs
+struct GpuLight {
+    position: vec3<f32>,
+    kind: u32,
+    // towards a directional light, along a spot light, normalised
+    direction: vec3<f32>,
+    range: f32,
+    // the colour times the intensity
+    color: vec3<f32>,
+    cone_scale: f32,
+    cone_offset: f32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+};
+
+// Matches `LightUniform` in lighting.rs
+struct LightUniform {
+    ambient: vec3<f32>,
+    // of the lights bound at binding 7 this frame
+    count: u32,
+    // which of them the terrain's shadows are cast from, the first
+    // directional one, or NO_SHADOW_CASTER
+    shadow_caster: u32,
+    _padding0: u32,
+    _padding1: u32,
+    _padding2: u32,
+};
+
+// Where one light comes from at a point, and what reaches a surface there
+// facing it
+struct LightSample {
+    direction: vec3<f32>,
+    radiance: vec3<f32>,
+};
+
+// Inverse square, windowed down to nothing at `range` as in Karis' "Real
+// Shading in Unreal Engine 4", and kept finite right at the light
+fn distance_falloff(distance: f32, range: f32) -> f32 {
+    let ratio = distance / range;
+    let window = clamp(1.0 - ratio * ratio * ratio * ratio, 0.0, 1.0);
+    return window * window / max(distance * distance, 1e-4);
+}
+
+// 0 outside the outer cone, 1 inside the inner, smooth in between
+fn cone_falloff(light: GpuLight, direction: vec3<f32>) -> f32 {
+    let t = clamp(dot(-direction, light.direction) * light.cone_scale + light.cone_offset, 0.0, 1.0);
+    return t * t;
+}
+
+fn sample_light(light: GpuLight, position: vec3<f32>) -> LightSample {
+    var sample: LightSample;
+    if light.kind == LIGHT_DIRECTIONAL {
+        sample.direction = light.direction;
+        sample.radiance = light.color;
+        return sample;
+    }
+    let to_light = light.position - position;
+    let distance = length(to_light);
+    sample.direction = to_light / max(distance, 1e-4);
+    sample.radiance = light.color * distance_falloff(distance, light.range);
+    if light.kind == LIGHT_SPOT {
+        sample.radiance *= cone_falloff(light, sample.direction);
+    }
+    return sample;
+}
+
+// Lambert, on a surface with the normalised `normal`
+fn diffuse_light(light: LightSample, normal: vec3<f32>) -> vec3<f32> {
+    return light.radiance * max(dot(normal, light.direction), 0.0);
+}
@@ -0,0 +1,196 @@
+use std::time::Duration;
+
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+use crate::bounds::Sphere;
+use crate::camera::Projection;
+
+// Bounds covering this much of the viewport's height or more get the full
+// detail mesh, every level after it is for half the size of the one before
+const FULL_DETAIL_SCREEN_SIZE: f32 = 0.5;
+// how far past a level's boundary the size has to go to switch, in levels,
+// so a model sitting on one doesn't flicker between the two
+const HYSTERESIS: f32 = 0.1;
+const CROSS_FADE_TIME: Duration = Duration::from_millis(250);
+
+// How models pick between their meshes' levels of detail
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LodMode {
+    // always the full detail meshes
+    Off,
+    // straight to the level for the model's size on screen
+    Switch,
+    // the old level dithers out as the new one dithers in
+    CrossFade,
+}
+
+impl LodMode {
+    pub fn next(self) -> Self {
+        match self {
+            LodMode::Off => LodMode::Switch,
+            LodMode::Switch => LodMode::CrossFade,
+            LodMode::CrossFade => LodMode::Off,
+        }
+    }
+}
+
+// Fraction of the viewport's height `bounds` covers, more than one from up
+// close and the whole viewport from inside
+pub fn screen_size(bounds: &Sphere, eye: Point3<f32>, projection: &Projection) -> f32 {
+    let distance = (bounds.center - eye).magnitude();
+    if distance <= bounds.radius {
+        return f32::MAX;
+    }
+    bounds.radius / projection.half_height_at(distance)
+}
+
+// Which level a model's meshes are drawn at, and the one it's fading out
+// of. Meshes with fewer levels use their coarsest.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LodState {
+    level: usize,
+    fading_from: Option<usize>,
+    // how far the fade has got, from zero to one
+    fade: f32,
+}
+
+impl LodState {
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    // Once per frame, for a model covering `screen_size` of the viewport
+    // whose most detailed mesh has `levels` levels
+    pub fn update(&mut self, mode: LodMode, screen_size: f32, levels: usize, dt: Duration) {
+        if self.fading_from.is_some() {
+            self.fade += dt.as_secs_f32() / CROSS_FADE_TIME.as_secs_f32();
+            // one fade at a time
+            if self.fade < 1.0 && mode == LodMode::CrossFade {
+                return;
+            }
+            self.fading_from = None;
+        }
+
+        let level = match mode {
+            LodMode::Off => 0.0,
+            _ => (FULL_DETAIL_SCREEN_SIZE / screen_size.max(f32::MIN_POSITIVE)).log2().max(0.0),
+        };
+        let current = self.level as f32;
+        let next = if level >= current + 1.0 + HYSTERESIS || level < current - HYSTERESIS {
+            (level.floor() as usize).min(levels.saturating_sub(1))
+        } else {
+            self.level
+        };
+        if next != self.level {
+            if mode == LodMode::CrossFade {
+                self.fading_from = Some(self.level);
+                self.fade = 0.0;
+            }
+            self.level = next;
+        }
+    }
+
+    // The levels of a mesh with `levels` of them to draw, each with the fade
+    // for `DrawData::fade`: one drawn whole, or the new and the old level
+    // dithered into each other
+    pub fn draws(&self, levels: usize) -> impl Iterator<Item = (usize, f32)> {
+        let clamp = |level: usize| level.min(levels.saturating_sub(1));
+        let level = clamp(self.level);
+        let from = self.fading_from.map(clamp).filter(|&from| from != level);
+        // zero is drawn whole, so even the start of a fade shows something
+        let fade = if from.is_some() { self.fade.max(1.0 / 256.0) } else { 0.0 };
+        std::iter::once((level, fade)).chain(from.map(|from| (from, -fade)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::Shape;
+
+    #[test]
+    fn chain_halves_each_level() {
+        let mut mesh = Shape::uv_sphere(32, 16).to_mesh_data("sphere");
+        let full = mesh.indices.len();
+        mesh.generate_lods();
+        assert!(!mesh.lods.is_empty());
+        let mut previous = full;
+        for level in &mesh.lods {
+            assert_eq!(level.len(), mesh.submeshes.len());
+            let count: usize = level.iter().map(|submesh| submesh.indices.len()).sum();
+            // about half, and never less than a tenth fewer
+            assert!(count * 10 <= previous * 9, "{} indices after {}", count, previous);
+            assert!(count * 3 >= previous, "{} indices after {}", count, previous);
+            for submesh in level {
+                let indices = &mesh.indices[submesh.indices.start as usize..submesh.indices.end as usize];
+                assert!(indices.iter().all(|&i| (i as usize) < mesh.vertices.len()));
+            }
+            previous = count;
+        }
+        // the levels index the vertices that were there
+        assert_eq!(mesh.vertices.len(), Shape::uv_sphere(32, 16).positions.len());
+
+        // levels that are there already are left alone
+        let lods = mesh.lods.len();
+        mesh.generate_lods();
+        assert_eq!(mesh.lods.len(), lods);
+    }
+
+    #[test]
+    fn small_meshes_get_no_chain() {
+        let mut mesh = Shape::cube(1).to_mesh_data("cube");
+        mesh.generate_lods();
+        assert!(mesh.lods.is_empty());
+    }
+
+    #[test]
+    fn levels_follow_screen_size() {
+        let frame = Duration::from_millis(16);
+        let mut state = LodState::default();
+        state.update(LodMode::Switch, 1.0, 4, frame);
+        assert_eq!(state.level(), 0);
+        // a quarter of the full detail size is two levels down
+        state.update(LodMode::Switch, FULL_DETAIL_SCREEN_SIZE / 4.0, 4, frame);
+        assert_eq!(state.level(), 2);
+        // the coarsest there is, however small
+        state.update(LodMode::Switch, 1e-6, 4, frame);
+        assert_eq!(state.level(), 3);
+        state.update(LodMode::Off, 1e-6, 4, frame);
+        assert_eq!(state.level(), 0);
+        assert_eq!(state.draws(4).collect::<Vec<_>>(), [(0, 0.0)]);
+    }
+
+    #[test]
+    fn hysteresis_holds_a_level() {
+        let frame = Duration::from_millis(16);
+        let mut state = LodState::default();
+        // just past the boundary to level 1 isn't enough
+        let size = |level: f32| FULL_DETAIL_SCREEN_SIZE / level.exp2();
+        state.update(LodMode::Switch, size(1.0 + HYSTERESIS / 2.0), 4, frame);
+        assert_eq!(state.level(), 0);
+        state.update(LodMode::Switch, size(1.0 + HYSTERESIS * 2.0), 4, frame);
+        assert_eq!(state.level(), 1);
+        // and just back over it doesn't go back
+        state.update(LodMode::Switch, size(1.0 - HYSTERESIS / 2.0), 4, frame);
+        assert_eq!(state.level(), 1);
+        state.update(LodMode::Switch, size(1.0 - HYSTERESIS * 2.0), 4, frame);
+        assert_eq!(state.level(), 0);
+    }
+
+    #[test]
+    fn cross_fade_draws_both_levels() {
+        let mut state = LodState::default();
+        state.update(LodMode::CrossFade, FULL_DETAIL_SCREEN_SIZE / 2.5, 4, Duration::ZERO);
+        assert_eq!(state.level(), 1);
+        let draws: Vec<_> = state.draws(4).collect();
+        assert_eq!(draws.len(), 2);
+        assert_eq!((draws[0].0, draws[1].0), (1, 0));
+        assert_eq!(draws[0].1, -draws[1].1);
+        // a mesh with one level has nothing to fade between
+        assert_eq!(state.draws(1).count(), 1);
+
+        state.update(LodMode::CrossFade, FULL_DETAIL_SCREEN_SIZE / 2.5, 4, CROSS_FADE_TIME);
+        assert_eq!(state.draws(4).collect::<Vec<_>>(), [(1, 0.0)]);
+    }
+}
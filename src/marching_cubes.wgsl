@@ -0,0 +1,154 @@
+// Marching cubes over a voxel field: one invocation per cell, which looks
+// its corners' case up in `triangles` and appends that many triangles to
+// `vertices`, counting them in the indirect draw's vertex count. The
+// vertices are `ModelVertex`es in world space, as words, see
+// skinning.wgsl for the layout.
+
+const VERTEX_WORDS: u32 = 19u;
+// triangles' edges per case, three a triangle and -1 after the last, see
+// `triangle_table` in voxels.rs
+const TABLE_STRIDE: u32 = 16u;
+
+struct Params {
+    // where sample 0, 0, 0 is, and how far apart the samples are
+    origin: vec3<f32>,
+    cell_size: f32,
+    cells: vec3<u32>,
+    // what `vertices` holds, a multiple of three
+    max_vertices: u32,
+    color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+};
+
+struct DrawArgs {
+    vertex_count: atomic<u32>,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: Params;
+// (cells + 1) samples along each axis, x first then y then z; solid above
+// zero
+@group(0) @binding(1)
+var<storage, read> density: array<f32>;
+@group(0) @binding(2)
+var<storage, read> triangles: array<i32>;
+@group(0) @binding(3)
+var<storage, read_write> args: DrawArgs;
+@group(0) @binding(4)
+var<storage, read_write> vertices: array<u32>;
+
+// The corners of a cell by their x, y and z bits, and its edges by the
+// corners they join, the lower one first, as voxels.rs numbers them
+const EDGE_START = array<u32, 12>(0u, 0u, 0u, 1u, 1u, 2u, 2u, 3u, 4u, 4u, 5u, 6u);
+const EDGE_END = array<u32, 12>(1u, 2u, 4u, 3u, 5u, 3u, 6u, 7u, 5u, 6u, 7u, 7u);
+
+fn edge_corners(edge: u32) -> vec2<u32> {
+    // copied into variables, as not every backend indexes constants
+    var starts = EDGE_START;
+    var ends = EDGE_END;
+    return vec2<u32>(starts[edge], ends[edge]);
+}
+
+fn corner_offset(corner: u32) -> vec3<u32> {
+    return vec3<u32>(corner & 1u, (corner >> 1u) & 1u, (corner >> 2u) & 1u);
+}
+
+// Samples on the field's faces are left empty, so the surface is closed
+// where it runs into them
+fn sample(p: vec3<i32>) -> f32 {
+    let samples = vec3<i32>(params.cells) + 1;
+    let q = clamp(p, vec3<i32>(0), samples - 1);
+    let value = density[(q.z * samples.y + q.y) * samples.x + q.x];
+    if any(q == vec3<i32>(0)) || any(q == samples - 1) {
+        return min(value, -0.5 * params.cell_size);
+    }
+    return value;
+}
+
+// Towards where it's more solid, by central differences
+fn gradient(p: vec3<i32>) -> vec3<f32> {
+    return vec3<f32>(
+        sample(p + vec3<i32>(1, 0, 0)) - sample(p - vec3<i32>(1, 0, 0)),
+        sample(p + vec3<i32>(0, 1, 0)) - sample(p - vec3<i32>(0, 1, 0)),
+        sample(p + vec3<i32>(0, 0, 1)) - sample(p - vec3<i32>(0, 0, 1)),
+    );
+}
+
+fn store_vec3(at: u32, value: vec3<f32>) {
+    vertices[at] = bitcast<u32>(value.x);
+    vertices[at + 1u] = bitcast<u32>(value.y);
+    vertices[at + 2u] = bitcast<u32>(value.z);
+}
+
+// Where the surface crosses `edge` of the cell at `cell`. Worked out from
+// the edge's lower corner in both cells that share it, so they agree to
+// the bit and the mesh has no cracks.
+fn store_vertex(index: u32, cell: vec3<u32>, edge: u32) {
+    let corners = edge_corners(edge);
+    let a = vec3<i32>(cell + corner_offset(corners.x));
+    let b = vec3<i32>(cell + corner_offset(corners.y));
+    let da = sample(a);
+    let db = sample(b);
+    let t = clamp(da / (da - db), 0.0, 1.0);
+    let position = params.origin + mix(vec3<f32>(a), vec3<f32>(b), t) * params.cell_size;
+    let toward_solid = mix(gradient(a), gradient(b), t);
+    var normal = vec3<f32>(0.0, 1.0, 0.0);
+    if length(toward_solid) > 1e-6 {
+        normal = -normalize(toward_solid);
+    }
+
+    let base = index * VERTEX_WORDS;
+    store_vec3(base, position);
+    store_vec3(base + 3u, normal);
+    vertices[base + 6u] = 0u;
+    vertices[base + 7u] = 0u;
+    store_vec3(base + 8u, params.color);
+    // every joint 0, the draw's identity palette, with all the weight
+    vertices[base + 11u] = 0u;
+    vertices[base + 12u] = 0u;
+    vertices[base + 13u] = bitcast<u32>(1.0);
+    vertices[base + 14u] = 0u;
+    vertices[base + 15u] = 0u;
+    vertices[base + 16u] = 0u;
+    vertices[base + 17u] = bitcast<u32>(params.metallic);
+    vertices[base + 18u] = bitcast<u32>(params.roughness);
+}
+
+@compute @workgroup_size(4, 4, 4)
+fn cs_march(@builtin(global_invocation_id) cell: vec3<u32>) {
+    if any(cell >= params.cells) {
+        return;
+    }
+    var cube = 0u;
+    for (var corner = 0u; corner < 8u; corner++) {
+        if sample(vec3<i32>(cell + corner_offset(corner))) > 0.0 {
+            cube |= 1u << corner;
+        }
+    }
+    let row = cube * TABLE_STRIDE;
+    var count = 0u;
+    while count < TABLE_STRIDE && triangles[row + count] >= 0 {
+        count += 3u;
+    }
+    if count == 0u {
+        return;
+    }
+    let first = atomicAdd(&args.vertex_count, count);
+    // past the end the triangles are dropped, and cs_finish takes them out
+    // of the count
+    for (var i = 0u; i < count && first + i + 3u <= params.max_vertices; i += 3u) {
+        store_vertex(first + i, cell, u32(triangles[row + i]));
+        store_vertex(first + i + 1u, cell, u32(triangles[row + i + 1u]));
+        store_vertex(first + i + 2u, cell, u32(triangles[row + i + 2u]));
+    }
+}
+
+// After cs_march, a single invocation
+@compute @workgroup_size(1)
+fn cs_finish() {
+    atomicStore(&args.vertex_count, min(atomicLoad(&args.vertex_count), params.max_vertices));
+}
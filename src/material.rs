@@ -0,0 +1,320 @@
+use std::num::NonZeroU32;
+use std::sync::Arc;
+
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::ShaderDefs;
+use crate::texture::{self, MipmapGenerator, SamplerCache, SamplerDesc, Texture};
+
+// Slots in the binding array when partially bound descriptors are available
+pub const MAX_BINDLESS_TEXTURES: u32 = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MaterialMode {
+    // binding_array<texture_2d>, each material keeps its own size
+    Bindless,
+    // layers of one texture_2d_array, resized to a common size
+    TextureArray,
+}
+
+// Features that enable the bindless path, request whatever the adapter has
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features()
+        & (wgpu::Features::TEXTURE_BINDING_ARRAY
+            | wgpu::Features::PARTIALLY_BOUND_BINDING_ARRAY
+            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING)
+}
+
+fn bindless_supported(features: wgpu::Features) -> bool {
+    // the index comes from instance data, which is non-uniform
+    features.contains(
+        wgpu::Features::TEXTURE_BINDING_ARRAY
+            | wgpu::Features::SAMPLED_TEXTURE_AND_STORAGE_BUFFER_ARRAY_NON_UNIFORM_INDEXING,
+    )
+}
+
+// Every material texture behind a single bind group, so draws pick their
+// texture by index instead of switching bind groups per material
+pub struct MaterialTextures {
+    mode: MaterialMode,
+    layout: wgpu::BindGroupLayout,
+    // what `layout` was made from, to check shaders against
+    layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    bind_group: wgpu::BindGroup,
+    count: u32,
+    // one per material when bindless, a single array texture otherwise
+    textures: Vec<Texture>,
+    // every material samples through the same sampler
+    sampler: Arc<wgpu::Sampler>,
+    sampler_desc: SamplerDesc,
+}
+
+impl MaterialTextures {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        images: &[image::RgbaImage],
+        samplers: &mut SamplerCache,
+        sampler_desc: SamplerDesc,
+    ) -> Self {
+        assert!(!images.is_empty(), "need at least one material texture");
+        let features = device.features();
+        let mode = if bindless_supported(features) && images.len() as u32 <= MAX_BINDLESS_TEXTURES {
+            MaterialMode::Bindless
+        } else {
+            MaterialMode::TextureArray
+        };
+
+        // one generator for every texture so its pipeline is only built once
+        let mut mipmaps = MipmapGenerator::new(device);
+        let (textures, layout_entries) = match mode {
+            MaterialMode::Bindless => Self::bindless_textures(device, queue, &mut mipmaps, images, features),
+            MaterialMode::TextureArray => Self::array_texture(device, queue, &mut mipmaps, images),
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(match mode {
+                MaterialMode::Bindless => "Bindless Material Bind Group Layout",
+                MaterialMode::TextureArray => "Material Array Bind Group Layout",
+            }),
+            entries: &layout_entries,
+        });
+        let sampler = samplers.get(device, &sampler_desc);
+        let bind_group = create_bind_group(device, mode, &layout, &textures, &sampler);
+
+        Self {
+            mode,
+            layout,
+            layout_entries,
+            bind_group,
+            count: images.len() as u32,
+            textures,
+            sampler,
+            sampler_desc,
+        }
+    }
+
+    fn bindless_textures(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        images: &[image::RgbaImage],
+        features: wgpu::Features,
+    ) -> (Vec<Texture>, Vec<wgpu::BindGroupLayoutEntry>) {
+        let textures: Vec<Texture> = images
+            .iter()
+            .enumerate()
+            .map(|(i, img)| {
+                let label = format!("Material Texture {}", i);
+                Texture::from_rgba_with_mipmaps(device, queue, mipmaps, img, img.width(), img.height(), Some(&label))
+            })
+            .collect();
+
+        // a large, partially bound array lets materials be added without
+        // new layouts; otherwise every slot has to be filled
+        let partially_bound = features.contains(wgpu::Features::PARTIALLY_BOUND_BINDING_ARRAY);
+        let slots = if partially_bound {
+            MAX_BINDLESS_TEXTURES
+        } else {
+            textures.len() as u32
+        };
+
+        let entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: NonZeroU32::new(slots),
+            },
+            sampler_entry(),
+        ];
+        (textures, entries)
+    }
+
+    fn array_texture(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        images: &[image::RgbaImage],
+    ) -> (Vec<Texture>, Vec<wgpu::BindGroupLayoutEntry>) {
+        // layers must share a size, so scale everything up to the largest one
+        let width = images.iter().map(|img| img.width()).max().unwrap_or(1);
+        let height = images.iter().map(|img| img.height()).max().unwrap_or(1);
+        let layers = images.len() as u32;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: layers,
+        };
+        let mip_level_count = texture::mip_level_count(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Material Texture Array"),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        for (layer, img) in images.iter().enumerate() {
+            let resized;
+            let pixels = if img.dimensions() == (width, height) {
+                img
+            } else {
+                resized = image::imageops::resize(img, width, height, image::imageops::FilterType::Triangle);
+                &resized
+            };
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                pixels,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(4 * width),
+                    rows_per_image: Some(height),
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        encoder.push_debug_group("Mipmaps for Material Texture Array");
+        mipmaps.generate(device, &mut encoder, &texture);
+        encoder.pop_debug_group();
+        queue.submit(Some(encoder.finish()));
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("Material Texture Array View"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        // the material bind group brings its own sampler
+        let sampler_desc = SamplerDesc::default();
+        let sampler = Arc::new(sampler_desc.create(device, Some("Material Array Sampler")));
+
+        let entries = vec![
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2Array,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            sampler_entry(),
+        ];
+        let texture = Texture {
+            texture: Tracked::texture(ResourceKind::Texture, texture),
+            view,
+            sampler,
+            sampler_desc,
+        };
+        (vec![texture], entries)
+    }
+
+    pub fn mode(&self) -> MaterialMode {
+        self.mode
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn layout_entries(&self) -> &[wgpu::BindGroupLayoutEntry] {
+        &self.layout_entries
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn sampler_desc(&self) -> SamplerDesc {
+        self.sampler_desc
+    }
+
+    // Rebuilds the bind group, so any draws recorded earlier keep the old sampler
+    pub fn set_sampler(&mut self, device: &wgpu::Device, samplers: &mut SamplerCache, desc: SamplerDesc) {
+        self.sampler = samplers.get(device, &desc);
+        self.sampler_desc = desc;
+        self.bind_group = create_bind_group(device, self.mode, &self.layout, &self.textures, &self.sampler);
+    }
+
+    // Defines for shaders that `#include "material.wgsl"`, which declares
+    // the bindings and `sample_material(index, uv)` for the active mode
+    pub fn shader_defs(&self) -> ShaderDefs {
+        match self.mode {
+            MaterialMode::Bindless => ShaderDefs::new().with("MATERIAL_BINDLESS"),
+            MaterialMode::TextureArray => ShaderDefs::new(),
+        }
+    }
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    mode: MaterialMode,
+    layout: &wgpu::BindGroupLayout,
+    textures: &[Texture],
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    let views: Vec<&wgpu::TextureView> = textures.iter().map(|t| &t.view).collect();
+    let (label, textures) = match mode {
+        MaterialMode::Bindless => (
+            "Bindless Material Bind Group",
+            wgpu::BindingResource::TextureViewArray(&views),
+        ),
+        MaterialMode::TextureArray => (
+            "Material Array Bind Group",
+            wgpu::BindingResource::TextureView(views[0]),
+        ),
+    };
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(label),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: textures,
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
+
+fn sampler_entry() -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
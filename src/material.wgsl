@@ -0,0 +1,21 @@
+// Material textures indexed per instance, as layers of one 2D array texture
+// or, with MATERIAL_BINDLESS, as a binding array. See
+// `MaterialTextures::shader_defs`.
+
+#ifdef MATERIAL_BINDLESS
+@group(1) @binding(0)
+var material_textures: binding_array<texture_2d<f32>>;
+#else
+@group(1) @binding(0)
+var material_textures: texture_2d_array<f32>;
+#endif
+@group(1) @binding(1)
+var material_sampler: sampler;
+
+fn sample_material(index: u32, uv: vec2<f32>) -> vec4<f32> {
+#ifdef MATERIAL_BINDLESS
+    return textureSample(material_textures[index], material_sampler, uv);
+#else
+    return textureSample(material_textures, material_sampler, uv, index);
+#endif
+}
@@ -0,0 +1,370 @@
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+
+use anyhow::*;
+use bytemuck::{Pod, Zeroable};
+use cgmath::*;
+
+use crate::assets;
+use crate::bounds::Aabb;
+use crate::model::{self, Mesh, MeshData, Model, ModelVertex, MorphDelta, Skin, Submesh};
+
+// At the start of every file, followed by the byte order and the version of
+// the layout below
+const MAGIC: [u8; 8] = *b"LWGPUMSH";
+// written in the writer's byte order, so it reads back swapped on a machine
+// of the other one
+const BYTE_ORDER: u32 = 0x0102_0304;
+const VERSION: u32 = 2;
+// a submesh's material when it has none
+const NO_MATERIAL: u32 = u32::MAX;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct FileHeader {
+    magic: [u8; 8],
+    byte_order: u32,
+    version: u32,
+    mesh_count: u32,
+    // how many materials the submeshes can refer to, numbered from 0
+    material_count: u32,
+}
+
+// Before each mesh's sections, which follow it in this order, each padded to
+// four bytes: its name, its morph target names one per line, the vertices,
+// the indices, the submeshes of every level, how many of them each level has
+// and the morph deltas. Everything is in the byte order of the machine that
+// wrote the file, as it was in memory there, and the file header says which:
+// files from a machine of the other order are refused rather than swapped.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct MeshHeader {
+    vertex_count: u32,
+    index_count: u32,
+    // every level's, the full detail one's first
+    submesh_count: u32,
+    level_count: u32,
+    morph_targets: u32,
+    // `morph_targets` times `vertex_count`, or none
+    morph_delta_count: u32,
+    // how many joints the vertices are skinned to, 0 for a static mesh
+    joints: u32,
+    name_len: u32,
+    target_names_len: u32,
+    // of the vertices, both zero for an empty mesh
+    bounds_min: [f32; 3],
+    bounds_max: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Pod, Zeroable)]
+struct FileSubmesh {
+    start: u32,
+    end: u32,
+    material: u32,
+}
+
+// Converts a glTF or OBJ file to a .mesh file next to it, returning its path
+pub fn convert(path: &Path) -> Result<PathBuf> {
+    let meshes = assets::load_mesh_data(path)?;
+    let out = path.with_extension("mesh");
+    save(&out, &meshes)?;
+    Ok(out)
+}
+
+// Writes `meshes`, each with how many joints it's skinned to, in the form
+// they're drawn in: after each one's header, its interleaved `ModelVertex`
+// stream, index buffer and levels of detail as they are in memory, so loading
+// them is a copy to the GPU without any parsing or simplifying. Skinned and
+// static meshes share the one vertex layout.
+pub fn save(path: &Path, meshes: &[(MeshData, usize)]) -> Result<()> {
+    let material_count = meshes
+        .iter()
+        .flat_map(|(data, _)| data.submeshes.iter().chain(data.lods.iter().flatten()))
+        .filter_map(|submesh| submesh.material)
+        .max()
+        .map_or(0, |material| material + 1);
+    let mut bytes = Vec::new();
+    push(&mut bytes, &[FileHeader {
+        magic: MAGIC,
+        byte_order: BYTE_ORDER,
+        version: VERSION,
+        mesh_count: meshes.len() as u32,
+        material_count: material_count as u32,
+    }]);
+    for (data, joints) in meshes {
+        let levels: Vec<&[Submesh]> =
+            std::iter::once(&data.submeshes).chain(&data.lods).map(Vec::as_slice).collect();
+        let submeshes: Vec<FileSubmesh> = levels
+            .iter()
+            .flat_map(|level| level.iter())
+            .map(|submesh| FileSubmesh {
+                start: submesh.indices.start,
+                end: submesh.indices.end,
+                material: submesh.material.map_or(NO_MATERIAL, |material| material as u32),
+            })
+            .collect();
+        let level_sizes: Vec<u32> = levels.iter().map(|level| level.len() as u32).collect();
+        let name = data.name.as_deref().unwrap_or("");
+        let target_names = data.target_names.join("\n");
+        let bounds = Aabb::from_points(data.vertices.iter().map(|vertex| Point3::from(vertex.position)));
+        let (bounds_min, bounds_max) =
+            bounds.map_or(([0.0; 3], [0.0; 3]), |bounds| (bounds.min.into(), bounds.max.into()));
+
+        push(&mut bytes, &[MeshHeader {
+            vertex_count: data.vertices.len() as u32,
+            index_count: data.indices.len() as u32,
+            submesh_count: submeshes.len() as u32,
+            level_count: levels.len() as u32,
+            morph_targets: data.morph_targets as u32,
+            morph_delta_count: data.morph_deltas.len() as u32,
+            joints: *joints as u32,
+            name_len: name.len() as u32,
+            target_names_len: target_names.len() as u32,
+            bounds_min,
+            bounds_max,
+        }]);
+        push(&mut bytes, name.as_bytes());
+        push(&mut bytes, target_names.as_bytes());
+        push(&mut bytes, &data.vertices);
+        push(&mut bytes, &data.indices);
+        push(&mut bytes, &submeshes);
+        push(&mut bytes, &level_sizes);
+        push(&mut bytes, &data.morph_deltas);
+    }
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+// The meshes in a .mesh file as a model with a root node for each. A skinned
+// mesh gets a skin with all of its joints at its own node, which leaves it in
+// the pose it was bound in until something moves them.
+pub fn load(device: &wgpu::Device, path: &Path) -> Result<Model> {
+    let bytes = FileBytes::open(path)?;
+    let mut reader = Reader { bytes: &bytes, offset: 0 };
+    let meshes =
+        read_meshes(device, &mut reader).with_context(|| format!("{}: not a usable .mesh file", path.display()))?;
+
+    let joints: Vec<usize> = meshes.iter().map(|(_, joints)| *joints).collect();
+    let mut model = Model::from_meshes(meshes.into_iter().map(|(mesh, _)| mesh).collect(), Vec::new());
+    for (node, joints) in joints.into_iter().enumerate().filter(|&(_, joints)| joints > 0) {
+        model.nodes[node].skin = Some(model.skins.len());
+        model.skins.push(Skin {
+            name: model.nodes[node].name.clone(),
+            joints: vec![node; joints],
+            inverse_bind_matrices: vec![Matrix4::identity(); joints],
+        });
+    }
+    Ok(model)
+}
+
+fn read_meshes(device: &wgpu::Device, reader: &mut Reader) -> Result<Vec<(Mesh, usize)>> {
+    let header = read_header(reader)?;
+    (0..header.mesh_count)
+        .map(|index| read_mesh(device, reader, header.material_count).with_context(|| format!("mesh {}", index)))
+        .collect()
+}
+
+fn read_header(reader: &mut Reader) -> Result<FileHeader> {
+    let header = reader.take::<FileHeader>(1)?[0];
+    ensure!(header.magic == MAGIC, "wrong magic number");
+    ensure!(header.byte_order != BYTE_ORDER.swap_bytes(), "written on a machine of the other byte order");
+    ensure!(header.byte_order == BYTE_ORDER, "unknown byte order {:#010x}", header.byte_order);
+    ensure!(header.version == VERSION, "version {}, expected {}", header.version, VERSION);
+    Ok(header)
+}
+
+fn read_mesh(device: &wgpu::Device, reader: &mut Reader, material_count: u32) -> Result<(Mesh, usize)> {
+    let header = reader.take::<MeshHeader>(1)?[0];
+    let name = reader.text(header.name_len)?;
+    let target_names = reader.text(header.target_names_len)?;
+    let vertices = reader.take::<ModelVertex>(header.vertex_count)?;
+    let indices = reader.take::<u32>(header.index_count)?;
+    let submeshes = reader.take::<FileSubmesh>(header.submesh_count)?;
+    let level_sizes = reader.take::<u32>(header.level_count)?;
+    let morph_deltas = reader.take::<MorphDelta>(header.morph_delta_count)?;
+
+    // what the GPU would otherwise read out of bounds
+    ensure!(indices.iter().all(|&index| index < header.vertex_count), "index past the last vertex");
+    ensure!(
+        submeshes.iter().all(|submesh| submesh.start <= submesh.end && submesh.end <= header.index_count),
+        "submesh past the last index"
+    );
+    ensure!(
+        submeshes.iter().all(|submesh| submesh.material == NO_MATERIAL || submesh.material < material_count),
+        "submesh material past the last of {} materials",
+        material_count
+    );
+    ensure!(
+        morph_deltas.is_empty() || morph_deltas.len() == header.morph_targets as usize * vertices.len(),
+        "{} morph deltas for {} targets of {} vertices",
+        morph_deltas.len(),
+        header.morph_targets,
+        vertices.len()
+    );
+    ensure!(level_sizes.first().is_some_and(|&size| size > 0) || submeshes.is_empty(), "no full detail level");
+    ensure!(level_sizes.iter().map(|&size| size as usize).sum::<usize>() == submeshes.len(), "levels don't add up");
+
+    let mut submeshes = submeshes.iter().map(|submesh| Submesh {
+        indices: submesh.start..submesh.end,
+        material: (submesh.material != NO_MATERIAL).then_some(submesh.material as usize),
+    });
+    let mut levels: Vec<Vec<Submesh>> =
+        level_sizes.iter().map(|&size| submeshes.by_ref().take(size as usize).collect()).collect();
+    let full = if levels.is_empty() { Vec::new() } else { levels.remove(0) };
+
+    let label = if name.is_empty() { "Mesh" } else { name };
+    let (vertex_buffer, index_buffer, morph_target_buffer) =
+        model::upload_buffers(device, label, &vertices, &indices, &morph_deltas);
+    let mesh = Mesh {
+        name: (!name.is_empty()).then(|| name.to_owned()),
+        vertex_buffer,
+        index_buffer,
+        num_vertices: header.vertex_count,
+        num_indices: header.index_count,
+        submeshes: full,
+        lods: levels,
+        bounds: (header.vertex_count > 0).then(|| Aabb::new(header.bounds_min.into(), header.bounds_max.into())),
+        morph_targets: header.morph_targets as usize,
+        target_names: target_names.lines().map(str::to_owned).collect(),
+        morph_deltas: morph_target_buffer,
+    };
+    Ok((mesh, header.joints as usize))
+}
+
+// Appends `values` as they are in memory, padded to four bytes so the next
+// section can be read where it lies
+fn push<T: Pod>(bytes: &mut Vec<u8>, values: &[T]) {
+    bytes.extend_from_slice(bytemuck::cast_slice(values));
+    bytes.resize(bytes.len().next_multiple_of(4), 0);
+}
+
+// A .mesh file's contents, mapped where it's a file on disk, so the vertices
+// and indices are copied to the GPU straight out of the page cache
+enum FileBytes {
+    #[cfg(not(target_arch = "wasm32"))]
+    Mapped(memmap2::Mmap),
+    // APK assets, and everything on the web
+    #[cfg(any(target_os = "android", target_arch = "wasm32"))]
+    Read(Vec<u8>),
+}
+
+impl FileBytes {
+    fn open(path: &Path) -> Result<Self> {
+        #[cfg(target_os = "android")]
+        if path.is_relative() {
+            return Ok(FileBytes::Read(assets::read(path)?));
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let file = std::fs::File::open(path).with_context(|| format!("Failed to read {}", path.display()))?;
+            // SAFETY: the map only lives as long as the load, and the file
+            // being truncated under it meanwhile is the one thing that will
+            // break it, as it would any other reader
+            let map =
+                unsafe { memmap2::Mmap::map(&file) }.with_context(|| format!("Failed to map {}", path.display()))?;
+            Ok(FileBytes::Mapped(map))
+        }
+        #[cfg(target_arch = "wasm32")]
+        Ok(FileBytes::Read(assets::read(path)?))
+    }
+}
+
+impl std::ops::Deref for FileBytes {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            FileBytes::Mapped(map) => map,
+            #[cfg(any(target_os = "android", target_arch = "wasm32"))]
+            FileBytes::Read(bytes) => bytes,
+        }
+    }
+}
+
+// Takes the sections in order, each in place unless it isn't aligned for its
+// type, as can happen to a file read into memory rather than mapped
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take<T: Pod>(&mut self, count: u32) -> Result<Cow<'a, [T]>> {
+        let size = std::mem::size_of::<T>();
+        // a count from a bad header can overflow on 32-bit targets
+        let end = (count as usize).checked_mul(size).and_then(|len| self.offset.checked_add(len));
+        let Some(end) = end.filter(|&end| end <= self.bytes.len()) else {
+            let end = self.offset as u128 + count as u128 * size as u128;
+            bail!("ends {} bytes early", end - self.bytes.len() as u128);
+        };
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end.next_multiple_of(4).min(self.bytes.len());
+        Ok(match bytemuck::try_cast_slice(bytes) {
+            Result::Ok(values) => Cow::Borrowed(values),
+            Err(_) => Cow::Owned(bytemuck::pod_collect_to_vec(bytes)),
+        })
+    }
+
+    fn text(&mut self, len: u32) -> Result<&'a str> {
+        let Cow::Borrowed(bytes) = self.take::<u8>(len)? else {
+            unreachable!("bytes are always aligned");
+        };
+        std::str::from_utf8(bytes).context("name isn't UTF-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FileHeader {
+        FileHeader {
+            magic: MAGIC,
+            byte_order: BYTE_ORDER,
+            version: VERSION,
+            mesh_count: 0,
+            material_count: 0,
+        }
+    }
+
+    fn read(header: FileHeader) -> Result<FileHeader> {
+        let mut bytes = Vec::new();
+        push(&mut bytes, &[header]);
+        read_header(&mut Reader { bytes: &bytes, offset: 0 })
+    }
+
+    #[test]
+    fn take_stops_at_the_end() {
+        let bytes = [0u8; 10];
+        let mut reader = Reader { bytes: &bytes, offset: 0 };
+        assert_eq!(reader.take::<u8>(1).unwrap().len(), 1);
+        // the next section starts after the byte's padding
+        assert_eq!(reader.take::<u16>(3).unwrap().len(), 3);
+        assert_eq!(format!("{}", reader.take::<u32>(1).unwrap_err()), "ends 4 bytes early");
+        let mut reader = Reader { bytes: &bytes, offset: 0 };
+        assert_eq!(format!("{}", reader.take::<u32>(4).unwrap_err()), "ends 6 bytes early");
+    }
+
+    #[test]
+    fn take_rejects_counts_that_overflow() {
+        let bytes = [0u8; 16];
+        let mut reader = Reader { bytes: &bytes, offset: 8 };
+        let error = reader.take::<ModelVertex>(u32::MAX).unwrap_err();
+        let wanted = 8 + u32::MAX as u128 * std::mem::size_of::<ModelVertex>() as u128;
+        assert_eq!(format!("{}", error), format!("ends {} bytes early", wanted - 16));
+    }
+
+    #[test]
+    fn header_byte_order_is_checked() {
+        assert!(read(header()).is_ok());
+        let swapped = FileHeader {
+            byte_order: BYTE_ORDER.swap_bytes(),
+            version: VERSION.swap_bytes(),
+            ..header()
+        };
+        assert_eq!(format!("{}", read(swapped).unwrap_err()), "written on a machine of the other byte order");
+        let old = FileHeader { version: 1, ..header() };
+        assert_eq!(format!("{}", read(old).unwrap_err()), "version 1, expected 2");
+    }
+}
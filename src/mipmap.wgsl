@@ -0,0 +1,27 @@
+// Mip chain downsampling: each pass draws one fullscreen triangle into the
+// next level, bilinearly sampling the level above it
+
+#include "fullscreen.wgsl"
+
+@group(0) @binding(0)
+var source: texture_2d<f32>;
+@group(0) @binding(1)
+var source_sampler: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    // a linear sample halfway between four texels is their 2x2 box average
+    return textureSample(source, source_sampler, in.uv);
+}
+
+// Array textures are sampled through a view of every layer, some backends
+// (GL) can't make 2D views of a single layer
+@group(0) @binding(2)
+var source_array: texture_2d_array<f32>;
+@group(0) @binding(3)
+var<uniform> layer: u32;
+
+@fragment
+fn fs_array(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSample(source_array, source_sampler, in.uv, layer);
+}
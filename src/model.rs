@@ -0,0 +1,757 @@
+use std::ops::Range;
+use std::path::Path;
+
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::animation::Animation;
+use crate::assets;
+use crate::bounds::Aabb;
+use crate::compute::StorageBuffer;
+use crate::resources::{ResourceKind, Tracked};
+use crate::simplify;
+use crate::texture::{MipmapGenerator, Texture};
+
+// Levels of detail `MeshData::generate_lods` makes, counting the full one
+const MAX_LOD_LEVELS: usize = 4;
+// meshes smaller than this draw the same at any distance
+const LOD_MIN_TRIANGLES: usize = 64;
+// how far a level may stray from the one before, relative to the mesh's size
+const LOD_MAX_ERROR: f32 = 0.05;
+
+// Vertex format shared by every glTF mesh. Unskinned meshes get joint 0
+// with full weight, so they go through the same pipeline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ModelVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub tex_coords: [f32; 2],
+    // material base colour, baked in per vertex
+    pub color: [f32; 3],
+    pub joints: [u16; 4],
+    pub weights: [f32; 4],
+    // the material's metallic and roughness factors, baked in the same way
+    pub metallic_roughness: [f32; 2],
+}
+
+impl ModelVertex {
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 7] = wgpu::vertex_attr_array![
+            0 => Float32x3,
+            1 => Float32x3,
+            2 => Float32x2,
+            3 => Float32x3,
+            4 => Uint16x4,
+            5 => Float32x4,
+            6 => Float32x2,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ModelVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+/// How far one morph target moves one vertex. vec4s to match the storage
+/// buffer layout in model.wgsl.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct MorphDelta {
+    pub position: [f32; 4],
+    pub normal: [f32; 4],
+}
+
+/// Local transform of a node, relative to its parent.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeTransform {
+    pub translation: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl NodeTransform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translation)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Blend towards `other`, `amount` 0 is `self` and 1 is `other`
+    pub fn lerp(&self, other: &Self, amount: f32) -> Self {
+        Self {
+            translation: self.translation.lerp(other.translation, amount),
+            rotation: self.rotation.slerp(other.rotation, amount),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+}
+
+impl Default for NodeTransform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl From<gltf::scene::Transform> for NodeTransform {
+    fn from(transform: gltf::scene::Transform) -> Self {
+        let (translation, rotation, scale) = transform.decomposed();
+        // glTF stores quaternions as x, y, z, w
+        let [x, y, z, w] = rotation;
+        Self {
+            translation: translation.into(),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: scale.into(),
+        }
+    }
+}
+
+// One entry in the scene graph. Animation writes into `transform`.
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub name: Option<String>,
+    pub transform: NodeTransform,
+    pub parent: Option<usize>,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    pub skin: Option<usize>,
+    // morph target weights for the node's mesh, one per target
+    pub weights: Vec<f32>,
+}
+
+// A run of a mesh's indices drawn with one material
+#[derive(Debug, Clone)]
+pub struct Submesh {
+    pub indices: Range<u32>,
+    // index into `Model::materials`, None draws with the vertex colour only
+    pub material: Option<usize>,
+}
+
+pub struct Mesh {
+    pub name: Option<String>,
+    // also read by skinning.wgsl, as storage
+    pub vertex_buffer: Tracked<wgpu::Buffer>,
+    pub index_buffer: Tracked<wgpu::Buffer>,
+    pub num_vertices: u32,
+    pub num_indices: u32,
+    pub submeshes: Vec<Submesh>,
+    // coarser versions of `submeshes`, each about half the triangles of the
+    // one before, in the same buffers
+    pub lods: Vec<Vec<Submesh>>,
+    // of the vertices as loaded, None for an empty mesh
+    pub bounds: Option<Aabb>,
+    pub morph_targets: usize,
+    // from the `targetNames` extra most exporters write, may be empty
+    pub target_names: Vec<String>,
+    // target-major, `morph_targets` runs of one delta per vertex; a single
+    // zero delta if the mesh has no targets
+    pub morph_deltas: StorageBuffer<MorphDelta>,
+}
+
+impl Mesh {
+    // The full detail one and any coarser ones
+    pub fn levels(&self) -> usize {
+        1 + self.lods.len()
+    }
+
+    // The submeshes of a level of detail, zero being the full one; levels
+    // past the coarsest give the coarsest
+    pub fn lod(&self, level: usize) -> &[Submesh] {
+        match level.min(self.lods.len()) {
+            0 => &self.submeshes,
+            level => &self.lods[level - 1],
+        }
+    }
+}
+
+/// CPU side of a `Mesh`, for building meshes from other formats.
+#[derive(Debug, Clone, Default)]
+pub struct MeshData {
+    pub name: Option<String>,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub submeshes: Vec<Submesh>,
+    // like `Mesh::lods`
+    pub lods: Vec<Vec<Submesh>>,
+    pub morph_targets: usize,
+    pub target_names: Vec<String>,
+    // laid out like `Mesh::morph_deltas`, may be empty without targets
+    pub morph_deltas: Vec<MorphDelta>,
+}
+
+impl MeshData {
+    // Adds coarser levels of detail, each simplified to about half the
+    // triangles of the one before, unless there are authored ones already.
+    // The levels index the same vertices, so skins and morph targets work
+    // on all of them.
+    pub fn generate_lods(&mut self) {
+        if !self.lods.is_empty() || self.indices.len() / 3 < LOD_MIN_TRIANGLES {
+            return;
+        }
+        while self.lods.len() + 1 < MAX_LOD_LEVELS {
+            let previous = self.lods.last().unwrap_or(&self.submeshes).clone();
+            let first_index = self.indices.len();
+            let level: Vec<Submesh> = previous
+                .iter()
+                .map(|submesh| {
+                    let indices = self.indices[submesh.indices.start as usize..submesh.indices.end as usize].to_vec();
+                    let (simplified, _) = simplify::simplify(&self.vertices, &indices, indices.len() / 6 * 3, LOD_MAX_ERROR);
+                    let start = self.indices.len() as u32;
+                    self.indices.extend(simplified);
+                    Submesh {
+                        indices: start..self.indices.len() as u32,
+                        material: submesh.material,
+                    }
+                })
+                .collect();
+            let count = |submeshes: &[Submesh]| submeshes.iter().map(|submesh| submesh.indices.len()).sum::<usize>();
+            // not worth another draw's worth of indices
+            if count(&level) * 10 > count(&previous) * 9 {
+                self.indices.truncate(first_index);
+                break;
+            }
+            self.lods.push(level);
+        }
+    }
+
+    // Appends another mesh as the next level of detail. Morph targets don't
+    // carry over, the level's vertices stay as they are.
+    pub fn push_lod(&mut self, lod: MeshData) {
+        let old_len = self.vertices.len();
+        let new_len = old_len + lod.vertices.len();
+        // target-major, so each target's run grows by the new vertices
+        if self.morph_deltas.len() == self.morph_targets * old_len && self.morph_targets > 0 {
+            let mut deltas = vec![MorphDelta::default(); self.morph_targets * new_len];
+            for (target, run) in self.morph_deltas.chunks_exact(old_len).enumerate() {
+                deltas[target * new_len..target * new_len + old_len].copy_from_slice(run);
+            }
+            self.morph_deltas = deltas;
+        }
+        self.vertices.extend(lod.vertices);
+        let first_index = self.indices.len() as u32;
+        self.indices.extend(lod.indices.iter().map(|&index| index + old_len as u32));
+        self.lods.push(
+            lod.submeshes
+                .into_iter()
+                .map(|submesh| Submesh {
+                    indices: submesh.indices.start + first_index..submesh.indices.end + first_index,
+                    material: submesh.material,
+                })
+                .collect(),
+        );
+    }
+
+    // Moves the vertices and morph targets by `transform`, for baking a
+    // node's placement into its mesh
+    pub fn transform(&mut self, transform: &Matrix4<f32>) {
+        let normal_matrix = transform.invert().unwrap_or(Matrix4::identity()).transpose();
+        let normal = |normal: Vector3<f32>| {
+            let normal = normal_matrix.transform_vector(normal);
+            if normal.magnitude2() > 0.0 { normal.normalize() } else { normal }
+        };
+        for vertex in &mut self.vertices {
+            vertex.position = transform.transform_point(vertex.position.into()).into();
+            vertex.normal = normal(vertex.normal.into()).into();
+        }
+        for delta in &mut self.morph_deltas {
+            let [x, y, z, _] = delta.position;
+            delta.position = transform.transform_vector(Vector3::new(x, y, z)).extend(0.0).into();
+            let [x, y, z, _] = delta.normal;
+            delta.normal = normal_matrix.transform_vector(Vector3::new(x, y, z)).extend(0.0).into();
+        }
+    }
+
+    pub fn upload(self, device: &wgpu::Device) -> Mesh {
+        let label = self.name.as_deref().unwrap_or("Mesh");
+        let (vertex_buffer, index_buffer, morph_deltas) =
+            upload_buffers(device, label, &self.vertices, &self.indices, &self.morph_deltas);
+        Mesh {
+            vertex_buffer,
+            index_buffer,
+            num_vertices: self.vertices.len() as u32,
+            num_indices: self.indices.len() as u32,
+            submeshes: self.submeshes,
+            lods: self.lods,
+            bounds: Aabb::from_points(self.vertices.iter().map(|v| Point3::from(v.position))),
+            morph_targets: self.morph_targets,
+            target_names: self.target_names,
+            morph_deltas,
+            name: self.name,
+        }
+    }
+}
+
+// A mesh's vertex and index buffers and its morph target storage, which
+// gets a single zero delta when there are none
+pub(crate) fn upload_buffers(
+    device: &wgpu::Device,
+    label: &str,
+    vertices: &[ModelVertex],
+    indices: &[u32],
+    morph_deltas: &[MorphDelta],
+) -> (Tracked<wgpu::Buffer>, Tracked<wgpu::Buffer>, StorageBuffer<MorphDelta>) {
+    let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Vertex Buffer", label)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::STORAGE,
+    });
+    let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{} Index Buffer", label)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX,
+    });
+    let morph_deltas = if morph_deltas.is_empty() { &[MorphDelta::default()] } else { morph_deltas };
+    (
+        Tracked::buffer(ResourceKind::Mesh, vertex_buffer),
+        Tracked::buffer(ResourceKind::Mesh, index_buffer),
+        StorageBuffer::from_slice(device, &format!("{} Morph Target Buffer", label), morph_deltas),
+    )
+}
+
+pub struct Material {
+    pub name: Option<String>,
+    // sRGB, sampled with texture coordinate set 0 and multiplied with the
+    // vertex colour
+    pub base_color_texture: Option<Texture>,
+}
+
+/// Joints are node indices. Joint `i` deforms vertices through
+/// `global(joints[i]) * inverse_bind_matrices[i]`.
+#[derive(Debug, Clone)]
+pub struct Skin {
+    pub name: Option<String>,
+    pub joints: Vec<usize>,
+    pub inverse_bind_matrices: Vec<Matrix4<f32>>,
+}
+
+impl Skin {
+    // Joint matrices for the current pose, `globals` comes from
+    // `Model::global_transforms`
+    pub fn joint_matrices(&self, globals: &[Matrix4<f32>]) -> Vec<Matrix4<f32>> {
+        self.joints
+            .iter()
+            .zip(&self.inverse_bind_matrices)
+            .map(|(&joint, inverse_bind)| globals[joint] * inverse_bind)
+            .collect()
+    }
+}
+
+// A glTF scene: the node hierarchy plus the meshes and skins it refers to.
+// Only triangle primitives are loaded, every primitive of a mesh ends up in
+// one vertex and index buffer.
+pub struct Model {
+    pub nodes: Vec<Node>,
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<Material>,
+    pub skins: Vec<Skin>,
+    pub animations: Vec<Animation>,
+    // nodes without a parent in the default scene
+    pub roots: Vec<usize>,
+}
+
+impl Model {
+    // Loads .gltf (with external or embedded buffers) and .glb files
+    pub fn load(device: &wgpu::Device, queue: &wgpu::Queue, path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let (document, buffers, images) = assets::import_gltf(path)?;
+        Self::from_gltf(device, queue, &document, &buffers, &images)
+    }
+
+    // Unanimated model with one root node per mesh
+    pub fn from_meshes(meshes: Vec<Mesh>, materials: Vec<Material>) -> Self {
+        let nodes = meshes
+            .iter()
+            .enumerate()
+            .map(|(index, mesh)| Node {
+                name: mesh.name.clone(),
+                transform: NodeTransform::default(),
+                parent: None,
+                children: Vec::new(),
+                mesh: Some(index),
+                skin: None,
+                weights: vec![0.0; mesh.morph_targets],
+            })
+            .collect();
+        Self {
+            nodes,
+            roots: (0..meshes.len()).collect(),
+            meshes,
+            materials,
+            skins: Vec::new(),
+            animations: Vec::new(),
+        }
+    }
+
+    pub fn from_gltf(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        document: &gltf::Document,
+        buffers: &[gltf::buffer::Data],
+        images: &[gltf::image::Data],
+    ) -> Result<Self> {
+        let (mut nodes, roots) = load_nodes(document);
+        let mut meshes = document.meshes().map(|mesh| load_mesh(&mesh, buffers)).collect::<Result<Vec<_>>>()?;
+        merge_authored_lods(&mut nodes, &mut meshes);
+        let meshes: Vec<Mesh> = meshes
+            .into_iter()
+            .map(|mut data| {
+                data.generate_lods();
+                data.upload(device)
+            })
+            .collect();
+
+        let mut mipmaps = MipmapGenerator::new(device);
+        let materials = document
+            .materials()
+            .map(|material| {
+                let info = material.pbr_metallic_roughness().base_color_texture();
+                if info.as_ref().is_some_and(|info| info.tex_coord() != 0) {
+                    log::warn!("Material {:?} samples texture coordinate set 1, ignoring its texture", material.name());
+                }
+                let image = info
+                    .filter(|info| info.tex_coord() == 0)
+                    .and_then(|info| images.get(info.texture().source().index()));
+                Material {
+                    name: material.name().map(str::to_owned),
+                    base_color_texture: image.and_then(|image| {
+                        let label = material.name().unwrap_or("Base Color Texture");
+                        load_image(device, queue, &mut mipmaps, image, label)
+                    }),
+                }
+            })
+            .collect();
+        for node in &mut nodes {
+            if let Some(mesh) = node.mesh {
+                node.weights.resize(meshes[mesh].morph_targets, 0.0);
+            }
+        }
+
+        let skins = document
+            .skins()
+            .map(|skin| {
+                let joints: Vec<usize> = skin.joints().map(|joint| joint.index()).collect();
+                let reader = skin.reader(|buffer| Some(&buffers[buffer.index()]));
+                // missing inverse bind matrices mean identity
+                let inverse_bind_matrices = match reader.read_inverse_bind_matrices() {
+                    Some(matrices) => matrices.map(Matrix4::from).collect(),
+                    None => vec![Matrix4::identity(); joints.len()],
+                };
+                if inverse_bind_matrices.len() != joints.len() {
+                    bail!("Skin {} has {} joints but {} inverse bind matrices", skin.index(), joints.len(), inverse_bind_matrices.len());
+                }
+                Ok(Skin {
+                    name: skin.name().map(str::to_owned),
+                    joints,
+                    inverse_bind_matrices,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let animations = document
+            .animations()
+            .map(|animation| Animation::from_gltf(&animation, buffers))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            nodes,
+            meshes,
+            materials,
+            skins,
+            animations,
+            roots,
+        })
+    }
+
+    pub fn find_node(&self, name: &str) -> Option<usize> {
+        self.nodes.iter().position(|node| node.name.as_deref() == Some(name))
+    }
+
+    pub fn find_animation(&self, name: &str) -> Option<usize> {
+        self.animations.iter().position(|animation| animation.name.as_deref() == Some(name))
+    }
+
+    // Sets a morph target weight by the target's name, false if the node
+    // has no mesh or its mesh no such target
+    pub fn set_morph_weight(&mut self, node: usize, target: &str, weight: f32) -> bool {
+        let Some(mesh) = self.nodes[node].mesh else {
+            return false;
+        };
+        let Some(index) = self.meshes[mesh].target_names.iter().position(|name| name == target) else {
+            return false;
+        };
+        self.nodes[node].weights[index] = weight;
+        true
+    }
+
+    // Model space bounds of the meshes in their rest pose
+    pub fn bounds(&self) -> Option<Aabb> {
+        let globals = self.global_transforms();
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, node)| {
+                let bounds = self.meshes[node.mesh?].bounds?;
+                // skinned vertices are placed by their joints, which start
+                // out undoing the bind pose
+                Some(if node.skin.is_some() { bounds } else { bounds.transformed(&globals[index]) })
+            })
+            .reduce(Aabb::union)
+    }
+
+    // Model space transform of every node, indexed like `nodes`
+    pub fn global_transforms(&self) -> Vec<Matrix4<f32>> {
+        let mut globals = vec![Matrix4::identity(); self.nodes.len()];
+        let mut stack: Vec<(usize, Matrix4<f32>)> = self.roots.iter().map(|&root| (root, Matrix4::identity())).collect();
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let global = parent * node.transform.matrix();
+            globals[index] = global;
+            stack.extend(node.children.iter().map(|&child| (child, global)));
+        }
+        globals
+    }
+}
+
+// The meshes of a glTF scene where its nodes place them in their rest pose,
+// for formats without a node hierarchy: one for each node with a mesh, with
+// the node's transform baked in, authored levels of detail merged and coarser
+// ones generated. Skinned meshes are left as they're bound, and come with how
+// many joints their skin has, 0 for the rest.
+pub fn gltf_mesh_data(document: &gltf::Document, buffers: &[gltf::buffer::Data]) -> Result<Vec<(MeshData, usize)>> {
+    let (mut nodes, roots) = load_nodes(document);
+    let mut meshes = document.meshes().map(|mesh| load_mesh(&mesh, buffers)).collect::<Result<Vec<_>>>()?;
+    merge_authored_lods(&mut nodes, &mut meshes);
+    meshes.iter_mut().for_each(MeshData::generate_lods);
+    let joints: Vec<usize> = document.skins().map(|skin| skin.joints().count()).collect();
+
+    let mut placed = Vec::new();
+    let mut stack: Vec<(usize, Matrix4<f32>)> = roots.iter().map(|&root| (root, Matrix4::identity())).collect();
+    while let Some((index, parent)) = stack.pop() {
+        let node = &nodes[index];
+        let global = parent * node.transform.matrix();
+        stack.extend(node.children.iter().map(|&child| (child, global)));
+        let Some(mesh) = node.mesh else {
+            continue;
+        };
+        let mut data = meshes[mesh].clone();
+        data.name = node.name.clone().or(data.name);
+        let joints = match node.skin {
+            Some(skin) => joints[skin],
+            None => {
+                data.transform(&global);
+                0
+            }
+        };
+        placed.push((data, joints));
+    }
+    Ok(placed)
+}
+
+// Every node, with its parent filled in, and the ones the default scene
+// starts from
+fn load_nodes(document: &gltf::Document) -> (Vec<Node>, Vec<usize>) {
+    let mut nodes: Vec<Node> = document
+        .nodes()
+        .map(|node| Node {
+            name: node.name().map(str::to_owned),
+            transform: node.transform().into(),
+            parent: None,
+            children: node.children().map(|child| child.index()).collect(),
+            mesh: node.mesh().map(|mesh| mesh.index()),
+            skin: node.skin().map(|skin| skin.index()),
+            // a node's own weights override its mesh's defaults
+            weights: node
+                .weights()
+                .or_else(|| node.mesh().and_then(|mesh| mesh.weights()))
+                .map(<[f32]>::to_vec)
+                .unwrap_or_default(),
+        })
+        .collect();
+    for parent in 0..nodes.len() {
+        for child in nodes[parent].children.clone() {
+            nodes[child].parent = Some(parent);
+        }
+    }
+
+    let roots = match document.default_scene().or_else(|| document.scenes().next()) {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => (0..nodes.len()).filter(|&i| nodes[i].parent.is_none()).collect(),
+    };
+    (nodes, roots)
+}
+
+fn load_mesh(mesh: &gltf::Mesh, buffers: &[gltf::buffer::Data]) -> Result<MeshData> {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut submeshes = Vec::new();
+    // first vertex of each primitive and its deltas, one list per target
+    let mut primitive_targets: Vec<(usize, Vec<Vec<MorphDelta>>)> = Vec::new();
+
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            log::warn!("Skipping non-triangle primitive in mesh {}", mesh.index());
+            continue;
+        }
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let Some(positions) = reader.read_positions() else {
+            bail!("Mesh {} has a primitive without positions", mesh.index());
+        };
+
+        let base = vertices.len() as u32;
+        let pbr = primitive.material().pbr_metallic_roughness();
+        let color = pbr.base_color_factor();
+        vertices.extend(positions.map(|position| ModelVertex {
+            position,
+            normal: [0.0, 1.0, 0.0],
+            tex_coords: [0.0; 2],
+            color: [color[0], color[1], color[2]],
+            joints: [0; 4],
+            weights: [1.0, 0.0, 0.0, 0.0],
+            metallic_roughness: [pbr.metallic_factor(), pbr.roughness_factor()],
+        }));
+        let added = &mut vertices[base as usize..];
+
+        if let Some(normals) = reader.read_normals() {
+            added.iter_mut().zip(normals).for_each(|(v, n)| v.normal = n);
+        }
+        if let Some(tex_coords) = reader.read_tex_coords(0) {
+            added.iter_mut().zip(tex_coords.into_f32()).for_each(|(v, t)| v.tex_coords = t);
+        }
+        if let (Some(joints), Some(weights)) = (reader.read_joints(0), reader.read_weights(0)) {
+            for (v, (j, w)) in added.iter_mut().zip(joints.into_u16().zip(weights.into_f32())) {
+                v.joints = j;
+                v.weights = w;
+            }
+        }
+
+        let targets = reader
+            .read_morph_targets()
+            .map(|(positions, normals, _tangents)| {
+                let mut deltas = vec![MorphDelta::default(); added.len()];
+                if let Some(positions) = positions {
+                    deltas.iter_mut().zip(positions).for_each(|(d, [x, y, z])| d.position = [x, y, z, 0.0]);
+                }
+                if let Some(normals) = normals {
+                    deltas.iter_mut().zip(normals).for_each(|(d, [x, y, z])| d.normal = [x, y, z, 0.0]);
+                }
+                deltas
+            })
+            .collect();
+        primitive_targets.push((base as usize, targets));
+
+        let first_index = indices.len() as u32;
+        match reader.read_indices() {
+            Some(read) => indices.extend(read.into_u32().map(|i| base + i)),
+            None => indices.extend(base..vertices.len() as u32),
+        }
+        submeshes.push(Submesh {
+            indices: first_index..indices.len() as u32,
+            material: primitive.material().index(),
+        });
+    }
+
+    // primitives with fewer targets than the mesh leave their vertices at
+    // zero in the extra ones
+    let morph_targets = primitive_targets.iter().map(|(_, targets)| targets.len()).max().unwrap_or(0);
+    let mut morph_deltas = vec![MorphDelta::default(); morph_targets * vertices.len()];
+    for (base, targets) in &primitive_targets {
+        for (target, deltas) in targets.iter().enumerate() {
+            let start = target * vertices.len() + base;
+            morph_deltas[start..start + deltas.len()].copy_from_slice(deltas);
+        }
+    }
+
+    Ok(MeshData {
+        name: mesh.name().map(str::to_owned),
+        vertices,
+        indices,
+        submeshes,
+        lods: Vec::new(),
+        morph_targets,
+        target_names: target_names(mesh),
+        morph_deltas,
+    })
+}
+
+// Nodes named like `Rock_LOD1`, `Rock_LOD2` are levels of detail of the mesh
+// of `Rock` or `Rock_LOD0`, the convention most exporters follow. Their
+// meshes are appended to that one as its levels, in order, and the nodes
+// lose them so they aren't drawn on their own. They're expected to sit where
+// the full detail node does.
+fn merge_authored_lods(nodes: &mut [Node], meshes: &mut [MeshData]) {
+    fn split(name: &str) -> Option<(&str, usize)> {
+        let (base, level) = name.rsplit_once('_')?;
+        let level = level.strip_prefix("LOD").or_else(|| level.strip_prefix("lod"))?;
+        Some((base, level.parse().ok()?))
+    }
+
+    let mut levels = Vec::new();
+    for (index, node) in nodes.iter().enumerate() {
+        let Some((base, level)) = node.name.as_deref().and_then(split).filter(|&(_, level)| level > 0) else {
+            continue;
+        };
+        if node.mesh.is_none() {
+            continue;
+        }
+        let full = nodes.iter().position(|other| {
+            other.mesh.is_some() && other.name.as_deref().is_some_and(|name| name == base || split(name) == Some((base, 0)))
+        });
+        match full {
+            Some(full) => levels.push((full, level, index)),
+            None => log::warn!("{} has no full detail node called {} to be a level of", node.name.as_deref().unwrap_or(""), base),
+        }
+    }
+    levels.sort();
+    for (full, _, index) in levels {
+        let (Some(mesh), Some(lod)) = (nodes[full].mesh, nodes[index].mesh.take()) else {
+            continue;
+        };
+        nodes[index].weights.clear();
+        if mesh != lod {
+            let lod = meshes[lod].clone();
+            meshes[mesh].push_lod(lod);
+        }
+    }
+}
+
+// 8-bit images only, anything else is skipped with a warning
+fn load_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    mipmaps: &mut MipmapGenerator,
+    image: &gltf::image::Data,
+    label: &str,
+) -> Option<Texture> {
+    use gltf::image::Format;
+
+    let rgba: Vec<u8> = match image.format {
+        Format::R8G8B8A8 => image.pixels.clone(),
+        Format::R8G8B8 => image.pixels.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+        Format::R8G8 => image.pixels.chunks_exact(2).flat_map(|p| [p[0], p[1], 0, 255]).collect(),
+        Format::R8 => image.pixels.iter().flat_map(|&p| [p, p, p, 255]).collect(),
+        format => {
+            log::warn!("{}: unsupported image format {:?}", label, format);
+            return None;
+        }
+    };
+    Some(Texture::from_rgba_with_mipmaps(device, queue, mipmaps, &rgba, image.width, image.height, Some(label)))
+}
+
+fn target_names(mesh: &gltf::Mesh) -> Vec<String> {
+    #[derive(serde::Deserialize)]
+    struct Extras {
+        #[serde(rename = "targetNames", default)]
+        target_names: Vec<String>,
+    }
+    mesh.extras()
+        .as_ref()
+        .and_then(|extras| serde_json::from_str::<Extras>(extras.get()).ok())
+        .map(|extras| extras.target_names)
+        .unwrap_or_default()
+}
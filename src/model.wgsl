@@ -0,0 +1,213 @@
+// glTF meshes, shaded with the metallic-roughness BRDF: the scene's lights
+// directly, and image based lighting for everything else. Skinned and
+// morphed meshes come already done by skinning.wgsl, with an identity
+// palette and no morph weights; the rest are placed by their node's
+// transform here.
+
+#include "camera.wgsl"
+#include "draw_data.wgsl"
+#include "fog.wgsl"
+#include "gbuffer.wgsl"
+#include "lighting.wgsl"
+#include "oit.wgsl"
+#include "pbr.wgsl"
+#include "reflection_probes.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+@group(0) @binding(2)
+var<uniform> lighting: LightUniform;
+@group(0) @binding(7)
+var<storage, read> lights: array<GpuLight>;
+// baked by ibl.rs, see `Ibl`
+@group(0) @binding(3)
+var t_irradiance: texture_cube<f32>;
+@group(0) @binding(4)
+var t_prefiltered: texture_cube<f32>;
+@group(0) @binding(5)
+var t_brdf_lut: texture_2d<f32>;
+@group(0) @binding(6)
+var s_ibl: sampler;
+
+// the prefiltered map's last mip, for roughness 1, as PREFILTERED_MIPS in
+// ibl.rs makes it
+const MAX_REFLECTION_LOD: f32 = 4.0;
+// below this the highlights get too small to see between pixels
+const MIN_ROUGHNESS: f32 = 0.04;
+
+// world space joint matrices; unskinned meshes get a single entry
+@group(1) @binding(0)
+var<storage, read> joints: array<mat4x4<f32>>;
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+};
+
+// one weight per morph target; meshes without targets get a single zero
+@group(1) @binding(1)
+var<storage, read> morph_weights: array<f32>;
+// target-major, one delta per vertex for every target
+@group(1) @binding(2)
+var<storage, read> morph_deltas: array<MorphDelta>;
+
+// base colour texture, plain white for untextured materials
+@group(2) @binding(0)
+var t_base_color: texture_2d<f32>;
+@group(2) @binding(1)
+var s_base_color: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) normal: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) color: vec3<f32>,
+    @location(4) joints: vec4<u32>,
+    @location(5) weights: vec4<f32>,
+    @location(6) metallic_roughness: vec2<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
+    @location(3) world_position: vec3<f32>,
+    @location(4) metallic_roughness: vec2<f32>,
+};
+
+@vertex
+fn vs_main(in: VertexInput, @builtin(vertex_index) vertex_index: u32) -> VertexOutput {
+    var position = in.position;
+    var normal = in.normal;
+    let targets = arrayLength(&morph_weights);
+    let vertex_count = arrayLength(&morph_deltas) / targets;
+    for (var t = 0u; t < targets; t++) {
+        let weight = morph_weights[t];
+        if weight != 0.0 {
+            let delta = morph_deltas[t * vertex_count + vertex_index];
+            position += weight * delta.position.xyz;
+            normal += weight * delta.normal.xyz;
+        }
+    }
+
+    let skin = joints[in.joints.x] * in.weights.x
+        + joints[in.joints.y] * in.weights.y
+        + joints[in.joints.z] * in.weights.z
+        + joints[in.joints.w] * in.weights.w;
+
+    var out: VertexOutput;
+    let world_position = skin * vec4<f32>(position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    // fine as long as joints don't scale non-uniformly
+    out.normal = (skin * vec4<f32>(normal, 0.0)).xyz;
+    out.color = in.color;
+    out.tex_coords = in.tex_coords;
+    out.metallic_roughness = in.metallic_roughness;
+    return out;
+}
+
+// Drops the pixels a level of detail doesn't cover while it's cross-fading:
+// a fade in (0, 1] keeps that fraction of a noise pattern, its negation the
+// remaining pixels, so the two levels fading into each other add up to a
+// whole mesh. Interleaved gradient noise, which TAA smooths out well.
+fn lod_fade(position: vec2<f32>) {
+    if draw.fade == 0.0 {
+        return;
+    }
+    let noise = fract(52.9829189 * fract(dot(floor(position), vec2<f32>(0.06711056, 0.00583715))));
+    if (draw.fade > 0.0 && noise >= draw.fade) || (draw.fade < 0.0 && noise < -draw.fade) {
+        discard;
+    }
+}
+
+// The environment's light: irradiance for the diffuse part, and the split
+// sum of the prefiltered radiance and the BRDF table for the specular, from
+// the reflection probes around `position` where there are any
+fn image_based_light(
+    position: vec3<f32>,
+    n: vec3<f32>,
+    v: vec3<f32>,
+    albedo: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let n_dot_v = max(dot(n, v), 0.0);
+    let f0 = mix(vec3<f32>(0.04), albedo, metallic);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let irradiance = textureSampleLevel(t_irradiance, s_ibl, n, 0.0).rgb;
+    let diffuse = (1.0 - f) * (1.0 - metallic) * albedo * irradiance;
+    let r = reflect(-v, n);
+    let lod = roughness * MAX_REFLECTION_LOD;
+    let environment = textureSampleLevel(t_prefiltered, s_ibl, r, lod).rgb;
+    let radiance = probe_radiance(s_ibl, position, r, lod, environment);
+    let brdf = textureSampleLevel(t_brdf_lut, s_ibl, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    return diffuse + radiance * (f * brdf.x + brdf.y);
+}
+
+// Lit and fogged colour, with the alpha of the texture and the tint
+fn shade(in: VertexOutput) -> vec4<f32> {
+    let texel = textureSample(t_base_color, s_base_color, in.tex_coords);
+    let albedo = in.color * texel.rgb * draw.tint.rgb;
+    let n = normalize(in.normal);
+    let v = normalize(camera.view_pos.xyz - in.world_position);
+    let metallic = clamp(in.metallic_roughness.x, 0.0, 1.0);
+    let roughness = clamp(in.metallic_roughness.y, MIN_ROUGHNESS, 1.0);
+    var lit = image_based_light(in.world_position, n, v, albedo, metallic, roughness);
+    for (var i = 0u; i < lighting.count; i++) {
+        let light = sample_light(lights[i], in.world_position);
+        lit += direct_brdf(n, v, light.direction, light.radiance, albedo, metallic, roughness);
+    }
+    return vec4<f32>(apply_fog(fog, camera.view_pos.xyz, in.world_position, lit), texel.a * draw.tint.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    lod_fade(in.clip_position.xy);
+    let color = shade(in);
+    // cut-out transparency, fs_blend and fs_oit blend instead
+    if color.a < 0.5 {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
+
+// Translucent models, alpha blended back to front after the opaque scene
+@fragment
+fn fs_blend(in: VertexOutput) -> @location(0) vec4<f32> {
+    lod_fade(in.clip_position.xy);
+    return shade(in);
+}
+
+// Translucent models, drawn into the weighted blended OIT targets
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    lod_fade(in.clip_position.xy);
+    let color = shade(in);
+    return oit_output(color.rgb, color.a, in.clip_position.z);
+}
+
+// Screen motion from the camera for TAA. The animation's own isn't tracked,
+// the resolve's clamping hides most of that.
+@fragment
+fn fs_velocity(in: VertexOutput) -> @location(0) vec2<f32> {
+    lod_fade(in.clip_position.xy);
+    return screen_motion(camera, in.world_position);
+}
+
+// Into the SSR G-buffer, cut out as fs_main
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    lod_fade(in.clip_position.xy);
+    let texel = textureSample(t_base_color, s_base_color, in.tex_coords);
+    if texel.a * draw.tint.a < 0.5 {
+        discard;
+    }
+    let albedo = in.color * texel.rgb * draw.tint.rgb;
+    let metallic = clamp(in.metallic_roughness.x, 0.0, 1.0);
+    let roughness = clamp(in.metallic_roughness.y, MIN_ROUGHNESS, 1.0);
+    return gbuffer_output(normalize(in.normal), roughness, mix(vec3<f32>(0.04), albedo, metallic));
+}
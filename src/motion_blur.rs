@@ -0,0 +1,242 @@
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::camera::CameraUniform;
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::SamplerDesc;
+use crate::tonemap::HDR_FORMAT;
+
+// the most taps a pixel's blur takes
+const MAX_SAMPLES: u32 = 64;
+
+/// Motion blur, as set per scene: each pixel is smeared along how far it
+/// moved on screen while the shutter was open.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MotionBlur {
+    pub enabled: bool,
+    // taps along each pixel's motion, 1 to 64, more is smoother and slower
+    pub samples: u32,
+    // how much of the frame the shutter's open for, 0.5 is a film camera's
+    // 180 degree shutter and 1 blurs across the whole of each frame's motion
+    pub shutter: f32,
+}
+
+// off, until a scene or `;` turns it on
+impl Default for MotionBlur {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            samples: 8,
+            shutter: 0.5,
+        }
+    }
+}
+
+// Matches `MotionBlurUniform` in motion_blur.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct MotionBlurUniform {
+    reprojection: [[f32; 4]; 4],
+    samples: u32,
+    shutter: f32,
+    _padding: [u32; 2],
+}
+
+// Motion blur over the HDR scene, before it's tonemapped, from the velocity
+// TAA's velocity pass draws: camera motion everywhere, and the instanced
+// meshes' own from their last frame's transforms. The scene is rendered, or
+// resolved by TAA, into `view`, and each pixel of it is averaged along its
+// motion, centred on the pixel, into the target `encode` is given. Where
+// the velocity pass drew nothing, the sky, the motion is the far plane's
+// as the camera turned.
+pub struct MotionBlurPass {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    frame: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl MotionBlurPass {
+    // `velocity` is `TemporalAa::velocity_view`, the size of the scene
+    pub fn new(device: &wgpu::Device, width: u32, height: u32, velocity: &wgpu::TextureView) -> Self {
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1, true),
+            texture_entry(2, false),
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let shader = shader_preprocessor::load("motion_blur.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &entries)?;
+                reflection.check_struct::<MotionBlurUniform>("MotionBlurUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Motion Blur Shader");
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Motion Blur Bind Group Layout"),
+            entries: &entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Motion Blur Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(device, "Motion Blur Pipeline", &pipeline_layout, &shader, HDR_FORMAT);
+        // the taps land between texels
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Motion Blur Sampler"));
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Motion Blur Buffer"),
+            contents: bytemuck::cast_slice(&[MotionBlurUniform {
+                reprojection: Matrix4::identity().into(),
+                samples: 1,
+                shutter: 0.0,
+                _padding: [0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (frame, bind_group) = Self::create_targets(device, &layout, &sampler, &buffer, width, height, velocity);
+        Self {
+            layout,
+            pipeline,
+            sampler,
+            buffer,
+            frame,
+            bind_group,
+        }
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+        velocity: &wgpu::TextureView,
+    ) -> (Tracked<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Motion Blur Frame"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let frame = Tracked::view(
+            ResourceKind::Target,
+            &texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("Motion Blur Frame View"),
+                ..Default::default()
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Motion Blur Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&frame),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(velocity),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        (frame, bind_group)
+    }
+
+    // After TAA's been resized, with its new velocity target
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32, velocity: &wgpu::TextureView) {
+        (self.frame, self.bind_group) =
+            Self::create_targets(device, &self.layout, &self.sampler, &self.buffer, width, height, velocity);
+    }
+
+    // Where the scene gets rendered, or TAA resolves it, to be blurred
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.frame
+    }
+
+    // Once a frame, with the main camera's uniform after it's been updated
+    pub fn update(&self, queue: &wgpu::Queue, settings: &MotionBlur, camera: &CameraUniform) {
+        let uniform = MotionBlurUniform {
+            reprojection: camera.reprojection().into(),
+            samples: settings.samples.clamp(1, MAX_SAMPLES),
+            shutter: settings.shutter.max(0.0),
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Blurs `view` into `target`, which is HDR and the same size
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Motion Blur Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
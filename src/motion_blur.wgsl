@@ -0,0 +1,63 @@
+// Motion blur, see motion_blur.rs. Each pixel averages the scene along how
+// far it moved on screen while the shutter was open, half before and half
+// after where it is now.
+
+#include "fullscreen.wgsl"
+
+// Matches `MotionBlurUniform` in motion_blur.rs
+struct MotionBlurUniform {
+    // from this frame's clip space to the last frame's, for the sky
+    reprojection: mat4x4<f32>,
+    samples: u32,
+    // the part of each frame's motion that's blurred over
+    shutter: f32,
+    _padding: vec2<u32>,
+};
+
+@group(0) @binding(0)
+var<uniform> blur: MotionBlurUniform;
+@group(0) @binding(1)
+var t_scene: texture_2d<f32>;
+// NDC motion since the last frame, from TAA's velocity pass
+@group(0) @binding(2)
+var t_velocity: texture_2d<f32>;
+@group(0) @binding(3)
+var s_scene: sampler;
+
+// the longest a blur gets, in pixels, so something flying past doesn't
+// smear across half the screen
+const MAX_LENGTH: f32 = 48.0;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let size = vec2<f32>(textureDimensions(t_scene));
+    let pixel = vec2<i32>(in.position.xy);
+    let uv = in.uv;
+    let center = textureLoad(t_scene, pixel, 0);
+
+    var motion = textureLoad(t_velocity, pixel, 0).xy;
+    // nothing was drawn here, so it's as far as the far plane and only
+    // moved as the camera did
+    if all(motion == vec2<f32>(0.0)) {
+        let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+        let previous = blur.reprojection * vec4<f32>(ndc, 1.0, 1.0);
+        motion = ndc - previous.xy / previous.w;
+    }
+    // NDC to texture coordinates, where y points down
+    var offset = motion * vec2<f32>(0.5, -0.5) * blur.shutter;
+    let length_px = length(offset * size);
+    if length_px > MAX_LENGTH {
+        offset *= MAX_LENGTH / length_px;
+    }
+    if length_px < 0.5 || blur.samples <= 1u {
+        return center;
+    }
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0u; i < blur.samples; i++) {
+        // evenly from where it was to where it is, centred on the pixel
+        let t = (f32(i) + 0.5) / f32(blur.samples) - 0.5;
+        color += textureSampleLevel(t_scene, s_scene, uv - offset * t, 0.0).rgb;
+    }
+    return vec4<f32>(color / f32(blur.samples), center.a);
+}
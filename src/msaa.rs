@@ -0,0 +1,240 @@
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_sampled;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+// The sample counts an MSAA target can have, most first
+const SAMPLE_COUNTS: [u32; 4] = [16, 8, 4, 2];
+
+// Without it a device only takes the sample counts every one supports, 4
+// for the HDR format
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+}
+
+// `requested` samples if `device` can draw `format` with that many and
+// resolve them, otherwise the most it can below that, 1 for no MSAA
+pub fn supported_samples(
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    format: wgpu::TextureFormat,
+    requested: u32,
+) -> u32 {
+    let features = if device.features().contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES) {
+        adapter.get_texture_format_features(format)
+    } else {
+        format.guaranteed_format_features(device.features())
+    };
+    let resolves = features.flags.contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+    let samples = choose_samples(requested, |count| resolves && features.flags.sample_count_supported(count));
+    if samples != requested.max(1) {
+        log::warn!("{} samples of MSAA aren't supported here, using {}", requested, samples);
+    }
+    samples
+}
+
+fn choose_samples(requested: u32, supported: impl Fn(u32) -> bool) -> u32 {
+    SAMPLE_COUNTS.into_iter().find(|&count| count <= requested && supported(count)).unwrap_or(1)
+}
+
+/// A pipeline for single sampled passes, and with MSAA on another for the
+/// multisampled ones. Which one a pass gets goes by its
+/// `TracedPass::sample_count`.
+pub struct SampledPipeline<P = wgpu::RenderPipeline> {
+    single: P,
+    multisampled: Option<P>,
+}
+
+impl<P> SampledPipeline<P> {
+    // `create` makes the pipeline with each multisample state needed, the
+    // default one first
+    pub fn new(samples: u32, mut create: impl FnMut(wgpu::MultisampleState) -> P) -> Self {
+        Self {
+            single: create(wgpu::MultisampleState::default()),
+            multisampled: (samples > 1).then(|| {
+                create(wgpu::MultisampleState {
+                    count: samples,
+                    ..Default::default()
+                })
+            }),
+        }
+    }
+
+    pub fn get(&self, samples: u32) -> &P {
+        match &self.multisampled {
+            Some(pipeline) if samples > 1 => pipeline,
+            _ => &self.single,
+        }
+    }
+}
+
+/// What the passes drawn straight into the scene draw to with MSAA on,
+/// resolved into the scene's view at the end of each. The passes in between
+/// have depth buffers or targets of their own and stay single sampled, so
+/// every pass but the first starts from what's been resolved so far.
+pub struct MsaaTarget {
+    samples: u32,
+    format: wgpu::TextureFormat,
+    view: Tracked<wgpu::TextureView>,
+    layout: wgpu::BindGroupLayout,
+    reload_pipeline: wgpu::RenderPipeline,
+}
+
+impl MsaaTarget {
+    pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat, samples: u32, width: u32, height: u32) -> Self {
+        let shader = shader_preprocessor::create_module(device, "MSAA Reload Shader", "msaa.wgsl", &ShaderDefs::new());
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MSAA Reload Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MSAA Reload Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let reload_pipeline = fullscreen_pipeline_sampled(
+            device,
+            "MSAA Reload Pipeline",
+            &pipeline_layout,
+            &shader,
+            "fs_main",
+            &[Some(format.into())],
+            wgpu::MultisampleState {
+                count: samples,
+                ..Default::default()
+            },
+        );
+        Self {
+            samples,
+            format,
+            view: Self::create_view(device, format, samples, width, height),
+            layout,
+            reload_pipeline,
+        }
+    }
+
+    fn create_view(
+        device: &wgpu::Device,
+        format: wgpu::TextureFormat,
+        samples: u32,
+        width: u32,
+        height: u32,
+    ) -> Tracked<wgpu::TextureView> {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MSAA Target"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: samples,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Tracked::view(ResourceKind::Target, &texture, &wgpu::TextureViewDescriptor::default())
+    }
+
+    // At the scene's size, which every view it resolves into has
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.view = Self::create_view(device, self.format, self.samples, width, height);
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // A pass that draws into the target and resolves it into `view`, with
+    // `load` of `Load` starting from what's in `view`
+    pub fn begin<'e>(
+        &'e self,
+        device: &wgpu::Device,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        view: &'e wgpu::TextureView,
+        load: wgpu::LoadOp<wgpu::Color>,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'e>>,
+    ) -> TracedPass<'e> {
+        if load == wgpu::LoadOp::Load {
+            self.reload(device, encoder, view);
+        }
+        let pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: Some(view),
+                ops: wgpu::Operations {
+                    load,
+                    // the next pass reloads it from `view`
+                    store: wgpu::StoreOp::Discard,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+        pass.with_sample_count(self.samples)
+    }
+
+    // Every sample of the target from `view`, which can't be read in the
+    // pass that resolves into it
+    fn reload(&self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MSAA Reload Bind Group"),
+            layout: &self.layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            }],
+        });
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("MSAA Reload Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every sample is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.reload_pipeline);
+        pass.set_bind_group(0, &bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn takes_the_most_samples_supported_up_to_the_request() {
+        let up_to_eight = |count| count <= 8;
+        assert_eq!(choose_samples(4, up_to_eight), 4);
+        assert_eq!(choose_samples(16, up_to_eight), 8);
+        // counts in between go down to the one below
+        assert_eq!(choose_samples(6, up_to_eight), 4);
+        assert_eq!(choose_samples(4, |count| count == 2), 2);
+    }
+
+    #[test]
+    fn falls_back_to_one_sample() {
+        assert_eq!(choose_samples(1, |_| true), 1);
+        assert_eq!(choose_samples(0, |_| true), 1);
+        assert_eq!(choose_samples(4, |_| false), 1);
+    }
+}
@@ -0,0 +1,13 @@
+// Fills the multisampled scene target with what's been resolved into the
+// scene so far, every sample of a pixel with its colour
+
+#include "fullscreen.wgsl"
+
+// same size as the target, loaded texel for texel
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureLoad(scene, vec2<i32>(in.position.xy), 0);
+}
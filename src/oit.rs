@@ -0,0 +1,205 @@
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_sampled;
+use crate::msaa::SampledPipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+// Accumulated premultiplied colour needs the range and precision, the
+// revealage is a product of (1 - alpha) and fits in a byte
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+// Weighted blended order-independent transparency. Transparent surfaces
+// are drawn in any order into an accumulation and a revealage target, see
+// oit.wgsl, and `resolve` composites them over the opaque scene. Surfaces
+// close in depth blend as a weighted average rather than strictly front to
+// back, which holds up better than `RenderQueues` sorting when many of them
+// overlap, like glass or smoke.
+pub struct WeightedBlendedOit {
+    layout: wgpu::BindGroupLayout,
+    pipeline: SampledPipeline,
+    accum: Tracked<wgpu::TextureView>,
+    revealage: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl WeightedBlendedOit {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let shader = shader_preprocessor::create_module(device, "OIT Resolve Shader", "oit_resolve.wgsl", &ShaderDefs::new());
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Resolve Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Resolve Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        // over the scene, so in a pass drawn straight into it
+        let pipeline = SampledPipeline::new(samples, |multisample| {
+            fullscreen_pipeline_sampled(
+                device,
+                "OIT Resolve Pipeline",
+                &pipeline_layout,
+                &shader,
+                "fs_main",
+                &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                multisample,
+            )
+        });
+
+        let (accum, revealage, bind_group) = Self::create_targets(device, &layout, width, height);
+        Self {
+            layout,
+            pipeline,
+            accum,
+            revealage,
+            bind_group,
+        }
+    }
+
+    // The fragment targets of pipelines that draw into `begin_accumulate`'s
+    // pass, for a fragment shader returning `OitOutput`
+    pub fn targets() -> [Option<wgpu::ColorTargetState>; 2] {
+        let add = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let reveal = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+            operation: wgpu::BlendOperation::Add,
+        };
+        [
+            Some(wgpu::ColorTargetState {
+                format: ACCUM_FORMAT,
+                blend: Some(wgpu::BlendState { color: add, alpha: add }),
+                write_mask: wgpu::ColorWrites::ALL,
+            }),
+            Some(wgpu::ColorTargetState {
+                format: REVEALAGE_FORMAT,
+                blend: Some(wgpu::BlendState {
+                    color: reveal,
+                    alpha: reveal,
+                }),
+                write_mask: wgpu::ColorWrites::RED,
+            }),
+        ]
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::TextureView>, Tracked<wgpu::TextureView>, wgpu::BindGroup) {
+        let target = |label: &str, format| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view_label = format!("{} View", label);
+            Tracked::view(
+                ResourceKind::Target,
+                &texture,
+                &wgpu::TextureViewDescriptor {
+                    label: Some(&view_label),
+                    ..Default::default()
+                },
+            )
+        };
+        let accum = target("OIT Accumulation Target", ACCUM_FORMAT);
+        let revealage = target("OIT Revealage Target", REVEALAGE_FORMAT);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Resolve Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage),
+                },
+            ],
+        });
+        (accum, revealage, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.accum, self.revealage, self.bind_group) = Self::create_targets(device, &self.layout, width, height);
+    }
+
+    // The pass transparent surfaces are drawn in, with nothing accumulated
+    // and everything revealed to start with
+    pub fn begin_accumulate<'e>(
+        &'e self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'e>>,
+    ) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("OIT Accumulation Pass"),
+            color_attachments: &[
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &self.accum,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                        store: wgpu::StoreOp::Store,
+                    },
+                }),
+                Some(wgpu::RenderPassColorAttachment {
+                    view: &self.revealage,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::WHITE),
+                        store: wgpu::StoreOp::Store,
+                    },
+                }),
+            ],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes,
+        })
+    }
+
+    // Blends what was accumulated over the pass's colour target, which has
+    // the format `new` was given
+    pub fn resolve<'p>(&'p self, render_pass: &mut TracedPass<'p>) {
+        render_pass.set_pipeline(self.pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,22 @@
+// Weighted blended order-independent transparency (McGuire and Bavoil,
+// 2013). Transparent surfaces write `OitOutput` to the targets
+// `WeightedBlendedOit` sets up, in any order, and oit_resolve.wgsl
+// composites the sum over the opaque scene.
+
+struct OitOutput {
+    // premultiplied colour and alpha, weighted and summed
+    @location(0) accum: vec4<f32>,
+    // alpha, multiplied into what's left of the background
+    @location(1) revealage: f32,
+};
+
+// `depth` is the fragment's clip space depth, 0 at the near plane. Nearer
+// and more opaque surfaces weigh more, the closest a stand-in for sorting
+// gets without it.
+fn oit_output(color: vec3<f32>, alpha: f32, depth: f32) -> OitOutput {
+    let weight = clamp(pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - depth * 0.9, 3.0), 1e-2, 3e3);
+    var out: OitOutput;
+    out.accum = vec4<f32>(color * alpha, alpha) * weight;
+    out.revealage = alpha;
+    return out;
+}
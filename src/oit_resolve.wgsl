@@ -0,0 +1,23 @@
+// Composites the weighted sums from oit.wgsl over the opaque scene
+
+#include "fullscreen.wgsl"
+
+// same size as the target, loaded texel for texel
+@group(0) @binding(0)
+var accum: texture_2d<f32>;
+@group(0) @binding(1)
+var revealage: texture_2d<f32>;
+
+// blended over the scene with the source alpha
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let revealed = textureLoad(revealage, pixel, 0).r;
+    if revealed >= 1.0 {
+        discard;
+    }
+    let sum = textureLoad(accum, pixel, 0);
+    // the weighted average colour of everything transparent here
+    let color = sum.rgb / clamp(sum.a, 1e-4, 5e4);
+    return vec4<f32>(color, 1.0 - revealed);
+}
@@ -0,0 +1,247 @@
+use wgpu::util::DeviceExt;
+
+use crate::camera;
+use crate::draw_list::TracedPass;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+
+const STENCIL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth24PlusStencil8;
+// what the selection leaves in the stencil buffer
+const SELECTED: u32 = 1;
+
+// Matches `OutlineUniform` in outline.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct OutlineUniform {
+    color: [f32; 4],
+    viewport: [f32; 2],
+    width: f32,
+    _padding: u32,
+}
+
+// Outlines the selection in a highlight colour, in a pass of its own over
+// the finished 3D scene. The selected meshes are drawn twice, first into the
+// stencil buffer, then pushed outwards and coloured where the stencil is
+// still clear, so only a rim around them shows.
+//
+// The depth in its target is only the selection's own, so the outline isn't
+// hidden by anything in front of the selection and always shows on top, like
+// an editor's. The main pass has no depth buffer to test against, and the
+// prepass depth is `Depth32Float` without a stencil aspect and has only the
+// instances in it, not the models.
+pub struct SelectionOutline {
+    mask_pipeline: wgpu::RenderPipeline,
+    outline_pipeline: wgpu::RenderPipeline,
+    uniform: OutlineUniform,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    stencil_view: Tracked<wgpu::TextureView>,
+}
+
+impl SelectionOutline {
+    // `vertex_layouts` are those of the meshes `encode` draws, starting
+    // with a Float32x3 position at location 0 and the instance matrix at
+    // locations 5 to 8
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let shader = shader_preprocessor::load("outline.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_struct::<OutlineUniform>("OutlineUniform")?;
+                reflection.check_vertex_buffers("vs_outline", vertex_layouts)?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Outline Shader");
+
+        let uniform = OutlineUniform {
+            color: [1.0, 0.6, 0.1, 1.0],
+            viewport: [width.max(1) as f32, height.max(1) as f32],
+            width: 3.0,
+            _padding: 0,
+        };
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Outline Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Outline Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Outline Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+
+        let create_pipeline = |label, vertex_entry_point, write_mask, stencil: wgpu::StencilFaceState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: vertex_entry_point,
+                    buffers: vertex_layouts,
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                // both faces, the mask has to cover the whole silhouette
+                primitive: wgpu::PrimitiveState::default(),
+                // there's no scene depth to test against, the outline is
+                // drawn over everything
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: STENCIL_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState {
+                        front: stencil,
+                        back: stencil,
+                        read_mask: 0xff,
+                        write_mask: 0xff,
+                    },
+                    bias: Default::default(),
+                }),
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let mask_pipeline = create_pipeline(
+            "Outline Mask Pipeline",
+            "vs_main",
+            wgpu::ColorWrites::empty(),
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::Always,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+        );
+        let outline_pipeline = create_pipeline(
+            "Outline Pipeline",
+            "vs_outline",
+            wgpu::ColorWrites::ALL,
+            wgpu::StencilFaceState {
+                compare: wgpu::CompareFunction::NotEqual,
+                fail_op: wgpu::StencilOperation::Keep,
+                depth_fail_op: wgpu::StencilOperation::Keep,
+                // write it too, so overlapping rim triangles don't blend twice
+                pass_op: wgpu::StencilOperation::Replace,
+            },
+        );
+
+        Self {
+            mask_pipeline,
+            outline_pipeline,
+            uniform,
+            buffer,
+            bind_group,
+            stencil_view: Self::create_stencil(device, width, height),
+        }
+    }
+
+    fn create_stencil(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Outline Stencil Target"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: STENCIL_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        Tracked::view(ResourceKind::Target, &texture, &wgpu::TextureViewDescriptor::default())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        self.stencil_view = Self::create_stencil(device, width, height);
+        self.uniform.viewport = [width.max(1) as f32, height.max(1) as f32];
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Linear RGBA, and the rim's thickness in pixels
+    pub fn set_style(&mut self, queue: &wgpu::Queue, color: [f32; 4], width: f32) {
+        self.uniform.color = color;
+        self.uniform.width = width;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[self.uniform]));
+    }
+
+    // Outlines whatever `draw_selection` draws over `target`. It's called
+    // twice with the pipeline set and group 1 bound; it binds the camera at
+    // group 0 and the vertex and instance buffers, and draws the selection.
+    pub fn encode<F>(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, draw_selection: F)
+    where
+        F: Fn(&mut TracedPass),
+    {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Outline Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.stencil_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+            }),
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        pass.set_stencil_reference(SELECTED);
+        pass.set_bind_group(1, &self.bind_group, &[]);
+        for pipeline in [&self.mask_pipeline, &self.outline_pipeline] {
+            pass.set_pipeline(pipeline);
+            draw_selection(&mut pass);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+// Selection outlines. The selected mesh goes into the stencil buffer with
+// vs_main, colour writes off, then again pushed outwards by vs_outline,
+// coloured only where the stencil is still clear: a rim around it.
+
+#include "camera.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+// Matches `OutlineUniform` in outline.rs
+struct OutlineUniform {
+    color: vec4<f32>,
+    // target size in pixels
+    viewport: vec2<f32>,
+    // rim thickness in pixels
+    width: f32,
+};
+@group(1) @binding(0)
+var<uniform> outline: OutlineUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    return camera.view_proj * model_matrix(instance) * vec4<f32>(position, 1.0);
+}
+
+// Moves each vertex `outline.width` pixels away from the object's origin on
+// screen, so the rim is as thick at any distance
+@vertex
+fn vs_outline(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let to_clip = camera.view_proj * model_matrix(instance);
+    let clip = to_clip * vec4<f32>(position, 1.0);
+    let origin = to_clip * vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    let away = clip.xy / clip.w - origin.xy / origin.w;
+    if dot(away, away) < 1e-12 {
+        return clip;
+    }
+    let offset = normalize(away * outline.viewport) * outline.width * 2.0 / outline.viewport;
+    return vec4<f32>(clip.xy + offset * clip.w, clip.zw);
+}
+
+@fragment
+fn fs_main() -> @location(0) vec4<f32> {
+    return outline.color;
+}
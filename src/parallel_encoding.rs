@@ -0,0 +1,35 @@
+use rayon::prelude::*;
+
+/// Part of a frame that doesn't depend on the command buffers recorded with
+/// it, only on the ones submitted before, so it can be recorded on a thread
+/// of its own
+pub struct EncodeJob<'a> {
+    label: &'static str,
+    record: Box<dyn FnOnce(&mut wgpu::CommandEncoder) + Send + 'a>,
+}
+
+impl<'a> EncodeJob<'a> {
+    // `label` names the encoder and the profiler scope
+    pub fn new(label: &'static str, record: impl FnOnce(&mut wgpu::CommandEncoder) + Send + 'a) -> Self {
+        Self {
+            label,
+            record: Box::new(record),
+        }
+    }
+}
+
+// Records each job into an encoder of its own on rayon's pool. The command
+// buffers come back in the jobs' order, to be submitted together in it, so
+// a job's passes still run after the ones before it on the GPU.
+pub fn encode(device: &wgpu::Device, jobs: Vec<EncodeJob>) -> Vec<wgpu::CommandBuffer> {
+    jobs.into_par_iter()
+        .map(|job| {
+            profiling::scope!("encode job", job.label);
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some(job.label),
+            });
+            (job.record)(&mut encoder);
+            encoder.finish()
+        })
+        .collect()
+}
@@ -0,0 +1,293 @@
+use std::time::Duration;
+
+use bytemuck::Zeroable;
+use wgpu::util::DeviceExt;
+
+use crate::compute::{self, ComputePipeline, StorageBuffer};
+use crate::draw_list::TracedPass;
+use crate::msaa::SampledPipeline;
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// Mirrors `Particle` in particles.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Particle {
+    position: [f32; 3],
+    age: f32,
+    velocity: [f32; 3],
+    lifetime: f32,
+}
+
+impl Particle {
+    // particles double as instance data for the billboard pass
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Mirrors `SimParams` in particles.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SimParams {
+    origin: [f32; 3],
+    dt: f32,
+    velocity: [f32; 3],
+    spread: f32,
+    gravity: [f32; 3],
+    lifetime: f32,
+    color_start: [f32; 4],
+    color_end: [f32; 4],
+    emit_start: u32,
+    emit_count: u32,
+    capacity: u32,
+    seed: u32,
+    size: f32,
+    _padding: [f32; 3],
+}
+
+/// Emission settings, can be changed every frame.
+#[derive(Clone, Copy, Debug)]
+pub struct EmitterParams {
+    pub origin: [f32; 3],
+    // particles per second
+    pub rate: f32,
+    // seconds, each particle gets 75-100% of this
+    pub lifetime: f32,
+    pub velocity: [f32; 3],
+    // random velocity added on each axis, +/- this much
+    pub spread: f32,
+    pub gravity: [f32; 3],
+    // billboard half size in world units
+    pub size: f32,
+    // color at birth and at death, alpha included
+    pub color_start: [f32; 4],
+    pub color_end: [f32; 4],
+}
+
+impl Default for EmitterParams {
+    fn default() -> Self {
+        Self {
+            origin: [0.0, 0.0, 0.0],
+            rate: 500.0,
+            lifetime: 2.0,
+            velocity: [0.0, 1.0, 0.0],
+            spread: 0.4,
+            gravity: [0.0, -0.5, 0.0],
+            size: 0.02,
+            color_start: [1.0, 0.8, 0.3, 1.0],
+            color_end: [0.8, 0.1, 0.0, 0.0],
+        }
+    }
+}
+
+// Fixed capacity particle pool simulated in a compute pass and drawn as
+// additive, camera-facing quads. Spawning recycles slots ring-buffer style.
+pub struct ParticleSystem {
+    pub params: EmitterParams,
+    pub emitting: bool,
+    capacity: u32,
+    particles: StorageBuffer<Particle>,
+    params_buffer: wgpu::Buffer,
+    update_pipeline: ComputePipeline,
+    update_bind_group: wgpu::BindGroup,
+    render_pipeline: SampledPipeline,
+    render_bind_group: wgpu::BindGroup,
+    emit_cursor: u32,
+    // fractional particles carried over between frames
+    emit_accumulator: f32,
+    frame: u32,
+}
+
+impl ParticleSystem {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        capacity: u32,
+        params: EmitterParams,
+    ) -> Self {
+        // the update and render pipelines share one source
+        let shader = shader_preprocessor::load("particles.wgsl", &ShaderDefs::new()).unwrap_or_else(|e| panic!("{:#}", e));
+        let capacity = capacity.max(1);
+
+        // start everything dead: zero age and lifetime
+        let particles = StorageBuffer::<Particle>::zeroed_with_usage(
+            device,
+            "Particle Buffer",
+            capacity as usize,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Params Buffer"),
+            contents: bytemuck::cast_slice(&[SimParams::zeroed()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let update_pipeline = ComputePipeline::new(
+            device,
+            "Particle Update",
+            shader.source(),
+            "cs_update",
+            &[&[compute::uniform_entry(0), compute::storage_entry(1, false)]],
+        );
+        let update_bind_group = update_pipeline.create_bind_group(
+            device,
+            0,
+            &[params_buffer.as_entire_binding(), particles.binding()],
+        );
+
+        let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Particle Render Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Particle Render Bind Group"),
+            layout: &render_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = shader.create_module(device, "Particle Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Render Pipeline Layout"),
+            bind_group_layouts: &[&render_layout, camera_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = SampledPipeline::new(samples, |multisample| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Particle Render Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[Particle::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        // additive, so particles never need sorting
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::SrcAlpha,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                            alpha: wgpu::BlendComponent {
+                                src_factor: wgpu::BlendFactor::Zero,
+                                dst_factor: wgpu::BlendFactor::One,
+                                operation: wgpu::BlendOperation::Add,
+                            },
+                        }),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    // billboards always face us, no culling needed
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample,
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        Self {
+            params,
+            emitting: true,
+            capacity,
+            particles,
+            params_buffer,
+            update_pipeline,
+            update_bind_group,
+            render_pipeline,
+            render_bind_group,
+            emit_cursor: 0,
+            emit_accumulator: 0.0,
+            frame: 0,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    // Works out this frame's spawn range and uploads the simulation params
+    pub fn update(&mut self, queue: &wgpu::Queue, dt: Duration) {
+        let dt = dt.as_secs_f32();
+
+        let mut emit_count = 0;
+        if self.emitting {
+            self.emit_accumulator += self.params.rate * dt;
+            emit_count = (self.emit_accumulator as u32).min(self.capacity);
+            self.emit_accumulator -= emit_count as f32;
+        }
+        let emit_start = self.emit_cursor;
+        self.emit_cursor = (self.emit_cursor + emit_count) % self.capacity;
+        self.frame = self.frame.wrapping_add(1);
+
+        let p = &self.params;
+        let sim_params = SimParams {
+            origin: p.origin,
+            dt,
+            velocity: p.velocity,
+            spread: p.spread,
+            gravity: p.gravity,
+            lifetime: p.lifetime,
+            color_start: p.color_start,
+            color_end: p.color_end,
+            emit_start,
+            emit_count,
+            capacity: self.capacity,
+            seed: self.frame.wrapping_mul(0x9E37_79B9),
+            size: p.size,
+            _padding: [0.0; 3],
+        };
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[sim_params]));
+    }
+
+    // Records the simulation step, call before the render pass that draws them
+    pub fn simulate(&self, encoder: &mut wgpu::CommandEncoder) {
+        let workgroups = compute::workgroup_count(self.capacity, WORKGROUP_SIZE);
+        self.update_pipeline
+            .dispatch(encoder, &[&self.update_bind_group], (workgroups, 1, 1));
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(self.render_pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(0, &self.render_bind_group, &[]);
+        render_pass.set_bind_group(1, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.particles.buffer().slice(..));
+        render_pass.draw(0..6, 0..self.capacity);
+    }
+}
@@ -0,0 +1,124 @@
+// GPU particles: compute update + instanced billboards
+
+struct Particle {
+    position: vec3<f32>,
+    age: f32,
+    velocity: vec3<f32>,
+    lifetime: f32,
+};
+
+struct SimParams {
+    origin: vec3<f32>,
+    dt: f32,
+    velocity: vec3<f32>,
+    spread: f32,
+    gravity: vec3<f32>,
+    lifetime: f32,
+    color_start: vec4<f32>,
+    color_end: vec4<f32>,
+    emit_start: u32,
+    emit_count: u32,
+    capacity: u32,
+    seed: u32,
+    size: f32,
+};
+
+@group(0) @binding(0)
+var<uniform> params: SimParams;
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+// PCG hash, good enough for spawn jitter
+fn hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = hash(*seed);
+    return f32(*seed) / 4294967295.0;
+}
+
+@compute @workgroup_size(64)
+fn cs_update(@builtin(global_invocation_id) id: vec3<u32>) {
+    let i = id.x;
+    if (i >= params.capacity) {
+        return;
+    }
+    var p = particles[i];
+
+    // emission walks a ring over the buffer, recycling the oldest slots
+    let offset = (i + params.capacity - params.emit_start) % params.capacity;
+    if (offset < params.emit_count) {
+        var seed = hash(i ^ params.seed);
+        let jitter = vec3<f32>(random(&seed), random(&seed), random(&seed)) * 2.0 - 1.0;
+        p.position = params.origin;
+        p.velocity = params.velocity + jitter * params.spread;
+        p.age = 0.0;
+        p.lifetime = params.lifetime * mix(0.75, 1.0, random(&seed));
+    } else if (p.age < p.lifetime) {
+        p.velocity += params.gravity * params.dt;
+        p.position += p.velocity * params.dt;
+        p.age += params.dt;
+    }
+
+    particles[i] = p;
+}
+
+#include "camera.wgsl"
+@group(1) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct InstanceInput {
+    @location(0) position_age: vec4<f32>,
+    @location(1) velocity_lifetime: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) uv: vec2<f32>,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // two triangles, corners in [-1, 1]
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, -1.0), vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0), vec2<f32>(1.0, 1.0), vec2<f32>(-1.0, 1.0),
+    );
+    let corner = corners[vertex_index];
+
+    var out: VertexOutput;
+    out.uv = corner;
+
+    let age = instance.position_age.w;
+    let lifetime = instance.velocity_lifetime.w;
+    if (age >= lifetime) {
+        // dead, collapse the quad
+        out.clip_position = vec4<f32>(0.0, 0.0, 0.0, 0.0);
+        out.color = vec4<f32>(0.0);
+        return out;
+    }
+
+    // camera-facing: the view matrix rows are the camera axes in world space
+    let right = vec3<f32>(camera.view[0][0], camera.view[1][0], camera.view[2][0]);
+    let up = vec3<f32>(camera.view[0][1], camera.view[1][1], camera.view[2][1]);
+    let world = instance.position_age.xyz + (right * corner.x + up * corner.y) * params.size;
+    out.clip_position = camera.view_proj * vec4<f32>(world, 1.0);
+
+    let t = clamp(age / lifetime, 0.0, 1.0);
+    out.color = mix(params.color_start, params.color_end, t);
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    // soft round sprite
+    let falloff = 1.0 - smoothstep(0.5, 1.0, length(in.uv));
+    return vec4<f32>(in.color.rgb, in.color.a * falloff);
+}
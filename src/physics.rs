@@ -0,0 +1,225 @@
+use std::time::Duration;
+
+use cgmath::*;
+use rapier3d::na;
+use rapier3d::prelude::{
+    CCDSolver, ColliderBuilder, ColliderSet, DefaultBroadPhase, ImpulseJointSet, IntegrationParameters, IslandManager,
+    Isometry, MultibodyJointSet, NarrowPhase, PhysicsPipeline, RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
+    TypedShape,
+};
+
+use crate::bounds::Aabb;
+use crate::debug_draw::{self, DebugDraw};
+use crate::model::NodeTransform;
+use crate::scene::{BodyDesc, BodyKind, ColliderDesc, SceneModel};
+use crate::terrain::Heightmap;
+
+// in units per second squared, down y
+const GRAVITY: f32 = 9.81;
+
+/// A rapier world with a rigid body for each scene model that has one, and
+/// the terrain as fixed ground. Stepped in `State`'s fixed update, it moves
+/// the dynamic models' placements.
+pub struct Physics {
+    pipeline: PhysicsPipeline,
+    parameters: IntegrationParameters,
+    islands: IslandManager,
+    broad_phase: DefaultBroadPhase,
+    narrow_phase: NarrowPhase,
+    bodies: RigidBodySet,
+    colliders: ColliderSet,
+    impulse_joints: ImpulseJointSet,
+    multibody_joints: MultibodyJointSet,
+    ccd_solver: CCDSolver,
+    // the body of each model that has one, by index into the scene's models
+    model_bodies: Vec<(usize, RigidBodyHandle)>,
+}
+
+impl Default for Physics {
+    fn default() -> Self {
+        Self {
+            pipeline: PhysicsPipeline::new(),
+            parameters: IntegrationParameters::default(),
+            islands: IslandManager::new(),
+            broad_phase: DefaultBroadPhase::new(),
+            narrow_phase: NarrowPhase::new(),
+            bodies: RigidBodySet::new(),
+            colliders: ColliderSet::new(),
+            impulse_joints: ImpulseJointSet::new(),
+            multibody_joints: MultibodyJointSet::new(),
+            ccd_solver: CCDSolver::new(),
+            model_bodies: Vec::new(),
+        }
+    }
+}
+
+impl Physics {
+    // Bodies where the models are now, at rest
+    pub fn new(models: &[SceneModel], ground: Option<&Heightmap>) -> Self {
+        let mut physics = Self::default();
+        if let Some(heightmap) = ground {
+            physics.add_ground(heightmap);
+        }
+        for (index, model) in models.iter().enumerate() {
+            if let Some(body) = &model.body {
+                physics.add_model(index, body, &model.placement, model.bounds);
+            }
+        }
+        physics
+    }
+
+    pub fn body_count(&self) -> usize {
+        self.model_bodies.len()
+    }
+
+    // The heightmap as a heightfield, which rapier splits its cells with
+    // the other diagonal, so bodies can sit a little above or in the
+    // terrain's triangles where it's coarse
+    fn add_ground(&mut self, heightmap: &Heightmap) {
+        let [columns, rows] = heightmap.size();
+        let heights = na::DMatrix::from_row_slice(rows, columns, heightmap.heights());
+        let bounds = heightmap.bounds();
+        let size = bounds.max - bounds.min;
+        let center = bounds.center();
+        // heightfields span -0.5 to 0.5 of their scale around the origin
+        let collider = ColliderBuilder::heightfield(heights, na::Vector3::new(size.x, 1.0, size.z))
+            .translation(na::Vector3::new(center.x, 0.0, center.z))
+            .build();
+        self.colliders.insert(collider);
+    }
+
+    fn add_model(&mut self, index: usize, desc: &BodyDesc, placement: &NodeTransform, bounds: Option<Aabb>) {
+        let builder = match desc.kind {
+            BodyKind::Dynamic => RigidBodyBuilder::dynamic(),
+            BodyKind::Fixed => RigidBodyBuilder::fixed(),
+            BodyKind::Kinematic => RigidBodyBuilder::kinematic_position_based(),
+        };
+        let body = self.bodies.insert(builder.position(isometry(placement)).build());
+
+        // rapier's shapes don't scale, so colliders are built at the
+        // model's scale; the largest axis sizes balls and capsules. They're
+        // all centred on the model's bounds.
+        let scale = placement.scale;
+        let uniform = scale.x.max(scale.y).max(scale.z);
+        let center = bounds.map_or(Vector3::zero(), |bounds| bounds.center().to_vec().mul_element_wise(scale));
+        let collider = match desc.collider {
+            ColliderDesc::Bounds => match bounds {
+                Some(bounds) => {
+                    let half_extents = bounds.half_extents().mul_element_wise(scale);
+                    ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+                }
+                // nothing to go by, about the unit the models are scaled to
+                None => ColliderBuilder::ball(0.5 * uniform),
+            },
+            ColliderDesc::Cuboid { half_extents: [x, y, z] } => {
+                ColliderBuilder::cuboid(x * scale.x, y * scale.y, z * scale.z)
+            }
+            ColliderDesc::Ball { radius } => ColliderBuilder::ball(radius * uniform),
+            ColliderDesc::Capsule { half_height, radius } => {
+                ColliderBuilder::capsule_y(half_height * scale.y, radius * uniform)
+            }
+        };
+        let collider = collider
+            .translation(na::Vector3::new(center.x, center.y, center.z))
+            .density(desc.density)
+            .friction(desc.friction)
+            .restitution(desc.restitution)
+            .build();
+        self.colliders.insert_with_parent(collider, body, &mut self.bodies);
+        self.model_bodies.push((index, body));
+    }
+
+    // One step of `dt`, kinematic bodies moving to where their models are
+    // placed and dynamic ones moving their models
+    pub fn step(&mut self, dt: Duration, models: &mut [SceneModel]) {
+        profiling::scope!("physics");
+        for &(index, handle) in &self.model_bodies {
+            let (Some(model), Some(body)) = (models.get(index), self.bodies.get_mut(handle)) else {
+                continue;
+            };
+            if body.is_kinematic() {
+                body.set_next_kinematic_position(isometry(&model.placement));
+            }
+        }
+
+        self.parameters.dt = dt.as_secs_f32();
+        self.pipeline.step(
+            &na::Vector3::new(0.0, -GRAVITY, 0.0),
+            &self.parameters,
+            &mut self.islands,
+            &mut self.broad_phase,
+            &mut self.narrow_phase,
+            &mut self.bodies,
+            &mut self.colliders,
+            &mut self.impulse_joints,
+            &mut self.multibody_joints,
+            &mut self.ccd_solver,
+            None,
+            &(),
+            &(),
+        );
+
+        for &(index, handle) in &self.model_bodies {
+            let (Some(model), Some(body)) = (models.get_mut(index), self.bodies.get(handle)) else {
+                continue;
+            };
+            if body.is_dynamic() {
+                let position = body.position();
+                let (t, r) = (position.translation.vector, position.rotation);
+                model.placement.translation = vec3(t.x, t.y, t.z);
+                model.placement.rotation = Quaternion::new(r.w, r.i, r.j, r.k);
+            }
+        }
+    }
+
+    // Every collider but the ground's, which the terrain shows already:
+    // awake bodies white, sleeping ones blue and the others red
+    pub fn queue_debug_lines(&self, lines: &mut DebugDraw) {
+        for (_, collider) in self.colliders.iter() {
+            let Some(body) = collider.parent().and_then(|handle| self.bodies.get(handle)) else {
+                continue;
+            };
+            let color = if !body.is_dynamic() {
+                debug_draw::RED
+            } else if body.is_sleeping() {
+                debug_draw::BLUE
+            } else {
+                debug_draw::WHITE
+            };
+            let transform = matrix(collider.position());
+            let center = transform.transform_point(Point3::origin());
+            match collider.shape().as_typed_shape() {
+                TypedShape::Cuboid(cuboid) => {
+                    let half_extents = Vector3::from(<[f32; 3]>::from(cuboid.half_extents));
+                    let aabb = Aabb::new(Point3::from_vec(-half_extents), Point3::from_vec(half_extents));
+                    lines.oriented_box(&aabb, &transform, color);
+                }
+                TypedShape::Ball(ball) => lines.sphere(center, ball.radius, color),
+                TypedShape::Capsule(capsule) => {
+                    let [a, b] = [capsule.segment.a, capsule.segment.b]
+                        .map(|p| transform.transform_point(Point3::new(p.x, p.y, p.z)));
+                    let radius = capsule.radius;
+                    lines.sphere(a, radius, color);
+                    lines.sphere(b, radius, color);
+                    for side in [Vector3::unit_x(), Vector3::unit_z()] {
+                        let offset = transform.transform_vector(side) * radius;
+                        lines.line(a + offset, b + offset, color);
+                        lines.line(a - offset, b - offset, color);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn isometry(placement: &NodeTransform) -> Isometry<f32> {
+    let (t, r) = (placement.translation, placement.rotation);
+    let rotation = na::UnitQuaternion::new_normalize(na::Quaternion::new(r.s, r.v.x, r.v.y, r.v.z));
+    Isometry::from_parts(na::Translation3::new(t.x, t.y, t.z), rotation)
+}
+
+fn matrix(isometry: &Isometry<f32>) -> Matrix4<f32> {
+    let (t, r) = (isometry.translation.vector, isometry.rotation);
+    Matrix4::from_translation(vec3(t.x, t.y, t.z)) * Matrix4::from(Quaternion::new(r.w, r.i, r.j, r.k))
+}
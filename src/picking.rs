@@ -0,0 +1,293 @@
+use cgmath::*;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::bounds::Aabb;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ray {
+    pub origin: Point3<f32>,
+    // normalized
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f32>, direction: Vector3<f32>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    // Unprojects a window position through the inverse view-projection matrix,
+    // from the near plane (depth 0 in wgpu) to the far plane (depth 1)
+    pub fn from_cursor(
+        cursor: PhysicalPosition<f64>,
+        size: PhysicalSize<u32>,
+        view_proj: Matrix4<f32>,
+    ) -> Option<Self> {
+        if size.width == 0 || size.height == 0 {
+            return None;
+        }
+        let inverse = view_proj.invert()?;
+        let x = (2.0 * cursor.x / size.width as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * cursor.y / size.height as f64) as f32;
+
+        let near = inverse * Vector4::new(x, y, 0.0, 1.0);
+        let far = inverse * Vector4::new(x, y, 1.0, 1.0);
+        let near = Point3::from_homogeneous(near);
+        let far = Point3::from_homogeneous(far);
+        Some(Self::new(near, far - near))
+    }
+
+    pub fn at(&self, t: f32) -> Point3<f32> {
+        self.origin + self.direction * t
+    }
+
+    // The same ray expressed in another space, direction is not renormalized
+    // so distances along both rays stay comparable
+    fn transformed(&self, transform: &Matrix4<f32>) -> Self {
+        Self {
+            origin: transform.transform_point(self.origin),
+            direction: transform.transform_vector(self.direction),
+        }
+    }
+
+    // Slab test, returns the entry distance (0 if the origin is inside)
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        let mut t_min = 0.0_f32;
+        let mut t_max = f32::INFINITY;
+        for axis in 0..3 {
+            let inv_d = 1.0 / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inv_d;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from 0 * inf (ray in the slab's plane) is ignored by min/max
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max < t_min {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+
+    // Möller–Trumbore, double sided
+    pub fn intersect_triangle(&self, triangle: &[Point3<f32>; 3]) -> Option<f32> {
+        const EPSILON: f32 = 1e-7;
+        let [a, b, c] = *triangle;
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = self.direction.cross(edge2);
+        let det = edge1.dot(p);
+        if det.abs() < EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = self.origin - a;
+        let u = s.dot(p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = s.cross(edge1);
+        let v = self.direction.dot(q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let t = edge2.dot(q) * inv_det;
+        (t > EPSILON).then_some(t)
+    }
+}
+
+/// Something that can be hit by a pick ray.
+pub struct Pickable<'a> {
+    pub id: u32,
+    pub model: Matrix4<f32>,
+    // bounds in model space
+    pub bounds: Aabb,
+    // model space triangles, tested after the bounds when present
+    pub triangles: Option<&'a [[Point3<f32>; 3]]>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Hit {
+    pub id: u32,
+    pub distance: f32,
+    pub point: Point3<f32>,
+}
+
+// Casts `ray` (in world space) against every target and returns the closest hit
+pub fn pick<'a, I>(ray: &Ray, targets: I) -> Option<Hit>
+where
+    I: IntoIterator<Item = &'a Pickable<'a>>,
+{
+    let mut closest: Option<Hit> = None;
+    for target in targets {
+        let Some(inverse) = target.model.invert() else {
+            continue;
+        };
+        let local_ray = ray.transformed(&inverse);
+
+        let Some(box_t) = local_ray.intersect_aabb(&target.bounds) else {
+            continue;
+        };
+        let t = match target.triangles {
+            Some(triangles) => match triangles
+                .iter()
+                .filter_map(|tri| local_ray.intersect_triangle(tri))
+                .min_by(f32::total_cmp)
+            {
+                Some(t) => t,
+                None => continue,
+            },
+            None => box_t,
+        };
+
+        let point = target.model.transform_point(local_ray.at(t));
+        let distance = (point - ray.origin).magnitude();
+        if closest.is_none_or(|hit| distance < hit.distance) {
+            closest = Some(Hit {
+                id: target.id,
+                distance,
+                point,
+            });
+        }
+    }
+    closest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(1.0, 1.0, 1.0))
+    }
+
+    fn along_x(y: f32) -> Ray {
+        Ray::new(Point3::new(-5.0, y, 0.5), Vector3::unit_x())
+    }
+
+    const TRIANGLE: [Point3<f32>; 3] = [
+        Point3::new(0.0, 0.0, 0.0),
+        Point3::new(1.0, 0.0, 0.0),
+        Point3::new(0.0, 1.0, 0.0),
+    ];
+
+    fn pickable(id: u32, model: Matrix4<f32>) -> Pickable<'static> {
+        Pickable {
+            id,
+            model,
+            bounds: unit_box(),
+            triangles: None,
+        }
+    }
+
+    #[test]
+    fn rays_enter_boxes_in_front_of_them() {
+        assert_eq!(along_x(0.5).intersect_aabb(&unit_box()), Some(5.0));
+        let diagonal = Ray::new(Point3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+        assert!((diagonal.intersect_aabb(&unit_box()).unwrap() - 3.0_f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn rays_miss_boxes_beside_and_behind_them() {
+        assert_eq!(along_x(2.0).intersect_aabb(&unit_box()), None);
+        let away = Ray::new(Point3::new(-5.0, 0.5, 0.5), -Vector3::unit_x());
+        assert_eq!(away.intersect_aabb(&unit_box()), None);
+        // past a corner, through the planes of every face but never all at once
+        let past = Ray::new(Point3::new(-1.0, 0.5, 0.5), Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(past.intersect_aabb(&unit_box()), None);
+    }
+
+    #[test]
+    fn rays_from_inside_a_box_hit_it_straight_away() {
+        let inside = Ray::new(Point3::new(0.5, 0.5, 0.5), Vector3::new(0.3, -1.0, 0.2));
+        assert_eq!(inside.intersect_aabb(&unit_box()), Some(0.0));
+    }
+
+    #[test]
+    fn rays_parallel_to_a_slab_hit_only_inside_it() {
+        // never crossing y or z, so only inside both slabs
+        assert_eq!(along_x(0.5).intersect_aabb(&unit_box()), Some(5.0));
+        assert_eq!(along_x(1.5).intersect_aabb(&unit_box()), None);
+        // and along a face, where 0 * inf is NaN
+        assert_eq!(along_x(0.0).intersect_aabb(&unit_box()), Some(5.0));
+        assert_eq!(along_x(1.0).intersect_aabb(&unit_box()), Some(5.0));
+    }
+
+    #[test]
+    fn triangles_are_hit_from_either_side() {
+        let front = Ray::new(Point3::new(0.25, 0.25, 2.0), -Vector3::unit_z());
+        let back = Ray::new(Point3::new(0.25, 0.25, -3.0), Vector3::unit_z());
+        assert!((front.intersect_triangle(&TRIANGLE).unwrap() - 2.0).abs() < 1e-6);
+        assert!((back.intersect_triangle(&TRIANGLE).unwrap() - 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn triangles_are_missed_outside_their_edges() {
+        // past the long edge, and beside each of the others
+        for (x, y) in [(0.6, 0.6), (-0.1, 0.5), (0.5, -0.1)] {
+            let ray = Ray::new(Point3::new(x, y, 2.0), -Vector3::unit_z());
+            assert_eq!(ray.intersect_triangle(&TRIANGLE), None, "({}, {})", x, y);
+        }
+        // behind the ray, and edge on
+        let away = Ray::new(Point3::new(0.25, 0.25, 2.0), Vector3::unit_z());
+        assert_eq!(away.intersect_triangle(&TRIANGLE), None);
+        let edge_on = Ray::new(Point3::new(-1.0, 0.25, 0.0), Vector3::unit_x());
+        assert_eq!(edge_on.intersect_triangle(&TRIANGLE), None);
+    }
+
+    #[test]
+    fn pick_returns_the_closest_hit() {
+        let far = pickable(1, Matrix4::from_translation(Vector3::new(10.0, 0.0, 0.0)));
+        let near = pickable(2, Matrix4::from_translation(Vector3::new(3.0, 0.0, 0.0)));
+        let beside = pickable(3, Matrix4::from_translation(Vector3::new(0.0, 5.0, 0.0)));
+        let ray = along_x(0.5);
+        let hit = pick(&ray, [&far, &beside, &near]).unwrap();
+        assert_eq!(hit.id, 2);
+        assert!((hit.distance - 8.0).abs() < 1e-5);
+        assert!((hit.point - Point3::new(3.0, 0.5, 0.5)).magnitude() < 1e-5);
+        assert_eq!(pick(&ray, [&near, &far]).unwrap().id, 2);
+        assert!(pick(&ray, [&beside]).is_none());
+    }
+
+    #[test]
+    fn pick_measures_distance_in_world_space() {
+        // scaled up, the box's near face is at x = 2 rather than 4
+        let model = Matrix4::from_translation(Vector3::new(4.0, -2.0, -2.0)) * Matrix4::from_scale(4.0);
+        let scaled = pickable(1, model);
+        let hit = pick(&along_x(0.5), [&scaled]).unwrap();
+        assert!((hit.distance - 9.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn pick_tests_triangles_inside_the_bounds() {
+        // only the lower left half of the box's z = 0.5 slice is solid
+        let triangles = [TRIANGLE.map(|corner| corner + Vector3::new(0.0, 0.0, 0.5))];
+        let mesh = Pickable {
+            triangles: Some(&triangles),
+            ..pickable(1, Matrix4::identity())
+        };
+        let behind = pickable(2, Matrix4::from_translation(Vector3::new(0.0, 0.0, -3.0)));
+        let hit_at = |x, y| pick(&Ray::new(Point3::new(x, y, 5.0), -Vector3::unit_z()), [&mesh, &behind]);
+        let hit = hit_at(0.25, 0.25).unwrap();
+        assert_eq!(hit.id, 1);
+        assert!((hit.distance - 4.5).abs() < 1e-5);
+        // through the bounds but not the triangle, on to what's behind it
+        assert_eq!(hit_at(0.75, 0.75).unwrap().id, 2);
+    }
+
+    #[test]
+    fn the_middle_of_the_window_looks_straight_ahead() {
+        let view = Matrix4::look_to_rh(Point3::new(1.0, 2.0, 3.0), -Vector3::unit_z(), Vector3::unit_y());
+        let projection = crate::camera::OPENGL_TO_WGPU_MATRIX * perspective(Deg(60.0), 2.0, 0.1, 100.0);
+        let size = PhysicalSize::new(200, 100);
+        let ray = Ray::from_cursor(PhysicalPosition::new(100.0, 50.0), size, projection * view).unwrap();
+        assert!((ray.direction - -Vector3::unit_z()).magnitude() < 1e-5);
+        assert!((ray.origin - Point3::new(1.0, 2.0, 2.9)).magnitude() < 1e-4);
+        let empty = PhysicalSize::new(0, 0);
+        assert!(Ray::from_cursor(PhysicalPosition::new(0.0, 0.0), empty, projection * view).is_none());
+    }
+}
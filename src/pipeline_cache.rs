@@ -0,0 +1,188 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Where cache blobs go unless LEARN_WGPU_CACHE_DIR says otherwise
+pub fn default_cache_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("LEARN_WGPU_CACHE_DIR") {
+        return PathBuf::from(dir);
+    }
+    // apps can't write to Android's temp directory
+    #[cfg(target_os = "android")]
+    if let Some(dir) = crate::android::data_dir() {
+        return dir.join("pipeline_cache");
+    }
+    std::env::temp_dir().join("learn_wgpu").join("pipeline_cache")
+}
+
+// Only Vulkan exposes driver pipeline caches today
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & wgpu::Features::PIPELINE_CACHE
+}
+
+// Driver pipeline cache persisted to disk per adapter, with an in-memory
+// layer on top so identical descriptors share one pipeline object
+pub struct PipelineCache {
+    cache: Option<Arc<wgpu::PipelineCache>>,
+    // None when the backend has no cache key, e.g. GL or WebGPU
+    path: Option<PathBuf>,
+    render_pipelines: HashMap<u64, Arc<wgpu::RenderPipeline>>,
+    compute_pipelines: HashMap<u64, Arc<wgpu::ComputePipeline>>,
+}
+
+impl PipelineCache {
+    pub fn new(device: &wgpu::Device, adapter_info: &wgpu::AdapterInfo, dir: &Path) -> Self {
+        let path = wgpu::util::pipeline_cache_key(adapter_info).map(|key| dir.join(key));
+        let cache = match &path {
+            Some(path) if device.features().contains(wgpu::Features::PIPELINE_CACHE) => {
+                let data = std::fs::read(path).ok();
+                log::info!(
+                    "Pipeline cache {} ({})",
+                    path.display(),
+                    if data.is_some() { "loaded" } else { "new" }
+                );
+                // SAFETY: the data was written by `save` from a pipeline cache
+                // created on an adapter with the same key, and `fallback`
+                // makes wgpu start empty if the driver still rejects it
+                Some(Arc::new(unsafe {
+                    device.create_pipeline_cache(&wgpu::PipelineCacheDescriptor {
+                        label: Some("Pipeline Cache"),
+                        data: data.as_deref(),
+                        fallback: true,
+                    })
+                }))
+            }
+            _ => None,
+        };
+
+        Self {
+            cache,
+            path,
+            render_pipelines: HashMap::new(),
+            compute_pipelines: HashMap::new(),
+        }
+    }
+
+    // The driver cache, for pipelines created outside of this type
+    pub fn cache(&self) -> Option<&wgpu::PipelineCache> {
+        self.cache.as_deref()
+    }
+
+    // Shared handle to the driver cache, e.g. for the background compiler
+    pub fn shared_cache(&self) -> Option<Arc<wgpu::PipelineCache>> {
+        self.cache.clone()
+    }
+
+    // Returns the pipeline built from an identical descriptor earlier, or
+    // builds it through the driver cache. `desc.cache` is ignored.
+    pub fn render_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::RenderPipelineDescriptor,
+    ) -> Arc<wgpu::RenderPipeline> {
+        let key = render_pipeline_key(desc);
+        if let Some(pipeline) = self.render_pipelines.get(&key) {
+            return pipeline.clone();
+        }
+        let pipeline = Arc::new(device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            cache: self.cache.as_deref(),
+            ..desc.clone()
+        }));
+        self.render_pipelines.insert(key, pipeline.clone());
+        pipeline
+    }
+
+    pub fn compute_pipeline(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &wgpu::ComputePipelineDescriptor,
+    ) -> Arc<wgpu::ComputePipeline> {
+        let key = compute_pipeline_key(desc);
+        if let Some(pipeline) = self.compute_pipelines.get(&key) {
+            return pipeline.clone();
+        }
+        let pipeline = Arc::new(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            cache: self.cache.as_deref(),
+            ..desc.clone()
+        }));
+        self.compute_pipelines.insert(key, pipeline.clone());
+        pipeline
+    }
+
+    pub fn len(&self) -> usize {
+        self.render_pipelines.len() + self.compute_pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Writes the driver cache blob, through a temporary file so a crash
+    // mid-write never leaves a truncated cache behind
+    pub fn save(&self) -> std::io::Result<()> {
+        let (Some(cache), Some(path)) = (&self.cache, &self.path) else {
+            return Ok(());
+        };
+        let Some(data) = cache.get_data() else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temp = path.with_extension("tmp");
+        std::fs::write(&temp, &data)?;
+        std::fs::rename(&temp, path)?;
+        log::info!("Saved {} bytes of pipeline cache to {}", data.len(), path.display());
+        Ok(())
+    }
+}
+
+fn hash_compilation_options(options: &wgpu::PipelineCompilationOptions, state: &mut DefaultHasher) {
+    // HashMap iteration order isn't stable, sort before hashing
+    let mut constants: Vec<_> = options.constants.iter().collect();
+    constants.sort_by(|a, b| a.0.cmp(b.0));
+    for (name, value) in constants {
+        name.hash(state);
+        value.to_bits().hash(state);
+    }
+    options.zero_initialize_workgroup_memory.hash(state);
+    options.vertex_pulling_transform.hash(state);
+}
+
+// Shader modules and layouts are identified by their wgpu ids, so two
+// descriptors only match when they use the very same objects
+fn render_pipeline_key(desc: &wgpu::RenderPipelineDescriptor) -> u64 {
+    let mut state = DefaultHasher::new();
+    desc.label.hash(&mut state);
+    desc.layout.map(|layout| layout.global_id()).hash(&mut state);
+
+    desc.vertex.module.global_id().hash(&mut state);
+    desc.vertex.entry_point.hash(&mut state);
+    desc.vertex.buffers.hash(&mut state);
+    hash_compilation_options(&desc.vertex.compilation_options, &mut state);
+
+    if let Some(fragment) = &desc.fragment {
+        fragment.module.global_id().hash(&mut state);
+        fragment.entry_point.hash(&mut state);
+        fragment.targets.hash(&mut state);
+        hash_compilation_options(&fragment.compilation_options, &mut state);
+    }
+
+    desc.primitive.hash(&mut state);
+    desc.depth_stencil.hash(&mut state);
+    desc.multisample.hash(&mut state);
+    desc.multiview.hash(&mut state);
+    state.finish()
+}
+
+fn compute_pipeline_key(desc: &wgpu::ComputePipelineDescriptor) -> u64 {
+    let mut state = DefaultHasher::new();
+    desc.label.hash(&mut state);
+    desc.layout.map(|layout| layout.global_id()).hash(&mut state);
+    desc.module.global_id().hash(&mut state);
+    desc.entry_point.hash(&mut state);
+    hash_compilation_options(&desc.compilation_options, &mut state);
+    state.finish()
+}
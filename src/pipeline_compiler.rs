@@ -0,0 +1,111 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+type Job<P> = Box<dyn FnOnce(&wgpu::Device, Option<&wgpu::PipelineCache>) -> P + Send>;
+
+enum Slot<P> {
+    Compiling,
+    Ready(Arc<P>),
+}
+
+// Builds pipelines on a worker thread so a new shader permutation never
+// stalls the frame that first asks for it. Until a pipeline is ready,
+// `get` returns None and the caller draws with a placeholder or skips.
+pub struct PipelineCompiler<K, P = wgpu::RenderPipeline> {
+    // dropped first on shutdown so the worker's loop ends
+    jobs: Option<mpsc::Sender<(K, Job<P>)>>,
+    results: mpsc::Receiver<(K, P)>,
+    slots: HashMap<K, Slot<P>>,
+    worker: Option<thread::JoinHandle<()>>,
+}
+
+impl<K, P> PipelineCompiler<K, P>
+where
+    K: Hash + Eq + Clone + Send + 'static,
+    P: Send + 'static,
+{
+    pub fn new(device: Arc<wgpu::Device>, cache: Option<Arc<wgpu::PipelineCache>>) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<(K, Job<P>)>();
+        let (result_sender, results) = mpsc::channel();
+
+        let worker = thread::Builder::new()
+            .name("pipeline compiler".into())
+            .spawn(move || {
+                for (key, build) in job_receiver {
+                    let pipeline = build(&device, cache.as_deref());
+                    if result_sender.send((key, pipeline)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn pipeline compiler thread");
+
+        Self {
+            jobs: Some(jobs),
+            results,
+            slots: HashMap::new(),
+            worker: Some(worker),
+        }
+    }
+
+    // Queues `build` unless `key` is already compiling or compiled.
+    // Validation errors are logged through the device's uncaptured error
+    // handler, or caught by whatever error scope the main thread has open,
+    // see `error_scope`.
+    pub fn request<F>(&mut self, key: K, build: F)
+    where
+        F: FnOnce(&wgpu::Device, Option<&wgpu::PipelineCache>) -> P + Send + 'static,
+    {
+        if self.slots.contains_key(&key) {
+            return;
+        }
+        let Some(jobs) = &self.jobs else {
+            return;
+        };
+        if jobs.send((key.clone(), Box::new(build))).is_ok() {
+            self.slots.insert(key, Slot::Compiling);
+        }
+    }
+
+    // Picks up finished pipelines, call once per frame.
+    // Returns the keys that became ready.
+    pub fn poll(&mut self) -> Vec<K> {
+        let mut ready = Vec::new();
+        while let Ok((key, pipeline)) = self.results.try_recv() {
+            self.slots.insert(key.clone(), Slot::Ready(Arc::new(pipeline)));
+            ready.push(key);
+        }
+        ready
+    }
+
+    pub fn get(&self, key: &K) -> Option<&Arc<P>> {
+        match self.slots.get(key) {
+            Some(Slot::Ready(pipeline)) => Some(pipeline),
+            _ => None,
+        }
+    }
+
+    pub fn is_compiling(&self, key: &K) -> bool {
+        matches!(self.slots.get(key), Some(Slot::Compiling))
+    }
+
+    // Number of requested pipelines that aren't ready yet
+    pub fn pending(&self) -> usize {
+        self.slots
+            .values()
+            .filter(|slot| matches!(slot, Slot::Compiling))
+            .count()
+    }
+}
+
+impl<K, P> Drop for PipelineCompiler<K, P> {
+    fn drop(&mut self) {
+        // finish whatever is in flight so the device outlives the worker's use of it
+        self.jobs = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::time::Duration;
+
+use winit::event::Event;
+
+use crate::pointer::PointerEvent;
+use crate::State;
+
+/// What a plugin draws with, once the frame's 2D overlay is recorded and
+/// before it's gamma corrected and presented
+pub struct RenderContext<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+    pub encoder: &'a mut wgpu::CommandEncoder,
+    // the finished frame, to load and draw over
+    pub view: &'a wgpu::TextureView,
+    pub format: wgpu::TextureFormat,
+    pub size: winit::dpi::PhysicalSize<u32>,
+}
+
+/// Work layered on top of the app without changing `run`: a UI, input
+/// recording, networking. Plugins are called in the order they were added,
+/// each before the app's own handling. Added with `State::add_plugin` or
+/// passed to `run_with_plugins`.
+pub trait Plugin {
+    // Once, when it's added, to create its GPU resources
+    fn on_attach(&mut self, _state: &mut State) {}
+
+    // Every event the event loop gets. True for a window event the plugin
+    // used, like a click on its UI, keeps it from the plugins after it and
+    // the app; other events can only be observed.
+    fn on_event(&mut self, _state: &mut State, _event: &Event<()>) -> bool {
+        false
+    }
+
+    // Mouse and touch input as pointer events, after every plugin's
+    // `on_event` has seen the window event they came from. True for one the
+    // plugin used keeps it from the plugins after it, and that window event
+    // from the app, so a tap on a button doesn't pick what's behind it.
+    fn on_pointer(&mut self, _state: &mut State, _event: &PointerEvent) -> bool {
+        false
+    }
+
+    // Each frame before the app's update, so text and debug lines queued
+    // here are drawn this frame
+    fn on_update(&mut self, _state: &mut State, _dt: Duration) {}
+
+    fn on_render(&mut self, _context: &mut RenderContext) {}
+}
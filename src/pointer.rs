@@ -0,0 +1,212 @@
+use std::time::{Duration, Instant};
+
+use winit::dpi::PhysicalPosition;
+use winit::event::{ElementState, MouseButton, TouchPhase, WindowEvent};
+
+// Longest a press can be held, and farthest it can wander in logical
+// pixels, and still be a tap
+const TAP_TIME: Duration = Duration::from_millis(300);
+const TAP_SLOP: f64 = 10.0;
+
+/// The mouse, or a finger by winit's touch ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PointerId {
+    Mouse,
+    Touch(u64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerPhase {
+    Down,
+    Moved,
+    Up,
+    // a finger the system took over, like for a gesture of its own
+    Cancelled,
+    // right after the Up of a press that was quick and stayed put
+    Tap,
+}
+
+/// Mouse and touch input as one kind of event, for UI and picking that
+/// shouldn't care which it was. Fingers press like the left button.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PointerEvent {
+    pub id: PointerId,
+    pub phase: PointerPhase,
+    // in physical pixels from the window's top left
+    pub position: PhysicalPosition<f64>,
+    // the button pressed or released, and held while moving; None for the
+    // mouse moving with nothing held
+    pub button: Option<MouseButton>,
+}
+
+impl PointerEvent {
+    pub fn is_touch(&self) -> bool {
+        matches!(self.id, PointerId::Touch(_))
+    }
+}
+
+#[derive(Debug)]
+struct Press {
+    id: PointerId,
+    button: MouseButton,
+    start: PhysicalPosition<f64>,
+    started: Instant,
+    // whether it ever left the tap slop
+    moved: bool,
+}
+
+/// Where the mouse and fingers are and what they're pressing, turning
+/// window events into `PointerEvent`s
+#[derive(Debug, Default)]
+pub struct Pointers {
+    // None once the cursor leaves the window
+    mouse: Option<PhysicalPosition<f64>>,
+    fingers: Vec<(u64, PhysicalPosition<f64>)>,
+    presses: Vec<Press>,
+}
+
+impl Pointers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn mouse(&self) -> Option<PhysicalPosition<f64>> {
+        self.mouse
+    }
+
+    pub fn position(&self, id: PointerId) -> Option<PhysicalPosition<f64>> {
+        match id {
+            PointerId::Mouse => self.mouse,
+            PointerId::Touch(touch) => self.fingers.iter().find(|(id, _)| *id == touch).map(|(_, position)| *position),
+        }
+    }
+
+    pub fn is_down(&self, id: PointerId) -> bool {
+        self.presses.iter().any(|press| press.id == id)
+    }
+
+    // Fingers on the screen, in the order they went down
+    pub fn touches(&self) -> impl Iterator<Item = (PointerId, PhysicalPosition<f64>)> + '_ {
+        self.fingers.iter().map(|(id, position)| (PointerId::Touch(*id), *position))
+    }
+
+    // The pointer events a window event makes, usually one. `scale_factor`
+    // is the window's, for the tap slop.
+    pub fn process(&mut self, event: &WindowEvent, scale_factor: f64) -> Vec<PointerEvent> {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                self.mouse = Some(*position);
+                let button = self.presses.iter().find(|press| press.id == PointerId::Mouse).map(|press| press.button);
+                vec![self.moved(PointerId::Mouse, *position, button, scale_factor)]
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.mouse = None;
+                Vec::new()
+            }
+            WindowEvent::MouseInput { state, button, .. } => {
+                let Some(position) = self.mouse else {
+                    return Vec::new();
+                };
+                match state {
+                    ElementState::Pressed => vec![self.down(PointerId::Mouse, *button, position)],
+                    ElementState::Released => self.up(PointerId::Mouse, *button, position),
+                }
+            }
+            WindowEvent::Touch(touch) => {
+                let id = PointerId::Touch(touch.id);
+                let position = touch.location;
+                let index = self.fingers.iter().position(|(finger, _)| *finger == touch.id);
+                match (touch.phase, index) {
+                    (TouchPhase::Started, None) => {
+                        self.fingers.push((touch.id, position));
+                        vec![self.down(id, MouseButton::Left, position)]
+                    }
+                    (TouchPhase::Moved, Some(index)) => {
+                        self.fingers[index].1 = position;
+                        vec![self.moved(id, position, Some(MouseButton::Left), scale_factor)]
+                    }
+                    (TouchPhase::Ended, Some(index)) => {
+                        self.fingers.remove(index);
+                        self.up(id, MouseButton::Left, position)
+                    }
+                    (TouchPhase::Cancelled, Some(index)) => {
+                        self.fingers.remove(index);
+                        self.presses.retain(|press| press.id != id);
+                        vec![PointerEvent {
+                            id,
+                            phase: PointerPhase::Cancelled,
+                            position,
+                            button: Some(MouseButton::Left),
+                        }]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            // a release outside the window never comes
+            WindowEvent::Focused(false) => {
+                self.presses.clear();
+                self.fingers.clear();
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn down(&mut self, id: PointerId, button: MouseButton, position: PhysicalPosition<f64>) -> PointerEvent {
+        self.presses.push(Press {
+            id,
+            button,
+            start: position,
+            started: Instant::now(),
+            moved: false,
+        });
+        PointerEvent {
+            id,
+            phase: PointerPhase::Down,
+            position,
+            button: Some(button),
+        }
+    }
+
+    fn moved(
+        &mut self,
+        id: PointerId,
+        position: PhysicalPosition<f64>,
+        button: Option<MouseButton>,
+        scale_factor: f64,
+    ) -> PointerEvent {
+        for press in self.presses.iter_mut().filter(|press| press.id == id) {
+            let (dx, dy) = (position.x - press.start.x, position.y - press.start.y);
+            press.moved |= dx.hypot(dy) > TAP_SLOP * scale_factor;
+        }
+        PointerEvent {
+            id,
+            phase: PointerPhase::Moved,
+            position,
+            button,
+        }
+    }
+
+    fn up(&mut self, id: PointerId, button: MouseButton, position: PhysicalPosition<f64>) -> Vec<PointerEvent> {
+        let up = PointerEvent {
+            id,
+            phase: PointerPhase::Up,
+            position,
+            button: Some(button),
+        };
+        let Some(index) = self.presses.iter().position(|press| press.id == id && press.button == button) else {
+            return vec![up];
+        };
+        let press = self.presses.remove(index);
+        if press.moved || press.started.elapsed() > TAP_TIME {
+            return vec![up];
+        }
+        vec![
+            up,
+            PointerEvent {
+                phase: PointerPhase::Tap,
+                ..up
+            },
+        ]
+    }
+}
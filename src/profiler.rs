@@ -0,0 +1,307 @@
+// CPU scopes are `profiling::scope!` calls, which compile to nothing unless
+// the `tracy` or `puffin` feature picks a backend. GPU timings come from
+// timestamp queries through wgpu-profiler with either feature, and show up
+// in the same profiler: Tracy has GPU timelines of its own, puffin gets them
+// as a "GPU" thread lined up with the CPU frame that submitted them.
+// `GpuProfiler::begin` scopes are debug groups as well, with or without a
+// feature, for RenderDoc and Xcode captures.
+
+#[cfg(all(feature = "tracy", feature = "puffin"))]
+compile_error!("the `tracy` and `puffin` features can't be enabled together");
+
+// Where `puffin_viewer` connects to
+#[cfg(feature = "puffin")]
+pub const PUFFIN_ADDR: &str = "127.0.0.1:8585";
+
+// The timestamp queries wgpu-profiler can use, only asked for when profiling
+const TIMER_FEATURES: wgpu::Features = wgpu::Features::TIMESTAMP_QUERY
+    .union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_ENCODERS)
+    .union(wgpu::Features::TIMESTAMP_QUERY_INSIDE_PASSES);
+
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    if cfg!(any(feature = "tracy", feature = "puffin")) {
+        adapter.features() & TIMER_FEATURES
+    } else {
+        wgpu::Features::empty()
+    }
+}
+
+/// Keeps the profiler backend running, start it before the first scope.
+pub struct Session {
+    #[cfg(feature = "puffin")]
+    _server: Option<puffin_http::Server>,
+}
+
+pub fn start() -> Session {
+    #[cfg(feature = "tracy")]
+    {
+        profiling::tracy_client::Client::start();
+        log::info!("Profiling to Tracy");
+    }
+    Session {
+        #[cfg(feature = "puffin")]
+        _server: start_puffin(),
+    }
+}
+
+#[cfg(feature = "puffin")]
+fn start_puffin() -> Option<puffin_http::Server> {
+    profiling::puffin::set_scopes_on(true);
+    match puffin_http::Server::new(PUFFIN_ADDR) {
+        Ok(server) => {
+            log::info!("Profiling to puffin, run `puffin_viewer --url {}`", PUFFIN_ADDR);
+            Some(server)
+        }
+        Err(e) => {
+            log::warn!("Failed to start the puffin server: {:#}", e);
+            None
+        }
+    }
+}
+
+pub use timestamps::{GpuProfiler, GpuScope};
+
+#[cfg(any(feature = "tracy", feature = "puffin"))]
+mod timestamps {
+    /// A GPU timer started by `GpuProfiler::begin` or `begin_pass`, to hand
+    /// back to `end`.
+    pub struct GpuScope {
+        query: wgpu_profiler::GpuProfilerQuery,
+    }
+
+    impl GpuScope {
+        // For the pass a `begin_pass` scope times, None without timestamps
+        pub fn render_pass_timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+            self.query.render_pass_timestamp_writes()
+        }
+    }
+
+    /// GPU timestamps around the work of a frame, on devices with timestamp
+    /// queries. Results come back a frame or more after the frame they time.
+    pub struct GpuProfiler {
+        inner: wgpu_profiler::GpuProfiler,
+        #[cfg(feature = "puffin")]
+        puffin: super::puffin_gpu::PuffinGpu,
+        // smoothed like the CPU frame time readout, in seconds
+        frame_time: Option<f32>,
+    }
+
+    impl GpuProfiler {
+        #[cfg_attr(feature = "puffin", allow(unused_variables))]
+        pub fn new(backend: wgpu::Backend, device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+            let timestamps = device.features().contains(wgpu::Features::TIMESTAMP_QUERY);
+            if !timestamps {
+                log::warn!("This device has no timestamp queries, profiling the CPU only");
+            }
+            let settings = wgpu_profiler::GpuProfilerSettings {
+                enable_timer_queries: timestamps,
+                ..Default::default()
+            };
+            #[cfg(feature = "tracy")]
+            if timestamps {
+                match wgpu_profiler::GpuProfiler::new_with_tracy_client(settings.clone(), backend, device, queue) {
+                    Ok(inner) => return Self::with(inner),
+                    Err(e) => log::warn!("No GPU timeline in Tracy: {}", e),
+                }
+            }
+            Self::with(wgpu_profiler::GpuProfiler::new(settings).unwrap())
+        }
+
+        fn with(inner: wgpu_profiler::GpuProfiler) -> Self {
+            Self {
+                inner,
+                #[cfg(feature = "puffin")]
+                puffin: Default::default(),
+                frame_time: None,
+            }
+        }
+
+        // Times the commands recorded into `encoder` until `end`, which
+        // needs TIMESTAMP_QUERY_INSIDE_ENCODERS, in a debug group of the
+        // same name either way
+        pub fn begin(&self, label: &str, encoder: &mut wgpu::CommandEncoder, device: &wgpu::Device) -> GpuScope {
+            GpuScope {
+                query: self.inner.begin_query(label, encoder, device),
+            }
+        }
+
+        // Times a single pass through its `timestamp_writes`, which only
+        // needs TIMESTAMP_QUERY. `end` it after the pass.
+        pub fn begin_pass(&self, label: &str, encoder: &mut wgpu::CommandEncoder, device: &wgpu::Device) -> GpuScope {
+            GpuScope {
+                query: self.inner.begin_pass_query(label, encoder, device),
+            }
+        }
+
+        pub fn end(&self, encoder: &mut wgpu::CommandEncoder, scope: GpuScope) {
+            self.inner.end_query(encoder, scope.query);
+        }
+
+        // Copies the frame's timestamps out, the last thing before `finish`
+        pub fn resolve(&mut self, encoder: &mut wgpu::CommandEncoder) {
+            self.inner.resolve_queries(encoder);
+        }
+
+        // After submitting the frame: collects whichever earlier frame has
+        // its timestamps back and sends them to the profiler
+        pub fn end_frame(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+            if let Err(e) = self.inner.end_frame() {
+                log::warn!("GPU profiler frame: {}", e);
+                return;
+            }
+            #[cfg(feature = "puffin")]
+            self.puffin.submitted();
+
+            device.poll(wgpu::Maintain::Poll);
+            // Tracy gets the results from wgpu-profiler itself
+            let Some(results) = self.inner.process_finished_frame(queue.get_timestamp_period()) else {
+                return;
+            };
+            #[cfg(feature = "puffin")]
+            self.puffin.report(&results);
+
+            let span = results
+                .iter()
+                .filter_map(|result| result.time.clone())
+                .reduce(|a, b| a.start.min(b.start)..a.end.max(b.end));
+            if let Some(span) = span {
+                let time = (span.end - span.start) as f32;
+                let smoothed = self.frame_time.map_or(time, |frame_time| frame_time + (time - frame_time) * 0.05);
+                self.frame_time = Some(smoothed);
+            }
+        }
+
+        // From the first timestamp of a frame to its last, in seconds
+        pub fn frame_time(&self) -> Option<f32> {
+            self.frame_time
+        }
+    }
+}
+
+// Without a profiling feature scopes are only debug groups, so captures
+// show the same stages
+#[cfg(not(any(feature = "tracy", feature = "puffin")))]
+mod timestamps {
+    pub enum GpuScope {
+        Group,
+        Pass,
+    }
+
+    impl GpuScope {
+        pub fn render_pass_timestamp_writes(&self) -> Option<wgpu::RenderPassTimestampWrites<'_>> {
+            None
+        }
+    }
+
+    pub struct GpuProfiler;
+
+    impl GpuProfiler {
+        pub fn new(_backend: wgpu::Backend, _device: &wgpu::Device, _queue: &wgpu::Queue) -> Self {
+            Self
+        }
+
+        pub fn begin(&self, label: &str, encoder: &mut wgpu::CommandEncoder, _device: &wgpu::Device) -> GpuScope {
+            encoder.push_debug_group(label);
+            GpuScope::Group
+        }
+
+        // passes have labels of their own
+        pub fn begin_pass(&self, _label: &str, _encoder: &mut wgpu::CommandEncoder, _device: &wgpu::Device) -> GpuScope {
+            GpuScope::Pass
+        }
+
+        pub fn end(&self, encoder: &mut wgpu::CommandEncoder, scope: GpuScope) {
+            if let GpuScope::Group = scope {
+                encoder.pop_debug_group();
+            }
+        }
+
+        pub fn resolve(&mut self, _encoder: &mut wgpu::CommandEncoder) {}
+
+        pub fn end_frame(&mut self, _device: &wgpu::Device, _queue: &wgpu::Queue) {}
+
+        pub fn frame_time(&self) -> Option<f32> {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "puffin")]
+mod puffin_gpu {
+    use std::collections::{HashMap, VecDeque};
+
+    use profiling::puffin;
+    use wgpu_profiler::GpuTimerQueryResult;
+
+    // Same as `GpuProfilerSettings::default`
+    const MAX_PENDING_FRAMES: usize = 3;
+
+    // The GPU clock has no common origin with the CPU's, so each frame's
+    // scopes start at the moment it was submitted, the earliest the GPU
+    // could have started on it
+    #[derive(Default)]
+    pub struct PuffinGpu {
+        scope_ids: HashMap<String, puffin::ScopeId>,
+        // CPU time of every frame whose timestamps aren't back yet
+        pending: VecDeque<puffin::NanoSecond>,
+    }
+
+    impl PuffinGpu {
+        pub fn submitted(&mut self) {
+            // wgpu-profiler drops the newest pending frame when full
+            if self.pending.len() == MAX_PENDING_FRAMES {
+                self.pending.pop_back();
+            }
+            self.pending.push_back(puffin::now_ns());
+        }
+
+        pub fn report(&mut self, results: &[GpuTimerQueryResult]) {
+            let Some(submitted) = self.pending.pop_front() else {
+                return;
+            };
+            let Some(gpu_start) = results
+                .iter()
+                .filter_map(|result| result.time.as_ref())
+                .map(|time| time.start)
+                .reduce(f64::min)
+            else {
+                return;
+            };
+            let to_ns = |seconds: f64| submitted + ((seconds - gpu_start) * 1e9) as puffin::NanoSecond;
+
+            let mut info = puffin::StreamInfo::default();
+            self.write_scopes(&mut info, results, &to_ns, 1);
+            let thread = puffin::ThreadInfo {
+                start_time_ns: None,
+                name: "GPU".to_owned(),
+            };
+            puffin::GlobalProfiler::lock().report_user_scopes(thread, &info.as_stream_into_ref());
+        }
+
+        fn write_scopes(
+            &mut self,
+            info: &mut puffin::StreamInfo,
+            results: &[GpuTimerQueryResult],
+            to_ns: &impl Fn(f64) -> puffin::NanoSecond,
+            depth: usize,
+        ) {
+            for result in results {
+                let Some(time) = &result.time else {
+                    continue;
+                };
+                let id = *self.scope_ids.entry(result.label.clone()).or_insert_with(|| {
+                    let details = puffin::ScopeDetails::from_scope_name(result.label.clone());
+                    puffin::GlobalProfiler::lock().register_user_scopes(&[details])[0]
+                });
+                let (start, stop) = (to_ns(time.start), to_ns(time.end));
+                let (offset, _) = info.stream.begin_scope(|| start, id, "");
+                self.write_scopes(info, &result.nested_queries, to_ns, depth + 1);
+                info.stream.end_scope(offset, stop);
+
+                info.num_scopes += 1;
+                info.depth = info.depth.max(depth);
+                info.range_ns = (info.range_ns.0.min(start), info.range_ns.1.max(stop));
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use std::ops::Range;
+use std::sync::mpsc;
+
+use anyhow::*;
+
+// How many frames' readbacks a `ReadbackRing` has on their way at once, one
+// for each frame in flight. Results usually arrive one or two frames after
+// their copy.
+pub use crate::frames::FRAMES_IN_FLIGHT;
+
+// Rows of a texture copy, padded in the buffer to COPY_BYTES_PER_ROW_ALIGNMENT
+#[derive(Debug, Clone, Copy)]
+struct Rows {
+    unpadded: u32,
+    padded: u32,
+    // block rows of each layer, and of every layer together
+    per_image: u32,
+    count: u32,
+}
+
+impl Rows {
+    // Compressed formats are copied as whole blocks; depth-stencil formats
+    // need `aspect` set to one of the two
+    fn new(format: wgpu::TextureFormat, aspect: wgpu::TextureAspect, extent: wgpu::Extent3d) -> Result<Self> {
+        let block_size = format
+            .block_copy_size(Some(aspect))
+            .with_context(|| format!("{:?} textures can't be copied as {:?}", format, aspect))?;
+        let (block_width, block_height) = format.block_dimensions();
+        let per_image = extent.height.div_ceil(block_height);
+        let unpadded = extent.width.div_ceil(block_width) * block_size;
+        Ok(Self {
+            unpadded,
+            padded: wgpu::util::align_to(unpadded, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT),
+            per_image,
+            count: per_image * extent.depth_or_array_layers,
+        })
+    }
+
+    fn size(&self) -> wgpu::BufferAddress {
+        self.padded as wgpu::BufferAddress * self.count as wgpu::BufferAddress
+    }
+
+    fn layout(&self) -> wgpu::ImageDataLayout {
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: Some(self.padded),
+            rows_per_image: Some(self.per_image),
+        }
+    }
+}
+
+/// A copy into a mappable buffer, recorded into an encoder. `map` it once
+/// that encoder has been submitted.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    // None for buffer copies, which have no padding
+    rows: Option<Rows>,
+}
+
+impl Readback {
+    // `source` needs COPY_SRC; the range has to start and end on multiples
+    // of `wgpu::COPY_BUFFER_ALIGNMENT`
+    pub fn buffer(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        range: Range<wgpu::BufferAddress>,
+    ) -> Self {
+        let size = range.end - range.start;
+        let buffer = create_buffer(device, "Readback Buffer", size);
+        encoder.copy_buffer_to_buffer(source, range.start, &buffer, 0, size);
+        Self { buffer, rows: None }
+    }
+
+    // `extent` of one mip level of `source.texture`, which needs COPY_SRC.
+    // Compressed formats are copied as whole blocks; depth-stencil formats
+    // need `source.aspect` set to one of the two.
+    pub fn texture(
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        source: wgpu::ImageCopyTexture,
+        extent: wgpu::Extent3d,
+    ) -> Result<Self> {
+        let rows = Rows::new(source.texture.format(), source.aspect, extent)?;
+        let buffer = create_buffer(device, "Readback Buffer", rows.size());
+        encoder.copy_texture_to_buffer(
+            source,
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: rows.layout(),
+            },
+            extent,
+        );
+        Ok(Self { buffer, rows: Some(rows) })
+    }
+
+    // Starts mapping; call after submitting the encoder the copy is in
+    pub fn map(self) -> PendingReadback {
+        PendingReadback {
+            receiver: map_async(&self.buffer),
+            buffer: self.buffer,
+            rows: self.rows,
+        }
+    }
+}
+
+type MapReceiver = mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>;
+
+fn create_buffer(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+        mapped_at_creation: false,
+    })
+}
+
+fn map_async(buffer: &wgpu::Buffer) -> MapReceiver {
+    let (sender, receiver) = mpsc::channel();
+    buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let _ = sender.send(result);
+    });
+    receiver
+}
+
+// The mapped buffer's data, then unmaps it. Texture rows come tightly
+// packed, without the copy's padding.
+fn read_mapped(
+    buffer: &wgpu::Buffer,
+    rows: Option<Rows>,
+    result: Result<(), wgpu::BufferAsyncError>,
+) -> Result<Vec<u8>> {
+    result.context("Failed to map the readback buffer")?;
+    let data = {
+        let mapped = buffer.slice(..).get_mapped_range();
+        match rows {
+            None => mapped.to_vec(),
+            Some(rows) => mapped
+                .chunks_exact(rows.padded as usize)
+                .flat_map(|row| &row[..rows.unpadded as usize])
+                .copied()
+                .collect(),
+        }
+    };
+    buffer.unmap();
+    Ok(data)
+}
+
+/// A readback on its way back from the GPU.
+pub struct PendingReadback {
+    buffer: wgpu::Buffer,
+    rows: Option<Rows>,
+    receiver: MapReceiver,
+}
+
+impl PendingReadback {
+    // Non-blocking, for checking once a frame: Some once the data is back.
+    // Texture rows come tightly packed, without the copy's padding.
+    pub fn try_read(&mut self, device: &wgpu::Device) -> Option<Result<Vec<u8>>> {
+        device.poll(wgpu::Maintain::Poll);
+        match self.receiver.try_recv() {
+            Result::Ok(result) => Some(self.finish(result)),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(anyhow!("The readback was dropped before mapping"))),
+        }
+    }
+
+    // Blocks until the data is back. The web can't block, so there only
+    // `try_read` works, from frame to frame.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait(self, device: &wgpu::Device) -> Result<Vec<u8>> {
+        device.poll(wgpu::Maintain::Wait);
+        let result = self.receiver.recv().context("The readback was dropped before mapping")?;
+        self.finish(result)
+    }
+
+    fn finish(&self, result: Result<(), wgpu::BufferAsyncError>) -> Result<Vec<u8>> {
+        read_mapped(&self.buffer, self.rows, result)
+    }
+}
+
+enum Slot {
+    Free,
+    // the copy is recorded, waiting for submit; the number is the copy's,
+    // counted from the first
+    Recorded(u64),
+    Mapping(u64, MapReceiver),
+}
+
+/// The same small copy made every frame into the next of a few reused
+/// buffers, for what's read back all the time: GPU picks, the adapted
+/// exposure, culling stats. Nothing waits on the GPU, the data turns up
+/// when it's done with the frame that copied it; a frame whose buffer is
+/// still on its way doesn't get a copy.
+pub struct ReadbackRing {
+    buffers: Vec<wgpu::Buffer>,
+    slots: Vec<Slot>,
+    // where the next copy goes
+    next: usize,
+    copies: u64,
+    // None for buffer copies
+    rows: Option<Rows>,
+    extent: wgpu::Extent3d,
+}
+
+impl ReadbackRing {
+    // `size` bytes a frame, a multiple of `wgpu::COPY_BUFFER_ALIGNMENT`,
+    // `frames` of them on their way at once
+    pub fn buffer(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress, frames: usize) -> Self {
+        Self::new(device, label, size, None, wgpu::Extent3d::default(), frames)
+    }
+
+    // `extent` texels of a mip level a frame, from textures of `format`
+    pub fn texture(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        aspect: wgpu::TextureAspect,
+        extent: wgpu::Extent3d,
+        frames: usize,
+    ) -> Result<Self> {
+        let rows = Rows::new(format, aspect, extent)?;
+        Ok(Self::new(device, label, rows.size(), Some(rows), extent, frames))
+    }
+
+    fn new(
+        device: &wgpu::Device,
+        label: &str,
+        size: wgpu::BufferAddress,
+        rows: Option<Rows>,
+        extent: wgpu::Extent3d,
+        frames: usize,
+    ) -> Self {
+        let frames = frames.max(1);
+        Self {
+            buffers: (0..frames).map(|_| create_buffer(device, label, size)).collect(),
+            slots: (0..frames).map(|_| Slot::Free).collect(),
+            next: 0,
+            copies: 0,
+            rows,
+            extent,
+        }
+    }
+
+    // Whether there's a buffer for this frame's copy
+    pub fn ready(&self) -> bool {
+        matches!(self.slots[self.next], Slot::Free)
+    }
+
+    // Records copying from `offset` in `source`, which needs COPY_SRC.
+    // False, with nothing recorded, if no buffer's free.
+    pub fn copy_buffer(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        source: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+    ) -> bool {
+        let Some(buffer) = self.take_slot() else {
+            return false;
+        };
+        encoder.copy_buffer_to_buffer(source, offset, buffer, 0, buffer.size());
+        true
+    }
+
+    // Records copying the ring's extent of `source.texture`, which needs
+    // COPY_SRC and the format the ring was made for
+    pub fn copy_texture(&mut self, encoder: &mut wgpu::CommandEncoder, source: wgpu::ImageCopyTexture) -> bool {
+        let (Some(rows), extent) = (self.rows, self.extent) else {
+            return false;
+        };
+        let Some(buffer) = self.take_slot() else {
+            return false;
+        };
+        let destination = wgpu::ImageCopyBuffer {
+            buffer,
+            layout: rows.layout(),
+        };
+        encoder.copy_texture_to_buffer(source, destination, extent);
+        true
+    }
+
+    fn take_slot(&mut self) -> Option<&wgpu::Buffer> {
+        if !self.ready() {
+            return None;
+        }
+        let index = self.next;
+        self.slots[index] = Slot::Recorded(self.copies);
+        self.copies += 1;
+        self.next = (index + 1) % self.slots.len();
+        Some(&self.buffers[index])
+    }
+
+    // Starts mapping what was recorded; call once the encoders with the
+    // copies have been submitted
+    pub fn map(&mut self) {
+        for (slot, buffer) in self.slots.iter_mut().zip(&self.buffers) {
+            if let Slot::Recorded(copy) = *slot {
+                *slot = Slot::Mapping(copy, map_async(buffer));
+            }
+        }
+    }
+
+    // Non-blocking, for checking once a frame: the newest copy that's come
+    // back since the last call, if any. Older ones that came back with it
+    // are dropped.
+    pub fn try_read(&mut self, device: &wgpu::Device) -> Option<Result<Vec<u8>>> {
+        device.poll(wgpu::Maintain::Poll);
+        let mut back = Vec::new();
+        for (index, slot) in self.slots.iter_mut().enumerate() {
+            let Slot::Mapping(copy, receiver) = slot else {
+                continue;
+            };
+            let result = match receiver.try_recv() {
+                Result::Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => continue,
+                // the mapping was abandoned, as when the device is lost
+                Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+            };
+            back.push((*copy, index, result));
+            *slot = Slot::Free;
+        }
+        back.sort_by_key(|&(copy, ..)| copy);
+        let (_, index, result) = back.pop()?;
+        for (_, older, _) in back.iter().filter(|(.., result)| result.is_ok()) {
+            self.buffers[*older].unmap();
+        }
+        Some(read_mapped(&self.buffers[index], self.rows, result))
+    }
+}
+
+// Blocking helpers for tools, tests and screenshots, each in its own
+// submission
+
+// All of `buffer`, which needs COPY_SRC
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Result<Vec<u8>> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    let readback = Readback::buffer(device, &mut encoder, buffer, 0..buffer.size());
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.map().wait(device)
+}
+
+// Mip level 0 of `texture` with every layer, rows tightly packed
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_texture(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) -> Result<Vec<u8>> {
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Readback Encoder"),
+    });
+    let readback = Readback::texture(device, &mut encoder, texture.as_image_copy(), texture.size())?;
+    queue.submit(std::iter::once(encoder.finish()));
+    readback.map().wait(device)
+}
+
+// Like `read_texture`, for 8-bit RGBA and BGRA textures
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_texture_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    texture: &wgpu::Texture,
+) -> Result<image::RgbaImage> {
+    let data = read_texture(device, queue, texture)?;
+    rgba_image(data, texture.width(), texture.height(), texture.format())
+}
+
+// Tightly packed texels as an image. sRGB formats keep their encoded
+// values, which is what image files expect.
+pub fn rgba_image(mut data: Vec<u8>, width: u32, height: u32, format: wgpu::TextureFormat) -> Result<image::RgbaImage> {
+    use wgpu::TextureFormat::*;
+
+    match format {
+        Rgba8Unorm | Rgba8UnormSrgb => {}
+        Bgra8Unorm | Bgra8UnormSrgb => data.chunks_exact_mut(4).for_each(|texel| texel.swap(0, 2)),
+        _ => bail!("Can't turn {:?} texels into an RGBA image", format),
+    }
+    // only the first layer of an array
+    data.truncate(width as usize * height as usize * 4);
+    image::RgbaImage::from_raw(width, height, data).context("Not enough texels for the image")
+}
@@ -0,0 +1,135 @@
+use bytemuck::Zeroable;
+use serde::{Deserialize, Serialize};
+
+use crate::ibl::{ENVIRONMENT_FORMAT, PREFILTERED_MIPS, PREFILTERED_SIZE};
+use crate::resources::{ResourceKind, Tracked};
+
+// how many probes the uniform has room for, as in reflection_probes.wgsl
+pub const MAX_PROBES: usize = 16;
+
+/// A point the scene's captured from for the reflections of what's around
+/// it, within `radius`. Captures are sampled as if everything in them was
+/// infinitely far away, so probes work best in the middle of open spaces
+/// and well away from what they reflect.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReflectionProbe {
+    pub position: [f32; 3],
+    // fading out towards it into overlapping probes or the environment
+    pub radius: f32,
+}
+
+// Matches `GpuProbe` in reflection_probes.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuProbe {
+    position: [f32; 3],
+    radius: f32,
+}
+
+// Matches `ProbeUniform` in reflection_probes.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ProbeUniform {
+    // of `probes` that have been baked
+    count: u32,
+    _padding: [f32; 3],
+    probes: [GpuProbe; MAX_PROBES],
+}
+
+// The scene's reflection probes as models sample them next to the camera:
+// their prefiltered radiance in six layers of an array texture each at
+// binding 8, where they are at binding 9. Arrays rather than cube arrays,
+// which GL ES doesn't have. Room for every probe is kept, so the bind
+// groups they're in stay valid, and the uniform only counts probes once
+// they're baked.
+pub struct ReflectionProbes {
+    probes: Vec<ReflectionProbe>,
+    texture: Tracked<wgpu::Texture>,
+    view: wgpu::TextureView,
+    buffer: wgpu::Buffer,
+}
+
+impl ReflectionProbes {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Reflection Probes"),
+            size: wgpu::Extent3d {
+                width: PREFILTERED_SIZE,
+                height: PREFILTERED_SIZE,
+                // and a spare, GL would otherwise take a square texture with
+                // a multiple of six layers for a cube array
+                depth_or_array_layers: 6 * MAX_PROBES as u32 + 1,
+            },
+            mip_level_count: PREFILTERED_MIPS,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: ENVIRONMENT_FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Reflection Probe Buffer"),
+            size: std::mem::size_of::<ProbeUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            probes: Vec::new(),
+            texture: Tracked::texture(ResourceKind::Texture, texture),
+            view,
+            buffer,
+        }
+    }
+
+    pub fn probes(&self) -> &[ReflectionProbe] {
+        &self.probes
+    }
+
+    // Leaves the old ones out until these are baked and `upload`ed. Any
+    // past MAX_PROBES are dropped.
+    pub fn set(&mut self, queue: &wgpu::Queue, mut probes: Vec<ReflectionProbe>) {
+        if probes.len() > MAX_PROBES {
+            log::warn!("{} reflection probes, only the first {MAX_PROBES} are used", probes.len());
+            probes.truncate(MAX_PROBES);
+        }
+        self.probes = probes;
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[ProbeUniform::zeroed()]));
+    }
+
+    // Once every probe has been baked with `Ibl::bake_probe`
+    pub fn upload(&self, queue: &wgpu::Queue) {
+        let mut uniform = ProbeUniform::zeroed();
+        uniform.count = self.probes.len() as u32;
+        for (gpu, probe) in uniform.probes.iter_mut().zip(&self.probes) {
+            *gpu = GpuProbe {
+                position: probe.position,
+                radius: probe.radius.max(1e-3),
+            };
+        }
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Probe `index`'s faces are layers 6 * index to 6 * index + 5, +x, -x, +y,
+    // -y, +z then -z as in the environment's cubemaps
+    pub(crate) fn texture(&self) -> &wgpu::Texture {
+        &self.texture
+    }
+
+    // Bindings 8 and 9 of the camera bind group, see `camera::layout_entries`
+    pub fn bind_group_entries(&self) -> [wgpu::BindGroupEntry<'_>; 2] {
+        [
+            wgpu::BindGroupEntry {
+                binding: 8,
+                resource: wgpu::BindingResource::TextureView(&self.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 9,
+                resource: self.buffer.as_entire_binding(),
+            },
+        ]
+    }
+}
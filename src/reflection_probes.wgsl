@@ -0,0 +1,69 @@
+// Reflection probes: the scene's prefiltered radiance around a few points,
+// baked by reflection_probes.rs, for the specular light of what's near them
+
+// Matches `GpuProbe` in reflection_probes.rs
+struct GpuProbe {
+    position: vec3<f32>,
+    radius: f32,
+};
+
+// Matches `ProbeUniform` in reflection_probes.rs, with MAX_PROBES
+struct ProbeUniform {
+    count: u32,
+    _padding0: f32,
+    _padding1: f32,
+    _padding2: f32,
+    probes: array<GpuProbe, 16>,
+};
+
+// six layers a probe, one for each face of its cube
+@group(0) @binding(8)
+var t_probes: texture_2d_array<f32>;
+@group(0) @binding(9)
+var<uniform> probes: ProbeUniform;
+
+// of its radius, how far from a probe it starts fading out
+const PROBE_FADE_START: f32 = 0.6;
+
+struct CubeFace {
+    index: u32,
+    uv: vec2<f32>,
+};
+
+// The face `direction` points through and where, the inverse of
+// `cube_face_direction` in cubemap.wgsl
+fn cube_face(direction: vec3<f32>) -> CubeFace {
+    let a = abs(direction);
+    var face: CubeFace;
+    var st: vec2<f32>;
+    if a.x >= a.y && a.x >= a.z {
+        face.index = select(1u, 0u, direction.x > 0.0);
+        st = vec2<f32>(-direction.z * sign(direction.x), -direction.y) / a.x;
+    } else if a.y >= a.z {
+        face.index = select(3u, 2u, direction.y > 0.0);
+        st = vec2<f32>(direction.x, direction.z * sign(direction.y)) / a.y;
+    } else {
+        face.index = select(5u, 4u, direction.z > 0.0);
+        st = vec2<f32>(direction.x * sign(direction.z), -direction.y) / a.z;
+    }
+    face.uv = st * 0.5 + 0.5;
+    return face;
+}
+
+// The probes' radiance towards `direction` at mip `lod`, each as much as
+// `position` is inside it, over `environment` where they don't add up to
+// all of it
+fn probe_radiance(s: sampler, position: vec3<f32>, direction: vec3<f32>, lod: f32, environment: vec3<f32>) -> vec3<f32> {
+    let face = cube_face(direction);
+    var radiance = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < probes.count; i++) {
+        let probe = probes.probes[i];
+        let w = 1.0 - smoothstep(PROBE_FADE_START, 1.0, distance(position, probe.position) / probe.radius);
+        if w > 0.0 {
+            radiance += w * textureSampleLevel(t_probes, s, face.uv, i * 6u + face.index, lod).rgb;
+            weight += w;
+        }
+    }
+    return (radiance + environment * max(1.0 - weight, 0.0)) / max(weight, 1.0);
+}
@@ -0,0 +1,84 @@
+/// Render bundles kept from frame to frame, each under a key for what it was
+/// recorded from, like the global IDs of its pipeline, bind groups and
+/// buffers. A bundle is only recorded again once its key changes, so draws
+/// that are the same every frame cost one `execute_bundles` to encode.
+pub struct BundleCache<K> {
+    bundles: Vec<(K, wgpu::RenderBundle)>,
+    // how many bundles were recorded, for spotting ones that never stay put
+    recorded: u64,
+}
+
+impl<K: PartialEq> BundleCache<K> {
+    pub fn new() -> Self {
+        Self {
+            bundles: Vec::new(),
+            recorded: 0,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&wgpu::RenderBundle> {
+        self.bundles.iter().find(|(bundle_key, _)| bundle_key == key).map(|(_, bundle)| bundle)
+    }
+
+    // Keeps a bundle for each of the `wanted` keys and no others. `record`
+    // is called with what comes with a key it has no bundle for yet.
+    pub fn update<T>(
+        &mut self,
+        wanted: impl IntoIterator<Item = (K, T)>,
+        mut record: impl FnMut(&K, T) -> wgpu::RenderBundle,
+    ) {
+        let mut old = std::mem::take(&mut self.bundles);
+        for (key, source) in wanted {
+            let bundle = match old.iter().position(|(old_key, _)| *old_key == key) {
+                Some(index) => old.swap_remove(index).1,
+                None => {
+                    self.recorded += 1;
+                    record(&key, source)
+                }
+            };
+            self.bundles.push((key, bundle));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bundles.clear();
+    }
+
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+}
+
+impl<K: PartialEq> Default for BundleCache<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// What a bundle's passes draw to, which the passes it's executed in have
+// to match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BundleTargets {
+    pub color: Option<wgpu::TextureFormat>,
+    pub depth: Option<wgpu::RenderBundleDepthStencil>,
+    pub sample_count: u32,
+}
+
+// A bundle of whatever `record` draws, for passes with `targets`
+pub fn record<'a>(
+    device: &'a wgpu::Device,
+    label: &str,
+    targets: BundleTargets,
+    record: impl FnOnce(&mut wgpu::RenderBundleEncoder<'a>),
+) -> wgpu::RenderBundle {
+    let color_formats = [targets.color];
+    let mut encoder = device.create_render_bundle_encoder(&wgpu::RenderBundleEncoderDescriptor {
+        label: Some(label),
+        color_formats: if targets.color.is_some() { &color_formats } else { &[] },
+        depth_stencil: targets.depth,
+        sample_count: targets.sample_count,
+        multiview: None,
+    });
+    record(&mut encoder);
+    encoder.finish(&wgpu::RenderBundleDescriptor { label: Some(label) })
+}
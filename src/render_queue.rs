@@ -0,0 +1,80 @@
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+// How models with a translucent tint are drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransparencyMode {
+    // with the opaque scene, discarding anything under half alpha
+    CutOut,
+    // alpha blended after the opaque scene, back to front
+    Sorted,
+    // blended through `WeightedBlendedOit`, in no particular order
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> Self {
+        match self {
+            TransparencyMode::CutOut => TransparencyMode::Sorted,
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::CutOut,
+        }
+    }
+}
+
+/// One frame's draws, split into the ones that write their colour outright
+/// and the ones that blend with what's behind them. Opaque draws keep the
+/// order they were pushed in; blended ones come out back to front, so each
+/// blends over everything farther away.
+pub struct RenderQueues<T> {
+    opaque: Vec<T>,
+    // view-space depth, larger is farther
+    transparent: Vec<(f32, T)>,
+}
+
+impl<T> RenderQueues<T> {
+    pub fn new() -> Self {
+        Self {
+            opaque: Vec::new(),
+            transparent: Vec::new(),
+        }
+    }
+
+    pub fn push_opaque(&mut self, draw: T) {
+        self.opaque.push(draw);
+    }
+
+    // Sorted by how far `center`, in world space, is in front of the camera
+    // with view matrix `view`. One point per draw, so draws that overlap in
+    // depth may still blend in the wrong order.
+    pub fn push_transparent(&mut self, draw: T, view: &Matrix4<f32>, center: Point3<f32>) {
+        // the camera looks down -z in view space
+        let depth = -view.transform_point(center).z;
+        self.transparent.push((depth, draw));
+    }
+
+    // Once everything is pushed. Queues are built anew every frame, as the
+    // camera and the draws move.
+    pub fn sort(&mut self) {
+        self.transparent.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    }
+
+    pub fn opaque(&self) -> impl Iterator<Item = &T> {
+        self.opaque.iter()
+    }
+
+    // Farthest first, after `sort`
+    pub fn transparent(&self) -> impl Iterator<Item = &T> {
+        self.transparent.iter().map(|(_, draw)| draw)
+    }
+
+    pub fn has_transparent(&self) -> bool {
+        !self.transparent.is_empty()
+    }
+}
+
+impl<T> Default for RenderQueues<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,396 @@
+use cgmath::*;
+
+use crate::camera::CameraUniform;
+use crate::depth_prepass;
+use crate::draw_list::TracedPass;
+use crate::resources::{ResourceKind, Tracked};
+
+/// What a target's texture is: one image, the layers of an array, or the six
+/// faces of a cubemap. Layers and faces are drawn into one at a time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetShape {
+    D2,
+    D2Array(u32),
+    // +x, -x, +y, -y, +z then -z, as `cube_face_view_proj` looks into them
+    Cube,
+}
+
+impl TargetShape {
+    pub fn layers(self) -> u32 {
+        match self {
+            TargetShape::D2 => 1,
+            TargetShape::D2Array(layers) => layers.max(1),
+            TargetShape::Cube => 6,
+        }
+    }
+
+    // What the whole texture is sampled as
+    pub fn view_dimension(self) -> wgpu::TextureViewDimension {
+        match self {
+            TargetShape::D2 => wgpu::TextureViewDimension::D2,
+            TargetShape::D2Array(_) => wgpu::TextureViewDimension::D2Array,
+            TargetShape::Cube => wgpu::TextureViewDimension::Cube,
+        }
+    }
+}
+
+// A colour texture to render into and sample afterwards, with a depth
+// buffer that can be sampled too, for passes drawn off screen and read by
+// later ones. Arrays and cubemaps have a view of each layer to draw into and
+// a depth layer for each. `resize` replaces everything, so bind groups that
+// use them have to be made again after it.
+pub struct RenderTarget {
+    label: String,
+    format: wgpu::TextureFormat,
+    shape: TargetShape,
+    views: TargetViews,
+}
+
+struct TargetViews {
+    texture: wgpu::Texture,
+    view: Tracked<wgpu::TextureView>,
+    depth_view: Tracked<wgpu::TextureView>,
+    // the layers' own, in order
+    layer_views: Vec<wgpu::TextureView>,
+    depth_layer_views: Vec<wgpu::TextureView>,
+}
+
+impl RenderTarget {
+    pub fn new(device: &wgpu::Device, label: &str, format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        Self::with_shape(device, label, format, TargetShape::D2, width, height)
+    }
+
+    // `layers` of `width` by `height`, such as one for each shadow cascade
+    pub fn array(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        layers: u32,
+    ) -> Self {
+        Self::with_shape(device, label, format, TargetShape::D2Array(layers), width, height)
+    }
+
+    // Six square faces `size` across, drawn with `CubeCameras`
+    pub fn cube(device: &wgpu::Device, label: &str, format: wgpu::TextureFormat, size: u32) -> Self {
+        Self::with_shape(device, label, format, TargetShape::Cube, size, size)
+    }
+
+    pub fn with_shape(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        shape: TargetShape,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        Self {
+            label: label.to_owned(),
+            format,
+            shape,
+            views: TargetViews::new(device, label, format, shape, width, height),
+        }
+    }
+
+    // A cubemap's faces stay square, `width` across
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        let height = if self.shape == TargetShape::Cube { width } else { height };
+        self.views = TargetViews::new(device, &self.label, self.format, self.shape, width, height);
+    }
+
+    pub fn shape(&self) -> TargetShape {
+        self.shape
+    }
+
+    pub fn format(&self) -> wgpu::TextureFormat {
+        self.format
+    }
+
+    // For copies, and views other than these
+    pub fn texture(&self) -> &wgpu::Texture {
+        &self.views.texture
+    }
+
+    // The whole texture, as a texture_2d, texture_2d_array or texture_cube
+    // to match the shape
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.views.view
+    }
+
+    // DEPTH_FORMAT, for pipelines that test against it and shaders that
+    // read it as a texture_depth_2d, or the _2d_array or _cube to match
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.views.depth_view
+    }
+
+    // One layer or face to draw into, or read as a texture_2d
+    pub fn layer_view(&self, layer: u32) -> &wgpu::TextureView {
+        &self.views.layer_views[layer as usize]
+    }
+
+    pub fn depth_layer_view(&self, layer: u32) -> &wgpu::TextureView {
+        &self.views.depth_layer_views[layer as usize]
+    }
+
+    // A pass into `layer` and its depth, both cleared
+    pub fn begin_layer<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        layer: u32,
+        clear_color: wgpu::Color,
+    ) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.layer_view(layer),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: self.depth_layer_view(layer),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    // The same without the depth, for pipelines that don't have any
+    pub fn begin_layer_color<'e>(
+        &self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        label: &str,
+        layer: u32,
+        clear_color: wgpu::Color,
+    ) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some(label),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.layer_view(layer),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        })
+    }
+}
+
+impl TargetViews {
+    fn new(
+        device: &wgpu::Device,
+        label: &str,
+        format: wgpu::TextureFormat,
+        shape: TargetShape,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: shape.layers(),
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let depth = depth_prepass::depth_texture(
+            device,
+            &format!("{} Depth", label),
+            width,
+            height,
+            shape.layers(),
+            wgpu::TextureUsages::TEXTURE_BINDING,
+        );
+        let whole = wgpu::TextureViewDescriptor {
+            dimension: Some(shape.view_dimension()),
+            ..Default::default()
+        };
+        Self {
+            view: Tracked::view(ResourceKind::Target, &texture, &whole),
+            depth_view: Tracked::view(ResourceKind::Target, &depth, &whole),
+            layer_views: (0..shape.layers()).map(|layer| layer_view(&texture, layer)).collect(),
+            depth_layer_views: (0..shape.layers()).map(|layer| layer_view(&depth, layer)).collect(),
+            texture,
+        }
+    }
+}
+
+// One layer of an array or face of a cubemap, at its full size
+pub fn layer_view(texture: &wgpu::Texture, layer: u32) -> wgpu::TextureView {
+    mip_layer_view(texture, layer, 0)
+}
+
+// The same at mip `mip_level`
+pub fn mip_layer_view(texture: &wgpu::Texture, layer: u32, mip_level: u32) -> wgpu::TextureView {
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some("Layer View"),
+        dimension: Some(wgpu::TextureViewDimension::D2),
+        base_mip_level: mip_level,
+        mip_level_count: Some(1),
+        base_array_layer: layer,
+        array_layer_count: Some(1),
+        ..Default::default()
+    })
+}
+
+// From the eye into face `face` of a cubemap, +x, -x, +y, -y, +z then -z,
+// as `cube_face_direction` in cubemap.wgsl reads them back, with depth from
+// `znear` to `zfar` for the passes that have a depth buffer. There's no view
+// matrix to go with it other than the eye's translation.
+pub fn cube_face_view_proj(face: u32, znear: f32, zfar: f32) -> Matrix4<f32> {
+    // where x, y and w in clip space come from, as rows
+    let (x, y, w) = match face {
+        0 => (-Vector3::unit_z(), Vector3::unit_y(), Vector3::unit_x()),
+        1 => (Vector3::unit_z(), Vector3::unit_y(), -Vector3::unit_x()),
+        2 => (Vector3::unit_x(), -Vector3::unit_z(), Vector3::unit_y()),
+        3 => (Vector3::unit_x(), Vector3::unit_z(), -Vector3::unit_y()),
+        4 => (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()),
+        _ => (-Vector3::unit_x(), Vector3::unit_y(), -Vector3::unit_z()),
+    };
+    let depth = (w * zfar / (zfar - znear)).extend(-zfar * znear / (zfar - znear));
+    Matrix4::from_cols(x.extend(0.0), y.extend(0.0), depth, w.extend(0.0)).transpose()
+}
+
+// How a triangle facing the eye winds under `cube_face_view_proj`. A cubemap
+// seen from inside is a mirror image of the camera's view, so the triangles
+// the camera sees counter-clockwise are clockwise here, and pipelines that
+// cull back faces need their own variant to draw into a face.
+pub const CUBE_FACE_FRONT_FACE: wgpu::FrontFace = wgpu::FrontFace::Cw;
+
+/// A camera bind group for each face of a cubemap, all looking out from one
+/// eye, to draw the scene into a `TargetShape::Cube` target a face at a time
+/// with the pipelines that draw it on screen, wound the other way round
+/// (`CUBE_FACE_FRONT_FACE`) where they cull.
+pub struct CubeCameras {
+    buffers: Vec<wgpu::Buffer>,
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl CubeCameras {
+    // `shared` is the rest of the camera bind group, everything in `layout`
+    // but the camera at binding 0
+    pub fn new(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        shared: &[wgpu::BindGroupEntry],
+    ) -> Self {
+        let buffers: Vec<wgpu::Buffer> = (0..6)
+            .map(|face| {
+                device.create_buffer(&wgpu::BufferDescriptor {
+                    label: Some(&format!("{} Camera Buffer {}", label, face)),
+                    size: std::mem::size_of::<CameraUniform>() as wgpu::BufferAddress,
+                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        let bind_groups = buffers
+            .iter()
+            .enumerate()
+            .map(|(face, buffer)| {
+                let mut entries = vec![wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }];
+                entries.extend_from_slice(shared);
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some(&format!("{} Camera Bind Group {}", label, face)),
+                    layout,
+                    entries: &entries,
+                })
+            })
+            .collect();
+        Self { buffers, bind_groups }
+    }
+
+    // Where the faces are seen from, from `znear` to `zfar`, before they're
+    // drawn
+    pub fn set_eye(&self, queue: &wgpu::Queue, eye: Point3<f32>, znear: f32, zfar: f32) {
+        let view = Matrix4::from_translation(-eye.to_vec());
+        for (face, buffer) in self.buffers.iter().enumerate() {
+            let camera = CameraUniform::from_view(eye, view, cube_face_view_proj(face as u32, znear, zfar));
+            queue.write_buffer(buffer, 0, bytemuck::cast_slice(&[camera]));
+        }
+    }
+
+    pub fn bind_group(&self, face: u32) -> &wgpu::BindGroup {
+        &self.bind_groups[face as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::{Camera, Projection};
+
+    // Which way `triangle` winds on screen under `view_proj`, as the
+    // rasterizer decides which face it's seeing
+    fn winding(view_proj: Matrix4<f32>, triangle: [Point3<f32>; 3]) -> wgpu::FrontFace {
+        let [a, b, c] = triangle.map(|corner| {
+            let clip = view_proj * corner.to_homogeneous();
+            assert!(clip.w > 0.0, "{:?} is behind the eye", corner);
+            (clip.x / clip.w, clip.y / clip.w)
+        });
+        let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+        if area > 0.0 {
+            wgpu::FrontFace::Ccw
+        } else {
+            wgpu::FrontFace::Cw
+        }
+    }
+
+    // a patch of ground a unit below the origin, facing up
+    const GROUND: [Point3<f32>; 3] = [
+        Point3::new(-0.5, -1.0, -0.5),
+        Point3::new(-0.5, -1.0, 0.5),
+        Point3::new(0.5, -1.0, -0.5),
+    ];
+
+    #[test]
+    fn the_camera_sees_front_faces_counter_clockwise() {
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(-90.0), Deg(-80.0));
+        let projection = Projection::new(64, 64, Deg(90.0), 0.1, 100.0);
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        assert_eq!(winding(view_proj, GROUND), wgpu::FrontFace::Ccw);
+    }
+
+    #[test]
+    fn a_probe_above_the_ground_sees_it() {
+        // from the -y face, looking down at it
+        let view_proj = cube_face_view_proj(3, 0.1, 100.0);
+        assert_eq!(winding(view_proj, GROUND), CUBE_FACE_FRONT_FACE);
+    }
+
+    #[test]
+    fn every_face_winds_the_same_way() {
+        for face in 0..6 {
+            let view_proj = cube_face_view_proj(face, 0.1, 100.0);
+            // the face's direction, and two across it whose cross product
+            // points back at the eye
+            let forward = view_proj.row(3).truncate();
+            let (right, up) = (view_proj.row(0).truncate(), view_proj.row(1).truncate());
+            let centre = Point3::from_vec(forward);
+            let triangle = [centre - right * 0.5, centre + right * 0.5 + up * 0.5, centre - up * 0.5];
+            assert!((triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]).dot(forward) < 0.0);
+            assert_eq!(winding(view_proj, triangle), CUBE_FACE_FRONT_FACE, "face {}", face);
+        }
+    }
+}
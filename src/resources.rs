@@ -0,0 +1,195 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What a buffer or texture is for, in the memory readout
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceKind {
+    // vertex and index buffers
+    Mesh,
+    // images sampled by shaders: materials, terrain, environment maps
+    Texture,
+    // what passes draw into: post-processing, shadow maps, depth
+    Target,
+    // everything else: instances, uniforms, storage
+    Buffer,
+}
+
+impl ResourceKind {
+    pub const ALL: [ResourceKind; 4] = [Self::Mesh, Self::Texture, Self::Target, Self::Buffer];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Mesh => "meshes",
+            Self::Texture => "textures",
+            Self::Target => "targets",
+            Self::Buffer => "buffers",
+        }
+    }
+}
+
+// Counts and bytes by `ResourceKind`, for every allocation still alive.
+// Shared by every device, the app only ever makes one.
+static COUNTS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+static BYTES: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+/// One buffer or texture's share of the usage totals, taken out again when
+/// it's dropped. Kept next to what it counts, so the two go together.
+#[derive(Debug)]
+pub struct Allocation {
+    kind: ResourceKind,
+    bytes: u64,
+}
+
+impl Allocation {
+    pub fn new(kind: ResourceKind, bytes: u64) -> Self {
+        COUNTS[kind as usize].fetch_add(1, Ordering::Relaxed);
+        BYTES[kind as usize].fetch_add(bytes, Ordering::Relaxed);
+        Self { kind, bytes }
+    }
+
+    pub fn buffer(kind: ResourceKind, buffer: &wgpu::Buffer) -> Self {
+        Self::new(kind, buffer.size())
+    }
+
+    pub fn texture(kind: ResourceKind, texture: &wgpu::Texture) -> Self {
+        Self::new(kind, texture_bytes(texture))
+    }
+
+    pub fn kind(&self) -> ResourceKind {
+        self.kind
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        COUNTS[self.kind as usize].fetch_sub(1, Ordering::Relaxed);
+        BYTES[self.kind as usize].fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+/// A view or buffer kept with the allocation it counts, for when what's
+/// behind it isn't kept otherwise, like a texture only used through one
+/// view. Derefs to what it wraps.
+#[derive(Debug)]
+pub struct Tracked<T> {
+    resource: T,
+    allocation: Allocation,
+}
+
+impl<T> Tracked<T> {
+    pub fn new(resource: T, allocation: Allocation) -> Self {
+        Self { resource, allocation }
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+}
+
+impl Tracked<wgpu::Texture> {
+    pub fn texture(kind: ResourceKind, texture: wgpu::Texture) -> Self {
+        let allocation = Allocation::texture(kind, &texture);
+        Self::new(texture, allocation)
+    }
+}
+
+impl Tracked<wgpu::TextureView> {
+    // A view of `texture`, with the whole texture counted as `kind`
+    pub fn view(kind: ResourceKind, texture: &wgpu::Texture, desc: &wgpu::TextureViewDescriptor) -> Self {
+        Self::new(texture.create_view(desc), Allocation::texture(kind, texture))
+    }
+}
+
+impl Tracked<wgpu::Buffer> {
+    pub fn buffer(kind: ResourceKind, buffer: wgpu::Buffer) -> Self {
+        let allocation = Allocation::buffer(kind, &buffer);
+        Self::new(buffer, allocation)
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.resource
+    }
+}
+
+// Roughly what a texture takes, at every mip level, layer and sample.
+// Drivers add padding and alignment on top. The packed depth formats,
+// which have no copy size, count as 4 bytes a texel.
+pub fn texture_bytes(texture: &wgpu::Texture) -> u64 {
+    let format = texture.format();
+    let (block_width, block_height) = format.block_dimensions();
+    let block_bytes = format.block_copy_size(None).unwrap_or(4) as u64;
+    // the layers stay the same down the mips, a 3D texture's depth halves
+    let blocks: u64 = (0..texture.mip_level_count())
+        .map(|level| {
+            let mip = texture.size().mip_level_size(level, texture.dimension());
+            let rows = mip.height.div_ceil(block_height) * mip.depth_or_array_layers;
+            mip.width.div_ceil(block_width) as u64 * rows as u64
+        })
+        .sum();
+    blocks * block_bytes * texture.sample_count() as u64
+}
+
+// `count` buffers or textures taking `bytes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// What the crate's buffers and textures take by kind, when it was read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    kinds: [Usage; 4],
+}
+
+impl ResourceUsage {
+    pub fn current() -> Self {
+        Self {
+            kinds: ResourceKind::ALL.map(|kind| Usage {
+                count: COUNTS[kind as usize].load(Ordering::Relaxed),
+                bytes: BYTES[kind as usize].load(Ordering::Relaxed),
+            }),
+        }
+    }
+
+    pub fn get(&self, kind: ResourceKind) -> Usage {
+        self.kinds[kind as usize]
+    }
+
+    pub fn total(&self) -> Usage {
+        self.kinds.iter().fold(Usage::default(), |total, usage| Usage {
+            count: total.count + usage.count,
+            bytes: total.bytes + usage.bytes,
+        })
+    }
+}
+
+// e.g. "212.4 MiB = meshes 20.1 MiB (40) + textures 150.0 MiB (12) + ..."
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Mebibytes(self.total().bytes))?;
+        for (index, kind) in ResourceKind::ALL.into_iter().enumerate() {
+            let usage = self.get(kind);
+            let separator = if index == 0 { " = " } else { " + " };
+            write!(f, "{}{} {} ({})", separator, kind.name(), Mebibytes(usage.bytes), usage.count)?;
+        }
+        Ok(())
+    }
+}
+
+struct Mebibytes(u64);
+
+impl fmt::Display for Mebibytes {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} MiB", self.0 as f64 / (1024.0 * 1024.0))
+    }
+}
@@ -0,0 +1,771 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::*;
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+
+use crate::animation::AnimationPlayer;
+use crate::assets;
+use crate::bounds::Aabb;
+use crate::camera::{Camera, Projection, ProjectionMode};
+use crate::color_grading::ColorGrading;
+use crate::culling::CullingMode;
+use crate::debug_draw::DebugCategories;
+use crate::depth_of_field::DepthOfField;
+use crate::draw_list::TracedPass;
+use crate::error_scope;
+use crate::exposure::Exposure;
+use crate::fog::Fog;
+use crate::instance::Instance;
+use crate::layers::RenderLayers;
+use crate::lighting::Light;
+use crate::lod::LodMode;
+use crate::model::{Mesh, Model, NodeTransform};
+use crate::motion_blur::MotionBlur;
+use crate::reflection_probes::ReflectionProbe;
+use crate::render_queue::TransparencyMode;
+use crate::skinning::{ModelInstance, ModelRenderer};
+use crate::upload::Uploader;
+use crate::wireframe::WireframeMode;
+
+/// A level as stored in a .ron or .json file. Every field has a default, so
+/// hand-written files only need to list what they change.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    // the instanced mesh; left out, the generated grid is kept
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instances: Option<Vec<InstanceDesc>>,
+    pub models: Vec<ModelDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub terrain: Option<TerrainDesc>,
+    // projected onto the terrain, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub decals: Vec<DecalDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub water: Option<WaterDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sky: Option<SkyDesc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub voxels: Option<VoxelDesc>,
+    // a .dds or .ktx2 cubemap, or an .hdr or .exr panorama, to bake image
+    // based lighting from and draw behind the scene, instead of the sky
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub environment: Option<PathBuf>,
+    // a .cube or strip .png 3D LUT the frame is graded through, when
+    // `settings.color_grading` is on
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_lut: Option<PathBuf>,
+    // point and spot lights, or more directional ones, besides the sun or
+    // the default light
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lights: Vec<Light>,
+    // baked when the scene's loaded, for what the models reflect
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub reflection_probes: Vec<ReflectionProbe>,
+    pub settings: RenderSettings,
+}
+
+impl SceneDesc {
+    // The format follows the extension, .ron or .json
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let format = Format::of(path)?;
+        let text = String::from_utf8(assets::read(path)?).with_context(|| format!("{}: not UTF-8 text", path.display()))?;
+        let scene = match format {
+            Format::Ron => ron::from_str(&text).map_err(Error::from),
+            Format::Json => serde_json::from_str(&text).map_err(Error::from),
+        };
+        scene.with_context(|| format!("{}: invalid scene", path.display()))
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = match Format::of(path)? {
+            Format::Ron => ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?,
+            Format::Json => serde_json::to_string_pretty(self)?,
+        };
+        std::fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+enum Format {
+    Ron,
+    Json,
+}
+
+impl Format {
+    fn of(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => Ok(Format::Ron),
+            Some("json") => Ok(Format::Json),
+            _ => bail!("{}: scenes need a .ron or .json extension", path.display()),
+        }
+    }
+}
+
+// Angles in degrees
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CameraDesc {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub fovy: f32,
+    pub znear: f32,
+    pub zfar: f32,
+    // `fovy` is only for a perspective projection
+    pub projection: ProjectionMode,
+    // what the camera draws, everything unless it's set
+    #[serde(skip_serializing_if = "RenderLayers::is_all")]
+    pub layers: RenderLayers,
+}
+
+impl CameraDesc {
+    pub fn from_camera(camera: &Camera, projection: &Projection) -> Self {
+        Self {
+            position: camera.position.into(),
+            yaw: Deg::from(camera.yaw).0,
+            pitch: Deg::from(camera.pitch).0,
+            fovy: Deg::from(projection.fovy()).0,
+            znear: projection.znear(),
+            zfar: projection.zfar(),
+            projection: projection.mode(),
+            layers: RenderLayers::ALL,
+        }
+    }
+
+    pub fn camera(&self) -> Camera {
+        Camera::new(self.position, Deg(self.yaw), Deg(self.pitch))
+    }
+
+    pub fn projection(&self, width: u32, height: u32) -> Projection {
+        Projection::new(width, height, Deg(self.fovy), self.znear, self.zfar).with_mode(self.projection)
+    }
+}
+
+// the camera `State::new` starts with
+impl Default for CameraDesc {
+    fn default() -> Self {
+        Self {
+            position: [0.0, 0.0, 2.0],
+            yaw: -90.0,
+            pitch: 0.0,
+            fovy: 45.0,
+            znear: 0.1,
+            zfar: 100.0,
+            projection: ProjectionMode::Perspective,
+            layers: RenderLayers::ALL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InstanceDesc {
+    pub position: [f32; 3],
+    // quaternion, x, y, z, w like glTF
+    #[serde(default = "identity_rotation")]
+    pub rotation: [f32; 4],
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    #[serde(default)]
+    pub material: u32,
+}
+
+impl From<&Instance> for InstanceDesc {
+    fn from(instance: &Instance) -> Self {
+        Self {
+            position: instance.position.into(),
+            rotation: xyzw(instance.rotation),
+            scale: instance.scale.into(),
+            material: instance.material,
+        }
+    }
+}
+
+impl From<&InstanceDesc> for Instance {
+    fn from(desc: &InstanceDesc) -> Self {
+        Self {
+            position: desc.position.into(),
+            rotation: quaternion(desc.rotation),
+            scale: desc.scale.into(),
+            material: desc.material,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelDesc {
+    // glTF, OBJ or image file, relative paths are resolved against the
+    // scene file
+    pub path: PathBuf,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    #[serde(default = "identity_rotation")]
+    pub rotation: [f32; 4],
+    #[serde(default = "unit_scale")]
+    pub scale: [f32; 3],
+    // clip to loop, by name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<String>,
+    // linear RGBA multiplied with the materials
+    #[serde(default = "white", skip_serializing_if = "is_white")]
+    pub tint: [f32; 4],
+    // simulated with the `physics` feature, kept as it is without it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<BodyDesc>,
+    // drawn by the cameras whose masks have one of these
+    #[serde(default, skip_serializing_if = "RenderLayers::is_world")]
+    pub layers: RenderLayers,
+}
+
+impl ModelDesc {
+    pub fn new(path: impl Into<PathBuf>, placement: &NodeTransform, animation: Option<&str>) -> Self {
+        Self {
+            path: path.into(),
+            translation: placement.translation.into(),
+            rotation: xyzw(placement.rotation),
+            scale: placement.scale.into(),
+            animation: animation.map(str::to_owned),
+            tint: white(),
+            body: None,
+            layers: RenderLayers::WORLD,
+        }
+    }
+
+    pub fn placement(&self) -> NodeTransform {
+        NodeTransform {
+            translation: self.translation.into(),
+            rotation: quaternion(self.rotation),
+            scale: self.scale.into(),
+        }
+    }
+}
+
+// A model's rigid body, which moves the model where it's dynamic. Its
+// collider is centred on the model's bounds, lengths in the model's units
+// scaled with it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct BodyDesc {
+    #[serde(default)]
+    pub kind: BodyKind,
+    #[serde(default)]
+    pub collider: ColliderDesc,
+    // mass per cubic unit
+    #[serde(default = "body_density")]
+    pub density: f32,
+    #[serde(default = "body_friction")]
+    pub friction: f32,
+    // how much of its speed a collision gives back, from 0 to 1
+    #[serde(default)]
+    pub restitution: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BodyKind {
+    // falls and is pushed around
+    #[default]
+    Dynamic,
+    // never moves, like the terrain
+    Fixed,
+    // follows the model's placement, pushing dynamic bodies out of the way
+    Kinematic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ColliderDesc {
+    // the box around the model's rest pose
+    #[default]
+    Bounds,
+    Cuboid { half_extents: [f32; 3] },
+    Ball { radius: f32 },
+    // along y, `half_height` from the middle to each end's centre
+    Capsule { half_height: f32, radius: f32 },
+}
+
+// Paths are resolved against the scene file, like models'
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TerrainDesc {
+    // 8 or 16-bit greyscale, a vertex per pixel, black at the height of
+    // `position` and white `height` above it
+    pub heightmap: PathBuf,
+    // layer weights in red, green, blue and alpha; left out, they're worked
+    // out from the height and slope
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub splat_map: Option<PathBuf>,
+    // up to four textures, one per splat map channel; missing ones are
+    // plain grass, rock, dirt and snow, in that order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<PathBuf>,
+    // the middle of the terrain's base
+    #[serde(default)]
+    pub position: [f32; 3],
+    // along x and z
+    #[serde(default = "terrain_size")]
+    pub size: [f32; 2],
+    #[serde(default = "terrain_height")]
+    pub height: f32,
+    // how far one repeat of a layer texture stretches
+    #[serde(default = "terrain_layer_size")]
+    pub layer_size: f32,
+    // chunks closer to the camera than this many times their width are
+    // split into four with twice the detail
+    #[serde(default = "terrain_lod_distance")]
+    pub lod_distance: f32,
+}
+
+impl TerrainDesc {
+    // The same terrain with every path passed through `f`
+    pub fn map_paths(&self, f: impl Fn(&Path) -> PathBuf) -> Self {
+        Self {
+            heightmap: f(&self.heightmap),
+            splat_map: self.splat_map.as_deref().map(&f),
+            layers: self.layers.iter().map(|layer| f(layer)).collect(),
+            ..self.clone()
+        }
+    }
+}
+
+// A box that projects a texture down its y axis onto the terrain inside it,
+// like a road marking or a splat, lengths in world units. Paths are
+// resolved against the scene file, like models'.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DecalDesc {
+    // in sRGB, with how much of the surface it covers in alpha, its top
+    // towards the box's -z
+    pub texture: PathBuf,
+    // in tangent space, x along the box's x and y up the texture; left out,
+    // the surface keeps its own normal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normal_map: Option<PathBuf>,
+    // the middle of the box
+    #[serde(default)]
+    pub position: [f32; 3],
+    #[serde(default = "identity_rotation")]
+    pub rotation: [f32; 4],
+    #[serde(default = "unit_scale")]
+    pub size: [f32; 3],
+    // linear RGBA multiplied with the texture
+    #[serde(default = "white", skip_serializing_if = "is_white")]
+    pub tint: [f32; 4],
+}
+
+impl DecalDesc {
+    // The same decal with both paths passed through `f`
+    pub fn map_paths(&self, f: impl Fn(&Path) -> PathBuf) -> Self {
+        Self {
+            texture: f(&self.texture),
+            normal_map: self.normal_map.as_deref().map(&f),
+            ..self.clone()
+        }
+    }
+
+    // The unit box around the origin to where the decal is
+    pub fn transform(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position.into())
+            * Matrix4::from(quaternion(self.rotation))
+            * Matrix4::from_nonuniform_scale(self.size[0], self.size[1], self.size[2])
+    }
+}
+
+// A rectangle of water, with waves and a clear colour to see the bottom
+// through, lengths in world units
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WaterDesc {
+    // the middle of the surface
+    #[serde(default)]
+    pub position: [f32; 3],
+    // along x and z
+    #[serde(default = "water_size")]
+    pub size: [f32; 2],
+    // linear, what deep water and the far bottom fade to
+    #[serde(default = "water_color")]
+    pub color: [f32; 3],
+    // how deep the bottom can be and still show through, about
+    #[serde(default = "water_clarity")]
+    pub clarity: f32,
+    // of the longest waves, the others are shorter
+    #[serde(default = "water_wave_length")]
+    pub wave_length: f32,
+    // how sharp the crests are, from round at zero to pointed at one
+    #[serde(default = "water_steepness")]
+    pub steepness: f32,
+}
+
+// A daytime sky worked out from where the sun is, which is also the light.
+// The sun crosses the sky as it would at an equinox, rising in the east
+// (+x) and highest in the south (+z).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SkyDesc {
+    // in hours, noon at 12
+    #[serde(default = "sky_time_of_day")]
+    pub time_of_day: f32,
+    // in degrees, how far from overhead the sun is at noon
+    #[serde(default = "sky_latitude")]
+    pub latitude: f32,
+    // how hazy the air is, from 2 for a clear day to about 10
+    #[serde(default = "sky_turbidity")]
+    pub turbidity: f32,
+    // in seconds, how long a whole day takes, 0 for the sun to stand still
+    #[serde(default)]
+    pub day_length: f32,
+}
+
+// A block of voxels, drawn as the surface marching cubes finds in it,
+// lengths in world units. What starts out solid is a ground and balls on
+// it; edits made since aren't saved.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VoxelDesc {
+    // the corner with the lowest x, y and z
+    #[serde(default)]
+    pub origin: [f32; 3],
+    // along x, y and z; the field is sampled at their corners, and the
+    // samples on its faces are always empty
+    #[serde(default = "voxel_cells")]
+    pub cells: [u32; 3],
+    #[serde(default = "voxel_cell_size")]
+    pub cell_size: f32,
+    // solid below this height, None for no ground
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ground: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub spheres: Vec<VoxelSphere>,
+    // linear, for the whole surface
+    #[serde(default = "voxel_color")]
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub metallic: f32,
+    #[serde(default = "voxel_roughness")]
+    pub roughness: f32,
+    // past these the surface has holes, each one 228 bytes
+    #[serde(default = "voxel_max_triangles")]
+    pub max_triangles: u32,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct VoxelSphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub present_mode: wgpu::PresentMode,
+    // None leaves the frame rate uncapped
+    pub max_fps: Option<f64>,
+    // a texture filtering preset by name, as shown in the stats readout
+    pub filtering: String,
+    pub wireframe: WireframeMode,
+    pub fog: Fog,
+    pub exposure: Exposure,
+    pub color_grading: ColorGrading,
+    pub transparency: TransparencyMode,
+    pub fxaa: bool,
+    pub taa: bool,
+    pub ssr: bool,
+    pub depth_of_field: DepthOfField,
+    pub motion_blur: MotionBlur,
+    pub uv_debug: bool,
+    pub culling: CullingMode,
+    // against last frame's depth, with GPU culling and the depth prepass
+    pub occlusion_culling: bool,
+    pub lod: LodMode,
+    pub depth_prepass: bool,
+    pub render_bundles: bool,
+    pub particles: bool,
+    pub show_hud: bool,
+    pub show_tilemap: bool,
+    pub show_stats: bool,
+    pub show_debug: bool,
+    // what the debug overlay draws when it's shown
+    pub debug_categories: DebugCategories,
+    // the ground grid and the corner axes
+    pub show_grid: bool,
+    pub show_axes: bool,
+}
+
+// what `State::new` starts with
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            present_mode: wgpu::PresentMode::Fifo,
+            max_fps: None,
+            filtering: "trilinear".to_owned(),
+            wireframe: WireframeMode::Off,
+            fog: Fog::default(),
+            exposure: Exposure::default(),
+            color_grading: ColorGrading::default(),
+            transparency: TransparencyMode::Sorted,
+            fxaa: false,
+            taa: false,
+            ssr: false,
+            depth_of_field: DepthOfField::default(),
+            motion_blur: MotionBlur::default(),
+            uv_debug: false,
+            culling: CullingMode::Gpu,
+            occlusion_culling: false,
+            lod: LodMode::CrossFade,
+            depth_prepass: false,
+            render_bundles: true,
+            particles: false,
+            show_hud: true,
+            show_tilemap: false,
+            show_stats: true,
+            show_debug: false,
+            debug_categories: DebugCategories::default(),
+            show_grid: false,
+            show_axes: false,
+        }
+    }
+}
+
+fn terrain_size() -> [f32; 2] {
+    [64.0; 2]
+}
+
+fn terrain_height() -> f32 {
+    8.0
+}
+
+fn terrain_layer_size() -> f32 {
+    4.0
+}
+
+fn terrain_lod_distance() -> f32 {
+    2.0
+}
+
+fn water_size() -> [f32; 2] {
+    [64.0; 2]
+}
+
+fn water_color() -> [f32; 3] {
+    [0.01, 0.05, 0.06]
+}
+
+fn water_clarity() -> f32 {
+    2.0
+}
+
+fn water_wave_length() -> f32 {
+    4.0
+}
+
+fn water_steepness() -> f32 {
+    0.5
+}
+
+fn sky_time_of_day() -> f32 {
+    10.0
+}
+
+fn sky_latitude() -> f32 {
+    40.0
+}
+
+fn sky_turbidity() -> f32 {
+    3.0
+}
+
+fn voxel_cells() -> [u32; 3] {
+    [48, 24, 48]
+}
+
+fn voxel_cell_size() -> f32 {
+    0.25
+}
+
+fn voxel_color() -> [f32; 3] {
+    [0.45, 0.36, 0.26]
+}
+
+fn voxel_roughness() -> f32 {
+    0.9
+}
+
+fn voxel_max_triangles() -> u32 {
+    100_000
+}
+
+fn body_density() -> f32 {
+    1.0
+}
+
+fn body_friction() -> f32 {
+    0.5
+}
+
+fn identity_rotation() -> [f32; 4] {
+    [0.0, 0.0, 0.0, 1.0]
+}
+
+fn unit_scale() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn white() -> [f32; 4] {
+    [1.0; 4]
+}
+
+fn is_white(tint: &[f32; 4]) -> bool {
+    *tint == white()
+}
+
+fn quaternion([x, y, z, w]: [f32; 4]) -> Quaternion<f32> {
+    Quaternion::new(w, x, y, z)
+}
+
+fn xyzw(q: Quaternion<f32>) -> [f32; 4] {
+    [q.v.x, q.v.y, q.v.z, q.s]
+}
+
+// A glTF model placed in the scene, with its own GPU instance and
+// animation state
+pub struct SceneModel {
+    // as loaded, for writing the scene back out
+    pub path: PathBuf,
+    pub placement: NodeTransform,
+    pub model: Model,
+    pub instance: ModelInstance,
+    pub player: AnimationPlayer,
+    // model space, of the rest pose, worked out once at load
+    pub bounds: Option<Aabb>,
+    pub body: Option<BodyDesc>,
+    pub layers: RenderLayers,
+}
+
+impl SceneModel {
+    // Anything `assets::load_model` can read
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        renderer: &ModelRenderer,
+        path: impl Into<PathBuf>,
+        placement: NodeTransform,
+    ) -> Result<Self> {
+        let path = path.into();
+        let label = path.file_name().map_or_else(|| path.display().to_string(), |name| name.to_string_lossy().into_owned());
+        let (model, instance) = error_scope::check(device, format!("Creating {}", path.display()), || {
+            let model = assets::load_model(device, queue, &path)?;
+            let instance = ModelInstance::new(device, renderer, &label, &model, placement.matrix());
+            Ok((model, instance))
+        })??;
+        let player = AnimationPlayer::new(&model);
+        let bounds = model.bounds();
+        Ok(Self {
+            path,
+            placement,
+            model,
+            instance,
+            player,
+            bounds,
+            body: None,
+            layers: RenderLayers::WORLD,
+        })
+    }
+
+    // Loops the named clip, or the first one if it's None or missing
+    pub fn play(&mut self, animation: Option<&str>) {
+        let clip = animation.and_then(|name| self.model.find_animation(name));
+        if let (Some(name), None) = (animation, clip) {
+            log::warn!("{}: no animation named {}", self.path.display(), name);
+        }
+        if let Some(clip) = clip.or((!self.model.animations.is_empty()).then_some(0)) {
+            self.player.play(clip, true);
+        }
+    }
+
+    // World space centre of the model's rest pose bounds, what transparent
+    // models are sorted by
+    pub fn center(&self) -> Point3<f32> {
+        let center = self.bounds.map_or(Point3::origin(), |bounds| bounds.center());
+        self.instance.transform.transform_point(center)
+    }
+
+    // What the model is culled by. Animation can move vertices outside of
+    // it, the rest pose is close enough for most clips.
+    pub fn world_bounds(&self) -> Option<Aabb> {
+        self.bounds.map(|bounds| bounds.transformed(&self.instance.transform))
+    }
+
+    // Levels of detail of the model's most detailed mesh
+    pub fn lod_levels(&self) -> usize {
+        self.model.meshes.iter().map(Mesh::levels).max().unwrap_or(1)
+    }
+
+    pub fn current_animation(&self) -> Option<&str> {
+        self.player.current().and_then(|clip| self.model.animations[clip].name.as_deref())
+    }
+
+    pub fn update(&mut self, dt: Duration) {
+        self.instance.transform = self.placement.matrix();
+        self.player.update(dt, &mut self.model);
+    }
+
+    // Once per frame after `update`, before drawing, between the renderer's
+    // `begin_frame` and `upload`
+    pub fn upload(&mut self, uploader: &mut Uploader, renderer: &mut ModelRenderer, object: u32) {
+        self.instance.upload(uploader, renderer, &self.model, object);
+    }
+
+    pub fn skin(&self, encoder: &mut wgpu::CommandEncoder, renderer: &ModelRenderer) {
+        self.instance.skin(encoder, renderer, &self.model);
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw(render_pass, renderer, camera_bind_group, &self.model);
+    }
+
+    pub fn draw_capture<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw_capture(render_pass, renderer, camera_bind_group, &self.model);
+    }
+
+    pub fn draw_blended<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw_blended(render_pass, renderer, camera_bind_group, &self.model);
+    }
+
+    pub fn draw_oit<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw_oit(render_pass, renderer, camera_bind_group, &self.model);
+    }
+
+    pub fn draw_velocity<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw_velocity(render_pass, renderer, camera_bind_group, &self.model);
+    }
+
+    pub fn draw_gbuffer<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.instance.draw_gbuffer(render_pass, renderer, camera_bind_group, &self.model);
+    }
+}
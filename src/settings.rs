@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::*;
+use serde::{Deserialize, Serialize};
+
+use crate::actions::Binding;
+use crate::dynamic_resolution::{RenderScale, Upscaling};
+use crate::shadow::ShadowQuality;
+
+// How often the file is checked for edits, and the app for changes to write
+// back to it
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The post-process anti-aliasing, over whatever `Settings::msaa_samples`
+/// smoothed already.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AntiAliasing {
+    #[default]
+    Off,
+    Fxaa,
+    Taa,
+    // FXAA over TAA's output
+    TaaFxaa,
+}
+
+impl AntiAliasing {
+    pub fn new(taa: bool, fxaa: bool) -> Self {
+        match (taa, fxaa) {
+            (false, false) => AntiAliasing::Off,
+            (false, true) => AntiAliasing::Fxaa,
+            (true, false) => AntiAliasing::Taa,
+            (true, true) => AntiAliasing::TaaFxaa,
+        }
+    }
+
+    pub fn taa(self) -> bool {
+        matches!(self, AntiAliasing::Taa | AntiAliasing::TaaFxaa)
+    }
+
+    pub fn fxaa(self) -> bool {
+        matches!(self, AntiAliasing::Fxaa | AntiAliasing::TaaFxaa)
+    }
+}
+
+/// The quality options users change without recompiling, from
+/// settings.ron. Anything left out of the file keeps its default.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    // the window's size in physical pixels, None leaves it to the platform
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resolution: Option<[u32; 2]>,
+    // Fifo, or AutoNoVsync without
+    pub vsync: bool,
+    pub anti_aliasing: AntiAliasing,
+    // samples per pixel of the passes drawn straight into the scene, 1 for
+    // no MSAA. Only read at startup, and brought down to what the adapter
+    // supports.
+    pub msaa_samples: u32,
+    pub shadow_quality: ShadowQuality,
+    // the 3D scene's size as a fraction of the window's, and how it's
+    // brought up to the window's
+    pub render_scale: RenderScale,
+    pub upscaling: Upscaling,
+    // a list like --backend takes, only read at startup
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    // the actions bound differently from the defaults, see
+    // `ActionMap::overrides`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bindings: BTreeMap<String, Vec<Binding>>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            resolution: None,
+            vsync: true,
+            anti_aliasing: AntiAliasing::default(),
+            msaa_samples: 1,
+            shadow_quality: ShadowQuality::default(),
+            render_scale: RenderScale::default(),
+            upscaling: Upscaling::default(),
+            backend: None,
+            bindings: BTreeMap::new(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("Reading settings from {}", path.display()))?;
+        ron::from_str(&text).with_context(|| format!("Parsing settings in {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Creating {}", dir.display()))?;
+        }
+        std::fs::write(path, text).with_context(|| format!("Writing settings to {}", path.display()))
+    }
+}
+
+// LEARN_WGPU_SETTINGS if it's set, otherwise settings.ron in the working
+// directory, or in the app's storage on Android
+pub fn default_path() -> PathBuf {
+    if let Some(path) = std::env::var_os("LEARN_WGPU_SETTINGS") {
+        return PathBuf::from(path);
+    }
+    #[cfg(target_os = "android")]
+    if let Some(dir) = crate::android::data_dir() {
+        return dir.join("settings.ron");
+    }
+    PathBuf::from("settings.ron")
+}
+
+/// settings.ron as the app keeps it in step: edits to the file are picked
+/// up while running and changes made in the app are written back, each
+/// checked for once a second
+#[derive(Debug, Clone)]
+pub struct SettingsFile {
+    path: PathBuf,
+    // when it was last read or written, to tell edits from elsewhere
+    modified: Option<SystemTime>,
+    since_sync: Duration,
+    // false while it doesn't parse, so what's in it isn't written over
+    writable: bool,
+}
+
+impl SettingsFile {
+    // The file and the settings in it, the defaults if there's no file yet.
+    // One that doesn't parse is logged and left alone until it's fixed.
+    pub fn open(path: PathBuf) -> (Self, Settings) {
+        let mut file = Self {
+            path,
+            modified: None,
+            since_sync: Duration::ZERO,
+            writable: true,
+        };
+        if !file.path.exists() {
+            return (file, Settings::default());
+        }
+        let settings = file.read().unwrap_or_else(|e| {
+            log::warn!("{:#}, using the defaults", e);
+            file.writable = false;
+            Settings::default()
+        });
+        (file, settings)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // True once every `SYNC_INTERVAL`, when it's time to `reload` and
+    // write back
+    pub fn tick(&mut self, dt: Duration) -> bool {
+        self.since_sync += dt;
+        if self.since_sync < SYNC_INTERVAL {
+            return false;
+        }
+        self.since_sync = Duration::ZERO;
+        true
+    }
+
+    // The settings in the file if it was changed since it was last read or
+    // written
+    pub fn reload(&mut self) -> Option<Settings> {
+        let modified = modified(&self.path);
+        if modified.is_none() || modified == self.modified {
+            return None;
+        }
+        match self.read() {
+            Result::Ok(settings) => {
+                log::info!("Reloaded {}", self.path.display());
+                self.writable = true;
+                Some(settings)
+            }
+            Err(e) => {
+                log::warn!("{:#}", e);
+                self.writable = false;
+                None
+            }
+        }
+    }
+
+    pub fn save(&mut self, settings: &Settings) {
+        if !self.writable {
+            return;
+        }
+        match settings.save(&self.path) {
+            Result::Ok(()) => self.modified = modified(&self.path),
+            Err(e) => log::warn!("{:#}", e),
+        }
+    }
+
+    fn read(&mut self) -> Result<Settings> {
+        // before parsing, so a broken file is only complained about once
+        self.modified = modified(&self.path);
+        Settings::load(&self.path)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -1,27 +1,73 @@
 // Vertex shader
 
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "material.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) color: vec3<f32>,
+    @location(2) tex_coords: vec2<f32>,
 }
 
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) material: u32,
+};
+
 struct VertexOutput {
-    @builtin(position) clip_position: vec4<f32>,
+    // invariant to match depth_only.wgsl's depth
+    @invariant @builtin(position) clip_position: vec4<f32>,
     @location(0) color: vec3<f32>,
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) @interpolate(flat) material: u32,
+    @location(3) world_position: vec3<f32>,
 };
 
 @vertex
 fn vs_main(
     model: VertexInput,
+    instance: InstanceInput,
 ) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
     var out: VertexOutput;
     out.color = model.color;
-    out.clip_position = vec4<f32>(model.position, 1.0);
+    out.tex_coords = model.tex_coords;
+    out.material = instance.material;
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
     return out;
 }
 
 // Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
-}
\ No newline at end of file
+#ifdef UV_DEBUG
+    // debug permutation: texture coordinates as colour
+    return vec4<f32>(in.tex_coords, 0.0, 1.0);
+#else
+    let albedo = sample_material(in.material, in.tex_coords);
+    let color = apply_fog(fog, camera.view_pos.xyz, in.world_position, in.color * albedo.rgb);
+    return vec4<f32>(color, albedo.a);
+#endif
+}
+
+// Flat colour for edges drawn with line polygon mode
+@fragment
+fn fs_wireframe(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(0.2, 1.0, 0.4, 1.0);
+}
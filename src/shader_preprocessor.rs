@@ -0,0 +1,440 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use anyhow::*;
+
+use crate::shader_validation;
+
+// A few C preprocessor-like directives on top of WGSL, so shaders can share
+// code and build permutations from one source:
+//
+//   #include "camera.wgsl"   pastes a file in, once per shader
+//   #define NAME [value]     adds to the defines for the rest of the shader
+//   #ifdef NAME / #ifndef NAME / #else / #endif, nested as needed
+//   #{NAME}                  replaced with the value of a define
+//
+// Directives have to start their line. Sources are compiled in, so includes
+// resolve the same on the web and Android, and `set_source` swaps in edited
+// ones for hot reloading.
+const SOURCES: &[(&str, &str)] = &[
+    ("axis_gizmo.wgsl", include_str!("axis_gizmo.wgsl")),
+    ("camera.wgsl", include_str!("camera.wgsl")),
+    ("cubemap.wgsl", include_str!("cubemap.wgsl")),
+    ("debug_draw.wgsl", include_str!("debug_draw.wgsl")),
+    ("decals.wgsl", include_str!("decals.wgsl")),
+    ("depth_of_field.wgsl", include_str!("depth_of_field.wgsl")),
+    ("depth_only.wgsl", include_str!("depth_only.wgsl")),
+    ("draw_data.wgsl", include_str!("draw_data.wgsl")),
+    ("equirect.wgsl", include_str!("equirect.wgsl")),
+    ("external.wgsl", include_str!("external.wgsl")),
+    ("fog.wgsl", include_str!("fog.wgsl")),
+    ("fullscreen.wgsl", include_str!("fullscreen.wgsl")),
+    ("fxaa.wgsl", include_str!("fxaa.wgsl")),
+    ("gamma.wgsl", include_str!("gamma.wgsl")),
+    ("gbuffer.wgsl", include_str!("gbuffer.wgsl")),
+    ("grid.wgsl", include_str!("grid.wgsl")),
+    ("ibl_bake.wgsl", include_str!("ibl_bake.wgsl")),
+    ("id.wgsl", include_str!("id.wgsl")),
+    ("lighting.wgsl", include_str!("lighting.wgsl")),
+    ("material.wgsl", include_str!("material.wgsl")),
+    ("mipmap.wgsl", include_str!("mipmap.wgsl")),
+    ("model.wgsl", include_str!("model.wgsl")),
+    ("motion_blur.wgsl", include_str!("motion_blur.wgsl")),
+    ("msaa.wgsl", include_str!("msaa.wgsl")),
+    ("oit.wgsl", include_str!("oit.wgsl")),
+    ("oit_resolve.wgsl", include_str!("oit_resolve.wgsl")),
+    ("outline.wgsl", include_str!("outline.wgsl")),
+    ("particles.wgsl", include_str!("particles.wgsl")),
+    ("pbr.wgsl", include_str!("pbr.wgsl")),
+    ("reflection_probes.wgsl", include_str!("reflection_probes.wgsl")),
+    ("shader.wgsl", include_str!("shader.wgsl")),
+    ("shadow.wgsl", include_str!("shadow.wgsl")),
+    ("sky.wgsl", include_str!("sky.wgsl")),
+    ("sky_cube.wgsl", include_str!("sky_cube.wgsl")),
+    ("skybox.wgsl", include_str!("skybox.wgsl")),
+    ("ssr.wgsl", include_str!("ssr.wgsl")),
+    ("ssr_gbuffer.wgsl", include_str!("ssr_gbuffer.wgsl")),
+    ("taa_resolve.wgsl", include_str!("taa_resolve.wgsl")),
+    ("taa_velocity.wgsl", include_str!("taa_velocity.wgsl")),
+    ("terrain.wgsl", include_str!("terrain.wgsl")),
+    ("tonemap.wgsl", include_str!("tonemap.wgsl")),
+    ("viewport.wgsl", include_str!("viewport.wgsl")),
+    ("water.wgsl", include_str!("water.wgsl")),
+];
+
+// Sources loaded over the compiled-in ones. Global rather than passed
+// around so every shader, including those the pipeline compiler's worker
+// builds, sees the same edits.
+static OVERRIDES: RwLock<BTreeMap<&'static str, Arc<str>>> = RwLock::new(BTreeMap::new());
+
+// Every source `#include` and `preprocess` can name
+pub fn source_names() -> impl Iterator<Item = &'static str> {
+    SOURCES.iter().map(|(name, _)| *name)
+}
+
+// Replaces the source of `name` for everything preprocessed from now on;
+// pipelines built earlier keep the old one
+pub fn set_source(name: &str, source: String) -> Result<()> {
+    let name = source_names()
+        .find(|source_name| *source_name == name)
+        .with_context(|| format!("There's no shader source called {}", name))?;
+    OVERRIDES.write().unwrap().insert(name, source.into());
+    Ok(())
+}
+
+fn source(name: &str) -> Option<(&'static str, Arc<str>)> {
+    let &(name, compiled_in) = SOURCES.iter().find(|(source_name, _)| *source_name == name)?;
+    let source = OVERRIDES.read().unwrap().get(name).cloned();
+    Some((name, source.unwrap_or_else(|| compiled_in.into())))
+}
+
+/// The defines a shader is preprocessed with, e.g. `HAS_NORMAL_MAP` or
+/// `DRAW_GROUP=3`. Each set is a permutation of its own, so it doubles as
+/// the key pipeline variants are compiled and cached under.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ShaderDefs(BTreeMap<String, String>);
+
+impl ShaderDefs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // A flag for `#ifdef`
+    pub fn with(self, name: &str) -> Self {
+        self.with_value(name, "")
+    }
+
+    // A define `#{NAME}` is replaced with, which also counts for `#ifdef`
+    pub fn with_value(mut self, name: &str, value: impl ToString) -> Self {
+        self.0.insert(name.to_owned(), value.to_string());
+        self
+    }
+
+    // Both sets, `other`'s values winning
+    pub fn union(mut self, other: &ShaderDefs) -> Self {
+        self.0.extend(other.0.iter().map(|(name, value)| (name.clone(), value.clone())));
+        self
+    }
+
+    pub fn is_defined(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+// Space separated, `NAME=value` for those with a value
+impl fmt::Display for ShaderDefs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "(no defines)");
+        }
+        for (i, (name, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            if value.is_empty() {
+                write!(f, "{}", name)?;
+            } else {
+                write!(f, "{}={}", name, value)?;
+            }
+        }
+        std::result::Result::Ok(())
+    }
+}
+
+/// WGSL ready for wgpu, remembering which file and line each of its lines
+/// came from.
+pub struct PreprocessedShader {
+    source: String,
+    // file index and 1-based line, per line of `source`
+    lines: Vec<(usize, usize)>,
+    // every file that went in, in the version that was used
+    files: Vec<(&'static str, Arc<str>)>,
+}
+
+impl PreprocessedShader {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    // The file name and line that 1-based `line` of the output came from
+    pub fn origin(&self, line: usize) -> Option<(&'static str, usize)> {
+        let &(file, file_line) = self.lines.get(line.checked_sub(1)?)?;
+        Some((self.files[file].0, file_line))
+    }
+
+    // A file as it was when preprocessed, for quoting it in errors
+    pub fn file_source(&self, name: &str) -> Option<&str> {
+        self.files
+            .iter()
+            .find(|(file_name, _)| *file_name == name)
+            .map(|(_, source)| &**source)
+    }
+
+    pub fn create_module(&self, device: &wgpu::Device, label: &str) -> wgpu::ShaderModule {
+        device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(label),
+            source: wgpu::ShaderSource::Wgsl(self.source.as_str().into()),
+        })
+    }
+}
+
+// `name` with its includes pasted in and its directives applied
+pub fn preprocess(name: &str, defs: &ShaderDefs) -> Result<PreprocessedShader> {
+    preprocess_from(name, defs, &source)
+}
+
+// The same with the sources looked up by `sources`
+fn preprocess_from(name: &str, defs: &ShaderDefs, sources: &SourceLookup) -> Result<PreprocessedShader> {
+    let mut preprocessor = Preprocessor {
+        sources,
+        defs: defs.clone(),
+        shader: PreprocessedShader {
+            source: String::new(),
+            lines: Vec::new(),
+            files: Vec::new(),
+        },
+    };
+    preprocessor
+        .file(name)
+        .with_context(|| format!("Failed to preprocess {} with {}", name, defs))?;
+    Ok(preprocessor.shader)
+}
+
+// Preprocesses `name` and checks it with naga, so errors point into the
+// file they're in rather than the preprocessed whole
+pub fn load(name: &str, defs: &ShaderDefs) -> Result<PreprocessedShader> {
+    let shader = preprocess(name, defs)?;
+    shader_validation::validate(&shader).with_context(|| format!("{} with {} doesn't compile", name, defs))?;
+    Ok(shader)
+}
+
+pub fn try_create_module(
+    device: &wgpu::Device,
+    label: &str,
+    name: &str,
+    defs: &ShaderDefs,
+) -> Result<wgpu::ShaderModule> {
+    Ok(load(name, defs)?.create_module(device, label))
+}
+
+// Like `try_create_module`, for shaders a renderer can't do without.
+// Panics with the error, which points at the broken line.
+pub fn create_module(device: &wgpu::Device, label: &str, name: &str, defs: &ShaderDefs) -> wgpu::ShaderModule {
+    try_create_module(device, label, name, defs).unwrap_or_else(|e| panic!("{:#}", e))
+}
+
+struct Branch {
+    active: bool,
+    in_else: bool,
+}
+
+// A source's name as `SOURCES` has it, and the source
+type SourceLookup = dyn Fn(&str) -> Option<(&'static str, Arc<str>)>;
+
+struct Preprocessor<'s> {
+    sources: &'s SourceLookup,
+    defs: ShaderDefs,
+    shader: PreprocessedShader,
+}
+
+impl Preprocessor<'_> {
+    fn file(&mut self, name: &str) -> Result<()> {
+        let (name, source) = (self.sources)(name).with_context(|| format!("There's no shader source called {}", name))?;
+        // shared declarations can only appear once
+        if self.shader.files.iter().any(|(file_name, _)| *file_name == name) {
+            return Ok(());
+        }
+        let file = self.shader.files.len();
+        self.shader.files.push((name, source.clone()));
+
+        let mut branches: Vec<Branch> = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            let at = || format!("{}:{}", name, index + 1);
+            let active = branches.iter().all(|branch| branch.active);
+            let directive = line
+                .trim_start()
+                .strip_prefix('#')
+                .filter(|rest| rest.starts_with(|c: char| c.is_ascii_alphabetic()));
+            let Some(directive) = directive else {
+                if active {
+                    let line = self.substitute(line).with_context(at)?;
+                    self.shader.source.push_str(&line);
+                    self.shader.source.push('\n');
+                    self.shader.lines.push((file, index + 1));
+                }
+                continue;
+            };
+
+            // directives can have a comment after them
+            let directive = directive.split("//").next().unwrap_or_default().trim_end();
+            let (keyword, argument) = directive
+                .split_once(char::is_whitespace)
+                .map_or((directive, ""), |(keyword, argument)| (keyword, argument.trim()));
+            match keyword {
+                "ifdef" | "ifndef" => {
+                    ensure!(!argument.is_empty(), "{}: #{} needs a name", at(), keyword);
+                    let defined = self.defs.is_defined(argument);
+                    branches.push(Branch {
+                        active: defined == (keyword == "ifdef"),
+                        in_else: false,
+                    });
+                }
+                "else" => {
+                    let branch = branches
+                        .last_mut()
+                        .filter(|branch| !branch.in_else)
+                        .with_context(|| format!("{}: #else without #ifdef", at()))?;
+                    branch.active = !branch.active;
+                    branch.in_else = true;
+                }
+                "endif" => {
+                    branches
+                        .pop()
+                        .with_context(|| format!("{}: #endif without #ifdef", at()))?;
+                }
+                _ if !active => {}
+                "define" => {
+                    let (define, value) = argument
+                        .split_once(char::is_whitespace)
+                        .map_or((argument, ""), |(define, value)| (define, value.trim()));
+                    ensure!(!define.is_empty(), "{}: #define needs a name", at());
+                    self.defs.0.insert(define.to_owned(), value.to_owned());
+                }
+                "include" => {
+                    let include = argument
+                        .strip_prefix('"')
+                        .and_then(|argument| argument.strip_suffix('"'))
+                        .with_context(|| format!("{}: expected #include \"file.wgsl\"", at()))?;
+                    self.file(include).with_context(|| format!("included from {}", at()))?;
+                }
+                _ => bail!("{}: unknown directive #{}", at(), keyword),
+            }
+        }
+        ensure!(branches.is_empty(), "{}: #ifdef without #endif", name);
+        Ok(())
+    }
+
+    // Replaces every `#{NAME}` in `line`
+    fn substitute(&self, line: &str) -> Result<String> {
+        let mut output = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find("#{") {
+            output.push_str(&rest[..start]);
+            let (name, after) = rest[start + 2..]
+                .split_once('}')
+                .context("#{ without a closing }")?;
+            let value = self.defs.0.get(name).with_context(|| format!("{} isn't defined", name))?;
+            output.push_str(value);
+            rest = after;
+        }
+        output.push_str(rest);
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_SOURCES: &[(&str, &str)] = &[
+        ("common.wgsl", "const COMMON: f32 = 1.0;"),
+        ("a.wgsl", "#include \"common.wgsl\"\nfn a() {}"),
+        ("b.wgsl", "#include \"common.wgsl\"\nfn b() {}"),
+        ("both.wgsl", "#include \"a.wgsl\"\n#include \"b.wgsl\"\nfn main() {}"),
+        (
+            "nested.wgsl",
+            "#ifdef OUTER\nouter\n#ifdef INNER\ninner\n#else\nnot_inner\n#endif\n#else\nnot_outer\n#endif\nafter",
+        ),
+        ("define.wgsl", "#define SIZE 4\nconst MAX_SIZE: u32 = #{SIZE}u;\nlet SIZE_X = SIZE;"),
+        ("undefined.wgsl", "let x = #{MISSING};"),
+        ("endif.wgsl", "fn main() {}\n#endif"),
+        ("unclosed.wgsl", "#ifdef A\nfn main() {}"),
+        ("double_else.wgsl", "#ifdef A\n#else\n#else\n#endif"),
+        ("missing_include.wgsl", "#include \"nowhere.wgsl\""),
+        (
+            "inactive.wgsl",
+            "#ifndef A\n#define B 2\n#endif\n#ifdef A\n#define B 3\n#include \"nowhere.wgsl\"\n#endif\n#{B}",
+        ),
+    ];
+
+    fn test_source(name: &str) -> Option<(&'static str, Arc<str>)> {
+        let &(name, source) = TEST_SOURCES.iter().find(|(source_name, _)| *source_name == name)?;
+        Some((name, source.into()))
+    }
+
+    fn run(name: &str, defs: &ShaderDefs) -> Result<PreprocessedShader> {
+        preprocess_from(name, defs, &test_source)
+    }
+
+    fn lines(name: &str, defs: &ShaderDefs) -> Vec<String> {
+        run(name, defs).unwrap().source().lines().map(str::to_owned).collect()
+    }
+
+    #[test]
+    fn includes_each_file_once() {
+        let shader = run("both.wgsl", &ShaderDefs::new()).unwrap();
+        assert_eq!(shader.source(), "const COMMON: f32 = 1.0;\nfn a() {}\nfn b() {}\nfn main() {}\n");
+        // every line knows where it came from
+        assert_eq!(shader.origin(1), Some(("common.wgsl", 1)));
+        assert_eq!(shader.origin(2), Some(("a.wgsl", 2)));
+        assert_eq!(shader.origin(3), Some(("b.wgsl", 2)));
+        assert_eq!(shader.origin(4), Some(("both.wgsl", 3)));
+        assert_eq!(shader.origin(5), None);
+    }
+
+    #[test]
+    fn nested_branches() {
+        let none = ShaderDefs::new();
+        assert_eq!(lines("nested.wgsl", &none), ["not_outer", "after"]);
+        let outer = ShaderDefs::new().with("OUTER");
+        assert_eq!(lines("nested.wgsl", &outer), ["outer", "not_inner", "after"]);
+        let both = ShaderDefs::new().with("OUTER").with("INNER");
+        assert_eq!(lines("nested.wgsl", &both), ["outer", "inner", "after"]);
+        // an inner define means nothing inside an inactive outer branch
+        let inner = ShaderDefs::new().with("INNER");
+        assert_eq!(lines("nested.wgsl", &inner), ["not_outer", "after"]);
+    }
+
+    #[test]
+    fn defines_only_replace_placeholders() {
+        // SIZE in MAX_SIZE, SIZE_X and on its own is left alone
+        let lines = lines("define.wgsl", &ShaderDefs::new());
+        assert_eq!(lines, ["const MAX_SIZE: u32 = 4u;", "let SIZE_X = SIZE;"]);
+    }
+
+    #[test]
+    fn defs_passed_in_substitute() {
+        let defs = ShaderDefs::new().with_value("MISSING", 7);
+        assert_eq!(lines("undefined.wgsl", &defs), ["let x = 7;"]);
+        assert!(run("undefined.wgsl", &ShaderDefs::new()).is_err());
+    }
+
+    #[test]
+    fn inactive_branches_skip_directives() {
+        assert_eq!(lines("inactive.wgsl", &ShaderDefs::new()), ["2"]);
+        assert!(run("inactive.wgsl", &ShaderDefs::new().with("A")).is_err());
+    }
+
+    #[test]
+    fn unbalanced_branches_are_errors() {
+        let defs = ShaderDefs::new();
+        let error = format!("{:#}", run("endif.wgsl", &defs).err().unwrap());
+        assert!(error.contains("endif.wgsl:2: #endif without #ifdef"), "{}", error);
+        let error = format!("{:#}", run("unclosed.wgsl", &defs).err().unwrap());
+        assert!(error.contains("#ifdef without #endif"), "{}", error);
+        let error = format!("{:#}", run("double_else.wgsl", &defs).err().unwrap());
+        assert!(error.contains("double_else.wgsl:3: #else without #ifdef"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_includes_are_errors() {
+        let error = format!("{:#}", run("missing_include.wgsl", &ShaderDefs::new()).err().unwrap());
+        assert!(error.contains("There's no shader source called nowhere.wgsl"), "{}", error);
+        assert!(error.contains("included from missing_include.wgsl:1"), "{}", error);
+    }
+}
@@ -0,0 +1,421 @@
+use std::num::{NonZeroU32, NonZeroU64};
+
+use anyhow::*;
+
+use crate::shader_preprocessor::PreprocessedShader;
+use crate::shader_validation;
+
+// What a shader says about its own interface, read from naga's IR, so bind
+// group layouts and the Rust structs behind its buffers don't have to be
+// kept in step with the WGSL by hand. Layouts used by one shader only can be
+// built from it; shared ones (the camera, materials, per-draw data) are
+// checked against every shader that binds them when its pipeline is created.
+pub struct ShaderReflection {
+    module: naga::Module,
+    info: naga::valid::ModuleInfo,
+    layouter: naga::proc::Layouter,
+}
+
+impl ShaderReflection {
+    pub fn new(shader: &PreprocessedShader) -> Result<Self> {
+        let (module, info) = shader_validation::validate(shader)?;
+        let mut layouter = naga::proc::Layouter::default();
+        layouter.update(module.to_ctx())?;
+        Ok(Self { module, info, layouter })
+    }
+
+    // Layout entries for every binding in `group`, visible to the stages
+    // whose entry points use them. Binding arrays declared without a size
+    // need their `count` filled in, and dynamic offsets are up to the caller.
+    pub fn group_entries(&self, group: u32) -> Result<Vec<wgpu::BindGroupLayoutEntry>> {
+        self.bindings(group)
+            .map(|(handle, variable, binding)| {
+                let (ty, count) = self
+                    .binding_type(variable)
+                    .with_context(|| format!("Binding {} of group {}", binding, group))?;
+                Ok(wgpu::BindGroupLayoutEntry {
+                    binding,
+                    visibility: self.visibility(handle),
+                    ty,
+                    count,
+                })
+            })
+            .collect()
+    }
+
+    pub fn create_bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        label: &str,
+        group: u32,
+    ) -> Result<wgpu::BindGroupLayout> {
+        let entries = self.group_entries(group)?;
+        ensure!(!entries.is_empty(), "The shader has no bindings in group {}", group);
+        Ok(device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(label),
+            entries: &entries,
+        }))
+    }
+
+    // Checks that a layout built by hand has everything the shader's
+    // `group` needs: every binding, of a compatible type, visible to the
+    // stages that use it
+    pub fn check_group(&self, group: u32, entries: &[wgpu::BindGroupLayoutEntry]) -> Result<()> {
+        for (handle, variable, binding) in self.bindings(group) {
+            let name = variable.name.as_deref().unwrap_or("?");
+            let at = || format!("`{}` at group {} binding {}", name, group, binding);
+            let (expected, _) = self.binding_type(variable).with_context(at)?;
+            let entry = entries
+                .iter()
+                .find(|entry| entry.binding == binding)
+                .with_context(|| format!("The layout has nothing for {}", at()))?;
+            let visibility = self.visibility(handle);
+            ensure!(
+                entry.visibility.contains(visibility),
+                "{} is used in {:?} but the layout is only visible to {:?}",
+                at(),
+                visibility,
+                entry.visibility
+            );
+            ensure!(
+                compatible(&expected, &entry.ty),
+                "{} is {:?} in the shader but {:?} in the layout",
+                at(),
+                expected,
+                entry.ty
+            );
+        }
+        Ok(())
+    }
+
+    // Checks that `T` has the size of the WGSL struct `name`, which catches
+    // fields added on one side only and padding that doesn't line up
+    pub fn check_struct<T>(&self, name: &str) -> Result<()> {
+        let (handle, _) = self
+            .module
+            .types
+            .iter()
+            .find(|(_, ty)| ty.name.as_deref() == Some(name))
+            .with_context(|| format!("The shader has no struct {}", name))?;
+        let wgsl = self.layouter[handle].size as usize;
+        let rust = std::mem::size_of::<T>();
+        ensure!(
+            rust == wgsl,
+            "{} is {} bytes but {} in the shader is {}",
+            std::any::type_name::<T>(),
+            rust,
+            name,
+            wgsl
+        );
+        Ok(())
+    }
+
+    // Checks that `T` has the size of the buffer binding at `group` and
+    // `binding`, or of one element when it's a runtime sized array
+    pub fn check_buffer<T>(&self, group: u32, binding: u32) -> Result<()> {
+        let (_, variable, _) = self
+            .bindings(group)
+            .find(|&(_, _, b)| b == binding)
+            .with_context(|| format!("The shader has nothing at group {} binding {}", group, binding))?;
+        let wgsl = match self.module.types[variable.ty].inner {
+            naga::TypeInner::Array {
+                size: naga::ArraySize::Dynamic,
+                stride,
+                ..
+            } => stride,
+            _ => self.layouter[variable.ty].size,
+        } as usize;
+        let rust = std::mem::size_of::<T>();
+        ensure!(
+            rust == wgsl,
+            "{} is {} bytes but `{}` at group {} binding {} takes {}",
+            std::any::type_name::<T>(),
+            rust,
+            variable.name.as_deref().unwrap_or("?"),
+            group,
+            binding,
+            wgsl
+        );
+        Ok(())
+    }
+
+    // Checks that `buffers` provide every input of the vertex entry point
+    // `entry_point`, with formats of the same scalar kind
+    pub fn check_vertex_buffers(&self, entry_point: &str, buffers: &[wgpu::VertexBufferLayout]) -> Result<()> {
+        let entry = self
+            .module
+            .entry_points
+            .iter()
+            .find(|entry| entry.name == entry_point && entry.stage == naga::ShaderStage::Vertex)
+            .with_context(|| format!("The shader has no vertex entry point {}", entry_point))?;
+        let mut inputs = Vec::new();
+        for argument in &entry.function.arguments {
+            self.collect_inputs(argument.ty, argument.binding.as_ref(), &mut inputs);
+        }
+        for (location, kind) in inputs {
+            let attribute = buffers
+                .iter()
+                .flat_map(|buffer| buffer.attributes)
+                .find(|attribute| attribute.shader_location == location)
+                .with_context(|| format!("No vertex buffer has @location({}) of {}", location, entry_point))?;
+            ensure!(
+                vertex_format_kind(attribute.format) == kind,
+                "@location({}) of {} is {:?} in the shader but {:?} in the vertex buffer",
+                location,
+                entry_point,
+                kind,
+                attribute.format
+            );
+        }
+        Ok(())
+    }
+
+    fn bindings(&self, group: u32) -> impl Iterator<Item = (naga::Handle<naga::GlobalVariable>, &naga::GlobalVariable, u32)> {
+        self.module
+            .global_variables
+            .iter()
+            .filter_map(move |(handle, variable)| match variable.binding {
+                Some(naga::ResourceBinding { group: g, binding }) if g == group => Some((handle, variable, binding)),
+                _ => None,
+            })
+    }
+
+    fn visibility(&self, handle: naga::Handle<naga::GlobalVariable>) -> wgpu::ShaderStages {
+        self.module
+            .entry_points
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| !self.info.get_entry_point(i)[handle].is_empty())
+            .fold(wgpu::ShaderStages::NONE, |stages, (_, entry)| {
+                stages
+                    | match entry.stage {
+                        naga::ShaderStage::Vertex => wgpu::ShaderStages::VERTEX,
+                        naga::ShaderStage::Fragment => wgpu::ShaderStages::FRAGMENT,
+                        naga::ShaderStage::Compute => wgpu::ShaderStages::COMPUTE,
+                    }
+            })
+    }
+
+    fn binding_type(&self, variable: &naga::GlobalVariable) -> Result<(wgpu::BindingType, Option<NonZeroU32>)> {
+        let size = NonZeroU64::new(self.layouter[variable.ty].size as u64);
+        let ty = match variable.space {
+            naga::AddressSpace::Uniform => wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: size,
+            },
+            // runtime sized arrays count as one element
+            naga::AddressSpace::Storage { access } => wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage {
+                    read_only: !access.contains(naga::StorageAccess::STORE),
+                },
+                has_dynamic_offset: false,
+                min_binding_size: size,
+            },
+            naga::AddressSpace::Handle => {
+                let (ty, count) = match self.module.types[variable.ty].inner {
+                    naga::TypeInner::BindingArray { base, size } => {
+                        let count = match size {
+                            naga::ArraySize::Constant(count) => Some(count),
+                            naga::ArraySize::Dynamic => None,
+                        };
+                        (base, count)
+                    }
+                    _ => (variable.ty, None),
+                };
+                return Ok((handle_type(&self.module.types[ty].inner)?, count));
+            }
+            space => bail!("{:?} variables can't be bound", space),
+        };
+        Ok((ty, None))
+    }
+
+    fn collect_inputs(
+        &self,
+        ty: naga::Handle<naga::Type>,
+        binding: Option<&naga::Binding>,
+        inputs: &mut Vec<(u32, naga::ScalarKind)>,
+    ) {
+        match (binding, &self.module.types[ty].inner) {
+            (Some(naga::Binding::Location { location, .. }), inner) => {
+                if let Some(scalar) = inner.scalar() {
+                    inputs.push((*location, scalar.kind));
+                }
+            }
+            (None, naga::TypeInner::Struct { members, .. }) => {
+                for member in members {
+                    self.collect_inputs(member.ty, member.binding.as_ref(), inputs);
+                }
+            }
+            // builtins like the vertex index
+            _ => {}
+        }
+    }
+}
+
+fn handle_type(inner: &naga::TypeInner) -> Result<wgpu::BindingType> {
+    Ok(match *inner {
+        naga::TypeInner::Sampler { comparison } => wgpu::BindingType::Sampler(if comparison {
+            wgpu::SamplerBindingType::Comparison
+        } else {
+            wgpu::SamplerBindingType::Filtering
+        }),
+        naga::TypeInner::Image { dim, arrayed, class } => {
+            let view_dimension = match (dim, arrayed) {
+                (naga::ImageDimension::D1, _) => wgpu::TextureViewDimension::D1,
+                (naga::ImageDimension::D2, false) => wgpu::TextureViewDimension::D2,
+                (naga::ImageDimension::D2, true) => wgpu::TextureViewDimension::D2Array,
+                (naga::ImageDimension::D3, _) => wgpu::TextureViewDimension::D3,
+                (naga::ImageDimension::Cube, false) => wgpu::TextureViewDimension::Cube,
+                (naga::ImageDimension::Cube, true) => wgpu::TextureViewDimension::CubeArray,
+            };
+            match class {
+                // the shader can't say whether the format is filterable,
+                // assume it is like the samplers here
+                naga::ImageClass::Sampled { kind, multi } => wgpu::BindingType::Texture {
+                    sample_type: match kind {
+                        naga::ScalarKind::Sint => wgpu::TextureSampleType::Sint,
+                        naga::ScalarKind::Uint => wgpu::TextureSampleType::Uint,
+                        _ => wgpu::TextureSampleType::Float { filterable: !multi },
+                    },
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Depth { multi } => wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Depth,
+                    view_dimension,
+                    multisampled: multi,
+                },
+                naga::ImageClass::Storage { format, access } => wgpu::BindingType::StorageTexture {
+                    access: match (access.contains(naga::StorageAccess::LOAD), access.contains(naga::StorageAccess::STORE)) {
+                        (true, true) => wgpu::StorageTextureAccess::ReadWrite,
+                        (true, false) => wgpu::StorageTextureAccess::ReadOnly,
+                        _ => wgpu::StorageTextureAccess::WriteOnly,
+                    },
+                    format: storage_format(format),
+                    view_dimension,
+                },
+            }
+        }
+        ref inner => bail!("Can't bind a {:?}", inner),
+    })
+}
+
+// Whether a layout entry can stand in for what the shader declares. Buffers
+// may be larger and writable where the shader only reads; filtering and
+// binding sizes are left to wgpu.
+fn compatible(shader: &wgpu::BindingType, layout: &wgpu::BindingType) -> bool {
+    use wgpu::BindingType::*;
+
+    match (shader, layout) {
+        (
+            Buffer {
+                ty: shader_ty,
+                min_binding_size: shader_size,
+                ..
+            },
+            Buffer {
+                ty: layout_ty,
+                min_binding_size: layout_size,
+                ..
+            },
+        ) => {
+            let ty = match (shader_ty, layout_ty) {
+                (
+                    wgpu::BufferBindingType::Storage { read_only: shader_read_only },
+                    wgpu::BufferBindingType::Storage { read_only: layout_read_only },
+                ) => *shader_read_only || !layout_read_only,
+                (shader_ty, layout_ty) => shader_ty == layout_ty,
+            };
+            let size = match (shader_size, layout_size) {
+                (Some(shader_size), Some(layout_size)) => layout_size >= shader_size,
+                _ => true,
+            };
+            ty && size
+        }
+        (
+            Texture {
+                sample_type: shader_sample,
+                view_dimension: shader_dimension,
+                multisampled: shader_multisampled,
+            },
+            Texture {
+                sample_type: layout_sample,
+                view_dimension: layout_dimension,
+                multisampled: layout_multisampled,
+            },
+        ) => {
+            let sample = match (shader_sample, layout_sample) {
+                (wgpu::TextureSampleType::Float { .. }, wgpu::TextureSampleType::Float { .. }) => true,
+                // depth textures can be sampled as floats
+                (wgpu::TextureSampleType::Float { .. }, wgpu::TextureSampleType::Depth) => true,
+                (shader_sample, layout_sample) => shader_sample == layout_sample,
+            };
+            sample && shader_dimension == layout_dimension && shader_multisampled == layout_multisampled
+        }
+        (Sampler(wgpu::SamplerBindingType::Comparison), Sampler(layout)) => {
+            *layout == wgpu::SamplerBindingType::Comparison
+        }
+        (Sampler(_), Sampler(layout)) => *layout != wgpu::SamplerBindingType::Comparison,
+        (shader, layout) => shader == layout,
+    }
+}
+
+fn vertex_format_kind(format: wgpu::VertexFormat) -> naga::ScalarKind {
+    use wgpu::VertexFormat::*;
+
+    match format {
+        Uint8x2 | Uint8x4 | Uint16x2 | Uint16x4 | Uint32 | Uint32x2 | Uint32x3 | Uint32x4 => naga::ScalarKind::Uint,
+        Sint8x2 | Sint8x4 | Sint16x2 | Sint16x4 | Sint32 | Sint32x2 | Sint32x3 | Sint32x4 => naga::ScalarKind::Sint,
+        // normalized formats read as floats
+        _ => naga::ScalarKind::Float,
+    }
+}
+
+fn storage_format(format: naga::StorageFormat) -> wgpu::TextureFormat {
+    use naga::StorageFormat as S;
+    use wgpu::TextureFormat as T;
+
+    match format {
+        S::R8Unorm => T::R8Unorm,
+        S::R8Snorm => T::R8Snorm,
+        S::R8Uint => T::R8Uint,
+        S::R8Sint => T::R8Sint,
+        S::R16Uint => T::R16Uint,
+        S::R16Sint => T::R16Sint,
+        S::R16Float => T::R16Float,
+        S::Rg8Unorm => T::Rg8Unorm,
+        S::Rg8Snorm => T::Rg8Snorm,
+        S::Rg8Uint => T::Rg8Uint,
+        S::Rg8Sint => T::Rg8Sint,
+        S::R32Uint => T::R32Uint,
+        S::R32Sint => T::R32Sint,
+        S::R32Float => T::R32Float,
+        S::Rg16Uint => T::Rg16Uint,
+        S::Rg16Sint => T::Rg16Sint,
+        S::Rg16Float => T::Rg16Float,
+        S::Rgba8Unorm => T::Rgba8Unorm,
+        S::Rgba8Snorm => T::Rgba8Snorm,
+        S::Rgba8Uint => T::Rgba8Uint,
+        S::Rgba8Sint => T::Rgba8Sint,
+        S::Bgra8Unorm => T::Bgra8Unorm,
+        S::Rgb10a2Uint => T::Rgb10a2Uint,
+        S::Rgb10a2Unorm => T::Rgb10a2Unorm,
+        S::Rg11b10Float => T::Rg11b10Float,
+        S::Rg32Uint => T::Rg32Uint,
+        S::Rg32Sint => T::Rg32Sint,
+        S::Rg32Float => T::Rg32Float,
+        S::Rgba16Uint => T::Rgba16Uint,
+        S::Rgba16Sint => T::Rgba16Sint,
+        S::Rgba16Float => T::Rgba16Float,
+        S::Rgba32Uint => T::Rgba32Uint,
+        S::Rgba32Sint => T::Rgba32Sint,
+        S::Rgba32Float => T::Rgba32Float,
+        S::R16Unorm => T::R16Unorm,
+        S::R16Snorm => T::R16Snorm,
+        S::Rg16Unorm => T::Rg16Unorm,
+        S::Rg16Snorm => T::Rg16Snorm,
+        S::Rgba16Unorm => T::Rgba16Unorm,
+        S::Rgba16Snorm => T::Rgba16Snorm,
+    }
+}
@@ -0,0 +1,76 @@
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::shader_preprocessor;
+
+// How often the sources are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Watches the WGSL files in the source tree and loads edits into the
+/// preprocessor, so shaders can be changed without a rebuild. Polls
+/// modification times, which is plenty for a handful of files.
+pub struct ShaderWatcher {
+    dir: PathBuf,
+    modified: Vec<(&'static str, Option<SystemTime>)>,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    // Watches `LEARN_WGPU_SHADER_DIR`, or the `src` directory of this
+    // checkout. None where there's nothing to watch: on the web, on Android
+    // or when the binary has moved away from its sources.
+    pub fn new() -> Option<Self> {
+        if cfg!(any(target_arch = "wasm32", target_os = "android")) {
+            return None;
+        }
+        let dir = std::env::var_os("LEARN_WGPU_SHADER_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("src"));
+        if !dir.is_dir() {
+            return None;
+        }
+        let modified = shader_preprocessor::source_names()
+            .map(|name| (name, modified_time(&dir.join(name))))
+            .collect();
+        log::info!("Watching {} for shader changes", dir.display());
+        Some(Self {
+            dir,
+            modified,
+            last_poll: Instant::now(),
+        })
+    }
+
+    // Call once a frame. True when a source changed since the last check,
+    // so pipelines built from it should be rebuilt.
+    pub fn poll(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = false;
+        for (name, modified) in &mut self.modified {
+            let path = self.dir.join(*name);
+            let time = modified_time(&path);
+            if time == *modified {
+                continue;
+            }
+            *modified = time;
+            match std::fs::read_to_string(&path) {
+                Ok(source) => {
+                    log::info!("Reloading {}", name);
+                    if let Err(e) = shader_preprocessor::set_source(name, source) {
+                        log::warn!("{:#}", e);
+                    }
+                    changed = true;
+                }
+                Err(e) => log::warn!("Failed to read {}: {}", path.display(), e),
+            }
+        }
+        changed
+    }
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
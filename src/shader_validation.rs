@@ -0,0 +1,94 @@
+use anyhow::*;
+
+use crate::shader_preprocessor::PreprocessedShader;
+
+// Lines of the file shown before and after a reported one
+const CONTEXT_LINES: usize = 2;
+
+// Runs naga over a preprocessed shader before wgpu sees it. Errors are
+// reported against the file and line they're in, with an excerpt of that
+// file and the offending span underlined:
+//
+//   expected ';', found 'return'
+//     --> material.wgsl:18:5
+//      |
+//   17 |     let uv = clamp(uv, 0.0, 1.0)
+//   18 |     return textureSample(material_textures, material_sampler, uv, index);
+//      |     ^^^^^^ expected ';'
+//
+// Validation assumes every capability, the device's own limits are left to
+// wgpu. The module comes back for reflection.
+pub fn validate(shader: &PreprocessedShader) -> Result<(naga::Module, naga::valid::ModuleInfo)> {
+    let module = naga::front::wgsl::parse_str(shader.source()).map_err(|e| {
+        let labels: Vec<_> = e.labels().map(|(span, label)| (span, label.to_owned())).collect();
+        report(shader, e.message(), &labels)
+    })?;
+    let info = naga::valid::Validator::new(naga::valid::ValidationFlags::all(), naga::valid::Capabilities::all())
+        .validate(&module)
+        .map_err(|e| {
+            let labels: Vec<_> = e.spans().cloned().collect();
+            report(shader, &error_chain(e.as_inner()), &labels)
+        })?;
+    Ok((module, info))
+}
+
+// naga's validation errors nest, from the function down to the expression
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut message = error.to_string();
+    let mut source = error.source();
+    while let Some(inner) = source {
+        message.push_str(&format!(": {}", inner));
+        source = inner.source();
+    }
+    message
+}
+
+fn report(shader: &PreprocessedShader, message: &str, labels: &[(naga::Span, String)]) -> Error {
+    let mut report = message.to_owned();
+    for (span, label) in labels {
+        if span.to_range().is_none() {
+            continue;
+        }
+        let location = span.location(shader.source());
+        // errors at the very end point past the last line
+        let line_count = shader.source().lines().count();
+        let line = (location.line_number as usize).min(line_count);
+        let (Some((file, file_line)), Some(output_line)) = (shader.origin(line), line.checked_sub(1).and_then(|i| shader.source().lines().nth(i)))
+        else {
+            continue;
+        };
+        let Some(file_source) = shader.file_source(file) else {
+            continue;
+        };
+
+        // naga counts in bytes, the underline in characters
+        let start = if line < location.line_number as usize {
+            output_line.len()
+        } else {
+            (location.line_position as usize - 1).min(output_line.len())
+        };
+        let end = (start + location.length as usize).min(output_line.len());
+        let (Some(before), Some(spanned)) = (output_line.get(..start), output_line.get(start..end)) else {
+            continue;
+        };
+        let column = before.chars().count() + 1;
+
+        let first = file_line.saturating_sub(CONTEXT_LINES).max(1);
+        let last = file_line + CONTEXT_LINES;
+        let width = last.to_string().len();
+        report.push_str(&format!("\n{:width$}--> {}:{}:{}", "", file, file_line, column, width = width));
+        report.push_str(&format!("\n{:width$} |", "", width = width));
+        for (number, text) in file_source.lines().enumerate().map(|(i, text)| (i + 1, text)) {
+            if number < first || number > last {
+                continue;
+            }
+            report.push_str(&format!("\n{:>width$} | {}", number, text, width = width));
+            if number == file_line {
+                let underline = "^".repeat(spanned.chars().count().max(1));
+                let marker = format!("{}{} {}", " ".repeat(column - 1), underline, label);
+                report.push_str(&format!("\n{:width$} | {}", "", marker.trim_end(), width = width));
+            }
+        }
+    }
+    anyhow!(report)
+}
@@ -0,0 +1,316 @@
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use crate::bounds::Aabb;
+use crate::camera::{self, CameraUniform, OPENGL_TO_WGPU_MATRIX};
+use crate::depth_prepass;
+use crate::draw_list::TracedPass;
+use crate::resources::Tracked;
+
+// casters are pushed back by this, in depth units and per unit of slope,
+// so surfaces don't shadow themselves
+pub const DEPTH_BIAS: wgpu::DepthBiasState = wgpu::DepthBiasState {
+    constant: 2,
+    slope_scale: 2.0,
+    clamp: 0.0,
+};
+// receivers look the map up this many texels off their surface
+const NORMAL_OFFSET_TEXELS: f32 = 1.5;
+
+/// How big the shadow map is, sharper shadows for four times the memory
+/// and fill a step up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ShadowQuality {
+    Low,
+    #[default]
+    Medium,
+    High,
+}
+
+impl ShadowQuality {
+    pub fn map_size(self) -> u32 {
+        match self {
+            ShadowQuality::Low => 1024,
+            ShadowQuality::Medium => 2048,
+            ShadowQuality::High => 4096,
+        }
+    }
+}
+
+// Matches `ShadowUniform` in shadow.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadowUniform {
+    view_proj: [[f32; 4]; 4],
+    texel_size: f32,
+    normal_offset: f32,
+    _padding: [f32; 2],
+}
+
+// One shadow map for the directional light, fitted around what receives
+// shadows. Casters are drawn into it from the light, in `begin`'s pass, with
+// pipelines on `caster_layout` that take the light as their camera at group
+// 0. Receivers bind `bind_group` and sample it with shadow.wgsl.
+pub struct ShadowMap {
+    quality: ShadowQuality,
+    // in texels, the quality's size or as much of it as the device allows
+    size: u32,
+    view: Tracked<wgpu::TextureView>,
+    light_buffer: wgpu::Buffer,
+    light_layout: wgpu::BindGroupLayout,
+    light_bind_group: wgpu::BindGroup,
+    caster_layout: wgpu::PipelineLayout,
+    instance_pipeline: wgpu::RenderPipeline,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup,
+    // the light's, once `update` has fitted the map
+    view_proj: Option<Matrix4<f32>>,
+}
+
+impl ShadowMap {
+    // `instance_layouts` are those of the instanced meshes, which cast
+    // shadows through `instance_pipeline`
+    pub fn new(device: &wgpu::Device, instance_layouts: &[wgpu::VertexBufferLayout], quality: ShadowQuality) -> Self {
+        let size = map_size(device, quality);
+        let view = map_view(device, size);
+
+        // only the camera, casters have no use for the fog next to it
+        let light_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Light Bind Group Layout"),
+            entries: &camera::layout_entries()[..1],
+        });
+        let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Light Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Shadow Light Bind Group"),
+            layout: &light_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            }],
+        });
+        let caster_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Shadow Caster Pipeline Layout"),
+            bind_group_layouts: &[&light_layout],
+            push_constant_ranges: &[],
+        });
+        let instance_pipeline =
+            depth_prepass::depth_only_pipeline(device, "Shadow Instance Pipeline", &caster_layout, instance_layouts, DEPTH_BIAS);
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shadow Buffer"),
+            contents: bytemuck::cast_slice(&[ShadowUniform {
+                view_proj: Matrix4::identity().into(),
+                texel_size: 1.0 / size as f32,
+                normal_offset: 0.0,
+                _padding: [0.0; 2],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Shadow Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+            ],
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Shadow Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+        let bind_group = create_bind_group(device, &layout, &uniform_buffer, &view, &sampler);
+
+        Self {
+            quality,
+            size,
+            view,
+            light_buffer,
+            light_layout,
+            light_bind_group,
+            caster_layout,
+            instance_pipeline,
+            uniform_buffer,
+            layout,
+            sampler,
+            bind_group,
+            view_proj: None,
+        }
+    }
+
+    pub fn quality(&self) -> ShadowQuality {
+        self.quality
+    }
+
+    // Replaces the map with one of the new size, empty until casters are
+    // drawn into it again. The bind group is new too, the layout isn't, so
+    // receivers' pipelines keep working. `update` has to be called again
+    // for the normal offset to match.
+    pub fn set_quality(&mut self, device: &wgpu::Device, quality: ShadowQuality) {
+        if quality == self.quality {
+            return;
+        }
+        self.quality = quality;
+        self.size = map_size(device, quality);
+        self.view = map_view(device, self.size);
+        self.bind_group = create_bind_group(device, &self.layout, &self.uniform_buffer, &self.view, &self.sampler);
+    }
+
+    // For receivers' pipelines, and the group they bind
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    // For casters' pipelines, with the light at group 0
+    pub fn caster_layout(&self) -> &wgpu::PipelineLayout {
+        &self.caster_layout
+    }
+
+    // The light's group 0, for casters that need groups of their own after it
+    pub fn light_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.light_layout
+    }
+
+    // Draws the instanced meshes, with the Float32x3 position at location 0
+    // and the instance matrix at locations 5 to 8 that `new` was given
+    pub fn instance_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.instance_pipeline
+    }
+
+    // What the map covers as the light sees it, None until it's fitted.
+    // There's the one map, no cascades.
+    pub fn view_proj(&self) -> Option<Matrix4<f32>> {
+        self.view_proj
+    }
+
+    // Points the light along `light_dir`, towards the light, and fits the
+    // map around `bounds`. Casters between the bounds and the light are
+    // kept as far out as the bounds are across again.
+    pub fn update(&mut self, queue: &wgpu::Queue, light_dir: Vector3<f32>, bounds: Aabb) {
+        let light_dir = light_dir.normalize();
+        let radius = bounds.half_extents().magnitude().max(f32::EPSILON);
+        let eye = bounds.center() + light_dir * radius * 3.0;
+        // anything but straight down works as up
+        let up = if light_dir.y.abs() > 0.99 { Vector3::unit_z() } else { Vector3::unit_y() };
+        let view = Matrix4::look_to_rh(eye, -light_dir, up);
+
+        let corners = bounds.corners().map(|corner| view.transform_point(corner));
+        let min = corners.iter().fold(Point3::from_value(f32::MAX), |m, p| Point3::new(m.x.min(p.x), m.y.min(p.y), m.z.min(p.z)));
+        let max = corners.iter().fold(Point3::from_value(f32::MIN), |m, p| Point3::new(m.x.max(p.x), m.y.max(p.y), m.z.max(p.z)));
+        // view space looks down -z, from the eye to the far side of the bounds
+        let projection = OPENGL_TO_WGPU_MATRIX * ortho(min.x, max.x, min.y, max.y, 0.0, -min.z);
+        let light = CameraUniform::from_view(eye, view, projection);
+        queue.write_buffer(&self.light_buffer, 0, bytemuck::cast_slice(&[light]));
+        self.view_proj = Some(projection * view);
+
+        let texel_world_size = (max.x - min.x).max(max.y - min.y) / self.size as f32;
+        let uniform = ShadowUniform {
+            view_proj: (projection * view).into(),
+            texel_size: 1.0 / self.size as f32,
+            normal_offset: texel_world_size * NORMAL_OFFSET_TEXELS,
+            _padding: [0.0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // The pass casters are drawn in, cleared to the far plane and with the
+    // light bound at group 0
+    pub fn begin<'e>(
+        &'e self,
+        encoder: &'e mut wgpu::CommandEncoder,
+        timestamp_writes: Option<wgpu::RenderPassTimestampWrites<'e>>,
+    ) -> TracedPass<'e> {
+        let mut render_pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Shadow Pass"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+        render_pass.set_bind_group(0, &self.light_bind_group, &[]);
+        render_pass
+    }
+}
+
+// High is past what some mobile GPUs take
+fn map_size(device: &wgpu::Device, quality: ShadowQuality) -> u32 {
+    let max = device.limits().max_texture_dimension_2d;
+    if quality.map_size() > max {
+        log::warn!("{:?} shadows need {} texel maps, this device's limit is {}", quality, quality.map_size(), max);
+    }
+    quality.map_size().min(max)
+}
+
+fn map_view(device: &wgpu::Device, size: u32) -> Tracked<wgpu::TextureView> {
+    depth_prepass::depth_target(device, "Shadow Map", size, size, wgpu::TextureUsages::TEXTURE_BINDING)
+}
+
+fn create_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    uniform_buffer: &wgpu::Buffer,
+    view: &wgpu::TextureView,
+    sampler: &wgpu::Sampler,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("Shadow Bind Group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ],
+    })
+}
@@ -0,0 +1,37 @@
+// Sampling the directional light's shadow map. Matches `ShadowUniform` in
+// shadow.rs, which shaders bind with the map and a comparison sampler.
+struct ShadowUniform {
+    view_proj: mat4x4<f32>,
+    // one texel of the map, in texture coordinates
+    texel_size: f32,
+    // how far off the surface receivers look the map up, in world units,
+    // against acne on slopes facing away from the light
+    normal_offset: f32,
+    _padding: vec2<f32>,
+};
+
+// How much of the light reaches `world_position`, from 0 in shadow to 1,
+// filtered over 3x3 texels. Everything outside the map is lit.
+fn shadow_visibility(
+    shadow: ShadowUniform,
+    map: texture_depth_2d,
+    map_sampler: sampler_comparison,
+    world_position: vec3<f32>,
+    normal: vec3<f32>,
+) -> f32 {
+    let offset = world_position + normalize(normal) * shadow.normal_offset;
+    let clip = shadow.view_proj * vec4<f32>(offset, 1.0);
+    let ndc = clip.xyz / clip.w;
+    let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) || ndc.z > 1.0 {
+        return 1.0;
+    }
+    var lit = 0.0;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let texel = uv + vec2<f32>(f32(x), f32(y)) * shadow.texel_size;
+            lit += textureSampleCompareLevel(map, map_sampler, texel, ndc.z);
+        }
+    }
+    return lit / 9.0;
+}
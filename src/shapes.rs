@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use cgmath::*;
+
+use crate::model::{MeshData, Model, ModelVertex, Submesh};
+
+// the sine of a triangle's angle under which it's left out, as at a
+// sphere's poles, where rounding keeps the corners a little apart
+const DEGENERATE_SINE: f32 = 1e-5;
+
+/// A generated mesh, counter-clockwise from the outside like glTF's, around
+/// the origin with y up and about a unit across. Texture coordinates go
+/// from the top left, and tangents are glTF's: xyz along +u and w the sign
+/// of the bitangent, `cross(normal, tangent) * w`, along +v. Vertices along
+/// seams are doubled for their texture coordinates.
+#[derive(Debug, Clone, Default)]
+pub struct Shape {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tangents: Vec<[f32; 4]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    pub indices: Vec<u32>,
+}
+
+impl Shape {
+    /// A unit cube, each face a grid of `subdivisions` x `subdivisions`
+    /// quads with the whole texture on it.
+    pub fn cube(subdivisions: u32) -> Self {
+        let mut shape = Self::default();
+        let n = subdivisions.max(1);
+        // the face's normal, and its u and v axes
+        let faces = [
+            (Vector3::unit_x(), -Vector3::unit_z(), -Vector3::unit_y()),
+            (-Vector3::unit_x(), Vector3::unit_z(), -Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_x(), Vector3::unit_z()),
+            (-Vector3::unit_y(), Vector3::unit_x(), -Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x(), -Vector3::unit_y()),
+            (-Vector3::unit_z(), -Vector3::unit_x(), -Vector3::unit_y()),
+        ];
+        for (normal, u_axis, v_axis) in faces {
+            shape.push_grid(n, n, |u, v| {
+                let position = normal * 0.5 + u_axis * (u - 0.5) + v_axis * (v - 0.5);
+                (position, normal)
+            });
+        }
+        shape.finish()
+    }
+
+    /// A sphere half a unit across, `segments` around the equator and
+    /// `rings` from pole to pole. The texture wraps around it once,
+    /// equirectangular, with the seam at -x.
+    pub fn uv_sphere(segments: u32, rings: u32) -> Self {
+        let mut shape = Self::default();
+        let segments = segments.max(3);
+        shape.push_grid(segments, rings.max(2), |u, v| {
+            let normal = sphere_point(u, v);
+            (normal * 0.5, normal)
+        });
+        shape.centre_tips(segments);
+        shape.finish()
+    }
+
+    /// A sphere half a unit across from an icosahedron, each triangle split
+    /// in four `subdivisions` times, so its triangles are about the same
+    /// size everywhere. Texture coordinates are the UV sphere's.
+    pub fn icosphere(subdivisions: u32) -> Self {
+        // the corners of three golden rectangles
+        let t = (1.0 + 5f32.sqrt()) / 2.0;
+        let mut points: Vec<Vector3<f32>> = [
+            [-1.0, t, 0.0],
+            [1.0, t, 0.0],
+            [-1.0, -t, 0.0],
+            [1.0, -t, 0.0],
+            [0.0, -1.0, t],
+            [0.0, 1.0, t],
+            [0.0, -1.0, -t],
+            [0.0, 1.0, -t],
+            [t, 0.0, -1.0],
+            [t, 0.0, 1.0],
+            [-t, 0.0, -1.0],
+            [-t, 0.0, 1.0],
+        ]
+        .iter()
+        .map(|&p| Vector3::from(p).normalize())
+        .collect();
+        #[rustfmt::skip]
+        let mut triangles: Vec<[u32; 3]> = vec![
+            [0, 11, 5], [0, 5, 1], [0, 1, 7], [0, 7, 10], [0, 10, 11],
+            [1, 5, 9], [5, 11, 4], [11, 10, 2], [10, 7, 6], [7, 1, 8],
+            [3, 9, 4], [3, 4, 2], [3, 2, 6], [3, 6, 8], [3, 8, 9],
+            [4, 9, 5], [2, 4, 11], [6, 2, 10], [8, 6, 7], [9, 8, 1],
+        ];
+        for _ in 0..subdivisions {
+            let mut midpoints = HashMap::new();
+            let mut midpoint = |a: u32, b: u32| {
+                *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                    points.push((points[a as usize] + points[b as usize]).normalize());
+                    points.len() as u32 - 1
+                })
+            };
+            triangles = triangles
+                .iter()
+                .flat_map(|&[a, b, c]| {
+                    let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                    [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+                })
+                .collect();
+        }
+
+        // the same texture coordinates as `sphere_point`, with the points
+        // on the seam doubled and the poles once for every triangle there
+        let mut shape = Self::default();
+        let mut vertices = HashMap::new();
+        for triangle in triangles {
+            let normals = triangle.map(|i| points[i as usize]);
+            let mut uvs = normals.map(sphere_uv);
+            let wraps = uvs.iter().any(|uv| uv[0] > 0.75) && uvs.iter().any(|uv| uv[0] < 0.25);
+            if wraps {
+                uvs.iter_mut().filter(|uv| uv[0] < 0.5).for_each(|uv| uv[0] += 1.0);
+            }
+            for corner in 0..3 {
+                if normals[corner].y.abs() > 0.9999 {
+                    uvs[corner][0] = (uvs[(corner + 1) % 3][0] + uvs[(corner + 2) % 3][0]) / 2.0;
+                }
+            }
+            let indices = [0, 1, 2].map(|corner| {
+                let key = (triangle[corner], uvs[corner][0].to_bits());
+                *vertices
+                    .entry(key)
+                    .or_insert_with(|| shape.push_vertex(normals[corner] * 0.5, normals[corner], uvs[corner]))
+            });
+            shape.push_triangle(indices);
+        }
+        shape.finish()
+    }
+
+    /// A unit square in the xz plane facing +y, a grid of `subdivisions` x
+    /// `subdivisions` quads. u runs along +x and v along +z.
+    pub fn plane(subdivisions: u32) -> Self {
+        let mut shape = Self::default();
+        let n = subdivisions.max(1);
+        shape.push_grid(n, n, |u, v| (vec3(u - 0.5, 0.0, v - 0.5), Vector3::unit_y()));
+        shape.finish()
+    }
+
+    /// A cylinder along y, a unit tall and half a unit across, `segments`
+    /// around and `rings` from top to bottom, with flat caps. The side
+    /// wraps the texture around once, each cap has it from above or below.
+    pub fn cylinder(segments: u32, rings: u32) -> Self {
+        let mut shape = Self::default();
+        let segments = segments.max(3);
+        shape.push_grid(segments, rings.max(1), |u, v| {
+            let (sin, cos) = (u * TAU).sin_cos();
+            (vec3(-cos * 0.5, 0.5 - v, sin * 0.5), vec3(-cos, 0.0, sin))
+        });
+        shape.push_cap(segments, 0.5, Vector3::unit_y());
+        shape.push_cap(segments, -0.5, -Vector3::unit_y());
+        shape.finish()
+    }
+
+    /// A cone along y with its tip at the top, a unit tall and half a unit
+    /// across the base, `segments` around and `rings` from the tip to the
+    /// base, which is a flat cap.
+    pub fn cone(segments: u32, rings: u32) -> Self {
+        let mut shape = Self::default();
+        let segments = segments.max(3);
+        shape.push_grid(segments, rings.max(1), |u, v| {
+            let (sin, cos) = (u * TAU).sin_cos();
+            // the slope of a 1:0.5 side, the same down its whole length
+            let normal = vec3(-cos, 0.5, sin).normalize();
+            (vec3(-cos * 0.5 * v, 0.5 - v, sin * 0.5 * v), normal)
+        });
+        shape.centre_tips(segments);
+        shape.push_cap(segments, -0.5, -Vector3::unit_y());
+        shape.finish()
+    }
+
+    /// A torus around y, `radius` from its centre to the middle of the
+    /// tube, which is `tube_radius` thick. `segments` go around the ring
+    /// and `tube_segments` around the tube; u runs around the ring and v
+    /// around the tube, from its outside over the top.
+    pub fn torus(radius: f32, tube_radius: f32, segments: u32, tube_segments: u32) -> Self {
+        let mut shape = Self::default();
+        shape.push_grid(segments.max(3), tube_segments.max(3), |u, v| {
+            let (sin, cos) = (u * TAU).sin_cos();
+            let (tube_sin, tube_cos) = (v * TAU).sin_cos();
+            let around = vec3(-cos, 0.0, sin);
+            let normal = around * tube_cos + Vector3::unit_y() * tube_sin;
+            (around * radius + normal * tube_radius, normal)
+        });
+        shape.finish()
+    }
+
+    // As a model's mesh, white and rough, for the tint and the lights to
+    // colour
+    pub fn to_mesh_data(&self, name: &str) -> MeshData {
+        let vertices = (0..self.positions.len())
+            .map(|i| ModelVertex {
+                position: self.positions[i],
+                normal: self.normals[i],
+                tex_coords: self.tex_coords[i],
+                color: [1.0; 3],
+                joints: [0; 4],
+                weights: [1.0, 0.0, 0.0, 0.0],
+                metallic_roughness: [0.0, 1.0],
+            })
+            .collect();
+        MeshData {
+            name: Some(name.to_owned()),
+            vertices,
+            submeshes: vec![Submesh {
+                indices: 0..self.indices.len() as u32,
+                material: None,
+            }],
+            indices: self.indices.clone(),
+            ..Default::default()
+        }
+    }
+
+    // A model of just this shape, for `ModelInstance::new`
+    pub fn to_model(&self, device: &wgpu::Device, name: &str) -> Model {
+        Model::from_meshes(vec![self.to_mesh_data(name).upload(device)], Vec::new())
+    }
+
+    fn push_vertex(&mut self, position: Vector3<f32>, normal: Vector3<f32>, tex_coords: [f32; 2]) -> u32 {
+        self.positions.push(position.into());
+        self.normals.push(normal.into());
+        self.tex_coords.push(tex_coords);
+        self.positions.len() as u32 - 1
+    }
+
+    // Wound to face the way its vertices' normals do, whichever order
+    // they come in, and left out if it has no area
+    fn push_triangle(&mut self, [a, b, c]: [u32; 3]) {
+        let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(self.positions[i as usize]));
+        let (e1, e2) = (pb - pa, pc - pa);
+        let geometric = e1.cross(e2);
+        if geometric.magnitude2() <= DEGENERATE_SINE * DEGENERATE_SINE * e1.magnitude2() * e2.magnitude2() {
+            return;
+        }
+        let normal: Vector3<f32> = [a, b, c].iter().map(|&i| Vector3::from(self.normals[i as usize])).sum();
+        if geometric.dot(normal) < 0.0 {
+            self.indices.extend([a, c, b]);
+        } else {
+            self.indices.extend([a, b, c]);
+        }
+    }
+
+    // A `columns` x `rows` grid of quads, `point` giving the position and
+    // normal at each (u, v) from 0 to 1. The last column and row double the
+    // first ones' positions on closed surfaces, for their own u and v.
+    fn push_grid(&mut self, columns: u32, rows: u32, point: impl Fn(f32, f32) -> (Vector3<f32>, Vector3<f32>)) {
+        let first = self.positions.len() as u32;
+        for row in 0..=rows {
+            for column in 0..=columns {
+                let (u, v) = (column as f32 / columns as f32, row as f32 / rows as f32);
+                let (position, normal) = point(u, v);
+                self.push_vertex(position, normal, [u, v]);
+            }
+        }
+        let index = |column, row| first + row * (columns + 1) + column;
+        for row in 0..rows {
+            for column in 0..columns {
+                self.push_triangle([index(column, row), index(column, row + 1), index(column + 1, row)]);
+                self.push_triangle([index(column + 1, row), index(column, row + 1), index(column + 1, row + 1)]);
+            }
+        }
+    }
+
+    // Moves the grid's vertices on a point at its top or bottom, which each
+    // belong to one triangle, to the middle of it, so the texture isn't
+    // sheared there. The first of them at the top and last at the bottom go
+    // unused.
+    fn centre_tips(&mut self, columns: u32) {
+        let offset = 0.5 / columns as f32;
+        for uv in &mut self.tex_coords {
+            if uv[1] == 0.0 {
+                uv[0] -= offset;
+            } else if uv[1] == 1.0 {
+                uv[0] += offset;
+            }
+        }
+    }
+
+    // A disc at height `y` facing `normal`, half a unit across, as a fan
+    // of `segments` around its centre, textured as seen from that side
+    fn push_cap(&mut self, segments: u32, y: f32, normal: Vector3<f32>) {
+        let flip = normal.y.signum();
+        let centre = self.push_vertex(vec3(0.0, y, 0.0), normal, [0.5, 0.5]);
+        let first = self.positions.len() as u32;
+        for segment in 0..segments {
+            let (sin, cos) = (segment as f32 / segments as f32 * TAU).sin_cos();
+            let (x, z) = (-cos * 0.5, sin * 0.5);
+            self.push_vertex(vec3(x, y, z), normal, [x + 0.5, z * flip + 0.5]);
+        }
+        for segment in 0..segments {
+            self.push_triangle([centre, first + segment, first + (segment + 1) % segments]);
+        }
+    }
+
+    // Tangents from how the texture coordinates run across each triangle,
+    // summed at the vertices and made perpendicular to the normals
+    fn finish(mut self) -> Self {
+        let count = self.positions.len();
+        let mut tangents = vec![Vector3::zero(); count];
+        let mut bitangents = vec![Vector3::zero(); count];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let [pa, pb, pc] = [a, b, c].map(|i| Vector3::from(self.positions[i]));
+            let [ta, tb, tc] = [a, b, c].map(|i| Vector2::from(self.tex_coords[i]));
+            let (e1, e2) = (pb - pa, pc - pa);
+            let (d1, d2) = (tb - ta, tc - ta);
+            let det = d1.x * d2.y - d2.x * d1.y;
+            // no texture across it, like the caps' centres on a pole
+            if det.abs() < 1e-12 {
+                continue;
+            }
+            let tangent = (e1 * d2.y - e2 * d1.y) / det;
+            let bitangent = (e2 * d1.x - e1 * d2.x) / det;
+            for i in [a, b, c] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+        self.tangents = (0..count)
+            .map(|i| {
+                let n = Vector3::from(self.normals[i]);
+                let mut t = tangents[i] - n * n.dot(tangents[i]);
+                if t.magnitude2() < 1e-12 {
+                    // any direction across, where nothing says which
+                    let up = if n.z.abs() < 0.999 { Vector3::unit_z() } else { Vector3::unit_x() };
+                    t = up.cross(n);
+                }
+                let t = t.normalize();
+                let w = if n.cross(t).dot(bitangents[i]) < 0.0 { -1.0 } else { 1.0 };
+                [t.x, t.y, t.z, w]
+            })
+            .collect();
+        self
+    }
+}
+
+// On the unit sphere, u around from -x and v down from the top
+fn sphere_point(u: f32, v: f32) -> Vector3<f32> {
+    let (sin_phi, cos_phi) = (u * TAU).sin_cos();
+    let (sin_theta, cos_theta) = (v * PI).sin_cos();
+    vec3(-cos_phi * sin_theta, cos_theta, sin_phi * sin_theta)
+}
+
+// `sphere_point`'s inverse
+fn sphere_uv(normal: Vector3<f32>) -> [f32; 2] {
+    let u = normal.z.atan2(-normal.x) / TAU;
+    [u.rem_euclid(1.0), normal.y.clamp(-1.0, 1.0).acos() / PI]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shapes() -> Vec<(&'static str, Shape)> {
+        vec![
+            ("cube", Shape::cube(3)),
+            ("uv_sphere", Shape::uv_sphere(16, 8)),
+            ("icosphere", Shape::icosphere(2)),
+            ("plane", Shape::plane(4)),
+            ("cylinder", Shape::cylinder(12, 3)),
+            ("cone", Shape::cone(12, 3)),
+            ("torus", Shape::torus(0.35, 0.15, 16, 8)),
+        ]
+    }
+
+    #[test]
+    fn indices_are_in_bounds() {
+        for (name, shape) in shapes() {
+            let count = shape.positions.len();
+            assert!(!shape.indices.is_empty(), "{}", name);
+            assert_eq!(shape.indices.len() % 3, 0, "{}", name);
+            assert!(shape.indices.iter().all(|&i| (i as usize) < count), "{} indexes past its vertices", name);
+            for attribute in [shape.normals.len(), shape.tangents.len(), shape.tex_coords.len()] {
+                assert_eq!(attribute, count, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_length() {
+        for (name, shape) in shapes() {
+            for normal in &shape.normals {
+                let length = Vector3::from(*normal).magnitude();
+                assert!((length - 1.0).abs() < 1e-4, "{} has a normal {:?} of length {}", name, normal, length);
+            }
+        }
+    }
+
+    #[test]
+    fn tangents_are_orthogonal_to_normals() {
+        for (name, shape) in shapes() {
+            for (normal, tangent) in shape.normals.iter().zip(&shape.tangents) {
+                let (n, t) = (Vector3::from(*normal), vec3(tangent[0], tangent[1], tangent[2]));
+                assert!((t.magnitude() - 1.0).abs() < 1e-4, "{} has a tangent {:?}", name, tangent);
+                assert!(n.dot(t).abs() < 1e-4, "{}: tangent {:?} against normal {:?}", name, tangent, normal);
+                assert!(tangent[3] == 1.0 || tangent[3] == -1.0, "{} has a tangent {:?}", name, tangent);
+            }
+        }
+    }
+
+    #[test]
+    fn triangles_face_out() {
+        for (name, shape) in shapes() {
+            for triangle in shape.indices.chunks_exact(3) {
+                let [a, b, c] = [0, 1, 2].map(|i| Vector3::from(shape.positions[triangle[i] as usize]));
+                let normal: Vector3<f32> = triangle.iter().map(|&i| Vector3::from(shape.normals[i as usize])).sum();
+                assert!((b - a).cross(c - a).dot(normal) > 0.0, "{} has a triangle {:?} facing in", name, triangle);
+            }
+        }
+    }
+}
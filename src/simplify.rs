@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+
+use cgmath::*;
+
+use crate::model::ModelVertex;
+
+// Plane quadric of Garland and Heckbert's error metric: the weighted sum of
+// squared distances to a set of planes, as the upper triangle of a symmetric
+// 4x4 matrix
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric {
+    matrix: [f64; 10],
+    weight: f64,
+}
+
+impl Quadric {
+    fn plane(normal: Vector3<f64>, distance: f64, weight: f64) -> Self {
+        let [a, b, c, d] = [normal.x, normal.y, normal.z, distance];
+        Self {
+            matrix: [a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|q| q * weight),
+            weight,
+        }
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        self.matrix.iter_mut().zip(other.matrix).for_each(|(q, o)| *q += o);
+        self.weight += other.weight;
+    }
+
+    // Mean squared distance to the planes
+    fn error(&self, p: Vector3<f64>) -> f64 {
+        if self.weight == 0.0 {
+            return 0.0;
+        }
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.matrix;
+        let (x, y, z) = (p.x, p.y, p.z);
+        let sum = aa * x * x + 2.0 * ab * x * y + 2.0 * ac * x * z + 2.0 * ad * x
+            + bb * y * y + 2.0 * bc * y * z + 2.0 * bd * y
+            + cc * z * z + 2.0 * cd * z
+            + dd;
+        sum.max(0.0) / self.weight
+    }
+}
+
+// Simplifies a triangle list over `vertices` by collapsing edges, cheapest
+// first by the quadric error metric, until it's down to `target_index_count`
+// indices or the next collapse would move the surface by more than
+// `max_error`, as a fraction of the mesh's size. Vertices only ever collapse
+// onto others, so the result indexes the same vertices and needs no new
+// vertex buffer. Returns the indices and the error it got to.
+//
+// Vertices in the same place are welded for the topology. Open borders stay
+// where they are, and the copies a seam splits a vertex into (by texture
+// coordinates or normals) only collapse along the seam, each onto the
+// copy on its own side.
+pub fn simplify(vertices: &[ModelVertex], indices: &[u32], target_index_count: usize, max_error: f32) -> (Vec<u32>, f32) {
+    let mut mesh = Simplifier::new(vertices, indices);
+    let max_error = max_error as f64 * mesh.scale;
+    let mut error = 0.0;
+    // each pass collapses edges that don't touch each other, with the costs
+    // from the start of the pass
+    while mesh.live_triangles * 3 > target_index_count {
+        let collapsed = mesh.pass(target_index_count, max_error * max_error, &mut error);
+        if collapsed == 0 {
+            break;
+        }
+    }
+    let error = (error.sqrt() / mesh.scale) as f32;
+    (mesh.indices(), error)
+}
+
+struct Simplifier<'v> {
+    vertices: &'v [ModelVertex],
+    triangles: Vec<[u32; 3]>,
+    alive: Vec<bool>,
+    live_triangles: usize,
+    // welded vertex of every vertex used, and the ones each welded vertex
+    // stands for
+    welded: HashMap<u32, usize>,
+    copies: Vec<Vec<u32>>,
+    positions: Vec<Vector3<f64>>,
+    quadrics: Vec<Quadric>,
+    // triangles around each welded vertex, some of them dead
+    around: Vec<Vec<usize>>,
+    locked: Vec<bool>,
+    // the vertex each vertex collapsed onto, itself if it's still there
+    remap: HashMap<u32, u32>,
+    // the mesh's extent, errors are relative to it
+    scale: f64,
+}
+
+impl<'v> Simplifier<'v> {
+    fn new(vertices: &'v [ModelVertex], indices: &[u32]) -> Self {
+        let triangles: Vec<[u32; 3]> = indices.chunks_exact(3).map(|t| [t[0], t[1], t[2]]).collect();
+
+        let mut welded = HashMap::new();
+        let mut by_position: HashMap<[u32; 3], usize> = HashMap::new();
+        let mut copies: Vec<Vec<u32>> = Vec::new();
+        let mut positions = Vec::new();
+        for &index in indices {
+            if welded.contains_key(&index) {
+                continue;
+            }
+            let position = vertices[index as usize].position;
+            let id = *by_position.entry(position.map(f32::to_bits)).or_insert_with(|| {
+                copies.push(Vec::new());
+                positions.push(Vector3::from(position).cast::<f64>().unwrap());
+                copies.len() - 1
+            });
+            copies[id].push(index);
+            welded.insert(index, id);
+        }
+
+        let mut quadrics = vec![Quadric::default(); copies.len()];
+        let mut around = vec![Vec::new(); copies.len()];
+        // directed welded edges, an edge without its reverse is on a border
+        let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+        let mut alive = vec![true; triangles.len()];
+        for (t, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|index| welded[&index]);
+            if a == b || b == c || c == a {
+                alive[t] = false;
+                continue;
+            }
+            let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+            let area = normal.magnitude();
+            if area > 0.0 {
+                let normal = normal / area;
+                // weighted by area, so slivers don't pin the surface down
+                let plane = Quadric::plane(normal, -normal.dot(positions[a]), area);
+                for id in [a, b, c] {
+                    quadrics[id].add(&plane);
+                }
+            }
+            for (from, to) in [(a, b), (b, c), (c, a)] {
+                *edges.entry((from, to)).or_default() += 1;
+                around[from].push(t);
+            }
+        }
+        let mut locked = vec![false; copies.len()];
+        for (&(from, to), &count) in &edges {
+            // open or shared by more than two triangles
+            if count != 1 || edges.get(&(to, from)) != Some(&1) {
+                locked[from] = true;
+                locked[to] = true;
+            }
+        }
+
+        let min = positions.iter().fold(Vector3::from_value(f64::MAX), |m, p| Vector3::new(m.x.min(p.x), m.y.min(p.y), m.z.min(p.z)));
+        let max = positions.iter().fold(Vector3::from_value(f64::MIN), |m, p| Vector3::new(m.x.max(p.x), m.y.max(p.y), m.z.max(p.z)));
+        let scale = (max - min).magnitude().max(f64::EPSILON);
+
+        Self {
+            vertices,
+            live_triangles: alive.iter().filter(|&&alive| alive).count(),
+            alive,
+            triangles,
+            remap: welded.keys().map(|&index| (index, index)).collect(),
+            welded,
+            copies,
+            positions,
+            quadrics,
+            around,
+            locked,
+            scale,
+        }
+    }
+
+    fn welded_triangle(&self, t: usize) -> [usize; 3] {
+        self.triangles[t].map(|index| self.welded[&self.resolve(index)])
+    }
+
+    fn resolve(&self, mut index: u32) -> u32 {
+        while self.remap[&index] != index {
+            index = self.remap[&index];
+        }
+        index
+    }
+
+    // Collapses as many independent edges as it can, returns how many
+    fn pass(&mut self, target_index_count: usize, max_error: f64, error: &mut f64) -> usize {
+        let mut candidates = Vec::new();
+        for t in (0..self.triangles.len()).filter(|&t| self.alive[t]) {
+            let [a, b, c] = self.welded_triangle(t);
+            for (from, to) in [(a, b), (b, c), (c, a), (b, a), (c, b), (a, c)] {
+                if !self.locked[from] && self.copies[from].len() == self.copies[to].len() {
+                    let mut quadric = self.quadrics[from];
+                    quadric.add(&self.quadrics[to]);
+                    candidates.push((quadric.error(self.positions[to]), from, to));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut touched = vec![false; self.copies.len()];
+        let mut collapsed = 0;
+        for (cost, from, to) in candidates {
+            if cost > max_error || self.live_triangles * 3 <= target_index_count {
+                break;
+            }
+            if touched[from] || touched[to] {
+                continue;
+            }
+            let Some(pairs) = self.match_copies(from, to) else {
+                continue;
+            };
+            if self.flips(from, to) {
+                continue;
+            }
+
+            for (copy, onto) in pairs {
+                self.remap.insert(copy, onto);
+            }
+            let quadric = self.quadrics[from];
+            self.quadrics[to].add(&quadric);
+            for t in std::mem::take(&mut self.around[from]) {
+                if !self.alive[t] {
+                    continue;
+                }
+                let triangle = self.welded_triangle(t);
+                for id in triangle {
+                    touched[id] = true;
+                }
+                if triangle[0] == triangle[1] || triangle[1] == triangle[2] || triangle[2] == triangle[0] {
+                    self.alive[t] = false;
+                    self.live_triangles -= 1;
+                } else {
+                    self.around[to].push(t);
+                }
+            }
+            touched[from] = true;
+            touched[to] = true;
+            *error = error.max(cost);
+            collapsed += 1;
+        }
+        collapsed
+    }
+
+    // Which copy of `to` each copy of `from` goes to, the closest by
+    // texture coordinates and normal, None unless they pair up one to one
+    fn match_copies(&self, from: usize, to: usize) -> Option<Vec<(u32, u32)>> {
+        let attributes = |index: u32| {
+            let v = &self.vertices[index as usize];
+            (Vector2::from(v.tex_coords), Vector3::from(v.normal))
+        };
+        let mut pairs = Vec::with_capacity(self.copies[from].len());
+        for &copy in &self.copies[from] {
+            let (uv, normal) = attributes(copy);
+            let onto = self.copies[to]
+                .iter()
+                .copied()
+                .min_by(|&a, &b| {
+                    let distance = |index| {
+                        let (other_uv, other_normal) = attributes(index);
+                        (other_uv - uv).magnitude2() + (other_normal - normal).magnitude2()
+                    };
+                    distance(a).total_cmp(&distance(b))
+                })?;
+            if pairs.iter().any(|&(_, taken)| taken == onto) {
+                return None;
+            }
+            pairs.push((copy, onto));
+        }
+        Some(pairs)
+    }
+
+    // Whether moving `from` onto `to` turns any triangle around it over
+    fn flips(&self, from: usize, to: usize) -> bool {
+        self.around[from].iter().filter(|&&t| self.alive[t]).any(|&t| {
+            let triangle = self.welded_triangle(t);
+            if triangle.contains(&to) {
+                return false;
+            }
+            let [a, b, c] = triangle.map(|id| self.positions[id]);
+            let [a2, b2, c2] = triangle.map(|id| self.positions[if id == from { to } else { id }]);
+            let before = (b - a).cross(c - a);
+            let after = (b2 - a2).cross(c2 - a2);
+            // parallel to within about 75 degrees, and not degenerate
+            after.dot(before) <= 0.25 * before.magnitude() * after.magnitude() || after.magnitude2() == 0.0
+        })
+    }
+
+    fn indices(&self) -> Vec<u32> {
+        (0..self.triangles.len())
+            .filter(|&t| self.alive[t])
+            .flat_map(|t| self.triangles[t].map(|index| self.resolve(index)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn vertex(position: [f32; 3], tex_coords: [f32; 2]) -> ModelVertex {
+        ModelVertex {
+            position,
+            normal: [0.0, 1.0, 0.0],
+            tex_coords,
+            ..bytemuck::Zeroable::zeroed()
+        }
+    }
+
+    // `n` by `n` quads over the unit square at heights from `height`, two
+    // triangles each, facing up
+    fn grid(n: u32, height: impl Fn(f32, f32) -> f32) -> (Vec<ModelVertex>, Vec<u32>) {
+        let mut vertices = Vec::new();
+        for z in 0..=n {
+            for x in 0..=n {
+                let (u, v) = (x as f32 / n as f32, z as f32 / n as f32);
+                vertices.push(vertex([u, height(u, v), v], [u, v]));
+            }
+        }
+        let mut indices = Vec::new();
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                indices.extend([i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+        (vertices, indices)
+    }
+
+    fn flat(_: f32, _: f32) -> f32 {
+        0.0
+    }
+
+    fn on_border(v: &ModelVertex) -> bool {
+        let [x, _, z] = v.position;
+        x == 0.0 || x == 1.0 || z == 0.0 || z == 1.0
+    }
+
+    #[test]
+    fn flat_grid_collapses_to_target() {
+        let (vertices, indices) = grid(16, flat);
+        let target = indices.len() / 4;
+        let (simplified, error) = simplify(&vertices, &indices, target, 0.01);
+        assert!(simplified.len() <= target, "{} indices for a target of {}", simplified.len(), target);
+        assert!(!simplified.is_empty());
+        assert_eq!(simplified.len() % 3, 0);
+        // the grid is flat, so nothing moved off it
+        assert!(error < 1e-6, "error {}", error);
+        // every triangle still faces up
+        for t in simplified.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| Vector3::from(vertices[i as usize].position));
+            assert!((b - a).cross(c - a).y > 0.0);
+        }
+    }
+
+    #[test]
+    fn borders_are_kept() {
+        let (vertices, indices) = grid(8, flat);
+        let (simplified, _) = simplify(&vertices, &indices, 0, 0.01);
+        assert!(simplified.len() < indices.len());
+        let used: HashSet<u32> = simplified.iter().copied().collect();
+        for (i, v) in vertices.iter().enumerate() {
+            if on_border(v) {
+                assert!(used.contains(&(i as u32)), "border vertex {:?} was collapsed", v.position);
+            }
+        }
+    }
+
+    #[test]
+    fn seams_are_kept() {
+        // two halves meeting at x = 0.5, the right one with its own copies
+        // of the seam's vertices and texture coordinates off to the side
+        let (mut vertices, mut indices) = grid(8, flat);
+        let (right, right_indices) = grid(8, flat);
+        let offset = vertices.len() as u32;
+        for v in &mut vertices {
+            v.position[0] *= 0.5;
+        }
+        vertices.extend(right.into_iter().map(|mut v| {
+            v.position[0] = 0.5 + v.position[0] * 0.5;
+            v.tex_coords[0] += 2.0;
+            v
+        }));
+        indices.extend(right_indices.into_iter().map(|i| i + offset));
+
+        let (simplified, _) = simplify(&vertices, &indices, 0, 0.01);
+        assert!(simplified.len() < indices.len());
+        // no triangle picked up a copy from the other side
+        for t in simplified.chunks_exact(3) {
+            let right = t.iter().filter(|&&i| i >= offset).count();
+            assert!(right == 0 || right == 3, "triangle {:?} crosses the seam", t);
+        }
+        // and whatever's left on the seam is still on it
+        let used: HashSet<u32> = simplified.iter().copied().collect();
+        let seam = |side: &dyn Fn(u32) -> bool| {
+            let mut rows: Vec<u32> = used
+                .iter()
+                .filter(|&&i| side(i) && vertices[i as usize].position[0] == 0.5)
+                .map(|&i| (vertices[i as usize].position[2] * 8.0) as u32)
+                .collect();
+            rows.sort();
+            rows
+        };
+        let left_seam = seam(&|i| i < offset);
+        assert_eq!(left_seam, seam(&|i| i >= offset));
+        // the two ends are on the border, so they're there at least
+        assert!(left_seam.contains(&0) && left_seam.contains(&8));
+    }
+
+    #[test]
+    fn error_bound_is_respected() {
+        let bumps = |u: f32, v: f32| 0.05 * (u * 12.0).sin() * (v * 12.0).sin();
+        let (vertices, indices) = grid(16, bumps);
+        let (tight, tight_error) = simplify(&vertices, &indices, 0, 0.001);
+        let (loose, loose_error) = simplify(&vertices, &indices, 0, 0.05);
+        assert!(tight_error <= 0.001, "error {}", tight_error);
+        assert!(loose_error <= 0.05, "error {}", loose_error);
+        // the bound stops it short of the target, sooner when it's tighter
+        assert!(tight.len() > loose.len(), "{} and {} indices", tight.len(), loose.len());
+        assert!(!loose.is_empty());
+    }
+
+    #[test]
+    fn degenerate_triangles_are_dropped() {
+        let (mut vertices, mut indices) = grid(2, flat);
+        // a copy of the first vertex, in the same place
+        vertices.push(vertices[0]);
+        let copy = vertices.len() as u32 - 1;
+        let triangles = indices.len() / 3;
+        indices.extend([0, 0, 1, 0, copy, 3, 4, 4, 4]);
+
+        // nothing to simplify, only to drop
+        let (kept, _) = simplify(&vertices, &indices, indices.len(), 0.0);
+        assert_eq!(kept, indices[..triangles * 3]);
+
+        let (simplified, _) = simplify(&vertices, &indices, 0, 0.01);
+        for t in simplified.chunks_exact(3) {
+            let [a, b, c] = [t[0], t[1], t[2]].map(|i| vertices[i as usize].position);
+            assert!(a != b && b != c && c != a, "degenerate triangle {:?}", t);
+        }
+    }
+}
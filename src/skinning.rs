@@ -0,0 +1,725 @@
+use anyhow::{Context, Result};
+use cgmath::*;
+
+use crate::camera;
+use crate::compute::{self, ComputePipeline, StorageBuffer};
+use crate::depth_prepass;
+use crate::draw_data::{DrawData, DrawSlot, PerDraw};
+use crate::draw_list::TracedPass;
+use crate::error_scope;
+use crate::fog::FogUniform;
+use crate::lighting::LightUniform;
+use crate::lod::LodState;
+use crate::model::{Model, ModelVertex, MorphDelta};
+use crate::msaa::SampledPipeline;
+use crate::oit::WeightedBlendedOit;
+use crate::reflection_probes::ProbeUniform;
+use crate::render_target;
+use crate::shader_preprocessor::{self, PreprocessedShader};
+use crate::shader_reflection::ShaderReflection;
+use crate::ssr::ScreenSpaceReflections;
+use crate::taa::TemporalAa;
+use crate::texture::Texture;
+use crate::upload::Uploader;
+
+// Bind group of the per-draw uniforms, when there are no push constants
+const DRAW_GROUP: u32 = 3;
+// skinning.wgsl's
+const SKINNING_WORKGROUP_SIZE: u32 = 64;
+
+// Pipelines for drawing `Model`s. One per color format, shared by every
+// `ModelInstance`.
+pub struct ModelRenderer {
+    render_pipeline: SampledPipeline,
+    // translucent instances, alpha blended or into `WeightedBlendedOit`'s
+    // targets
+    blend_pipeline: SampledPipeline,
+    oit_pipeline: wgpu::RenderPipeline,
+    // into `TemporalAa::begin_velocity`'s pass
+    velocity_pipeline: wgpu::RenderPipeline,
+    // into `ScreenSpaceReflections::begin_gbuffer`'s
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    // the opaque one with a depth test, for generated geometry drawn into a
+    // pass of its own
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    // the opaque one into a face of a cubemap, see
+    // `render_target::CUBE_FACE_FRONT_FACE`
+    capture_pipeline: wgpu::RenderPipeline,
+    // kept for rebuilding the pipelines when model.wgsl changes, which has
+    // to keep to the layouts the bind groups were made with
+    pipeline_layout: wgpu::PipelineLayout,
+    color_format: wgpu::TextureFormat,
+    samples: u32,
+    node_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    material_layout_entries: Vec<wgpu::BindGroupLayoutEntry>,
+    joints_layout: wgpu::BindGroupLayout,
+    material_layout: wgpu::BindGroupLayout,
+    // plain white, for submeshes without a base colour texture
+    white_bind_group: wgpu::BindGroup,
+    // morphs and skins the vertices of the nodes that need it, once a frame
+    // before anything's drawn, see skinning.wgsl
+    skinning_pipeline: ComputePipeline,
+    // an identity palette and no morph weights, for drawing vertices that
+    // are already in world space, like its output
+    skinned_bind_group: wgpu::BindGroup,
+    // tint, object and material index of every submesh drawn this frame
+    per_draw: PerDraw,
+}
+
+impl ModelRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let per_draw = PerDraw::new(device, "Model Draw");
+        log::info!("Model draw data: {:?}", per_draw.mode());
+        let (shader, reflection) = load_shader(&per_draw).unwrap_or_else(|e| panic!("{:#}", e));
+        // joint palette, morph weights and morph deltas
+        let node_layout_entries = reflection.group_entries(1).unwrap();
+        let material_layout_entries = reflection.group_entries(2).unwrap();
+        let joints_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Model Node Bind Group Layout"),
+            entries: &node_layout_entries,
+        });
+        let material_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Model Material Bind Group Layout"),
+            entries: &material_layout_entries,
+        });
+        let shader = shader.create_module(device, "Model Shader");
+        let mut bind_group_layouts = vec![camera_layout, &joints_layout, &material_layout];
+        bind_group_layouts.extend(per_draw.bind_group_layout());
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Model Pipeline Layout"),
+            bind_group_layouts: &bind_group_layouts,
+            push_constant_ranges: per_draw.push_constant_ranges(),
+        });
+        let (
+            render_pipeline,
+            blend_pipeline,
+            oit_pipeline,
+            velocity_pipeline,
+            gbuffer_pipeline,
+            depth_tested_pipeline,
+            capture_pipeline,
+        ) = create_pipelines(device, &pipeline_layout, &shader, color_format, samples);
+
+        let white = Texture::from_rgba(device, queue, &[255; 4], 1, 1, Some("White Texture"));
+        let white_bind_group = material_bind_group(device, "White", &material_layout, &white);
+
+        let skinning_pipeline = ComputePipeline::new(
+            device,
+            "Skinning",
+            include_str!("skinning.wgsl"),
+            "cs_main",
+            &[&[
+                compute::storage_entry(0, true),
+                compute::storage_entry(1, true),
+                compute::storage_entry(2, true),
+                compute::storage_entry(3, true),
+                compute::storage_entry(4, false),
+            ]],
+        );
+        let identity: [[f32; 4]; 4] = Matrix4::identity().into();
+        let palette = StorageBuffer::from_slice(device, "Skinned Joint Palette Buffer", &[identity]);
+        let weights = StorageBuffer::<f32>::zeroed(device, "Skinned Morph Weight Buffer", 1);
+        let deltas = StorageBuffer::<MorphDelta>::zeroed(device, "Skinned Morph Target Buffer", 1);
+        let skinned_bind_group = node_bind_group(device, "Skinned", &joints_layout, &palette, &weights, &deltas);
+        Self {
+            render_pipeline,
+            blend_pipeline,
+            oit_pipeline,
+            velocity_pipeline,
+            gbuffer_pipeline,
+            depth_tested_pipeline,
+            capture_pipeline,
+            pipeline_layout,
+            color_format,
+            samples,
+            node_layout_entries,
+            material_layout_entries,
+            joints_layout,
+            material_layout,
+            white_bind_group,
+            skinning_pipeline,
+            skinned_bind_group,
+            per_draw,
+        }
+    }
+
+    // Rebuilds the pipelines from the current model.wgsl, keeping the old
+    // ones when the new source doesn't compile
+    pub fn reload_shader(&mut self, device: &wgpu::Device) -> Result<()> {
+        let (shader, reflection) = load_shader(&self.per_draw)?;
+        reflection
+            .check_group(1, &self.node_layout_entries)
+            .and_then(|()| reflection.check_group(2, &self.material_layout_entries))
+            .context("model.wgsl no longer matches the layouts its bind groups were made with")?;
+        let shader = shader.create_module(device, "Model Shader");
+        (
+            self.render_pipeline,
+            self.blend_pipeline,
+            self.oit_pipeline,
+            self.velocity_pipeline,
+            self.gbuffer_pipeline,
+            self.depth_tested_pipeline,
+            self.capture_pipeline,
+        ) = error_scope::check(device, "Rebuilding the model pipelines", || {
+                create_pipelines(device, &self.pipeline_layout, &shader, self.color_format, self.samples)
+            })?;
+        Ok(())
+    }
+
+    // Before any `ModelInstance::upload` of the frame
+    pub fn begin_frame(&mut self) {
+        self.per_draw.clear();
+    }
+
+    // After every `ModelInstance::upload` of the frame
+    pub fn upload(&mut self, uploader: &mut Uploader) {
+        self.per_draw.upload(uploader);
+    }
+
+    // Queues the per-draw data of something other than a `ModelInstance`
+    // drawn with these pipelines, like `draw_generated`'s geometry
+    pub fn push_draw(&mut self, data: DrawData) -> DrawSlot {
+        self.per_draw.push(data)
+    }
+
+    // World space `ModelVertex`es a compute pass wrote, as many as the
+    // `wgpu::util::DrawIndirectArgs` in `args` says, into a pass with a
+    // `depth_prepass::DEPTH_FORMAT` depth buffer. Untextured.
+    pub fn draw_generated<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        vertices: &'a wgpu::Buffer,
+        args: &'a wgpu::Buffer,
+        slot: &DrawSlot,
+    ) {
+        self.draw_generated_with(render_pass, &self.depth_tested_pipeline, camera_bind_group, vertices, args, slot);
+    }
+
+    // The same into the pass from `ScreenSpaceReflections::begin_gbuffer`
+    pub fn draw_generated_gbuffer<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        vertices: &'a wgpu::Buffer,
+        args: &'a wgpu::Buffer,
+        slot: &DrawSlot,
+    ) {
+        self.draw_generated_with(render_pass, &self.gbuffer_pipeline, camera_bind_group, vertices, args, slot);
+    }
+
+    // And into the one from `TemporalAa::begin_velocity`
+    pub fn draw_generated_velocity<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        vertices: &'a wgpu::Buffer,
+        args: &'a wgpu::Buffer,
+        slot: &DrawSlot,
+    ) {
+        self.draw_generated_with(render_pass, &self.velocity_pipeline, camera_bind_group, vertices, args, slot);
+    }
+
+    fn draw_generated_with<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
+        vertices: &'a wgpu::Buffer,
+        args: &'a wgpu::Buffer,
+        slot: &DrawSlot,
+    ) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.skinned_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.white_bind_group, &[]);
+        self.per_draw.set(render_pass, DRAW_GROUP, slot);
+        render_pass.set_vertex_buffer(0, vertices.slice(..));
+        render_pass.draw_indirect(args, 0);
+    }
+}
+
+// model.wgsl, checked against the shared layouts and the buffers it's drawn
+// with. Its node and material layouts are made from it.
+fn load_shader(per_draw: &PerDraw) -> Result<(PreprocessedShader, ShaderReflection)> {
+    let defs = per_draw.shader_defs(DRAW_GROUP);
+    let shader = shader_preprocessor::load("model.wgsl", &defs)?;
+    let reflection = ShaderReflection::new(&shader)?;
+    let check = || -> Result<()> {
+        reflection.check_group(0, &camera::layout_entries())?;
+        reflection.check_group(DRAW_GROUP, &per_draw.layout_entries())?;
+        reflection.check_struct::<FogUniform>("FogUniform")?;
+        reflection.check_struct::<LightUniform>("LightUniform")?;
+        reflection.check_struct::<ProbeUniform>("ProbeUniform")?;
+        reflection.check_struct::<DrawData>("DrawData")?;
+        reflection.check_buffer::<[[f32; 4]; 4]>(1, 0)?;
+        reflection.check_buffer::<f32>(1, 1)?;
+        reflection.check_buffer::<MorphDelta>(1, 2)?;
+        reflection.check_vertex_buffers("vs_main", &[ModelVertex::desc()])
+    };
+    check().with_context(|| format!("model.wgsl with {} doesn't match the model pipeline", defs))?;
+    Ok((shader, reflection))
+}
+
+// The opaque pipeline, the alpha blended one, the OIT one, the TAA velocity
+// one, the SSR G-buffer one, the depth tested one and the capture one
+fn create_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    samples: u32,
+) -> (
+    SampledPipeline,
+    SampledPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+    wgpu::RenderPipeline,
+) {
+    let target = |blend| {
+        [Some(wgpu::ColorTargetState {
+            format: color_format,
+            blend: Some(blend),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
+    };
+    let pipeline = |label, entry_point, targets: &[_], multisample| {
+        create_pipeline(device, label, layout, shader, entry_point, targets, None, wgpu::FrontFace::Ccw, multisample)
+    };
+    // the ones drawn straight into the scene, see `msaa::MsaaTarget`
+    let sampled = |label, entry_point, blend| {
+        SampledPipeline::new(samples, |multisample| pipeline(label, entry_point, &target(blend), multisample))
+    };
+    (
+        sampled("Model Pipeline", "fs_main", wgpu::BlendState::REPLACE),
+        sampled("Model Blend Pipeline", "fs_blend", wgpu::BlendState::ALPHA_BLENDING),
+        pipeline("Model OIT Pipeline", "fs_oit", &WeightedBlendedOit::targets(), Default::default()),
+        pipeline("Model Velocity Pipeline", "fs_velocity", &TemporalAa::velocity_targets(), Default::default()),
+        create_pipeline(
+            device,
+            "Model G-Buffer Pipeline",
+            layout,
+            shader,
+            "fs_gbuffer",
+            &ScreenSpaceReflections::gbuffer_targets(),
+            Some(ScreenSpaceReflections::gbuffer_depth()),
+            wgpu::FrontFace::Ccw,
+            Default::default(),
+        ),
+        create_pipeline(
+            device,
+            "Model Depth Tested Pipeline",
+            layout,
+            shader,
+            "fs_main",
+            &target(wgpu::BlendState::REPLACE),
+            Some(wgpu::DepthStencilState {
+                format: depth_prepass::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            wgpu::FrontFace::Ccw,
+            Default::default(),
+        ),
+        create_pipeline(
+            device,
+            "Model Capture Pipeline",
+            layout,
+            shader,
+            "fs_main",
+            &target(wgpu::BlendState::REPLACE),
+            None,
+            render_target::CUBE_FACE_FRONT_FACE,
+            Default::default(),
+        ),
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    targets: &[Option<wgpu::ColorTargetState>],
+    depth_stencil: Option<wgpu::DepthStencilState>,
+    front_face: wgpu::FrontFace,
+    multisample: wgpu::MultisampleState,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc()],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets,
+            compilation_options: Default::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            front_face,
+            // glTF winding is counter-clockwise, as the camera sees it
+            cull_mode: Some(wgpu::Face::Back),
+            ..Default::default()
+        },
+        depth_stencil,
+        multisample,
+        multiview: None,
+        cache: None,
+    })
+}
+
+fn node_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    palette: &StorageBuffer<[[f32; 4]; 4]>,
+    weights: &StorageBuffer<f32>,
+    morph_deltas: &StorageBuffer<MorphDelta>,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{} Node Bind Group", label)),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: palette.binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: weights.binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: morph_deltas.binding(),
+            },
+        ],
+    })
+}
+
+fn material_bind_group(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::BindGroupLayout,
+    texture: &Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some(&format!("{} Material Bind Group", label)),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&texture.sampler),
+            },
+        ],
+    })
+}
+
+// One mesh-carrying node with its joint palette and morph weights
+struct NodeDraw {
+    node: usize,
+    mesh: usize,
+    palette: StorageBuffer<[[f32; 4]; 4]>,
+    weights: StorageBuffer<f32>,
+    bind_group: wgpu::BindGroup,
+    // for a skinned or morphed mesh, which is drawn from these instead
+    skinned: Option<SkinnedVertices>,
+}
+
+// A node's mesh as `ModelRenderer::skinning_pipeline` leaves it this frame
+struct SkinnedVertices {
+    vertices: StorageBuffer<ModelVertex>,
+    // the node's palette, weights and deltas, the mesh and `vertices`
+    bind_group: wgpu::BindGroup,
+}
+
+// A `Model` placed in the world. The model keeps the pose (its node
+// transforms), the instance turns it into joint palettes for the GPU.
+pub struct ModelInstance {
+    // names the instance's resources and its debug group in captures
+    label: String,
+    pub transform: Matrix4<f32>,
+    // linear RGBA, white leaves the materials as they are
+    pub tint: [f32; 4],
+    // which of the meshes' levels of detail are drawn
+    pub lod: LodState,
+    draws: Vec<NodeDraw>,
+    // one per `Model::materials`, None where there is no texture
+    materials: Vec<Option<wgpu::BindGroup>>,
+    // this frame's draw data, one per submesh in drawing order
+    slots: Vec<DrawSlot>,
+}
+
+impl ModelInstance {
+    // `label` is usually the model's file name
+    pub fn new(
+        device: &wgpu::Device,
+        renderer: &ModelRenderer,
+        label: &str,
+        model: &Model,
+        transform: Matrix4<f32>,
+    ) -> Self {
+        let draws = model
+            .nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(node, n)| n.mesh.map(|mesh| (node, n, mesh, n.skin)))
+            .map(|(node, n, mesh, skin)| {
+                let name = n.name.as_deref().or(model.meshes[mesh].name.as_deref()).unwrap_or("Node");
+                let label = format!("{} {}", label, name);
+                let joints = skin.map_or(1, |skin| model.skins[skin].joints.len().max(1));
+                let palette = StorageBuffer::zeroed(device, &format!("{} Joint Palette Buffer", label), joints);
+                // the shader reads the target count from this buffer's length
+                let weights = StorageBuffer::zeroed(
+                    device,
+                    &format!("{} Morph Weight Buffer", label),
+                    model.meshes[mesh].morph_targets.max(1),
+                );
+                let m = &model.meshes[mesh];
+                let bind_group =
+                    node_bind_group(device, &label, &renderer.joints_layout, &palette, &weights, &m.morph_deltas);
+                // the rest only need their node's transform, which the vertex
+                // shader does as well
+                let skinned = (m.num_vertices > 0 && (skin.is_some() || m.morph_targets > 0)).then(|| {
+                    let vertices = StorageBuffer::zeroed_with_usage(
+                        device,
+                        &format!("{} Skinned Vertex Buffer", label),
+                        m.num_vertices as usize,
+                        wgpu::BufferUsages::VERTEX,
+                    );
+                    let bind_group = renderer.skinning_pipeline.create_bind_group(
+                        device,
+                        0,
+                        &[
+                            palette.binding(),
+                            weights.binding(),
+                            m.morph_deltas.binding(),
+                            m.vertex_buffer.as_entire_binding(),
+                            vertices.binding(),
+                        ],
+                    );
+                    SkinnedVertices { vertices, bind_group }
+                });
+                NodeDraw {
+                    node,
+                    mesh,
+                    palette,
+                    weights,
+                    bind_group,
+                    skinned,
+                }
+            })
+            .collect();
+
+        let materials = model
+            .materials
+            .iter()
+            .map(|material| {
+                let texture = material.base_color_texture.as_ref()?;
+                let label = format!("{} {}", label, material.name.as_deref().unwrap_or("Material"));
+                Some(material_bind_group(device, &label, &renderer.material_layout, texture))
+            })
+            .collect();
+
+        Self {
+            label: label.to_owned(),
+            transform,
+            tint: [1.0; 4],
+            lod: LodState::default(),
+            draws,
+            materials,
+            slots: Vec::new(),
+        }
+    }
+
+    // Uploads joint palettes and morph weights for the model's current pose,
+    // and queues the per-draw data. `object` ends up in `DrawData::object`.
+    pub fn upload(&mut self, uploader: &mut Uploader, renderer: &mut ModelRenderer, model: &Model, object: u32) {
+        self.slots.clear();
+        // in the same order `draw` goes through them
+        for draw in self.draws.iter().filter(|draw| model.meshes[draw.mesh].num_indices > 0) {
+            let mesh = &model.meshes[draw.mesh];
+            for (level, fade) in self.lod.draws(mesh.levels()) {
+                for submesh in mesh.lod(level) {
+                    let material = submesh.material.map(|material| material as u32);
+                    let data = DrawData::new(object, material, self.tint).with_fade(fade);
+                    self.slots.push(renderer.per_draw.push(data));
+                }
+            }
+        }
+
+        let globals = model.global_transforms();
+        for draw in &self.draws {
+            let palette: Vec<[[f32; 4]; 4]> = match model.nodes[draw.node].skin {
+                // skinned meshes ignore their own node transform, the joints
+                // place them (glTF 2.0 spec, "Skins")
+                Some(skin) => model.skins[skin]
+                    .joint_matrices(&globals)
+                    .into_iter()
+                    .map(|joint| (self.transform * joint).into())
+                    .collect(),
+                None => vec![(self.transform * globals[draw.node]).into()],
+            };
+            draw.palette.write_staged(uploader, 0, &palette);
+            let weights = &model.nodes[draw.node].weights;
+            if !weights.is_empty() {
+                draw.weights.write_staged(uploader, 0, &weights[..weights.len().min(draw.weights.len())]);
+            }
+        }
+    }
+
+    // Morphs and skins the vertices of this frame's pose, after `upload` and
+    // before any of the draws
+    pub fn skin(&self, encoder: &mut wgpu::CommandEncoder, renderer: &ModelRenderer, model: &Model) {
+        if self.draws.iter().all(|draw| draw.skinned.is_none()) {
+            return;
+        }
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some(&format!("{} Skinning", self.label)),
+            timestamp_writes: None,
+        });
+        for draw in &self.draws {
+            if let Some(skinned) = &draw.skinned {
+                let vertices = model.meshes[draw.mesh].num_vertices;
+                let workgroups = compute::workgroup_count(vertices, SKINNING_WORKGROUP_SIZE);
+                renderer
+                    .skinning_pipeline
+                    .dispatch_in_pass(&mut pass, &[&skinned.bind_group], (workgroups, 1, 1));
+            }
+        }
+    }
+
+    // A tint with an alpha below one; drawn by `draw` these are cut out,
+    // `draw_blended` and `draw_oit` blend them
+    pub fn is_transparent(&self) -> bool {
+        self.tint[3] < 1.0
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        let pipeline = renderer.render_pipeline.get(render_pass.sample_count());
+        self.draw_with(render_pass, pipeline, renderer, camera_bind_group, model);
+    }
+
+    // Into one of `Ibl::begin_capture`'s passes
+    pub fn draw_capture<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        self.draw_with(render_pass, &renderer.capture_pipeline, renderer, camera_bind_group, model);
+    }
+
+    // Alpha blended over what's drawn already, so after everything behind it
+    pub fn draw_blended<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        let pipeline = renderer.blend_pipeline.get(render_pass.sample_count());
+        self.draw_with(render_pass, pipeline, renderer, camera_bind_group, model);
+    }
+
+    // Into the pass from `WeightedBlendedOit::begin_accumulate`
+    pub fn draw_oit<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        self.draw_with(render_pass, &renderer.oit_pipeline, renderer, camera_bind_group, model);
+    }
+
+    // Into the pass from `TemporalAa::begin_velocity`
+    pub fn draw_velocity<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        self.draw_with(render_pass, &renderer.velocity_pipeline, renderer, camera_bind_group, model);
+    }
+
+    // Into the pass from `ScreenSpaceReflections::begin_gbuffer`
+    pub fn draw_gbuffer<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        self.draw_with(render_pass, &renderer.gbuffer_pipeline, renderer, camera_bind_group, model);
+    }
+
+    fn draw_with<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        model: &'a Model,
+    ) {
+        render_pass.push_debug_group(&self.label);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        let mut slots = self.slots.iter();
+        for draw in &self.draws {
+            let mesh = &model.meshes[draw.mesh];
+            if mesh.num_indices == 0 {
+                continue;
+            }
+            render_pass.insert_debug_marker(mesh.name.as_deref().unwrap_or("Mesh"));
+            match &draw.skinned {
+                Some(skinned) => {
+                    render_pass.set_bind_group(1, &renderer.skinned_bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, skinned.vertices.buffer().slice(..));
+                }
+                None => {
+                    render_pass.set_bind_group(1, &draw.bind_group, &[]);
+                    render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                }
+            }
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            let submeshes = self.lod.draws(mesh.levels()).flat_map(|(level, _)| mesh.lod(level));
+            for (submesh, slot) in submeshes.zip(slots.by_ref()) {
+                let material = submesh.material.and_then(|material| self.materials.get(material)?.as_ref());
+                render_pass.set_bind_group(2, material.unwrap_or(&renderer.white_bind_group), &[]);
+                renderer.per_draw.set(render_pass, DRAW_GROUP, slot);
+                render_pass.draw_indexed(submesh.indices.clone(), 0, 0..1);
+            }
+        }
+        render_pass.pop_debug_group();
+    }
+}
@@ -0,0 +1,84 @@
+// Morphs and skins a mesh's vertices once a frame, for every pass that
+// draws the mesh to read as they are: world space positions and normals,
+// with all the weight on joint 0, which the draw's palette leaves as the
+// identity. The vertices are read as words, as they're laid out in
+// `ModelVertex`: position 0-2, normal 3-5, tex_coords 6-7, color 8-10,
+// joints 11-12 as four u16s, weights 13-16 and metallic_roughness 17-18.
+
+const VERTEX_WORDS: u32 = 19u;
+
+struct MorphDelta {
+    position: vec4<f32>,
+    normal: vec4<f32>,
+};
+
+// as model.wgsl binds them for a draw that isn't skinned here
+@group(0) @binding(0)
+var<storage, read> joints: array<mat4x4<f32>>;
+@group(0) @binding(1)
+var<storage, read> morph_weights: array<f32>;
+@group(0) @binding(2)
+var<storage, read> morph_deltas: array<MorphDelta>;
+// the mesh's vertex buffer, and what's drawn instead of it
+@group(0) @binding(3)
+var<storage, read> source: array<u32>;
+@group(0) @binding(4)
+var<storage, read_write> skinned: array<u32>;
+
+fn load_vec3(at: u32) -> vec3<f32> {
+    return vec3<f32>(bitcast<f32>(source[at]), bitcast<f32>(source[at + 1u]), bitcast<f32>(source[at + 2u]));
+}
+
+fn store_vec3(at: u32, value: vec3<f32>) {
+    skinned[at] = bitcast<u32>(value.x);
+    skinned[at + 1u] = bitcast<u32>(value.y);
+    skinned[at + 2u] = bitcast<u32>(value.z);
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let vertex_count = arrayLength(&source) / VERTEX_WORDS;
+    let vertex = id.x;
+    if (vertex >= vertex_count) {
+        return;
+    }
+    let base = vertex * VERTEX_WORDS;
+    var position = load_vec3(base);
+    var normal = load_vec3(base + 3u);
+    let targets = arrayLength(&morph_weights);
+    let delta_count = arrayLength(&morph_deltas) / targets;
+    for (var t = 0u; t < targets; t++) {
+        let weight = morph_weights[t];
+        if weight != 0.0 {
+            let delta = morph_deltas[t * delta_count + vertex];
+            position += weight * delta.position.xyz;
+            normal += weight * delta.normal.xyz;
+        }
+    }
+
+    let packed = vec2<u32>(source[base + 11u], source[base + 12u]);
+    let joint = vec4<u32>(packed.x & 0xffffu, packed.x >> 16u, packed.y & 0xffffu, packed.y >> 16u);
+    let weight = vec4<f32>(
+        bitcast<f32>(source[base + 13u]),
+        bitcast<f32>(source[base + 14u]),
+        bitcast<f32>(source[base + 15u]),
+        bitcast<f32>(source[base + 16u]),
+    );
+    let skin = joints[joint.x] * weight.x
+        + joints[joint.y] * weight.y
+        + joints[joint.z] * weight.z
+        + joints[joint.w] * weight.w;
+
+    for (var word = 0u; word < VERTEX_WORDS; word++) {
+        skinned[base + word] = source[base + word];
+    }
+    store_vec3(base, (skin * vec4<f32>(position, 1.0)).xyz);
+    // fine as long as joints don't scale non-uniformly
+    store_vec3(base + 3u, (skin * vec4<f32>(normal, 0.0)).xyz);
+    skinned[base + 11u] = 0u;
+    skinned[base + 12u] = 0u;
+    skinned[base + 13u] = bitcast<u32>(1.0);
+    skinned[base + 14u] = 0u;
+    skinned[base + 15u] = 0u;
+    skinned[base + 16u] = 0u;
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::PI;
+use std::time::Duration;
+
+use cgmath::*;
+
+use crate::camera::{self, CameraUniform};
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::ibl;
+use crate::lighting::{self, Light, LightUniform};
+use crate::msaa::SampledPipeline;
+use crate::scene::SkyDesc;
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::shader_reflection::ShaderReflection;
+use crate::sky_cube;
+
+// what a sun at the zenith puts on a surface facing it, as `Light::default`
+const SUN_INTENSITY: f32 = 0.75;
+// of the ambient at night, so the scene doesn't go black
+const NIGHT_AMBIENT: f32 = 0.1;
+// per unit of air mass past the first, red, green and blue at turbidity 3,
+// so the sun reddens and dims towards the horizon
+const EXTINCTION: [f32; 3] = [0.08, 0.16, 0.35];
+
+// Matches `SkyUniform` in sky.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    coefficients: [[f32; 4]; 5],
+    zenith: [f32; 4],
+    sun_direction: [f32; 3],
+    fog_distance: f32,
+}
+
+// Preetham, Shirley and Smits' fit of the Perez sky model to turbidity `t`,
+// A to E for the luminance Y and the chromaticities x and y
+fn perez_coefficients(t: f32) -> [[f32; 3]; 5] {
+    [
+        [0.1787 * t - 1.4630, -0.0193 * t - 0.2592, -0.0167 * t - 0.2608],
+        [-0.3554 * t + 0.4275, -0.0665 * t + 0.0008, -0.0950 * t + 0.0092],
+        [-0.0227 * t + 5.3251, -0.0004 * t + 0.2125, -0.0079 * t + 0.2102],
+        [0.1206 * t - 2.5771, -0.0641 * t - 0.8989, -0.0441 * t - 1.6537],
+        [-0.0670 * t + 0.3703, -0.0033 * t + 0.0452, -0.0109 * t + 0.0529],
+    ]
+}
+
+// Relative to the zenith, `theta` radians from the zenith and `gamma` from
+// the sun, as sky.wgsl works it out per pixel
+fn perez(coefficients: &[[f32; 3]; 5], channel: usize, theta: f32, gamma: f32) -> f32 {
+    let [a, b, c, d, e] = coefficients.map(|coefficient| coefficient[channel]);
+    (1.0 + a * (b / theta.cos()).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// Y in kcd/m², x and y at the zenith, with the sun `theta` radians from it
+fn zenith(t: f32, theta: f32) -> [f32; 3] {
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta);
+    let luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let turbidity = Vector3::new(t * t, t, 1.0);
+    let angle = Vector4::new(theta.powi(3), theta.powi(2), theta, 1.0);
+    let chromaticity = |m: [[f32; 4]; 3]| turbidity.dot(Vector3::new(
+        Vector4::from(m[0]).dot(angle),
+        Vector4::from(m[1]).dot(angle),
+        Vector4::from(m[2]).dot(angle),
+    ));
+    let x = chromaticity([
+        [0.00166, -0.00375, 0.00209, 0.0],
+        [-0.02903, 0.06377, -0.03202, 0.00394],
+        [0.11693, -0.21196, 0.06052, 0.25886],
+    ]);
+    let y = chromaticity([
+        [0.00275, -0.00610, 0.00317, 0.0],
+        [-0.04214, 0.08970, -0.04153, 0.00516],
+        [0.15346, -0.26756, 0.06670, 0.26688],
+    ]);
+    [luminance, x, y]
+}
+
+// How much of the day's light is left with the sun `sun_height` up, as the
+// y of its direction: all of it from about 6 degrees above the horizon,
+// none from 6 under
+fn daylight(sun_height: f32) -> f32 {
+    let t = ((sun_height + 0.1) / 0.2).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Preetham's analytic daytime sky, drawn behind everything else instead of
+// the clear colour. Where the sun is, from the time of day, also makes the
+// scene's light and ambient, see `light` and `ambient`.
+pub struct Sky {
+    desc: SkyDesc,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: SampledPipeline,
+    // into `Ibl::begin_capture`'s passes
+    capture_pipeline: wgpu::RenderPipeline,
+}
+
+impl Sky {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        desc: &SkyDesc,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sky Buffer"),
+            size: std::mem::size_of::<SkyUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let entries = [wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sky Bind Group Layout"),
+            entries: &entries,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sky Bind Group"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let shader = |defs: &ShaderDefs| {
+            shader_preprocessor::load("sky.wgsl", defs)
+                .and_then(|shader| {
+                    let reflection = ShaderReflection::new(&shader)?;
+                    reflection.check_group(0, &camera::layout_entries())?;
+                    reflection.check_group(1, &entries)?;
+                    reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                    reflection.check_struct::<FogUniform>("FogUniform")?;
+                    reflection.check_struct::<LightUniform>("LightUniform")?;
+                    reflection.check_struct::<SkyUniform>("SkyUniform")?;
+                    Ok(shader)
+                })
+                .unwrap_or_else(|e| panic!("{:#}", e))
+                .create_module(device, "Sky Shader")
+        };
+        let main_shader = shader(&ShaderDefs::new());
+        let pipeline = SampledPipeline::new(samples, |multisample| {
+            let shader = &main_shader;
+            sky_cube::sky_cube_pipeline(device, "Sky Pipeline", &pipeline_layout, shader, color_format, multisample)
+        });
+        let capture_pipeline = sky_cube::sky_cube_pipeline(
+            device,
+            "Sky Capture Pipeline",
+            &pipeline_layout,
+            &shader(&ShaderDefs::new().with("SKY_CAPTURE")),
+            ibl::ENVIRONMENT_FORMAT,
+            Default::default(),
+        );
+
+        Self {
+            desc: desc.clone(),
+            uniform_buffer,
+            bind_group,
+            pipeline,
+            capture_pipeline,
+        }
+    }
+    // As it was made, at the time of day it is now
+    pub fn desc(&self) -> &SkyDesc {
+        &self.desc
+    }
+
+    pub fn set_time_of_day(&mut self, hours: f32) {
+        self.desc.time_of_day = hours.rem_euclid(24.0);
+    }
+
+    // Moves the sun on by `dt` of the day, if it's moving
+    pub fn advance(&mut self, dt: Duration) {
+        if self.desc.day_length > 0.0 {
+            self.set_time_of_day(self.desc.time_of_day + dt.as_secs_f32() / self.desc.day_length * 24.0);
+        }
+    }
+
+    // Towards the sun, which is under the horizon at night
+    pub fn sun_direction(&self) -> Vector3<f32> {
+        let hour_angle = (self.desc.time_of_day - 12.0) / 24.0 * 2.0 * PI;
+        let latitude = Rad::from(Deg(self.desc.latitude)).0;
+        Vector3::new(-hour_angle.sin(), latitude.cos() * hour_angle.cos(), latitude.sin() * hour_angle.cos())
+    }
+
+    // The sun as the scene's light: white overhead, through more air and
+    // redder towards the horizon, and gone once it's set
+    pub fn light(&self) -> Light {
+        let direction = self.sun_direction();
+        // Kasten and Young's, relative to the air straight up
+        let zenith = direction.y.clamp(0.0, 1.0).acos().min(PI / 2.0);
+        let air_mass = 1.0 / (zenith.cos() + 0.50572 * (96.07995 - zenith.to_degrees()).powf(-1.6364));
+        let haze = self.desc.turbidity / 3.0;
+        Light::Directional {
+            direction: direction.into(),
+            color: EXTINCTION.map(|extinction| (-extinction * haze * (air_mass - 1.0)).exp()),
+            intensity: SUN_INTENSITY * daylight(direction.y),
+        }
+    }
+
+    // What's left of the ambient once the sun has set
+    pub fn ambient(&self) -> [f32; 3] {
+        let daylight = daylight(self.sun_direction().y);
+        lighting::AMBIENT.map(|ambient| ambient * (NIGHT_AMBIENT + (1.0 - NIGHT_AMBIENT) * daylight))
+    }
+
+    // Once a frame, after `advance`; the sky is fogged as the far plane,
+    // `zfar` away, would be
+    pub fn update(&self, queue: &wgpu::Queue, zfar: f32) {
+        let sun = self.sun_direction();
+        let t = self.desc.turbidity.clamp(1.0, 12.0);
+        // the fit is only good with the sun above the horizon
+        let theta = sun.y.clamp(0.01, 1.0).acos();
+        let coefficients = perez_coefficients(t);
+        let zenith = zenith(t, theta);
+        let [y, x, chromaticity_y] =
+            [0, 1, 2].map(|channel| zenith[channel] / perez(&coefficients, channel, 0.0, theta));
+        let uniform = SkyUniform {
+            coefficients: coefficients.map(|[y, x, chromaticity_y]| [y, x, chromaticity_y, 0.0]),
+            zenith: [y, x, chromaticity_y, daylight(sun.y)],
+            sun_direction: sun.into(),
+            fog_distance: zfar,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // First in a pass, with nothing under it
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(self.pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..sky_cube::VERTEX_COUNT, 0..1);
+    }
+
+    // Into one of `Ibl::begin_capture`'s passes, with its camera
+    pub fn draw_capture<'a>(&'a self, render_pass: &mut TracedPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.capture_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..sky_cube::VERTEX_COUNT, 0..1);
+    }
+}
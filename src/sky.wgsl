@@ -0,0 +1,85 @@
+// Preetham's daytime sky: the Perez model's luminance and chromaticity for
+// each view direction, from the sun's position and the air's turbidity.
+// With SKY_CAPTURE it's drawn into the environment image based lighting is
+// baked from, without the sun's disc, which is the direct light's, and with
+// a plain ground under the horizon.
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "lighting.wgsl"
+#include "sky_cube.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+// the sun first, as it lights the scene
+@group(0) @binding(2)
+var<uniform> lighting: LightUniform;
+@group(0) @binding(7)
+var<storage, read> lights: array<GpuLight>;
+
+// Matches `SkyUniform` in sky.rs
+struct SkyUniform {
+    // Perez's A to E, for the luminance Y in x and the chromaticities x and
+    // y in y and z
+    coefficients: array<vec4<f32>, 5>,
+    // Y, x and y at the zenith over the Perez function there, and in w how
+    // much daylight is left
+    zenith: vec4<f32>,
+    // towards the sun, under the horizon at night
+    sun_direction: vec3<f32>,
+    // the sky is as fogged as the far plane
+    fog_distance: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> sky: SkyUniform;
+
+// brings the sky's luminance, in kcd/m², into the frame's range
+const EXPOSURE: f32 = 0.08;
+// what's left once the sun has set, linear
+const NIGHT: vec3<f32> = vec3<f32>(0.002, 0.004, 0.01);
+// the sun's angular radius, about 0.27 degrees, as its cosine
+const SUN_COS: f32 = 0.99999;
+// of the light that reaches it, for the ground the environment sees
+const GROUND_ALBEDO: f32 = 0.2;
+
+// linear sRGB from CIE XYZ
+const XYZ_TO_RGB: mat3x3<f32> = mat3x3<f32>(
+    vec3<f32>(3.2406, -0.9689, 0.0557),
+    vec3<f32>(-1.5372, 1.8758, -0.2040),
+    vec3<f32>(-0.4986, 0.0415, 1.0570),
+);
+
+fn perez(cos_theta: f32, gamma: f32, cos_gamma: f32) -> vec3<f32> {
+    let c = sky.coefficients;
+    return (1.0 + c[0].xyz * exp(c[1].xyz / cos_theta))
+        * (1.0 + c[2].xyz * exp(c[3].xyz * gamma) + c[4].xyz * cos_gamma * cos_gamma);
+}
+
+@fragment
+fn fs_main(in: SkyCubeOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+    let sun = lights[0];
+    // under the horizon looks like the horizon
+    let cos_theta = max(direction.y, 0.01);
+    let cos_gamma = dot(direction, sky.sun_direction);
+    let gamma = acos(clamp(cos_gamma, -1.0, 1.0));
+    let yxy = sky.zenith.xyz * perez(cos_theta, gamma, cos_gamma);
+    let xyz = vec3<f32>(yxy.y / yxy.z, 1.0, (1.0 - yxy.y - yxy.z) / yxy.z) * yxy.x;
+    let day = 1.0 - exp(-max(XYZ_TO_RGB * xyz, vec3<f32>(0.0)) * EXPOSURE);
+    var color = mix(NIGHT, day, sky.zenith.w);
+#ifdef SKY_CAPTURE
+    if direction.y < 0.0 {
+        color = GROUND_ALBEDO * (lighting.ambient + sun.color * max(sun.direction.y, 0.0));
+    }
+#else
+    // the disc, in the light's colour and bright enough to saturate
+    if cos_gamma > SUN_COS && direction.y > 0.0 {
+        color += sun.color * 8.0;
+    }
+#endif
+    let eye = camera.view_pos.xyz;
+    return vec4<f32>(apply_fog(fog, eye, eye + direction * sky.fog_distance, color), 1.0);
+}
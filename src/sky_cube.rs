@@ -0,0 +1,43 @@
+// The entry point of sky_cube.wgsl's vertex stage, for shaders that
+// `#include` it
+pub const VERTEX_ENTRY: &str = "vs_sky_cube";
+// as one triangle strip
+pub const VERTEX_COUNT: u32 = 14;
+
+// A cube around the eye, shaded by `module`'s `fs_main` into one `format`
+// target behind everything else: no vertex buffers and no depth, drawn with
+// `draw(0..VERTEX_COUNT, 0..1)`
+pub fn sky_cube_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    layout: &wgpu::PipelineLayout,
+    module: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+    multisample: wgpu::MultisampleState,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module,
+            entry_point: VERTEX_ENTRY,
+            buffers: &[],
+            compilation_options: Default::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module,
+            entry_point: "fs_main",
+            targets: &[Some(format.into())],
+            compilation_options: Default::default(),
+        }),
+        // seen from inside whichever way it faces, so nothing's culled
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample,
+        multiview: None,
+        cache: None,
+    })
+}
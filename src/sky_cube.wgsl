@@ -0,0 +1,25 @@
+// The vertex stage of what's drawn infinitely far away behind the scene,
+// see sky_cube.rs: a cube around the eye from `draw(0..14, 0..1)` with no
+// vertex buffers, through the `camera` uniform of the shader that includes
+// it. `direction` is from the eye, not normalized.
+
+#include "camera.wgsl"
+
+struct SkyCubeOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) direction: vec3<f32>,
+};
+
+@vertex
+fn vs_sky_cube(@builtin(vertex_index) index: u32) -> SkyCubeOutput {
+    // a cube as one strip of 14 vertices
+    let corner = vec3<u32>((0x287au >> index) & 1u, (0x02afu >> index) & 1u, (0x31e3u >> index) & 1u);
+    var out: SkyCubeOutput;
+    out.direction = vec3<f32>(corner) * 2.0 - 1.0;
+    // infinitely far, so it turns with the camera but never moves, and just
+    // inside the far plane. Through a perspective projection even when the
+    // camera's is parallel, which would leave w at 0.
+    let clip = camera.sky_view_proj * vec4<f32>(out.direction, 0.0);
+    out.position = vec4<f32>(clip.xy, clip.w * 0.9999, clip.w);
+    return out;
+}
@@ -0,0 +1,138 @@
+use crate::camera::{self, CameraUniform};
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::msaa::SampledPipeline;
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::shader_reflection::ShaderReflection;
+use crate::sky_cube;
+use crate::texture::Texture;
+
+// Matches `SkyboxUniform` in skybox.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyboxUniform {
+    fog_distance: f32,
+}
+
+// An environment cubemap drawn behind everything else instead of the clear
+// colour, or the sky's own background
+pub struct Skybox {
+    // kept for its view, which the bind group holds
+    environment: Texture,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: SampledPipeline,
+}
+
+impl Skybox {
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        environment: Texture,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Skybox Buffer"),
+            size: std::mem::size_of::<SkyboxUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::Cube,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Skybox Bind Group Layout"),
+            entries: &entries,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Skybox Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&environment.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&environment.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let shader = shader_preprocessor::load("skybox.wgsl", &ShaderDefs::new())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_group(1, &entries)?;
+                reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                reflection.check_struct::<FogUniform>("FogUniform")?;
+                reflection.check_struct::<SkyboxUniform>("SkyboxUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Skybox Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = SampledPipeline::new(samples, |multisample| {
+            sky_cube::sky_cube_pipeline(device, "Skybox Pipeline", &pipeline_layout, &shader, color_format, multisample)
+        });
+
+        Self {
+            environment,
+            uniform_buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    pub fn environment(&self) -> &Texture {
+        &self.environment
+    }
+
+    // The environment is fogged as the far plane, `zfar` away, would be
+    pub fn update(&self, queue: &wgpu::Queue, zfar: f32) {
+        let uniform = SkyboxUniform { fog_distance: zfar };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // First in a pass, with nothing under it
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(self.pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..sky_cube::VERTEX_COUNT, 0..1);
+    }
+}
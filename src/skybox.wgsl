@@ -0,0 +1,32 @@
+// An environment cubemap drawn behind everything, as sky.wgsl draws the sky
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "sky_cube.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+
+// Matches `SkyboxUniform` in skybox.rs
+struct SkyboxUniform {
+    // the environment is as fogged as the far plane
+    fog_distance: f32,
+};
+
+@group(1) @binding(0)
+var t_environment: texture_cube<f32>;
+@group(1) @binding(1)
+var s_environment: sampler;
+@group(1) @binding(2)
+var<uniform> skybox: SkyboxUniform;
+
+@fragment
+fn fs_main(in: SkyCubeOutput) -> @location(0) vec4<f32> {
+    let direction = normalize(in.direction);
+    // the top mip, derivatives would jump across the faces' edges
+    let color = textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb;
+    let eye = camera.view_pos.xyz;
+    return vec4<f32>(apply_fog(fog, eye, eye + direction * skybox.fog_distance, color), 1.0);
+}
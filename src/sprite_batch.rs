@@ -0,0 +1,329 @@
+use cgmath::{Matrix4, Rad, Vector2};
+use wgpu::util::DeviceExt;
+
+use crate::atlas::AtlasRect;
+use crate::camera::OPENGL_TO_WGPU_MATRIX;
+use crate::draw_list::TracedPass;
+use crate::resources::{ResourceKind, Tracked};
+use crate::texture::Texture;
+
+// instance buffer size before the first frame grows it
+const INITIAL_CAPACITY: usize = 256;
+
+/// A textured quad in screen space. Positions and sizes are in logical
+/// pixels with the origin at the top-left corner and y pointing down, so
+/// positive rotations turn clockwise on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct Sprite {
+    pub position: Vector2<f32>,
+    // unscaled size in pixels
+    pub size: Vector2<f32>,
+    pub scale: Vector2<f32>,
+    pub rotation: Rad<f32>,
+    // point the sprite is placed and rotated around, as a fraction of its
+    // size: (0, 0) is the top-left corner, (0.5, 0.5) the centre
+    pub pivot: Vector2<f32>,
+    // multiplied with the texture, alpha included
+    pub color: [f32; 4],
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+}
+
+impl Sprite {
+    // Covers the whole texture, centred on the pivot
+    pub fn new(position: Vector2<f32>, size: Vector2<f32>) -> Self {
+        Self {
+            position,
+            size,
+            scale: Vector2::new(1.0, 1.0),
+            rotation: Rad(0.0),
+            pivot: Vector2::new(0.5, 0.5),
+            color: [1.0; 4],
+            uv_min: [0.0, 0.0],
+            uv_max: [1.0, 1.0],
+        }
+    }
+
+    // One atlas region drawn at its native pixel size
+    pub fn from_rect(position: Vector2<f32>, rect: AtlasRect) -> Self {
+        Self {
+            uv_min: rect.uv_min,
+            uv_max: rect.uv_max,
+            ..Self::new(position, Vector2::new(rect.width as f32, rect.height as f32))
+        }
+    }
+
+    pub fn with_scale(mut self, scale: Vector2<f32>) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_rotation<R: Into<Rad<f32>>>(mut self, rotation: R) -> Self {
+        self.rotation = rotation.into();
+        self
+    }
+
+    pub fn with_pivot(mut self, pivot: Vector2<f32>) -> Self {
+        self.pivot = pivot;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn to_raw(self) -> SpriteRaw {
+        SpriteRaw {
+            position: self.position.into(),
+            size: [self.size.x * self.scale.x, self.size.y * self.scale.y],
+            pivot: self.pivot.into(),
+            rotation: self.rotation.0,
+            uv_rect: [self.uv_min[0], self.uv_min[1], self.uv_max[0], self.uv_max[1]],
+            color: self.color,
+        }
+    }
+}
+
+// Mirrors `SpriteInput` in sprite.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct SpriteRaw {
+    position: [f32; 2],
+    size: [f32; 2],
+    pivot: [f32; 2],
+    rotation: f32,
+    uv_rect: [f32; 4],
+    color: [f32; 4],
+}
+
+impl SpriteRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x2,
+            2 => Float32x2,
+            3 => Float32,
+            4 => Float32x4,
+            5 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<SpriteRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Pixels to clip space with the origin at the top-left corner
+pub(crate) fn screen_projection(width: u32, height: u32) -> Matrix4<f32> {
+    OPENGL_TO_WGPU_MATRIX * cgmath::ortho(0.0, width.max(1) as f32, height.max(1) as f32, 0.0, -1.0, 1.0)
+}
+
+// Sprites sharing one texture (usually an atlas), queued during `update()`
+// and drawn as a single instanced draw in submission order. Alpha blended
+// with no depth test, so later sprites land on top.
+pub struct SpriteBatch {
+    sprites: Vec<SpriteRaw>,
+    instance_buffer: Tracked<wgpu::Buffer>,
+    capacity: usize,
+    // instances uploaded by the last `flush`
+    instance_count: u32,
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    texture_bind_group: wgpu::BindGroup,
+    render_pipeline: wgpu::RenderPipeline,
+    // physical pixels per logical one
+    scale_factor: f32,
+}
+
+impl SpriteBatch {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, texture: &Texture) -> Self {
+        let proj: [[f32; 4]; 4] = screen_projection(1, 1).into();
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Sprite Screen Buffer"),
+            contents: bytemuck::cast_slice(&[proj]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sprite Screen Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sprite Screen Bind Group"),
+            layout: &screen_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            }],
+        });
+
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Sprite Texture Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let texture_bind_group = Self::create_texture_bind_group(device, &texture_layout, texture);
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Sprite Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("sprite.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sprite Pipeline Layout"),
+            bind_group_layouts: &[&screen_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sprite Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[SpriteRaw::desc()],
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                // one quad per instance, no index buffer needed
+                topology: wgpu::PrimitiveTopology::TriangleStrip,
+                // the y flip reverses the winding, so don't cull
+                cull_mode: None,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: Default::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        Self {
+            sprites: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            instance_count: 0,
+            screen_buffer,
+            screen_bind_group,
+            texture_layout,
+            texture_bind_group,
+            render_pipeline,
+            scale_factor: 1.0,
+        }
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> Tracked<wgpu::Buffer> {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Sprite Instance Buffer"),
+            size: (capacity * std::mem::size_of::<SpriteRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Tracked::buffer(ResourceKind::Buffer, buffer)
+    }
+
+    fn create_texture_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        texture: &Texture,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sprite Texture Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+            ],
+        })
+    }
+
+    // Swaps the texture every sprite samples from
+    pub fn set_texture(&mut self, device: &wgpu::Device, texture: &Texture) {
+        self.texture_bind_group = Self::create_texture_bind_group(device, &self.texture_layout, texture);
+    }
+
+    pub fn push(&mut self, sprite: Sprite) {
+        self.sprites.push(sprite.to_raw());
+    }
+
+    // sprites queued since the last flush
+    pub fn len(&self) -> usize {
+        self.sprites.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sprites.is_empty()
+    }
+
+    // Uploads the queued sprites and the projection for a `width` x `height`
+    // target in physical pixels, then empties the queue for the next frame
+    pub fn flush(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) {
+        if self.sprites.len() > self.capacity {
+            self.capacity = self.sprites.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&self.sprites));
+
+        let proj: [[f32; 4]; 4] = (screen_projection(width, height) * Matrix4::from_scale(self.scale_factor)).into();
+        queue.write_buffer(&self.screen_buffer, 0, bytemuck::cast_slice(&[proj]));
+
+        self.instance_count = self.sprites.len() as u32;
+        self.sprites.clear();
+    }
+
+    // Draws whatever the last `flush` uploaded
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>) {
+        if self.instance_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, &self.screen_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.texture_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
+        render_pass.draw(0..4, 0..self.instance_count);
+    }
+}
@@ -0,0 +1,443 @@
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera::{self, CameraUniform};
+use crate::depth_prepass::{self, DEPTH_FORMAT};
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::reflection_probes::ProbeUniform;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::SamplerDesc;
+
+// world space normals and roughness, which need more than 8 bits
+const NORMAL_ROUGHNESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const SPECULAR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+// Matches `SsrUniform` in ssr.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SsrUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+}
+
+// Screen-space reflections. The opaque scene is drawn again into a G-buffer
+// of its own, with depth, normals, roughness and f0 (`begin_gbuffer`), and
+// the trace marches each smooth enough pixel's reflection through that
+// depth. Where a ray hits something on screen, the scene's colour there
+// replaces the environment or probe reflection the pixel was lit with, so
+// the difference is added onto the scene; where it misses it keeps the
+// one it has. Only what's on screen and in the G-buffer can be reflected,
+// and hits fade out towards the screen's edges and the rays' ends.
+pub struct ScreenSpaceReflections {
+    color_format: wgpu::TextureFormat,
+    instance_pipeline: wgpu::RenderPipeline,
+    trace_pipeline: wgpu::RenderPipeline,
+    composite_pipeline: wgpu::RenderPipeline,
+    // the G-buffer, and the input, which is the scene for the trace and
+    // the reflections for the composite
+    layout: wgpu::BindGroupLayout,
+    input_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    depth: Tracked<wgpu::TextureView>,
+    normal_roughness: Tracked<wgpu::TextureView>,
+    specular: Tracked<wgpu::TextureView>,
+    reflection: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    reflection_bind_group: wgpu::BindGroup,
+}
+
+impl ScreenSpaceReflections {
+    // `vertex_layouts` are those of the instanced meshes, starting with a
+    // Float32x3 position at location 0 and the instance matrix at
+    // locations 5 to 8
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let instance_shader = shader_preprocessor::load("ssr_gbuffer.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_vertex_buffers("vs_main", vertex_layouts)?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "SSR G-Buffer Shader");
+        let instance_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSR G-Buffer Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let instance_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("SSR G-Buffer Pipeline"),
+            layout: Some(&instance_layout),
+            vertex: wgpu::VertexState {
+                module: &instance_shader,
+                entry_point: "vs_main",
+                buffers: vertex_layouts,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &instance_shader,
+                entry_point: "fs_main",
+                targets: &Self::gbuffer_targets(),
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                ..Default::default()
+            },
+            depth_stencil: Some(Self::gbuffer_depth()),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1, false),
+            texture_entry(2, false),
+            texture_entry(3, false),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let input_entries = [texture_entry(0, true)];
+        let shader = shader_preprocessor::load("ssr.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_group(1, &entries)?;
+                reflection.check_group(2, &input_entries)?;
+                reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                reflection.check_struct::<FogUniform>("FogUniform")?;
+                reflection.check_struct::<ProbeUniform>("ProbeUniform")?;
+                reflection.check_struct::<SsrUniform>("SsrUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "SSR Shader");
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSR Bind Group Layout"),
+            entries: &entries,
+        });
+        let input_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("SSR Input Bind Group Layout"),
+            entries: &input_entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("SSR Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout, &input_layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point, blend| {
+            fullscreen_pipeline_with(
+                device,
+                label,
+                &pipeline_layout,
+                &shader,
+                entry_point,
+                &[Some(wgpu::ColorTargetState {
+                    format: color_format,
+                    blend,
+                    write_mask: if blend.is_some() { wgpu::ColorWrites::COLOR } else { wgpu::ColorWrites::ALL },
+                })],
+            )
+        };
+        let trace_pipeline = create_pipeline("SSR Trace Pipeline", "fs_trace", None);
+        // added onto the scene, which the reflections can darken as well
+        // as brighten, the scene's alpha stays as it is
+        let add = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let composite_pipeline = create_pipeline(
+            "SSR Composite Pipeline",
+            "fs_composite",
+            Some(wgpu::BlendState {
+                color: add,
+                alpha: add,
+            }),
+        );
+        // hits land between pixels
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("SSR Sampler"));
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("SSR Buffer"),
+            contents: bytemuck::cast_slice(&[SsrUniform {
+                inverse_view_proj: Matrix4::identity().into(),
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (depth, normal_roughness, specular, reflection, bind_group, reflection_bind_group) =
+            Self::create_targets(device, &layout, &input_layout, &sampler, &buffer, color_format, width, height);
+        Self {
+            color_format,
+            instance_pipeline,
+            trace_pipeline,
+            composite_pipeline,
+            layout,
+            input_layout,
+            sampler,
+            buffer,
+            depth,
+            normal_roughness,
+            specular,
+            reflection,
+            bind_group,
+            reflection_bind_group,
+        }
+    }
+
+    // The fragment targets of pipelines that draw into `begin_gbuffer`'s
+    // pass, for a fragment shader returning `GBufferOutput` from
+    // gbuffer.wgsl
+    pub fn gbuffer_targets() -> [Option<wgpu::ColorTargetState>; 2] {
+        [Some(NORMAL_ROUGHNESS_FORMAT.into()), Some(SPECULAR_FORMAT.into())]
+    }
+
+    // The depth state of those pipelines
+    pub fn gbuffer_depth() -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias: Default::default(),
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        input_layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (
+        Tracked<wgpu::TextureView>,
+        Tracked<wgpu::TextureView>,
+        Tracked<wgpu::TextureView>,
+        Tracked<wgpu::TextureView>,
+        wgpu::BindGroup,
+        wgpu::BindGroup,
+    ) {
+        let target = |label: &str, format| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view_label = format!("{} View", label);
+            Tracked::view(
+                ResourceKind::Target,
+                &texture,
+                &wgpu::TextureViewDescriptor {
+                    label: Some(&view_label),
+                    ..Default::default()
+                },
+            )
+        };
+        let depth_usage = wgpu::TextureUsages::TEXTURE_BINDING;
+        let depth = depth_prepass::depth_target(device, "SSR Depth", width, height, depth_usage);
+        let normal_roughness = target("SSR Normal Roughness Target", NORMAL_ROUGHNESS_FORMAT);
+        let specular = target("SSR Specular Target", SPECULAR_FORMAT);
+        let reflection = target("SSR Reflection Target", color_format);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SSR Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&normal_roughness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&specular),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        let reflection_bind_group = Self::input_bind_group(device, input_layout, &reflection);
+        (depth, normal_roughness, specular, reflection, bind_group, reflection_bind_group)
+    }
+
+    fn input_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        input: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("SSR Input Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            }],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (
+            self.depth,
+            self.normal_roughness,
+            self.specular,
+            self.reflection,
+            self.bind_group,
+            self.reflection_bind_group,
+        ) = Self::create_targets(
+            device,
+            &self.layout,
+            &self.input_layout,
+            &self.sampler,
+            &self.buffer,
+            self.color_format,
+            width,
+            height,
+        );
+    }
+
+    // Once a frame, after the camera has moved
+    pub fn update(&self, queue: &wgpu::Queue, camera_uniform: &CameraUniform) {
+        let uniform = SsrUniform {
+            inverse_view_proj: camera_uniform.view_proj().invert().unwrap_or(Matrix4::identity()).into(),
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // The G-buffer's, DEPTH_FORMAT and read as a texture_2d<f32>, for
+    // depth of field too
+    pub fn depth_view(&self) -> &wgpu::TextureView {
+        &self.depth
+    }
+
+    // The instanced meshes' pipeline, which `begin_gbuffer` starts with
+    pub fn instance_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.instance_pipeline
+    }
+
+    // The pass the G-buffer pipelines draw in; pixels nothing is drawn to
+    // are as far as the sky and as rough as can be
+    pub fn begin_gbuffer<'e>(&'e self, encoder: &'e mut wgpu::CommandEncoder) -> TracedPass<'e> {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("SSR G-Buffer Pass"),
+            color_attachments: &[&self.normal_roughness, &self.specular].map(|view| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            }),
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.instance_pipeline);
+        pass
+    }
+
+    // Traces the G-buffer from `begin_gbuffer` for the reflections of
+    // `target`, which has the format `new` was given and the opaque scene
+    // in it, and adds them on
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let scene_bind_group = Self::input_bind_group(device, &self.input_layout, target);
+        // every reflection pixel is overwritten, the scene's added onto
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        let passes = [
+            ("SSR Trace Pass", &*self.reflection, clear, &self.trace_pipeline, &scene_bind_group),
+            ("SSR Composite Pass", target, wgpu::LoadOp::Load, &self.composite_pipeline, &self.reflection_bind_group),
+        ];
+        for (label, view, load, pipeline, input) in passes {
+            let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+                label: Some(label),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, camera_bind_group, &[]);
+            pass.set_bind_group(1, &self.bind_group, &[]);
+            pass.set_bind_group(2, input, &[]);
+            pass.draw(0..3, 0..1);
+        }
+    }
+}
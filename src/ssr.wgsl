@@ -0,0 +1,188 @@
+// Screen-space reflections, see ssr.rs. fs_trace marches each reflective
+// pixel's reflection through the G-buffer's depth and takes the scene's
+// colour where it hits something, as a correction to the environment or
+// probe reflection the pixel was lit with. fs_composite adds that onto the
+// scene.
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "fullscreen.wgsl"
+#include "pbr.wgsl"
+#include "reflection_probes.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+@group(0) @binding(4)
+var t_prefiltered: texture_cube<f32>;
+@group(0) @binding(5)
+var t_brdf_lut: texture_2d<f32>;
+@group(0) @binding(6)
+var s_ibl: sampler;
+
+// Matches `SsrUniform` in ssr.rs
+struct SsrUniform {
+    // the camera's, from clip space back to the world
+    inverse_view_proj: mat4x4<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> ssr: SsrUniform;
+// read as floats, GL can't load from depth textures
+@group(1) @binding(1)
+var t_depth: texture_2d<f32>;
+@group(1) @binding(2)
+var t_normal_roughness: texture_2d<f32>;
+@group(1) @binding(3)
+var t_specular: texture_2d<f32>;
+@group(1) @binding(4)
+var s_input: sampler;
+
+// the scene for fs_trace, the reflections for fs_composite
+@group(2) @binding(0)
+var t_input: texture_2d<f32>;
+
+// the prefiltered map's last mip, as in model.wgsl
+const MAX_REFLECTION_LOD: f32 = 4.0;
+// rougher surfaces keep the environment's blurrier reflection, and fade
+// into it from half this
+const MAX_ROUGHNESS: f32 = 0.5;
+// how far rays go, in world units, in steps that grow further apart
+const MAX_DISTANCE: f32 = 40.0;
+const STEPS: u32 = 48u;
+// halving the last step, once something's been hit
+const REFINE_STEPS: u32 = 5u;
+// how far behind a surface a ray can be and still have hit it, at least
+const THICKNESS: f32 = 0.5;
+// off the surface along its normal, so rays don't hit where they start
+const SURFACE_BIAS: f32 = 0.02;
+// of the way towards the screen's edges, where hits start fading out
+const EDGE_FADE_START: f32 = 0.8;
+
+fn world_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let clip = ssr.inverse_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return clip.xyz / clip.w;
+}
+
+struct Hit {
+    uv: vec2<f32>,
+    // how far along the ray, zero for none
+    distance: f32,
+};
+
+// Where the ray from `origin` along `r` first goes behind the depth buffer,
+// within its thickness. `noise` shifts the steps per pixel, which turns
+// the banding between them into noise TAA smooths out.
+fn trace(origin: vec3<f32>, r: vec3<f32>, noise: f32) -> Hit {
+    let eye = camera.view_pos.xyz;
+    let size = vec2<f32>(textureDimensions(t_depth));
+    var hit: Hit;
+    var last = 0.0;
+    for (var i = 0u; i < STEPS; i++) {
+        let step = (f32(i) + noise) / f32(STEPS);
+        let t = MAX_DISTANCE * step * step;
+        let p = origin + r * t;
+        let clip = camera.view_proj * vec4<f32>(p, 1.0);
+        if clip.w <= 0.0 {
+            break;
+        }
+        let uv = vec2<f32>(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5);
+        if any(uv < vec2<f32>(0.0)) || any(uv >= vec2<f32>(1.0)) {
+            break;
+        }
+        let depth = textureLoad(t_depth, vec2<i32>(uv * size), 0).r;
+        // nothing but the sky there; it's what the environment already is
+        if depth < 1.0 {
+            let behind = distance(eye, p) - distance(eye, world_position(uv, depth));
+            // any further and it's gone behind whatever's there
+            if behind > 0.0 && behind < max(THICKNESS, t - last) {
+                hit.distance = t;
+                break;
+            }
+        }
+        last = t;
+    }
+    if hit.distance == 0.0 {
+        return hit;
+    }
+
+    // between the last step in front and the first behind
+    var near = last;
+    var far = hit.distance;
+    for (var i = 0u; i < REFINE_STEPS; i++) {
+        let t = (near + far) * 0.5;
+        let p = origin + r * t;
+        let clip = camera.view_proj * vec4<f32>(p, 1.0);
+        let uv = vec2<f32>(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5);
+        let depth = textureLoad(t_depth, vec2<i32>(clamp(uv, vec2<f32>(0.0), vec2<f32>(1.0)) * (size - 1.0)), 0).r;
+        if distance(eye, p) > distance(eye, world_position(uv, depth)) {
+            far = t;
+        } else {
+            near = t;
+        }
+    }
+    let clip = camera.view_proj * vec4<f32>(origin + r * far, 1.0);
+    hit.uv = vec2<f32>(clip.x / clip.w * 0.5 + 0.5, 0.5 - clip.y / clip.w * 0.5);
+    hit.distance = far;
+    return hit;
+}
+
+// What the scene's reflection adds over the environment's, as the pixel
+// was lit: the split sum's specular light, outside the fog
+@fragment
+fn fs_trace(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.position.xy);
+    let depth = textureLoad(t_depth, pixel, 0).r;
+    let normal_roughness = textureLoad(t_normal_roughness, pixel, 0);
+    let roughness = normal_roughness.w;
+    if depth >= 1.0 || roughness >= MAX_ROUGHNESS {
+        return vec4<f32>(0.0);
+    }
+    let uv = in.position.xy / vec2<f32>(textureDimensions(t_depth));
+    let position = world_position(uv, depth);
+    let eye = camera.view_pos.xyz;
+    let n = normalize(normal_roughness.xyz);
+    let v = normalize(eye - position);
+    let r = reflect(-v, n);
+
+    // interleaved gradient noise, as model.wgsl's `lod_fade`
+    let noise = fract(52.9829189 * fract(dot(floor(in.position.xy), vec2<f32>(0.06711056, 0.00583715))));
+    let hit = trace(position + n * SURFACE_BIAS, r, noise);
+    if hit.distance == 0.0 {
+        return vec4<f32>(0.0);
+    }
+    // the back of something can't be seen in the reflection; the instanced
+    // meshes have no normal and count as facing it
+    let hit_normal = textureLoad(t_normal_roughness, vec2<i32>(hit.uv * vec2<f32>(textureDimensions(t_depth))), 0).xyz;
+    if dot(hit_normal, r) > 0.0 {
+        return vec4<f32>(0.0);
+    }
+
+    // fading out where there's little to go on: near the screen's edges,
+    // far along the ray, on rougher surfaces and towards the camera, where
+    // what's hit is mostly hidden by what's reflecting it
+    let edge = max(abs(hit.uv.x * 2.0 - 1.0), abs(hit.uv.y * 2.0 - 1.0));
+    let confidence = (1.0 - smoothstep(EDGE_FADE_START, 1.0, edge))
+        * (1.0 - smoothstep(0.5, 1.0, hit.distance / MAX_DISTANCE))
+        * (1.0 - smoothstep(MAX_ROUGHNESS * 0.5, MAX_ROUGHNESS, roughness))
+        * (1.0 - smoothstep(0.0, 0.5, dot(r, v)));
+
+    // the reflection the pixel was lit with, as `image_based_light` has it
+    let f0 = textureLoad(t_specular, pixel, 0).rgb;
+    let n_dot_v = max(dot(n, v), 0.0);
+    let f = fresnel_schlick_roughness(n_dot_v, f0, roughness);
+    let lod = roughness * MAX_REFLECTION_LOD;
+    let environment = textureSampleLevel(t_prefiltered, s_ibl, r, lod).rgb;
+    let fallback = probe_radiance(s_ibl, position, r, lod, environment);
+    let brdf = textureSampleLevel(t_brdf_lut, s_ibl, vec2<f32>(n_dot_v, roughness), 0.0).rg;
+    let scene = textureSampleLevel(t_input, s_input, hit.uv, 0.0).rgb;
+    let visible = 1.0 - fog_amount(fog, eye, position);
+    return vec4<f32>((scene - fallback) * (f * brdf.x + brdf.y) * confidence * visible, 1.0);
+}
+
+// Added onto the scene
+@fragment
+fn fs_composite(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureLoad(t_input, vec2<i32>(in.position.xy), 0);
+}
@@ -0,0 +1,30 @@
+// The instanced meshes in the SSR G-buffer. They're unlit, so there's
+// nothing in them to reflect; they only hide what's behind them.
+
+#include "camera.wgsl"
+#include "gbuffer.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput) -> @builtin(position) vec4<f32> {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return camera.view_proj * model_matrix * vec4<f32>(position, 1.0);
+}
+
+@fragment
+fn fs_main() -> GBufferOutput {
+    return gbuffer_output(vec3<f32>(0.0), 1.0, vec3<f32>(0.04));
+}
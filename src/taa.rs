@@ -0,0 +1,378 @@
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera;
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::SamplerDesc;
+
+// NDC motion since the last frame, which fits half floats easily
+const VELOCITY_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg16Float;
+// how much of each resolved frame is new, the rest is the history
+const CURRENT_WEIGHT: f32 = 0.1;
+// jitter sequence length, after which every pixel has been sampled at as
+// many positions inside it
+const JITTER_SAMPLES: u32 = 8;
+
+// Matches `TaaUniform` in taa_resolve.wgsl
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TaaUniform {
+    current_weight: f32,
+    _padding: [u32; 3],
+}
+
+// Temporal anti-aliasing. Every frame the projection is shifted by a
+// different fraction of a pixel (`next_jitter`) and the scene is rendered
+// into `view`. A velocity pass records how far each pixel moved on screen
+// since the last frame, and the resolve blends the frame into the history
+// of earlier ones found by following that motion back. The history is
+// clamped to the colours around each pixel this frame first, which stops
+// most ghosting where the reprojection is wrong.
+pub struct TemporalAa {
+    color_format: wgpu::TextureFormat,
+    velocity_pipeline: wgpu::RenderPipeline,
+    layout: wgpu::BindGroupLayout,
+    resolve_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    buffer: wgpu::Buffer,
+    frame: Tracked<wgpu::TextureView>,
+    velocity: Tracked<wgpu::TextureView>,
+    // ping-ponged, the resolve reads one and writes the other
+    history: [Tracked<wgpu::TextureView>; 2],
+    // reading history[i], one per direction
+    bind_groups: [wgpu::BindGroup; 2],
+    // the history that was written last
+    current: usize,
+    // false until a frame has been resolved at this size
+    history_valid: bool,
+    jitter_index: u32,
+}
+
+impl TemporalAa {
+    // `vertex_layouts` are those of the meshes drawn into the velocity
+    // pass, starting with a Float32x3 position at location 0, the instance
+    // matrix at locations 5 to 8 and last frame's at 10 to 13
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+        camera_layout: &wgpu::BindGroupLayout,
+        vertex_layouts: &[wgpu::VertexBufferLayout],
+    ) -> Self {
+        let velocity_shader = shader_preprocessor::load("taa_velocity.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_vertex_buffers("vs_main", vertex_layouts)?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "TAA Velocity Shader");
+        let velocity_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Velocity Pipeline Layout"),
+            bind_group_layouts: &[camera_layout],
+            push_constant_ranges: &[],
+        });
+        let velocity_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("TAA Velocity Pipeline"),
+            layout: Some(&velocity_layout),
+            vertex: wgpu::VertexState {
+                module: &velocity_shader,
+                entry_point: "vs_main",
+                buffers: vertex_layouts,
+                compilation_options: Default::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &velocity_shader,
+                entry_point: "fs_main",
+                targets: &Self::velocity_targets(),
+                compilation_options: Default::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let resolve_shader = shader_preprocessor::load("taa_resolve.wgsl", &Default::default())
+            .and_then(|shader| {
+                ShaderReflection::new(&shader)?.check_struct::<TaaUniform>("TaaUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "TAA Resolve Shader");
+        let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable },
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TAA Resolve Bind Group Layout"),
+            entries: &[
+                texture_entry(0, false),
+                texture_entry(1, true),
+                texture_entry(2, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let resolve_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("TAA Resolve Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let resolve_pipeline = fullscreen_pipeline_with(
+            device,
+            "TAA Resolve Pipeline",
+            &resolve_layout,
+            &resolve_shader,
+            "fs_main",
+            // the frame and the next history
+            &[Some(color_format.into()), Some(color_format.into())],
+        );
+        // the history is sampled between texels wherever things moved
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("TAA History Sampler"));
+        let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TAA Buffer"),
+            contents: bytemuck::cast_slice(&[TaaUniform {
+                current_weight: 1.0,
+                _padding: [0; 3],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let (frame, velocity, history, bind_groups) =
+            Self::create_targets(device, &layout, &sampler, &buffer, color_format, width, height);
+        Self {
+            color_format,
+            velocity_pipeline,
+            layout,
+            resolve_pipeline,
+            sampler,
+            buffer,
+            frame,
+            velocity,
+            history,
+            bind_groups,
+            current: 0,
+            history_valid: false,
+            jitter_index: 0,
+        }
+    }
+
+    // The fragment targets of pipelines that draw into `begin_velocity`'s
+    // pass, for a fragment shader returning `screen_motion` from camera.wgsl
+    pub fn velocity_targets() -> [Option<wgpu::ColorTargetState>; 1] {
+        [Some(VELOCITY_FORMAT.into())]
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        buffer: &wgpu::Buffer,
+        color_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (
+        Tracked<wgpu::TextureView>,
+        Tracked<wgpu::TextureView>,
+        [Tracked<wgpu::TextureView>; 2],
+        [wgpu::BindGroup; 2],
+    ) {
+        let target = |label: &str, format| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: width.max(1),
+                    height: height.max(1),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let view_label = format!("{} View", label);
+            Tracked::view(
+                ResourceKind::Target,
+                &texture,
+                &wgpu::TextureViewDescriptor {
+                    label: Some(&view_label),
+                    ..Default::default()
+                },
+            )
+        };
+        let frame = target("TAA Frame", color_format);
+        let velocity = target("TAA Velocity Target", VELOCITY_FORMAT);
+        let history = [target("TAA History 0", color_format), target("TAA History 1", color_format)];
+        let bind_group = |history: &wgpu::TextureView| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("TAA Resolve Bind Group"),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&frame),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(history),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&velocity),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+        let bind_groups = [bind_group(&history[0]), bind_group(&history[1])];
+        (frame, velocity, history, bind_groups)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.frame, self.velocity, self.history, self.bind_groups) = Self::create_targets(
+            device,
+            &self.layout,
+            &self.sampler,
+            &self.buffer,
+            self.color_format,
+            width,
+            height,
+        );
+        self.reset();
+    }
+
+    // Starts over from the next frame alone, for when the history no
+    // longer has anything to do with it, like after TAA was off
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+    }
+
+    // The sub-pixel offset to render the next frame with, in NDC units for
+    // `CameraUniform::update_jittered`. Points of the Halton (2, 3)
+    // sequence, which cover each pixel evenly.
+    pub fn next_jitter(&mut self, width: u32, height: u32) -> Vector2<f32> {
+        self.jitter_index = self.jitter_index % JITTER_SAMPLES + 1;
+        let pixel = vec2(halton(self.jitter_index, 2), halton(self.jitter_index, 3)) - vec2(0.5, 0.5);
+        // two NDC units across the target
+        vec2(pixel.x * 2.0 / width.max(1) as f32, pixel.y * 2.0 / height.max(1) as f32)
+    }
+
+    // Where the scene gets rendered instead of the frame
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.frame
+    }
+
+    // What `begin_velocity`'s pass drew, an `Rg16Float` texture of NDC
+    // motion since the last frame
+    pub fn velocity_view(&self) -> &wgpu::TextureView {
+        &self.velocity
+    }
+
+    // The instanced meshes' pipeline, which `begin_velocity` starts with
+    pub fn velocity_pipeline(&self) -> &wgpu::RenderPipeline {
+        &self.velocity_pipeline
+    }
+
+    // The pass the velocity pipelines draw in; pixels nothing is drawn to
+    // haven't moved
+    pub fn begin_velocity<'e>(&'e self, encoder: &'e mut wgpu::CommandEncoder) -> TracedPass<'e> {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("TAA Velocity Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &self.velocity,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.velocity_pipeline);
+        pass
+    }
+
+    // Resolves the frame into `target`, which has the format `new` was
+    // given, and keeps it as the next frame's history
+    pub fn resolve(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let uniform = TaaUniform {
+            current_weight: if self.history_valid { CURRENT_WEIGHT } else { 1.0 },
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let next = 1 - self.current;
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("TAA Resolve Pass"),
+            color_attachments: &[target, &self.history[next]].map(|view| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        // every pixel is overwritten
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            }),
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.resolve_pipeline);
+        pass.set_bind_group(0, &self.bind_groups[self.current], &[]);
+        pass.draw(0..3, 0..1);
+        drop(pass);
+        self.current = next;
+        self.history_valid = true;
+    }
+}
+
+// The `index`th point of the van der Corput sequence in `base`, in [0, 1)
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
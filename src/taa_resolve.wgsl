@@ -0,0 +1,65 @@
+// Blends this frame into the reprojected TAA history, see taa.rs
+
+#include "fullscreen.wgsl"
+
+// Matches `TaaUniform` in taa.rs
+struct TaaUniform {
+    // how much of the result is this frame, 1 when there's no history
+    current_weight: f32,
+    // to 16 bytes, as uniform buffers are bound
+    _padding_0: u32,
+    _padding_1: u32,
+    _padding_2: u32,
+};
+
+// all the same size as the target
+@group(0) @binding(0)
+var current: texture_2d<f32>;
+@group(0) @binding(1)
+var history: texture_2d<f32>;
+@group(0) @binding(2)
+var velocity: texture_2d<f32>;
+@group(0) @binding(3)
+var history_sampler: sampler;
+@group(0) @binding(4)
+var<uniform> taa: TaaUniform;
+
+struct ResolveOutput {
+    @location(0) color: vec4<f32>,
+    // the next frame's history, the same colour
+    @location(1) history: vec4<f32>,
+};
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> ResolveOutput {
+    let size = vec2<i32>(textureDimensions(current));
+    let pixel = vec2<i32>(in.position.xy);
+    let color = textureLoad(current, pixel, 0);
+
+    // the range of colours around the pixel this frame; history outside it
+    // belongs to something that's no longer there
+    var low = color;
+    var high = color;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let neighbour = textureLoad(current, clamp(pixel + vec2<i32>(x, y), vec2<i32>(0), size - 1), 0);
+            low = min(low, neighbour);
+            high = max(high, neighbour);
+        }
+    }
+
+    // NDC motion to texture coordinates, where y points down
+    let motion = textureLoad(velocity, pixel, 0).xy * vec2<f32>(0.5, -0.5);
+    let uv = in.position.xy / vec2<f32>(size) - motion;
+    var weight = taa.current_weight;
+    // newly uncovered at the edge of the screen
+    if any(uv < vec2<f32>(0.0)) || any(uv > vec2<f32>(1.0)) {
+        weight = 1.0;
+    }
+    let previous = clamp(textureSampleLevel(history, history_sampler, uv, 0.0), low, high);
+
+    var out: ResolveOutput;
+    out.color = mix(previous, color, weight);
+    out.history = out.color;
+    return out;
+}
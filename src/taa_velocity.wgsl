@@ -0,0 +1,55 @@
+// How far the instanced meshes moved on screen since the last frame, for
+// reprojecting the TAA history and motion blur
+
+#include "camera.wgsl"
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+};
+
+// The same instance's matrix last frame, from `InstanceRaw::previous_desc`
+struct PreviousInput {
+    @location(10) model_matrix_0: vec4<f32>,
+    @location(11) model_matrix_1: vec4<f32>,
+    @location(12) model_matrix_2: vec4<f32>,
+    @location(13) model_matrix_3: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+    @location(1) prev_world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@location(0) position: vec3<f32>, instance: InstanceInput, previous: PreviousInput) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let prev_model_matrix = mat4x4<f32>(
+        previous.model_matrix_0,
+        previous.model_matrix_1,
+        previous.model_matrix_2,
+        previous.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(position, 1.0);
+    var out: VertexOutput;
+    out.world_position = world_position.xyz;
+    out.prev_world_position = (prev_model_matrix * vec4<f32>(position, 1.0)).xyz;
+    // jittered, to cover the same pixels as the colour pass
+    out.clip_position = camera.view_proj * world_position;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec2<f32> {
+    return object_motion(camera, in.world_position, in.prev_world_position);
+}
@@ -0,0 +1,871 @@
+use std::ops::Range;
+
+use anyhow::*;
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::assets;
+use crate::bounds::{Aabb, Frustum};
+use crate::camera::{self, CameraUniform};
+use crate::depth_prepass::{self, DEPTH_FORMAT};
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::lighting::LightUniform;
+use crate::render_target;
+use crate::resources::{ResourceKind, Tracked};
+use crate::scene::TerrainDesc;
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::shadow::{self, ShadowMap};
+use crate::ssr::ScreenSpaceReflections;
+use crate::taa::TemporalAa;
+use crate::texture::{MipmapGenerator, SamplerDesc, Texture};
+
+// sRGB colours of the layers a terrain has no texture for, in splat map
+// channel order: grass, rock, dirt and snow
+const DEFAULT_LAYERS: [[u8; 3]; 4] = [[92, 128, 56], [118, 112, 104], [128, 98, 66], [236, 238, 242]];
+const DEFAULT_LAYER_SIZE: u32 = 64;
+
+// Cells along each side of a chunk, at every level of detail
+const CHUNK_CELLS: u32 = 32;
+// the patch mesh's grid and its four skirts
+const CHUNK_TRIANGLES: u32 = 2 * CHUNK_CELLS * CHUNK_CELLS + 8 * CHUNK_CELLS;
+
+// One vertex of the patch mesh every chunk is drawn with
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PatchVertex {
+    // column and row in the chunk, and one on its skirt
+    position: [f32; 3],
+}
+
+impl PatchVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Float32x3];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<PatchVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// A grid of CHUNK_CELLS x CHUNK_CELLS cells, two triangles a cell
+// counter-clockwise from above, with a skirt hanging down from each edge to
+// hide the cracks where a chunk meets one with less detail
+fn patch_mesh() -> (Vec<PatchVertex>, Vec<u16>) {
+    let n = CHUNK_CELLS as u16;
+    let grid = |column: u16, row: u16| row * (n + 1) + column;
+    let mut vertices: Vec<_> = (0..=n)
+        .flat_map(|row| (0..=n).map(move |column| PatchVertex { position: [column as f32, row as f32, 0.0] }))
+        .collect();
+    let mut indices: Vec<_> = (0..n)
+        .flat_map(|row| (0..n).map(move |column| grid(column, row)))
+        .flat_map(|a| {
+            let (b, c, d) = (a + 1, a + n + 1, a + n + 2);
+            [a, c, b, b, c, d]
+        })
+        .collect();
+    // each edge is walked so its skirt faces outwards
+    for edge in 0..4 {
+        let skirt = vertices.len() as u16;
+        let top: Vec<_> = (0..=n)
+            .map(|t| {
+                let (column, row) = match edge {
+                    0 => (t, 0),
+                    1 => (n, t),
+                    2 => (n - t, n),
+                    _ => (0, n - t),
+                };
+                vertices.push(PatchVertex { position: [column as f32, row as f32, 1.0] });
+                grid(column, row)
+            })
+            .collect();
+        for t in 0..n {
+            let (top0, top1, bottom0, bottom1) = (top[t as usize], top[t as usize + 1], skirt + t, skirt + t + 1);
+            indices.extend([top0, top1, bottom0, top1, bottom1, bottom0]);
+        }
+    }
+    (vertices, indices)
+}
+
+// Which chunk an instance of the patch mesh draws
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ChunkInstance {
+    // the first sample's column and row, how many samples apart the
+    // vertices are, and how far the skirt hangs down
+    chunk: [f32; 4],
+}
+
+impl ChunkInstance {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ChunkInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// A square of the heightmap drawn with one instance of the patch mesh, a
+// vertex every `step` samples
+#[derive(Debug, Clone)]
+struct Chunk {
+    origin: [u32; 2],
+    step: u32,
+    bounds: Aabb,
+    // the up to four chunks covering this one with twice the detail, none
+    // for the most detailed
+    children: Range<usize>,
+}
+
+// Matches `TerrainUniform` in terrain.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct TerrainUniform {
+    origin: [f32; 3],
+    _padding0: f32,
+    spacing: [f32; 2],
+    last: [f32; 2],
+    tiling: [f32; 2],
+    _padding: [f32; 2],
+}
+
+/// A terrain's heights on a regular grid in world space, a sample per
+/// heightmap pixel with the rows running along +z.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    columns: usize,
+    rows: usize,
+    heights: Vec<f32>,
+    // the first sample's corner at the terrain's base, and how far apart
+    // samples are along x and z
+    origin: Point3<f32>,
+    spacing: Vector2<f32>,
+}
+
+impl Heightmap {
+    // Scaled and placed as `desc` says. Colour images go by their luma.
+    pub fn from_image(image: &image::DynamicImage, desc: &TerrainDesc) -> Result<Self> {
+        let luma = image.to_luma16();
+        let (columns, rows) = (luma.width() as usize, luma.height() as usize);
+        ensure!(columns >= 2 && rows >= 2, "A {}x{} heightmap has no triangles", columns, rows);
+        let [x, y, z] = desc.position;
+        let [width, depth] = desc.size;
+        Ok(Self {
+            columns,
+            rows,
+            heights: luma.pixels().map(|p| y + p.0[0] as f32 / u16::MAX as f32 * desc.height).collect(),
+            origin: Point3::new(x - width / 2.0, y, z - depth / 2.0),
+            spacing: Vector2::new(width / (columns - 1) as f32, depth / (rows - 1) as f32),
+        })
+    }
+
+    fn height(&self, column: usize, row: usize) -> f32 {
+        self.heights[row * self.columns + column]
+    }
+
+    // The ground's height at `x`, `z` on the triangles of the terrain's most
+    // detailed chunks, None off its edges. For putting the camera and
+    // objects on it.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let u = (x - self.origin.x) / self.spacing.x;
+        let v = (z - self.origin.z) / self.spacing.y;
+        if !(0.0..=(self.columns - 1) as f32).contains(&u) || !(0.0..=(self.rows - 1) as f32).contains(&v) {
+            return None;
+        }
+        let column = (u as usize).min(self.columns - 2);
+        let row = (v as usize).min(self.rows - 2);
+        let (u, v) = (u - column as f32, v - row as f32);
+        let [a, b, c, d] = [(0, 0), (1, 0), (0, 1), (1, 1)].map(|(x, z)| self.height(column + x, row + z));
+        // cells are split from corner b to c, as in `patch_mesh`
+        Some(if u + v <= 1.0 {
+            a + (b - a) * u + (c - a) * v
+        } else {
+            d + (c - d) * (1.0 - u) + (b - d) * (1.0 - v)
+        })
+    }
+
+    // From the slopes to the neighbouring samples, one-sided at the edges
+    fn normal(&self, column: usize, row: usize) -> Vector3<f32> {
+        let (left, right) = (column.saturating_sub(1), (column + 1).min(self.columns - 1));
+        let (back, front) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+        let dx = (self.height(right, row) - self.height(left, row)) / ((right - left) as f32 * self.spacing.x);
+        let dz = (self.height(column, front) - self.height(column, back)) / ((front - back) as f32 * self.spacing.y);
+        Vector3::new(-dx, 1.0, -dz).normalize()
+    }
+
+    pub fn bounds(&self) -> Aabb {
+        self.region_bounds([0, 0], self.columns.max(self.rows))
+    }
+
+    // Samples along x and along z
+    pub fn size(&self) -> [usize; 2] {
+        [self.columns, self.rows]
+    }
+
+    // In world units, a row along x at a time
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    // Around the samples from `column`, `row` to `cells` further along both,
+    // or to the edges
+    fn region_bounds(&self, [column, row]: [usize; 2], cells: usize) -> Aabb {
+        let (last_column, last_row) = ((column + cells).min(self.columns - 1), (row + cells).min(self.rows - 1));
+        let (min, max) = (row..=last_row)
+            .flat_map(|row| (column..=last_column).map(move |column| (column, row)))
+            .map(|(column, row)| self.height(column, row))
+            .fold((f32::MAX, f32::MIN), |(min, max), height| (min.min(height), max.max(height)));
+        let corner = |column: usize, row: usize| {
+            Vector2::new(column as f32, row as f32).mul_element_wise(self.spacing) + Vector2::new(self.origin.x, self.origin.z)
+        };
+        let (first, last) = (corner(column, row), corner(last_column, last_row));
+        Aabb::new(Point3::new(first.x, min, first.y), Point3::new(last.x, max, last.y))
+    }
+
+    // The quadtree of chunks covering the heightmap, the root first and
+    // every chunk's children after it
+    fn chunks(&self) -> Vec<Chunk> {
+        let cells = (self.columns.max(self.rows) - 1) as u32;
+        let mut step = 1;
+        while CHUNK_CELLS * step < cells {
+            step *= 2;
+        }
+        let chunk = |origin, step| Chunk {
+            origin,
+            step,
+            bounds: Aabb::new(Point3::origin(), Point3::origin()),
+            children: 0..0,
+        };
+        let mut chunks = vec![chunk([0, 0], step)];
+        let mut i = 0;
+        while i < chunks.len() {
+            let Chunk { origin: [column, row], step, .. } = chunks[i];
+            if step > 1 {
+                let half = CHUNK_CELLS * step / 2;
+                let first = chunks.len();
+                for (x, z) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let origin = [column + x * half, row + z * half];
+                    // quadtrees over heightmaps that aren't square or a
+                    // power of two across leave some out
+                    if (origin[0] as usize) < self.columns - 1 && (origin[1] as usize) < self.rows - 1 {
+                        chunks.push(chunk(origin, step / 2));
+                    }
+                }
+                chunks[i].children = first..chunks.len();
+            }
+            i += 1;
+        }
+        for i in (0..chunks.len()).rev() {
+            let Chunk { origin, step, .. } = chunks[i];
+            chunks[i].bounds = chunks[i]
+                .children
+                .clone()
+                .map(|child| chunks[child].bounds)
+                .reduce(Aabb::union)
+                .unwrap_or_else(|| self.region_bounds(origin.map(|i| i as usize), (CHUNK_CELLS * step) as usize));
+        }
+        chunks
+    }
+
+    // Layer weights for terrains without a splat map: rock on steep slopes,
+    // snow on the peaks, dirt in the lowest parts and grass everywhere else
+    fn generated_splat_map(&self) -> image::RgbaImage {
+        let bounds = self.bounds();
+        let range = (bounds.max.y - bounds.min.y).max(f32::EPSILON);
+        image::RgbaImage::from_fn(self.columns as u32, self.rows as u32, |column, row| {
+            let (column, row) = (column as usize, row as usize);
+            let height = (self.height(column, row) - bounds.min.y) / range;
+            let slope = 1.0 - self.normal(column, row).y;
+            let rock = smoothstep(0.12, 0.3, slope);
+            let snow = smoothstep(0.7, 0.85, height) * (1.0 - rock);
+            let dirt = (1.0 - smoothstep(0.05, 0.2, height)) * (1.0 - rock - snow);
+            let grass = 1.0 - rock - snow - dirt;
+            image::Rgba([grass, rock, dirt, snow].map(|weight| (weight.clamp(0.0, 1.0) * 255.0).round() as u8))
+        })
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// A plain colour with some texel to texel noise, so tiling shows
+fn default_layer([r, g, b]: [u8; 3]) -> image::RgbaImage {
+    image::RgbaImage::from_fn(DEFAULT_LAYER_SIZE, DEFAULT_LAYER_SIZE, |x, y| {
+        let hash = (x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663)).wrapping_mul(2_654_435_761);
+        let shade = 0.85 + 0.3 * (hash >> 24) as f32 / 255.0;
+        let scale = |channel: u8| (channel as f32 * shade).min(255.0) as u8;
+        image::Rgba([scale(r), scale(g), scale(b), 255])
+    })
+}
+
+// Weights aren't colours, so unlike the layers this is linear, and a
+// single level
+fn splat_texture(device: &wgpu::Device, queue: &wgpu::Queue, splat: &image::RgbaImage) -> Tracked<wgpu::TextureView> {
+    let texture = device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Terrain Splat Map"),
+                size: wgpu::Extent3d {
+                    width: splat.width(),
+                    height: splat.height(),
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            splat,
+        );
+    Tracked::view(ResourceKind::Texture, &texture, &wgpu::TextureViewDescriptor::default())
+}
+
+// The heights as they are, for the vertex shader to load
+fn height_texture(device: &wgpu::Device, queue: &wgpu::Queue, heightmap: &Heightmap) -> Tracked<wgpu::TextureView> {
+    let texture = device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Terrain Heights"),
+                size: wgpu::Extent3d {
+                    width: heightmap.columns as u32,
+                    height: heightmap.rows as u32,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(&heightmap.heights),
+        );
+    Tracked::view(ResourceKind::Texture, &texture, &wgpu::TextureViewDescriptor::default())
+}
+
+// A heightmap terrain, drawn as a quadtree of chunks that all share one
+// patch mesh and read their heights in the vertex shader. Every frame the
+// chunks near the camera are split into ones with more detail, so distant
+// hills cost as much as close ones, and each chunk's skirt covers the
+// cracks between different levels. Up to four tiling texture layers are
+// blended by a splat map, and it's lit by the scene's light, in and out of
+// the shadows it and the instanced meshes cast. It's drawn first, in a pass
+// with a depth buffer of its own, so its hills hide each other; the rest of
+// the scene draws over it.
+pub struct Terrain {
+    desc: TerrainDesc,
+    heightmap: Heightmap,
+    chunks: Vec<Chunk>,
+    patch_vertex_buffer: Tracked<wgpu::Buffer>,
+    patch_index_buffer: Tracked<wgpu::Buffer>,
+    patch_index_count: u32,
+    // the chunks picked this frame, those in view first
+    chunk_buffer: Tracked<wgpu::Buffer>,
+    visible_chunks: u32,
+    selected_chunks: u32,
+    // the splat map and heights, only used through `bind_group` and kept
+    // for the memory readout
+    _maps: [Tracked<wgpu::TextureView>; 2],
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    // into a face of a cubemap, see `render_target::CUBE_FACE_FRONT_FACE`
+    capture_pipeline: wgpu::RenderPipeline,
+    velocity_pipeline: wgpu::RenderPipeline,
+    gbuffer_pipeline: wgpu::RenderPipeline,
+    shadow_pipeline: wgpu::RenderPipeline,
+    depth_view: Tracked<wgpu::TextureView>,
+}
+
+impl Terrain {
+    // Paths in `desc` are used as they are. Draws into `color_format`
+    // targets of `width` x `height`, in the shadows of `shadow_map`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn load(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        shadow_map: &ShadowMap,
+        desc: &TerrainDesc,
+        width: u32,
+        height: u32,
+    ) -> Result<Self> {
+        ensure!(desc.layers.len() <= DEFAULT_LAYERS.len(), "A terrain has four layers at most, not {}", desc.layers.len());
+        let image = assets::read_image(&desc.heightmap)?;
+        let heightmap = Heightmap::from_image(&image, desc).with_context(|| desc.heightmap.display().to_string())?;
+        let max_size = device.limits().max_texture_dimension_2d as usize;
+        ensure!(
+            heightmap.columns <= max_size && heightmap.rows <= max_size,
+            "{}: a {}x{} heightmap is over this device's {} sample limit",
+            desc.heightmap.display(),
+            heightmap.columns,
+            heightmap.rows,
+            max_size
+        );
+        let splat = match &desc.splat_map {
+            Some(path) => assets::read_image(path)?.to_rgba8(),
+            None => heightmap.generated_splat_map(),
+        };
+        let mut mipmaps = MipmapGenerator::new(device);
+        let layers = DEFAULT_LAYERS
+            .iter()
+            .enumerate()
+            .map(|(i, &color)| {
+                let image = match desc.layers.get(i) {
+                    Some(path) => assets::read_image(path)?.to_rgba8(),
+                    None => default_layer(color),
+                };
+                let label = format!("Terrain Layer {}", i);
+                Ok(Texture::from_rgba_with_mipmaps(device, queue, &mut mipmaps, &image, image.width(), image.height(), Some(&label)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let (vertices, indices) = patch_mesh();
+        let patch_vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Patch Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let patch_index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Patch Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let chunks = heightmap.chunks();
+        // room for every chunk, more than any frame picks
+        let chunk_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Terrain Chunk Buffer"),
+            size: (chunks.len() * std::mem::size_of::<ChunkInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let uniform = TerrainUniform {
+            origin: heightmap.origin.into(),
+            _padding0: 0.0,
+            spacing: heightmap.spacing.into(),
+            last: [(heightmap.columns - 1) as f32, (heightmap.rows - 1) as f32],
+            tiling: desc.size.map(|size| size / desc.layer_size.max(f32::EPSILON)),
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Terrain Buffer"),
+            contents: bytemuck::cast_slice(&[uniform]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1),
+            sampler_entry(2),
+            texture_entry(3),
+            texture_entry(4),
+            texture_entry(5),
+            texture_entry(6),
+            sampler_entry(7),
+            wgpu::BindGroupLayoutEntry {
+                binding: 8,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+        ];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Terrain Bind Group Layout"),
+            entries: &entries,
+        });
+        let splat_view = splat_texture(device, queue, &splat);
+        let height_view = height_texture(device, queue, &heightmap);
+        let splat_sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Terrain Splat Sampler"));
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Terrain Bind Group"),
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&splat_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&splat_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&layers[0].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::TextureView(&layers[1].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: wgpu::BindingResource::TextureView(&layers[2].view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::TextureView(&layers[3].view),
+                },
+                // all of them repeat with trilinear filtering
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: wgpu::BindingResource::Sampler(&layers[0].sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: wgpu::BindingResource::TextureView(&height_view),
+                },
+            ],
+        });
+
+        let shader = shader_preprocessor::load("terrain.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_group(1, &entries)?;
+                reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                reflection.check_struct::<FogUniform>("FogUniform")?;
+                reflection.check_struct::<LightUniform>("LightUniform")?;
+                reflection.check_struct::<TerrainUniform>("TerrainUniform")?;
+                reflection.check_vertex_buffers("vs_main", &[PatchVertex::desc(), ChunkInstance::desc()])?;
+                Ok(shader)
+            })?
+            .create_module(device, "Terrain Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout, shadow_map.layout()],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label,
+                               layout,
+                               fragment: Option<(&str, &[Option<wgpu::ColorTargetState>])>,
+                               depth_stencil,
+                               front_face| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: if fragment.is_some() { "vs_main" } else { "vs_shadow" },
+                    buffers: &[PatchVertex::desc(), ChunkInstance::desc()],
+                    compilation_options: Default::default(),
+                },
+                fragment: fragment.map(|(entry_point, targets)| wgpu::FragmentState {
+                    module: &shader,
+                    entry_point,
+                    targets,
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    front_face,
+                    cull_mode: Some(wgpu::Face::Back),
+                    ..Default::default()
+                },
+                depth_stencil,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let depth_write = |bias| wgpu::DepthStencilState {
+            format: DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: Default::default(),
+            bias,
+        };
+        let pipeline = create_pipeline(
+            "Terrain Pipeline",
+            &pipeline_layout,
+            Some(("fs_main", &[Some(color_format.into())])),
+            Some(depth_write(Default::default())),
+            wgpu::FrontFace::Ccw,
+        );
+        let capture_pipeline = create_pipeline(
+            "Terrain Capture Pipeline",
+            &pipeline_layout,
+            Some(("fs_main", &[Some(color_format.into())])),
+            Some(depth_write(Default::default())),
+            render_target::CUBE_FACE_FRONT_FACE,
+        );
+        // no depth there, it's drawn before anything else
+        let velocity_pipeline = create_pipeline(
+            "Terrain Velocity Pipeline",
+            &pipeline_layout,
+            Some(("fs_velocity", &TemporalAa::velocity_targets())),
+            None,
+            wgpu::FrontFace::Ccw,
+        );
+        let gbuffer_pipeline = create_pipeline(
+            "Terrain G-Buffer Pipeline",
+            &pipeline_layout,
+            Some(("fs_gbuffer", &ScreenSpaceReflections::gbuffer_targets())),
+            Some(ScreenSpaceReflections::gbuffer_depth()),
+            wgpu::FrontFace::Ccw,
+        );
+        // the light, and the heights after it
+        let shadow_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Terrain Shadow Pipeline Layout"),
+            bind_group_layouts: &[shadow_map.light_layout(), &layout],
+            push_constant_ranges: &[],
+        });
+        let shadow_pipeline = create_pipeline(
+            "Terrain Shadow Pipeline",
+            &shadow_pipeline_layout,
+            None,
+            Some(depth_write(shadow::DEPTH_BIAS)),
+            wgpu::FrontFace::Ccw,
+        );
+
+        Ok(Self {
+            desc: desc.clone(),
+            heightmap,
+            chunks,
+            patch_vertex_buffer: Tracked::buffer(ResourceKind::Mesh, patch_vertex_buffer),
+            patch_index_buffer: Tracked::buffer(ResourceKind::Mesh, patch_index_buffer),
+            patch_index_count: indices.len() as u32,
+            chunk_buffer: Tracked::buffer(ResourceKind::Buffer, chunk_buffer),
+            visible_chunks: 0,
+            selected_chunks: 0,
+            _maps: [splat_view, height_view],
+            bind_group,
+            pipeline,
+            capture_pipeline,
+            velocity_pipeline,
+            gbuffer_pipeline,
+            shadow_pipeline,
+            depth_view: Self::create_depth(device, width, height),
+        })
+    }
+
+    fn create_depth(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        depth_prepass::depth_target(device, "Terrain Depth", width, height, wgpu::TextureUsages::empty())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_view = Self::create_depth(device, width, height);
+    }
+
+    // As it was loaded
+    pub fn desc(&self) -> &TerrainDesc {
+        &self.desc
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    // Chunks drawn this frame, and their triangles
+    pub fn visible_chunks(&self) -> u32 {
+        self.visible_chunks
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.visible_chunks * CHUNK_TRIANGLES
+    }
+
+    // Once a frame, before drawing: splits the chunks closer to `eye` than
+    // `lod_distance` times their width, down to the most detailed, and
+    // sorts out those outside `frustum`. Chunks out of view still cast
+    // shadows, at the same detail as the ones in view.
+    pub fn select(&mut self, queue: &wgpu::Queue, eye: Point3<f32>, frustum: Option<&Frustum>) {
+        let spacing = self.heightmap.spacing.x.max(self.heightmap.spacing.y);
+        let (mut visible, mut hidden) = (Vec::new(), Vec::new());
+        let mut stack = vec![0];
+        while let Some(i) = stack.pop() {
+            let chunk = &self.chunks[i];
+            let width = (CHUNK_CELLS * chunk.step) as f32 * spacing;
+            if !chunk.children.is_empty() && chunk.bounds.distance(eye) < self.desc.lod_distance * width {
+                stack.extend(chunk.children.clone());
+                continue;
+            }
+            // deep enough to reach the lowest point in the chunk, and half
+            // a cell on flat ground
+            let skirt = (chunk.bounds.max.y - chunk.bounds.min.y).max(width / CHUNK_CELLS as f32 / 2.0);
+            let instance = ChunkInstance {
+                chunk: [chunk.origin[0] as f32, chunk.origin[1] as f32, chunk.step as f32, skirt],
+            };
+            if frustum.is_none_or(|frustum| frustum.intersects_aabb(&chunk.bounds)) {
+                visible.push(instance);
+            } else {
+                hidden.push(instance);
+            }
+        }
+        self.visible_chunks = visible.len() as u32;
+        visible.append(&mut hidden);
+        self.selected_chunks = visible.len() as u32;
+        queue.write_buffer(&self.chunk_buffer, 0, bytemuck::cast_slice(&visible));
+    }
+
+    fn draw_chunks<'a>(&'a self, render_pass: &mut TracedPass<'a>, count: u32) {
+        render_pass.set_vertex_buffer(0, self.patch_vertex_buffer.slice(..));
+        render_pass.set_vertex_buffer(1, self.chunk_buffer.slice(..));
+        render_pass.set_index_buffer(self.patch_index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+        render_pass.draw_indexed(0..self.patch_index_count, 0, 0..count);
+    }
+
+    // Draws the terrain over what's in `target` already, in a pass of its
+    // own with its depth buffer
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        shadow_map: &ShadowMap,
+    ) {
+        let mut render_pass = Self::begin_pass(encoder, target, &self.depth_view, wgpu::StoreOp::Discard);
+        self.draw(&mut render_pass, &self.pipeline, camera_bind_group, shadow_map, self.visible_chunks);
+    }
+
+    // The same with `depth` cleared and kept for later passes, and every
+    // selected chunk rather than only those the camera sees, for views
+    // from somewhere else like a reflection's
+    pub fn encode_with_depth(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        shadow_map: &ShadowMap,
+    ) {
+        let mut render_pass = Self::begin_pass(encoder, target, depth, wgpu::StoreOp::Store);
+        self.draw(&mut render_pass, &self.pipeline, camera_bind_group, shadow_map, self.selected_chunks);
+    }
+
+    // The same into a face of a cubemap, from one of `CubeCameras`'
+    pub fn encode_capture(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        depth: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        shadow_map: &ShadowMap,
+    ) {
+        let mut render_pass = Self::begin_pass(encoder, target, depth, wgpu::StoreOp::Store);
+        self.draw(&mut render_pass, &self.capture_pipeline, camera_bind_group, shadow_map, self.selected_chunks);
+    }
+
+    fn begin_pass<'e>(
+        encoder: &'e mut wgpu::CommandEncoder,
+        target: &'e wgpu::TextureView,
+        depth: &'e wgpu::TextureView,
+        store: wgpu::StoreOp,
+    ) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Terrain Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        })
+    }
+
+    fn draw<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        pipeline: &'a wgpu::RenderPipeline,
+        camera_bind_group: &'a wgpu::BindGroup,
+        shadow_map: &'a ShadowMap,
+        count: u32,
+    ) {
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_bind_group(2, shadow_map.bind_group(), &[]);
+        self.draw_chunks(render_pass, count);
+    }
+
+    // Into TAA's velocity pass, before anything else
+    pub fn draw_velocity<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        shadow_map: &'a ShadowMap,
+    ) {
+        render_pass.set_pipeline(&self.velocity_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_bind_group(2, shadow_map.bind_group(), &[]);
+        self.draw_chunks(render_pass, self.visible_chunks);
+    }
+
+    // Into the SSR G-buffer pass
+    pub fn draw_gbuffer<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        shadow_map: &'a ShadowMap,
+    ) {
+        render_pass.set_pipeline(&self.gbuffer_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_bind_group(2, shadow_map.bind_group(), &[]);
+        self.draw_chunks(render_pass, self.visible_chunks);
+    }
+
+    // Into `ShadowMap::begin`'s pass, which has the light bound
+    pub fn draw_shadow<'a>(&'a self, render_pass: &mut TracedPass<'a>) {
+        render_pass.set_pipeline(&self.shadow_pipeline);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        self.draw_chunks(render_pass, self.selected_chunks);
+    }
+}
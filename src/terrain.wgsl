@@ -0,0 +1,151 @@
+// Heightmap terrain in chunks of one patch mesh, four texture layers blended
+// by a splat map, lit by the scene's lights and the first directional one's
+// shadow map
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "gbuffer.wgsl"
+#include "lighting.wgsl"
+#include "shadow.wgsl"
+
+// the light in the shadow pass, see vs_shadow
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+@group(0) @binding(2)
+var<uniform> lighting: LightUniform;
+@group(0) @binding(7)
+var<storage, read> lights: array<GpuLight>;
+
+// Matches `TerrainUniform` in terrain.rs
+struct TerrainUniform {
+    // the first sample's corner at the terrain's base
+    origin: vec3<f32>,
+    _padding0: f32,
+    // between samples along x and z
+    spacing: vec2<f32>,
+    // the last sample's column and row
+    last: vec2<f32>,
+    // how many times the layers repeat across the terrain, along x and z
+    tiling: vec2<f32>,
+    _padding: vec2<f32>,
+};
+
+@group(1) @binding(0)
+var<uniform> terrain: TerrainUniform;
+// layer weights in r, g, b and a, linear
+@group(1) @binding(1)
+var t_splat: texture_2d<f32>;
+@group(1) @binding(2)
+var s_splat: sampler;
+@group(1) @binding(3)
+var t_layer0: texture_2d<f32>;
+@group(1) @binding(4)
+var t_layer1: texture_2d<f32>;
+@group(1) @binding(5)
+var t_layer2: texture_2d<f32>;
+@group(1) @binding(6)
+var t_layer3: texture_2d<f32>;
+@group(1) @binding(7)
+var s_layer: sampler;
+// world space heights, one texel per sample
+@group(1) @binding(8)
+var t_height: texture_2d<f32>;
+
+@group(2) @binding(0)
+var<uniform> shadow: ShadowUniform;
+@group(2) @binding(1)
+var t_shadow: texture_depth_2d;
+@group(2) @binding(2)
+var s_shadow: sampler_comparison;
+
+struct PatchInput {
+    // column and row in the chunk, and one on its skirt
+    @location(0) position: vec3<f32>,
+    // the chunk's first column and row, how many samples apart its vertices
+    // are and how far its skirt hangs down
+    @location(1) chunk: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) normal: vec3<f32>,
+    // across the whole terrain, zero to one
+    @location(1) tex_coords: vec2<f32>,
+    @location(2) world_position: vec3<f32>,
+};
+
+fn height(sample: vec2<f32>) -> f32 {
+    return textureLoad(t_height, vec2<i32>(clamp(sample, vec2<f32>(0.0), terrain.last)), 0).r;
+}
+
+// Where the patch vertex lands in its chunk, with the normal from the slopes
+// to the vertices either side at the chunk's detail
+fn surface(in: PatchInput) -> VertexOutput {
+    let step = in.chunk.z;
+    // chunks hanging over the far edges fold onto them
+    let sample = min(in.chunk.xy + in.position.xy * step, terrain.last);
+    let low = max(sample - step, vec2<f32>(0.0));
+    let high = min(sample + step, terrain.last);
+    let slope = vec2<f32>(
+        height(vec2<f32>(high.x, sample.y)) - height(vec2<f32>(low.x, sample.y)),
+        height(vec2<f32>(sample.x, high.y)) - height(vec2<f32>(sample.x, low.y)),
+    ) / ((high - low) * terrain.spacing);
+
+    var out: VertexOutput;
+    let xz = terrain.origin.xz + sample * terrain.spacing;
+    out.world_position = vec3<f32>(xz.x, height(sample) - in.position.z * in.chunk.w, xz.y);
+    out.clip_position = camera.view_proj * vec4<f32>(out.world_position, 1.0);
+    out.normal = normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+    out.tex_coords = sample / terrain.last;
+    return out;
+}
+
+@vertex
+fn vs_main(in: PatchInput) -> VertexOutput {
+    return surface(in);
+}
+
+// Into the shadow map, with the light bound as the camera
+@vertex
+fn vs_shadow(in: PatchInput) -> @builtin(position) vec4<f32> {
+    return surface(in).clip_position;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let weights = textureSample(t_splat, s_splat, in.tex_coords);
+    let tiled = in.tex_coords * terrain.tiling;
+    let albedo = textureSample(t_layer0, s_layer, tiled).rgb * weights.r
+        + textureSample(t_layer1, s_layer, tiled).rgb * weights.g
+        + textureSample(t_layer2, s_layer, tiled).rgb * weights.b
+        + textureSample(t_layer3, s_layer, tiled).rgb * weights.a;
+    // painted splat maps don't always add up to one
+    let color = albedo / max(dot(weights, vec4<f32>(1.0)), 1e-4);
+
+    // Lambert with the ambient on top, with only `visibility` of the shadow
+    // caster getting through
+    let visibility = shadow_visibility(shadow, t_shadow, s_shadow, in.world_position, in.normal);
+    let n = normalize(in.normal);
+    var light = lighting.ambient;
+    for (var i = 0u; i < lighting.count; i++) {
+        let sample = sample_light(lights[i], in.world_position);
+        light += diffuse_light(sample, n) * select(1.0, visibility, i == lighting.shadow_caster);
+    }
+    let lit = color * light;
+    return vec4<f32>(apply_fog(fog, camera.view_pos.xyz, in.world_position, lit), 1.0);
+}
+
+// Screen motion from the camera for TAA, the terrain never moves itself
+@fragment
+fn fs_velocity(in: VertexOutput) -> @location(0) vec2<f32> {
+    return screen_motion(camera, in.world_position);
+}
+
+// Into the SSR G-buffer; it's only lit diffusely, so only hides what's
+// behind it
+@fragment
+fn fs_gbuffer(in: VertexOutput) -> GBufferOutput {
+    return gbuffer_output(normalize(in.normal), 1.0, vec3<f32>(0.04));
+}
@@ -0,0 +1,222 @@
+use anyhow::*;
+use glyphon::{
+    Attrs, Buffer, Cache, Color, Family, FontSystem, Metrics, Resolution, Shaping, SwashCache,
+    TextArea, TextAtlas, TextBounds, Viewport,
+};
+
+use crate::draw_list::TracedPass;
+
+/// Which font to shape with. Named families fall back to the generic
+/// sans-serif one if no loaded font matches.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FontFamily {
+    SansSerif,
+    Serif,
+    Monospace,
+    Name(String),
+}
+
+impl FontFamily {
+    fn family(&self) -> Family<'_> {
+        match self {
+            FontFamily::SansSerif => Family::SansSerif,
+            FontFamily::Serif => Family::Serif,
+            FontFamily::Monospace => Family::Monospace,
+            FontFamily::Name(name) => Family::Name(name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    pub family: FontFamily,
+    // font size in logical pixels
+    pub size: f32,
+    // line spacing as a multiple of the font size
+    pub line_height: f32,
+    pub color: [u8; 4],
+}
+
+impl TextStyle {
+    pub fn new(size: f32) -> Self {
+        Self {
+            size,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_family(mut self, family: FontFamily) -> Self {
+        self.family = family;
+        self
+    }
+
+    pub fn with_color(mut self, color: [u8; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    fn metrics(&self) -> Metrics {
+        Metrics::new(self.size, self.size * self.line_height)
+    }
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            family: FontFamily::SansSerif,
+            size: 16.0,
+            line_height: 1.2,
+            color: [255; 4],
+        }
+    }
+}
+
+// One queued string. Buffers are kept between frames and only reshaped
+// when their text or style changes.
+struct Entry {
+    text: String,
+    style: TextStyle,
+    buffer: Buffer,
+    position: [f32; 2],
+}
+
+// Screen-space UTF-8 text through glyphon. Queue strings during `update()`,
+// `prepare` once they're all in and `draw` in a pass after the scene.
+// Positions are the top-left corner of the text in logical pixels, glyphs are
+// rasterized at the scale factor so they stay crisp on HiDPI screens.
+pub struct TextRenderer {
+    font_system: FontSystem,
+    swash_cache: SwashCache,
+    atlas: TextAtlas,
+    viewport: Viewport,
+    renderer: glyphon::TextRenderer,
+    entries: Vec<Entry>,
+    // entries queued this frame, the rest are spares from earlier frames
+    queued: usize,
+    // physical pixels per logical one
+    scale_factor: f32,
+}
+
+impl TextRenderer {
+    // Loads the system fonts, which can take a moment on first use
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue, color_format: wgpu::TextureFormat) -> Self {
+        let cache = Cache::new(device);
+        let mut atlas = TextAtlas::new(device, queue, &cache, color_format);
+        let renderer = glyphon::TextRenderer::new(&mut atlas, device, wgpu::MultisampleState::default(), None);
+        Self {
+            font_system: FontSystem::new(),
+            swash_cache: SwashCache::new(),
+            atlas,
+            viewport: Viewport::new(device, &cache),
+            renderer,
+            entries: Vec::new(),
+            queued: 0,
+            scale_factor: 1.0,
+        }
+    }
+
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
+
+    pub fn set_scale_factor(&mut self, scale_factor: f32) {
+        self.scale_factor = scale_factor;
+    }
+
+    // Makes a TTF/OTF font available by its family name
+    pub fn load_font(&mut self, data: Vec<u8>) {
+        self.font_system.db_mut().load_font_data(data);
+    }
+
+    pub fn queue(&mut self, text: &str, position: [f32; 2], style: &TextStyle) {
+        let index = self.queued;
+        self.queued += 1;
+
+        if let Some(entry) = self.entries.get_mut(index) {
+            entry.position = position;
+            if entry.text == text && entry.style == *style {
+                return;
+            }
+            entry.text.clear();
+            entry.text.push_str(text);
+            entry.style = style.clone();
+            shape(&mut self.font_system, &mut entry.buffer, text, style);
+            return;
+        }
+
+        let mut buffer = Buffer::new(&mut self.font_system, style.metrics());
+        shape(&mut self.font_system, &mut buffer, text, style);
+        self.entries.push(Entry {
+            text: text.to_owned(),
+            style: style.clone(),
+            buffer,
+            position,
+        });
+    }
+
+    // Width and height `text` would take up in logical pixels
+    pub fn measure(&mut self, text: &str, style: &TextStyle) -> (f32, f32) {
+        let mut buffer = Buffer::new(&mut self.font_system, style.metrics());
+        shape(&mut self.font_system, &mut buffer, text, style);
+        buffer_size(&buffer)
+    }
+
+    // Lays out everything queued since the last call for a `width` x
+    // `height` target (in physical pixels) and uploads the glyphs, then
+    // starts a new frame
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, width: u32, height: u32) -> Result<()> {
+        // glyphs nobody used last frame can be evicted now
+        self.atlas.trim();
+        self.viewport.update(queue, Resolution { width, height });
+
+        let bounds = TextBounds {
+            left: 0,
+            top: 0,
+            right: width as i32,
+            bottom: height as i32,
+        };
+        let areas = self.entries[..self.queued].iter().map(|entry| {
+            let [r, g, b, a] = entry.style.color;
+            TextArea {
+                buffer: &entry.buffer,
+                left: entry.position[0] * self.scale_factor,
+                top: entry.position[1] * self.scale_factor,
+                scale: self.scale_factor,
+                bounds,
+                default_color: Color::rgba(r, g, b, a),
+                custom_glyphs: &[],
+            }
+        });
+        let result = self.renderer.prepare(
+            device,
+            queue,
+            &mut self.font_system,
+            &mut self.atlas,
+            &self.viewport,
+            areas,
+            &mut self.swash_cache,
+        );
+        self.queued = 0;
+        result.context("Failed to prepare text")
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>) {
+        if let Err(e) = self.renderer.render(&self.atlas, &self.viewport, render_pass.raw()) {
+            log::warn!("Failed to draw text: {}", e);
+        }
+    }
+}
+
+fn shape(font_system: &mut FontSystem, buffer: &mut Buffer, text: &str, style: &TextStyle) {
+    buffer.set_metrics(font_system, style.metrics());
+    // no wrapping, lines only break on '\n'
+    buffer.set_size(font_system, None, None);
+    buffer.set_text(font_system, text, Attrs::new().family(style.family.family()), Shaping::Advanced);
+    buffer.shape_until_scroll(font_system, false);
+}
+
+fn buffer_size(buffer: &Buffer) -> (f32, f32) {
+    buffer.layout_runs().fold((0.0f32, 0.0f32), |(width, height), run| {
+        (width.max(run.line_w), height + buffer.metrics().line_height)
+    })
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+
+use anyhow::*;
+use image::GenericImageView;
+
+use crate::resources::{ResourceKind, Tracked};
+
+mod compressed;
+mod equirect;
+mod mipmap;
+mod sampler;
+
+pub use compressed::optional_features;
+pub use mipmap::{mip_level_count, MipmapGenerator};
+pub use sampler::{SamplerCache, SamplerDesc};
+
+pub struct Texture {
+    pub texture: Tracked<wgpu::Texture>,
+    pub view: wgpu::TextureView,
+    // shared, so textures with equal settings can use one sampler
+    pub sampler: Arc<wgpu::Sampler>,
+    pub sampler_desc: SamplerDesc,
+}
+
+impl Texture {
+    pub fn from_bytes(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let img = image::load_from_memory(bytes)?;
+        Ok(Self::from_image(device, queue, &img, Some(label)))
+    }
+
+    pub fn from_image(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        img: &image::DynamicImage,
+        label: Option<&str>,
+    ) -> Self {
+        let rgba = img.to_rgba8();
+        let dimensions = img.dimensions();
+        Self::from_rgba(device, queue, &rgba, dimensions.0, dimensions.1, label)
+    }
+
+    // Tightly packed 8-bit sRGB RGBA pixels. Builds a throwaway mipmap
+    // generator, use `from_rgba_with_mipmaps` when loading many textures.
+    pub fn from_rgba(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let mut mipmaps = MipmapGenerator::new(device);
+        Self::from_rgba_with_mipmaps(device, queue, &mut mipmaps, rgba, width, height, label)
+    }
+
+    pub fn from_rgba_with_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+        Self::with_format(device, queue, mipmaps, format, rgba, width, height, label)
+    }
+
+    // The same for data rather than colour, like normal maps, which are
+    // read back as they were written instead of decoded from sRGB
+    pub fn from_linear_rgba_with_mipmaps(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let format = wgpu::TextureFormat::Rgba8Unorm;
+        Self::with_format(device, queue, mipmaps, format, rgba, width, height, label)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn with_format(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        mipmaps: &mut MipmapGenerator,
+        format: wgpu::TextureFormat,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        label: Option<&str>,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let mip_level_count = mip_level_count(width, height);
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
+            // COPY_DST means that we want to copy data to this texture
+            // RENDER_ATTACHMENT lets the mipmap generator draw the smaller levels
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+
+        // the write above is queued ahead of this submission, so level 0 is ready
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mipmap Encoder"),
+        });
+        encoder.push_debug_group(&format!("Mipmaps for {}", label.unwrap_or("Texture")));
+        mipmaps.generate(device, &mut encoder, &texture);
+        encoder.pop_debug_group();
+        queue.submit(Some(encoder.finish()));
+
+        let view_label = label.map(|label| format!("{} View", label));
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: view_label.as_deref(),
+            ..Default::default()
+        });
+        let sampler_desc = SamplerDesc::default();
+        let sampler = Arc::new(sampler_desc.create(device, label));
+
+        Self {
+            texture: Tracked::texture(ResourceKind::Texture, texture),
+            view,
+            sampler,
+            sampler_desc,
+        }
+    }
+
+    pub fn set_sampler(&mut self, device: &wgpu::Device, samplers: &mut SamplerCache, desc: SamplerDesc) {
+        self.sampler = samplers.get(device, &desc);
+        self.sampler_desc = desc;
+    }
+}
+
+// Procedural checkerboard, so there is something to texture with
+// before any assets exist
+pub fn checkerboard(size: u32, cells: u32, a: [u8; 4], b: [u8; 4]) -> image::RgbaImage {
+    let cell = (size / cells.max(1)).max(1);
+    image::RgbaImage::from_fn(size, size, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            image::Rgba(a)
+        } else {
+            image::Rgba(b)
+        }
+    })
+}
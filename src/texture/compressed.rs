@@ -0,0 +1,465 @@
+// KTX2 and DDS container loading: block-compressed and uncompressed
+// payloads are uploaded as-is, UASTC is transcoded to whatever the device
+// can sample
+use std::io::Read;
+
+use anyhow::*;
+use ddsfile::{Caps2, D3DFormat, DxgiFormat, FourCC, MiscFlag};
+use ktx2::{ColorModel, Format, SupercompressionScheme, TransferFunction};
+use wgpu::util::DeviceExt;
+
+use super::{SamplerDesc, Texture};
+use crate::resources::{ResourceKind, Tracked};
+
+// Compressed formats worth asking for, the adapter decides which we get
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features()
+        & (wgpu::Features::TEXTURE_COMPRESSION_BC
+            | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+            | wgpu::Features::TEXTURE_COMPRESSION_ASTC)
+}
+
+impl Texture {
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow!("{}: invalid KTX2 file: {:?}", label, e))?;
+        let header = reader.header();
+        if header.pixel_depth > 1 {
+            bail!("{}: 3D KTX2 textures aren't supported", label);
+        }
+
+        let srgb = is_srgb(&reader);
+        let levels = decompress_levels(&reader)?;
+        let (format, data) = match header.format {
+            Some(format) => {
+                let format = wgpu_format(format)
+                    .ok_or_else(|| anyhow!("{}: unsupported KTX2 format {:?}", label, format))?;
+                (format, levels.concat())
+            }
+            // no vkFormat means a Basis Universal payload
+            None => transcode_basis(device.features(), &reader, &levels, srgb)
+                .with_context(|| format!("{}: failed to transcode", label))?,
+        };
+
+        // KTX2 stores every layer of mip 0 first, then mip 1 and so on
+        upload(
+            device,
+            queue,
+            label,
+            format,
+            (header.pixel_width, header.pixel_height.max(1)),
+            header.layer_count.max(1),
+            header.face_count == 6,
+            header.level_count.max(1),
+            wgpu::util::TextureDataOrder::MipMajor,
+            &data,
+        )
+    }
+
+    // DX10 headers are mapped exactly. Legacy DXT1-5 files carry no colour
+    // space, so they are assumed to be sRGB colour data, while BC4/BC5
+    // (usually masks and normal maps) stay linear.
+    pub fn from_dds(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+    ) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes).map_err(|e| anyhow!("{}: invalid DDS file: {}", label, e))?;
+        if dds.get_depth() > 1 {
+            bail!("{}: volume DDS textures aren't supported", label);
+        }
+
+        let format = match (dds.get_dxgi_format(), dds.get_d3d_format()) {
+            (Some(format), _) => dxgi_format(format),
+            (None, Some(format)) => d3d_format(format),
+            // BC4 and BC5 FourCCs that ddsfile doesn't map to a D3D format
+            (None, None) => dds.header.spf.fourcc.as_ref().and_then(|fourcc| fourcc_format(fourcc.0)),
+        }
+        .ok_or_else(|| {
+            anyhow!(
+                "{}: unsupported DDS format {:?}",
+                label,
+                dds.get_dxgi_format().map_or_else(|| format!("{:?}", dds.get_d3d_format()), |f| format!("{:?}", f))
+            )
+        })?;
+
+        // DX10 array sizes count whole cubes
+        let (cube, layers) = match &dds.header10 {
+            Some(header10) => (header10.misc_flag.contains(MiscFlag::TEXTURECUBE), header10.array_size.max(1)),
+            None => (dds.header.caps2.contains(Caps2::CUBEMAP), 1),
+        };
+
+        // DDS stores the full mip chain of each layer (or face) in turn
+        upload(
+            device,
+            queue,
+            label,
+            format,
+            (dds.get_width(), dds.get_height().max(1)),
+            layers,
+            cube,
+            dds.get_num_mipmap_levels().max(1),
+            wgpu::util::TextureDataOrder::LayerMajor,
+            &dds.data,
+        )
+    }
+}
+
+// Validates `data` against the texture it describes and uploads every layer
+// and mip at once. Cubemaps have six faces per layer.
+#[allow(clippy::too_many_arguments)]
+fn upload(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    format: wgpu::TextureFormat,
+    (width, height): (u32, u32),
+    layers: u32,
+    cube: bool,
+    mip_level_count: u32,
+    order: wgpu::util::TextureDataOrder,
+    data: &[u8],
+) -> Result<Texture> {
+    if !device.features().contains(format.required_features()) {
+        bail!("{}: device doesn't support {:?}", label, format);
+    }
+
+    let faces = if cube { 6 } else { 1 };
+    let size = wgpu::Extent3d {
+        width,
+        height,
+        depth_or_array_layers: layers * faces,
+    };
+    // mips below 1x1 don't exist
+    let max_mips = size.max_mips(wgpu::TextureDimension::D2);
+    if mip_level_count > max_mips {
+        bail!("{}: {} mip levels for a {}x{} texture", label, mip_level_count, width, height);
+    }
+
+    // the upload below indexes into `data` and would panic on short files
+    let expected = (0..mip_level_count)
+        .map(|level| level_size(format, size.mip_level_size(level, wgpu::TextureDimension::D2)))
+        .sum::<u64>();
+    if (data.len() as u64) < expected {
+        bail!("{}: expected {} bytes of texel data, found {}", label, expected, data.len());
+    }
+
+    let texture = device.create_texture_with_data(
+        queue,
+        &wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        },
+        order,
+        data,
+    );
+
+    let dimension = match (cube, layers) {
+        (true, 1) => wgpu::TextureViewDimension::Cube,
+        (true, _) => wgpu::TextureViewDimension::CubeArray,
+        (false, 1) => wgpu::TextureViewDimension::D2,
+        (false, _) => wgpu::TextureViewDimension::D2Array,
+    };
+    let view = texture.create_view(&wgpu::TextureViewDescriptor {
+        label: Some(&format!("{} View", label)),
+        dimension: Some(dimension),
+        ..Default::default()
+    });
+    let sampler_desc = SamplerDesc::default();
+    let sampler = std::sync::Arc::new(sampler_desc.create(device, Some(label)));
+
+    Ok(Texture {
+        texture: Tracked::texture(ResourceKind::Texture, texture),
+        view,
+        sampler,
+        sampler_desc,
+    })
+}
+
+// Bytes for one mip level across all layers, rows tightly packed
+fn level_size(format: wgpu::TextureFormat, size: wgpu::Extent3d) -> u64 {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(0) as u64;
+    let blocks_x = size.width.div_ceil(block_width) as u64;
+    let blocks_y = size.height.div_ceil(block_height) as u64;
+    blocks_x * blocks_y * block_size * size.depth_or_array_layers as u64
+}
+
+// The basic descriptor says how to interpret the texels. The writer is
+// required to include one, but ktx2 panics on files that don't.
+fn basic_dfd<'a>(reader: &'a ktx2::Reader<&[u8]>) -> Option<ktx2::BasicDataFormatDescriptor<'a>> {
+    let dfd_length = u32::from_le_bytes(reader.data()[52..56].try_into().unwrap());
+    if dfd_length < 4 {
+        return None;
+    }
+    reader
+        .data_format_descriptors()
+        .find(|dfd| dfd.header.vendor_id == 0 && dfd.header.descriptor_type == 0)
+        .and_then(|dfd| ktx2::BasicDataFormatDescriptor::parse(dfd.data).ok())
+}
+
+fn is_srgb(reader: &ktx2::Reader<&[u8]>) -> bool {
+    basic_dfd(reader).is_some_and(|dfd| dfd.transfer_function == Some(TransferFunction::SRGB))
+}
+
+fn color_model(reader: &ktx2::Reader<&[u8]>) -> Option<ColorModel> {
+    basic_dfd(reader).and_then(|dfd| dfd.color_model)
+}
+
+fn decompress_levels(reader: &ktx2::Reader<&[u8]>) -> Result<Vec<Vec<u8>>> {
+    let scheme = reader.header().supercompression_scheme;
+    reader
+        .levels()
+        .map(|level| match scheme {
+            None => Ok(level.to_vec()),
+            Some(SupercompressionScheme::Zstandard) => {
+                let mut source = level;
+                let mut decoder = ruzstd::decoding::StreamingDecoder::new(&mut source)
+                    .map_err(|e| anyhow!("zstd: {}", e))?;
+                let mut out = Vec::new();
+                decoder.read_to_end(&mut out)?;
+                Ok(out)
+            }
+            Some(scheme) => bail!("unsupported KTX2 supercompression {:?}", scheme),
+        })
+        .collect()
+}
+
+#[cfg(feature = "basis")]
+fn transcode_basis(
+    features: wgpu::Features,
+    reader: &ktx2::Reader<&[u8]>,
+    levels: &[Vec<u8>],
+    srgb: bool,
+) -> Result<(wgpu::TextureFormat, Vec<u8>)> {
+    use basis_universal::{DecodeFlags, LowLevelUastcTranscoder, SliceParametersUastc, TranscoderBlockFormat};
+
+    match color_model(reader) {
+        Some(ColorModel::UASTC) => {}
+        // BasisLZ needs the global codebooks, which the Rust bindings don't expose
+        Some(ColorModel::ETC1S) => bail!("ETC1S/BasisLZ isn't supported, encode with --uastc instead"),
+        model => bail!("unknown Basis color model {:?}", model),
+    }
+
+    // best quality first, uncompressed RGBA as the last resort
+    let (block_format, format) = if features.contains(wgpu::Features::TEXTURE_COMPRESSION_BC) {
+        let format = if srgb {
+            wgpu::TextureFormat::Bc7RgbaUnormSrgb
+        } else {
+            wgpu::TextureFormat::Bc7RgbaUnorm
+        };
+        (TranscoderBlockFormat::BC7, format)
+    } else if features.contains(wgpu::Features::TEXTURE_COMPRESSION_ASTC) {
+        let channel = if srgb {
+            wgpu::AstcChannel::UnormSrgb
+        } else {
+            wgpu::AstcChannel::Unorm
+        };
+        let format = wgpu::TextureFormat::Astc {
+            block: wgpu::AstcBlock::B4x4,
+            channel,
+        };
+        (TranscoderBlockFormat::ASTC_4x4, format)
+    } else if features.contains(wgpu::Features::TEXTURE_COMPRESSION_ETC2) {
+        let format = if srgb {
+            wgpu::TextureFormat::Etc2Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Etc2Rgba8Unorm
+        };
+        (TranscoderBlockFormat::ETC2_RGBA, format)
+    } else {
+        let format = if srgb {
+            wgpu::TextureFormat::Rgba8UnormSrgb
+        } else {
+            wgpu::TextureFormat::Rgba8Unorm
+        };
+        (TranscoderBlockFormat::RGBA32, format)
+    };
+
+    let header = reader.header();
+    let slices_per_level = (header.layer_count.max(1) * header.face_count) as usize;
+    let transcoder = LowLevelUastcTranscoder::new();
+    let mut data = Vec::new();
+    for (level, level_data) in levels.iter().enumerate() {
+        let width = (header.pixel_width >> level).max(1);
+        let height = (header.pixel_height.max(1) >> level).max(1);
+        let num_blocks_x = width.div_ceil(4);
+        let num_blocks_y = height.div_ceil(4);
+        // UASTC blocks are always 16 bytes
+        let slice_len = (num_blocks_x * num_blocks_y * 16) as usize;
+        if level_data.len() != slice_len * slices_per_level {
+            bail!("mip {} has {} bytes, expected {}", level, level_data.len(), slice_len * slices_per_level);
+        }
+        for slice in level_data.chunks_exact(slice_len) {
+            let transcoded = transcoder
+                .transcode_slice(
+                    slice,
+                    SliceParametersUastc {
+                        num_blocks_x,
+                        num_blocks_y,
+                        has_alpha: true,
+                        original_width: width,
+                        original_height: height,
+                    },
+                    DecodeFlags::HIGH_QUALITY,
+                    block_format,
+                )
+                .map_err(|e| anyhow!("mip {}: {:?}", level, e))?;
+            data.extend_from_slice(&transcoded);
+        }
+    }
+    Ok((format, data))
+}
+
+#[cfg(not(feature = "basis"))]
+fn transcode_basis(
+    _features: wgpu::Features,
+    reader: &ktx2::Reader<&[u8]>,
+    _levels: &[Vec<u8>],
+    _srgb: bool,
+) -> Result<(wgpu::TextureFormat, Vec<u8>)> {
+    bail!(
+        "Basis Universal ({:?}) payloads need the `basis` feature",
+        color_model(reader)
+    )
+}
+
+fn wgpu_format(format: Format) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat as T};
+
+    let astc = |block, srgb| {
+        let channel = if srgb { AstcChannel::UnormSrgb } else { AstcChannel::Unorm };
+        Some(T::Astc { block, channel })
+    };
+
+    match format {
+        Format::R8_UNORM => Some(T::R8Unorm),
+        Format::R8G8_UNORM => Some(T::Rg8Unorm),
+        Format::R8G8B8A8_UNORM => Some(T::Rgba8Unorm),
+        Format::R8G8B8A8_SRGB => Some(T::Rgba8UnormSrgb),
+        Format::B8G8R8A8_UNORM => Some(T::Bgra8Unorm),
+        Format::B8G8R8A8_SRGB => Some(T::Bgra8UnormSrgb),
+        Format::R16G16B16A16_SFLOAT => Some(T::Rgba16Float),
+        Format::R32G32B32A32_SFLOAT => Some(T::Rgba32Float),
+        Format::BC1_RGBA_UNORM_BLOCK => Some(T::Bc1RgbaUnorm),
+        Format::BC1_RGBA_SRGB_BLOCK => Some(T::Bc1RgbaUnormSrgb),
+        Format::BC2_UNORM_BLOCK => Some(T::Bc2RgbaUnorm),
+        Format::BC2_SRGB_BLOCK => Some(T::Bc2RgbaUnormSrgb),
+        Format::BC3_UNORM_BLOCK => Some(T::Bc3RgbaUnorm),
+        Format::BC3_SRGB_BLOCK => Some(T::Bc3RgbaUnormSrgb),
+        Format::BC4_UNORM_BLOCK => Some(T::Bc4RUnorm),
+        Format::BC4_SNORM_BLOCK => Some(T::Bc4RSnorm),
+        Format::BC5_UNORM_BLOCK => Some(T::Bc5RgUnorm),
+        Format::BC5_SNORM_BLOCK => Some(T::Bc5RgSnorm),
+        Format::BC6H_UFLOAT_BLOCK => Some(T::Bc6hRgbUfloat),
+        Format::BC6H_SFLOAT_BLOCK => Some(T::Bc6hRgbFloat),
+        Format::BC7_UNORM_BLOCK => Some(T::Bc7RgbaUnorm),
+        Format::BC7_SRGB_BLOCK => Some(T::Bc7RgbaUnormSrgb),
+        Format::ETC2_R8G8B8_UNORM_BLOCK => Some(T::Etc2Rgb8Unorm),
+        Format::ETC2_R8G8B8_SRGB_BLOCK => Some(T::Etc2Rgb8UnormSrgb),
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => Some(T::Etc2Rgb8A1Unorm),
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => Some(T::Etc2Rgb8A1UnormSrgb),
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => Some(T::Etc2Rgba8Unorm),
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => Some(T::Etc2Rgba8UnormSrgb),
+        Format::EAC_R11_UNORM_BLOCK => Some(T::EacR11Unorm),
+        Format::EAC_R11_SNORM_BLOCK => Some(T::EacR11Snorm),
+        Format::EAC_R11G11_UNORM_BLOCK => Some(T::EacRg11Unorm),
+        Format::EAC_R11G11_SNORM_BLOCK => Some(T::EacRg11Snorm),
+        Format::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, false),
+        Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, true),
+        Format::ASTC_5x4_UNORM_BLOCK => astc(AstcBlock::B5x4, false),
+        Format::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4, true),
+        Format::ASTC_5x5_UNORM_BLOCK => astc(AstcBlock::B5x5, false),
+        Format::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5, true),
+        Format::ASTC_6x5_UNORM_BLOCK => astc(AstcBlock::B6x5, false),
+        Format::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5, true),
+        Format::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, false),
+        Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, true),
+        Format::ASTC_8x5_UNORM_BLOCK => astc(AstcBlock::B8x5, false),
+        Format::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5, true),
+        Format::ASTC_8x6_UNORM_BLOCK => astc(AstcBlock::B8x6, false),
+        Format::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6, true),
+        Format::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, false),
+        Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, true),
+        Format::ASTC_10x5_UNORM_BLOCK => astc(AstcBlock::B10x5, false),
+        Format::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5, true),
+        Format::ASTC_10x6_UNORM_BLOCK => astc(AstcBlock::B10x6, false),
+        Format::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6, true),
+        Format::ASTC_10x8_UNORM_BLOCK => astc(AstcBlock::B10x8, false),
+        Format::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8, true),
+        Format::ASTC_10x10_UNORM_BLOCK => astc(AstcBlock::B10x10, false),
+        Format::ASTC_10x10_SRGB_BLOCK => astc(AstcBlock::B10x10, true),
+        Format::ASTC_12x10_UNORM_BLOCK => astc(AstcBlock::B12x10, false),
+        Format::ASTC_12x10_SRGB_BLOCK => astc(AstcBlock::B12x10, true),
+        Format::ASTC_12x12_UNORM_BLOCK => astc(AstcBlock::B12x12, false),
+        Format::ASTC_12x12_SRGB_BLOCK => astc(AstcBlock::B12x12, true),
+        _ => None,
+    }
+}
+
+fn dxgi_format(format: DxgiFormat) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as T;
+
+    match format {
+        DxgiFormat::R8G8B8A8_UNorm => Some(T::Rgba8Unorm),
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => Some(T::Rgba8UnormSrgb),
+        DxgiFormat::B8G8R8A8_UNorm => Some(T::Bgra8Unorm),
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => Some(T::Bgra8UnormSrgb),
+        DxgiFormat::R16G16B16A16_Float => Some(T::Rgba16Float),
+        DxgiFormat::R32G32B32A32_Float => Some(T::Rgba32Float),
+        DxgiFormat::BC1_Typeless | DxgiFormat::BC1_UNorm => Some(T::Bc1RgbaUnorm),
+        DxgiFormat::BC1_UNorm_sRGB => Some(T::Bc1RgbaUnormSrgb),
+        DxgiFormat::BC2_Typeless | DxgiFormat::BC2_UNorm => Some(T::Bc2RgbaUnorm),
+        DxgiFormat::BC2_UNorm_sRGB => Some(T::Bc2RgbaUnormSrgb),
+        DxgiFormat::BC3_Typeless | DxgiFormat::BC3_UNorm => Some(T::Bc3RgbaUnorm),
+        DxgiFormat::BC3_UNorm_sRGB => Some(T::Bc3RgbaUnormSrgb),
+        DxgiFormat::BC4_Typeless | DxgiFormat::BC4_UNorm => Some(T::Bc4RUnorm),
+        DxgiFormat::BC4_SNorm => Some(T::Bc4RSnorm),
+        DxgiFormat::BC5_Typeless | DxgiFormat::BC5_UNorm => Some(T::Bc5RgUnorm),
+        DxgiFormat::BC5_SNorm => Some(T::Bc5RgSnorm),
+        DxgiFormat::BC6H_Typeless | DxgiFormat::BC6H_UF16 => Some(T::Bc6hRgbUfloat),
+        DxgiFormat::BC6H_SF16 => Some(T::Bc6hRgbFloat),
+        DxgiFormat::BC7_Typeless | DxgiFormat::BC7_UNorm => Some(T::Bc7RgbaUnorm),
+        DxgiFormat::BC7_UNorm_sRGB => Some(T::Bc7RgbaUnormSrgb),
+        _ => None,
+    }
+}
+
+fn d3d_format(format: D3DFormat) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as T;
+
+    match format {
+        D3DFormat::A8B8G8R8 => Some(T::Rgba8UnormSrgb),
+        D3DFormat::A8R8G8B8 => Some(T::Bgra8UnormSrgb),
+        D3DFormat::A16B16G16R16F => Some(T::Rgba16Float),
+        D3DFormat::A32B32G32R32F => Some(T::Rgba32Float),
+        D3DFormat::DXT1 => Some(T::Bc1RgbaUnormSrgb),
+        // premultiplied alpha variants share the block layout
+        D3DFormat::DXT2 | D3DFormat::DXT3 => Some(T::Bc2RgbaUnormSrgb),
+        D3DFormat::DXT4 | D3DFormat::DXT5 => Some(T::Bc3RgbaUnormSrgb),
+        _ => None,
+    }
+}
+
+fn fourcc_format(fourcc: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::TextureFormat as T;
+
+    match fourcc {
+        FourCC::ATI1 | FourCC::BC4_UNORM => Some(T::Bc4RUnorm),
+        FourCC::BC4_SNORM => Some(T::Bc4RSnorm),
+        FourCC::ATI2 => Some(T::Bc5RgUnorm),
+        FourCC::BC5_SNORM => Some(T::Bc5RgSnorm),
+        _ => None,
+    }
+}
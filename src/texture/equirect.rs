@@ -0,0 +1,224 @@
+// Equirectangular `.hdr` and `.exr` panoramas, resampled into cubemaps on
+// the GPU
+use std::sync::Arc;
+
+use anyhow::*;
+use wgpu::util::DeviceExt;
+
+use super::{mip_level_count, MipmapGenerator, SamplerDesc, Texture};
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::shader_reflection::ShaderReflection;
+
+// filterable everywhere, unlike Rgba32Float, and still high dynamic range
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Matches `EquirectParams` in equirect.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct EquirectParams {
+    face: u32,
+    lod: f32,
+    _padding: [f32; 2],
+}
+
+impl Texture {
+    // A cubemap with a full mip chain, each face a quarter of the
+    // panorama's width rounded up to a power of two. The panorama's middle
+    // ends up towards -z.
+    pub fn from_equirect(device: &wgpu::Device, queue: &wgpu::Queue, bytes: &[u8], label: &str) -> Result<Self> {
+        let image = image::load_from_memory(bytes)
+            .with_context(|| format!("{}: invalid HDR or EXR image", label))?
+            .into_rgba32f();
+        let (width, height) = image.dimensions();
+        let texels: Vec<u16> = image.into_raw().into_iter().map(|value| half::f16::from_f32(value).to_bits()).collect();
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let equirect = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("{} Panorama", label)),
+            size,
+            mip_level_count: mip_level_count(width, height),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            equirect.as_image_copy(),
+            bytemuck::cast_slice(&texels),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * width),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
+        let equirect_view = equirect.create_view(&Default::default());
+
+        let face_size = (width / 4).next_power_of_two().min(device.limits().max_texture_dimension_2d);
+        let cube = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: face_size,
+                height: face_size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count: mip_level_count(face_size, face_size),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
+
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Equirect Bind Group Layout"),
+            entries: &entries,
+        });
+        let shader = shader_preprocessor::load("equirect.wgsl", &ShaderDefs::new())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &entries)?;
+                reflection.check_struct::<EquirectParams>("EquirectParams")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Equirect Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Equirect Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(device, "Equirect Pipeline", &pipeline_layout, &shader, FORMAT);
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Equirect Sampler"),
+            address_mode_u: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let mut mipmaps = MipmapGenerator::new(device);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Equirect Encoder"),
+        });
+        encoder.push_debug_group(&format!("Cubemap from {}", label));
+        mipmaps.generate(device, &mut encoder, &equirect);
+        // Each of the cubemap's mips is drawn from the panorama's, GL can't
+        // sample a cubemap's faces as the layers of an array to downsample
+        // them
+        for (mip_level, face) in (0..cube.mip_level_count()).flat_map(|mip_level| (0..6).map(move |face| (mip_level, face))) {
+            // a face texel near the middle is about 2 / size radians across,
+            // a panorama texel 2π / width
+            let size = (face_size >> mip_level).max(1);
+            let lod = (width as f32 / (std::f32::consts::PI * size as f32)).log2().max(0.0);
+            let params = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Equirect Params Buffer"),
+                contents: bytemuck::cast_slice(&[EquirectParams {
+                    face,
+                    lod,
+                    _padding: [0.0; 2],
+                }]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("Equirect Bind Group"),
+                layout: &layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&equirect_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: params.as_entire_binding(),
+                    },
+                ],
+            });
+            let target = cube.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("Equirect Face View"),
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip_level,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            });
+            let mut pass = TracedPass::begin(&mut encoder, &wgpu::RenderPassDescriptor {
+                label: Some(&format!("Equirect Pass {}/{}", face, mip_level)),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.draw(0..3, 0..1);
+        }
+        encoder.pop_debug_group();
+        queue.submit(Some(encoder.finish()));
+
+        let view = cube.create_view(&wgpu::TextureViewDescriptor {
+            label: Some(&format!("{} View", label)),
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sampler_desc = SamplerDesc::default();
+        let sampler = Arc::new(sampler_desc.create(device, Some(label)));
+        Ok(Self {
+            texture: Tracked::texture(ResourceKind::Texture, cube),
+            view,
+            sampler,
+            sampler_desc,
+        })
+    }
+}
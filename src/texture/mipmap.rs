@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use wgpu::util::DeviceExt;
+
+use crate::draw_list::TracedPass;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::shader_preprocessor::{self, ShaderDefs};
+
+// Length of a full mip chain, down to 1x1
+pub fn mip_level_count(width: u32, height: u32) -> u32 {
+    32 - width.max(height).max(1).leading_zeros()
+}
+
+// Fills in every mip below level 0 by repeatedly downsampling with a render
+// pass. Pipelines are cached per format, so keep one generator around when
+// creating many textures.
+pub struct MipmapGenerator {
+    shader: wgpu::ShaderModule,
+    sampler: wgpu::Sampler,
+    layout: wgpu::BindGroupLayout,
+    array_layout: wgpu::BindGroupLayout,
+    pipeline_layout: wgpu::PipelineLayout,
+    array_pipeline_layout: wgpu::PipelineLayout,
+    // keyed by format and whether the source is an array texture
+    pipelines: HashMap<(wgpu::TextureFormat, bool), wgpu::RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = shader_preprocessor::create_module(device, "Mipmap Shader", "mipmap.wgsl", &ShaderDefs::new());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Mipmap Sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let source_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mipmap Bind Group Layout"),
+            entries: &[source_entry(0, wgpu::TextureViewDimension::D2), sampler_entry],
+        });
+        let array_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Mipmap Array Bind Group Layout"),
+            entries: &[
+                sampler_entry,
+                source_entry(2, wgpu::TextureViewDimension::D2Array),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let array_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Mipmap Array Pipeline Layout"),
+            bind_group_layouts: &[&array_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            shader,
+            sampler,
+            layout,
+            array_layout,
+            pipeline_layout,
+            array_pipeline_layout,
+            pipelines: HashMap::new(),
+        }
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, format: wgpu::TextureFormat, array: bool) -> wgpu::RenderPipeline {
+        let (layout, entry_point) = if array {
+            (&self.array_pipeline_layout, "fs_array")
+        } else {
+            (&self.pipeline_layout, "fs_main")
+        };
+        fullscreen_pipeline_with(device, "Mipmap Pipeline", layout, &self.shader, entry_point, &[Some(format.into())])
+    }
+
+    // `texture` needs TEXTURE_BINDING and RENDER_ATTACHMENT usage, and a
+    // filterable, renderable format. Every array layer gets its own chain.
+    pub fn generate(&mut self, device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        let format = texture.format();
+        let layers = texture.depth_or_array_layers();
+        let array = layers > 1;
+        if !self.pipelines.contains_key(&(format, array)) {
+            let pipeline = self.create_pipeline(device, format, array);
+            self.pipelines.insert((format, array), pipeline);
+        }
+        let pipeline = &self.pipelines[&(format, array)];
+
+        for layer in 0..layers {
+            // padded to 16 bytes for uniform buffer rules
+            let layer_buffer = array.then(|| {
+                device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some("Mipmap Layer Buffer"),
+                    contents: bytemuck::cast_slice(&[layer, 0, 0, 0]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                })
+            });
+
+            for mip_level in 1..texture.mip_level_count() {
+                let source = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mipmap Source View"),
+                    dimension: Some(if array {
+                        wgpu::TextureViewDimension::D2Array
+                    } else {
+                        wgpu::TextureViewDimension::D2
+                    }),
+                    base_mip_level: mip_level - 1,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                });
+                let target = texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("Mipmap Target View"),
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    base_mip_level: mip_level,
+                    mip_level_count: Some(1),
+                    base_array_layer: layer,
+                    array_layer_count: Some(1),
+                    ..Default::default()
+                });
+
+                let bind_group = match &layer_buffer {
+                    Some(layer_buffer) => device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("Mipmap Array Bind Group"),
+                        layout: &self.array_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&self.sampler),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: wgpu::BindingResource::TextureView(&source),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: layer_buffer.as_entire_binding(),
+                            },
+                        ],
+                    }),
+                    None => device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("Mipmap Bind Group"),
+                        layout: &self.layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(&source),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&self.sampler),
+                            },
+                        ],
+                    }),
+                };
+
+                let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+                    label: Some(&format!("Mipmap Pass {}/{}", layer, mip_level)),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+                pass.set_pipeline(pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
+        }
+    }
+}
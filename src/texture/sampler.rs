@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Everything that makes two samplers different, in a form that can key a
+// cache. Defaults to trilinear filtering with repeat addressing over the
+// full mip chain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SamplerDesc {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    pub lod_min_clamp: f32,
+    pub lod_max_clamp: f32,
+    pub compare: Option<wgpu::CompareFunction>,
+    // 1 turns anisotropic filtering off, wgpu allows up to 16
+    pub anisotropy_clamp: u16,
+    pub border_color: Option<wgpu::SamplerBorderColor>,
+}
+
+impl Default for SamplerDesc {
+    fn default() -> Self {
+        Self::TRILINEAR
+    }
+}
+
+impl SamplerDesc {
+    pub const TRILINEAR: Self = Self {
+        address_mode_u: wgpu::AddressMode::Repeat,
+        address_mode_v: wgpu::AddressMode::Repeat,
+        address_mode_w: wgpu::AddressMode::Repeat,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Linear,
+        lod_min_clamp: 0.0,
+        // views limit sampling to their own mips, so this covers any chain
+        lod_max_clamp: 32.0,
+        compare: None,
+        anisotropy_clamp: 1,
+        border_color: None,
+    };
+
+    // Blocky texels and no blending between mips, for pixel art
+    pub const NEAREST: Self = Self {
+        mag_filter: wgpu::FilterMode::Nearest,
+        min_filter: wgpu::FilterMode::Nearest,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Self::TRILINEAR
+    };
+
+    pub const fn with_address_mode(self, mode: wgpu::AddressMode) -> Self {
+        Self {
+            address_mode_u: mode,
+            address_mode_v: mode,
+            address_mode_w: mode,
+            ..self
+        }
+    }
+
+    // Anisotropic filtering needs linear filtering everywhere, so this
+    // switches all three filters to linear as well
+    pub const fn with_anisotropy(self, anisotropy_clamp: u16) -> Self {
+        Self {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy_clamp: if anisotropy_clamp > 16 {
+                16
+            } else if anisotropy_clamp < 1 {
+                1
+            } else {
+                anisotropy_clamp
+            },
+            ..self
+        }
+    }
+
+    pub fn descriptor<'a>(&self, label: Option<&'a str>) -> wgpu::SamplerDescriptor<'a> {
+        // wgpu rejects anisotropy with any nearest filter instead of ignoring it
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|filter| *filter == wgpu::FilterMode::Linear);
+        wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode_u,
+            address_mode_v: self.address_mode_v,
+            address_mode_w: self.address_mode_w,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            lod_min_clamp: self.lod_min_clamp,
+            lod_max_clamp: self.lod_max_clamp,
+            compare: self.compare,
+            anisotropy_clamp: if linear { self.anisotropy_clamp.clamp(1, 16) } else { 1 },
+            border_color: self.border_color,
+        }
+    }
+
+    // Uncached, for one-off samplers
+    pub fn create(&self, device: &wgpu::Device, label: Option<&str>) -> wgpu::Sampler {
+        device.create_sampler(&self.descriptor(label))
+    }
+
+    // f32 isn't Hash, so the LOD clamps are keyed by their bits
+    fn key(&self) -> SamplerKey {
+        SamplerKey {
+            address_modes: [self.address_mode_u, self.address_mode_v, self.address_mode_w],
+            filters: [self.mag_filter, self.min_filter, self.mipmap_filter],
+            lod_clamps: [self.lod_min_clamp.to_bits(), self.lod_max_clamp.to_bits()],
+            compare: self.compare,
+            anisotropy_clamp: self.anisotropy_clamp,
+            border_color: self.border_color,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SamplerKey {
+    address_modes: [wgpu::AddressMode; 3],
+    filters: [wgpu::FilterMode; 3],
+    lod_clamps: [u32; 2],
+    compare: Option<wgpu::CompareFunction>,
+    anisotropy_clamp: u16,
+    border_color: Option<wgpu::SamplerBorderColor>,
+}
+
+// Hands out one shared sampler per distinct `SamplerDesc`
+#[derive(Default)]
+pub struct SamplerCache {
+    samplers: HashMap<SamplerKey, Arc<wgpu::Sampler>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&mut self, device: &wgpu::Device, desc: &SamplerDesc) -> Arc<wgpu::Sampler> {
+        self.samplers
+            .entry(desc.key())
+            .or_insert_with(|| Arc::new(desc.create(device, Some("Cached Sampler"))))
+            .clone()
+    }
+
+    pub fn len(&self) -> usize {
+        self.samplers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samplers.is_empty()
+    }
+}
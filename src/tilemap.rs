@@ -0,0 +1,400 @@
+use anyhow::*;
+use cgmath::Vector2;
+use wgpu::util::DeviceExt;
+
+use crate::compute::StorageBuffer;
+use crate::draw_list::TracedPass;
+use crate::msaa::SampledPipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::sprite_batch::screen_projection;
+use crate::texture::Texture;
+
+/// Width and height of a chunk in tiles. Each chunk is drawn as one quad.
+pub const CHUNK_SIZE: u32 = 16;
+const CHUNK_TILES: usize = (CHUNK_SIZE * CHUNK_SIZE) as usize;
+
+/// CPU side tile grid for one layer. Tiles are stored row by row, 0 is an
+/// empty cell and anything else is a 1-based index into the tileset,
+/// counting left to right and top to bottom.
+#[derive(Debug, Clone)]
+pub struct TileLayer {
+    pub width: u32,
+    pub height: u32,
+    pub tiles: Vec<u32>,
+    // how fast the layer scrolls with the camera: 1 moves with the
+    // foreground, smaller values lag behind like a distant background
+    pub parallax: Vector2<f32>,
+    pub tint: [f32; 4],
+}
+
+impl TileLayer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            tiles: vec![0; (width * height) as usize],
+            parallax: Vector2::new(1.0, 1.0),
+            tint: [1.0; 4],
+        }
+    }
+
+    pub fn with_parallax(mut self, parallax: Vector2<f32>) -> Self {
+        self.parallax = parallax;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: [f32; 4]) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> u32 {
+        self.tiles[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, tile: u32) {
+        self.tiles[(y * self.width + x) as usize] = tile;
+    }
+}
+
+// Mirrors `Layer` in tilemap.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct LayerUniform {
+    proj: [[f32; 4]; 4],
+    tint: [f32; 4],
+    offset: [f32; 2],
+    tile_size: [f32; 2],
+    tile_texels: [u32; 2],
+    tileset_columns: u32,
+    chunk_size: u32,
+}
+
+// A layer's GPU resources, plus what's needed to cull its chunks
+struct Layer {
+    width: u32,
+    height: u32,
+    chunks_x: u32,
+    chunks_y: u32,
+    parallax: Vector2<f32>,
+    tint: [f32; 4],
+    visible: bool,
+    // chunk-major copy of what's in `tile_buffer`
+    tiles: Vec<u32>,
+    tile_buffer: StorageBuffer<u32>,
+    // non-empty tiles per chunk, empty chunks are never drawn
+    filled: Vec<u32>,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    // (x, y, first tile, unused) per visible chunk, rebuilt every frame
+    chunk_buffer: Tracked<wgpu::Buffer>,
+    visible_chunks: u32,
+}
+
+impl Layer {
+    // Index of tile (x, y) in the chunk-major storage buffer
+    fn tile_index(&self, x: u32, y: u32) -> usize {
+        let chunk = (y / CHUNK_SIZE) * self.chunks_x + x / CHUNK_SIZE;
+        let local = (y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE;
+        chunk as usize * CHUNK_TILES + local as usize
+    }
+}
+
+// Multi-layer 2D tilemap drawing from a grid tileset. Tile indices live in a
+// storage buffer and every chunk is a single quad whose fragment shader
+// looks the tiles up, so the vertex count doesn't grow with the map. Layers
+// are drawn in the order they were added, back to front.
+pub struct Tilemap {
+    // top-left of the view in unscaled map pixels
+    pub camera: Vector2<f32>,
+    // screen pixels per tileset texel
+    pub scale: f32,
+    tile_texels: [u32; 2],
+    tileset_columns: u32,
+    layers: Vec<Layer>,
+    layer_layout: wgpu::BindGroupLayout,
+    tileset_bind_group: wgpu::BindGroup,
+    render_pipeline: SampledPipeline,
+}
+
+impl Tilemap {
+    // `tileset` is a grid of `tile_width` x `tile_height` tiles with no
+    // spacing between them
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        samples: u32,
+        tileset: &Texture,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> Result<Self> {
+        let (width, height) = (tileset.texture.width(), tileset.texture.height());
+        if tile_width == 0 || tile_height == 0 || tile_width > width || tile_height > height {
+            bail!("{}x{} tiles don't fit a {}x{} tileset", tile_width, tile_height, width, height);
+        }
+
+        let layer_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tilemap Layer Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let tileset_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tileset Bind Group Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            }],
+        });
+        let tileset_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tileset Bind Group"),
+            layout: &tileset_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&tileset.view),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Tilemap Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("tilemap.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tilemap Pipeline Layout"),
+            bind_group_layouts: &[&layer_layout, &tileset_layout],
+            push_constant_ranges: &[],
+        });
+        const ATTRIBUTES: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![0 => Uint32x4];
+        let render_pipeline = SampledPipeline::new(samples, |multisample| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Tilemap Pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[wgpu::VertexBufferLayout {
+                        array_stride: std::mem::size_of::<[u32; 4]>() as wgpu::BufferAddress,
+                        step_mode: wgpu::VertexStepMode::Instance,
+                        attributes: &ATTRIBUTES,
+                    }],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: color_format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleStrip,
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample,
+                multiview: None,
+                cache: None,
+            })
+        });
+
+        Ok(Self {
+            camera: Vector2::new(0.0, 0.0),
+            scale: 1.0,
+            tile_texels: [tile_width, tile_height],
+            tileset_columns: width / tile_width,
+            layers: Vec::new(),
+            layer_layout,
+            tileset_bind_group,
+            render_pipeline,
+        })
+    }
+
+    // Uploads a layer on top of the existing ones and returns its index
+    pub fn add_layer(&mut self, device: &wgpu::Device, layer: &TileLayer) -> usize {
+        assert_eq!(layer.tiles.len(), (layer.width * layer.height) as usize, "tile count doesn't match the layer size");
+        let chunks_x = layer.width.div_ceil(CHUNK_SIZE).max(1);
+        let chunks_y = layer.height.div_ceil(CHUNK_SIZE).max(1);
+
+        // regroup the rows into chunks, padding the last chunks with empty tiles
+        let mut tiles = vec![0u32; (chunks_x * chunks_y) as usize * CHUNK_TILES];
+        let mut filled = vec![0u32; (chunks_x * chunks_y) as usize];
+        for y in 0..layer.height {
+            for x in 0..layer.width {
+                let tile = layer.get(x, y);
+                let chunk = (y / CHUNK_SIZE) * chunks_x + x / CHUNK_SIZE;
+                let local = (y % CHUNK_SIZE) * CHUNK_SIZE + x % CHUNK_SIZE;
+                tiles[chunk as usize * CHUNK_TILES + local as usize] = tile;
+                if tile != 0 {
+                    filled[chunk as usize] += 1;
+                }
+            }
+        }
+
+        let index = self.layers.len();
+        let tile_buffer = StorageBuffer::from_slice(device, &format!("Tilemap Layer {} Tiles", index), &tiles);
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tilemap Layer Buffer"),
+            size: std::mem::size_of::<LayerUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tilemap Layer Bind Group"),
+            layout: &self.layer_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: tile_buffer.binding(),
+                },
+            ],
+        });
+        let chunk_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Tilemap Chunk Buffer"),
+            contents: bytemuck::cast_slice(&vec![[0u32; 4]; filled.len()]),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        self.layers.push(Layer {
+            width: layer.width,
+            height: layer.height,
+            chunks_x,
+            chunks_y,
+            parallax: layer.parallax,
+            tint: layer.tint,
+            visible: true,
+            tiles,
+            tile_buffer,
+            filled,
+            uniform_buffer,
+            bind_group,
+            chunk_buffer: Tracked::buffer(ResourceKind::Buffer, chunk_buffer),
+            visible_chunks: 0,
+        });
+        index
+    }
+
+    pub fn layer_count(&self) -> usize {
+        self.layers.len()
+    }
+
+    // Changes one tile in place, only that tile is re-uploaded
+    pub fn set_tile(&mut self, queue: &wgpu::Queue, layer: usize, x: u32, y: u32, tile: u32) {
+        let layer = &mut self.layers[layer];
+        assert!(x < layer.width && y < layer.height, "tile ({}, {}) is outside the layer", x, y);
+        let index = layer.tile_index(x, y);
+        let chunk = index / CHUNK_TILES;
+        match (layer.tiles[index] != 0, tile != 0) {
+            (false, true) => layer.filled[chunk] += 1,
+            (true, false) => layer.filled[chunk] -= 1,
+            _ => {}
+        }
+        layer.tiles[index] = tile;
+        layer.tile_buffer.write(queue, index, &[tile]);
+    }
+
+    pub fn set_parallax(&mut self, layer: usize, parallax: Vector2<f32>) {
+        self.layers[layer].parallax = parallax;
+    }
+
+    pub fn set_tint(&mut self, layer: usize, tint: [f32; 4]) {
+        self.layers[layer].tint = tint;
+    }
+
+    pub fn set_visible(&mut self, layer: usize, visible: bool) {
+        self.layers[layer].visible = visible;
+    }
+
+    // Culls every layer's chunks against a `width` x `height` target and
+    // uploads the per-layer uniforms. Call once per frame before `draw`.
+    pub fn prepare(&mut self, queue: &wgpu::Queue, width: u32, height: u32) {
+        let proj: [[f32; 4]; 4] = screen_projection(width, height).into();
+        let tile_size = Vector2::new(
+            self.tile_texels[0] as f32 * self.scale,
+            self.tile_texels[1] as f32 * self.scale,
+        );
+        let chunk_pixels = tile_size * CHUNK_SIZE as f32;
+
+        for layer in &mut self.layers {
+            let offset = Vector2::new(
+                -self.camera.x * layer.parallax.x * self.scale,
+                -self.camera.y * layer.parallax.y * self.scale,
+            );
+            queue.write_buffer(
+                &layer.uniform_buffer,
+                0,
+                bytemuck::cast_slice(&[LayerUniform {
+                    proj,
+                    tint: layer.tint,
+                    offset: offset.into(),
+                    tile_size: tile_size.into(),
+                    tile_texels: self.tile_texels,
+                    tileset_columns: self.tileset_columns,
+                    chunk_size: CHUNK_SIZE,
+                }]),
+            );
+
+            // range of chunks overlapping the screen
+            let visible_range = |offset: f32, screen: u32, chunk: f32, count: u32| {
+                let first = (-offset / chunk).floor().clamp(0.0, count as f32) as u32;
+                let last = ((screen as f32 - offset) / chunk).ceil().clamp(0.0, count as f32) as u32;
+                first..last
+            };
+            let mut chunks = Vec::new();
+            if layer.visible {
+                for y in visible_range(offset.y, height, chunk_pixels.y, layer.chunks_y) {
+                    for x in visible_range(offset.x, width, chunk_pixels.x, layer.chunks_x) {
+                        let chunk = y * layer.chunks_x + x;
+                        if layer.filled[chunk as usize] > 0 {
+                            chunks.push([x, y, chunk * CHUNK_TILES as u32, 0]);
+                        }
+                    }
+                }
+            }
+            queue.write_buffer(&layer.chunk_buffer, 0, bytemuck::cast_slice(&chunks));
+            layer.visible_chunks = chunks.len() as u32;
+        }
+    }
+
+    pub fn draw<'a>(&'a self, render_pass: &mut TracedPass<'a>) {
+        render_pass.set_pipeline(self.render_pipeline.get(render_pass.sample_count()));
+        render_pass.set_bind_group(1, &self.tileset_bind_group, &[]);
+        for layer in self.layers.iter().filter(|layer| layer.visible_chunks > 0) {
+            render_pass.set_bind_group(0, &layer.bind_group, &[]);
+            render_pass.set_vertex_buffer(0, layer.chunk_buffer.slice(..));
+            render_pass.draw(0..4, 0..layer.visible_chunks);
+        }
+    }
+}
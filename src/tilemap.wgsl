@@ -0,0 +1,70 @@
+// Chunked tilemap: one quad per chunk, tiles fetched per fragment
+
+struct Layer {
+    // pixels to clip space, y pointing down
+    proj: mat4x4<f32>,
+    tint: vec4<f32>,
+    // screen position of tile (0, 0), parallax already applied
+    offset: vec2<f32>,
+    // on-screen size of one tile in pixels
+    tile_size: vec2<f32>,
+    // size of one tile in the tileset texture
+    tile_texels: vec2<u32>,
+    tileset_columns: u32,
+    chunk_size: u32,
+};
+
+@group(0) @binding(0)
+var<uniform> layer: Layer;
+// chunk-major: every chunk's tiles are contiguous, row by row.
+// 0 is an empty cell, anything else is a 1-based tileset index
+@group(0) @binding(1)
+var<storage, read> tiles: array<u32>;
+
+@group(1) @binding(0)
+var t_tileset: texture_2d<f32>;
+
+struct ChunkInput {
+    // x, y in chunks, then the offset of the chunk's first tile
+    @location(0) chunk: vec4<u32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    // position inside the chunk, in tiles
+    @location(0) local: vec2<f32>,
+    @location(1) @interpolate(flat) base: u32,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_index: u32, in: ChunkInput) -> VertexOutput {
+    let corner = vec2<f32>(f32(vertex_index & 1u), f32(vertex_index >> 1u));
+    let size = f32(layer.chunk_size);
+    let tile = (vec2<f32>(in.chunk.xy) + corner) * size;
+
+    var out: VertexOutput;
+    out.clip_position = layer.proj * vec4<f32>(layer.offset + tile * layer.tile_size, 0.0, 1.0);
+    out.local = corner * size;
+    out.base = in.chunk.z;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let cell = min(vec2<u32>(in.local), vec2<u32>(layer.chunk_size - 1u));
+    let id = tiles[in.base + cell.y * layer.chunk_size + cell.x];
+    if id == 0u {
+        discard;
+    }
+
+    // straight texel fetches, so neighbouring tiles never bleed in
+    let texels = layer.tile_texels;
+    let index = id - 1u;
+    let origin = vec2<u32>(index % layer.tileset_columns, index / layer.tileset_columns) * texels;
+    let texel = origin + min(vec2<u32>(fract(in.local) * vec2<f32>(texels)), texels - 1u);
+    let color = textureLoad(t_tileset, texel, 0) * layer.tint;
+    if color.a <= 0.0 {
+        discard;
+    }
+    return color;
+}
@@ -0,0 +1,142 @@
+use std::time::{Duration, Instant};
+
+/// Default simulation rate used by `State` for its fixed update steps.
+pub const DEFAULT_SIMULATION_HZ: f64 = 60.0;
+
+/// Accumulates variable frame times and hands them out as fixed-size
+/// simulation steps, so gameplay logic runs the same regardless of frame rate.
+#[derive(Debug, Clone)]
+pub struct FixedTimestep {
+    step: Duration,
+    accumulator: Duration,
+    // cap on steps per frame so a long stall (debugger, window drag)
+    // doesn't turn into a "spiral of death" of catch-up updates
+    max_steps: u32,
+}
+
+impl FixedTimestep {
+    pub fn new(hz: f64) -> Self {
+        Self {
+            step: Duration::from_secs_f64(1.0 / hz),
+            accumulator: Duration::ZERO,
+            max_steps: 8,
+        }
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps.max(1);
+        self
+    }
+
+    /// Length of a single simulation step.
+    pub fn step(&self) -> Duration {
+        self.step
+    }
+
+    /// Adds the frame's elapsed time and returns how many fixed steps
+    /// should be simulated this frame.
+    pub fn advance(&mut self, dt: Duration) -> u32 {
+        self.accumulator += dt;
+
+        let mut steps = 0;
+        while self.accumulator >= self.step && steps < self.max_steps {
+            self.accumulator -= self.step;
+            steps += 1;
+        }
+
+        // we hit the cap: throw away the backlog instead of carrying it over
+        if steps == self.max_steps && self.accumulator >= self.step {
+            self.accumulator = Duration::ZERO;
+        }
+
+        steps
+    }
+
+    /// How far we are between the last simulated step and the next one,
+    /// in `[0, 1)`. Use it to interpolate between previous and current state.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator.as_secs_f64() / self.step.as_secs_f64()) as f32
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(DEFAULT_SIMULATION_HZ)
+    }
+}
+
+// OS timers wake a millisecond or two late, so the end of a wait is spun
+// through instead
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+/// Caps the frame rate: frames are due a fixed interval apart, and the event
+/// loop sleeps until one is due instead of asking for the next straight away.
+/// Without a cap a frame is always due, and only a blocking present mode
+/// holds the loop back.
+#[derive(Debug, Clone)]
+pub struct FramePacer {
+    max_fps: Option<f64>,
+    interval: Option<Duration>,
+    // when the next frame is due
+    deadline: Instant,
+}
+
+impl FramePacer {
+    // None or a rate that isn't above zero leaves it uncapped
+    pub fn new(max_fps: Option<f64>) -> Self {
+        let mut pacer = Self {
+            max_fps: None,
+            interval: None,
+            deadline: Instant::now(),
+        };
+        pacer.set_max_fps(max_fps);
+        pacer
+    }
+
+    pub fn max_fps(&self) -> Option<f64> {
+        self.max_fps
+    }
+
+    pub fn set_max_fps(&mut self, max_fps: Option<f64>) {
+        self.max_fps = max_fps.filter(|fps| fps.is_finite() && *fps > 0.0);
+        self.interval = self.max_fps.map(|fps| Duration::from_secs_f64(1.0 / fps));
+        self.deadline = Instant::now();
+    }
+
+    /// For the event loop once it has nothing else to do: the instant to
+    /// sleep until, or None once the next frame is due. Within a couple of
+    /// milliseconds of the deadline it spins to it rather than sleeping.
+    pub fn wait(&self) -> Option<Instant> {
+        // uncapped, a frame is always due
+        self.interval?;
+        let now = Instant::now();
+        if self.deadline > now + SPIN_MARGIN {
+            return Some(self.deadline - SPIN_MARGIN);
+        }
+        while Instant::now() < self.deadline {
+            std::hint::spin_loop();
+        }
+        None
+    }
+
+    /// Call as a frame starts, to schedule the one after it.
+    pub fn begin_frame(&mut self) {
+        let Some(interval) = self.interval else {
+            return;
+        };
+        let now = Instant::now();
+        // keep to the schedule through a late frame, but not past a stall,
+        // which would be made up for with frames back to back
+        self.deadline = if now > self.deadline + interval {
+            now + interval
+        } else {
+            self.deadline + interval
+        };
+    }
+}
+
+impl Default for FramePacer {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
@@ -0,0 +1,280 @@
+use wgpu::util::DeviceExt;
+
+use crate::color_grading::{ColorGrading, ColorLut, GradingUniform};
+use crate::draw_list::TracedPass;
+use crate::exposure::{Exposure, ExposureUniform};
+use crate::fullscreen::fullscreen_pipeline;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::texture::SamplerDesc;
+
+// What the 3D scene renders into, with room above 1 for `Tonemap` to bring
+// back down
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+// Takes the HDR scene to the frame, see tonemap.wgsl. The scene is rendered
+// into `view`, and `encode` exposes, tonemaps and grades it into the frame,
+// before anything that wants a low dynamic range one: FXAA and the 2D
+// overlay.
+pub struct Tonemap {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    // written by `set_exposure` or on the GPU by `AutoExposure`
+    exposure_buffer: wgpu::Buffer,
+    grading_buffer: wgpu::Buffer,
+    grading: ColorGrading,
+    // an identity LUT until `set_lut` is given one, `lut_size` is None then
+    lut: Tracked<wgpu::TextureView>,
+    lut_size: Option<u32>,
+    lut_sampler: wgpu::Sampler,
+    view: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl Tonemap {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        frame_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let uniform = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                },
+                count: None,
+            },
+            uniform(1),
+            uniform(2),
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D3,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let shader = shader_preprocessor::load("tonemap.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &entries)?;
+                reflection.check_struct::<GradingUniform>("GradingUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Tonemap Shader");
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Tonemap Bind Group Layout"),
+            entries: &entries,
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Tonemap Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = fullscreen_pipeline(device, "Tonemap Pipeline", &pipeline_layout, &shader, frame_format);
+        let exposure_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Exposure Buffer"),
+            size: std::mem::size_of::<ExposureUniform>() as wgpu::BufferAddress,
+            // COPY_SRC so auto exposure can be read back
+            usage: wgpu::BufferUsages::UNIFORM
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let grading = ColorGrading::default();
+        let grading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Color Grading Buffer"),
+            contents: bytemuck::cast_slice(&[GradingUniform::new(&grading, None)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let lut = Self::create_lut(device, queue, &ColorLut::identity(2));
+        // between the entries, and no further than the first and last
+        let lut_sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Color Grading LUT Sampler"));
+
+        let view = Self::create_frame(device, width, height);
+        let bind_group =
+            Self::create_bind_group(device, &layout, &view, &exposure_buffer, &grading_buffer, &lut, &lut_sampler);
+        Self {
+            layout,
+            pipeline,
+            exposure_buffer,
+            grading_buffer,
+            grading,
+            lut,
+            lut_size: None,
+            lut_sampler,
+            view,
+            bind_group,
+        }
+    }
+
+    fn create_lut(device: &wgpu::Device, queue: &wgpu::Queue, lut: &ColorLut) -> Tracked<wgpu::TextureView> {
+        let texture = lut.create_texture(device, queue, "Color Grading LUT");
+        Tracked::view(ResourceKind::Texture, &texture, &Default::default())
+    }
+
+    fn create_frame(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("HDR Frame"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        Tracked::view(
+            ResourceKind::Target,
+            &texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("HDR Frame View"),
+                ..Default::default()
+            },
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        exposure_buffer: &wgpu::Buffer,
+        grading_buffer: &wgpu::Buffer,
+        lut: &wgpu::TextureView,
+        lut_sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Tonemap Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: grading_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(lut_sampler),
+                },
+            ],
+        })
+    }
+
+    fn rebind(&mut self, device: &wgpu::Device) {
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.layout,
+            &self.view,
+            &self.exposure_buffer,
+            &self.grading_buffer,
+            &self.lut,
+            &self.lut_sampler,
+        );
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.view = Self::create_frame(device, width, height);
+        self.rebind(device);
+    }
+
+    // Where the scene gets rendered instead of the frame
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    // What the scene is multiplied by before the curve, as `AutoExposure`
+    // works it out
+    pub fn exposure_buffer(&self) -> &wgpu::Buffer {
+        &self.exposure_buffer
+    }
+
+    // The fixed exposure, which is where auto exposure starts from
+    pub fn set_exposure(&self, queue: &wgpu::Queue, exposure: &Exposure) {
+        let uniform = ExposureUniform::fixed(exposure);
+        queue.write_buffer(&self.exposure_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Lift, gamma and gain, and how much of the LUT to mix in
+    pub fn set_grading(&mut self, queue: &wgpu::Queue, grading: &ColorGrading) {
+        self.grading = *grading;
+        self.write_grading(queue);
+    }
+
+    // The LUT the tonemapped frame is looked up in, None for no LUT
+    pub fn set_lut(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, lut: Option<&ColorLut>) {
+        self.lut = Self::create_lut(device, queue, lut.unwrap_or(&ColorLut::identity(2)));
+        self.lut_size = lut.map(|lut| lut.size);
+        self.rebind(device);
+        self.write_grading(queue);
+    }
+
+    fn write_grading(&self, queue: &wgpu::Queue) {
+        let uniform = GradingUniform::new(&self.grading, self.lut_size);
+        queue.write_buffer(&self.grading_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Tonemaps the scene into `target`, which has the format `new` was given
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Tonemap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,82 @@
+// Takes the HDR scene to the frame: scaled by the exposure, then through
+// Narkowicz's fit of the ACES filmic curve, which rolls highlights off
+// towards white instead of clipping them, then colour graded
+
+#include "fullscreen.wgsl"
+
+// Matches `ExposureUniform` in exposure.rs
+struct ExposureUniform {
+    exposure: f32,
+    // the adapted average luminance, only auto exposure needs it
+    luminance: f32,
+    _padding: vec2<f32>,
+};
+
+// same size as the target, one texel per pixel
+@group(0) @binding(0)
+var scene: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> exposure: ExposureUniform;
+
+// Matches `GradingUniform` in color_grading.rs
+struct GradingUniform {
+    // per channel, w unused
+    lift: vec4<f32>,
+    gamma: vec4<f32>,
+    gain: vec4<f32>,
+    // 0 when there's no LUT, and the identity one is bound
+    lut_strength: f32,
+    // entries along each axis
+    lut_size: f32,
+    enabled: u32,
+    _padding: u32,
+};
+
+@group(0) @binding(2)
+var<uniform> grading: GradingUniform;
+// from sRGB encoded colours to sRGB encoded colours, red along x, green
+// along y and blue along z
+@group(0) @binding(3)
+var t_lut: texture_3d<f32>;
+@group(0) @binding(4)
+var s_lut: sampler;
+
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return clamp(x * (2.51 * x + 0.03) / (x * (2.43 * x + 0.59) + 0.14), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// the exact piecewise sRGB curve, as the frame's format applies it
+fn srgb_encode(linear: vec3<f32>) -> vec3<f32> {
+    let low = linear * 12.92;
+    let high = 1.055 * pow(linear, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, linear <= vec3<f32>(0.0031308));
+}
+
+fn srgb_decode(encoded: vec3<f32>) -> vec3<f32> {
+    let low = encoded / 12.92;
+    let high = pow((encoded + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, encoded <= vec3<f32>(0.04045));
+}
+
+// On the tonemapped colour, which is 0 to 1, in the sRGB encoding LUTs and
+// grading controls are made for
+fn grade(linear: vec3<f32>) -> vec3<f32> {
+    var color = srgb_encode(linear);
+    color = grading.gain.rgb * (color + grading.lift.rgb * (1.0 - color));
+    color = pow(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)), 1.0 / grading.gamma.rgb);
+    // 0 and 1 land on the centres of the first and last entries
+    let coords = color * (grading.lut_size - 1.0) / grading.lut_size + 0.5 / grading.lut_size;
+    let looked_up = textureSampleLevel(t_lut, s_lut, coords, 0.0).rgb;
+    color = mix(color, looked_up, grading.lut_strength);
+    return srgb_decode(clamp(color, vec3<f32>(0.0), vec3<f32>(1.0)));
+}
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let color = textureLoad(scene, vec2<i32>(in.position.xy), 0);
+    var tonemapped = aces(max(color.rgb, vec3<f32>(0.0)) * exposure.exposure);
+    if grading.enabled != 0u {
+        tonemapped = grade(tonemapped);
+    }
+    return vec4<f32>(tonemapped, color.a);
+}
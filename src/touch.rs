@@ -0,0 +1,93 @@
+use cgmath::*;
+use winit::event::{Touch, TouchPhase};
+
+// How far, as a fraction of the window's shorter side, the two-finger
+// midpoint has to be dragged for full speed
+const FULL_SPEED_DRAG: f32 = 0.25;
+
+/// What a touch did to the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TouchGesture {
+    // a lone finger moved this far, in pixels
+    Look(Vector2<f32>),
+    // the first two fingers moved apart, by how many times as far
+    Pinch(f32),
+}
+
+/// Touch screen gestures for the camera controller: one finger drags to look
+/// around like the mouse, two fingers drag like a gamepad stick to move and
+/// pinch to zoom.
+#[derive(Debug, Default)]
+pub struct TouchInput {
+    // fingers down, oldest first
+    touches: Vec<(u64, Point2<f32>)>,
+    // midpoint of the first two fingers when the second went down
+    move_origin: Option<Point2<f32>>,
+}
+
+impl TouchInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn process(&mut self, touch: &Touch) -> Option<TouchGesture> {
+        let position = Point2::new(touch.location.x as f32, touch.location.y as f32);
+        let index = self.touches.iter().position(|(id, _)| *id == touch.id);
+        match (touch.phase, index) {
+            (TouchPhase::Started, None) => {
+                self.touches.push((touch.id, position));
+                if self.touches.len() == 2 {
+                    self.move_origin = self.midpoint();
+                }
+                None
+            }
+            (TouchPhase::Moved, Some(index)) => {
+                let delta = position - self.touches[index].1;
+                let spread = self.spread();
+                self.touches[index].1 = position;
+                match (self.touches.len(), spread, self.spread()) {
+                    (1, ..) => Some(TouchGesture::Look(delta)),
+                    // fingers on top of each other don't pinch
+                    (_, Some(before), Some(after)) if index < 2 && before > 1.0 && after > 1.0 => {
+                        Some(TouchGesture::Pinch(after / before))
+                    }
+                    _ => None,
+                }
+            }
+            (TouchPhase::Ended | TouchPhase::Cancelled, Some(index)) => {
+                self.touches.remove(index);
+                if self.touches.len() < 2 {
+                    self.move_origin = None;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    // (right, up, forward) like `CameraController::process_analog`, zero
+    // unless two fingers are down. Dragging up moves forward.
+    pub fn movement(&self, window_size: winit::dpi::PhysicalSize<u32>) -> Vector3<f32> {
+        let (Some(origin), Some(midpoint)) = (self.move_origin, self.midpoint()) else {
+            return Vector3::zero();
+        };
+        let full_speed = window_size.width.min(window_size.height).max(1) as f32 * FULL_SPEED_DRAG;
+        let drag = (midpoint - origin) / full_speed;
+        Vector3::new(drag.x.clamp(-1.0, 1.0), 0.0, (-drag.y).clamp(-1.0, 1.0))
+    }
+
+    // How far apart the first two fingers are
+    fn spread(&self) -> Option<f32> {
+        match self.touches.as_slice() {
+            [(_, a), (_, b), ..] => Some(a.distance(*b)),
+            _ => None,
+        }
+    }
+
+    fn midpoint(&self) -> Option<Point2<f32>> {
+        match self.touches.as_slice() {
+            [(_, a), (_, b), ..] => Some(a.midpoint(*b)),
+            _ => None,
+        }
+    }
+}
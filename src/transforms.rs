@@ -0,0 +1,290 @@
+use std::ops::Deref;
+
+use cgmath::*;
+
+use crate::compute::StorageBuffer;
+use crate::instance::{Instance, InstanceRaw};
+
+/// One instance's transform, from `Transforms::handle`. It stops working once
+/// the instances are replaced, rather than moving whichever instance took
+/// its place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransformHandle {
+    index: u32,
+    generation: u32,
+}
+
+impl TransformHandle {
+    // Its instance's index, which is also its ID when it's picked
+    pub fn index(self) -> usize {
+        self.index as usize
+    }
+}
+
+/// The instanced mesh copies, placed by transforms game code sets through
+/// handles. Setting one only marks it changed; once a frame the changed ones
+/// are repacked and written over their slots in the instance buffer, a run of
+/// neighbouring ones at a time, and the rest are left as they are on the GPU.
+pub struct Transforms {
+    instances: Vec<Instance>,
+    // bumped by `replace`, to tell old handles from new
+    generation: u32,
+    // what's changed since the last upload, each index once
+    dirty: Vec<u32>,
+    is_dirty: Vec<bool>,
+    // the run being uploaded, reused from frame to frame
+    packed: Vec<InstanceRaw>,
+}
+
+impl Transforms {
+    pub fn new(instances: Vec<Instance>) -> Self {
+        Self {
+            is_dirty: vec![false; instances.len()],
+            instances,
+            generation: 0,
+            dirty: Vec::new(),
+            packed: Vec::new(),
+        }
+    }
+
+    // Swaps in new instances, which are uploaded from scratch with a buffer
+    // of their own, and invalidates every handle to the old ones
+    pub(crate) fn replace(&mut self, instances: Vec<Instance>) {
+        self.is_dirty = vec![false; instances.len()];
+        self.instances = instances;
+        self.generation = self.generation.wrapping_add(1);
+        self.dirty.clear();
+    }
+
+    pub fn handle(&self, index: usize) -> Option<TransformHandle> {
+        (index < self.instances.len()).then_some(TransformHandle {
+            index: index as u32,
+            generation: self.generation,
+        })
+    }
+
+    // A handle to every instance, in order
+    pub fn handles(&self) -> impl Iterator<Item = TransformHandle> + '_ {
+        (0..self.instances.len()).filter_map(|index| self.handle(index))
+    }
+
+    // None for a handle from before the instances were replaced
+    pub fn instance(&self, handle: TransformHandle) -> Option<&Instance> {
+        self.valid(handle).then(|| &self.instances[handle.index()])
+    }
+
+    // Each setter returns false for a stale handle, and changes nothing
+    pub fn set_position(&mut self, handle: TransformHandle, position: Vector3<f32>) -> bool {
+        self.modify(handle, |instance| instance.position = position)
+    }
+
+    pub fn set_rotation(&mut self, handle: TransformHandle, rotation: Quaternion<f32>) -> bool {
+        self.modify(handle, |instance| instance.rotation = rotation)
+    }
+
+    pub fn set_scale(&mut self, handle: TransformHandle, scale: Vector3<f32>) -> bool {
+        self.modify(handle, |instance| instance.scale = scale)
+    }
+
+    pub fn set_transform(
+        &mut self,
+        handle: TransformHandle,
+        position: Vector3<f32>,
+        rotation: Quaternion<f32>,
+        scale: Vector3<f32>,
+    ) -> bool {
+        self.modify(handle, |instance| {
+            instance.position = position;
+            instance.rotation = rotation;
+            instance.scale = scale;
+        })
+    }
+
+    // How many instances will be uploaded with the next frame
+    pub fn dirty_count(&self) -> usize {
+        self.dirty.len()
+    }
+
+    // Writes what's changed into `buffer`, calling `uploaded` with each
+    // instance written so what's kept of them on the CPU can follow.
+    // Returns how many there were.
+    pub(crate) fn upload(
+        &mut self,
+        queue: &wgpu::Queue,
+        buffer: &StorageBuffer<InstanceRaw>,
+        uploaded: impl FnMut(usize, &Instance),
+    ) -> usize {
+        self.upload_with(|start, packed| buffer.write(queue, start, packed), uploaded)
+    }
+
+    // The same with `write` given each run of neighbouring instances and
+    // the index of its first
+    fn upload_with(
+        &mut self,
+        mut write: impl FnMut(usize, &[InstanceRaw]),
+        mut uploaded: impl FnMut(usize, &Instance),
+    ) -> usize {
+        if self.dirty.is_empty() {
+            return 0;
+        }
+        self.dirty.sort_unstable();
+        let count = self.dirty.len();
+        let mut rest = &self.dirty[..];
+        while let Some(&start) = rest.first() {
+            // how far the indices go up one at a time
+            let len = rest.iter().zip(start..).take_while(|&(&index, expected)| index == expected).count();
+            let run = start as usize..start as usize + len;
+            self.packed.clear();
+            self.packed.extend(self.instances[run.clone()].iter().map(Instance::to_raw));
+            write(run.start, &self.packed);
+            for index in run {
+                self.is_dirty[index] = false;
+                uploaded(index, &self.instances[index]);
+            }
+            rest = &rest[len..];
+        }
+        self.dirty.clear();
+        count
+    }
+
+    fn valid(&self, handle: TransformHandle) -> bool {
+        handle.generation == self.generation && handle.index() < self.instances.len()
+    }
+
+    fn modify(&mut self, handle: TransformHandle, change: impl FnOnce(&mut Instance)) -> bool {
+        if !self.valid(handle) {
+            return false;
+        }
+        let index = handle.index();
+        change(&mut self.instances[index]);
+        if !self.is_dirty[index] {
+            self.is_dirty[index] = true;
+            self.dirty.push(index as u32);
+        }
+        true
+    }
+}
+
+// Read access to the instances as a slice, for everything that only looks at
+// them
+impl Deref for Transforms {
+    type Target = [Instance];
+
+    fn deref(&self) -> &[Instance] {
+        &self.instances
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transforms(count: usize) -> Transforms {
+        let instance = Instance {
+            position: Vector3::zero(),
+            rotation: Quaternion::one(),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            material: 0,
+        };
+        Transforms::new(vec![instance; count])
+    }
+
+    fn move_to(transforms: &mut Transforms, index: usize, x: f32) -> bool {
+        let handle = transforms.handle(index).unwrap();
+        transforms.set_position(handle, Vector3::new(x, 0.0, 0.0))
+    }
+
+    // The runs an upload writes, as (first index, how many), and the
+    // instances it reports
+    fn upload(transforms: &mut Transforms) -> (Vec<(usize, usize)>, Vec<usize>) {
+        let (mut runs, mut uploaded) = (Vec::new(), Vec::new());
+        let count = transforms.upload_with(
+            |start, packed| runs.push((start, packed.len())),
+            |index, _| uploaded.push(index),
+        );
+        assert_eq!(count, uploaded.len());
+        (runs, uploaded)
+    }
+
+    #[test]
+    fn neighbours_go_up_in_one_run() {
+        let mut transforms = transforms(10);
+        for index in [7, 2, 3, 9, 1, 8] {
+            assert!(move_to(&mut transforms, index, 1.0));
+        }
+        let (runs, uploaded) = upload(&mut transforms);
+        assert_eq!(runs, [(1, 3), (7, 3)]);
+        assert_eq!(uploaded, [1, 2, 3, 7, 8, 9]);
+        // and nothing's left for the next frame
+        assert_eq!(transforms.dirty_count(), 0);
+        assert_eq!(upload(&mut transforms), (vec![], vec![]));
+    }
+
+    #[test]
+    fn runs_carry_the_latest_transform() {
+        let mut transforms = transforms(3);
+        move_to(&mut transforms, 1, 1.0);
+        move_to(&mut transforms, 1, 5.0);
+        let mut written = Vec::new();
+        transforms.upload_with(|start, packed| written.push((start, packed.to_vec())), |_, _| {});
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].0, 1);
+        assert_eq!(written[0].1[0].model[3][0], 5.0);
+    }
+
+    #[test]
+    fn each_instance_is_queued_once() {
+        let mut transforms = transforms(4);
+        let handle = transforms.handle(2).unwrap();
+        assert!(transforms.set_position(handle, Vector3::unit_x()));
+        assert!(transforms.set_rotation(handle, Quaternion::from_angle_y(Deg(90.0))));
+        assert!(transforms.set_scale(handle, Vector3::new(2.0, 2.0, 2.0)));
+        assert_eq!(transforms.dirty_count(), 1);
+        assert_eq!(upload(&mut transforms), (vec![(2, 1)], vec![2]));
+        // and again once it's been uploaded
+        assert!(transforms.set_position(handle, Vector3::unit_y()));
+        assert_eq!(transforms.dirty_count(), 1);
+    }
+
+    #[test]
+    fn handles_are_only_for_instances_there_are() {
+        let transforms = transforms(2);
+        assert!(transforms.handle(1).is_some());
+        assert!(transforms.handle(2).is_none());
+        let indices: Vec<usize> = transforms.handles().map(TransformHandle::index).collect();
+        assert_eq!(indices, [0, 1]);
+    }
+
+    #[test]
+    fn replacing_makes_old_handles_stale() {
+        let mut transforms = transforms(3);
+        let old = transforms.handle(0).unwrap();
+        move_to(&mut transforms, 1, 1.0);
+        transforms.replace(transforms.to_vec());
+        // what was queued went with the old instances
+        assert_eq!(transforms.dirty_count(), 0);
+        assert!(transforms.instance(old).is_none());
+        assert!(!transforms.set_position(old, Vector3::unit_x()));
+        assert!(!transforms.set_transform(old, Vector3::unit_x(), Quaternion::one(), Vector3::unit_x()));
+        assert_eq!(transforms.dirty_count(), 0);
+        assert_eq!(transforms[0].position, Vector3::zero());
+        // the same index through a new handle is fine
+        let new = transforms.handle(0).unwrap();
+        assert_ne!(new, old);
+        assert!(transforms.set_position(new, Vector3::unit_x()));
+        assert_eq!(transforms.instance(new).unwrap().position, Vector3::unit_x());
+        assert_eq!(upload(&mut transforms), (vec![(0, 1)], vec![0]));
+    }
+
+    #[test]
+    fn handles_past_the_end_of_fewer_instances_are_stale() {
+        let mut transforms = transforms(3);
+        transforms.replace(vec![transforms[0]; 1]);
+        let handle = TransformHandle {
+            index: 2,
+            generation: transforms.generation,
+        };
+        assert!(transforms.instance(handle).is_none());
+        assert!(!transforms.set_position(handle, Vector3::unit_x()));
+    }
+}
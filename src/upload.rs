@@ -0,0 +1,262 @@
+use std::marker::PhantomData;
+
+use crate::frames::{FramesInFlight, Retired, FRAMES_IN_FLIGHT};
+use crate::resources::{ResourceKind, Tracked};
+
+// Big enough for the demo's joint palettes in one chunk, the belt adds more
+// chunks when a frame needs them
+pub const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+
+/// Records buffer writes into a frame's command encoder through a staging
+/// belt, so a frame with many objects doesn't go through
+/// `queue.write_buffer` once per object. Call `StagingBelt::finish` before
+/// submitting the encoder and `StagingBelt::recall` after.
+pub struct Uploader<'a> {
+    device: &'a wgpu::Device,
+    encoder: &'a mut wgpu::CommandEncoder,
+    belt: &'a mut wgpu::util::StagingBelt,
+    // None outside the frame loop, where there's one copy of everything
+    frames: Option<&'a mut FramesInFlight>,
+}
+
+impl<'a> Uploader<'a> {
+    pub fn new(
+        device: &'a wgpu::Device,
+        encoder: &'a mut wgpu::CommandEncoder,
+        belt: &'a mut wgpu::util::StagingBelt,
+    ) -> Self {
+        Self {
+            device,
+            encoder,
+            belt,
+            frames: None,
+        }
+    }
+
+    // For a frame's uploads, into its slot's copies of what's written every
+    // frame
+    pub fn in_frame(self, frames: &'a mut FramesInFlight) -> Self {
+        Self {
+            frames: Some(frames),
+            ..self
+        }
+    }
+
+    pub fn device(&self) -> &wgpu::Device {
+        self.device
+    }
+
+    // See `FramesInFlight::slot`, always 0 outside a frame
+    pub fn frame_slot(&self) -> usize {
+        self.frames.as_ref().map_or(0, |frames| frames.slot())
+    }
+
+    // For what an upload replaced, which frames in flight may still use.
+    // Outside a frame it's dropped, and wgpu keeps it alive until whatever
+    // was submitted with it is done.
+    pub fn retire(&mut self, resource: impl Into<Retired>) {
+        if let Some(frames) = &mut self.frames {
+            frames.retire(resource);
+        }
+    }
+
+    // `buffer` needs COPY_DST; `offset` and the length of `data` have to
+    // be multiples of `wgpu::COPY_BUFFER_ALIGNMENT`
+    pub fn write(&mut self, buffer: &wgpu::Buffer, offset: wgpu::BufferAddress, data: &[u8]) {
+        let Some(size) = wgpu::BufferSize::new(data.len() as wgpu::BufferAddress) else {
+            return;
+        };
+        self.belt
+            .write_buffer(self.encoder, buffer, offset, size, self.device)
+            .copy_from_slice(data);
+    }
+}
+
+// Layout entry for a `DynamicUniforms` binding
+pub fn dynamic_uniform_entry<T>(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: true,
+            min_binding_size: wgpu::BufferSize::new(std::mem::size_of::<T>() as wgpu::BufferAddress),
+        },
+        count: None,
+    }
+}
+
+/// Per-object uniforms packed into one buffer and bound with a dynamic
+/// offset, instead of a buffer and bind group per object. Each value takes
+/// `stride` bytes so its offset meets `min_uniform_buffer_offset_alignment`.
+///
+/// For data that changes every frame, make them `per_frame`, `clear` and
+/// `push` every object and `upload` before drawing; for data that doesn't,
+/// `push` once and `write`. The buffer grows as needed, which replaces
+/// `bind_group` but keeps the offsets already handed out valid.
+pub struct DynamicUniforms<T: bytemuck::Pod> {
+    label: String,
+    layout: wgpu::BindGroupLayout,
+    visibility: wgpu::ShaderStages,
+    // one, or one for each frame in flight, so a frame's upload doesn't
+    // write over what an earlier one is still drawing with
+    arenas: Vec<Arena>,
+    // the one last written, which is what's drawn with
+    current: usize,
+    stride: wgpu::BufferAddress,
+    // every value pushed since the last `clear`, padded to `stride`
+    data: Vec<u8>,
+    _marker: PhantomData<T>,
+}
+
+struct Arena {
+    buffer: Tracked<wgpu::Buffer>,
+    bind_group: wgpu::BindGroup,
+}
+
+impl<T: bytemuck::Pod> DynamicUniforms<T> {
+    // `capacity` is how many values fit before the buffer first grows
+    pub fn new(device: &wgpu::Device, label: &str, visibility: wgpu::ShaderStages, capacity: usize) -> Self {
+        Self::with_arenas(device, label, visibility, capacity, 1)
+    }
+
+    // A buffer for each of `FRAMES_IN_FLIGHT`, `upload` writing the one for
+    // the uploader's frame slot
+    pub fn per_frame(device: &wgpu::Device, label: &str, visibility: wgpu::ShaderStages, capacity: usize) -> Self {
+        Self::with_arenas(device, label, visibility, capacity, FRAMES_IN_FLIGHT)
+    }
+
+    fn with_arenas(
+        device: &wgpu::Device,
+        label: &str,
+        visibility: wgpu::ShaderStages,
+        capacity: usize,
+        count: usize,
+    ) -> Self {
+        let alignment = device.limits().min_uniform_buffer_offset_alignment as wgpu::BufferAddress;
+        let stride = wgpu::util::align_to(std::mem::size_of::<T>() as wgpu::BufferAddress, alignment);
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some(&format!("{} Bind Group Layout", label)),
+            entries: &[dynamic_uniform_entry::<T>(0, visibility)],
+        });
+        let arenas = (0..count)
+            .map(|_| {
+                let buffer = Self::create_buffer(device, label, stride * capacity.max(1) as wgpu::BufferAddress);
+                let bind_group = Self::create_bind_group(device, label, &layout, &buffer);
+                Arena { buffer, bind_group }
+            })
+            .collect();
+        Self {
+            label: label.to_owned(),
+            layout,
+            visibility,
+            arenas,
+            current: 0,
+            stride,
+            data: Vec::new(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, label: &str, size: wgpu::BufferAddress) -> Tracked<wgpu::Buffer> {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} Buffer", label)),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Tracked::buffer(ResourceKind::Buffer, buffer)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        label: &str,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("{} Bind Group", label)),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                // one value wide, the dynamic offset picks which
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(std::mem::size_of::<T>() as wgpu::BufferAddress),
+                }),
+            }],
+        })
+    }
+
+    // Appends a value, returning the dynamic offset to draw it with
+    pub fn push(&mut self, value: &T) -> u32 {
+        let offset = self.data.len();
+        self.data.extend_from_slice(bytemuck::bytes_of(value));
+        self.data.resize(offset + self.stride as usize, 0);
+        offset as u32
+    }
+
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len() / self.stride as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Bytes between consecutive offsets
+    pub fn stride(&self) -> wgpu::BufferAddress {
+        self.stride
+    }
+
+    // Grows the current buffer to fit everything pushed, doubling so a
+    // growing scene doesn't reallocate every frame, and returns the one it
+    // replaced
+    fn reserve(&mut self, device: &wgpu::Device) -> Option<Tracked<wgpu::Buffer>> {
+        let needed = self.data.len() as wgpu::BufferAddress;
+        let arena = &mut self.arenas[self.current];
+        if needed <= arena.buffer.size() {
+            return None;
+        }
+        let size = needed.next_power_of_two().max(arena.buffer.size() * 2);
+        let buffer = Self::create_buffer(device, &self.label, size);
+        arena.bind_group = Self::create_bind_group(device, &self.label, &self.layout, &buffer);
+        Some(std::mem::replace(&mut arena.buffer, buffer))
+    }
+
+    // Copies the values into the frame slot's buffer as part of the
+    // uploader's encoder
+    pub fn upload(&mut self, uploader: &mut Uploader) {
+        self.current = uploader.frame_slot() % self.arenas.len();
+        if let Some(replaced) = self.reserve(uploader.device()) {
+            uploader.retire(replaced);
+        }
+        uploader.write(&self.arenas[self.current].buffer, 0, &self.data);
+    }
+
+    // Like `upload`, through the queue, for values that rarely change. A
+    // buffer it outgrows is dropped, wgpu keeps it until it's unused.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.reserve(device);
+        queue.write_buffer(&self.arenas[self.current].buffer, 0, &self.data);
+    }
+
+    pub fn layout(&self) -> &wgpu::BindGroupLayout {
+        &self.layout
+    }
+
+    // What `layout` was made from, to check shaders against
+    pub fn layout_entry(&self) -> wgpu::BindGroupLayoutEntry {
+        dynamic_uniform_entry::<T>(0, self.visibility)
+    }
+
+    // Bind with one of the offsets `push` returned
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.arenas[self.current].bind_group
+    }
+}
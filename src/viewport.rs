@@ -0,0 +1,267 @@
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::camera::{Camera, CameraUniform, Projection};
+use crate::draw_list::TracedPass;
+use crate::dynamic_resolution::Upscaling;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::layers::RenderLayers;
+use crate::resources::{ResourceKind, Tracked};
+use crate::shader_preprocessor::{self, ShaderDefs};
+use crate::texture::SamplerDesc;
+
+/// Part of the window, in fractions of its size from the top left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: Self = Self::new(0.0, 0.0, 1.0, 1.0);
+    pub const LEFT_HALF: Self = Self::new(0.0, 0.0, 0.5, 1.0);
+    pub const RIGHT_HALF: Self = Self::new(0.5, 0.0, 0.5, 1.0);
+    pub const TOP_HALF: Self = Self::new(0.0, 0.0, 1.0, 0.5);
+    pub const BOTTOM_HALF: Self = Self::new(0.0, 0.5, 1.0, 0.5);
+
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn is_full(&self) -> bool {
+        *self == Self::FULL
+    }
+
+    // x, y, width and height in whole pixels of a window `size` big, at
+    // least one across and inside it
+    pub fn pixels(&self, size: PhysicalSize<u32>) -> [u32; 4] {
+        let (width, height) = (size.width as f32, size.height as f32);
+        let x = ((self.x * width).round() as u32).min(size.width.saturating_sub(1));
+        let y = ((self.y * height).round() as u32).min(size.height.saturating_sub(1));
+        let right = (((self.x + self.width) * width).round() as u32).clamp(x + 1, size.width.max(x + 1));
+        let bottom = (((self.y + self.height) * height).round() as u32).clamp(y + 1, size.height.max(y + 1));
+        [x, y, right - x, bottom - y]
+    }
+
+    // Where a window position is in the frame scaled into this rectangle,
+    // which is drawn at the window's size. None outside of it.
+    pub fn to_frame(&self, position: PhysicalPosition<f64>, size: PhysicalSize<u32>) -> Option<PhysicalPosition<f64>> {
+        let [x, y, width, height] = self.pixels(size).map(f64::from);
+        let u = (position.x - x) / width;
+        let v = (position.y - y) / height;
+        ((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v))
+            .then(|| PhysicalPosition::new(u * size.width as f64, v * size.height as f64))
+    }
+
+    // The other way, from the frame to the window
+    pub fn from_frame(&self, [u, v]: [f32; 2], size: PhysicalSize<u32>) -> [f32; 2] {
+        let [x, y, width, height] = self.pixels(size).map(|pixels| pixels as f32);
+        [x + u / size.width as f32 * width, y + v / size.height as f32 * height]
+    }
+}
+
+/// Another camera's view of the scene, in a rectangle of the window next to
+/// or over the main camera's, for split-screen or an editor's other views.
+pub struct Viewport {
+    pub rect: ViewportRect,
+    pub camera: Camera,
+    // its aspect follows the rectangle's when the window's resized
+    pub projection: Projection,
+    // its own, so last frame's view-projection is this camera's
+    pub(crate) uniform: CameraUniform,
+    // what it draws, everything to begin with
+    pub layers: RenderLayers,
+}
+
+impl Viewport {
+    pub fn new(rect: ViewportRect, camera: Camera, projection: Projection) -> Self {
+        Self {
+            rect,
+            camera,
+            projection,
+            uniform: CameraUniform::new(),
+            layers: RenderLayers::ALL,
+        }
+    }
+}
+
+// Scales whole frames into viewports' rectangles, see viewport.wgsl. A
+// viewport's frame is drawn at the render size into `view`, like the main
+// camera's would be into the window, then `encode` draws it over its
+// rectangle of the window, filtered. Frames scaled up are sharpened too
+// with `Upscaling::Sharpened`.
+pub struct ViewportCompositor {
+    color_format: wgpu::TextureFormat,
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    sharpen_pipeline: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    view: Tracked<wgpu::TextureView>,
+    bind_group: wgpu::BindGroup,
+    size: PhysicalSize<u32>,
+    upscaling: Upscaling,
+}
+
+impl ViewportCompositor {
+    pub fn new(device: &wgpu::Device, color_format: wgpu::TextureFormat, width: u32, height: u32) -> Self {
+        let shader = shader_preprocessor::create_module(device, "Viewport Shader", "viewport.wgsl", &ShaderDefs::new());
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Viewport Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Viewport Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, entry_point| {
+            fullscreen_pipeline_with(
+                device,
+                label,
+                &pipeline_layout,
+                &shader,
+                entry_point,
+                &[Some(color_format.into())],
+            )
+        };
+        let pipeline = create_pipeline("Viewport Pipeline", "fs_main");
+        let sharpen_pipeline = create_pipeline("Viewport Sharpen Pipeline", "fs_sharpen");
+        // shrunk into the rectangle, and nothing wraps around the edges
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Viewport Sampler"));
+
+        let (view, bind_group) = Self::create_frame(device, &layout, &sampler, color_format, width, height);
+        Self {
+            color_format,
+            layout,
+            pipeline,
+            sharpen_pipeline,
+            sampler,
+            view,
+            bind_group,
+            size: PhysicalSize::new(width, height),
+            upscaling: Upscaling::default(),
+        }
+    }
+
+    fn create_frame(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        sampler: &wgpu::Sampler,
+        format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> (Tracked<wgpu::TextureView>, wgpu::BindGroup) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Viewport Frame"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = Tracked::view(
+            ResourceKind::Target,
+            &texture,
+            &wgpu::TextureViewDescriptor {
+                label: Some("Viewport Frame View"),
+                ..Default::default()
+            },
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Viewport Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        });
+        (view, bind_group)
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        (self.view, self.bind_group) =
+            Self::create_frame(device, &self.layout, &self.sampler, self.color_format, width, height);
+        self.size = PhysicalSize::new(width, height);
+    }
+
+    // Where a viewport's frame goes before it's scaled into the window
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.view
+    }
+
+    pub fn upscaling(&self) -> Upscaling {
+        self.upscaling
+    }
+
+    pub fn set_upscaling(&mut self, upscaling: Upscaling) {
+        self.upscaling = upscaling;
+    }
+
+    // Draws the frame in `view` into `rect` of `target`, which has the
+    // format `new` was given and is `size` big. `clear` clears the rest of
+    // the target first, for the first viewport of a frame.
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        size: PhysicalSize<u32>,
+        rect: ViewportRect,
+        clear: Option<wgpu::Color>,
+    ) {
+        let mut pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Viewport Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: clear.map_or(wgpu::LoadOp::Load, wgpu::LoadOp::Clear),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        let [x, y, width, height] = rect.pixels(size);
+        pass.set_viewport(x as f32, y as f32, width as f32, height as f32, 0.0, 1.0);
+        // only scaling up blurs enough to need it
+        let upscaled = width > self.size.width || height > self.size.height;
+        if upscaled && self.upscaling == Upscaling::Sharpened {
+            pass.set_pipeline(&self.sharpen_pipeline);
+        } else {
+            pass.set_pipeline(&self.pipeline);
+        }
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+}
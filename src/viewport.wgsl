@@ -0,0 +1,44 @@
+// A finished frame, drawn at the render size, scaled into a viewport's
+// rectangle of the window. The pass's viewport is the rectangle, so the
+// triangle covers just that.
+
+#include "fullscreen.wgsl"
+
+@group(0) @binding(0)
+var frame: texture_2d<f32>;
+@group(0) @binding(1)
+var frame_sampler: sampler;
+
+@fragment
+fn fs_main(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(frame, frame_sampler, in.uv, 0.0);
+}
+
+// FSR 1's default sharpness, 0.2 stops down from the most, and its limit on
+// how far a pixel's pushed, which keeps it from ringing
+const SHARPNESS: f32 = 0.87055;
+const RCAS_LIMIT: f32 = 0.1875;
+
+// Scaled up and sharpened with FSR 1's robust contrast adaptive sharpening
+// (RCAS): each pixel's pushed away from its four neighbours, a pixel of the
+// target away, by as much as their range allows without clipping
+@fragment
+fn fs_sharpen(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    let dx = vec2<f32>(dpdx(in.uv.x), 0.0);
+    let dy = vec2<f32>(0.0, dpdy(in.uv.y));
+    let e = textureSampleLevel(frame, frame_sampler, in.uv, 0.0);
+    let b = textureSampleLevel(frame, frame_sampler, in.uv - dy, 0.0).rgb;
+    let d = textureSampleLevel(frame, frame_sampler, in.uv - dx, 0.0).rgb;
+    let f = textureSampleLevel(frame, frame_sampler, in.uv + dx, 0.0).rgb;
+    let h = textureSampleLevel(frame, frame_sampler, in.uv + dy, 0.0).rgb;
+
+    let lo = min(min(min(b, d), min(f, h)), e.rgb);
+    let hi = max(max(max(b, d), max(f, h)), e.rgb);
+    // the most each channel can be pushed towards black and towards white
+    let hit_min = lo / max(4.0 * hi, vec3<f32>(1e-5));
+    let hit_max = (1.0 - hi) / min(4.0 * lo - 4.0, vec3<f32>(-1e-5));
+    let lobes = max(-hit_min, hit_max);
+    let lobe = max(-RCAS_LIMIT, min(max(lobes.r, max(lobes.g, lobes.b)), 0.0)) * SHARPNESS;
+    let color = (lobe * (b + d + f + h) + e.rgb) / (4.0 * lobe + 1.0);
+    return vec4<f32>(color, e.a);
+}
@@ -0,0 +1,454 @@
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::compute::{self, ComputePipeline, StorageBuffer};
+use crate::depth_prepass;
+use crate::draw_data::{DrawData, DrawSlot};
+use crate::draw_list::TracedPass;
+use crate::model::ModelVertex;
+use crate::picking::Ray;
+use crate::resources::Tracked;
+use crate::scene::VoxelDesc;
+use crate::skinning::ModelRenderer;
+
+// marching_cubes.wgsl's
+const WORKGROUP_SIZE: u32 = 4;
+// entries a case takes in the triangle table, five triangles at most and
+// the -1 after them
+const TABLE_STRIDE: usize = 16;
+// the corners of a cell are numbered by their x, y and z bits, its edges
+// by the corners they join, the lower one first
+const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [0, 2],
+    [0, 4],
+    [1, 3],
+    [1, 5],
+    [2, 3],
+    [2, 6],
+    [3, 7],
+    [4, 5],
+    [4, 6],
+    [5, 7],
+    [6, 7],
+];
+// each face's corners, counter-clockwise seen from outside the cell
+const FACES: [[usize; 4]; 6] = [[4, 6, 2, 0], [1, 3, 7, 5], [0, 1, 5, 4], [6, 7, 3, 2], [2, 3, 1, 0], [4, 5, 7, 6]];
+
+/// Densities at the corners of a grid of cells: solid above zero, empty
+/// below, and the surface where it crosses zero. Kept as a distance to the
+/// surface where it's edited with spheres.
+#[derive(Debug, Clone)]
+pub struct VoxelField {
+    origin: Point3<f32>,
+    cell_size: f32,
+    // samples along x, y and z, one more than the cells
+    samples: [usize; 3],
+    // x first, then y, then z
+    density: Vec<f32>,
+}
+
+impl VoxelField {
+    // Empty everywhere
+    pub fn new(origin: Point3<f32>, cells: [u32; 3], cell_size: f32) -> Self {
+        let samples = cells.map(|cells| cells.max(1) as usize + 1);
+        Self {
+            origin,
+            cell_size,
+            samples,
+            density: vec![-cell_size; samples.iter().product()],
+        }
+    }
+
+    pub fn from_desc(desc: &VoxelDesc) -> Self {
+        let mut field = Self::new(desc.origin.into(), desc.cells, desc.cell_size);
+        if let Some(ground) = desc.ground {
+            field.apply(|position, density| density.max(ground - position.y));
+        }
+        for sphere in &desc.spheres {
+            field.add_sphere(sphere.center.into(), sphere.radius);
+        }
+        field
+    }
+
+    pub fn cells(&self) -> [u32; 3] {
+        self.samples.map(|samples| samples as u32 - 1)
+    }
+
+    fn position(&self, [x, y, z]: [usize; 3]) -> Point3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * self.cell_size
+    }
+
+    // Sets every sample to `f` of where it is and what it was
+    pub fn apply(&mut self, mut f: impl FnMut(Point3<f32>, f32) -> f32) {
+        let [nx, ny, _] = self.samples;
+        for i in 0..self.density.len() {
+            let position = self.position([i % nx, i / nx % ny, i / (nx * ny)]);
+            self.density[i] = f(position, self.density[i]);
+        }
+    }
+
+    // Only visits the samples within `radius` of `center`
+    fn apply_near(&mut self, center: Point3<f32>, radius: f32, mut f: impl FnMut(f32, f32) -> f32) {
+        let lo = (center - Vector3::from_value(radius) - self.origin) / self.cell_size;
+        let hi = (center + Vector3::from_value(radius) - self.origin) / self.cell_size;
+        let range = |axis: usize| {
+            let last = self.samples[axis] - 1;
+            (lo[axis].floor().max(0.0) as usize).min(last)..=(hi[axis].ceil().max(0.0) as usize).min(last)
+        };
+        let (xs, ys, zs) = (range(0), range(1), range(2));
+        for z in zs {
+            for y in ys.clone() {
+                for x in xs.clone() {
+                    let distance = self.position([x, y, z]).distance(center);
+                    let i = (z * self.samples[1] + y) * self.samples[0] + x;
+                    self.density[i] = f(self.density[i], distance);
+                }
+            }
+        }
+    }
+
+    // Makes a ball solid
+    pub fn add_sphere(&mut self, center: Point3<f32>, radius: f32) {
+        // a cell further out, so the distance is right up to the surface
+        let reach = radius + self.cell_size;
+        self.apply_near(center, reach, |density, distance| density.max(radius - distance));
+    }
+
+    // Empties one
+    pub fn carve_sphere(&mut self, center: Point3<f32>, radius: f32) {
+        let reach = radius + self.cell_size;
+        self.apply_near(center, reach, |density, distance| density.min(distance - radius));
+    }
+
+    // Trilinear between the samples around `position`, empty outside the
+    // field and on its faces like marching_cubes.wgsl has them
+    pub fn density(&self, position: Point3<f32>) -> f32 {
+        let p = (position - self.origin) / self.cell_size;
+        let inside = (0..3).all(|axis| p[axis] > 0.0 && p[axis] < (self.samples[axis] - 1) as f32);
+        if !inside {
+            return -self.cell_size;
+        }
+        let base = [p.x.floor() as usize, p.y.floor() as usize, p.z.floor() as usize];
+        let t = [p.x.fract(), p.y.fract(), p.z.fract()];
+        let mut value = 0.0;
+        for corner in 0..8 {
+            let offset = [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1];
+            let [x, y, z] = [0, 1, 2].map(|axis| (base[axis] + offset[axis]).min(self.samples[axis] - 1));
+            let on_face = [x, y, z].iter().zip(self.samples).any(|(&at, samples)| at == 0 || at == samples - 1);
+            let mut density = self.density[(z * self.samples[1] + y) * self.samples[0] + x];
+            if on_face {
+                density = density.min(-0.5 * self.cell_size);
+            }
+            let weight: f32 = (0..3).map(|axis| if offset[axis] == 1 { t[axis] } else { 1.0 - t[axis] }).product();
+            value += weight * density;
+        }
+        value
+    }
+
+    // Where `ray` first reaches the surface, within `max_distance`, by
+    // stepping half a cell at a time and bisecting the last step
+    pub fn raycast(&self, ray: &Ray, max_distance: f32) -> Option<Point3<f32>> {
+        let step = 0.5 * self.cell_size;
+        let mut t = 0.0;
+        while t < max_distance {
+            let next = t + step;
+            if self.density(ray.at(next)) > 0.0 {
+                let (mut lo, mut hi) = (t, next);
+                for _ in 0..8 {
+                    let mid = 0.5 * (lo + hi);
+                    if self.density(ray.at(mid)) > 0.0 {
+                        hi = mid;
+                    } else {
+                        lo = mid;
+                    }
+                }
+                return Some(ray.at(hi));
+            }
+            t = next;
+        }
+        None
+    }
+}
+
+// Which of a cell's edges the surface crosses for each of the 256 ways its
+// corners can be solid, three to a triangle, counter-clockwise seen from
+// the empty side. Worked out rather than written down: on each face the
+// surface enters the solid corners by one edge and leaves them by the next,
+// which keeps solid corners apart where a face could go either way, the
+// same from both cells that share it; those crossings link up into loops
+// around the cell, each fanned out into triangles.
+fn triangle_table() -> Vec<i32> {
+    let edge = |a: usize, b: usize| EDGES.iter().position(|&edge| edge == [a.min(b), a.max(b)]).unwrap();
+    let mut table = Vec::with_capacity(256 * TABLE_STRIDE);
+    for case in 0..256 {
+        let solid = |corner: usize| case & (1 << corner) != 0;
+        let mut next = [None; 12];
+        for face in FACES {
+            let crossings: Vec<(bool, usize)> = (0..4)
+                .map(|k| (face[k], face[(k + 1) % 4]))
+                .filter(|&(a, b)| solid(a) != solid(b))
+                .map(|(a, b)| (solid(b), edge(a, b)))
+                .collect();
+            for (i, &(enters, from)) in crossings.iter().enumerate() {
+                if enters {
+                    next[from] = Some(crossings[(i + 1) % crossings.len()].1);
+                }
+            }
+        }
+
+        let mut triangles = Vec::with_capacity(TABLE_STRIDE);
+        let mut visited = [false; 12];
+        for start in 0..12 {
+            if next[start].is_none() || visited[start] {
+                continue;
+            }
+            let mut ring = vec![start];
+            visited[start] = true;
+            let mut at = next[start].unwrap();
+            while at != start {
+                ring.push(at);
+                visited[at] = true;
+                at = next[at].unwrap();
+            }
+            for i in 1..ring.len() - 1 {
+                triangles.extend([ring[0], ring[i], ring[i + 1]].map(|edge| edge as i32));
+            }
+        }
+        debug_assert!(triangles.len() < TABLE_STRIDE);
+        triangles.resize(TABLE_STRIDE, -1);
+        table.extend(triangles);
+    }
+    table
+}
+
+// Mirrors `Params` in marching_cubes.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelParams {
+    origin: [f32; 3],
+    cell_size: f32,
+    cells: [u32; 3],
+    max_vertices: u32,
+    color: [f32; 3],
+    metallic: f32,
+    roughness: f32,
+    _padding: [f32; 3],
+}
+
+/// A `VoxelField` on the GPU, meshed by marching cubes in a compute pass
+/// whenever it's edited and drawn by `ModelRenderer` from the vertices the
+/// pass leaves, as many as it counted into an indirect draw. Like the
+/// terrain it has a depth buffer of its own, and what's drawn after it goes
+/// on top.
+pub struct VoxelTerrain {
+    desc: VoxelDesc,
+    field: VoxelField,
+    march_pipeline: ComputePipeline,
+    finish_pipeline: ComputePipeline,
+    density: StorageBuffer<f32>,
+    // a `wgpu::util::DrawIndirectArgs`, the vertex count added up by the
+    // march
+    args: wgpu::Buffer,
+    vertices: StorageBuffer<ModelVertex>,
+    march_bind_group: wgpu::BindGroup,
+    finish_bind_group: wgpu::BindGroup,
+    depth_view: Tracked<wgpu::TextureView>,
+    // the field's changed since it was last meshed
+    dirty: bool,
+    // this frame's draw data
+    slot: Option<DrawSlot>,
+}
+
+impl VoxelTerrain {
+    pub fn new(device: &wgpu::Device, desc: &VoxelDesc, width: u32, height: u32) -> Self {
+        let field = VoxelField::from_desc(desc);
+        let entries = [
+            compute::uniform_entry(0),
+            compute::storage_entry(1, true),
+            compute::storage_entry(2, true),
+            compute::storage_entry(3, false),
+            compute::storage_entry(4, false),
+        ];
+        let source = include_str!("marching_cubes.wgsl");
+        let march_pipeline = ComputePipeline::new(device, "Marching Cubes", source, "cs_march", &[&entries]);
+        let finish_pipeline = ComputePipeline::new(device, "Marching Cubes Finish", source, "cs_finish", &[&entries]);
+
+        let max_vertices = desc.max_triangles.max(1) * 3;
+        let params = VoxelParams {
+            origin: desc.origin,
+            cell_size: desc.cell_size,
+            cells: field.cells(),
+            max_vertices,
+            color: desc.color,
+            metallic: desc.metallic,
+            roughness: desc.roughness,
+            _padding: [0.0; 3],
+        };
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Voxel Params Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let table = StorageBuffer::from_slice(device, "Marching Cubes Table Buffer", &triangle_table());
+        let density = StorageBuffer::from_slice(device, "Voxel Density Buffer", &field.density);
+        let args = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Voxel Draw Args Buffer"),
+            size: std::mem::size_of::<wgpu::util::DrawIndirectArgs>() as wgpu::BufferAddress,
+            // COPY_SRC so the vertex count can be read back
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let vertices = StorageBuffer::zeroed_with_usage(
+            device,
+            "Voxel Vertex Buffer",
+            max_vertices as usize,
+            wgpu::BufferUsages::VERTEX,
+        );
+        let resources = || {
+            [
+                params_buffer.as_entire_binding(),
+                density.binding(),
+                table.binding(),
+                args.as_entire_binding(),
+                vertices.binding(),
+            ]
+        };
+        let march_bind_group = march_pipeline.create_bind_group(device, 0, &resources());
+        let finish_bind_group = finish_pipeline.create_bind_group(device, 0, &resources());
+
+        Self {
+            desc: desc.clone(),
+            field,
+            march_pipeline,
+            finish_pipeline,
+            density,
+            args,
+            vertices,
+            march_bind_group,
+            finish_bind_group,
+            depth_view: Self::create_depth(device, width, height),
+            dirty: true,
+            slot: None,
+        }
+    }
+
+    fn create_depth(device: &wgpu::Device, width: u32, height: u32) -> Tracked<wgpu::TextureView> {
+        depth_prepass::depth_target(device, "Voxel Depth", width, height, wgpu::TextureUsages::empty())
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.depth_view = Self::create_depth(device, width, height);
+    }
+
+    // As it was loaded, without the edits since
+    pub fn desc(&self) -> &VoxelDesc {
+        &self.desc
+    }
+
+    pub fn field(&self) -> &VoxelField {
+        &self.field
+    }
+
+    // Remeshed before it's next drawn
+    pub fn field_mut(&mut self) -> &mut VoxelField {
+        self.dirty = true;
+        &mut self.field
+    }
+
+    // The mesh, as many `ModelVertex`es of it as the vertex count in
+    // `indirect_buffer` says
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        self.vertices.buffer()
+    }
+
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.args
+    }
+
+    // Once a frame, between the renderer's `begin_frame` and `upload`
+    pub fn upload(&mut self, renderer: &mut ModelRenderer, object: u32) {
+        self.slot = Some(renderer.push_draw(DrawData::new(object, None, [1.0; 4])));
+    }
+
+    // Meshes the field again if it's been edited, before it's drawn
+    pub fn encode_mesh(&mut self, queue: &wgpu::Queue, encoder: &mut wgpu::CommandEncoder) {
+        if !self.dirty {
+            return;
+        }
+        self.dirty = false;
+        self.density.write(queue, 0, &self.field.density);
+        let reset = wgpu::util::DrawIndirectArgs {
+            vertex_count: 0,
+            instance_count: 1,
+            first_vertex: 0,
+            first_instance: 0,
+        };
+        queue.write_buffer(&self.args, 0, reset.as_bytes());
+        let [x, y, z] = self.field.cells().map(|cells| compute::workgroup_count(cells, WORKGROUP_SIZE));
+        self.march_pipeline.dispatch(encoder, &[&self.march_bind_group], (x, y, z));
+        self.finish_pipeline.dispatch(encoder, &[&self.finish_bind_group], (1, 1, 1));
+    }
+
+    // Over `target`, in a pass with its own depth
+    pub fn encode(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        renderer: &ModelRenderer,
+        camera_bind_group: &wgpu::BindGroup,
+    ) {
+        let Some(slot) = &self.slot else {
+            return;
+        };
+        let mut render_pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Voxel Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Discard,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        renderer.draw_generated(&mut render_pass, camera_bind_group, self.vertices.buffer(), &self.args, slot);
+    }
+
+    // Into the pass from `ScreenSpaceReflections::begin_gbuffer`
+    pub fn draw_gbuffer<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if let Some(slot) = &self.slot {
+            renderer.draw_generated_gbuffer(render_pass, camera_bind_group, self.vertices.buffer(), &self.args, slot);
+        }
+    }
+
+    // Into the pass from `TemporalAa::begin_velocity`
+    pub fn draw_velocity<'a>(
+        &'a self,
+        render_pass: &mut TracedPass<'a>,
+        renderer: &'a ModelRenderer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if let Some(slot) = &self.slot {
+            renderer.draw_generated_velocity(render_pass, camera_bind_group, self.vertices.buffer(), &self.args, slot);
+        }
+    }
+}
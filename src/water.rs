@@ -0,0 +1,393 @@
+use std::time::Duration;
+
+use cgmath::*;
+use wgpu::util::DeviceExt;
+
+use crate::camera::{self, Camera, CameraUniform, Projection, OPENGL_TO_WGPU_MATRIX};
+use crate::draw_list::TracedPass;
+use crate::fog::FogUniform;
+use crate::fullscreen::fullscreen_pipeline_with;
+use crate::lighting::LightUniform;
+use crate::render_target::RenderTarget;
+use crate::scene::WaterDesc;
+use crate::shader_preprocessor;
+use crate::shader_reflection::ShaderReflection;
+use crate::taa::TemporalAa;
+use crate::texture::SamplerDesc;
+
+// Matches `WaterUniform` in water.wgsl
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    inverse_view_proj: [[f32; 4]; 4],
+    color: [f32; 3],
+    clarity: f32,
+    rect: [f32; 4],
+    height: f32,
+    time: f32,
+    wave_length: f32,
+    steepness: f32,
+}
+
+// The camera's view of the world mirrored in a surface at `height`. The near
+// plane is tilted onto the surface, so what's under the water is clipped
+// away whatever shader draws it. The image is upside down: mirroring turns
+// every triangle over, and flipping it turns them back, so pipelines cull
+// the same faces they do from the camera.
+fn reflection_camera(camera: &Camera, projection: &Projection, height: f32) -> CameraUniform {
+    let mirror = Matrix4::from_translation(Vector3::unit_y() * height)
+        * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)
+        * Matrix4::from_translation(-Vector3::unit_y() * height);
+    let view = camera.calc_matrix() * mirror;
+
+    let mut gl_projection = projection.gl_matrix();
+    // the surface in view space, facing away from the eye, which is under
+    // it once mirrored; from under the water there's nothing to clip
+    let plane = view.invert().map(|inverse| inverse.transpose() * Vector4::new(0.0, 1.0, 0.0, -height));
+    if let (Some(plane), Some(inverse)) = (plane.filter(|plane| plane.w < 0.0), gl_projection.invert()) {
+        // Lengyel's oblique near plane: the far corner of the view frustum
+        // opposite the plane stays put, the near plane becomes the surface
+        let corner = inverse * Vector4::new(plane.x.signum(), plane.y.signum(), 1.0, 1.0);
+        let near = plane * (2.0 / plane.dot(corner));
+        gl_projection.x.z = near.x - gl_projection.x.w;
+        gl_projection.y.z = near.y - gl_projection.y.w;
+        gl_projection.z.z = near.z - gl_projection.z.w;
+        gl_projection.w.z = near.w - gl_projection.w.w;
+    }
+    let flip = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+    let eye = Point3::new(camera.position.x, 2.0 * height - camera.position.y, camera.position.z);
+    CameraUniform::from_view(eye, view, flip * OPENGL_TO_WGPU_MATRIX * gl_projection)
+        .with_sky(flip * OPENGL_TO_WGPU_MATRIX * projection.gl_sky_matrix())
+}
+
+// A rectangle of animated water, see water.wgsl. Its waves only bend the
+// normals, the surface itself stays flat. Two passes off screen go before
+// it: the scene above the water, mirrored in it, is drawn into `reflection`
+// from `reflection_bind_group`'s camera, and what's under and behind the
+// water is drawn into `scene` with its depth, so `encode` can refract it.
+pub struct Water {
+    desc: WaterDesc,
+    uniform_buffer: wgpu::Buffer,
+    reflection_buffer: wgpu::Buffer,
+    reflection_bind_group: wgpu::BindGroup,
+    reflection: RenderTarget,
+    scene: RenderTarget,
+    layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    bind_group: wgpu::BindGroup,
+    copy_pipeline: wgpu::RenderPipeline,
+    pipeline: wgpu::RenderPipeline,
+    velocity_pipeline: wgpu::RenderPipeline,
+}
+
+impl Water {
+    // Draws into `color_format` targets of `width` x `height`. `shared` is
+    // everything but the camera in the scene's camera bind group, the fog,
+    // the light and the image based lighting, bound next to the reflection's
+    // camera the same way.
+    pub fn new(
+        device: &wgpu::Device,
+        color_format: wgpu::TextureFormat,
+        camera_layout: &wgpu::BindGroupLayout,
+        shared: &[wgpu::BindGroupEntry],
+        desc: &WaterDesc,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Water Buffer"),
+            size: std::mem::size_of::<WaterUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let reflection_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Water Reflection Camera Buffer"),
+            contents: bytemuck::cast_slice(&[CameraUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let mut entries = vec![wgpu::BindGroupEntry {
+            binding: 0,
+            resource: reflection_buffer.as_entire_binding(),
+        }];
+        entries.extend_from_slice(shared);
+        let reflection_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Water Reflection Camera Bind Group"),
+            layout: camera_layout,
+            entries: &entries,
+        });
+        let reflection = RenderTarget::new(device, "Water Reflection", color_format, width, height);
+        let scene = RenderTarget::new(device, "Water Scene", color_format, width, height);
+
+        let texture_entry = |binding, sample_type| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type,
+            },
+            count: None,
+        };
+        let color = wgpu::TextureSampleType::Float { filterable: true };
+        let entries = [
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            texture_entry(1, color),
+            // as floats, GL can't load from depth textures
+            texture_entry(2, wgpu::TextureSampleType::Float { filterable: false }),
+            texture_entry(3, color),
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+        ];
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Water Bind Group Layout"),
+            entries: &entries,
+        });
+        // ripples pull samples from past the edges
+        let sampler = SamplerDesc::TRILINEAR
+            .with_address_mode(wgpu::AddressMode::ClampToEdge)
+            .create(device, Some("Water Sampler"));
+        let bind_group = Self::create_bind_group(device, &layout, &uniform_buffer, &sampler, &scene, &reflection);
+
+        let shader = shader_preprocessor::load("water.wgsl", &Default::default())
+            .and_then(|shader| {
+                let reflection = ShaderReflection::new(&shader)?;
+                reflection.check_group(0, &camera::layout_entries())?;
+                reflection.check_group(1, &entries)?;
+                reflection.check_struct::<CameraUniform>("CameraUniform")?;
+                reflection.check_struct::<FogUniform>("FogUniform")?;
+                reflection.check_struct::<LightUniform>("LightUniform")?;
+                reflection.check_struct::<WaterUniform>("WaterUniform")?;
+                Ok(shader)
+            })
+            .unwrap_or_else(|e| panic!("{:#}", e))
+            .create_module(device, "Water Shader");
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Water Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let create_pipeline = |label, fragment_entry_point, targets: &[Option<wgpu::ColorTargetState>], cull_mode| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                    compilation_options: Default::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment_entry_point,
+                    targets,
+                    compilation_options: Default::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode,
+                    ..Default::default()
+                },
+                depth_stencil: None,
+                multisample: Default::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let targets = [Some(color_format.into())];
+        let copy_pipeline =
+            fullscreen_pipeline_with(device, "Water Copy Pipeline", &pipeline_layout, &shader, "fs_copy", &targets);
+        // the water is only seen from above
+        let back = Some(wgpu::Face::Back);
+        let pipeline = create_pipeline("Water Pipeline", "fs_main", &targets, back);
+        let velocity_pipeline =
+            create_pipeline("Water Velocity Pipeline", "fs_velocity", &TemporalAa::velocity_targets(), back);
+
+        Self {
+            desc: desc.clone(),
+            uniform_buffer,
+            reflection_buffer,
+            reflection_bind_group,
+            reflection,
+            scene,
+            layout,
+            sampler,
+            bind_group,
+            copy_pipeline,
+            pipeline,
+            velocity_pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        uniform_buffer: &wgpu::Buffer,
+        sampler: &wgpu::Sampler,
+        scene: &RenderTarget,
+        reflection: &RenderTarget,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Water Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(scene.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(scene.depth_view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(reflection.view()),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.reflection.resize(device, width, height);
+        self.scene.resize(device, width, height);
+        self.bind_group =
+            Self::create_bind_group(device, &self.layout, &self.uniform_buffer, &self.sampler, &self.scene, &self.reflection);
+    }
+
+    // As it was made
+    pub fn desc(&self) -> &WaterDesc {
+        &self.desc
+    }
+
+    // Once a frame, after the camera has moved; `camera_uniform` is what the
+    // scene under the water is drawn with
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &Camera,
+        projection: &Projection,
+        camera_uniform: &CameraUniform,
+        elapsed: Duration,
+    ) {
+        let [x, height, z] = self.desc.position;
+        let [width, depth] = self.desc.size;
+        let reflection = reflection_camera(camera, projection, height);
+        queue.write_buffer(&self.reflection_buffer, 0, bytemuck::cast_slice(&[reflection]));
+        let uniform = WaterUniform {
+            inverse_view_proj: camera_uniform.view_proj().invert().unwrap_or(Matrix4::identity()).into(),
+            color: self.desc.color,
+            clarity: self.desc.clarity.max(f32::EPSILON),
+            rect: [x - width / 2.0, z - depth / 2.0, x + width / 2.0, z + depth / 2.0],
+            height,
+            // wraps around after a few hours rather than losing precision
+            time: (elapsed.as_secs_f64() % 10_000.0) as f32,
+            wave_length: self.desc.wave_length.max(f32::EPSILON),
+            steepness: self.desc.steepness.clamp(0.0, 1.0),
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // The camera and fog for drawing into the reflection
+    pub fn reflection_bind_group(&self) -> &wgpu::BindGroup {
+        &self.reflection_bind_group
+    }
+
+    // Where the mirrored scene goes, with a depth buffer for passes that
+    // need one
+    pub fn reflection(&self) -> &RenderTarget {
+        &self.reflection
+    }
+
+    // Where the scene under and behind the water goes, its depth included,
+    // before `encode`
+    pub fn scene(&self) -> &RenderTarget {
+        &self.scene
+    }
+
+    // The reflection's pass, cleared to `clear_color`
+    pub fn begin_reflection<'e>(&'e self, encoder: &'e mut wgpu::CommandEncoder, clear_color: wgpu::Color) -> TracedPass<'e> {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Water Reflection Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: self.reflection.view(),
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear_color),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        })
+    }
+
+    // For frames where nothing draws into the scene's depth, so the water
+    // sees nothing but the far plane under it
+    pub fn clear_depth(&self, encoder: &mut wgpu::CommandEncoder) {
+        TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Water Depth Clear"),
+            color_attachments: &[],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: self.scene.depth_view(),
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+    }
+
+    // Copies the scene into `target` and draws the water over it, hidden
+    // by whatever the scene's depth has in front of it
+    pub fn encode(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView, camera_bind_group: &wgpu::BindGroup) {
+        let mut render_pass = TracedPass::begin(encoder, &wgpu::RenderPassDescriptor {
+            label: Some("Water Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    // every pixel is overwritten
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.set_pipeline(&self.copy_pipeline);
+        render_pass.draw(0..3, 0..1);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.draw(0..6, 0..1);
+    }
+
+    // Into TAA's velocity pass, after the scene's depth has been drawn
+    pub fn draw_velocity<'a>(&'a self, render_pass: &mut TracedPass<'a>, camera_bind_group: &'a wgpu::BindGroup) {
+        render_pass.set_pipeline(&self.velocity_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
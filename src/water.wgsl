@@ -0,0 +1,167 @@
+// A flat rectangle of water whose normals are bent by Gerstner waves. It
+// refracts the scene drawn under it, reflects the mirrored scene drawn from
+// under the surface, and blends the two by Fresnel.
+
+#include "camera.wgsl"
+#include "fog.wgsl"
+#include "fullscreen.wgsl"
+#include "lighting.wgsl"
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+@group(0) @binding(1)
+var<uniform> fog: FogUniform;
+@group(0) @binding(2)
+var<uniform> lighting: LightUniform;
+@group(0) @binding(7)
+var<storage, read> lights: array<GpuLight>;
+
+// Matches `WaterUniform` in water.rs
+struct WaterUniform {
+    // the camera's, from clip space back to the world
+    inverse_view_proj: mat4x4<f32>,
+    // what's left once light has gone through a lot of water
+    color: vec3<f32>,
+    // how far light goes through the water before it's mostly that colour
+    clarity: f32,
+    // min x, min z, max x, max z
+    rect: vec4<f32>,
+    height: f32,
+    // seconds
+    time: f32,
+    // of the longest wave
+    wave_length: f32,
+    // zero for a still surface, up to one for sharp crests
+    steepness: f32,
+};
+
+@group(1) @binding(0)
+var<uniform> water: WaterUniform;
+// what's under and behind the water, from the camera
+@group(1) @binding(1)
+var t_scene: texture_2d<f32>;
+// read as floats, GL can't load from depth textures
+@group(1) @binding(2)
+var t_depth: texture_2d<f32>;
+// upside down, see `reflection_camera` in water.rs
+@group(1) @binding(3)
+var t_reflection: texture_2d<f32>;
+@group(1) @binding(4)
+var s_linear: sampler;
+
+// how far the surface's slope pushes the refraction and reflection, as a
+// fraction of the screen
+const DISTORTION: f32 = 0.03;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_position: vec3<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    // two triangles, counter-clockwise from above
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(1.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+    );
+    let xz = mix(water.rect.xy, water.rect.zw, corners[index]);
+    var out: VertexOutput;
+    out.world_position = vec3<f32>(xz.x, water.height, xz.y);
+    out.clip_position = camera.view_proj * vec4<f32>(out.world_position, 1.0);
+    return out;
+}
+
+// One wave's part of the surface's slope at `position`, as x, the loss of
+// height along y and z of the normal's sum
+fn gerstner(direction: vec2<f32>, wave_length: f32, position: vec2<f32>) -> vec3<f32> {
+    let k = 6.2831853 / wave_length;
+    // deep water waves, the long ones are the fast ones
+    let speed = sqrt(9.81 / k);
+    let phase = k * (dot(direction, position) - speed * water.time);
+    // the four together are never steeper than `steepness`
+    let slope = water.steepness * 0.25;
+    let c = cos(phase);
+    return vec3<f32>(direction.x * slope * c, water.steepness * slope * sin(phase), direction.y * slope * c);
+}
+
+fn wave_normal(position: vec2<f32>) -> vec3<f32> {
+    let longest = water.wave_length;
+    let sum = gerstner(vec2<f32>(1.0, 0.0), longest, position)
+        + gerstner(vec2<f32>(0.6, 0.8), longest * 0.62, position)
+        + gerstner(vec2<f32>(-0.44, 0.9), longest * 0.37, position)
+        + gerstner(vec2<f32>(0.89, -0.45), longest * 0.23, position);
+    return normalize(vec3<f32>(-sum.x, 1.0 - sum.y, -sum.z));
+}
+
+fn scene_depth(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(t_depth));
+    return textureLoad(t_depth, vec2<i32>(clamp(uv * size, vec2<f32>(0.0), size - 1.0)), 0).r;
+}
+
+// Where the scene's depth at `uv` is in the world
+fn scene_position(uv: vec2<f32>, depth: f32) -> vec3<f32> {
+    let position = water.inverse_view_proj * vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, depth, 1.0);
+    return position.xyz / position.w;
+}
+
+fn screen_uv(clip_position: vec4<f32>) -> vec2<f32> {
+    return clip_position.xy / vec2<f32>(textureDimensions(t_depth));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let uv = screen_uv(in.clip_position);
+    // the scene has its own depth, there's none in this pass
+    if scene_depth(uv) < in.clip_position.z {
+        discard;
+    }
+
+    let normal = wave_normal(in.world_position.xz);
+    let to_eye = normalize(camera.view_pos.xyz - in.world_position);
+    let offset = normal.xz * DISTORTION;
+
+    // refracted, but not from anything standing in front of the water
+    var refracted_uv = uv + offset;
+    var depth = scene_depth(refracted_uv);
+    if depth < in.clip_position.z {
+        refracted_uv = uv;
+        depth = scene_depth(uv);
+    }
+    let thickness = distance(scene_position(refracted_uv, depth), in.world_position);
+    let transmittance = exp(-thickness / water.clarity);
+    let under = textureSampleLevel(t_scene, s_linear, refracted_uv, 0.0).rgb;
+    let refraction = mix(water.color, under, transmittance);
+
+    let reflection_uv = vec2<f32>(uv.x, 1.0 - uv.y) + offset;
+    let reflection = textureSampleLevel(t_reflection, s_linear, reflection_uv, 0.0).rgb;
+
+    // Schlick's, for water's index of refraction
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, to_eye), 0.0), 5.0);
+    var color = mix(refraction, reflection, fresnel);
+    for (var i = 0u; i < lighting.count; i++) {
+        let light = sample_light(lights[i], in.world_position);
+        let halfway = normalize(light.direction + to_eye);
+        color += light.radiance * pow(max(dot(normal, halfway), 0.0), 256.0) * 2.5;
+    }
+    return vec4<f32>(apply_fog(fog, camera.view_pos.xyz, in.world_position, color), 1.0);
+}
+
+// Screen motion from the camera for TAA, where the water isn't hidden
+@fragment
+fn fs_velocity(in: VertexOutput) -> @location(0) vec2<f32> {
+    if scene_depth(screen_uv(in.clip_position)) < in.clip_position.z {
+        discard;
+    }
+    return screen_motion(camera, in.world_position);
+}
+
+// The scene as it was, for the water to go over
+@fragment
+fn fs_copy(in: FullscreenOutput) -> @location(0) vec4<f32> {
+    return textureLoad(t_scene, vec2<i32>(in.position.xy), 0);
+}
@@ -0,0 +1,50 @@
+use cgmath::{Matrix4, Point3, Transform};
+
+use crate::debug_draw::DebugDraw;
+
+// How the scene's triangle edges are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum WireframeMode {
+    Off,
+    // edges drawn over the shaded scene
+    Overlay,
+    // edges only, the scene isn't shaded at all
+    Only,
+}
+
+impl WireframeMode {
+    pub fn next(self) -> Self {
+        match self {
+            WireframeMode::Off => WireframeMode::Overlay,
+            WireframeMode::Overlay => WireframeMode::Only,
+            WireframeMode::Only => WireframeMode::Off,
+        }
+    }
+}
+
+// Line polygon mode is native-only, without it wireframes go through the
+// debug line fallback below
+pub fn optional_features(adapter: &wgpu::Adapter) -> wgpu::Features {
+    adapter.features() & wgpu::Features::POLYGON_MODE_LINE
+}
+
+pub fn supported(device: &wgpu::Device) -> bool {
+    device.features().contains(wgpu::Features::POLYGON_MODE_LINE)
+}
+
+// Fallback for devices without POLYGON_MODE_LINE: the edges of a CPU copy
+// of the mesh queued as debug lines. Shared edges are drawn twice, which is
+// fine for debugging.
+pub fn outline_triangles(
+    debug_draw: &mut DebugDraw,
+    triangles: &[[Point3<f32>; 3]],
+    model: &Matrix4<f32>,
+    color: [f32; 4],
+) {
+    for triangle in triangles {
+        let [a, b, c] = triangle.map(|p| model.transform_point(p));
+        debug_draw.line(a, b, color);
+        debug_draw.line(b, c, color);
+        debug_draw.line(c, a, color);
+    }
+}